@@ -0,0 +1,195 @@
+//! Size and proving cost of finalization circuits across update counts and tree
+//! heights, to choose batching and recursion parameters by. Besides the criterion
+//! reports, writes a JSON array with one entry per shape to
+//! `target/circuit_sizes.json`, or the path in `QED_BENCH_JSON`, holding the size
+//! of the circuit and a single timing of each stage.
+//!
+//! The full grid takes hours, so it can be narrowed down with comma separated
+//! lists, e.g. `QED_BENCH_UPDATES=1,16 QED_BENCH_HEIGHTS=16 cargo bench --bench circuit_sizes`.
+
+use std::{str::FromStr, time::Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2::{
+    field::goldilocks_field::GoldilocksField, iop::witness::PartialWitness,
+    plonk::config::PoseidonGoldilocksConfig,
+};
+use plonky2_tree_hacks::{
+    balance::{
+        accounts::{BalanceTx, TallySlot, VoterLeaf},
+        storage::BalanceStorage,
+        weight::{Weight, WeightDelta},
+    },
+    circuits::{
+        quadratic::VotingPolicy,
+        update_balance::{UpdateBalanceCircuit, UpdateBalanceShape},
+        witness::set_witnesses,
+    },
+    proof::certificate::{compute_action_hash, compute_statement_hash},
+    proposal::action::ProposalAction,
+};
+use serde::Serialize;
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+const UPDATE_COUNTS: [usize; 6] = [1, 4, 16, 64, 256, 1024];
+const TREE_HEIGHTS: [u8; 3] = [16, 24, 32];
+
+/// Size of the circuit of one shape, and how long each stage took once.
+#[derive(Serialize)]
+struct ShapeReport {
+    number_updates: usize,
+    tree_height: u8,
+    degree_bits: usize,
+    gate_types: usize,
+    public_inputs: usize,
+    proof_bytes: usize,
+    build_ms: f64,
+    witness_ms: f64,
+    prove_ms: f64,
+    verify_ms: f64,
+}
+
+fn grid<T: FromStr + Copy>(var: &str, default: &[T]) -> Vec<T> {
+    match std::env::var(var) {
+        Ok(list) => list
+            .split(',')
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .unwrap_or_else(|_| panic!("{} holds an invalid value {:?}", var, value))
+            })
+            .collect(),
+        Err(_) => default.to_vec(),
+    }
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let statement_hash = compute_statement_hash("benchmark");
+    let action_hash = compute_action_hash(&ProposalAction::TextOnly);
+    let mut reports = vec![];
+    let mut group = c.benchmark_group("circuit_sizes");
+    group.sample_size(10);
+    for tree_height in grid("QED_BENCH_HEIGHTS", &TREE_HEIGHTS) {
+        for number_updates in grid("QED_BENCH_UPDATES", &UPDATE_COUNTS) {
+            let mut storage =
+                BalanceStorage::new(tree_height, vec![Weight::from(1); number_updates]);
+            let txs = (0..number_updates)
+                .map(|position| BalanceTx::Vote {
+                    voter: VoterLeaf::from_position(position as u64),
+                    slot: if position % 2 == 0 {
+                        TallySlot::YES
+                    } else {
+                        TallySlot::NO
+                    },
+                    amount: WeightDelta::from(1),
+                })
+                .collect();
+            let updates = storage.process_txs(txs).unwrap();
+            let tally_proofs = [
+                storage.get_tally_proof(TallySlot::NO).unwrap(),
+                storage.get_tally_proof(TallySlot::YES).unwrap(),
+            ];
+            let shape = UpdateBalanceShape {
+                number_updates,
+                tree_height: tree_height as usize,
+                balance_bits: storage.balance_bits(),
+                conviction: false,
+                voting_policy: VotingPolicy::Linear,
+                dependencies: false,
+            };
+            let label = format!("{}x{}", number_updates, tree_height);
+
+            let start = Instant::now();
+            let circuit = UpdateBalanceCircuit::<F, C, 2>::new(shape);
+            let build_ms = elapsed_ms(start);
+            let start = Instant::now();
+            let mut pw = PartialWitness::new();
+            set_witnesses(
+                &mut pw,
+                &circuit.updates,
+                &updates,
+                |update, witness, proof| update.set_witness_proof(witness, proof),
+            );
+            let witness_ms = elapsed_ms(start);
+            let start = Instant::now();
+            let proof = circuit
+                .prove(statement_hash, action_hash, &updates, &tally_proofs)
+                .unwrap();
+            let prove_ms = elapsed_ms(start);
+            let proof_bytes = proof.to_bytes().len();
+            let start = Instant::now();
+            circuit.base_circuit_data.verify(proof.clone()).unwrap();
+            let verify_ms = elapsed_ms(start);
+            let common = &circuit.base_circuit_data.common;
+            reports.push(ShapeReport {
+                number_updates,
+                tree_height,
+                degree_bits: common.degree_bits(),
+                gate_types: common.gates.len(),
+                public_inputs: common.num_public_inputs,
+                proof_bytes,
+                build_ms,
+                witness_ms,
+                prove_ms,
+                verify_ms,
+            });
+
+            group.bench_with_input(BenchmarkId::new("build", &label), &shape, |b, shape| {
+                b.iter(|| UpdateBalanceCircuit::<F, C, 2>::new(black_box(*shape)))
+            });
+            group.bench_with_input(
+                BenchmarkId::new("witness", &label),
+                &updates,
+                |b, updates| {
+                    b.iter(|| {
+                        let mut pw = PartialWitness::new();
+                        set_witnesses(
+                            &mut pw,
+                            &circuit.updates,
+                            black_box(updates),
+                            |update, witness, proof| update.set_witness_proof(witness, proof),
+                        );
+                        pw
+                    })
+                },
+            );
+            group.bench_with_input(BenchmarkId::new("prove", &label), &updates, |b, updates| {
+                b.iter(|| {
+                    circuit
+                        .prove(
+                            statement_hash,
+                            action_hash,
+                            black_box(updates),
+                            &tally_proofs,
+                        )
+                        .unwrap()
+                })
+            });
+            group.bench_with_input(BenchmarkId::new("verify", &label), &proof, |b, proof| {
+                b.iter(|| {
+                    circuit
+                        .base_circuit_data
+                        .verify(black_box(proof.clone()))
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+
+    let path =
+        std::env::var("QED_BENCH_JSON").unwrap_or_else(|_| "target/circuit_sizes.json".to_string());
+    std::fs::write(&path, serde_json::to_vec_pretty(&reports).unwrap())
+        .unwrap_or_else(|err| panic!("Failed to write {}: {}", path, err));
+    println!("Wrote the circuit sizes to {}", path);
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
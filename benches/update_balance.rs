@@ -0,0 +1,97 @@
+//! Proving large proposals. Run with and without `--features parallel` to see
+//! the speedup of parallel witness generation and proving.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2::{
+    field::goldilocks_field::GoldilocksField, iop::witness::PartialWitness,
+    plonk::config::PoseidonGoldilocksConfig,
+};
+use plonky2_tree_hacks::{
+    balance::{
+        accounts::{BalanceTx, TallySlot, VoterLeaf},
+        storage::BalanceStorage,
+        weight::{Weight, WeightDelta},
+    },
+    circuits::{
+        quadratic::VotingPolicy,
+        update_balance::{UpdateBalanceCircuit, UpdateBalanceShape},
+        witness::set_witnesses,
+    },
+    proof::certificate::{compute_action_hash, compute_statement_hash},
+    proposal::action::ProposalAction,
+};
+
+const TREE_HEIGHT: u8 = 16;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_balance");
+    group.sample_size(10);
+    for number_updates in [16, 64, 256] {
+        let mut storage = BalanceStorage::new(TREE_HEIGHT, vec![Weight::from(1); number_updates]);
+        let txs = (0..number_updates)
+            .map(|position| BalanceTx::Vote {
+                voter: VoterLeaf::from_position(position as u64),
+                slot: if position % 2 == 0 {
+                    TallySlot::YES
+                } else {
+                    TallySlot::NO
+                },
+                amount: WeightDelta::from(1),
+            })
+            .collect();
+        let updates = storage.process_txs(txs).unwrap();
+        let tally_proofs = [
+            storage.get_tally_proof(TallySlot::NO).unwrap(),
+            storage.get_tally_proof(TallySlot::YES).unwrap(),
+        ];
+        let circuit = UpdateBalanceCircuit::<GoldilocksField, PoseidonGoldilocksConfig, 2>::new(
+            UpdateBalanceShape {
+                number_updates,
+                tree_height: TREE_HEIGHT as usize,
+                balance_bits: storage.balance_bits(),
+                conviction: false,
+                voting_policy: VotingPolicy::Linear,
+                dependencies: false,
+            },
+        );
+        let statement_hash = compute_statement_hash("benchmark");
+        let action_hash = compute_action_hash(&ProposalAction::TextOnly);
+
+        group.bench_with_input(
+            BenchmarkId::new("set witnesses", number_updates),
+            &updates,
+            |b, updates| {
+                b.iter(|| {
+                    let mut pw = PartialWitness::new();
+                    set_witnesses(
+                        &mut pw,
+                        &circuit.updates,
+                        updates,
+                        |update, witness, proof| update.set_witness_proof(witness, proof),
+                    );
+                    pw
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("prove", number_updates),
+            &updates,
+            |b, updates| {
+                b.iter(|| {
+                    circuit
+                        .prove(
+                            statement_hash,
+                            action_hash,
+                            black_box(updates),
+                            &tally_proofs,
+                        )
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,162 @@
+//! Rust SDK of the QED server: async functions over the typed HTTP client of
+//! the server, sharing its request and response types, and offline
+//! verification of the proofs it finalizes proposals with.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use qed_sdk::QedClient;
+//!
+//! let client = QedClient::new("http://127.0.0.1:8080");
+//! let id = "67e55044-10b1-426f-9247-bb680e5fe0c8".parse()?;
+//! let finalized = qed_sdk::fetch_proof(&client, id).await?;
+//! let tally = qed_sdk::verify_locally(&finalized).await?;
+//! println!("{} yes, {} no", tally.yes_votes, tally.no_votes);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests the server rejects fail with an [`ApiError`], which callers can get
+//! back with `err.downcast_ref::<ApiError>()` to match on its code.
+
+use anyhow::ensure;
+use uuid::Uuid;
+
+pub use plonky2_tree_hacks::{
+    api,
+    balance::{accounts::Tally, weight::Weight},
+    errors::{ApiError, ApiErrorCode},
+    proof::{certificate::FinalizationCertificate, codec::ProofEnvelope},
+    qed_client::QedClient,
+};
+
+use plonky2_tree_hacks::{
+    api::{ActionResponse, ProposeQuery, VoteQuery},
+    proof::verify::verify_finalization,
+};
+
+/// Chunks the proof envelope is downloaded in, and how often each is retried.
+const PROOF_CHUNK_SIZE: u64 = 1 << 20;
+const PROOF_CHUNK_RETRIES: u32 = 3;
+
+/// The certificate of a finalized proposal with the proof it was finalized with.
+#[derive(Clone, Debug)]
+pub struct FinalizedProposal {
+    pub certificate: FinalizationCertificate,
+    pub proof: ProofEnvelope,
+}
+
+/// Creates a proposal, returning the id the server gave it in the response.
+pub async fn create_proposal(
+    client: &QedClient,
+    query: &ProposeQuery,
+) -> anyhow::Result<ActionResponse> {
+    client.propose(query).await
+}
+
+/// Casts the vote of `query.voter_id` on `query.proposal_id`.
+pub async fn cast_vote(client: &QedClient, query: &VoteQuery) -> anyhow::Result<ActionResponse> {
+    client.vote(query).await
+}
+
+/// Fetches the certificate of the finalized proposal `id` and downloads the
+/// proof it was finalized with, failing if the proposal is not finalized yet.
+pub async fn fetch_proof(client: &QedClient, id: Uuid) -> anyhow::Result<FinalizedProposal> {
+    let certificate = client.get_certificate(id).await?;
+    let proof = client
+        .download_proof(id, PROOF_CHUNK_SIZE, PROOF_CHUNK_RETRIES)
+        .await?;
+    Ok(FinalizedProposal { certificate, proof })
+}
+
+/// Verifies the proof of a finalized proposal against its certificate without
+/// trusting the server, returning the tally it proves. Fails if the proof does
+/// not go from the initial to the final root of the certificate, was made for
+/// another statement, document, action, dependencies or rules, or proves
+/// another tally than the certificate claims.
+///
+/// Rebuilding the circuit of the proof takes a while, so it is done on a
+/// blocking thread.
+pub async fn verify_locally(finalized: &FinalizedProposal) -> anyhow::Result<Tally> {
+    let FinalizedProposal { certificate, proof } = finalized.clone();
+    // Cheap to check, and spares building the circuit of a proof of another tree
+    proof.expect_public_inputs(certificate.initial_root, certificate.final_root)?;
+    let tally = tokio::task::spawn_blocking(move || {
+        let tally = verify_finalization(
+            &proof,
+            certificate.initial_root,
+            certificate.final_root,
+            &certificate.statement,
+            certificate.content.as_ref(),
+            &certificate.action,
+            &certificate.dependencies,
+            &certificate.rules,
+        )?;
+        ensure!(
+            tally.yes_votes == certificate.yes_votes && tally.no_votes == certificate.no_votes,
+            "the proof tallies {} yes and {} no votes, the certificate {} and {}",
+            tally.yes_votes,
+            tally.no_votes,
+            certificate.yes_votes,
+            certificate.no_votes
+        );
+        Ok(tally)
+    })
+    .await??;
+    Ok(tally)
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2_tree_hacks::{
+        common::WHashOut,
+        proof::{
+            certificate::FinalizationCertificate,
+            codec::{ProofEnvelope, PROOF_ENVELOPE_VERSION},
+        },
+        proposal::rules::{ProposalOutcome, TiePolicy},
+    };
+    use uuid::Uuid;
+
+    use super::{verify_locally, FinalizedProposal, Weight};
+
+    #[tokio::test]
+    async fn test_verify_locally_rejects_a_proof_of_another_tree() {
+        let root = |value: u64| WHashOut::from_values(value, value, value, value);
+        let certificate = FinalizationCertificate {
+            proposal_id: Uuid::new_v4(),
+            statement: "Fund the audit".to_string(),
+            content: None,
+            action: Default::default(),
+            initial_root: root(1),
+            final_root: root(3),
+            yes_votes: Weight::from(2),
+            no_votes: Weight::from(1),
+            outcome: ProposalOutcome::Passed,
+            tie_policy: TiePolicy::default(),
+            beacon: None,
+            beacon_block: None,
+            revote: None,
+            circuit_id: "update_balance:1:32:32".to_string(),
+            nullifier_root: None,
+            binding: WHashOut::ZERO,
+            anchors: vec![],
+            transcript_digest: [0; 32],
+            timestamps: vec![],
+            issuer: None,
+            dependencies: vec![],
+            approvals: vec![],
+            rules: Default::default(),
+        };
+        let proof = ProofEnvelope {
+            version: PROOF_ENVELOPE_VERSION,
+            circuit_id: certificate.circuit_id.clone(),
+            common_data_hash: vec![1; 32],
+            public_inputs: vec![1, 1, 1, 1, 2, 2, 2, 2, 2, 1],
+            proof_bytes: vec![],
+        };
+        let err = verify_locally(&FinalizedProposal { certificate, proof })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("final root"));
+    }
+}
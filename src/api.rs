@@ -0,0 +1,647 @@
+//! Request and response bodies of the HTTP API, shared by the server and
+//! [`qed_client`](crate::qed_client). Their schemas make up the OpenAPI document
+//! served at `/openapi.json`.
+
+use plonky2::field::goldilocks_field::GoldilocksField;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use web3::types::Address;
+
+use crate::{
+    auth::{ApiKeyView, Role},
+    balance::{
+        accounts::{Tally, VoteSplit},
+        funds::Payout,
+        locks::{LockStatus, TokenLock},
+        treasury::DepositStatus,
+        vesting::VestingRules,
+        weight::Weight,
+    },
+    chain::{anchor::ResultRootRecord, token_snapshot::TokenSnapshotRequest},
+    circuits::quadratic::VotingPolicy,
+    common::WHashOut,
+    did::Did,
+    proof::{
+        codec::ProofEnvelope,
+        leaf::{LeafChange, LeafDeltaProof, LeafProof},
+    },
+    proposal::{
+        action::ProposalAction,
+        approval::FinalizerPolicy,
+        content::StatementContent,
+        encryption::{BallotCommittee, EncryptedBallot},
+        org::{CategoryPolicy, RegistrationStatus},
+        quota::{DaoQuotas, DaoUsage},
+        relay::RelayedVote,
+        rules::{ConvictionRules, ProposalOutcome, TiePolicy},
+        sanity::TreeDivergence,
+        scoped_delegation::DelegationScope,
+        tally_history::TallyPoint,
+        ProposalStatus,
+    },
+    webhook::{DeliveryStatus, WebhookTarget},
+};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ProposeQuery {
+    pub proposer_id: u32,
+    pub statement: String,
+    /// External document the statement refers to, whose hash the finalization proof commits to
+    pub content: Option<StatementContent>,
+    /// What passing the proposal commits to, text only if not set
+    pub action: Option<ProposalAction>,
+    pub voting_period_secs: Option<u64>,
+    pub quorum: Option<Weight>,
+    pub tie_policy: Option<TiePolicy>,
+    /// Block whose hash breaks ties, required by the random-with-beacon tie policy. It has
+    /// to lie beyond the head of the chain, and the server fetches it from its Ethereum RPC
+    /// endpoint at finalization
+    pub beacon_block: Option<u64>,
+    /// Seeds voting power from ERC-20 balances instead of one vote per voter
+    pub token_snapshot: Option<TokenSnapshotRequest>,
+    /// DAO the proposal is accounted to, the default DAO if not set
+    pub dao_id: Option<String>,
+    /// Category to file the proposal under, which delegations can be scoped to
+    pub category: Option<String>,
+    /// Seconds during which voters commit to their votes before casting them
+    pub commit_period_secs: Option<u64>,
+    /// Bits balances are range checked to, for electorates too heavy for the default
+    pub balance_bits: Option<usize>,
+    /// Registers one voter per DID, with one vote each, whose requests then have to be signed
+    #[schema(value_type = Option<Vec<String>>)]
+    pub voter_dids: Option<Vec<Did>>,
+    /// Number of voters with one vote each, when the electorate is neither registered
+    /// by DID nor seeded from a token snapshot. The balance tree, and so the proofs of
+    /// the proposal, are sized to fit it.
+    pub electorate_size: Option<usize>,
+    /// Weighs votes by how long before the deadline they are cast
+    pub conviction: Option<ConvictionRules>,
+    /// Counts the square root of the weight each vote spends, linear if not set
+    pub voting_policy: Option<VotingPolicy>,
+    /// Proposals this one can only pass along with, which it is finalized after
+    pub depends_on: Option<Vec<Uuid>>,
+    /// Derives the id of the proposal from its statement, proposer and this nonce, so
+    /// that creating it again returns the same proposal, see `proposal::id`
+    pub nonce: Option<u64>,
+    /// Places voters at the leaves of a keyed pseudorandom permutation instead of at
+    /// their ids, which only auditors are told, see `proposal::blinding`
+    pub blind_voters: Option<bool>,
+    /// Finalizers a threshold of whom has to approve finalizing the proposal through
+    /// `POST /finalize/approve`, instead of the proposer finalizing it alone
+    pub finalizers: Option<FinalizerPolicy>,
+    /// Seeds voters with the tokens they hold in the token tree shared by all
+    /// proposals, and locks the tokens of each voter as they vote or delegate until
+    /// the proposal is finalized or cancelled, see `balance::locks`
+    pub lock_tokens: Option<bool>,
+    /// Takes votes as ballots encrypted to this committee, which decrypts them once the
+    /// voting period ends, see `proposal::encryption`
+    pub ballot_committee: Option<BallotCommittee>,
+    /// The epoch the proposal is voted at and the weight of voters locked until a
+    /// later epoch, which they cannot cast before it, see `balance::vesting`
+    pub vesting: Option<VestingRules>,
+    /// Least weight a vote or delegation moves, rejecting votes of a few units
+    /// that would bloat the proof of the proposal
+    pub min_transfer: Option<Weight>,
+    /// Splits the electorate across this many balance trees, proven in parallel, which
+    /// take plain votes and delegations between voters of the same tree only, see
+    /// `balance::shards`
+    pub shard_count: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct VoteQuery {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    /// Ignored when the vote is split
+    #[serde(default)]
+    pub is_yes: bool,
+    /// Weight to cast on each option, adding up to the voting weight of the voter,
+    /// instead of casting all of it on `is_yes`
+    pub split: Option<VoteSplit>,
+    /// Hex encoded salt opening the commitment of the voter, on proposals with a commitment period
+    pub salt: Option<String>,
+    /// The vote encrypted to the committee, instead of `is_yes`, on proposals that take
+    /// encrypted ballots
+    pub ballot: Option<EncryptedBallot>,
+    /// Hex encoded signature of the "vote" request by the DID of the voter, see `did_request_message`
+    pub did_signature: Option<String>,
+    /// Number of signed requests of the voter applied on the proposal before this one,
+    /// which the signature covers so that it cannot be replayed
+    #[serde(default)]
+    pub did_nonce: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct RelayedVoteQuery {
+    pub vote: RelayedVote,
+    /// Hex encoded signature of the EIP-712 digest of the vote by the DID of the voter,
+    /// see `RelayedVote::digest`
+    pub signature: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CommitQuery {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    /// Hex encoded SHA-256 of the voter id, vote and salt, see `compute_vote_commitment`
+    pub commitment: String,
+    /// Hex encoded signature of the "commit" request by the DID of the voter, see `did_request_message`
+    pub did_signature: Option<String>,
+    /// Number of signed requests of the voter applied on the proposal before this one,
+    /// which the signature covers so that it cannot be replayed
+    #[serde(default)]
+    pub did_nonce: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct DelegateQuery {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    pub delegator_id: u32,
+    /// Hex encoded signature of the "delegate" request by the DID of the voter, see `did_request_message`
+    pub did_signature: Option<String>,
+    /// Number of signed requests of the voter applied on the proposal before this one,
+    /// which the signature covers so that it cannot be replayed
+    #[serde(default)]
+    pub did_nonce: u64,
+}
+
+/// A delegation of a [`DelegateBatchQuery`].
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchDelegationItem {
+    pub voter_id: u32,
+    pub delegator_id: u32,
+    /// Hex encoded signature of the "delegate" request by the DID of the voter, as for a single delegation
+    pub did_signature: Option<String>,
+    /// Next nonce of the voter, as for a single delegation
+    #[serde(default)]
+    pub did_nonce: u64,
+}
+
+/// Delegations on a proposal applied all or none, see
+/// [`Proposal::delegate_batch`](crate::proposal::Proposal::delegate_batch).
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct DelegateBatchQuery {
+    pub proposal_id: Uuid,
+    /// Applied in order, at most [`MAX_BATCH_DELEGATIONS`](crate::proposal::delegation::MAX_BATCH_DELEGATIONS)
+    pub delegations: Vec<BatchDelegationItem>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct RevokeQuery {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    /// Hex encoded signature of the "revoke" request by the DID of the voter, see `did_request_message`
+    pub did_signature: Option<String>,
+    /// Number of signed requests of the voter applied on the proposal before this one,
+    /// which the signature covers so that it cannot be replayed
+    #[serde(default)]
+    pub did_nonce: u64,
+}
+
+/// Delegates the weight of a voter on every proposal in `scope`, see
+/// [`scoped_delegation`](crate::proposal::scoped_delegation).
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScopedDelegateQuery {
+    pub voter_id: u32,
+    pub delegate_id: u32,
+    pub scope: DelegationScope,
+    /// Unix time the weight moves back to the voter at, if before the proposals close
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScopedRevokeQuery {
+    pub voter_id: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CancelQuery {
+    pub proposer_id: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AmendQuery {
+    pub proposer_id: u32,
+    pub statement: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeQuery {
+    pub proposal_id: Uuid,
+    pub finalizer_id: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeDryRunQuery {
+    pub finalizer_id: u32,
+}
+
+/// What finalizing a proposal now would prove with, found without proving.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeDryRunResponse {
+    pub proposal_id: Uuid,
+    pub circuit_id: String,
+    /// Whether the circuit was built before the dry run, rather than by it
+    pub circuit_cached: bool,
+    /// Log2 of the number of rows of the circuit, which proving time grows with
+    pub degree_bits: usize,
+    /// Updates proven, padded like for the proof
+    pub number_updates: usize,
+    /// How long generating the witness took
+    pub witness_ms: u64,
+    /// Expected proving time, from the proofs of this or other circuits so far;
+    /// not set before the server has proven anything
+    pub estimated_proving_ms: Option<u64>,
+    /// Why proving would fail, empty if it is expected to succeed
+    pub violations: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeApprovalQuery {
+    pub proposal_id: Uuid,
+    pub finalizer_id: u32,
+    /// Hex encoded Ed25519 signature of the finalizer over `approval_message`
+    pub signature: String,
+    /// Hex encoded beacon value, used if this approval meets the threshold
+    pub beacon: Option<String>,
+}
+
+/// Answer to an approval that left the proposal short of its threshold.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeApprovalResponse {
+    pub proposal_id: Uuid,
+    /// Finalizers who approved so far
+    pub approved_by: Vec<u32>,
+    pub threshold: usize,
+}
+
+/// Answer to an approval: the finalization if the approval met the threshold, and
+/// the approvals so far otherwise.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FinalizeApprovalOutcome {
+    Finalized(FinalizeResponse),
+    Pending(FinalizeApprovalResponse),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CycleFinalizeQuery {
+    /// DAO the cycle belongs to, the default DAO if not set
+    pub dao_id: Option<String>,
+    /// Finalized proposals of the DAO, in the order their results are committed to
+    pub proposal_ids: Vec<Uuid>,
+}
+
+/// Answer to a request that changed a proposal.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ActionResponse {
+    pub proposal_id: Uuid,
+    /// Human readable summary of what was done.
+    pub message: String,
+}
+
+/// Answer to a finalization, whose certificate is served by
+/// `GET /proposal/{id}/certificate`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeResponse {
+    pub proposal_id: Uuid,
+    pub tally: Tally,
+    pub outcome: ProposalOutcome,
+    /// Round voting reopened in, after a tie under the revote tie policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revote: Option<Uuid>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizationPreview {
+    pub tally: Tally,
+    pub tie_policy: TiePolicy,
+    pub is_tie: bool,
+    /// The outcome if the proposal was finalized now; unknown for a tie that is
+    /// broken with a beacon value, or while a proposal it depends on is pending
+    pub outcome: Option<ProposalOutcome>,
+    /// Round finalizing now would reopen voting in, if the outcome is a revote
+    pub revote: Option<Uuid>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DaoUsageResponse {
+    pub usage: DaoUsage,
+    pub quotas: DaoQuotas,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProposalDivergence {
+    pub proposal_id: Uuid,
+    pub divergence: TreeDivergence,
+}
+
+/// Outcome of the last background check of the trees of open proposals against
+/// their recorded updates.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TreeHealthResponse {
+    /// When the last check ran, not set before the first one.
+    pub checked_at: Option<u64>,
+    pub proposals_checked: u64,
+    /// Proposals whose tree diverged from their updates in the last check.
+    pub divergences: Vec<ProposalDivergence>,
+    /// Divergences found since the server started, counting each proposal once.
+    pub divergences_detected: u64,
+}
+
+/// Answer to a restored snapshot. Circuits are built in the background after
+/// the server has answered.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RestoreResponse {
+    pub proposals_restored: usize,
+    pub audit_entries_restored: usize,
+    #[serde(default)]
+    pub events_restored: usize,
+    pub circuits_warming: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct TreasuryCreditQuery {
+    pub proposer_id: u32,
+    /// Added to the account of the proposer, in the unit deposits are required in
+    pub amount: u64,
+}
+
+/// The treasury account a proposer locks deposits from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TreasuryAccount {
+    pub proposer_id: u32,
+    pub balance: u64,
+}
+
+/// The deposit locked for a proposal and, once it is settled and proven, the
+/// proof of its transfer out of escrow.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DepositReceipt {
+    pub proposal_id: Uuid,
+    pub amount: u64,
+    pub status: DepositStatus,
+    /// Leaf of the treasury tree the deposit is escrowed in.
+    pub escrow_index: u64,
+    pub settlement_proof: Option<ProofEnvelope>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FundsCreditQuery {
+    pub dao_id: String,
+    /// ERC-20 token contract, ether if not set.
+    #[schema(value_type = Option<String>)]
+    pub token: Option<Address>,
+    /// Added to the fund of the DAO, in base units of the token
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenCreditQuery {
+    pub voter_id: u32,
+    /// Added to the free tokens of the voter
+    pub amount: u64,
+}
+
+/// Tokens a voter locked on a proposal and, once they are proven, the proofs of
+/// the lock and of the release.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TokenLockReceipt {
+    pub proposal_id: Uuid,
+    pub amount: u64,
+    pub status: LockStatus,
+    /// Leaf of the token tree holding the tokens while they are locked.
+    pub lock_index: u64,
+    pub locked_at: u64,
+    pub lock_proof: Option<ProofEnvelope>,
+    pub release_proof: Option<ProofEnvelope>,
+}
+
+impl From<TokenLock> for TokenLockReceipt {
+    fn from(lock: TokenLock) -> Self {
+        Self {
+            proposal_id: lock.proposal_id,
+            amount: lock.amount,
+            status: lock.status,
+            lock_index: lock.lock_index,
+            locked_at: lock.locked_at,
+            lock_proof: lock.lock_proof,
+            release_proof: lock.release_proof,
+        }
+    }
+}
+
+/// The tokens a voter holds in the shared token tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TokenAccount {
+    pub voter_id: u32,
+    /// Tokens the voter can vote with on proposals that lock tokens.
+    pub free: u64,
+    /// Tokens locked on proposals that have not resolved yet.
+    pub locked: u64,
+    /// Every lock of the voter, oldest first.
+    pub locks: Vec<TokenLockReceipt>,
+}
+
+/// A payout out of the funds of a DAO and, once it is proven, the proof of its
+/// transfer out of the fund.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PayoutReceipt {
+    pub proposal_id: Uuid,
+    pub dao_id: String,
+    #[schema(value_type = Option<String>)]
+    pub token: Option<Address>,
+    #[schema(value_type = String)]
+    pub recipient: Address,
+    pub amount: u64,
+    /// Leaf of the funds tree the payout was made from.
+    pub fund_index: u64,
+    /// Leaf of the funds tree that received the payout.
+    pub payout_index: u64,
+    pub paid_at: u64,
+    pub proof: Option<ProofEnvelope>,
+}
+
+impl From<Payout> for PayoutReceipt {
+    fn from(payout: Payout) -> Self {
+        Self {
+            proposal_id: payout.proposal_id,
+            dao_id: payout.dao_id,
+            token: payout.token,
+            recipient: payout.recipient,
+            amount: payout.amount,
+            fund_index: payout.fund_index,
+            payout_index: payout.payout_index,
+            paid_at: payout.paid_at,
+            proof: payout.proof,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LeafProofQuery {
+    /// Root to prove the leaf under, as the number of recorded updates applied:
+    /// 0 for the initial root, the current root if not set
+    pub at_root: Option<usize>,
+}
+
+/// A leaf of the balance tree of a proposal, for light clients to check a
+/// single balance, see [`LeafProof::verify`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LeafProofResponse {
+    pub proposal_id: Uuid,
+    /// Number of recorded updates applied to the root the leaf is proven under.
+    pub at_root: usize,
+    /// Inclusion of the leaf under the root after `at_root` updates.
+    pub proof: LeafProof,
+    /// The last of those updates that changed the leaf, ending at the value of
+    /// the leaf but not necessarily at the root it is proven under. Not set for a
+    /// leaf none of them updated.
+    pub last_update: Option<LeafDeltaProof>,
+}
+
+/// The root of the tree committing to the results of finalized proposals, see
+/// [`crate::proof::results`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResultRootResponse {
+    #[schema(value_type = String)]
+    pub root: WHashOut<GoldilocksField>,
+    /// Number of results the root commits to.
+    pub size: u64,
+    /// The last root posted on-chain, which may commit to fewer results.
+    pub last_anchor: Option<ResultRootRecord>,
+}
+
+/// The leaves of the balance tree of a proposal that votes changed, for auditors
+/// to check every change against the initial and final roots, see
+/// [`LeafChange::verify`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TreeDiffResponse {
+    pub proposal_id: Uuid,
+    #[schema(value_type = String)]
+    pub initial_root: WHashOut<GoldilocksField>,
+    #[schema(value_type = String)]
+    pub final_root: WHashOut<GoldilocksField>,
+    /// By leaf index.
+    pub changes: Vec<LeafChange>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateOrganizationQuery {
+    /// Lowercase letters, digits and dashes; also the id of the DAO of the organization
+    pub id: String,
+    pub name: String,
+    /// Bearer token of the admins of the organization, of which the server keeps a digest
+    pub admin_token: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct RegisterQuery {
+    #[schema(value_type = String)]
+    pub did: Did,
+    /// Hex encoded signature of `qed-dapp:register:{org_id}:{did}` by the key of the DID
+    pub did_signature: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VotersQuery {
+    /// Only registrations of this status
+    pub status: Option<RegistrationStatus>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CategoryPolicyQuery {
+    pub category: String,
+    /// Defaults of the proposals filed under the category, none clearing them
+    pub policy: Option<CategoryPolicy>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct WebhooksQuery {
+    /// Replace the webhooks of the organization, an empty list removing them all
+    pub webhooks: Vec<WebhookTarget>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WebhookDeliveriesQuery {
+    /// Only deliveries of proposals of this organization
+    pub org_id: Option<String>,
+    /// Only deliveries of this status
+    pub status: Option<DeliveryStatus>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct IssueKeyQuery {
+    pub id: String,
+    pub role: Role,
+    /// Hex encoded Ed25519 key the holder signs requests with, instead of a bearer secret
+    pub public_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct RotateKeyQuery {
+    /// New signing key of a signing key; bearer secrets are generated anew
+    pub public_key: Option<String>,
+}
+
+/// Answer to an issued or rotated key, carrying its secret this once.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct IssuedKeyResponse {
+    pub key: ApiKeyView,
+    /// Bearer secret of the key, unset for signing keys
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct PauseQuery {
+    /// Why the server is paused, reported with the pause until it is lifted
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VotingPauseQuery {
+    /// Rejects votes, commitments, revocations and delegations on every proposal while set
+    pub paused: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProposalHistoryQuery {
+    /// Unix time to replay the events of the proposal up to, now if not set
+    pub at: Option<u64>,
+}
+
+/// A proposal as it was at a past time, replayed from its events.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProposalHistoryResponse {
+    pub proposal_id: Uuid,
+    pub at: u64,
+    /// Events of the proposal accepted up to `at`, its creation included
+    pub events_applied: usize,
+    pub status: ProposalStatus,
+    pub statement: String,
+    pub tally: Tally,
+    #[schema(value_type = String)]
+    pub balance_root: WHashOut<GoldilocksField>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TallyHistoryQuery {
+    /// Keeps the last point of each window of this many seconds since the proposal was
+    /// created, stamped with the end of the window; every point if not set
+    pub bucket_secs: Option<u64>,
+}
+
+/// The tally of a proposal after each action that changed it, oldest first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TallyHistoryResponse {
+    pub proposal_id: Uuid,
+    pub created_at: u64,
+    pub points: Vec<TallyPoint>,
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::ensure;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    common::WHashOut,
+    proof::identity::{InstanceSigner, IssuerSignature},
+};
+
+/// The state mutations recorded in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Propose,
+    Amend,
+    Cancel,
+    /// Cancelled for spam by an admin, slashing the deposit of the proposer.
+    Slash,
+    /// Deleted for spam by an admin, slashing the deposit of the proposer.
+    Delete,
+    Vote,
+    Commit,
+    Delegate,
+    Revoke,
+    Finalize,
+    /// Approved for finalization by one of the finalizers of the proposal.
+    ApproveFinalization,
+    /// Paid out of the funds of its DAO after passing with a treasury transfer.
+    Payout,
+    /// Decryption of the encrypted ballots shared by a member of the committee.
+    Decrypt,
+    /// A scoped delegation moved back to its voter when its scope ended.
+    Undelegate,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    /// Position of the entry in the log, across all proposals.
+    pub seq: u64,
+    pub proposal_id: Uuid,
+    pub action: AuditAction,
+    /// The voter or proposer id the request was made for.
+    pub actor_id: u32,
+    pub timestamp: u64,
+    /// SHA-256 of the JSON encoded request.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub request_hash: [u8; 32],
+    /// Roots of the proposal after the mutation was applied.
+    #[schema(value_type = String)]
+    pub balance_root: WHashOut<GoldilocksField>,
+    #[schema(value_type = Option<String>)]
+    pub nullifier_root: Option<WHashOut<GoldilocksField>>,
+    /// Signature of the instance that accepted the mutation, over [`Self::digest`].
+    #[serde(default)]
+    pub issuer: Option<IssuerSignature>,
+}
+
+impl AuditEntry {
+    /// SHA-256 digest of the entry, leaving out the issuer signature.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut entry = self.clone();
+        entry.issuer = None;
+        Sha256::digest(serde_json::to_vec(&entry).unwrap()).into()
+    }
+}
+
+pub fn hash_request<T: Serialize>(request: &T) -> anyhow::Result<[u8; 32]> {
+    Ok(Sha256::digest(serde_json::to_vec(request)?).into())
+}
+
+/// Append-only log of every accepted mutation, persisted as JSON lines when
+/// backed by a file and indexed by proposal in memory.
+pub struct AuditLog {
+    file: Option<File>,
+    entries: HashMap<Uuid, Vec<AuditEntry>>,
+    next_seq: u64,
+    signer: Option<Arc<InstanceSigner>>,
+}
+
+impl AuditLog {
+    pub fn in_memory() -> Self {
+        Self {
+            file: None,
+            entries: HashMap::new(),
+            next_seq: 0,
+            signer: None,
+        }
+    }
+
+    /// Signs every entry appended from now on, making it a receipt attributable to this instance.
+    pub fn with_signer(mut self, signer: Arc<InstanceSigner>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Opens the log at `path`, loading the entries already written to it.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut log = Self::in_memory();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                let entry: AuditEntry = serde_json::from_str(&line)?;
+                log.next_seq = entry.seq + 1;
+                log.entries
+                    .entry(entry.proposal_id)
+                    .or_default()
+                    .push(entry);
+            }
+        }
+        log.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(log)
+    }
+
+    /// Appends an entry, assigning its sequence number. The entry is on disk
+    /// before it becomes visible through [`Self::entries`].
+    pub fn append(&mut self, mut entry: AuditEntry) -> anyhow::Result<u64> {
+        entry.seq = self.next_seq;
+        if let Some(signer) = &self.signer {
+            entry.issuer = Some(signer.sign(&entry.digest())?);
+        }
+        if let Some(file) = &mut self.file {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            file.sync_data()?;
+        }
+        self.next_seq += 1;
+        let seq = entry.seq;
+        self.entries
+            .entry(entry.proposal_id)
+            .or_default()
+            .push(entry);
+        Ok(seq)
+    }
+
+    pub fn entries(&self, proposal_id: &Uuid) -> &[AuditEntry] {
+        self.entries
+            .get(proposal_id)
+            .map_or(&[], |entries| entries.as_slice())
+    }
+
+    /// Every entry of the log, in sequence order.
+    pub fn all_entries(&self) -> Vec<AuditEntry> {
+        let mut entries: Vec<AuditEntry> = self.entries.values().flatten().cloned().collect();
+        entries.sort_by_key(|entry| entry.seq);
+        entries
+    }
+
+    /// Takes over the entries of another log, as they were signed, e.g. when
+    /// restoring a snapshot. The log has to be empty so sequence numbers stay unique.
+    pub fn import(&mut self, entries: Vec<AuditEntry>) -> anyhow::Result<()> {
+        ensure!(
+            self.next_seq == 0,
+            "the audit log already holds {} entries",
+            self.next_seq
+        );
+        ensure!(
+            entries.windows(2).all(|pair| pair[0].seq < pair[1].seq),
+            "audit entries are not in sequence order"
+        );
+        if let Some(file) = &mut self.file {
+            for entry in &entries {
+                let mut line = serde_json::to_vec(entry)?;
+                line.push(b'\n');
+                file.write_all(&line)?;
+            }
+            file.sync_data()?;
+        }
+        self.next_seq = entries.last().map_or(0, |entry| entry.seq + 1);
+        for entry in entries {
+            self.entries
+                .entry(entry.proposal_id)
+                .or_default()
+                .push(entry);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::common::WHashOut;
+
+    use super::{hash_request, AuditAction, AuditEntry, AuditLog};
+
+    #[test]
+    fn test_audit_log_reloads_from_disk() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("qed-audit-{}.jsonl", Uuid::new_v4()));
+        let proposal_id = Uuid::new_v4();
+        let entry = AuditEntry {
+            seq: 0,
+            proposal_id,
+            action: AuditAction::Vote,
+            actor_id: 7,
+            timestamp: 100,
+            request_hash: hash_request(&("vote", 7))?,
+            balance_root: WHashOut::from_values(1, 2, 3, 4),
+            nullifier_root: None,
+            issuer: None,
+        };
+        {
+            let mut log = AuditLog::open(&path)?;
+            log.append(entry.clone())?;
+            log.append(entry.clone())?;
+        }
+        let mut log = AuditLog::open(&path)?;
+        assert_eq!(log.entries(&proposal_id).len(), 2);
+        assert_eq!(log.append(entry)?, 2);
+        assert!(log.entries(&Uuid::new_v4()).is_empty());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+//! Roles of API callers and the keys that authenticate them.
+//!
+//! Every key carries one [`Role`]. A key is either a secret, sent as a bearer
+//! token, of which only the SHA-256 is kept, or an Ed25519 public key, whose
+//! holder signs each request (see [`request_message`]) instead of sending a
+//! secret. Admins issue, rotate and revoke keys through the admin endpoints.
+//!
+//! Which role a route requires is decided by [`required_role`], from its
+//! method and route pattern, so one middleware enforces it for every route.
+
+use std::collections::BTreeMap;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, ApiErrorCode};
+
+/// Prefixes every signed request message.
+pub const REQUEST_SIGNATURE_DOMAIN: &str = "qed-dapp:request:v1";
+
+/// How far the timestamp of a signed request may be from the time of the server,
+/// in seconds, bounding how long a captured request can be replayed.
+pub const MAX_SIGNATURE_SKEW_SECS: u64 = 300;
+
+/// Longest key id, in bytes.
+pub const MAX_KEY_ID_BYTES: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Manages the server, and may do anything the other roles may.
+    Admin,
+    /// Creates, amends, cancels and finalizes proposals.
+    Proposer,
+    /// Votes, commits, revokes and delegates.
+    Voter,
+    /// Reads audit logs, transcripts, the history and blinding of proposals,
+    /// treasury and token accounts and usage.
+    Auditor,
+}
+
+impl Role {
+    /// Whether a caller with this role may call a route requiring `required`.
+    pub fn grants(self, required: Role) -> bool {
+        self == Role::Admin || self == required
+    }
+}
+
+/// The role a route requires, by its method and route pattern, or `None` for
+/// routes anyone may call. Organization routes other than proposing are left to
+/// the admin tokens of their organizations.
+pub fn required_role(method: &str, pattern: &str) -> Option<Role> {
+    match (method, pattern) {
+        (_, pattern) if pattern.starts_with("/admin/") => Some(Role::Admin),
+        (
+            "POST",
+            "/vote"
+            | "/vote/revoke"
+            | "/commit"
+            | "/delegate"
+            | "/delegate/batch"
+            | "/delegations"
+            | "/delegations/{id}/revoke",
+        ) => Some(Role::Voter),
+        ("POST", "/org/{org_id}/register") => Some(Role::Voter),
+        (
+            "POST",
+            "/propose"
+            | "/finalize"
+            | "/finalize/approve"
+            | "/cycle/finalize"
+            | "/proposal/{id}/cancel"
+            | "/proposal/{id}/amend"
+            | "/proposal/{id}/finalize/dry-run"
+            | "/org/{org_id}/propose",
+        ) => Some(Role::Proposer),
+        (
+            "GET",
+            "/proposal/{id}/audit"
+            | "/proposal/{id}/transcript"
+            | "/proposal/{id}/history"
+            | "/proposal/{id}/blinding"
+            | "/proposal/{id}/diff"
+            | "/treasury/{proposer_id}"
+            | "/tokens/{voter_id}"
+            | "/dao/{id}/usage"
+            | "/dao/{id}/funds"
+            | "/dao/{id}/payouts"
+            | "/health/tree",
+        ) => Some(Role::Auditor),
+        _ => None,
+    }
+}
+
+/// The message the holder of a signing key signs to make a request: the domain,
+/// method, path with its query, unix timestamp and hex encoded SHA-256 of the
+/// body, separated by colons.
+pub fn request_message(method: &str, path: &str, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    format!(
+        "{}:{}:{}:{}:{}",
+        REQUEST_SIGNATURE_DOMAIN,
+        method,
+        path,
+        timestamp,
+        hex::encode(Sha256::digest(body))
+    )
+    .into_bytes()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Credential {
+    /// SHA-256 of a bearer secret.
+    Secret([u8; 32]),
+    /// Ed25519 key requests are signed with.
+    SigningKey(VerifyingKey),
+}
+
+#[derive(Clone, Debug)]
+struct ApiKey {
+    role: Role,
+    credential: Credential,
+    created_at: u64,
+    rotated_at: Option<u64>,
+}
+
+/// A key as served to admins, without its secret.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyView {
+    pub id: String,
+    pub role: Role,
+    /// Hex encoded Ed25519 key of a signing key, unset for bearer secrets.
+    pub public_key: Option<String>,
+    pub created_at: u64,
+    pub rotated_at: Option<u64>,
+}
+
+/// Who made a request, once authenticated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal {
+    pub key_id: String,
+    pub role: Role,
+}
+
+fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+fn parse_public_key(hex_key: &str) -> Result<VerifyingKey, ApiError> {
+    let invalid = || {
+        ApiError::new(
+            ApiErrorCode::InvalidQuery,
+            "Public keys are 32 hex encoded bytes of an Ed25519 key",
+        )
+    };
+    let bytes: [u8; 32] = hex::decode(hex_key.trim_start_matches("0x"))
+        .map_err(|_| invalid())?
+        .try_into()
+        .map_err(|_| invalid())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| invalid())
+}
+
+fn key_not_found(id: &str) -> ApiError {
+    ApiError::new(ApiErrorCode::ApiKeyNotFound, format!("No key {}", id))
+}
+
+/// The keys of a server, by id.
+#[derive(Default)]
+pub struct KeyRing {
+    keys: BTreeMap<String, ApiKey>,
+}
+
+impl KeyRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn credential(public_key: Option<&str>) -> Result<(Credential, Option<String>), ApiError> {
+        match public_key {
+            Some(public_key) => Ok((Credential::SigningKey(parse_public_key(public_key)?), None)),
+            None => {
+                let secret = generate_secret();
+                Ok((
+                    Credential::Secret(Sha256::digest(&secret).into()),
+                    Some(secret),
+                ))
+            }
+        }
+    }
+    /// Adds a key with `role`, registering `public_key` if given and generating a
+    /// secret otherwise. The secret is returned, and never again.
+    pub fn issue(
+        &mut self,
+        id: String,
+        role: Role,
+        public_key: Option<&str>,
+        now: u64,
+    ) -> Result<Option<String>, ApiError> {
+        if id.is_empty() || id.len() > MAX_KEY_ID_BYTES {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                format!("Key ids are 1 to {} bytes long", MAX_KEY_ID_BYTES),
+            ));
+        }
+        if self.keys.contains_key(&id) {
+            return Err(ApiError::new(
+                ApiErrorCode::ApiKeyExists,
+                format!("Key {} already exists", id),
+            ));
+        }
+        let (credential, secret) = Self::credential(public_key)?;
+        self.keys.insert(
+            id,
+            ApiKey {
+                role,
+                credential,
+                created_at: now,
+                rotated_at: None,
+            },
+        );
+        Ok(secret)
+    }
+    /// Replaces the credential of a key, keeping its id and role, so the old
+    /// secret or signing key stops working at once.
+    pub fn rotate(
+        &mut self,
+        id: &str,
+        public_key: Option<&str>,
+        now: u64,
+    ) -> Result<Option<String>, ApiError> {
+        let key = self.keys.get_mut(id).ok_or_else(|| key_not_found(id))?;
+        let (credential, secret) = Self::credential(public_key)?;
+        key.credential = credential;
+        key.rotated_at = Some(now);
+        Ok(secret)
+    }
+    pub fn revoke(&mut self, id: &str) -> Result<(), ApiError> {
+        self.keys
+            .remove(id)
+            .map(|_| ())
+            .ok_or_else(|| key_not_found(id))
+    }
+    pub fn view(&self, id: &str) -> Option<ApiKeyView> {
+        let key = self.keys.get(id)?;
+        Some(ApiKeyView {
+            id: id.to_string(),
+            role: key.role,
+            public_key: match &key.credential {
+                Credential::SigningKey(public_key) => Some(hex::encode(public_key.as_bytes())),
+                Credential::Secret(_) => None,
+            },
+            created_at: key.created_at,
+            rotated_at: key.rotated_at,
+        })
+    }
+    pub fn list(&self) -> Vec<ApiKeyView> {
+        self.keys.keys().filter_map(|id| self.view(id)).collect()
+    }
+    /// The key whose secret is `secret`. Compares digests, so the time taken
+    /// does not tell how much of a secret matched.
+    pub fn authenticate_secret(&self, secret: &str) -> Option<Principal> {
+        let digest: [u8; 32] = Sha256::digest(secret).into();
+        self.keys
+            .iter()
+            .find(|(_, key)| key.credential == Credential::Secret(digest))
+            .map(|(id, key)| Principal {
+                key_id: id.clone(),
+                role: key.role,
+            })
+    }
+    /// The signing key `key_id` if it signed `message` with the hex encoded
+    /// `signature`.
+    pub fn authenticate_signature(
+        &self,
+        key_id: &str,
+        message: &[u8],
+        signature: &str,
+    ) -> Option<Principal> {
+        let key = self.keys.get(key_id)?;
+        let public_key = match &key.credential {
+            Credential::SigningKey(public_key) => public_key,
+            Credential::Secret(_) => return None,
+        };
+        let signature: [u8; 64] = hex::decode(signature).ok()?.try_into().ok()?;
+        public_key
+            .verify_strict(message, &Signature::from_bytes(&signature))
+            .ok()?;
+        Some(Principal {
+            key_id: key_id.to_string(),
+            role: key.role,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::{request_message, required_role, KeyRing, Role};
+    use crate::errors::ApiErrorCode;
+
+    #[test]
+    fn test_keys_authenticate_their_role() {
+        let mut keys = KeyRing::new();
+        let secret = keys
+            .issue("ops".to_string(), Role::Auditor, None, 1)
+            .unwrap()
+            .unwrap();
+        let principal = keys.authenticate_secret(&secret).unwrap();
+        assert_eq!(
+            (principal.key_id.as_str(), principal.role),
+            ("ops", Role::Auditor)
+        );
+        assert_eq!(
+            keys.issue("ops".to_string(), Role::Voter, None, 1)
+                .unwrap_err()
+                .code,
+            ApiErrorCode::ApiKeyExists
+        );
+
+        let rotated = keys.rotate("ops", None, 2).unwrap().unwrap();
+        assert!(keys.authenticate_secret(&secret).is_none());
+        assert!(keys.authenticate_secret(&rotated).is_some());
+        assert_eq!(keys.view("ops").unwrap().rotated_at, Some(2));
+
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = hex::encode(signing_key.verifying_key().as_bytes());
+        assert_eq!(
+            keys.issue("bot".to_string(), Role::Voter, Some(&public_key), 3),
+            Ok(None)
+        );
+        let message = request_message("POST", "/vote", 3, b"{}");
+        let signature = hex::encode(signing_key.sign(&message).to_bytes());
+        assert_eq!(
+            keys.authenticate_signature("bot", &message, &signature)
+                .unwrap()
+                .role,
+            Role::Voter
+        );
+        let replayed = request_message("POST", "/delegate", 3, b"{}");
+        assert!(keys
+            .authenticate_signature("bot", &replayed, &signature)
+            .is_none());
+        assert!(keys
+            .authenticate_signature("ops", &message, &signature)
+            .is_none());
+
+        keys.revoke("bot").unwrap();
+        assert!(keys
+            .authenticate_signature("bot", &message, &signature)
+            .is_none());
+        assert_eq!(keys.list().len(), 1);
+    }
+
+    #[test]
+    fn test_routes_require_roles() {
+        assert_eq!(required_role("POST", "/vote"), Some(Role::Voter));
+        assert_eq!(required_role("POST", "/vote/relayed"), None);
+        assert_eq!(required_role("POST", "/finalize"), Some(Role::Proposer));
+        assert_eq!(
+            required_role("DELETE", "/admin/proposal/{id}"),
+            Some(Role::Admin)
+        );
+        assert_eq!(
+            required_role("GET", "/proposal/{id}/audit"),
+            Some(Role::Auditor)
+        );
+        assert_eq!(required_role("GET", "/proposal/{id}/proof"), None);
+        assert!(Role::Admin.grants(Role::Voter));
+        assert!(!Role::Proposer.grants(Role::Voter));
+    }
+}
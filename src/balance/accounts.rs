@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::weight::{Weight, WeightDelta};
+
+/// Number of leaves at the start of every balance tree reserved for vote tallies.
+pub const TALLY_SLOT_COUNT: u64 = 2;
+/// Element of a voter leaf set to one once the voter has delegated; element zero holds the balance.
+pub const DELEGATION_FLAG_ELEMENT: usize = 1;
+/// Element of a voter leaf holding the part of the balance locked by a vesting
+/// schedule, see [`super::vesting`].
+pub const LOCKED_ELEMENT: usize = 2;
+/// Element of a voter leaf holding the epoch its locked weight unlocks at.
+pub const UNLOCK_EPOCH_ELEMENT: usize = 3;
+/// Element of a tally slot holding when it last took a vote or gave weight back,
+/// in unix seconds, on proposals whose updates are stamped with the time they
+/// were recorded, see [`crate::circuits::deadline`]. Zero on other proposals.
+pub const TALLY_CLOCK_ELEMENT: usize = 1;
+
+/// Widest balances the update circuit can range check. Two such balances sum to
+/// less than 2^64, so a sum wrapping around the Goldilocks modulus ends up below
+/// the balance it was added to, which the circuit rejects.
+pub const MAX_BALANCE_BITS: usize = 63;
+/// Width balances are range checked to unless a proposal asks for wider ones.
+pub const DEFAULT_BALANCE_BITS: usize = 32;
+
+/// A leaf of the balance tree that accumulates votes for one option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TallySlot(u64);
+
+impl TallySlot {
+    pub const NO: Self = Self(0);
+    pub const YES: Self = Self(1);
+
+    pub fn for_vote(is_yes: bool) -> Self {
+        if is_yes {
+            Self::YES
+        } else {
+            Self::NO
+        }
+    }
+    pub fn index(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A leaf of the balance tree owned by a voter, never one of the tally slots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VoterLeaf(u64);
+
+impl VoterLeaf {
+    pub fn from_voter_id(voter_id: u32) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            voter_id as u64 >= TALLY_SLOT_COUNT,
+            "voter id {} is reserved for a tally slot",
+            voter_id
+        );
+        Ok(Self(voter_id as u64))
+    }
+    /// Leaf of the `position`-th voter of the electorate.
+    pub fn from_position(position: u64) -> Self {
+        Self(position + TALLY_SLOT_COUNT)
+    }
+    pub fn index(&self) -> u64 {
+        self.0
+    }
+    /// Position of the voter in the electorate.
+    pub fn position(&self) -> u64 {
+        self.0 - TALLY_SLOT_COUNT
+    }
+}
+
+/// A transfer of voting weight between two leaves of the balance tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceTx {
+    Vote {
+        voter: VoterLeaf,
+        slot: TallySlot,
+        amount: WeightDelta,
+    },
+    Delegate {
+        voter: VoterLeaf,
+        delegate: VoterLeaf,
+        amount: WeightDelta,
+    },
+    /// Moves weight `voter` cast on `slot` back to their leaf.
+    Revoke {
+        voter: VoterLeaf,
+        slot: TallySlot,
+        amount: WeightDelta,
+    },
+    /// Moves weight `voter` delegated back from `delegate`, who holds it, to
+    /// the leaf of `voter`, which may then delegate or vote again.
+    Undelegate {
+        voter: VoterLeaf,
+        delegate: VoterLeaf,
+        amount: WeightDelta,
+    },
+}
+
+impl BalanceTx {
+    pub fn sender_index(&self) -> u64 {
+        match self {
+            BalanceTx::Vote { voter, .. } => voter.index(),
+            BalanceTx::Delegate { voter, .. } => voter.index(),
+            BalanceTx::Revoke { slot, .. } => slot.index(),
+            BalanceTx::Undelegate { delegate, .. } => delegate.index(),
+        }
+    }
+    pub fn receiver_index(&self) -> u64 {
+        match self {
+            BalanceTx::Vote { slot, .. } => slot.index(),
+            BalanceTx::Delegate { delegate, .. } => delegate.index(),
+            BalanceTx::Revoke { voter, .. } => voter.index(),
+            BalanceTx::Undelegate { voter, .. } => voter.index(),
+        }
+    }
+    pub fn amount(&self) -> WeightDelta {
+        match self {
+            BalanceTx::Vote { amount, .. } => *amount,
+            BalanceTx::Delegate { amount, .. } => *amount,
+            BalanceTx::Revoke { amount, .. } => *amount,
+            BalanceTx::Undelegate { amount, .. } => *amount,
+        }
+    }
+}
+
+/// The weight a voter casts on each option, adding up to their full balance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VoteSplit {
+    pub yes_votes: Weight,
+    pub no_votes: Weight,
+}
+
+impl VoteSplit {
+    /// The options receiving weight, each with the weight it receives.
+    pub fn parts(&self) -> Vec<(TallySlot, WeightDelta)> {
+        [
+            (TallySlot::YES, self.yes_votes),
+            (TallySlot::NO, self.no_votes),
+        ]
+        .into_iter()
+        .filter(|(_, weight)| *weight != Weight::ZERO)
+        .map(|(slot, weight)| (slot, weight.into()))
+        .collect()
+    }
+    /// The weight of both options together, `None` if it overflows.
+    pub fn total(&self) -> Option<Weight> {
+        self.yes_votes.checked_add(self.no_votes.into())
+    }
+}
+
+/// The vote totals of a proposal, as read from its tally slots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Tally {
+    pub yes_votes: Weight,
+    pub no_votes: Weight,
+}
+
+impl Tally {
+    /// True if there is a strict majority of yes votes.
+    pub fn is_passed(&self) -> bool {
+        self.yes_votes > self.no_votes
+    }
+    pub fn is_tie(&self) -> bool {
+        self.yes_votes == self.no_votes
+    }
+}
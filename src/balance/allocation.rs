@@ -0,0 +1,114 @@
+//! Assignment of voter leaves within the capacity of a balance tree.
+//!
+//! The leaves of a balance tree are the tally slots followed by one leaf per
+//! voter. Voters are allocated the leaves in the order they are registered, so
+//! that replaying the same registrations always yields the same voter ids, and
+//! never past the last leaf of the tree nor past the largest `u32` voter id.
+
+use std::ops::Range;
+
+use crate::errors::QedError;
+
+use super::accounts::{VoterLeaf, TALLY_SLOT_COUNT};
+
+/// Hands out the voter leaves of a tree of a given height one after another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeafAllocator {
+    height: u8,
+    allocated: u64,
+}
+
+impl LeafAllocator {
+    /// An allocator of the voter leaves of a tree of `height`, of which the
+    /// first `allocated` are already taken.
+    pub fn new(height: u8, allocated: u64) -> Self {
+        Self { height, allocated }
+    }
+    /// Number of voters the tree has leaves for.
+    pub fn capacity(&self) -> u64 {
+        let leaves = 1u64
+            .checked_shl(self.height as u32)
+            .unwrap_or(u64::MAX)
+            .min(u32::MAX as u64 + 1);
+        leaves.saturating_sub(TALLY_SLOT_COUNT)
+    }
+    /// Number of leaves handed out so far.
+    pub fn allocated(&self) -> u64 {
+        self.allocated
+    }
+    /// Leaf indices of the voters allocated so far.
+    pub fn bounds(&self) -> Range<u64> {
+        VoterLeaf::from_position(0).index()..VoterLeaf::from_position(self.allocated).index()
+    }
+    /// The next free voter leaf, failing once the tree is full.
+    pub fn allocate(&mut self) -> Result<VoterLeaf, QedError> {
+        if self.allocated >= self.capacity() {
+            return Err(QedError::LeafOutOfRange {
+                leaf: VoterLeaf::from_position(self.allocated).index(),
+                voters: self.capacity(),
+            });
+        }
+        let leaf = VoterLeaf::from_position(self.allocated);
+        self.allocated += 1;
+        Ok(leaf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::{BalanceStorage, MAX_TREE_HEIGHT},
+            weight::{Weight, WeightDelta},
+        },
+        errors::{ApiErrorCode, QedError},
+    };
+
+    use super::LeafAllocator;
+
+    #[test]
+    fn test_allocates_leaves_within_the_tree() {
+        let mut allocator = LeafAllocator::new(2, 0);
+        assert_eq!(allocator.capacity(), 2);
+        assert_eq!(allocator.allocate(), Ok(VoterLeaf::from_position(0)));
+        assert_eq!(allocator.allocate(), Ok(VoterLeaf::from_position(1)));
+        assert_eq!(allocator.bounds(), 2..4);
+        assert_eq!(
+            allocator.allocate(),
+            Err(QedError::LeafOutOfRange { leaf: 4, voters: 2 })
+        );
+        // The tallest tree is bounded by the largest voter id
+        assert_eq!(
+            LeafAllocator::new(MAX_TREE_HEIGHT, 0).capacity(),
+            u32::MAX as u64 - 1
+        );
+
+        // Leaves past the electorate are rejected even where the tree has room
+        let mut storage = BalanceStorage::new(32, [3u32, 5].map(Weight::from).to_vec());
+        let err = storage
+            .process_tx(BalanceTx::Vote {
+                voter: VoterLeaf::from_voter_id(4_000_000_000).unwrap(),
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(0),
+            })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<QedError>().map(QedError::code),
+            Some(ApiErrorCode::InvalidVoter)
+        );
+        let err = storage
+            .process_tx(BalanceTx::Delegate {
+                voter: VoterLeaf::from_position(0),
+                delegate: VoterLeaf::from_position(2),
+                amount: WeightDelta::from(1),
+            })
+            .unwrap_err();
+        assert!(err.downcast_ref::<QedError>().is_some());
+        assert_eq!(
+            storage.get_balance(VoterLeaf::from_position(0)).unwrap(),
+            Weight::from(3)
+        );
+        assert!(!storage.is_registered(VoterLeaf::from_position(2)));
+    }
+}
@@ -0,0 +1,390 @@
+//! Funds DAOs hold, paid out by passed proposals with a
+//! [`ProposalAction::TreasuryTransfer`](crate::proposal::action::ProposalAction::TreasuryTransfer)
+//! action, kept in a tree apart from the [treasury](super::treasury) of deposits.
+//!
+//! Every DAO gets a fund leaf per token it holds, from 0 on in the order funds
+//! are first credited, and every payout gets a leaf of its own from
+//! [`PAYOUT_OFFSET`] on, which receives the amount paid. Payouts are transfers
+//! between the two, recorded as [`BalanceUpdate`]s so that each can be proven
+//! with a [`PayoutCircuit`](crate::circuits::payout::PayoutCircuit).
+
+use anyhow::ensure;
+use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use web3::types::{Address, U256};
+
+use crate::{
+    circuits::{
+        quadratic::VotingPolicy,
+        update_balance::{BalanceUpdate, UpdateKind},
+    },
+    common::{hash::merkle::helpers::merkle_proof::DeltaMerkleProof, WHashOut},
+    proof::codec::ProofEnvelope,
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+use super::accounts::MAX_BALANCE_BITS;
+
+pub const FUNDS_TREE_HEIGHT: u8 = 33;
+/// Index of the first payout leaf, right after the fund leaves.
+pub const PAYOUT_OFFSET: u64 = 1 << 32;
+/// Width balances of the funds tree are range checked to in payout proofs.
+pub const FUNDS_BALANCE_BITS: usize = MAX_BALANCE_BITS;
+
+/// What a DAO holds of one token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FundBalance {
+    pub dao_id: String,
+    /// ERC-20 token contract, ether if not set.
+    #[schema(value_type = Option<String>)]
+    pub token: Option<Address>,
+    /// Leaf of the funds tree holding the balance.
+    pub fund_index: u64,
+    pub balance: u64,
+}
+
+/// A transfer out of the funds of a DAO, made when a proposal passed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Payout {
+    pub proposal_id: Uuid,
+    pub dao_id: String,
+    pub token: Option<Address>,
+    pub recipient: Address,
+    pub amount: u64,
+    pub fund_index: u64,
+    pub payout_index: u64,
+    pub paid_at: u64,
+    /// Hash of the action of the proposal, which the proof of the payout names.
+    pub action_hash: WHashOut<GoldilocksField>,
+    /// Transfer from the fund leaf to the payout leaf.
+    pub transfer: BalanceUpdate<GoldilocksField>,
+    /// Proof of `transfer`, made in the background once the payout is made.
+    pub proof: Option<ProofEnvelope>,
+}
+
+/// The funds and payouts of every DAO, which rebuild the funds tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FundsSnapshot {
+    /// In fund leaf order.
+    pub funds: Vec<FundBalance>,
+    /// In payout leaf order.
+    pub payouts: Vec<Payout>,
+    pub root: WHashOut<GoldilocksField>,
+}
+
+pub struct DaoFunds {
+    tree: ZeroMerkleTree<GoldilocksField, PoseidonHash, NodeStore>,
+    /// The fund at leaf `i` is the `i`-th.
+    funds: Vec<FundBalance>,
+    /// The payout at leaf `PAYOUT_OFFSET + i` is the `i`-th.
+    payouts: Vec<Payout>,
+}
+
+impl DaoFunds {
+    pub fn new() -> Self {
+        Self {
+            tree: ZeroMerkleTree::new(FUNDS_TREE_HEIGHT, NodeStore::Memory(SimpleNodeStore::new())),
+            funds: vec![],
+            payouts: vec![],
+        }
+    }
+    /// Rebuilds the funds a snapshot was taken of, failing unless the funds and
+    /// payouts are in leaf order and reproduce its root.
+    pub fn restore(snapshot: FundsSnapshot) -> anyhow::Result<Self> {
+        let mut funds = Self::new();
+        for (position, fund) in snapshot.funds.iter().enumerate() {
+            ensure!(
+                fund.fund_index == position as u64,
+                "the fund of {} is not at leaf {}",
+                fund.dao_id,
+                position
+            );
+            funds.set_balance(fund.fund_index, fund.balance)?;
+        }
+        for (position, payout) in snapshot.payouts.iter().enumerate() {
+            ensure!(
+                payout.payout_index == PAYOUT_OFFSET + position as u64,
+                "the payout of proposal {} is not at leaf {}",
+                payout.proposal_id,
+                PAYOUT_OFFSET + position as u64
+            );
+            funds.set_balance(payout.payout_index, payout.amount)?;
+        }
+        ensure!(
+            funds.root()? == snapshot.root,
+            "the balances of the funds do not reproduce their root"
+        );
+        funds.funds = snapshot.funds;
+        funds.payouts = snapshot.payouts;
+        Ok(funds)
+    }
+    pub fn snapshot(&self) -> anyhow::Result<FundsSnapshot> {
+        Ok(FundsSnapshot {
+            funds: self.funds.clone(),
+            payouts: self.payouts.clone(),
+            root: self.root()?,
+        })
+    }
+    /// Whether no fund was ever credited.
+    pub fn is_empty(&self) -> bool {
+        self.funds.is_empty()
+    }
+    pub fn root(&self) -> anyhow::Result<WHashOut<GoldilocksField>> {
+        self.tree.get_root()
+    }
+    fn set_balance(
+        &mut self,
+        index: u64,
+        balance: u64,
+    ) -> anyhow::Result<DeltaMerkleProof<GoldilocksField>> {
+        ensure!(
+            balance < 1 << FUNDS_BALANCE_BITS,
+            "fund balances are at most {} bits wide",
+            FUNDS_BALANCE_BITS
+        );
+        self.tree
+            .set_leaf(index, WHashOut::from_values(balance, 0, 0, 0))
+    }
+    fn fund_position(&self, dao_id: &str, token: Option<Address>) -> Option<usize> {
+        self.funds
+            .iter()
+            .position(|fund| fund.dao_id == dao_id && fund.token == token)
+    }
+    /// The funds `dao_id` holds, one per token.
+    pub fn dao_funds(&self, dao_id: &str) -> Vec<FundBalance> {
+        self.funds
+            .iter()
+            .filter(|fund| fund.dao_id == dao_id)
+            .cloned()
+            .collect()
+    }
+    /// The payouts out of the funds of `dao_id`, oldest first.
+    pub fn dao_payouts(&self, dao_id: &str) -> Vec<Payout> {
+        self.payouts
+            .iter()
+            .filter(|payout| payout.dao_id == dao_id)
+            .cloned()
+            .collect()
+    }
+    pub fn payout(&self, proposal_id: &Uuid) -> Option<&Payout> {
+        self.payouts
+            .iter()
+            .find(|payout| payout.proposal_id == *proposal_id)
+    }
+    /// Adds `amount` of `token` to the funds of `dao_id`, e.g. once the DAO
+    /// deposited it with the operator, giving the DAO a fund leaf for the token
+    /// on its first credit.
+    pub fn credit(
+        &mut self,
+        dao_id: &str,
+        token: Option<Address>,
+        amount: u64,
+    ) -> anyhow::Result<FundBalance> {
+        let position = match self.fund_position(dao_id, token) {
+            Some(position) => position,
+            None => {
+                ensure!(
+                    (self.funds.len() as u64) < PAYOUT_OFFSET,
+                    "the funds tree has run out of fund leaves"
+                );
+                self.funds.push(FundBalance {
+                    dao_id: dao_id.to_string(),
+                    token,
+                    fund_index: self.funds.len() as u64,
+                    balance: 0,
+                });
+                self.funds.len() - 1
+            }
+        };
+        let (fund_index, balance) = (
+            self.funds[position].fund_index,
+            self.funds[position].balance,
+        );
+        let balance = balance
+            .checked_add(amount)
+            .ok_or_else(|| anyhow::anyhow!("the fund of {} overflows", dao_id))?;
+        self.set_balance(fund_index, balance)?;
+        self.funds[position].balance = balance;
+        Ok(self.funds[position].clone())
+    }
+    /// Pays `amount` of `token` out of the funds of `dao_id` to a new payout leaf
+    /// for `recipient`, once per proposal.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pay(
+        &mut self,
+        proposal_id: Uuid,
+        dao_id: &str,
+        token: Option<Address>,
+        recipient: Address,
+        amount: U256,
+        action_hash: WHashOut<GoldilocksField>,
+        paid_at: u64,
+    ) -> anyhow::Result<Payout> {
+        ensure!(
+            self.payout(&proposal_id).is_none(),
+            "proposal {} has already been paid out",
+            proposal_id
+        );
+        ensure!(
+            amount.bits() <= FUNDS_BALANCE_BITS,
+            "payouts are at most {} bits wide",
+            FUNDS_BALANCE_BITS
+        );
+        let amount = amount.as_u64();
+        let position = self
+            .fund_position(dao_id, token)
+            .ok_or_else(|| anyhow::anyhow!("{} holds none of the token", dao_id))?;
+        let fund = &self.funds[position];
+        ensure!(
+            fund.balance >= amount,
+            "{} holds {} of the token, not {}",
+            dao_id,
+            fund.balance,
+            amount
+        );
+        let (fund_index, balance) = (fund.fund_index, fund.balance - amount);
+        let payout_index = PAYOUT_OFFSET + self.payouts.len() as u64;
+        let sender_update = self.set_balance(fund_index, balance)?;
+        let receiver_update = self.set_balance(payout_index, amount)?;
+        self.funds[position].balance = balance;
+        let payout = Payout {
+            proposal_id,
+            dao_id: dao_id.to_string(),
+            token,
+            recipient,
+            amount,
+            fund_index,
+            payout_index,
+            paid_at,
+            action_hash,
+            transfer: BalanceUpdate {
+                sender_update,
+                receiver_update,
+                kind: UpdateKind::default(),
+                conviction: None,
+                policy: VotingPolicy::Linear,
+                vesting_epoch: None,
+                window: None,
+                min_transfer: None,
+            },
+            proof: None,
+        };
+        self.payouts.push(payout.clone());
+        Ok(payout)
+    }
+    /// Payouts whose transfer has not been proven yet.
+    pub fn unproven(&self) -> Vec<Payout> {
+        self.payouts
+            .iter()
+            .filter(|payout| payout.proof.is_none())
+            .cloned()
+            .collect()
+    }
+    pub fn set_proof(&mut self, proposal_id: &Uuid, proof: ProofEnvelope) {
+        if let Some(payout) = self
+            .payouts
+            .iter_mut()
+            .find(|payout| payout.proposal_id == *proposal_id)
+        {
+            payout.proof = Some(proof);
+        }
+    }
+}
+
+impl Default for DaoFunds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use web3::types::{Address, U256};
+
+    use super::{DaoFunds, PAYOUT_OFFSET};
+    use crate::{proof::certificate::compute_action_hash, proposal::action::ProposalAction};
+
+    #[test]
+    fn test_pays_out_of_dao_funds() -> anyhow::Result<()> {
+        let mut funds = DaoFunds::new();
+        let recipient = Address::repeat_byte(7);
+        let token = Some(Address::repeat_byte(9));
+        let action_hash = compute_action_hash(&ProposalAction::TextOnly);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(funds
+            .pay(
+                first,
+                "dao",
+                None,
+                recipient,
+                U256::from(10),
+                action_hash,
+                1
+            )
+            .is_err());
+        assert_eq!(funds.credit("dao", None, 100)?.fund_index, 0);
+        assert_eq!(funds.credit("other", None, 5)?.fund_index, 1);
+        assert_eq!(funds.credit("dao", token, 50)?.fund_index, 2);
+
+        let payout = funds.pay(
+            first,
+            "dao",
+            None,
+            recipient,
+            U256::from(60),
+            action_hash,
+            1,
+        )?;
+        assert_eq!(payout.payout_index, PAYOUT_OFFSET);
+        assert_eq!(payout.transfer.check_weights(63)?.get(), 60);
+        assert_eq!(payout.transfer.new_root(), funds.root()?);
+        // Paid once per proposal, and never more than the fund holds
+        assert!(funds
+            .pay(first, "dao", None, recipient, U256::from(1), action_hash, 2)
+            .is_err());
+        assert!(funds
+            .pay(
+                second,
+                "dao",
+                None,
+                recipient,
+                U256::from(41),
+                action_hash,
+                2
+            )
+            .is_err());
+        assert!(funds
+            .pay(second, "dao", None, recipient, U256::MAX, action_hash, 2)
+            .is_err());
+        funds.pay(
+            second,
+            "dao",
+            token,
+            recipient,
+            U256::from(50),
+            action_hash,
+            2,
+        )?;
+        let balances: Vec<u64> = funds
+            .dao_funds("dao")
+            .iter()
+            .map(|fund| fund.balance)
+            .collect();
+        assert_eq!(balances, [40, 0]);
+        assert_eq!(funds.dao_payouts("dao").len(), 2);
+        assert!(funds.dao_payouts("other").is_empty());
+
+        let restored = DaoFunds::restore(funds.snapshot()?)?;
+        assert_eq!(restored.root()?, funds.root()?);
+        let mut tampered = funds.snapshot()?;
+        tampered.payouts[0].amount = 61;
+        assert!(DaoFunds::restore(tampered).is_err());
+        Ok(())
+    }
+}
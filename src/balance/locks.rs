@@ -0,0 +1,398 @@
+//! Tokens voters hold across proposals, kept in a token tree shared by every
+//! proposal created with `lock_tokens`. Such a proposal seeds the weight of each
+//! voter from the tokens they hold, and voting or delegating on it locks them
+//! until the proposal is finalized or cancelled, so that the same tokens do not
+//! vote on two proposals at once.
+//!
+//! Leaves below [`LOCK_OFFSET`] are the accounts of voters, by voter id, holding
+//! the tokens they have free. Every lock gets a leaf of its own from
+//! [`LOCK_OFFSET`] on, holding the locked tokens until they are released back to
+//! the account. Locks and releases are transfers between the two, recorded as
+//! [`BalanceUpdate`]s so that each can be proven with a
+//! [`TokenLockCircuit`](crate::circuits::token_lock::TokenLockCircuit).
+
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    circuits::{
+        quadratic::VotingPolicy,
+        update_balance::{BalanceUpdate, UpdateKind},
+    },
+    common::{hash::merkle::helpers::merkle_proof::DeltaMerkleProof, WHashOut},
+    errors::{ApiError, ApiErrorCode},
+    proof::codec::ProofEnvelope,
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+use super::accounts::MAX_BALANCE_BITS;
+
+pub const TOKEN_TREE_HEIGHT: u8 = 33;
+/// Index of the first lock leaf, right after the accounts of all voter ids.
+pub const LOCK_OFFSET: u64 = 1 << 32;
+/// Width balances of the token tree are range checked to in lock proofs. The
+/// tokens a voter holds, free or locked, never add up to more either.
+pub const TOKEN_BALANCE_BITS: usize = MAX_BALANCE_BITS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LockStatus {
+    Locked,
+    /// Returned to the account of the voter once the proposal was finalized or cancelled.
+    Released,
+}
+
+/// Tokens a voter locked to vote on a proposal, and their release.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenLock {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    pub amount: u64,
+    pub lock_index: u64,
+    pub status: LockStatus,
+    pub locked_at: u64,
+    /// Transfer from the account of the voter to the lock leaf.
+    pub lock: BalanceUpdate<GoldilocksField>,
+    /// Transfer from the lock leaf back to the account of the voter.
+    pub release: Option<BalanceUpdate<GoldilocksField>>,
+    /// Proofs of `lock` and `release`, made in the background.
+    pub lock_proof: Option<ProofEnvelope>,
+    pub release_proof: Option<ProofEnvelope>,
+}
+
+/// The accounts and locks of a token tree, which rebuild it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenLocksSnapshot {
+    /// Free tokens of every voter ever credited.
+    pub accounts: BTreeMap<u32, u64>,
+    /// In lock leaf order.
+    pub locks: Vec<TokenLock>,
+    pub root: WHashOut<GoldilocksField>,
+}
+
+pub struct TokenLocks {
+    tree: ZeroMerkleTree<GoldilocksField, PoseidonHash, NodeStore>,
+    /// Free tokens of every voter ever credited, so the tree is archived without
+    /// walking it.
+    accounts: BTreeMap<u32, u64>,
+    /// The lock at leaf `LOCK_OFFSET + i` is the `i`-th.
+    locks: Vec<TokenLock>,
+}
+
+impl TokenLocks {
+    pub fn new() -> Self {
+        Self {
+            tree: ZeroMerkleTree::new(TOKEN_TREE_HEIGHT, NodeStore::Memory(SimpleNodeStore::new())),
+            accounts: BTreeMap::new(),
+            locks: vec![],
+        }
+    }
+    /// Rebuilds the token tree a snapshot was taken of, failing unless the locks
+    /// are in leaf order and the balances reproduce its root.
+    pub fn restore(snapshot: TokenLocksSnapshot) -> anyhow::Result<Self> {
+        let mut locks = Self::new();
+        for (voter_id, balance) in &snapshot.accounts {
+            locks.set_balance(*voter_id as u64, *balance)?;
+        }
+        for (position, lock) in snapshot.locks.iter().enumerate() {
+            ensure!(
+                lock.lock_index == LOCK_OFFSET + position as u64,
+                "the lock of voter {} on proposal {} is not at leaf {}",
+                lock.voter_id,
+                lock.proposal_id,
+                LOCK_OFFSET + position as u64
+            );
+            if lock.status == LockStatus::Locked {
+                locks.set_balance(lock.lock_index, lock.amount)?;
+            }
+        }
+        ensure!(
+            locks.root()? == snapshot.root,
+            "the balances of the token tree do not reproduce its root"
+        );
+        locks.accounts = snapshot.accounts;
+        locks.locks = snapshot.locks;
+        Ok(locks)
+    }
+    pub fn snapshot(&self) -> anyhow::Result<TokenLocksSnapshot> {
+        Ok(TokenLocksSnapshot {
+            accounts: self.accounts.clone(),
+            locks: self.locks.clone(),
+            root: self.root()?,
+        })
+    }
+    /// Whether no account was ever credited.
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.locks.is_empty()
+    }
+    pub fn root(&self) -> anyhow::Result<WHashOut<GoldilocksField>> {
+        self.tree.get_root()
+    }
+    fn set_balance(
+        &mut self,
+        index: u64,
+        balance: u64,
+    ) -> anyhow::Result<DeltaMerkleProof<GoldilocksField>> {
+        ensure!(
+            balance < 1 << TOKEN_BALANCE_BITS,
+            "token balances are at most {} bits wide",
+            TOKEN_BALANCE_BITS
+        );
+        self.tree
+            .set_leaf(index, WHashOut::from_values(balance, 0, 0, 0))
+    }
+    /// Tokens `voter_id` can lock.
+    pub fn free_balance(&self, voter_id: u32) -> u64 {
+        self.accounts.get(&voter_id).copied().unwrap_or(0)
+    }
+    /// Tokens `voter_id` has locked on proposals that have not resolved yet.
+    pub fn locked_balance(&self, voter_id: u32) -> u64 {
+        self.locks
+            .iter()
+            .filter(|lock| lock.voter_id == voter_id && lock.status == LockStatus::Locked)
+            .map(|lock| lock.amount)
+            .sum()
+    }
+    /// Tokens `voter_id` holds, free or locked, which proposals locking tokens
+    /// seed its weight with.
+    pub fn holdings(&self, voter_id: u32) -> u64 {
+        self.free_balance(voter_id) + self.locked_balance(voter_id)
+    }
+    /// The locks of `voter_id`, oldest first.
+    pub fn voter_locks(&self, voter_id: u32) -> Vec<TokenLock> {
+        self.locks
+            .iter()
+            .filter(|lock| lock.voter_id == voter_id)
+            .cloned()
+            .collect()
+    }
+    pub fn lock_of(&self, proposal_id: &Uuid, voter_id: u32) -> Option<&TokenLock> {
+        self.locks
+            .iter()
+            .find(|lock| lock.proposal_id == *proposal_id && lock.voter_id == voter_id)
+    }
+    /// Adds `amount` to the free tokens of `voter_id`, e.g. once they bridged
+    /// them to the operator, returning their new free balance.
+    pub fn credit(&mut self, voter_id: u32, amount: u64) -> anyhow::Result<u64> {
+        let holdings = self
+            .holdings(voter_id)
+            .checked_add(amount)
+            .filter(|holdings| *holdings < 1 << TOKEN_BALANCE_BITS);
+        ensure!(
+            holdings.is_some(),
+            "the tokens of voter {} would not fit in {} bits",
+            voter_id,
+            TOKEN_BALANCE_BITS
+        );
+        let balance = self.free_balance(voter_id) + amount;
+        self.set_balance(voter_id as u64, balance)?;
+        self.accounts.insert(voter_id, balance);
+        Ok(balance)
+    }
+    /// Whether `voter_id` still has to lock `amount` to vote on `proposal_id`,
+    /// which they do once per proposal and not for no tokens, failing if they
+    /// do not have that much free.
+    pub fn check_lock(
+        &self,
+        proposal_id: &Uuid,
+        voter_id: u32,
+        amount: u64,
+    ) -> Result<bool, ApiError> {
+        if amount == 0 || self.lock_of(proposal_id, voter_id).is_some() {
+            return Ok(false);
+        }
+        let free = self.free_balance(voter_id);
+        if free < amount {
+            return Err(ApiError::new(
+                ApiErrorCode::TokensLocked,
+                format!(
+                    "Voter {} has {} tokens free, {} of their {} are locked on other proposals",
+                    voter_id,
+                    free,
+                    self.locked_balance(voter_id),
+                    self.holdings(voter_id)
+                ),
+            ));
+        }
+        Ok(true)
+    }
+    /// Moves `amount` from the account of `voter_id` to a new lock leaf, until
+    /// `proposal_id` resolves.
+    pub fn lock(
+        &mut self,
+        proposal_id: Uuid,
+        voter_id: u32,
+        amount: u64,
+        locked_at: u64,
+    ) -> anyhow::Result<TokenLock> {
+        ensure!(
+            self.check_lock(&proposal_id, voter_id, amount)
+                .map_err(|err| anyhow::anyhow!(err.message))?,
+            "voter {} has already locked tokens on proposal {}",
+            voter_id,
+            proposal_id
+        );
+        ensure!(
+            (self.locks.len() as u64) < LOCK_OFFSET,
+            "the token tree has run out of lock leaves"
+        );
+        let lock_index = LOCK_OFFSET + self.locks.len() as u64;
+        let balance = self.free_balance(voter_id) - amount;
+        let sender_update = self.set_balance(voter_id as u64, balance)?;
+        let receiver_update = self.set_balance(lock_index, amount)?;
+        self.accounts.insert(voter_id, balance);
+        let lock = TokenLock {
+            proposal_id,
+            voter_id,
+            amount,
+            lock_index,
+            status: LockStatus::Locked,
+            locked_at,
+            lock: BalanceUpdate {
+                sender_update,
+                receiver_update,
+                kind: UpdateKind::default(),
+                conviction: None,
+                policy: VotingPolicy::Linear,
+                vesting_epoch: None,
+                window: None,
+                min_transfer: None,
+            },
+            release: None,
+            lock_proof: None,
+            release_proof: None,
+        };
+        self.locks.push(lock.clone());
+        Ok(lock)
+    }
+    /// Returns the tokens locked on `proposal_id` to the accounts of their voters.
+    pub fn release(&mut self, proposal_id: &Uuid) -> anyhow::Result<Vec<TokenLock>> {
+        let positions: Vec<usize> = self
+            .locks
+            .iter()
+            .enumerate()
+            .filter(|(_, lock)| {
+                lock.proposal_id == *proposal_id && lock.status == LockStatus::Locked
+            })
+            .map(|(position, _)| position)
+            .collect();
+        let mut released = vec![];
+        for position in positions {
+            let (voter_id, amount, lock_index) = (
+                self.locks[position].voter_id,
+                self.locks[position].amount,
+                self.locks[position].lock_index,
+            );
+            let balance = self.free_balance(voter_id) + amount;
+            let sender_update = self.set_balance(lock_index, 0)?;
+            let receiver_update = self.set_balance(voter_id as u64, balance)?;
+            self.accounts.insert(voter_id, balance);
+            let lock = &mut self.locks[position];
+            lock.release = Some(BalanceUpdate {
+                sender_update,
+                receiver_update,
+                kind: UpdateKind::default(),
+                conviction: None,
+                policy: VotingPolicy::Linear,
+                vesting_epoch: None,
+                window: None,
+                min_transfer: None,
+            });
+            lock.status = LockStatus::Released;
+            released.push(lock.clone());
+        }
+        Ok(released)
+    }
+    /// Locks whose lock, or release once made, has not been proven yet.
+    pub fn unproven(&self) -> Vec<TokenLock> {
+        self.locks
+            .iter()
+            .filter(|lock| {
+                lock.lock_proof.is_none()
+                    || (lock.release.is_some() && lock.release_proof.is_none())
+            })
+            .cloned()
+            .collect()
+    }
+    /// Attaches the proof of the lock at `lock_index`, or of its release.
+    pub fn set_proof(&mut self, lock_index: u64, release: bool, proof: ProofEnvelope) {
+        if let Some(lock) = self
+            .locks
+            .iter_mut()
+            .find(|lock| lock.lock_index == lock_index)
+        {
+            if release {
+                lock.release_proof = Some(proof);
+            } else {
+                lock.lock_proof = Some(proof);
+            }
+        }
+    }
+}
+
+impl Default for TokenLocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{LockStatus, TokenLocks, LOCK_OFFSET};
+    use crate::errors::ApiErrorCode;
+
+    #[test]
+    fn test_locks_tokens_until_the_proposal_resolves() -> anyhow::Result<()> {
+        let mut locks = TokenLocks::new();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(locks.credit(7, 100)?, 100);
+        locks.credit(8, 40)?;
+
+        let lock = locks.lock(first, 7, 100, 1)?;
+        assert_eq!(lock.lock_index, LOCK_OFFSET);
+        assert_eq!(lock.lock.check_weights(63)?.get(), 100);
+        assert_eq!(lock.lock.new_root(), locks.root()?);
+        assert_eq!((locks.free_balance(7), locks.holdings(7)), (0, 100));
+        // Voting again on the same proposal locks nothing more
+        assert!(!locks.check_lock(&first, 7, 100)?);
+        // The same tokens cannot vote on another proposal until the first resolves
+        assert_eq!(
+            locks.check_lock(&second, 7, 100).unwrap_err().code,
+            ApiErrorCode::TokensLocked
+        );
+        assert!(locks.lock(second, 7, 100, 2).is_err());
+        locks.lock(first, 8, 40, 2)?;
+
+        let released = locks.release(&first)?;
+        assert_eq!(released.len(), 2);
+        assert!(released
+            .iter()
+            .all(|lock| lock.status == LockStatus::Released));
+        assert_eq!(
+            released[1].release.as_ref().unwrap().new_root(),
+            locks.root()?
+        );
+        assert_eq!(locks.free_balance(7), 100);
+        assert!(locks.release(&first)?.is_empty());
+        locks.lock(second, 7, 100, 3)?;
+        assert_eq!(locks.unproven().len(), 3);
+
+        let restored = TokenLocks::restore(locks.snapshot()?)?;
+        assert_eq!(restored.root()?, locks.root()?);
+        assert_eq!(restored.locked_balance(7), 100);
+        let mut tampered = locks.snapshot()?;
+        tampered.locks[2].amount = 99;
+        assert!(TokenLocks::restore(tampered).is_err());
+        Ok(())
+    }
+}
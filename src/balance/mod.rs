@@ -0,0 +1,9 @@
+pub mod accounts;
+pub mod allocation;
+pub mod funds;
+pub mod locks;
+pub mod shards;
+pub mod storage;
+pub mod treasury;
+pub mod vesting;
+pub mod weight;
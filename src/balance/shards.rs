@@ -0,0 +1,425 @@
+//! Electorates too large to prove as one tree are split across shards, each a
+//! balance tree of its own with its own tally slots and update pipeline. Every
+//! shard is proven by an [`UpdateBalanceCircuit`](crate::circuits::update_balance::UpdateBalanceCircuit),
+//! in parallel, and a [`ShardRootCircuit`](crate::circuits::shard_root::ShardRootCircuit)
+//! combines the shard proofs into the roots and tallies of the whole electorate.
+//!
+//! Voter ids number the whole electorate as in an unsharded tree, the first
+//! shards holding `shard_size` consecutive voters each. Votes stay within the
+//! shard of the voter, and delegations between shards are not supported.
+//!
+//! Proposals created with a shard count, see
+//! [`ProposalRules::shard_count`](crate::proposal::rules::ProposalRules::shard_count),
+//! keep their electorate in a single [`BalanceStorage`] as well, which serves
+//! reads, and mirror every vote and delegation to their shards, whose roots
+//! their finalization proof and certificate expose. Only plain votes and
+//! delegations are sharded, proven without conviction, quadratic weights,
+//! vesting, a voting window or a minimum transfer.
+
+use anyhow::{anyhow, ensure};
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    hash::poseidon::PoseidonHash,
+};
+
+use crate::{
+    circuits::{
+        quadratic::VotingPolicy,
+        shard_root::ShardWitness,
+        update_balance::{padded_update_count, BalanceUpdate, UpdateBalanceShape, UpdateKind},
+    },
+    common::{hash::traits::hasher::FieldWHasher, WHashOut},
+    utils::zmt::node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+};
+
+use super::{
+    accounts::{BalanceTx, Tally, TallySlot, VoterLeaf},
+    storage::BalanceStorage,
+    weight::Weight,
+};
+
+type F = GoldilocksField;
+
+/// Merkle root over the roots of the shards, padded with zero hashes up to a
+/// power of two. Matches the roots exposed by the shard root circuit.
+pub fn compute_sharded_root(shard_roots: &[WHashOut<F>]) -> WHashOut<F> {
+    let mut level = shard_roots.to_vec();
+    level.resize(shard_roots.len().max(1).next_power_of_two(), WHashOut::ZERO);
+    while level.len() > 1 {
+        level = level
+            .chunks_exact(2)
+            .map(|pair| {
+                PoseidonHash::w_hash_many(&[pair[0].0.elements, pair[1].0.elements].concat())
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// A balance tree holding a slice of the electorate, with the updates applied to it.
+pub struct BalanceShard {
+    pub storage: BalanceStorage,
+    pub updates: Vec<BalanceUpdate<F>>,
+}
+
+pub struct ShardedBalanceStorage {
+    pub shards: Vec<BalanceShard>,
+    /// Voters per shard, the last shard holding whatever remains.
+    shard_size: usize,
+}
+
+impl ShardedBalanceStorage {
+    /// Splits the electorate across in-memory trees, see [`Self::with_stores`].
+    pub fn new(
+        shard_height: u8,
+        voter_balances: Vec<Weight>,
+        shard_count: usize,
+        balance_bits: usize,
+    ) -> anyhow::Result<Self> {
+        Self::with_stores(
+            shard_height,
+            voter_balances,
+            shard_count,
+            balance_bits,
+            |_| Ok(NodeStore::Memory(SimpleNodeStore::new())),
+        )
+    }
+    /// Splits the electorate into runs of `ceil(voters / shard_count)` voters, one
+    /// tree of height `shard_height` each, opening the store of the `i`-th with
+    /// `open_store(i)`. Small electorates may end up with fewer shards.
+    ///
+    /// The total weight of the electorate has to fit in `balance_bits` bits, so
+    /// that the tallies summed across shards do as well.
+    pub fn with_stores(
+        shard_height: u8,
+        voter_balances: Vec<Weight>,
+        shard_count: usize,
+        balance_bits: usize,
+        mut open_store: impl FnMut(usize) -> anyhow::Result<NodeStore>,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            (1..=voter_balances.len()).contains(&shard_count),
+            "{} voters cannot be split across {} shards",
+            voter_balances.len(),
+            shard_count
+        );
+        let total_weight = voter_balances
+            .iter()
+            .try_fold(Weight::ZERO, |total, balance| {
+                total.checked_add((*balance).into())
+            })
+            .filter(|total| total.fits(balance_bits));
+        ensure!(
+            total_weight.is_some(),
+            "the total weight of the electorate does not fit in {} bits",
+            balance_bits
+        );
+        let shard_size = voter_balances.len().div_ceil(shard_count);
+        let shards = voter_balances
+            .chunks(shard_size)
+            .enumerate()
+            .map(|(i, balances)| {
+                let storage = BalanceStorage::with_store(
+                    shard_height,
+                    balances.to_vec(),
+                    balance_bits,
+                    open_store(i)?,
+                )?;
+                Ok(BalanceShard {
+                    storage,
+                    updates: vec![],
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { shards, shard_size })
+    }
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+    /// The shard holding `voter_id` and the leaf of the voter within it.
+    pub fn locate(&self, voter_id: u32) -> anyhow::Result<(usize, VoterLeaf)> {
+        self.locate_leaf(VoterLeaf::from_voter_id(voter_id)?)
+    }
+    /// The shard holding the voter at `leaf` of an unsharded tree of the whole
+    /// electorate, and the leaf of the voter within it.
+    fn locate_leaf(&self, leaf: VoterLeaf) -> anyhow::Result<(usize, VoterLeaf)> {
+        let position = leaf.position();
+        let shard = (position / self.shard_size as u64) as usize;
+        let voter = VoterLeaf::from_position(position % self.shard_size as u64);
+        ensure!(
+            shard < self.shards.len() && self.shards[shard].storage.is_registered(voter),
+            "voter id {} is not part of the electorate",
+            leaf.index()
+        );
+        Ok((shard, voter))
+    }
+    /// The shard `tx`, a transaction on an unsharded tree of the whole
+    /// electorate, applies to, and the transaction within that shard. Only
+    /// votes and delegations between voters of the same shard are sharded.
+    pub fn shard_tx(&self, tx: BalanceTx) -> anyhow::Result<(usize, BalanceTx)> {
+        match tx {
+            BalanceTx::Vote {
+                voter,
+                slot,
+                amount,
+            } => {
+                let (shard, voter) = self.locate_leaf(voter)?;
+                Ok((
+                    shard,
+                    BalanceTx::Vote {
+                        voter,
+                        slot,
+                        amount,
+                    },
+                ))
+            }
+            BalanceTx::Delegate {
+                voter,
+                delegate,
+                amount,
+            } => {
+                let (shard, local_voter) = self.locate_leaf(voter)?;
+                let (delegate_shard, local_delegate) = self.locate_leaf(delegate)?;
+                ensure!(
+                    shard == delegate_shard,
+                    "voter {} in shard {} cannot delegate to voter {} in shard {}",
+                    voter.index(),
+                    shard,
+                    delegate.index(),
+                    delegate_shard
+                );
+                Ok((
+                    shard,
+                    BalanceTx::Delegate {
+                        voter: local_voter,
+                        delegate: local_delegate,
+                        amount,
+                    },
+                ))
+            }
+            _ => Err(anyhow!("only votes and delegations are sharded")),
+        }
+    }
+    /// Applies `tx`, a transaction on an unsharded tree of the whole electorate,
+    /// to the shard it falls in, see [`Self::shard_tx`]. Returns the shard.
+    pub fn process_tx(&mut self, tx: BalanceTx) -> anyhow::Result<usize> {
+        let (shard, tx) = self.shard_tx(tx)?;
+        let shard_storage = &mut self.shards[shard];
+        let update = shard_storage.storage.process_tx(tx)?;
+        shard_storage.updates.push(update);
+        Ok(shard)
+    }
+    /// Applies the votes and delegations of `updates`, recorded on an unsharded
+    /// tree of the whole electorate, to the shards in order.
+    pub fn replay(&mut self, updates: &[BalanceUpdate<F>]) -> anyhow::Result<()> {
+        for update in updates {
+            let sender = update.sender_update.index.to_canonical_u64();
+            let receiver = update.receiver_update.index.to_canonical_u64();
+            let voter = VoterLeaf::from_voter_id(sender as u32)?;
+            let amount = update.check_weights(self.shards[0].storage.balance_bits())?;
+            let tx = match update.kind {
+                UpdateKind::Vote => BalanceTx::Vote {
+                    voter,
+                    slot: TallySlot::for_vote(receiver == TallySlot::YES.index()),
+                    amount,
+                },
+                UpdateKind::Delegation => BalanceTx::Delegate {
+                    voter,
+                    delegate: VoterLeaf::from_voter_id(receiver as u32)?,
+                    amount,
+                },
+                kind => return Err(anyhow!("{:?} updates are not sharded", kind)),
+            };
+            self.process_tx(tx)?;
+        }
+        Ok(())
+    }
+    /// Casts the full balance of `voter_id` in the tally of its shard, returning the shard.
+    pub fn vote(&mut self, voter_id: u32, is_yes: bool) -> anyhow::Result<usize> {
+        let (shard, voter) = self.locate(voter_id)?;
+        let amount = self.shards[shard].storage.get_balance(voter)?.into();
+        self.process_tx(BalanceTx::Vote {
+            voter: VoterLeaf::from_voter_id(voter_id)?,
+            slot: TallySlot::for_vote(is_yes),
+            amount,
+        })
+    }
+    /// Moves the full balance of `voter_id` to `delegate_id`, which has to be in
+    /// the same shard. Returns the shard.
+    pub fn delegate(&mut self, voter_id: u32, delegate_id: u32) -> anyhow::Result<usize> {
+        let (shard, voter) = self.locate(voter_id)?;
+        let amount = self.shards[shard].storage.get_balance(voter)?.into();
+        self.process_tx(BalanceTx::Delegate {
+            voter: VoterLeaf::from_voter_id(voter_id)?,
+            delegate: VoterLeaf::from_voter_id(delegate_id)?,
+            amount,
+        })
+    }
+    /// Root of the whole electorate as seeded, see [`compute_sharded_root`].
+    pub fn initial_root(&self) -> WHashOut<F> {
+        let roots: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| shard.storage.initial_root())
+            .collect();
+        compute_sharded_root(&roots)
+    }
+    /// Current root of the whole electorate, see [`compute_sharded_root`].
+    pub fn root(&self) -> anyhow::Result<WHashOut<F>> {
+        let roots = self
+            .shards
+            .iter()
+            .map(|shard| shard.storage.tree.get_root())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(compute_sharded_root(&roots))
+    }
+    /// The tallies of all shards added up.
+    pub fn tally(&self) -> anyhow::Result<Tally> {
+        let mut total = Tally {
+            yes_votes: Weight::ZERO,
+            no_votes: Weight::ZERO,
+        };
+        for shard in &self.shards {
+            let tally = shard.storage.tally()?;
+            total = Tally {
+                yes_votes: total
+                    .yes_votes
+                    .checked_add(tally.yes_votes.into())
+                    .ok_or_else(|| anyhow!("yes votes overflow"))?,
+                no_votes: total
+                    .no_votes
+                    .checked_add(tally.no_votes.into())
+                    .ok_or_else(|| anyhow!("no votes overflow"))?,
+            };
+        }
+        Ok(total)
+    }
+    /// The shape every shard is proven with, fitting the longest pipeline, and
+    /// the witness of each shard padded to it with no-ops.
+    pub fn witnesses(&self) -> anyhow::Result<(UpdateBalanceShape, Vec<ShardWitness<F>>)> {
+        let first = &self.shards[0].storage;
+        let tree_height = first.tree.get_height() as usize;
+        let shape = UpdateBalanceShape {
+            number_updates: self
+                .shards
+                .iter()
+                .map(|shard| padded_update_count(shard.updates.len()))
+                .max()
+                .unwrap(),
+            tree_height,
+            balance_bits: first.balance_bits(),
+            conviction: false,
+            voting_policy: VotingPolicy::Linear,
+            dependencies: false,
+            vesting: false,
+            deadline: false,
+            min_transfer: false,
+        };
+        let witnesses = self
+            .shards
+            .iter()
+            .map(|shard| {
+                let root = shard.storage.tree.get_root()?;
+                let mut updates = shard.updates.clone();
+                updates.resize(shape.number_updates, BalanceUpdate::noop(root, tree_height));
+                Ok(ShardWitness {
+                    updates,
+                    tally_proofs: [
+                        shard.storage.get_tally_proof(TallySlot::NO)?,
+                        shard.storage.get_tally_proof(TallySlot::YES)?,
+                    ],
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((shape, witnesses))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{accounts::DEFAULT_BALANCE_BITS, weight::Weight},
+        errors::{ApiError, ApiErrorCode},
+        proposal::{rules::ProposalRules, Proposal},
+    };
+
+    use super::{compute_sharded_root, ShardedBalanceStorage};
+
+    #[test]
+    fn test_votes_stay_within_their_shard() -> anyhow::Result<()> {
+        let mut storage =
+            ShardedBalanceStorage::new(8, vec![Weight::from(1); 5], 2, DEFAULT_BALANCE_BITS)?;
+        assert_eq!(storage.shard_count(), 2);
+        assert_eq!(storage.locate(2)?.0, 0);
+        assert_eq!(storage.locate(5)?.0, 1);
+        assert!(storage.locate(7).is_err());
+
+        let initial_root = storage.initial_root();
+        assert_eq!(storage.root()?, initial_root);
+        assert_eq!(storage.vote(2, true)?, 0);
+        assert_eq!(storage.vote(6, false)?, 1);
+        assert_eq!(storage.delegate(3, 4)?, 0);
+        assert!(storage.delegate(5, 2).is_err());
+        assert_eq!(storage.vote(4, true)?, 0);
+
+        let tally = storage.tally()?;
+        assert_eq!(
+            (tally.yes_votes, tally.no_votes),
+            (Weight::from(3), Weight::from(1))
+        );
+        let roots = [
+            storage.shards[0].storage.tree.get_root()?,
+            storage.shards[1].storage.tree.get_root()?,
+        ];
+        assert_eq!(storage.root()?, compute_sharded_root(&roots));
+        assert_ne!(storage.root()?, initial_root);
+
+        let (shape, witnesses) = storage.witnesses()?;
+        assert_eq!(shape.number_updates, 4);
+        assert!(witnesses
+            .iter()
+            .all(|witness| witness.updates.len() == shape.number_updates));
+        // The idle half of the second shard's pipeline is padded with no-ops
+        assert!(witnesses[1].updates[1].is_noop());
+        Ok(())
+    }
+    #[test]
+    fn test_sharded_proposals_mirror_votes_and_delegations() -> anyhow::Result<()> {
+        let rules = ProposalRules {
+            shard_count: Some(2),
+            ..ProposalRules::default()
+        };
+        let mut proposal = Proposal::with_voter_balances(
+            "Fund the audit".to_string(),
+            2,
+            0,
+            rules,
+            vec![Weight::from(1); 6],
+        )?;
+        // Voters 2 to 4 are in the first shard, 5 to 7 in the second
+        proposal.cast_vote(2, true, None, 0).unwrap();
+        proposal.delegate(3, 4, 0).unwrap();
+        proposal.cast_vote(6, false, None, 0).unwrap();
+
+        let code = |result: Result<(), ApiError>| result.unwrap_err().code;
+        let root = proposal.storage.tree.get_root()?;
+        assert_eq!(code(proposal.delegate(5, 4, 0)), ApiErrorCode::Sharded);
+        assert_eq!(proposal.storage.tree.get_root()?, root);
+        assert_eq!(code(proposal.revoke_vote(2, 0)), ApiErrorCode::Sharded);
+
+        let shards = proposal.shards.as_ref().unwrap();
+        assert_eq!(shards.tally()?, proposal.storage.tally()?);
+        assert_eq!(
+            proposal.proven_roots()?,
+            (shards.initial_root(), shards.root()?)
+        );
+        assert_ne!(shards.root()?, root);
+
+        // A restored proposal rebuilds its shards from the recorded updates
+        let roots = proposal.proven_roots()?;
+        proposal.shards = None;
+        proposal.seed_shards()?;
+        assert_eq!(proposal.proven_roots()?, roots);
+        Ok(())
+    }
+}
@@ -0,0 +1,701 @@
+use std::{collections::BTreeSet, ops::Range};
+
+use anyhow::{anyhow, ensure};
+use plonky2::{
+    field::{
+        goldilocks_field::GoldilocksField,
+        types::{Field, PrimeField64},
+    },
+    hash::poseidon::PoseidonHash,
+};
+use tracing::trace;
+
+use crate::{
+    circuits::{
+        conviction::ConvictionStamp,
+        deadline::WindowStamp,
+        quadratic::VotingPolicy,
+        update_balance::{BalanceUpdate, UpdateKind},
+    },
+    common::{
+        hash::merkle::helpers::merkle_proof::{DeltaMerkleProof, MerkleProof},
+        WHashOut,
+    },
+    errors::QedError,
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+use super::{
+    accounts::{
+        BalanceTx, Tally, TallySlot, VoteSplit, VoterLeaf, DEFAULT_BALANCE_BITS,
+        DELEGATION_FLAG_ELEMENT, MAX_BALANCE_BITS, TALLY_CLOCK_ELEMENT,
+    },
+    allocation::LeafAllocator,
+    vesting::{LeafValue, VestingRules},
+    weight::{Weight, WeightDelta},
+};
+
+/// Height of the tallest balance tree, whose leaves are indexed by a `u32` voter id.
+pub const MAX_TREE_HEIGHT: u8 = 32;
+
+/// Height of the smallest balance tree with a leaf for each tally slot and each
+/// of `voter_count` voters, which keeps the merkle proofs of finalization short.
+pub fn min_tree_height(voter_count: usize) -> u8 {
+    let leaves_needed = VoterLeaf::from_position(voter_count as u64).index();
+    (leaves_needed.next_power_of_two().trailing_zeros() as u8).max(1)
+}
+
+pub struct BalanceStorage {
+    pub tree: ZeroMerkleTree<GoldilocksField, PoseidonHash, NodeStore>,
+    initial_balances: Vec<Weight>,
+    initial_root: WHashOut<GoldilocksField>,
+    /// Width balances are range checked to when proving, see [`MAX_BALANCE_BITS`].
+    balance_bits: usize,
+    /// How votes are weighted in the tallies, recorded on every update.
+    voting_policy: VotingPolicy,
+    /// Epoch the tree is voted at and the weight it locks, see [`super::vesting`].
+    vesting: Option<VestingRules>,
+    /// Least weight a vote or delegation moves, recorded on every update, see
+    /// [`crate::circuits::min_transfer`].
+    min_transfer: Option<Weight>,
+    /// Leaves written since the tree was seeded, which [`Self::restore`] resets.
+    touched: BTreeSet<u64>,
+    /// Whether the tree was cut down to its root and tallies, see [`Self::compact`].
+    compacted: bool,
+}
+
+impl BalanceStorage {
+    /// Seeds an in-memory tree with balances of [`DEFAULT_BALANCE_BITS`] bits,
+    /// panicking if the electorate is too heavy for them.
+    pub fn new(height: u8, voter_balances: Vec<Weight>) -> Self {
+        Self::with_store(
+            height,
+            voter_balances,
+            DEFAULT_BALANCE_BITS,
+            NodeStore::Memory(SimpleNodeStore::new()),
+        )
+        .unwrap()
+    }
+    /// Seeds the tree with the electorate, whose total weight has to fit in
+    /// `balance_bits` bits since the tallies may end up holding all of it.
+    pub fn with_store(
+        height: u8,
+        voter_balances: Vec<Weight>,
+        balance_bits: usize,
+        store: NodeStore,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            balance_bits <= MAX_BALANCE_BITS,
+            "balances can be at most {} bits wide",
+            MAX_BALANCE_BITS
+        );
+        let total_weight = voter_balances
+            .iter()
+            .try_fold(Weight::ZERO, |total, balance| {
+                total.checked_add((*balance).into())
+            })
+            .filter(|total| total.fits(balance_bits));
+        ensure!(
+            total_weight.is_some(),
+            "the total weight of the electorate does not fit in {} bits",
+            balance_bits
+        );
+        ensure!(
+            voter_balances.len() as u64 <= LeafAllocator::new(height, 0).capacity(),
+            "{} voters do not fit in a balance tree of height {}",
+            voter_balances.len(),
+            height
+        );
+        let mut tree =
+            ZeroMerkleTree::<GoldilocksField, PoseidonHash, NodeStore>::new(height, store);
+
+        for slot in [TallySlot::NO, TallySlot::YES] {
+            tree.set_leaf(slot.index(), WHashOut::from_values(0, 0, 0, 0))
+                .unwrap();
+        }
+        for (i, balance) in voter_balances.iter().enumerate() {
+            let leaf = VoterLeaf::from_position(i as u64);
+            tree.set_leaf(leaf.index(), WHashOut::from_values(balance.get(), 0, 0, 0))
+                .unwrap();
+        }
+        let initial_root = tree.get_root().unwrap();
+        Ok(Self {
+            tree,
+            initial_balances: voter_balances,
+            initial_root,
+            balance_bits,
+            voting_policy: VotingPolicy::Linear,
+            vesting: None,
+            min_transfer: None,
+            touched: BTreeSet::new(),
+            compacted: false,
+        })
+    }
+    pub fn balance_bits(&self) -> usize {
+        self.balance_bits
+    }
+    pub fn set_voting_policy(&mut self, voting_policy: VotingPolicy) {
+        self.voting_policy = voting_policy;
+    }
+    pub fn set_min_transfer(&mut self, min_transfer: Option<Weight>) {
+        self.min_transfer = min_transfer;
+    }
+    pub fn min_transfer(&self) -> Option<Weight> {
+        self.min_transfer
+    }
+    /// Locks the weight of the voters `vesting` has schedules for, if given,
+    /// which votes and delegations can only spend once unlocked at the epoch of
+    /// `vesting`. Fails unless the tree is as it was seeded.
+    pub fn with_vesting(mut self, vesting: Option<VestingRules>) -> anyhow::Result<Self> {
+        let vesting = match vesting {
+            Some(vesting) => vesting,
+            None => return Ok(self),
+        };
+        ensure!(
+            self.touched.is_empty() && !self.compacted,
+            "vesting schedules are set before any update"
+        );
+        vesting.validate(&self.initial_balances)?;
+        for schedule in &vesting.schedules {
+            let voter = VoterLeaf::from_voter_id(schedule.voter_id)?;
+            let value = LeafValue {
+                balance: self.initial_balance(voter),
+                delegated: false,
+                locked: schedule.locked,
+                unlock_epoch: schedule.unlock_epoch,
+            };
+            self.tree.set_leaf(voter.index(), value.pack())?;
+        }
+        self.initial_root = self.tree.get_root()?;
+        self.vesting = Some(vesting);
+        Ok(self)
+    }
+    pub fn vesting(&self) -> Option<&VestingRules> {
+        self.vesting.as_ref()
+    }
+    pub fn tree_height(&self) -> usize {
+        self.tree.get_height() as usize
+    }
+    /// Root of the tree as seeded with the electorate, before any vote.
+    pub fn initial_root(&self) -> WHashOut<GoldilocksField> {
+        self.initial_root
+    }
+    /// Weights the electorate was seeded with, by voter position.
+    pub fn initial_balances(&self) -> &[Weight] {
+        &self.initial_balances
+    }
+    /// Proves the weight `voter` was registered with against [`Self::initial_root`].
+    pub fn initial_membership_proof(
+        &self,
+        voter: VoterLeaf,
+    ) -> anyhow::Result<MerkleProof<GoldilocksField>> {
+        Ok(self.initial_leaf_proofs(&[voter.index()])?.remove(0))
+    }
+    /// Proves the leaves at `indices` as they were seeded, against [`Self::initial_root`].
+    pub fn initial_leaf_proofs(
+        &self,
+        indices: &[u64],
+    ) -> anyhow::Result<Vec<MerkleProof<GoldilocksField>>> {
+        if self.touched.is_empty() && !self.compacted {
+            return indices
+                .iter()
+                .map(|index| self.tree.get_leaf(*index))
+                .collect();
+        }
+        // Votes have changed the tree since, so the proofs come from a rebuilt copy of the seeded tree
+        let initial = self.seeded_copy()?;
+        indices
+            .iter()
+            .map(|index| initial.tree.get_leaf(*index))
+            .collect()
+    }
+    /// A copy in memory of the tree as it was after `updates`, the first of the
+    /// updates applied to this one, for proving leaves at a past root. Works on a
+    /// compacted tree too, since the copy is seeded and replayed from scratch.
+    pub fn replayed(&self, updates: &[BalanceUpdate<GoldilocksField>]) -> anyhow::Result<Self> {
+        let mut replayed = self.seeded_copy()?;
+        replayed.restore(updates)?;
+        Ok(replayed)
+    }
+    /// A copy in memory of the tree as it was seeded.
+    fn seeded_copy(&self) -> anyhow::Result<Self> {
+        let mut seeded = Self::with_store(
+            self.tree.get_height(),
+            self.initial_balances.clone(),
+            self.balance_bits,
+            NodeStore::Memory(SimpleNodeStore::new()),
+        )?;
+        seeded.voting_policy = self.voting_policy;
+        seeded.min_transfer = self.min_transfer;
+        seeded.with_vesting(self.vesting.clone())
+    }
+    /// Weight `voter` was seeded with.
+    pub fn initial_balance(&self, voter: VoterLeaf) -> Weight {
+        self.initial_leaf_balance(voter.index())
+    }
+    /// Leaf indices of the voters the tree was seeded with, the only voter
+    /// leaves it writes to.
+    pub fn electorate_bounds(&self) -> Range<u64> {
+        LeafAllocator::new(self.tree.get_height(), self.initial_balances.len() as u64).bounds()
+    }
+    /// Whether `voter` is one of the voters the tree was seeded with.
+    pub fn is_registered(&self, voter: VoterLeaf) -> bool {
+        self.electorate_bounds().contains(&voter.index())
+    }
+    /// Fails unless leaf `index` is a tally slot or the leaf of a voter the tree
+    /// was seeded with.
+    fn check_leaf(&self, index: u64) -> Result<(), QedError> {
+        if index <= TallySlot::YES.index() || self.electorate_bounds().contains(&index) {
+            return Ok(());
+        }
+        Err(QedError::LeafOutOfRange {
+            leaf: index,
+            voters: self.initial_balances.len() as u64,
+        })
+    }
+    fn initial_leaf_balance(&self, index: u64) -> Weight {
+        if index <= TallySlot::YES.index() {
+            return Weight::ZERO;
+        }
+        let position = index - VoterLeaf::from_position(0).index();
+        self.initial_balances
+            .get(position as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+    /// What leaf `index` was seeded with, its vesting schedule included.
+    fn initial_leaf_value(&self, index: u64) -> WHashOut<GoldilocksField> {
+        let schedule = self.vesting.as_ref().and_then(|vesting| {
+            vesting.schedules.iter().find(|schedule| {
+                VoterLeaf::from_voter_id(schedule.voter_id)
+                    .map_or(false, |voter| voter.index() == index)
+            })
+        });
+        LeafValue {
+            balance: self.initial_leaf_balance(index),
+            delegated: false,
+            locked: schedule.map_or(Weight::ZERO, |schedule| schedule.locked),
+            unlock_epoch: schedule.map_or(0, |schedule| schedule.unlock_epoch),
+        }
+        .pack()
+    }
+    /// Rolls the tree back to the state after `updates`, undoing any write that
+    /// was not recorded as an update, e.g. because a handler panicked halfway.
+    pub fn restore(&mut self, updates: &[BalanceUpdate<GoldilocksField>]) -> anyhow::Result<()> {
+        let expected_root = updates
+            .last()
+            .map_or(self.initial_root, |update| update.new_root());
+        // A write that panicked never got to end the undo log it started
+        self.tree.commit();
+        // The root is written last, so a write failing halfway through a leaf can leave
+        // the nodes below it changed while it still matches: the leaves are always rewritten
+        for index in self.touched.clone() {
+            let value = self.initial_leaf_value(index);
+            self.tree.set_leaf(index, value)?;
+        }
+        for update in updates {
+            for proof in [&update.sender_update, &update.receiver_update] {
+                let index = proof.index.to_canonical_u64();
+                self.touched.insert(index);
+                self.tree.set_leaf(index, proof.new_value)?;
+            }
+        }
+        if self.tree.get_root()? != expected_root {
+            return Err(QedError::TreeCorruption(format!(
+                "replaying {} updates does not reproduce the recorded root",
+                updates.len()
+            ))
+            .into());
+        }
+        Ok(())
+    }
+    /// Drops every node of the tree but the root and the tally leaves, along with
+    /// the nodes proving them, for a proposal that takes no more updates. Leaves of
+    /// voters can no longer be read afterwards. Returns the number of nodes dropped.
+    pub fn compact(&mut self) -> anyhow::Result<usize> {
+        let keep = self
+            .tree
+            .proof_nodes(&[TallySlot::NO.index(), TallySlot::YES.index()]);
+        // Set first, since a store failing halfway leaves some of the nodes dropped
+        self.compacted = true;
+        self.tree
+            .store_mut()
+            .retain(|level, index| keep.contains(&(level, index)))
+    }
+    pub fn is_compacted(&self) -> bool {
+        self.compacted
+    }
+    fn get_leaf_balance(&self, index: u64) -> anyhow::Result<Weight> {
+        ensure!(
+            !self.compacted || index <= TallySlot::YES.index(),
+            "leaf {} was dropped when the tree was compacted",
+            index
+        );
+        let leaf = self.tree.get_leaf_value(index)?;
+        Weight::try_from(leaf.0.elements[0])
+    }
+    pub fn get_balance(&self, voter: VoterLeaf) -> anyhow::Result<Weight> {
+        self.get_leaf_balance(voter.index())
+    }
+    pub fn get_tally(&self, slot: TallySlot) -> anyhow::Result<Weight> {
+        self.get_leaf_balance(slot.index())
+    }
+    pub fn get_tally_proof(&self, slot: TallySlot) -> anyhow::Result<MerkleProof<GoldilocksField>> {
+        self.tree.get_leaf(slot.index())
+    }
+    pub fn tally(&self) -> anyhow::Result<Tally> {
+        Ok(Tally {
+            yes_votes: self.get_tally(TallySlot::YES)?,
+            no_votes: self.get_tally(TallySlot::NO)?,
+        })
+    }
+    pub fn has_delegated(&self, voter: VoterLeaf) -> anyhow::Result<bool> {
+        let leaf = self.tree.get_leaf_value(voter.index())?;
+        Ok(leaf.0.elements[DELEGATION_FLAG_ELEMENT] != GoldilocksField::ZERO)
+    }
+    pub fn process_tx(&mut self, tx: BalanceTx) -> anyhow::Result<BalanceUpdate<GoldilocksField>> {
+        self.process_stamped_tx(tx, None, None)
+    }
+    /// Runs `write`, undoing every node it wrote should it fail, so that the tree
+    /// is left at the root it had before. Writes nested in another call are undone
+    /// along with those of the outermost one.
+    fn atomically<T>(
+        &mut self,
+        write: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        if self.tree.in_transaction() {
+            return write(self);
+        }
+        self.tree.begin();
+        let result = write(self);
+        if result.is_ok() {
+            self.tree.commit();
+            return result;
+        }
+        // Undoing a fault must not fault itself
+        #[cfg(feature = "chaos")]
+        let rolled_back = crate::utils::chaos::calm(|| self.tree.rollback());
+        #[cfg(not(feature = "chaos"))]
+        let rolled_back = self.tree.rollback();
+        if let Err(err) = rolled_back {
+            return Err(QedError::TreeCorruption(format!(
+                "{}, and rolling the tree back failed, leaving it inconsistent: {}",
+                result.err().unwrap(),
+                err
+            ))
+            .into());
+        }
+        result
+    }
+    /// Starts a batch of transactions that [`Self::rollback_batch`] undoes
+    /// together. Transactions failing within it are left for the batch to undo.
+    pub fn begin_batch(&mut self) {
+        self.tree.begin();
+    }
+    /// Keeps the transactions of the batch.
+    pub fn commit_batch(&mut self) {
+        self.tree.commit();
+    }
+    /// Undoes every transaction of the batch, leaving the tree at the root it had
+    /// when the batch began.
+    pub fn rollback_batch(&mut self) -> anyhow::Result<()> {
+        self.tree.rollback().map_err(|err| {
+            QedError::TreeCorruption(format!(
+                "rolling back a batch failed, leaving the tree inconsistent: {}",
+                err
+            ))
+            .into()
+        })
+    }
+    /// The weight a vote spending `amount` adds to its tally, recorded with `conviction`.
+    fn vote_weight(
+        &self,
+        amount: WeightDelta,
+        conviction: Option<ConvictionStamp>,
+    ) -> Option<WeightDelta> {
+        let multiplier = conviction.map_or(1, |stamp| stamp.multiplier());
+        self.voting_policy
+            .vote_weight(amount)
+            .checked_mul(multiplier)
+    }
+    /// Applies `tx` recorded with `conviction`, under which a vote adds the
+    /// weight the voting policy gives its amount, times the conviction
+    /// multiplier, to the tally. A vote or revocation recorded within `window`
+    /// sets the clock of its tally slot to the time it was recorded, failing if
+    /// the slot took a later update, see [`TALLY_CLOCK_ELEMENT`].
+    pub fn process_stamped_tx(
+        &mut self,
+        tx: BalanceTx,
+        conviction: Option<ConvictionStamp>,
+        window: Option<WindowStamp>,
+    ) -> anyhow::Result<BalanceUpdate<GoldilocksField>> {
+        let sender = tx.sender_index();
+        let receiver = tx.receiver_index();
+        let amount = tx.amount();
+        self.check_leaf(sender)?;
+        self.check_leaf(receiver)?;
+        let clocked_slot = match tx {
+            BalanceTx::Vote { .. } => Some(receiver),
+            BalanceTx::Revoke { .. } => Some(sender),
+            _ => None,
+        };
+        if let (Some(conviction), Some(window)) = (conviction, window) {
+            ensure!(
+                conviction.voted_at == window.voted_at,
+                "the update is stamped at {} for conviction and at {} for the voting window",
+                conviction.voted_at,
+                window.voted_at
+            );
+        }
+        let voted_at = window
+            .map(|stamp| stamp.voted_at)
+            .or(conviction.map(|stamp| stamp.voted_at));
+        let clock = match (clocked_slot, voted_at) {
+            (Some(slot), Some(voted_at)) => {
+                let clock = self.tree.get_leaf_value(slot)?.0.elements[TALLY_CLOCK_ELEMENT];
+                ensure!(
+                    clock.to_canonical_u64() <= voted_at,
+                    "tally {} took an update at {}, after {}",
+                    slot,
+                    clock,
+                    voted_at
+                );
+                Some(GoldilocksField::from_canonical_u64(voted_at))
+            }
+            _ => None,
+        };
+        if matches!(tx, BalanceTx::Vote { .. } | BalanceTx::Delegate { .. }) {
+            self.check_min_transfer(sender, amount)?;
+        }
+        let received = match tx {
+            BalanceTx::Vote { .. } => self.vote_weight(amount, conviction).ok_or_else(|| {
+                anyhow!("the weight of {} votes does not fit in a balance", amount)
+            })?,
+            _ => amount,
+        };
+        let mut sender_leaf = self.tree.get_leaf_value(sender)?;
+        let sender_balance = Weight::try_from(sender_leaf.0.elements[0])?;
+        trace!(sender, %sender_balance, "Processing balance transaction");
+        // Weight still locked by a vesting schedule cannot be spent
+        let spendable = match &self.vesting {
+            Some(vesting) => LeafValue::unpack(&sender_leaf)?.spendable(vesting.epoch),
+            None => sender_balance,
+        };
+        let insufficient = QedError::InsufficientWeight {
+            leaf: sender,
+            balance: spendable,
+            amount,
+        };
+        let sender_new_balance = spendable
+            .checked_sub(amount)
+            .and_then(|_| sender_balance.checked_sub(amount))
+            .ok_or(insufficient)?;
+        // Checked before writing anything, a voter delegating to themselves is credited what they were debited
+        let receiver_balance = if receiver == sender {
+            sender_new_balance
+        } else {
+            self.get_leaf_balance(receiver)?
+        };
+        let receiver_new_balance = receiver_balance
+            .checked_add(received)
+            .filter(|balance| balance.fits(self.balance_bits))
+            .ok_or_else(|| {
+                anyhow!(
+                    "balance of leaf {} would exceed {} bits",
+                    receiver,
+                    self.balance_bits
+                )
+            })?;
+        let kind = match tx {
+            BalanceTx::Vote { .. } => UpdateKind::Vote,
+            BalanceTx::Delegate { voter, .. } => {
+                ensure!(
+                    !self.has_delegated(voter)?,
+                    "voter {} has already delegated",
+                    sender
+                );
+                sender_leaf.0.elements[DELEGATION_FLAG_ELEMENT] = GoldilocksField::ONE;
+                UpdateKind::Delegation
+            }
+            BalanceTx::Revoke { .. } => UpdateKind::Revocation,
+            BalanceTx::Undelegate { voter, .. } => {
+                ensure!(
+                    sender != receiver,
+                    "voter {} cannot undelegate from themselves",
+                    receiver
+                );
+                ensure!(
+                    self.has_delegated(voter)?,
+                    "voter {} has not delegated",
+                    receiver
+                );
+                UpdateKind::Undelegation
+            }
+        };
+        sender_leaf.0.elements[0] = sender_new_balance.to_element();
+        if let (UpdateKind::Revocation, Some(clock)) = (kind, clock) {
+            sender_leaf.0.elements[TALLY_CLOCK_ELEMENT] = clock;
+        }
+        let (sender_proof, receiver_proof) = self.atomically(|storage| {
+            storage.touched.insert(sender);
+            let sender_proof: DeltaMerkleProof<GoldilocksField> =
+                storage.tree.set_leaf(sender, sender_leaf)?;
+            // Read after the sender is debited, in case a voter delegates to themselves
+            let mut receiver_leaf = storage.tree.get_leaf_value(receiver)?;
+            receiver_leaf.0.elements[0] = receiver_new_balance.to_element();
+            if kind == UpdateKind::Undelegation {
+                receiver_leaf.0.elements[DELEGATION_FLAG_ELEMENT] = GoldilocksField::ZERO;
+            }
+            if let (UpdateKind::Vote, Some(clock)) = (kind, clock) {
+                receiver_leaf.0.elements[TALLY_CLOCK_ELEMENT] = clock;
+            }
+            storage.touched.insert(receiver);
+            let receiver_proof = storage.tree.set_leaf(receiver, receiver_leaf)?;
+            Ok((sender_proof, receiver_proof))
+        })?;
+        trace!(
+            sender,
+            receiver,
+            %sender_new_balance,
+            %receiver_new_balance,
+            "Processed balance transaction"
+        );
+        Ok(BalanceUpdate {
+            sender_update: sender_proof,
+            receiver_update: receiver_proof,
+            kind,
+            conviction,
+            policy: self.voting_policy,
+            vesting_epoch: self.vesting.as_ref().map(|vesting| vesting.epoch),
+            window,
+            min_transfer: self.min_transfer,
+        })
+    }
+    /// Fails if a vote or delegation out of leaf `sender` moving `amount` moves
+    /// less than the minimum transfer.
+    fn check_min_transfer(&self, sender: u64, amount: WeightDelta) -> Result<(), QedError> {
+        match self.min_transfer {
+            Some(minimum) if amount.get() < minimum.get() => Err(QedError::BelowMinTransfer {
+                leaf: sender,
+                amount,
+                minimum,
+            }),
+            _ => Ok(()),
+        }
+    }
+    /// Casts the full balance of `voter` across both options as `split` says, one
+    /// vote per option receiving weight. All votes but the last are marked as
+    /// [`UpdateKind::SplitVote`], so the circuit checks the parts add up to the balance.
+    ///
+    /// Everything is checked before the first vote is applied, so either all parts
+    /// are cast or the tree is left unchanged. Every part is recorded with
+    /// `conviction` and `window`.
+    pub fn process_split_vote(
+        &mut self,
+        voter: VoterLeaf,
+        split: VoteSplit,
+        conviction: Option<ConvictionStamp>,
+        window: Option<WindowStamp>,
+    ) -> anyhow::Result<Vec<BalanceUpdate<GoldilocksField>>> {
+        let balance = self.get_balance(voter)?;
+        ensure!(
+            split.total() == Some(balance),
+            "split of {} yes and {} no votes does not add up to the balance {} of leaf {}",
+            split.yes_votes,
+            split.no_votes,
+            balance,
+            voter.index()
+        );
+        let parts = split.parts();
+        for (slot, amount) in &parts {
+            self.check_min_transfer(voter.index(), *amount)?;
+            let tally = self.get_tally(*slot)?;
+            ensure!(
+                self.vote_weight(*amount, conviction)
+                    .and_then(|weight| tally.checked_add(weight))
+                    .map_or(false, |tally| tally.fits(self.balance_bits)),
+                "tally {} would exceed {} bits",
+                slot.index(),
+                self.balance_bits
+            );
+        }
+        self.atomically(|storage| {
+            let mut updates = vec![];
+            for (slot, amount) in parts {
+                let mut update = storage.process_stamped_tx(
+                    BalanceTx::Vote {
+                        voter,
+                        slot,
+                        amount,
+                    },
+                    conviction,
+                    window,
+                )?;
+                if storage.get_balance(voter)? != Weight::ZERO {
+                    update.kind = UpdateKind::SplitVote;
+                }
+                updates.push(update);
+            }
+            Ok(updates)
+        })
+    }
+    /// Moves the weight `voter` cast on each option, as given by `cast`, back from
+    /// the tallies to their leaf, one revocation per option holding weight of theirs.
+    ///
+    /// Everything is checked before the first revocation is applied, so either all
+    /// of the vote is revoked or the tree is left unchanged. Every revocation is
+    /// recorded with `window`.
+    pub fn process_revocation(
+        &mut self,
+        voter: VoterLeaf,
+        cast: VoteSplit,
+        window: Option<WindowStamp>,
+    ) -> anyhow::Result<Vec<BalanceUpdate<GoldilocksField>>> {
+        let parts = cast.parts();
+        for (slot, amount) in &parts {
+            ensure!(
+                self.get_tally(*slot)?.checked_sub(*amount).is_some(),
+                "tally {} holds less than the {} votes to revoke",
+                slot.index(),
+                amount
+            );
+        }
+        let balance = self.get_balance(voter)?;
+        ensure!(
+            cast.total()
+                .and_then(|total| balance.checked_add(total.into()))
+                .map_or(false, |balance| balance.fits(self.balance_bits)),
+            "balance of leaf {} would exceed {} bits",
+            voter.index(),
+            self.balance_bits
+        );
+        self.atomically(|storage| {
+            let mut updates = vec![];
+            for (slot, amount) in parts {
+                updates.push(storage.process_stamped_tx(
+                    BalanceTx::Revoke {
+                        voter,
+                        slot,
+                        amount,
+                    },
+                    None,
+                    window,
+                )?);
+            }
+            Ok(updates)
+        })
+    }
+    pub fn process_txs(
+        &mut self,
+        txs: Vec<BalanceTx>,
+    ) -> anyhow::Result<Vec<BalanceUpdate<GoldilocksField>>> {
+        let mut proofs = vec![];
+        for tx in txs {
+            proofs.push(self.process_tx(tx)?);
+        }
+        Ok(proofs)
+    }
+}
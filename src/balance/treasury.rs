@@ -0,0 +1,287 @@
+//! Deposits proposers lock to create proposals, kept in a treasury tree apart
+//! from the balance trees of proposals.
+//!
+//! Leaves below [`ESCROW_OFFSET`] are the accounts of proposers, by proposer id.
+//! Each proposal with a deposit gets an escrow leaf from [`ESCROW_OFFSET`] on, and
+//! [`SLASHED_DEPOSITS_LEAF`] collects the deposits of proposals cancelled for spam.
+//! Deposits move as transfers between two leaves, recorded as [`BalanceUpdate`]s
+//! so that each can be proven with a
+//! [`DepositTransferCircuit`](crate::circuits::deposit::DepositTransferCircuit).
+
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    circuits::{
+        quadratic::VotingPolicy,
+        update_balance::{BalanceUpdate, UpdateKind},
+    },
+    common::{hash::merkle::helpers::merkle_proof::DeltaMerkleProof, WHashOut},
+    proof::codec::ProofEnvelope,
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+use super::accounts::MAX_BALANCE_BITS;
+
+pub const TREASURY_TREE_HEIGHT: u8 = 33;
+/// Index of the first escrow leaf, right after the accounts of all proposer ids.
+pub const ESCROW_OFFSET: u64 = 1 << 32;
+pub const SLASHED_DEPOSITS_LEAF: u64 = (1 << TREASURY_TREE_HEIGHT) - 1;
+/// Width balances of the treasury are range checked to in deposit proofs.
+pub const TREASURY_BALANCE_BITS: usize = MAX_BALANCE_BITS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DepositStatus {
+    Locked,
+    /// Returned to the proposer once the proposal was finalized, or cancelled by them.
+    Refunded,
+    /// Kept by the treasury after the proposal was cancelled for spam.
+    Slashed,
+}
+
+/// The deposit locked for a proposal, and where it went.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProposalDeposit {
+    pub amount: u64,
+    pub escrow_index: u64,
+    pub status: DepositStatus,
+    /// Transfer from the account of the proposer to the escrow leaf.
+    pub lock: BalanceUpdate<GoldilocksField>,
+    /// Transfer out of the escrow leaf, back to the proposer or to the slashed deposits.
+    pub settlement: Option<BalanceUpdate<GoldilocksField>>,
+    /// Proof of `settlement`, made in the background once the deposit is settled.
+    pub settlement_proof: Option<ProofEnvelope>,
+}
+
+/// The non-zero leaves of a treasury, which rebuild it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreasurySnapshot {
+    pub balances: BTreeMap<u64, u64>,
+    pub next_escrow: u64,
+    pub root: WHashOut<GoldilocksField>,
+}
+
+pub struct Treasury {
+    tree: ZeroMerkleTree<GoldilocksField, PoseidonHash, NodeStore>,
+    /// Non-zero leaves, so the treasury is archived without walking the tree.
+    balances: BTreeMap<u64, u64>,
+    next_escrow: u64,
+}
+
+impl Treasury {
+    pub fn new() -> Self {
+        Self {
+            tree: ZeroMerkleTree::new(
+                TREASURY_TREE_HEIGHT,
+                NodeStore::Memory(SimpleNodeStore::new()),
+            ),
+            balances: BTreeMap::new(),
+            next_escrow: ESCROW_OFFSET,
+        }
+    }
+    /// Rebuilds the treasury a snapshot was taken of, failing unless it has the same root.
+    pub fn restore(snapshot: TreasurySnapshot) -> anyhow::Result<Self> {
+        let mut treasury = Self::new();
+        for (index, balance) in snapshot.balances {
+            treasury.set_balance(index, balance)?;
+        }
+        ensure!(
+            treasury.root()? == snapshot.root,
+            "the balances of the treasury do not reproduce its root"
+        );
+        ensure!(
+            snapshot.next_escrow >= ESCROW_OFFSET && snapshot.next_escrow < SLASHED_DEPOSITS_LEAF,
+            "escrow leaf {} is out of range",
+            snapshot.next_escrow
+        );
+        treasury.next_escrow = snapshot.next_escrow;
+        Ok(treasury)
+    }
+    pub fn snapshot(&self) -> anyhow::Result<TreasurySnapshot> {
+        Ok(TreasurySnapshot {
+            balances: self.balances.clone(),
+            next_escrow: self.next_escrow,
+            root: self.root()?,
+        })
+    }
+    /// Whether no account was ever credited and no deposit locked.
+    pub fn is_empty(&self) -> bool {
+        self.balances.is_empty() && self.next_escrow == ESCROW_OFFSET
+    }
+    pub fn root(&self) -> anyhow::Result<WHashOut<GoldilocksField>> {
+        self.tree.get_root()
+    }
+    pub fn balance(&self, index: u64) -> u64 {
+        self.balances.get(&index).copied().unwrap_or(0)
+    }
+    pub fn account_balance(&self, proposer_id: u32) -> u64 {
+        self.balance(proposer_id as u64)
+    }
+    fn set_balance(
+        &mut self,
+        index: u64,
+        balance: u64,
+    ) -> anyhow::Result<DeltaMerkleProof<GoldilocksField>> {
+        ensure!(
+            balance < 1 << TREASURY_BALANCE_BITS,
+            "treasury balances are at most {} bits wide",
+            TREASURY_BALANCE_BITS
+        );
+        let proof = self
+            .tree
+            .set_leaf(index, WHashOut::from_values(balance, 0, 0, 0))?;
+        if balance == 0 {
+            self.balances.remove(&index);
+        } else {
+            self.balances.insert(index, balance);
+        }
+        Ok(proof)
+    }
+    /// Adds `amount` to the account of `proposer_id`, e.g. once the operator has
+    /// been paid for it, returning the new balance.
+    pub fn credit(&mut self, proposer_id: u32, amount: u64) -> anyhow::Result<u64> {
+        let balance = self
+            .account_balance(proposer_id)
+            .checked_add(amount)
+            .ok_or_else(|| anyhow::anyhow!("the balance of proposer {} overflows", proposer_id))?;
+        self.set_balance(proposer_id as u64, balance)?;
+        Ok(balance)
+    }
+    fn transfer(
+        &mut self,
+        sender: u64,
+        receiver: u64,
+        amount: u64,
+    ) -> anyhow::Result<BalanceUpdate<GoldilocksField>> {
+        let sender_balance = self.balance(sender);
+        ensure!(
+            sender_balance >= amount,
+            "leaf {} holds {}, not {}",
+            sender,
+            sender_balance,
+            amount
+        );
+        let receiver_balance = self
+            .balance(receiver)
+            .checked_add(amount)
+            .ok_or_else(|| anyhow::anyhow!("the balance of leaf {} overflows", receiver))?;
+        ensure!(
+            receiver_balance < 1 << TREASURY_BALANCE_BITS,
+            "treasury balances are at most {} bits wide",
+            TREASURY_BALANCE_BITS
+        );
+        let sender_update = self.set_balance(sender, sender_balance - amount)?;
+        let receiver_update = self.set_balance(receiver, receiver_balance)?;
+        Ok(BalanceUpdate {
+            sender_update,
+            receiver_update,
+            kind: UpdateKind::default(),
+            conviction: None,
+            policy: VotingPolicy::Linear,
+            vesting_epoch: None,
+            window: None,
+            min_transfer: None,
+        })
+    }
+    /// Moves `amount` from the account of `proposer_id` to a new escrow leaf.
+    pub fn lock(&mut self, proposer_id: u32, amount: u64) -> anyhow::Result<ProposalDeposit> {
+        ensure!(
+            self.next_escrow < SLASHED_DEPOSITS_LEAF,
+            "the treasury has run out of escrow leaves"
+        );
+        let escrow_index = self.next_escrow;
+        let lock = self.transfer(proposer_id as u64, escrow_index, amount)?;
+        self.next_escrow += 1;
+        Ok(ProposalDeposit {
+            amount,
+            escrow_index,
+            status: DepositStatus::Locked,
+            lock,
+            settlement: None,
+            settlement_proof: None,
+        })
+    }
+    fn settle(
+        &mut self,
+        deposit: &mut ProposalDeposit,
+        receiver: u64,
+        status: DepositStatus,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            deposit.status == DepositStatus::Locked,
+            "the deposit has already been {:?}",
+            deposit.status
+        );
+        deposit.settlement = Some(self.transfer(deposit.escrow_index, receiver, deposit.amount)?);
+        deposit.status = status;
+        Ok(())
+    }
+    /// Returns the deposit to the account of `proposer_id`.
+    pub fn refund(
+        &mut self,
+        proposer_id: u32,
+        deposit: &mut ProposalDeposit,
+    ) -> anyhow::Result<()> {
+        self.settle(deposit, proposer_id as u64, DepositStatus::Refunded)
+    }
+    /// Moves the deposit to the slashed deposits.
+    pub fn slash(&mut self, deposit: &mut ProposalDeposit) -> anyhow::Result<()> {
+        self.settle(deposit, SLASHED_DEPOSITS_LEAF, DepositStatus::Slashed)
+    }
+}
+
+impl Default for Treasury {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DepositStatus, Treasury, ESCROW_OFFSET, SLASHED_DEPOSITS_LEAF};
+
+    #[test]
+    fn test_locks_refunds_and_slashes_deposits() -> anyhow::Result<()> {
+        let mut treasury = Treasury::new();
+        assert!(treasury.lock(7, 100).is_err());
+        assert_eq!(treasury.credit(7, 250)?, 250);
+
+        let mut refunded = treasury.lock(7, 100)?;
+        let mut slashed = treasury.lock(7, 100)?;
+        assert_eq!(refunded.escrow_index, ESCROW_OFFSET);
+        assert_eq!(slashed.escrow_index, ESCROW_OFFSET + 1);
+        assert_eq!(treasury.account_balance(7), 50);
+        assert!(treasury.lock(7, 100).is_err());
+        // Locks chain from one root to the next
+        assert_eq!(refunded.lock.new_root(), slashed.lock.old_root());
+        assert_eq!(slashed.lock.check_weights(63)?.get(), 100);
+
+        treasury.refund(7, &mut refunded)?;
+        treasury.slash(&mut slashed)?;
+        assert_eq!(refunded.status, DepositStatus::Refunded);
+        assert_eq!(treasury.account_balance(7), 150);
+        assert_eq!(treasury.balance(SLASHED_DEPOSITS_LEAF), 100);
+        assert_eq!(treasury.balance(refunded.escrow_index), 0);
+        assert!(treasury.slash(&mut refunded).is_err());
+        assert_eq!(
+            slashed.settlement.as_ref().unwrap().new_root(),
+            treasury.root()?
+        );
+
+        let restored = Treasury::restore(treasury.snapshot()?)?;
+        assert_eq!(restored.root()?, treasury.root()?);
+        assert!(!restored.is_empty());
+        let mut tampered = treasury.snapshot()?;
+        tampered.balances.insert(7, 151);
+        assert!(Treasury::restore(tampered).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! Vesting-style voting power: part of the weight of a voter stays locked until
+//! an epoch, and cannot be cast before it.
+//!
+//! A voter leaf packs its balance and delegation flag with the weight locked by
+//! its schedule and the epoch that weight unlocks at, see [`LeafValue`]. Votes
+//! and delegations of a proposal with vesting may only spend what is unlocked
+//! at the epoch of the proposal, which [`crate::circuits::vesting`] enforces
+//! when proving. What an epoch is, e.g. a day or a block, is up to the proposer,
+//! as long as schedules and proposals count them the same way.
+
+use anyhow::ensure;
+use plonky2::field::{
+    goldilocks_field::GoldilocksField,
+    types::{Field, PrimeField64},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::common::WHashOut;
+
+use super::{
+    accounts::{VoterLeaf, DELEGATION_FLAG_ELEMENT, LOCKED_ELEMENT, UNLOCK_EPOCH_ELEMENT},
+    weight::Weight,
+};
+
+/// Bits epochs are range checked to when proving.
+pub const EPOCH_BITS: usize = 40;
+
+/// Weight of a voter locked until an epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VestingSchedule {
+    pub voter_id: u32,
+    /// Part of the balance of the voter that cannot be cast before `unlock_epoch`.
+    pub locked: Weight,
+    pub unlock_epoch: u64,
+}
+
+/// The epoch a proposal is voted at and the schedules of its voters.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VestingRules {
+    pub epoch: u64,
+    /// At most one schedule per voter; voters without one have nothing locked.
+    pub schedules: Vec<VestingSchedule>,
+}
+
+impl VestingRules {
+    /// Checks that epochs can be range checked and that every schedule locks at
+    /// most the balance of a distinct voter of `voter_balances`.
+    pub fn validate(&self, voter_balances: &[Weight]) -> anyhow::Result<()> {
+        ensure!(
+            self.epoch >> EPOCH_BITS == 0,
+            "epoch {} does not fit in {} bits",
+            self.epoch,
+            EPOCH_BITS
+        );
+        let mut seen = std::collections::BTreeSet::new();
+        for schedule in &self.schedules {
+            ensure!(
+                seen.insert(schedule.voter_id),
+                "voter {} has more than one vesting schedule",
+                schedule.voter_id
+            );
+            ensure!(
+                schedule.unlock_epoch >> EPOCH_BITS == 0,
+                "epoch {} does not fit in {} bits",
+                schedule.unlock_epoch,
+                EPOCH_BITS
+            );
+            let balance = VoterLeaf::from_voter_id(schedule.voter_id)
+                .ok()
+                .and_then(|voter| voter_balances.get(voter.position() as usize));
+            match balance {
+                Some(balance) => ensure!(
+                    schedule.locked <= *balance,
+                    "voter {} cannot have {} of their {} votes locked",
+                    schedule.voter_id,
+                    schedule.locked,
+                    balance
+                ),
+                None => anyhow::bail!(
+                    "voter {} of a vesting schedule is not part of the electorate",
+                    schedule.voter_id
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a leaf of the balance tree holds, one field element each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LeafValue {
+    pub balance: Weight,
+    pub delegated: bool,
+    pub locked: Weight,
+    pub unlock_epoch: u64,
+}
+
+impl LeafValue {
+    pub fn pack(&self) -> WHashOut<GoldilocksField> {
+        WHashOut::from_values(
+            self.balance.get(),
+            self.delegated as u64,
+            self.locked.get(),
+            self.unlock_epoch,
+        )
+    }
+    pub fn unpack(value: &WHashOut<GoldilocksField>) -> anyhow::Result<Self> {
+        let elements = value.0.elements;
+        Ok(Self {
+            balance: Weight::try_from(elements[0])?,
+            delegated: elements[DELEGATION_FLAG_ELEMENT] != GoldilocksField::ZERO,
+            locked: Weight::try_from(elements[LOCKED_ELEMENT])?,
+            unlock_epoch: elements[UNLOCK_EPOCH_ELEMENT].to_canonical_u64(),
+        })
+    }
+    /// Whether the locked weight is still locked at `epoch`.
+    pub fn is_locked(&self, epoch: u64) -> bool {
+        epoch < self.unlock_epoch
+    }
+    /// The part of the balance that can be spent at `epoch`.
+    pub fn spendable(&self, epoch: u64) -> Weight {
+        if !self.is_locked(epoch) {
+            return self.balance;
+        }
+        Weight::try_from(self.balance.get().saturating_sub(self.locked.get())).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        errors::{ApiErrorCode, QedError},
+    };
+
+    use super::{LeafValue, VestingRules, VestingSchedule};
+
+    #[test]
+    fn test_locked_weight_is_spent_once_unlocked() -> anyhow::Result<()> {
+        let value = LeafValue {
+            balance: Weight::from(10),
+            delegated: true,
+            locked: Weight::from(4),
+            unlock_epoch: 7,
+        };
+        assert_eq!(LeafValue::unpack(&value.pack())?, value);
+        assert_eq!(value.spendable(6), Weight::from(6));
+        assert_eq!(value.spendable(7), Weight::from(10));
+
+        let vesting = |epoch| VestingRules {
+            epoch,
+            schedules: vec![VestingSchedule {
+                voter_id: 2,
+                locked: Weight::from(4),
+                unlock_epoch: 7,
+            }],
+        };
+        let balances = vec![Weight::from(10), Weight::from(1)];
+        assert!(vesting(6).validate(&balances[1..]).is_err());
+        let mut storage =
+            BalanceStorage::new(8, balances.clone()).with_vesting(Some(vesting(6)))?;
+        let voter = VoterLeaf::from_position(0);
+        let vote = |amount| BalanceTx::Vote {
+            voter,
+            slot: TallySlot::YES,
+            amount: WeightDelta::from(amount),
+        };
+        let err = storage.process_tx(vote(7)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<QedError>().map(QedError::code),
+            Some(ApiErrorCode::InsufficientWeight)
+        );
+        let update = storage.process_tx(vote(6))?;
+        assert_eq!(update.vesting_epoch, Some(6));
+        // The schedule stays in the leaf, and the seeded tree is rebuilt with it
+        assert_eq!(
+            LeafValue::unpack(&update.sender_update.new_value)?.locked,
+            Weight::from(4)
+        );
+        assert_eq!(
+            storage.replayed(&[update])?.tree.get_root()?,
+            storage.tree.get_root()?
+        );
+
+        let mut storage = BalanceStorage::new(8, balances).with_vesting(Some(vesting(7)))?;
+        storage.process_tx(vote(10))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+
+use anyhow::ensure;
+use plonky2::field::{
+    goldilocks_field::GoldilocksField,
+    types::{Field, PrimeField64},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::accounts::MAX_BALANCE_BITS;
+
+/// Voting weight, as held by a leaf of the balance tree or accumulated in a tally.
+/// Never wider than [`MAX_BALANCE_BITS`], so it always fits a field element and
+/// can be range checked by the update circuit.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    ToSchema,
+)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct Weight(u64);
+
+/// Weight moved between two leaves by a vote or a delegation.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct WeightDelta(u64);
+
+fn ensure_fits(value: u64) -> anyhow::Result<()> {
+    ensure!(
+        value >> MAX_BALANCE_BITS == 0,
+        "{} does not fit in {} bits",
+        value,
+        MAX_BALANCE_BITS
+    );
+    Ok(())
+}
+
+impl Weight {
+    pub const ZERO: Self = Self(0);
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+    /// Adds `delta`, failing if the sum is wider than [`MAX_BALANCE_BITS`].
+    pub fn checked_add(self, delta: WeightDelta) -> Option<Self> {
+        self.0
+            .checked_add(delta.0)
+            .filter(|sum| ensure_fits(*sum).is_ok())
+            .map(Self)
+    }
+    pub fn checked_sub(self, delta: WeightDelta) -> Option<Self> {
+        self.0.checked_sub(delta.0).map(Self)
+    }
+    /// The weight `self` holds on top of `lower`, `None` if it holds less.
+    pub fn checked_diff(self, lower: Self) -> Option<WeightDelta> {
+        self.0.checked_sub(lower.0).map(WeightDelta)
+    }
+    /// Whether the weight can be range checked to `bits` bits.
+    pub fn fits(self, bits: usize) -> bool {
+        bits >= 64 || self.0 >> bits == 0
+    }
+    pub fn to_element(self) -> GoldilocksField {
+        GoldilocksField::from_canonical_u64(self.0)
+    }
+}
+
+impl WeightDelta {
+    pub const ZERO: Self = Self(0);
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+    /// Multiplies by `factor`, failing if the product is wider than [`MAX_BALANCE_BITS`].
+    pub fn checked_mul(self, factor: u64) -> Option<Self> {
+        self.0
+            .checked_mul(factor)
+            .filter(|product| ensure_fits(*product).is_ok())
+            .map(Self)
+    }
+}
+
+impl From<u32> for Weight {
+    fn from(weight: u32) -> Self {
+        Self(weight as u64)
+    }
+}
+
+impl TryFrom<u64> for Weight {
+    type Error = anyhow::Error;
+
+    fn try_from(weight: u64) -> anyhow::Result<Self> {
+        ensure_fits(weight)?;
+        Ok(Self(weight))
+    }
+}
+
+/// Reads the weight held by an element of a leaf, failing rather than truncating
+/// if the element was not written as a weight.
+impl TryFrom<GoldilocksField> for Weight {
+    type Error = anyhow::Error;
+
+    fn try_from(element: GoldilocksField) -> anyhow::Result<Self> {
+        Self::try_from(element.to_canonical_u64())
+    }
+}
+
+impl From<Weight> for u64 {
+    fn from(weight: Weight) -> Self {
+        weight.0
+    }
+}
+
+/// Moving a whole balance, as votes and delegations do.
+impl From<Weight> for WeightDelta {
+    fn from(weight: Weight) -> Self {
+        Self(weight.0)
+    }
+}
+
+impl From<u32> for WeightDelta {
+    fn from(delta: u32) -> Self {
+        Self(delta as u64)
+    }
+}
+
+impl TryFrom<u64> for WeightDelta {
+    type Error = anyhow::Error;
+
+    fn try_from(delta: u64) -> anyhow::Result<Self> {
+        ensure_fits(delta)?;
+        Ok(Self(delta))
+    }
+}
+
+impl From<WeightDelta> for u64 {
+    fn from(delta: WeightDelta) -> Self {
+        delta.0
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for WeightDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::field::{goldilocks_field::GoldilocksField, types::Field};
+
+    use super::{Weight, WeightDelta};
+    use crate::balance::accounts::MAX_BALANCE_BITS;
+
+    #[test]
+    fn test_weight_conversions_are_checked() -> anyhow::Result<()> {
+        let widest = Weight::try_from((1u64 << MAX_BALANCE_BITS) - 1)?;
+        assert!(Weight::try_from(1u64 << MAX_BALANCE_BITS).is_err());
+        assert!(Weight::try_from(GoldilocksField::NEG_ONE).is_err());
+        assert_eq!(
+            Weight::try_from(GoldilocksField::from_canonical_u64(7))?,
+            Weight::from(7)
+        );
+        assert!(serde_json::from_str::<Weight>(&u64::MAX.to_string()).is_err());
+        assert_eq!(serde_json::to_string(&Weight::from(7))?, "7");
+
+        assert_eq!(widest.checked_add(WeightDelta::ZERO), Some(widest));
+        assert_eq!(widest.checked_add(WeightDelta::from(1)), None);
+        assert_eq!(Weight::from(2).checked_sub(WeightDelta::from(3)), None);
+        assert_eq!(Weight::from(2).checked_diff(Weight::from(3)), None);
+        assert_eq!(
+            Weight::from(3).checked_diff(Weight::from(2)),
+            Some(WeightDelta::from(1))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use plonky2::plonk::config::{GenericConfig, PoseidonGoldilocksConfig};
+use plonky2_tree_hacks::{
+    chain::{client::ChainClient, settlement::prove_groth16},
+    circuits::{
+        evm_wrapper::{wrap_finalization, WrapperHasher},
+        update_balance::{parse_update_balance_circuit_id, UpdateBalanceCircuit},
+    },
+    proof::codec::ProofEnvelope,
+};
+use uuid::Uuid;
+use web3::types::Address;
+
+const D: usize = 2;
+type C = PoseidonGoldilocksConfig;
+type F = <C as GenericConfig<D>>::F;
+
+/// Wraps a downloaded finalization proof for verification on an EVM chain.
+#[derive(Parser, Debug)]
+#[command(name = "qed-evm")]
+struct Args {
+    /// Proof envelope, either as JSON or bincode.
+    proof: PathBuf,
+    /// Directory the wrapper circuit data and proof are written to.
+    #[arg(long, default_value = "evm-wrapper")]
+    out_dir: PathBuf,
+    /// Hash function the wrapper proof is built with, which the Groth16 prover has to verify.
+    #[arg(long, value_enum, default_value_t = WrapperHasher::Poseidon)]
+    hasher: WrapperHasher,
+    /// Groth16 prover run on the wrapper artifacts; without it only the artifacts are written.
+    #[arg(long)]
+    groth16_prover: Option<PathBuf>,
+    /// Proposal the proof finalizes, to print the calldata settling its result.
+    #[arg(long)]
+    proposal_id: Option<Uuid>,
+    /// Ethereum RPC endpoint to settle the result of the proposal through, instead of
+    /// only printing the calldata.
+    #[arg(long, requires_all = ["settlement_contract", "from", "proposal_id"])]
+    rpc_url: Option<String>,
+    /// Settlement contract the proof is verified and the result settled on.
+    #[arg(long)]
+    settlement_contract: Option<Address>,
+    /// Account the settlement is sent from, whose key the node holds.
+    #[arg(long)]
+    from: Option<Address>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let bytes = std::fs::read(&args.proof)?;
+    let envelope = match std::str::from_utf8(&bytes) {
+        Ok(json) => ProofEnvelope::from_json(json)?,
+        Err(_) => ProofEnvelope::from_bincode(&bytes)?,
+    };
+    let shape = parse_update_balance_circuit_id(&envelope.circuit_id)?;
+    let inner = UpdateBalanceCircuit::<F, C, D>::new(shape);
+    let inner_proof = envelope.to_proof(&inner.base_circuit_data)?;
+    inner.base_circuit_data.verify(inner_proof.clone())?;
+
+    wrap_finalization(
+        &inner.base_circuit_data,
+        &inner_proof,
+        args.hasher,
+        &args.out_dir,
+    )?;
+    println!(
+        "{} wrapper artifacts written to {}",
+        args.hasher.name(),
+        args.out_dir.display()
+    );
+
+    let prover = match &args.groth16_prover {
+        Some(prover) => prover,
+        None => return Ok(()),
+    };
+    let groth16_proof = prove_groth16(prover, &args.out_dir)?;
+    println!(
+        "verifyProof calldata: 0x{}",
+        hex::encode(groth16_proof.verify_calldata()?)
+    );
+    if let Some(proposal_id) = args.proposal_id {
+        println!(
+            "settleFinalization calldata: 0x{}",
+            hex::encode(groth16_proof.settle_calldata(&proposal_id)?)
+        );
+    }
+    let (rpc_url, contract, from, proposal_id) = match (
+        &args.rpc_url,
+        args.settlement_contract,
+        args.from,
+        args.proposal_id,
+    ) {
+        (Some(rpc_url), Some(contract), Some(from), Some(proposal_id)) => {
+            (rpc_url, contract, from, proposal_id)
+        }
+        _ => return Ok(()),
+    };
+    let client = ChainClient::new(rpc_url, from)?;
+    let verifier = client.verifier(contract);
+    tokio::runtime::Runtime::new()?.block_on(async {
+        anyhow::ensure!(
+            verifier.verify_proof(&groth16_proof).await?,
+            "the settlement contract rejects the proof"
+        );
+        let tx_hash = verifier
+            .settle_finalization(&proposal_id, &groth16_proof)
+            .await?;
+        println!("settleFinalization sent: {:?}", tx_hash);
+        Ok(())
+    })
+}
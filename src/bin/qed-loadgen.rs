@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use clap::Parser;
+use plonky2_tree_hacks::{
+    loadgen::{run, LoadOptions, VoteMix},
+    qed_client::QedClient,
+};
+
+/// Fires synthetic votes at a running server and reports latency percentiles
+/// and the depth of its prover queue, for capacity planning.
+#[derive(Parser, Debug)]
+#[command(name = "qed-loadgen")]
+struct Args {
+    /// Base URL of the server, e.g. http://127.0.0.1:8080.
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    server: String,
+    /// Proposals to create, each with its own synthetic voters.
+    #[arg(long, default_value_t = 1)]
+    proposals: usize,
+    /// Synthetic voters of each proposal, with one vote each.
+    #[arg(long, default_value_t = 100)]
+    voters: usize,
+    /// Requests in flight at once.
+    #[arg(long, default_value_t = 16)]
+    concurrency: usize,
+    /// Relative weights of the kinds of vote sent, out of yes, no, split and revoke.
+    #[arg(long, default_value = "yes=1,no=1")]
+    mix: VoteMix,
+    /// Finalizes the proposals once every vote is in, proving them.
+    #[arg(long)]
+    finalize: bool,
+    /// Proposer and finalizer of the proposals.
+    #[arg(long, default_value_t = 0)]
+    proposer_id: u32,
+    /// DAO the proposals are created in, a new one for every run if not set.
+    #[arg(long)]
+    dao_id: Option<String>,
+    /// Milliseconds between samples of the prover queue.
+    #[arg(long, default_value_t = 500)]
+    sample_ms: u64,
+    /// Seeds the kind of vote of each voter.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Secret of an API key, for servers enforcing roles.
+    #[arg(long)]
+    api_key: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mut client = QedClient::new(args.server);
+    if let Some(api_key) = args.api_key {
+        client = client.with_api_key(api_key);
+    }
+    let defaults = LoadOptions::default();
+    let options = LoadOptions {
+        proposals: args.proposals,
+        voters: args.voters,
+        concurrency: args.concurrency,
+        mix: args.mix,
+        finalize: args.finalize,
+        proposer_id: args.proposer_id,
+        dao_id: args.dao_id.unwrap_or(defaults.dao_id),
+        sample_interval: Duration::from_millis(args.sample_ms),
+        seed: args.seed,
+    };
+    let report = run(&client, &options).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use plonky2_tree_hacks::{
+    proposal::transcript::Transcript,
+    simulation::{simulate, PhaseSchedule, SimulationOptions, SimulationReport},
+};
+
+/// Replays an exported proposal transcript at accelerated virtual time.
+#[derive(Parser, Debug)]
+#[command(name = "qed-simulate")]
+struct Args {
+    /// Transcript as exported by GET /proposal/{id}/transcript.
+    transcript: PathBuf,
+    /// JSON phase schedule; the transcript's rules apply and the proposal is
+    /// finalized right after its last event when this is not set.
+    #[arg(long)]
+    schedule: Option<PathBuf>,
+    /// Virtual seconds per real second; zero replays without waiting.
+    #[arg(long, default_value_t = 0.0)]
+    speedup: f64,
+    /// Proves the finalization instead of only resolving its outcome.
+    #[arg(long)]
+    prove: bool,
+    /// Rejects double votes like a server with anchoring enabled.
+    #[arg(long)]
+    nullifiers: bool,
+    /// Report of an earlier run to compare against, failing on any difference.
+    #[arg(long)]
+    expect: Option<PathBuf>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let transcript: Transcript = serde_json::from_slice(&std::fs::read(&args.transcript)?)?;
+    let schedule: PhaseSchedule = match &args.schedule {
+        Some(path) => serde_json::from_slice(&std::fs::read(path)?)?,
+        None => PhaseSchedule::default(),
+    };
+    let options = SimulationOptions {
+        speedup: args.speedup,
+        prove: args.prove,
+        nullifiers: args.nullifiers,
+    };
+    let report = simulate(&transcript, &schedule, &options).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if let Some(path) = &args.expect {
+        let expected: SimulationReport = serde_json::from_slice(&std::fs::read(path)?)?;
+        anyhow::ensure!(
+            report == expected,
+            "replay differs from the expected report in {}",
+            path.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2_tree_hacks::{
+    common::WHashOut,
+    proof::{codec::ProofEnvelope, verify::verify_finalization},
+    proposal::{action::ProposalAction, content::StatementContent, dependency::DependencyResult},
+};
+use qed_verifier::ProvenRules;
+
+/// Verifies a downloaded finalization proof offline.
+#[derive(Parser, Debug)]
+#[command(name = "qed-verify")]
+struct Args {
+    /// Proof envelope, either as JSON or bincode.
+    proof: PathBuf,
+    /// Root of the balance tree before the first vote.
+    #[arg(long)]
+    initial_root: WHashOut<GoldilocksField>,
+    /// Root of the balance tree after the last vote.
+    #[arg(long)]
+    final_root: WHashOut<GoldilocksField>,
+    /// Statement of the proposal the proof is expected to be for.
+    #[arg(long)]
+    statement: String,
+    /// Document the statement refers to as JSON, the `content` of its certificate.
+    #[arg(long)]
+    content: Option<String>,
+    /// Copy of the document, checked against the hash in `--content`.
+    #[arg(long, requires = "content")]
+    document: Option<PathBuf>,
+    /// Action of the proposal as JSON, e.g. `{"kind":"parameter_change","parameter":"fee","value":"1"}`.
+    /// A text only proposal if not set.
+    #[arg(long)]
+    action: Option<String>,
+    /// Results of the proposals it depends on as JSON, the `dependencies` of its certificate.
+    #[arg(long)]
+    dependencies: Option<String>,
+    /// Rules of the proposal as JSON, the `rules` of its certificate, e.g.
+    /// `{"voting_window":{"opens_at":1700000000,"deadline":1700086400}}`.
+    #[arg(long)]
+    rules: Option<String>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let bytes = std::fs::read(&args.proof)?;
+    let envelope = match std::str::from_utf8(&bytes) {
+        Ok(json) => ProofEnvelope::from_json(json)?,
+        Err(_) => ProofEnvelope::from_bincode(&bytes)?,
+    };
+    let content: Option<StatementContent> = match &args.content {
+        Some(json) => Some(serde_json::from_str(json)?),
+        None => None,
+    };
+    if let (Some(content), Some(document)) = (&content, &args.document) {
+        anyhow::ensure!(
+            content.matches(&std::fs::read(document)?),
+            "{} does not match the hash of the document of the proposal",
+            document.display()
+        );
+    }
+    let action: ProposalAction = match &args.action {
+        Some(json) => serde_json::from_str(json)?,
+        None => ProposalAction::TextOnly,
+    };
+    let dependencies: Vec<DependencyResult> = match &args.dependencies {
+        Some(json) => serde_json::from_str(json)?,
+        None => vec![],
+    };
+    let rules: ProvenRules = match &args.rules {
+        Some(json) => serde_json::from_str(json)?,
+        None => ProvenRules::default(),
+    };
+    let tally = verify_finalization(
+        &envelope,
+        args.initial_root,
+        args.final_root,
+        &args.statement,
+        content.as_ref(),
+        &action,
+        &dependencies,
+        &rules,
+    )?;
+    let result = if tally.is_tie() {
+        "tied (decided by the tie policy in its certificate)"
+    } else if tally.is_passed() {
+        "passed"
+    } else {
+        "vetoed"
+    };
+    println!(
+        "Proof verified; # of Yes votes: {}, # of No votes: {} -> Proposal {}",
+        tally.yes_votes, tally.no_votes, result
+    );
+    Ok(())
+}
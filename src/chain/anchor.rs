@@ -0,0 +1,139 @@
+use plonky2::{field::goldilocks_field::GoldilocksField, plonk::config::GenericHashOut};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use web3::{
+    contract::{Contract, Options},
+    transports::Http,
+    types::{Address, H256},
+    Web3,
+};
+
+use crate::{common::WHashOut, errors::QedError, utils::time::unix_timestamp};
+
+const ROOT_ANCHOR_ABI: &str = r#"[
+    {
+        "type": "function",
+        "name": "anchorRoots",
+        "stateMutability": "nonpayable",
+        "inputs": [
+            { "name": "proposalId", "type": "bytes32" },
+            { "name": "balanceRoot", "type": "bytes32" },
+            { "name": "nullifierRoot", "type": "bytes32" }
+        ],
+        "outputs": []
+    },
+    {
+        "type": "function",
+        "name": "anchorResultRoot",
+        "stateMutability": "nonpayable",
+        "inputs": [
+            { "name": "resultRoot", "type": "bytes32" },
+            { "name": "size", "type": "uint64" }
+        ],
+        "outputs": []
+    }
+]"#;
+
+/// A pair of roots that has been posted on-chain for a proposal.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AnchorRecord {
+    #[schema(value_type = String)]
+    pub balance_root: WHashOut<GoldilocksField>,
+    #[schema(value_type = String)]
+    pub nullifier_root: WHashOut<GoldilocksField>,
+    #[schema(value_type = String)]
+    pub tx_hash: H256,
+    pub anchored_at: u64,
+}
+
+/// A root of the result tree that has been posted on-chain, see
+/// [`crate::proof::results`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResultRootRecord {
+    #[schema(value_type = String)]
+    pub root: WHashOut<GoldilocksField>,
+    /// Number of results the root commits to.
+    pub size: u64,
+    #[schema(value_type = String)]
+    pub tx_hash: H256,
+    pub anchored_at: u64,
+}
+
+pub fn proposal_id_to_h256(proposal_id: &Uuid) -> H256 {
+    let mut bytes = [0u8; 32];
+    bytes[16..].copy_from_slice(proposal_id.as_bytes());
+    H256(bytes)
+}
+
+/// Encodes a root with the same big endian byte order used by its hex serialization.
+pub fn root_to_h256(root: &WHashOut<GoldilocksField>) -> H256 {
+    let mut bytes = root.to_bytes();
+    bytes.reverse();
+    H256::from_slice(&bytes)
+}
+
+/// Posts balance and nullifier roots, and roots of the result tree, to an anchor contract.
+pub struct RootAnchor {
+    contract: Contract<Http>,
+    from: Address,
+}
+
+impl RootAnchor {
+    pub fn new(rpc_url: &str, contract_address: Address, from: Address) -> anyhow::Result<Self> {
+        let web3 = Web3::new(Http::new(rpc_url)?);
+        let contract =
+            Contract::from_json(web3.eth(), contract_address, ROOT_ANCHOR_ABI.as_bytes())?;
+        Ok(Self { contract, from })
+    }
+    pub async fn anchor(
+        &self,
+        proposal_id: &Uuid,
+        balance_root: WHashOut<GoldilocksField>,
+        nullifier_root: WHashOut<GoldilocksField>,
+    ) -> anyhow::Result<AnchorRecord> {
+        let tx_hash = self
+            .contract
+            .call(
+                "anchorRoots",
+                (
+                    proposal_id_to_h256(proposal_id),
+                    root_to_h256(&balance_root),
+                    root_to_h256(&nullifier_root),
+                ),
+                self.from,
+                Options::default(),
+            )
+            .await
+            .map_err(|err| QedError::ChainSubmissionFailed(err.to_string()))?;
+        Ok(AnchorRecord {
+            balance_root,
+            nullifier_root,
+            tx_hash,
+            anchored_at: unix_timestamp(),
+        })
+    }
+    /// Posts the root of the first `size` results of the result tree.
+    pub async fn anchor_result_root(
+        &self,
+        root: WHashOut<GoldilocksField>,
+        size: u64,
+    ) -> anyhow::Result<ResultRootRecord> {
+        let tx_hash = self
+            .contract
+            .call(
+                "anchorResultRoot",
+                (root_to_h256(&root), size),
+                self.from,
+                Options::default(),
+            )
+            .await
+            .map_err(|err| QedError::ChainSubmissionFailed(err.to_string()))?;
+        Ok(ResultRootRecord {
+            root,
+            size,
+            tx_hash,
+            anchored_at: unix_timestamp(),
+        })
+    }
+}
@@ -0,0 +1,85 @@
+//! Randomness beacons breaking ties under [`TiePolicy::RandomWithBeacon`].
+//!
+//! The beacon of a proposal is the hash of a block its rules fix at creation,
+//! see [`ProposalRules::beacon_block`], which the node fetches itself at
+//! finalization. The block has to lie [`BEACON_LEAD_BLOCKS`] beyond the head of
+//! the chain when the proposal is created, so neither the proposer nor the
+//! finalizer know or choose its value, and anyone can check a certificate
+//! against the chain.
+//!
+//! [`TiePolicy::RandomWithBeacon`]: crate::proposal::rules::TiePolicy::RandomWithBeacon
+//! [`ProposalRules::beacon_block`]: crate::proposal::rules::ProposalRules::beacon_block
+
+use anyhow::ensure;
+use web3::{
+    transports::Http,
+    types::{BlockId, BlockNumber},
+    Web3,
+};
+
+/// Blocks mined on top of a beacon block before its hash is taken, so that a
+/// reorganization of the chain is unlikely to change it.
+pub const BEACON_CONFIRMATIONS: u64 = 12;
+
+/// Blocks a beacon block has to lie beyond the head of the chain when a proposal
+/// names it, so that its hash cannot be known, even to the miner of the next
+/// block, when the proposal is created.
+pub const BEACON_LEAD_BLOCKS: u64 = 8;
+
+/// Checks that `block` lies far enough beyond `latest`, the head of the chain,
+/// to serve as the beacon block of a proposal created now.
+pub fn check_beacon_lead(block: u64, latest: u64) -> anyhow::Result<()> {
+    ensure!(
+        block > latest.saturating_add(BEACON_LEAD_BLOCKS),
+        "the beacon block has to lie more than {} blocks beyond the head of the chain at {}",
+        BEACON_LEAD_BLOCKS,
+        latest
+    );
+    Ok(())
+}
+
+/// Fetches block hashes from one RPC endpoint.
+pub struct BlockBeacon {
+    web3: Web3<Http>,
+}
+
+impl BlockBeacon {
+    pub fn new(rpc_url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            web3: Web3::new(Http::new(rpc_url)?),
+        })
+    }
+    /// Number of the head of the chain.
+    pub async fn latest_block(&self) -> anyhow::Result<u64> {
+        Ok(self.web3.eth().block_number().await?.as_u64())
+    }
+    /// The hash of `block`, or `None` while it has fewer than
+    /// [`BEACON_CONFIRMATIONS`] blocks on top.
+    pub async fn block_hash(&self, block: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        let latest = self.latest_block().await?;
+        if latest < block.saturating_add(BEACON_CONFIRMATIONS) {
+            return Ok(None);
+        }
+        let hash = self
+            .web3
+            .eth()
+            .block(BlockId::Number(BlockNumber::Number(block.into())))
+            .await?
+            .and_then(|block| block.hash);
+        Ok(hash.map(|hash| hash.as_bytes().to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_beacon_lead, BEACON_LEAD_BLOCKS};
+
+    #[test]
+    fn test_beacon_blocks_lie_beyond_the_head() {
+        assert!(check_beacon_lead(100 + BEACON_LEAD_BLOCKS + 1, 100).is_ok());
+        // Mined, or close enough to the head for its miner to pick its hash
+        assert!(check_beacon_lead(90, 100).is_err());
+        assert!(check_beacon_lead(100 + BEACON_LEAD_BLOCKS, 100).is_err());
+        assert!(check_beacon_lead(u64::MAX, u64::MAX).is_err());
+    }
+}
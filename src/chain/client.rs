@@ -0,0 +1,246 @@
+//! Typed bindings of the verifier and governance contracts over one RPC endpoint.
+//!
+//! The bindings take their calldata from the ABIs declared next to the
+//! contracts, see [`super::settlement`] and [`super::governance`], and send it
+//! through a [`ChainClient`]. The client sends transactions from an account the
+//! node holds the key of, estimating the gas of each with a margin, numbering
+//! them with nonces it tracks itself so that transactions sent back to back do
+//! not reuse one, and retrying what fails for reasons a retry may fix.
+
+use std::time::Duration;
+
+use tokio::{sync::Mutex, time::sleep};
+use tracing::warn;
+use uuid::Uuid;
+use web3::{
+    transports::Http,
+    types::{Address, BlockNumber, Bytes, CallRequest, TransactionRequest, H256, U256},
+    Web3,
+};
+
+use crate::errors::QedError;
+
+use super::{governance::GovernanceListener, settlement::Groth16Proof};
+
+/// Attempts made at each call or transaction by default.
+pub const DEFAULT_CHAIN_ATTEMPTS: u32 = 3;
+/// Gas added on top of the estimate of a transaction, in percent of it.
+pub const GAS_MARGIN_PERCENT: u64 = 20;
+
+/// How calls and transactions failing for transient reasons are retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainRetryPolicy {
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled for every further one.
+    pub backoff: Duration,
+}
+
+impl Default for ChainRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_CHAIN_ATTEMPTS,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The estimate of a transaction with [`GAS_MARGIN_PERCENT`] added.
+pub fn with_gas_margin(estimate: U256) -> U256 {
+    estimate.saturating_add(estimate / 100 * GAS_MARGIN_PERCENT)
+}
+
+/// Whether an RPC error may not happen again: the node was unreachable, or
+/// rejected a nonce that another transaction of the account took meanwhile.
+/// Reverts and malformed requests fail the same way every time.
+fn is_transient(err: &web3::Error) -> bool {
+    match err {
+        web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_) => true,
+        web3::Error::Rpc(err) => is_nonce_error(&err.message),
+        _ => false,
+    }
+}
+
+fn is_nonce_error(message: &str) -> bool {
+    message.to_lowercase().contains("nonce too low")
+}
+
+/// Sends calls and transactions to one node on behalf of one account.
+pub struct ChainClient {
+    web3: Web3<Http>,
+    from: Address,
+    retry_policy: ChainRetryPolicy,
+    /// Nonce of the next transaction, read from the node when unknown. Held while
+    /// a transaction is sent, so transactions of the client are sent one by one.
+    next_nonce: Mutex<Option<U256>>,
+}
+
+impl ChainClient {
+    pub fn new(rpc_url: &str, from: Address) -> anyhow::Result<Self> {
+        Ok(Self {
+            web3: Web3::new(Http::new(rpc_url)?),
+            from,
+            retry_policy: ChainRetryPolicy::default(),
+            next_nonce: Mutex::new(None),
+        })
+    }
+    pub fn with_retry_policy(mut self, retry_policy: ChainRetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+    /// Account the transactions of the client are sent from.
+    pub fn account(&self) -> Address {
+        self.from
+    }
+    pub fn verifier(&self, contract: Address) -> VerifierContract<'_> {
+        VerifierContract {
+            client: self,
+            contract,
+        }
+    }
+    /// A listener of the `ProposalCreated` events of `contract` on the node of the client.
+    pub fn governance(&self, contract: Address) -> GovernanceListener {
+        GovernanceListener::with_web3(self.web3.clone(), contract)
+    }
+    /// Calls `contract` with `data` without sending a transaction.
+    pub async fn call(&self, contract: Address, data: Vec<u8>) -> Result<Bytes, web3::Error> {
+        let request = CallRequest {
+            from: Some(self.from),
+            to: Some(contract),
+            data: Some(Bytes(data)),
+            ..Default::default()
+        };
+        let mut attempt = 1;
+        loop {
+            match self.web3.eth().call(request.clone(), None).await {
+                Err(err) if is_transient(&err) && attempt < self.retry_policy.max_attempts => {
+                    warn!(%contract, attempt, "Call failed, retrying: {}", err);
+                    sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Sends a transaction calling `contract` with `data` and returns its hash
+    /// once the node accepted it, without waiting for it to be mined.
+    pub async fn send(&self, contract: Address, data: Vec<u8>) -> Result<H256, QedError> {
+        let mut next_nonce = self.next_nonce.lock().await;
+        let mut attempt = 1;
+        loop {
+            match self.try_send(contract, &data, *next_nonce).await {
+                Ok((tx_hash, nonce)) => {
+                    *next_nonce = Some(nonce + 1);
+                    return Ok(tx_hash);
+                }
+                Err(err) => {
+                    // Another sender of the account may have taken the nonce
+                    *next_nonce = None;
+                    if !is_transient(&err) || attempt >= self.retry_policy.max_attempts {
+                        return Err(QedError::ChainSubmissionFailed(err.to_string()));
+                    }
+                    warn!(%contract, attempt, "Transaction failed, retrying: {}", err);
+                    sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+    async fn try_send(
+        &self,
+        contract: Address,
+        data: &[u8],
+        nonce: Option<U256>,
+    ) -> Result<(H256, U256), web3::Error> {
+        let nonce = match nonce {
+            Some(nonce) => nonce,
+            None => {
+                self.web3
+                    .eth()
+                    .transaction_count(self.from, Some(BlockNumber::Pending))
+                    .await?
+            }
+        };
+        let call = CallRequest {
+            from: Some(self.from),
+            to: Some(contract),
+            data: Some(Bytes(data.to_vec())),
+            ..Default::default()
+        };
+        let gas = self.web3.eth().estimate_gas(call, None).await?;
+        let tx_hash = self
+            .web3
+            .eth()
+            .send_transaction(TransactionRequest {
+                from: self.from,
+                to: Some(contract),
+                gas: Some(with_gas_margin(gas)),
+                nonce: Some(nonce),
+                data: Some(Bytes(data.to_vec())),
+                ..Default::default()
+            })
+            .await?;
+        Ok((tx_hash, nonce))
+    }
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.retry_policy.backoff * (1 << (attempt - 1).min(10))
+    }
+}
+
+/// The Solidity verifier exported by the Groth16 prover, along with the
+/// settlement contract calling it, see [`super::settlement`].
+pub struct VerifierContract<'a> {
+    client: &'a ChainClient,
+    contract: Address,
+}
+
+impl VerifierContract<'_> {
+    /// Whether the contract accepts `proof`. The verifier reverts on proofs that
+    /// do not verify, which is answered with `false` rather than an error.
+    pub async fn verify_proof(&self, proof: &Groth16Proof) -> anyhow::Result<bool> {
+        match self
+            .client
+            .call(self.contract, proof.verify_calldata()?)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(web3::Error::Rpc(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+    /// Settles the result of `proposal_id` with `proof`, returning the hash of
+    /// the transaction.
+    pub async fn settle_finalization(
+        &self,
+        proposal_id: &Uuid,
+        proof: &Groth16Proof,
+    ) -> anyhow::Result<H256> {
+        let data = proof.settle_calldata(proposal_id)?;
+        Ok(self.client.send(self.contract, data).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use web3::types::{Address, U256};
+
+    use super::{is_nonce_error, with_gas_margin, ChainClient, ChainRetryPolicy};
+
+    #[test]
+    fn test_sends_with_gas_margin_and_backoff() -> anyhow::Result<()> {
+        assert_eq!(with_gas_margin(U256::from(100_000)), U256::from(120_000));
+        assert_eq!(with_gas_margin(U256::MAX), U256::MAX);
+        assert!(is_nonce_error("Nonce too low"));
+        assert!(!is_nonce_error("execution reverted"));
+
+        let client = ChainClient::new("http://localhost:8545", Address::repeat_byte(1))?
+            .with_retry_policy(ChainRetryPolicy {
+                max_attempts: 4,
+                backoff: Duration::from_millis(100),
+            });
+        assert_eq!(client.backoff(1), Duration::from_millis(100));
+        assert_eq!(client.backoff(3), Duration::from_millis(400));
+        Ok(())
+    }
+}
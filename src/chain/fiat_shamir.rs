@@ -0,0 +1,331 @@
+//! Proving knowledge of a secret to a `FiatShamirZKP` contract.
+//!
+//! The contract checks Schnorr proofs of knowledge of a discrete log `x` of
+//! `y = g^x mod p`, in the subgroup of prime order `q` that `g` generates, made
+//! non-interactive with the Fiat–Shamir transform:
+//!
+//! 1. the prover commits to a random `v` with `t = g^v mod p`,
+//! 2. the challenge `c` is the Keccak-256 hash of the transcript
+//!    `abi.encodePacked(g, y, t, prover)`, reduced mod `q`, in place of one
+//!    drawn by the verifier,
+//! 3. the prover responds with `r = v - c * x mod q`,
+//!
+//! and the contract accepts `(y, t, r)` from `prover` if `g^r * y^c = t mod p`.
+//! The prover address in the transcript keeps a proof seen in the mempool from
+//! being submitted by anyone else. The group is read from the contract, so
+//! proofs are made for the parameters it was deployed with.
+
+use anyhow::{anyhow, ensure};
+use num::{bigint::RandBigInt, BigUint, One, Zero};
+use rand::Rng;
+use web3::{
+    ethabi::{Contract, Token},
+    signing::keccak256,
+    types::{Address, H256, U256},
+};
+
+use super::client::ChainClient;
+
+const FIAT_SHAMIR_ABI: &str = r#"[
+    {
+        "type": "function",
+        "name": "p",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [{ "name": "", "type": "uint256" }]
+    },
+    {
+        "type": "function",
+        "name": "q",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [{ "name": "", "type": "uint256" }]
+    },
+    {
+        "type": "function",
+        "name": "g",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [{ "name": "", "type": "uint256" }]
+    },
+    {
+        "type": "function",
+        "name": "verify",
+        "stateMutability": "view",
+        "inputs": [
+            { "name": "y", "type": "uint256" },
+            { "name": "t", "type": "uint256" },
+            { "name": "r", "type": "uint256" }
+        ],
+        "outputs": [{ "name": "", "type": "bool" }]
+    },
+    {
+        "type": "function",
+        "name": "submitProof",
+        "stateMutability": "nonpayable",
+        "inputs": [
+            { "name": "y", "type": "uint256" },
+            { "name": "t", "type": "uint256" },
+            { "name": "r", "type": "uint256" }
+        ],
+        "outputs": []
+    }
+]"#;
+
+fn to_u256(value: &BigUint) -> anyhow::Result<U256> {
+    let bytes = value.to_bytes_be();
+    ensure!(bytes.len() <= 32, "{} does not fit in a uint256", value);
+    Ok(U256::from_big_endian(&bytes))
+}
+
+fn from_u256(value: U256) -> BigUint {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    BigUint::from_bytes_be(&bytes)
+}
+
+/// A subgroup of prime order `q` of the integers mod `p`, generated by `g`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchnorrGroup {
+    pub p: BigUint,
+    pub q: BigUint,
+    pub g: BigUint,
+}
+
+impl SchnorrGroup {
+    /// Checks that `g` generates a subgroup of order `q` and that the values fit
+    /// in the uint256 the contract takes them as. Does not check that `p` and `q`
+    /// are prime, which the deployer of the contract vouches for.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        to_u256(&self.p)?;
+        ensure!(
+            self.q > BigUint::one() && self.p > self.q,
+            "the order q must be between 1 and p"
+        );
+        ensure!(
+            ((&self.p - 1u32) % &self.q).is_zero(),
+            "q does not divide p - 1"
+        );
+        ensure!(
+            self.g > BigUint::one() && self.g < self.p,
+            "g must be between 1 and p"
+        );
+        ensure!(
+            self.g.modpow(&self.q, &self.p).is_one(),
+            "g does not generate a subgroup of order q"
+        );
+        Ok(())
+    }
+    /// The public key of `secret`, `g^secret mod p`.
+    pub fn public_key(&self, secret: &BigUint) -> BigUint {
+        self.g.modpow(secret, &self.p)
+    }
+    /// The challenge of the commitment `t` to a proof of the key `y` by `prover`,
+    /// the Keccak-256 hash of `abi.encodePacked(g, y, t, prover)` mod `q`.
+    pub fn challenge(&self, y: &BigUint, t: &BigUint, prover: Address) -> anyhow::Result<BigUint> {
+        let mut transcript = Vec::with_capacity(3 * 32 + 20);
+        for value in [&self.g, y, t] {
+            let mut word = [0u8; 32];
+            to_u256(value)?.to_big_endian(&mut word);
+            transcript.extend_from_slice(&word);
+        }
+        transcript.extend_from_slice(prover.as_bytes());
+        Ok(BigUint::from_bytes_be(&keccak256(&transcript)) % &self.q)
+    }
+}
+
+/// The first move of the prover: a commitment `t = g^v mod p` to a random `v`.
+pub struct SchnorrProver<'a> {
+    group: &'a SchnorrGroup,
+    secret: BigUint,
+    nonce: BigUint,
+    pub commitment: BigUint,
+}
+
+impl<'a> SchnorrProver<'a> {
+    pub fn commit(group: &'a SchnorrGroup, secret: BigUint, rng: &mut impl Rng) -> Self {
+        let nonce = rng.gen_biguint_range(&BigUint::one(), &group.q);
+        Self {
+            commitment: group.g.modpow(&nonce, &group.p),
+            group,
+            secret,
+            nonce,
+        }
+    }
+    /// The response `r = v - c * x mod q` to the challenge `c`.
+    pub fn respond(&self, challenge: &BigUint) -> BigUint {
+        let q = &self.group.q;
+        (&self.nonce + q - (challenge * &self.secret) % q) % q
+    }
+}
+
+/// A proof of knowledge of the discrete log of `y`, as the contract takes it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FiatShamirProof {
+    pub y: BigUint,
+    pub t: BigUint,
+    pub r: BigUint,
+}
+
+impl FiatShamirProof {
+    /// Proves knowledge of `secret` for submission by `prover`.
+    pub fn prove(
+        group: &SchnorrGroup,
+        secret: &BigUint,
+        prover: Address,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<Self> {
+        let secret = secret % &group.q;
+        let y = group.public_key(&secret);
+        let commitment = SchnorrProver::commit(group, secret, rng);
+        let challenge = group.challenge(&y, &commitment.commitment, prover)?;
+        Ok(Self {
+            r: commitment.respond(&challenge),
+            t: commitment.commitment,
+            y,
+        })
+    }
+    /// Checks the proof the way the contract does for a submission by `prover`.
+    pub fn verify(&self, group: &SchnorrGroup, prover: Address) -> anyhow::Result<bool> {
+        let challenge = group.challenge(&self.y, &self.t, prover)?;
+        let lhs = group.g.modpow(&self.r, &group.p) * self.y.modpow(&challenge, &group.p);
+        Ok(lhs % &group.p == self.t)
+    }
+    fn tokens(&self) -> anyhow::Result<[Token; 3]> {
+        Ok([
+            Token::Uint(to_u256(&self.y)?),
+            Token::Uint(to_u256(&self.t)?),
+            Token::Uint(to_u256(&self.r)?),
+        ])
+    }
+    /// Calldata of `verify(uint256,uint256,uint256)`.
+    pub fn verify_calldata(&self) -> anyhow::Result<Vec<u8>> {
+        let contract = Contract::load(FIAT_SHAMIR_ABI.as_bytes())?;
+        Ok(contract.function("verify")?.encode_input(&self.tokens()?)?)
+    }
+    /// Calldata of `submitProof(uint256,uint256,uint256)`.
+    pub fn submit_calldata(&self) -> anyhow::Result<Vec<u8>> {
+        let contract = Contract::load(FIAT_SHAMIR_ABI.as_bytes())?;
+        Ok(contract
+            .function("submitProof")?
+            .encode_input(&self.tokens()?)?)
+    }
+}
+
+/// A deployed `FiatShamirZKP` contract, proven to from the account of a [`ChainClient`].
+pub struct FiatShamirContract<'a> {
+    client: &'a ChainClient,
+    contract: Address,
+}
+
+impl<'a> FiatShamirContract<'a> {
+    pub fn new(client: &'a ChainClient, contract: Address) -> Self {
+        Self { client, contract }
+    }
+    async fn query(&self, name: &str, tokens: &[Token]) -> anyhow::Result<Token> {
+        let abi = Contract::load(FIAT_SHAMIR_ABI.as_bytes())?;
+        let function = abi.function(name)?;
+        let output = self
+            .client
+            .call(self.contract, function.encode_input(tokens)?)
+            .await?;
+        function
+            .decode_output(&output.0)?
+            .pop()
+            .ok_or_else(|| anyhow!("{} returned nothing", name))
+    }
+    async fn query_uint(&self, name: &str) -> anyhow::Result<BigUint> {
+        match self.query(name, &[]).await? {
+            Token::Uint(value) => Ok(from_u256(value)),
+            token => Err(anyhow!("{} is not a uint: {:?}", name, token)),
+        }
+    }
+    /// The group the contract was deployed with, checked to be one.
+    pub async fn group(&self) -> anyhow::Result<SchnorrGroup> {
+        let group = SchnorrGroup {
+            p: self.query_uint("p").await?,
+            q: self.query_uint("q").await?,
+            g: self.query_uint("g").await?,
+        };
+        group.validate()?;
+        Ok(group)
+    }
+    /// Whether the contract accepts `proof` from the account of the client.
+    pub async fn verify(&self, proof: &FiatShamirProof) -> anyhow::Result<bool> {
+        match self.query("verify", &proof.tokens()?).await? {
+            Token::Bool(accepted) => Ok(accepted),
+            token => Err(anyhow!("verify did not return a bool: {:?}", token)),
+        }
+    }
+    /// Proves knowledge of `secret` to the contract end to end: reads its group,
+    /// proves for the account of the client, checks the proof locally and with
+    /// the contract, and submits it. Returns the hash of the submission.
+    pub async fn prove(&self, secret: &BigUint) -> anyhow::Result<H256> {
+        let group = self.group().await?;
+        let prover = self.client.account();
+        let proof = FiatShamirProof::prove(&group, secret, prover, &mut rand::thread_rng())?;
+        ensure!(proof.verify(&group, prover)?, "the proof does not verify");
+        ensure!(
+            self.verify(&proof).await?,
+            "the contract rejects the proof, whose transcript may differ from the one it hashes"
+        );
+        Ok(self
+            .client
+            .send(self.contract, proof.submit_calldata()?)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::BigUint;
+    use rand::{rngs::StdRng, SeedableRng};
+    use web3::{signing::keccak256, types::Address};
+
+    use super::{FiatShamirProof, SchnorrGroup};
+
+    #[test]
+    fn test_proves_knowledge_of_a_discrete_log() -> anyhow::Result<()> {
+        // 4 generates the subgroup of prime order q of the integers mod the safe prime 2q + 1
+        let group = SchnorrGroup {
+            p: BigUint::from(4_611_686_018_427_394_499u64),
+            q: BigUint::from(2_305_843_009_213_697_249u64),
+            g: BigUint::from(4u32),
+        };
+        group.validate()?;
+        assert!(SchnorrGroup {
+            q: BigUint::from(11u32),
+            ..group.clone()
+        }
+        .validate()
+        .is_err());
+
+        let prover = Address::repeat_byte(7);
+        let mut rng = StdRng::seed_from_u64(0);
+        let proof = FiatShamirProof::prove(&group, &BigUint::from(6u32), prover, &mut rng)?;
+        assert_eq!(proof.y, BigUint::from(4096u32));
+        assert!(proof.verify(&group, prover)?);
+        // The challenge hashes the packed transcript, as the contract does
+        let mut transcript = vec![];
+        for value in [&group.g, &proof.y, &proof.t] {
+            let bytes = value.to_bytes_be();
+            transcript.extend(std::iter::repeat(0u8).take(32 - bytes.len()));
+            transcript.extend_from_slice(&bytes);
+        }
+        transcript.extend_from_slice(&[7u8; 20]);
+        assert_eq!(
+            group.challenge(&proof.y, &proof.t, prover)?,
+            BigUint::from_bytes_be(&keccak256(&transcript)) % &group.q
+        );
+
+        // Neither another prover nor another response passes
+        assert!(!proof.verify(&group, Address::repeat_byte(8))?);
+        let forged = FiatShamirProof {
+            r: (&proof.r + 1u32) % &group.q,
+            ..proof.clone()
+        };
+        assert!(!forged.verify(&group, prover)?);
+        assert_eq!(proof.submit_calldata()?.len(), 4 + 3 * 32);
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Mirroring the proposals of an on-chain governance contract as local proposals.
+//!
+//! The contract emits `ProposalCreated(uint256 indexed proposalId, bytes32
+//! statementHash, uint256 snapshotBlock, uint256 deadline)` for each proposal.
+//! The local proposal takes the statement hash as its statement, the deadline as
+//! the end of its voting period and, when a token is given, the balances of the
+//! token at the snapshot block as its voting power. Its id is derived from the
+//! on-chain id, see [`crate::proposal::id`], so mirroring the same event again,
+//! e.g. after a restart rescans the contract, leaves the proposal as it is.
+
+use anyhow::{anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use web3::{
+    ethabi::{Contract, RawLog, Token},
+    transports::Http,
+    types::{Address, BlockNumber, FilterBuilder, H256, U256},
+    Web3,
+};
+
+use crate::api::ProposeQuery;
+
+use super::token_snapshot::{TokenSnapshotRequest, LOG_SCAN_CHUNK};
+
+const GOVERNANCE_ABI: &str = r#"[
+    {
+        "type": "event",
+        "name": "ProposalCreated",
+        "anonymous": false,
+        "inputs": [
+            { "name": "proposalId", "type": "uint256", "indexed": true },
+            { "name": "statementHash", "type": "bytes32", "indexed": false },
+            { "name": "snapshotBlock", "type": "uint256", "indexed": false },
+            { "name": "deadline", "type": "uint256", "indexed": false }
+        ]
+    }
+]"#;
+
+/// A proposal created on the governance contract.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainProposal {
+    /// Id of the proposal on the contract.
+    pub proposal_id: U256,
+    pub statement_hash: H256,
+    /// Block the voting power of the proposal is taken at.
+    pub snapshot_block: u64,
+    /// Unix time the voting period ends at.
+    pub deadline: u64,
+    /// Block the proposal was created in.
+    pub block: u64,
+}
+
+/// How mirrored proposals are created.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MirrorOptions {
+    /// Proposer, and so finalizer, of the mirrored proposals.
+    pub proposer_id: u32,
+    /// DAO the proposals are created in, the default DAO if not set.
+    pub dao_id: Option<String>,
+    /// Token whose balances at the snapshot block weigh the votes, one vote per
+    /// voter of the default electorate if not set.
+    pub token: Option<Address>,
+    /// First block scanned for holders of the token.
+    pub token_from_block: u64,
+    /// Blocks mined on top of an event before the proposal is mirrored.
+    pub confirmations: u64,
+}
+
+impl ChainProposal {
+    /// Decodes a `ProposalCreated` event.
+    pub fn from_log(topics: Vec<H256>, data: Vec<u8>, block: u64) -> anyhow::Result<Self> {
+        let contract = Contract::load(GOVERNANCE_ABI.as_bytes())?;
+        let log = contract
+            .event("ProposalCreated")?
+            .parse_log(RawLog { topics, data })?;
+        let param = |name: &str| {
+            log.params
+                .iter()
+                .find(|param| param.name == name)
+                .map(|param| param.value.clone())
+                .ok_or_else(|| anyhow!("ProposalCreated has no {}", name))
+        };
+        let uint = |name: &str| match param(name)? {
+            Token::Uint(value) => Ok(value),
+            token => Err(anyhow!("{} is not a uint: {:?}", name, token)),
+        };
+        let statement_hash = match param("statementHash")? {
+            Token::FixedBytes(bytes) if bytes.len() == 32 => H256::from_slice(&bytes),
+            token => return Err(anyhow!("statementHash is not a bytes32: {:?}", token)),
+        };
+        let snapshot_block = uint("snapshotBlock")?;
+        let deadline = uint("deadline")?;
+        ensure!(
+            snapshot_block.bits() <= 64 && deadline.bits() <= 64,
+            "snapshot block {} or deadline {} does not fit in 64 bits",
+            snapshot_block,
+            deadline
+        );
+        Ok(Self {
+            proposal_id: uint("proposalId")?,
+            statement_hash,
+            snapshot_block: snapshot_block.as_u64(),
+            deadline: deadline.as_u64(),
+            block,
+        })
+    }
+    /// Statement of the local proposal, the hex encoded statement hash.
+    pub fn statement(&self) -> String {
+        format!("0x{}", hex::encode(self.statement_hash.as_bytes()))
+    }
+    /// The request creating the local proposal at time `now`, or `None` if the
+    /// voting period has already ended.
+    pub fn propose_query(&self, options: &MirrorOptions, now: u64) -> Option<ProposeQuery> {
+        if self.deadline <= now {
+            return None;
+        }
+        Some(ProposeQuery {
+            proposer_id: options.proposer_id,
+            statement: self.statement(),
+            voting_period_secs: Some(self.deadline - now),
+            token_snapshot: options.token.map(|token| TokenSnapshotRequest {
+                token,
+                block: self.snapshot_block,
+                from_block: options.token_from_block,
+                decimals: None,
+            }),
+            dao_id: options.dao_id.clone(),
+            // Ids past 64 bits wrap, which the statement hash keeps apart in practice
+            nonce: Some(self.proposal_id.low_u64()),
+            ..ProposeQuery::default()
+        })
+    }
+}
+
+/// Reads the `ProposalCreated` events of a governance contract.
+pub struct GovernanceListener {
+    web3: Web3<Http>,
+    contract: Address,
+}
+
+impl GovernanceListener {
+    pub fn new(rpc_url: &str, contract: Address) -> anyhow::Result<Self> {
+        Ok(Self::with_web3(Web3::new(Http::new(rpc_url)?), contract))
+    }
+    /// A listener sharing the connection of `web3`, see
+    /// [`ChainClient::governance`](super::client::ChainClient::governance).
+    pub fn with_web3(web3: Web3<Http>, contract: Address) -> Self {
+        Self { web3, contract }
+    }
+    /// Proposals created from `from_block` up to the latest block with
+    /// `confirmations` blocks on top, along with the block to scan from next time.
+    /// Events of more recent blocks are left to a later scan, as a reorganization
+    /// of the chain may still drop them.
+    pub async fn scan(
+        &self,
+        from_block: u64,
+        confirmations: u64,
+    ) -> anyhow::Result<(Vec<ChainProposal>, u64)> {
+        let topic = Contract::load(GOVERNANCE_ABI.as_bytes())?
+            .event("ProposalCreated")?
+            .signature();
+        let head = self.web3.eth().block_number().await?.as_u64();
+        let latest = match head.checked_sub(confirmations) {
+            Some(latest) => latest,
+            None => return Ok((vec![], from_block)),
+        };
+        let mut proposals = vec![];
+        let mut from = from_block;
+        while from <= latest {
+            let to = (from + LOG_SCAN_CHUNK - 1).min(latest);
+            let filter = FilterBuilder::default()
+                .address(vec![self.contract])
+                .topics(Some(vec![topic]), None, None, None)
+                .from_block(BlockNumber::Number(from.into()))
+                .to_block(BlockNumber::Number(to.into()))
+                .build();
+            for log in self.web3.eth().logs(filter).await? {
+                // Logs of a block that is being mined have no number yet
+                let block = log
+                    .block_number
+                    .ok_or_else(|| anyhow!("ProposalCreated log without a block number"))?;
+                proposals.push(ChainProposal::from_log(
+                    log.topics,
+                    log.data.0,
+                    block.as_u64(),
+                )?);
+            }
+            from = to + 1;
+        }
+        Ok((proposals, from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::{
+        ethabi::{encode, Contract, Token},
+        types::{Address, H256, U256},
+    };
+
+    use super::{ChainProposal, MirrorOptions, GOVERNANCE_ABI};
+
+    #[test]
+    fn test_mirrors_proposal_created_events() -> anyhow::Result<()> {
+        let topic = Contract::load(GOVERNANCE_ABI.as_bytes())?
+            .event("ProposalCreated")?
+            .signature();
+        let mut id = [0u8; 32];
+        id[31] = 42;
+        let data = encode(&[
+            Token::FixedBytes(vec![0xab; 32]),
+            Token::Uint(U256::from(1_000)),
+            Token::Uint(U256::from(5_000)),
+        ]);
+        let proposal = ChainProposal::from_log(vec![topic, H256(id)], data, 1_200)?;
+        assert_eq!(
+            proposal,
+            ChainProposal {
+                proposal_id: U256::from(42),
+                statement_hash: H256([0xab; 32]),
+                snapshot_block: 1_000,
+                deadline: 5_000,
+                block: 1_200,
+            }
+        );
+
+        let options = MirrorOptions {
+            proposer_id: 3,
+            dao_id: None,
+            token: Some(Address::repeat_byte(1)),
+            token_from_block: 10,
+            confirmations: 12,
+        };
+        let query = proposal.propose_query(&options, 4_000).unwrap();
+        assert_eq!(query.statement, format!("0x{}", "ab".repeat(32)));
+        assert_eq!(query.voting_period_secs, Some(1_000));
+        assert_eq!(query.nonce, Some(42));
+        let snapshot = query.token_snapshot.unwrap();
+        assert_eq!((snapshot.block, snapshot.from_block), (1_000, 10));
+        assert!(proposal.propose_query(&options, 5_000).is_none());
+
+        // A log of another event does not decode
+        let data = encode(&[Token::Uint(U256::one())]);
+        assert!(ChainProposal::from_log(vec![topic, H256(id)], data, 1).is_err());
+        Ok(())
+    }
+}
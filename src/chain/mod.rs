@@ -0,0 +1,8 @@
+pub mod anchor;
+pub mod beacon;
+pub mod client;
+pub mod fiat_shamir;
+pub mod governance;
+pub mod settlement;
+pub mod timestamp;
+pub mod token_snapshot;
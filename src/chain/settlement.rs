@@ -0,0 +1,197 @@
+//! Settling finalization results on an EVM chain.
+//!
+//! The plonky2 proof of a finalization is wrapped by an
+//! [`EvmWrapperCircuit`](crate::circuits::evm_wrapper::EvmWrapperCircuit), whose
+//! proof an external gnark-based prover turns into a Groth16 proof over BN254.
+//! The Groth16 circuit, and so the trusted setup of its proving key, belongs to
+//! that prover; this module only runs it and encodes its proof as calldata for
+//! the Solidity verifier it exports and a settlement contract calling it.
+
+use std::{fs, path::Path, process::Command, str::FromStr};
+
+use anyhow::{anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use web3::{
+    ethabi::{Contract, Token},
+    types::U256,
+};
+
+use crate::circuits::update_balance::ACTION_HASH_PUBLIC_INPUTS;
+
+use super::anchor::proposal_id_to_h256;
+
+/// Public inputs of a finalization proof, and so of its wrapper and Groth16 proofs.
+pub const FINALIZATION_PUBLIC_INPUT_COUNT: usize = ACTION_HASH_PUBLIC_INPUTS.end;
+// The ABI below spells out the number of public inputs
+const _: () = assert!(FINALIZATION_PUBLIC_INPUT_COUNT == 18);
+/// File the external prover writes its proof to, in the directory of the wrapper artifacts.
+pub const GROTH16_PROOF_FILE: &str = "groth16_proof.json";
+
+const SETTLEMENT_ABI: &str = r#"[
+    {
+        "type": "function",
+        "name": "verifyProof",
+        "stateMutability": "view",
+        "inputs": [
+            { "name": "proof", "type": "uint256[8]" },
+            { "name": "input", "type": "uint256[18]" }
+        ],
+        "outputs": []
+    },
+    {
+        "type": "function",
+        "name": "settleFinalization",
+        "stateMutability": "nonpayable",
+        "inputs": [
+            { "name": "proposalId", "type": "bytes32" },
+            { "name": "proof", "type": "uint256[8]" },
+            { "name": "input", "type": "uint256[18]" }
+        ],
+        "outputs": []
+    }
+]"#;
+
+/// Parses a field element written either in decimal, as gnark does, or as 0x prefixed hex.
+fn parse_uint(value: &str) -> anyhow::Result<U256> {
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str(hex).map_err(|err| anyhow!("invalid hex {}: {}", value, err)),
+        None => {
+            U256::from_dec_str(value).map_err(|err| anyhow!("invalid uint {}: {:?}", value, err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Groth16ProofJson {
+    proof: Vec<String>,
+    public_inputs: Vec<String>,
+}
+
+/// A Groth16 proof of a wrapped finalization proof, as the Solidity verifier takes it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Groth16ProofJson")]
+pub struct Groth16Proof {
+    /// The points A, B and C, with the coordinates of B in the order of the verifier.
+    pub proof: [U256; 8],
+    /// The public inputs of the finalization proof, one Goldilocks element each.
+    pub public_inputs: Vec<U256>,
+}
+
+impl TryFrom<Groth16ProofJson> for Groth16Proof {
+    type Error = anyhow::Error;
+
+    fn try_from(json: Groth16ProofJson) -> anyhow::Result<Self> {
+        let proof: Vec<U256> = json
+            .proof
+            .iter()
+            .map(|value| parse_uint(value))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            proof: proof
+                .try_into()
+                .map_err(|_| anyhow!("a Groth16 proof is 8 field elements"))?,
+            public_inputs: json
+                .public_inputs
+                .iter()
+                .map(|value| parse_uint(value))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+impl Groth16Proof {
+    fn tokens(&self) -> anyhow::Result<[Token; 2]> {
+        ensure!(
+            self.public_inputs.len() == FINALIZATION_PUBLIC_INPUT_COUNT,
+            "expected {} public inputs, got {}",
+            FINALIZATION_PUBLIC_INPUT_COUNT,
+            self.public_inputs.len()
+        );
+        let uints =
+            |values: &[U256]| Token::FixedArray(values.iter().copied().map(Token::Uint).collect());
+        Ok([uints(&self.proof), uints(&self.public_inputs)])
+    }
+    /// Calldata of `verifyProof(uint256[8],uint256[18])` on the Solidity verifier.
+    pub fn verify_calldata(&self) -> anyhow::Result<Vec<u8>> {
+        let contract = Contract::load(SETTLEMENT_ABI.as_bytes())?;
+        Ok(contract
+            .function("verifyProof")?
+            .encode_input(&self.tokens()?)?)
+    }
+    /// Calldata of `settleFinalization(bytes32,uint256[8],uint256[18])`, recording
+    /// the result of `proposal_id` once the proof verifies.
+    pub fn settle_calldata(&self, proposal_id: &Uuid) -> anyhow::Result<Vec<u8>> {
+        let contract = Contract::load(SETTLEMENT_ABI.as_bytes())?;
+        let [proof, input] = self.tokens()?;
+        Ok(contract.function("settleFinalization")?.encode_input(&[
+            Token::FixedBytes(proposal_id_to_h256(proposal_id).as_bytes().to_vec()),
+            proof,
+            input,
+        ])?)
+    }
+}
+
+/// Runs an external Groth16 prover on the wrapper artifacts in `artifacts_dir`,
+/// see [`EvmWrapperCircuit::write_artifacts`](crate::circuits::evm_wrapper::EvmWrapperCircuit::write_artifacts).
+/// The prover is called with the directory as its only argument and writes its
+/// proof to [`GROTH16_PROOF_FILE`] in it. The hasher the wrapper was built with
+/// is named in [`HASHER_FILE`](crate::circuits::evm_wrapper::HASHER_FILE).
+pub fn prove_groth16(prover: &Path, artifacts_dir: &Path) -> anyhow::Result<Groth16Proof> {
+    let output = Command::new(prover).arg(artifacts_dir).output()?;
+    ensure!(
+        output.status.success(),
+        "Groth16 prover {} failed with {}: {}",
+        prover.display(),
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json = fs::read(artifacts_dir.join(GROTH16_PROOF_FILE))?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use web3::{signing::keccak256, types::U256};
+
+    use super::{Groth16Proof, FINALIZATION_PUBLIC_INPUT_COUNT};
+
+    #[test]
+    fn test_encodes_settlement_calldata() -> anyhow::Result<()> {
+        let public_inputs: Vec<String> = (0..FINALIZATION_PUBLIC_INPUT_COUNT)
+            .map(|i| i.to_string())
+            .collect();
+        let json = serde_json::json!({
+            "proof": ["1", "2", "3", "4", "5", "6", "7", "0x08"],
+            "public_inputs": public_inputs,
+        });
+        let proof: Groth16Proof = serde_json::from_value(json)?;
+        assert_eq!(proof.proof[7], U256::from(8));
+        // Serialized as hex, which parses back to the same proof
+        assert_eq!(
+            serde_json::from_value::<Groth16Proof>(serde_json::to_value(&proof)?)?,
+            proof
+        );
+
+        let calldata = proof.verify_calldata()?;
+        let selector = &keccak256(b"verifyProof(uint256[8],uint256[18])")[..4];
+        assert_eq!(&calldata[..4], selector);
+        assert_eq!(
+            calldata.len(),
+            4 + 32 * (8 + FINALIZATION_PUBLIC_INPUT_COUNT)
+        );
+        assert_eq!(calldata[4 + 32 * 8 - 1], 8);
+
+        let calldata = proof.settle_calldata(&Uuid::nil())?;
+        assert_eq!(
+            calldata.len(),
+            4 + 32 * (9 + FINALIZATION_PUBLIC_INPUT_COUNT)
+        );
+
+        let mut short = proof;
+        short.public_inputs.pop();
+        assert!(short.verify_calldata().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use utoipa::ToSchema;
+
+use crate::utils::time::unix_timestamp;
+
+/// DER encoded AlgorithmIdentifier for SHA-256 (2.16.840.1.101.3.4.2.1) with NULL parameters.
+const SHA256_ALGORITHM_ID: [u8; 15] = [
+    0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00,
+];
+const DER_BOOLEAN: u8 = 0x01;
+const DER_INTEGER: u8 = 0x02;
+const DER_OCTET_STRING: u8 = 0x04;
+const DER_SEQUENCE: u8 = 0x30;
+
+/// What a timestamp token attests to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSubject {
+    /// The digest of the frozen vote transcript.
+    Transcript,
+    /// The digest of the finalization certificate.
+    Certificate,
+}
+
+/// A RFC 3161 timestamp token obtained for a SHA-256 digest.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TimestampRecord {
+    pub subject: TimestampSubject,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub digest: Vec<u8>,
+    pub tsa_url: String,
+    /// The DER encoded TimeStampToken (a CMS SignedData) as returned by the authority.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub token: Vec<u8>,
+    pub requested_at: u64,
+}
+
+fn der_encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0x80 | len_bytes.len() as u8);
+        out.extend_from_slice(&len_bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn der_encode_u64(value: u64) -> Vec<u8> {
+    let mut bytes: Vec<u8> = value
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|b| *b == 0)
+        .collect();
+    if bytes.first().map_or(true, |b| b & 0x80 != 0) {
+        bytes.insert(0, 0);
+    }
+    der_encode(DER_INTEGER, &bytes)
+}
+
+/// Splits the first DER element off `bytes`, returning (tag, content, whole element, rest).
+fn der_decode(bytes: &[u8]) -> anyhow::Result<(u8, &[u8], &[u8], &[u8])> {
+    anyhow::ensure!(bytes.len() >= 2, "truncated DER element");
+    let tag = bytes[0];
+    let (len, header) = if bytes[1] < 0x80 {
+        (bytes[1] as usize, 2)
+    } else {
+        let num_bytes = (bytes[1] & 0x7f) as usize;
+        anyhow::ensure!(
+            num_bytes > 0 && num_bytes <= 4 && bytes.len() >= 2 + num_bytes,
+            "unsupported DER length"
+        );
+        let len = bytes[2..2 + num_bytes]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, 2 + num_bytes)
+    };
+    anyhow::ensure!(bytes.len() >= header + len, "truncated DER element");
+    Ok((
+        tag,
+        &bytes[header..header + len],
+        &bytes[..header + len],
+        &bytes[header + len..],
+    ))
+}
+
+/// Encodes a TimeStampReq for a SHA-256 digest, asking the authority to include its certificate.
+pub fn encode_timestamp_request(digest: &[u8; 32], nonce: u64) -> Vec<u8> {
+    let message_imprint = der_encode(
+        DER_SEQUENCE,
+        &[
+            SHA256_ALGORITHM_ID.to_vec(),
+            der_encode(DER_OCTET_STRING, digest),
+        ]
+        .concat(),
+    );
+    der_encode(
+        DER_SEQUENCE,
+        &[
+            der_encode_u64(1),
+            message_imprint,
+            der_encode_u64(nonce),
+            der_encode(DER_BOOLEAN, &[0xff]),
+        ]
+        .concat(),
+    )
+}
+
+/// Extracts the TimeStampToken from a TimeStampResp, failing unless the request was granted.
+pub fn decode_timestamp_response(response: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (tag, content, _, _) = der_decode(response)?;
+    anyhow::ensure!(tag == DER_SEQUENCE, "timestamp response is not a sequence");
+    let (tag, status_info, _, rest) = der_decode(content)?;
+    anyhow::ensure!(tag == DER_SEQUENCE, "malformed PKIStatusInfo");
+    let (tag, status, _, _) = der_decode(status_info)?;
+    anyhow::ensure!(tag == DER_INTEGER, "malformed PKIStatus");
+    // 0 = granted, 1 = grantedWithMods
+    anyhow::ensure!(
+        status == [0] || status == [1],
+        "timestamp request rejected with status {}",
+        hex::encode(status)
+    );
+    let (_, _, token, _) = der_decode(rest)?;
+    Ok(token.to_vec())
+}
+
+/// Requests RFC 3161 timestamp tokens from a time-stamping authority.
+pub struct TimestampAuthority {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl TimestampAuthority {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+    pub async fn timestamp(
+        &self,
+        subject: TimestampSubject,
+        digest: [u8; 32],
+    ) -> anyhow::Result<TimestampRecord> {
+        let nonce: u64 = rand::random();
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/timestamp-query")
+            .body(encode_timestamp_request(&digest, nonce))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let token = decode_timestamp_response(&response)?;
+        // The signed TSTInfo embeds the message imprint; a token for another digest is useless
+        anyhow::ensure!(
+            token.windows(digest.len()).any(|window| window == digest),
+            "timestamp token does not cover the requested digest"
+        );
+        Ok(TimestampRecord {
+            subject,
+            digest: digest.to_vec(),
+            tsa_url: self.url.clone(),
+            token,
+            requested_at: unix_timestamp(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_request_and_response() {
+        let digest = [0xab; 32];
+        let request = encode_timestamp_request(&digest, 0x80);
+        let (tag, content, _, rest) = der_decode(&request).unwrap();
+        assert_eq!(tag, DER_SEQUENCE);
+        assert!(rest.is_empty());
+        let (_, version, _, content) = der_decode(content).unwrap();
+        assert_eq!(version, [1]);
+        let (_, imprint, _, content) = der_decode(content).unwrap();
+        assert_eq!(&imprint[..SHA256_ALGORITHM_ID.len()], SHA256_ALGORITHM_ID);
+        let (_, nonce, _, _) = der_decode(content).unwrap();
+        assert_eq!(nonce, [0x00, 0x80]);
+
+        let token = der_encode(DER_SEQUENCE, &digest);
+        let granted = der_encode(
+            DER_SEQUENCE,
+            &[der_encode(DER_SEQUENCE, &der_encode_u64(0)), token.clone()].concat(),
+        );
+        assert_eq!(decode_timestamp_response(&granted).unwrap(), token);
+
+        let rejected = der_encode(DER_SEQUENCE, &der_encode(DER_SEQUENCE, &der_encode_u64(2)));
+        assert!(decode_timestamp_response(&rejected).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use web3::{
+    contract::{Contract, Options},
+    transports::Http,
+    types::{Address, BlockId, BlockNumber, FilterBuilder, H256, U256},
+    Web3,
+};
+
+use crate::balance::{
+    accounts::{VoterLeaf, MAX_BALANCE_BITS},
+    weight::Weight,
+};
+
+const ERC20_ABI: &str = r#"[
+    {
+        "type": "function",
+        "name": "balanceOf",
+        "stateMutability": "view",
+        "inputs": [{ "name": "owner", "type": "address" }],
+        "outputs": [{ "name": "", "type": "uint256" }]
+    },
+    {
+        "type": "function",
+        "name": "decimals",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [{ "name": "", "type": "uint8" }]
+    }
+]"#;
+
+/// keccak256("Transfer(address,address,uint256)")
+const TRANSFER_TOPIC: [u8; 32] = [
+    0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b, 0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
+    0x95, 0x2b, 0xa7, 0xf1, 0x63, 0xc4, 0xa1, 0x16, 0x28, 0xf5, 0x5a, 0x4d, 0xf5, 0x23, 0xb3, 0xef,
+];
+
+/// Number of blocks requested per `eth_getLogs` call, to stay below provider limits.
+pub(crate) const LOG_SCAN_CHUNK: u64 = 10_000;
+
+/// Which token balances, at which block, a proposal's voting power is taken from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TokenSnapshotRequest {
+    #[schema(value_type = String)]
+    pub token: Address,
+    pub block: u64,
+    /// First block scanned for holders, usually the token deployment block.
+    #[serde(default)]
+    pub from_block: u64,
+    /// Token units per vote are `10^decimals`; read from the token if not set.
+    pub decimals: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TokenHolder {
+    #[schema(value_type = String)]
+    pub address: Address,
+    /// Raw token balance, as a 0x prefixed hex number.
+    #[schema(value_type = String)]
+    pub balance: U256,
+    pub weight: Weight,
+}
+
+/// The holders of a token at a block, ordered by address. The `i`-th holder
+/// votes with the leaf [`VoterLeaf::from_position`]`(i)`, unless the proposal
+/// blinds voter indices, see [`crate::proposal::blinding`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TokenSnapshot {
+    #[schema(value_type = String)]
+    pub token: Address,
+    pub block: u64,
+    pub decimals: u32,
+    pub holders: Vec<TokenHolder>,
+}
+
+impl TokenSnapshot {
+    pub fn voter_balances(&self) -> Vec<Weight> {
+        self.holders.iter().map(|holder| holder.weight).collect()
+    }
+    pub fn voter_leaf(&self, address: &Address) -> Option<VoterLeaf> {
+        self.holders
+            .iter()
+            .position(|holder| holder.address == *address)
+            .map(|position| VoterLeaf::from_position(position as u64))
+    }
+}
+
+/// Converts a raw token balance into whole-token voting weight.
+pub fn balance_to_weight(balance: U256, decimals: u32) -> anyhow::Result<Weight> {
+    let weight = balance / U256::exp10(decimals as usize);
+    ensure!(
+        weight.bits() <= MAX_BALANCE_BITS,
+        "balance {} does not fit into a {} bit voting weight",
+        balance,
+        MAX_BALANCE_BITS
+    );
+    Weight::try_from(weight.as_u64())
+}
+
+pub struct TokenSnapshotter {
+    web3: Web3<Http>,
+}
+
+impl TokenSnapshotter {
+    pub fn new(rpc_url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            web3: Web3::new(Http::new(rpc_url)?),
+        })
+    }
+
+    async fn scan_holders(
+        &self,
+        request: &TokenSnapshotRequest,
+    ) -> anyhow::Result<BTreeSet<Address>> {
+        let mut holders = BTreeSet::new();
+        let mut from = request.from_block;
+        while from <= request.block {
+            let to = (from + LOG_SCAN_CHUNK - 1).min(request.block);
+            let filter = FilterBuilder::default()
+                .address(vec![request.token])
+                .topics(Some(vec![H256(TRANSFER_TOPIC)]), None, None, None)
+                .from_block(BlockNumber::Number(from.into()))
+                .to_block(BlockNumber::Number(to.into()))
+                .build();
+            for log in self.web3.eth().logs(filter).await? {
+                // topics are [Transfer, from, to]; receivers are the candidate holders
+                if let Some(receiver) = log.topics.get(2) {
+                    holders.insert(Address::from_slice(&receiver.as_bytes()[12..]));
+                }
+            }
+            from = to + 1;
+        }
+        Ok(holders)
+    }
+
+    /// Fetches every holder with at least one whole token of voting weight.
+    pub async fn snapshot(&self, request: &TokenSnapshotRequest) -> anyhow::Result<TokenSnapshot> {
+        let contract = Contract::from_json(self.web3.eth(), request.token, ERC20_ABI.as_bytes())?;
+        let at_block = Some(BlockId::Number(BlockNumber::Number(request.block.into())));
+        let decimals = match request.decimals {
+            Some(decimals) => decimals,
+            None => {
+                let decimals: u8 = contract
+                    .query("decimals", (), None, Options::default(), at_block)
+                    .await
+                    .map_err(|err| anyhow!("failed to read token decimals: {}", err))?;
+                decimals as u32
+            }
+        };
+
+        let mut holders = vec![];
+        for address in self.scan_holders(request).await? {
+            let balance: U256 = contract
+                .query("balanceOf", (address,), None, Options::default(), at_block)
+                .await?;
+            let weight = balance_to_weight(balance, decimals)?;
+            if weight > Weight::ZERO {
+                holders.push(TokenHolder {
+                    address,
+                    balance,
+                    weight,
+                });
+            }
+        }
+        Ok(TokenSnapshot {
+            token: request.token,
+            block: request.block,
+            decimals,
+            holders,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_balance_to_weight() -> anyhow::Result<()> {
+        assert_eq!(balance_to_weight(U256::exp10(18) * 5, 18)?, Weight::from(5));
+        assert_eq!(balance_to_weight(U256::exp10(17), 18)?, Weight::ZERO);
+        assert_eq!(
+            balance_to_weight(U256::exp10(30), 18)?,
+            Weight::try_from(10u64.pow(12))?
+        );
+        assert!(balance_to_weight(U256::exp10(40), 18).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use plonky2::{
+    field::{extension::Extendable, types::PrimeField64},
+    hash::hash_types::{HashOut, RichField},
+    iop::{
+        target::Target,
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+};
+use uuid::Uuid;
+
+use crate::{
+    common::hash::merkle::gadgets::merkle_proof::hash_merkle_leaves,
+    nullifier::nullifier_set::proposal_id_to_elements, proof::codec::ProofEnvelope,
+};
+
+/// Identifies an [`AggregateFinalizationCircuit`] by the circuits of the proofs it verifies.
+pub fn aggregate_finalization_circuit_id(inner_circuit_ids: &[String]) -> String {
+    format!("aggregate_finalization:{}", inner_circuit_ids.join(","))
+}
+
+/// Recursively verifies the finalization proofs of several proposals and exposes
+/// a single commitment to their results: the merkle root over one leaf per proof,
+/// `Hash(proposal id, public inputs)`, padded with zero leaves up to a power of two.
+/// See [`compute_cycle_root`](crate::proof::cycle::compute_cycle_root).
+///
+/// The verifier data of every inner circuit is a constant of the circuit, so a
+/// circuit is built per sequence of inner circuit shapes.
+pub struct AggregateFinalizationCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub proofs: Vec<ProofWithPublicInputsTarget<D>>,
+    pub proposal_ids: Vec<[Target; 4]>,
+    pub base_circuit_data: CircuitData<F, C, D>,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    AggregateFinalizationCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn new(inner_circuits: &[&CircuitData<F, C, D>]) -> Self {
+        assert!(!inner_circuits.is_empty(), "nothing to aggregate");
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let mut proofs = vec![];
+        let mut proposal_ids = vec![];
+        let mut leaves = vec![];
+        for inner in inner_circuits {
+            let proof = builder.add_virtual_proof_with_pis(&inner.common);
+            let verifier_data = builder.constant_verifier_data(&inner.verifier_only);
+            builder.verify_proof::<C>(&proof, &verifier_data, &inner.common);
+            let proposal_id = [0; 4].map(|_| builder.add_virtual_target());
+            leaves.push(builder.hash_n_to_hash_no_pad::<C::Hasher>(
+                [proposal_id.to_vec(), proof.public_inputs.clone()].concat(),
+            ));
+            proofs.push(proof);
+            proposal_ids.push(proposal_id);
+        }
+        let zero_leaf = builder.constant_hash(HashOut::ZERO);
+        leaves.resize(leaves.len().next_power_of_two(), zero_leaf);
+        let root = hash_merkle_leaves::<F, D, C::Hasher>(&mut builder, &leaves);
+        builder.register_public_inputs(&root.elements);
+        let base_circuit_data = builder.build::<C>();
+        Self {
+            proofs,
+            proposal_ids,
+            base_circuit_data,
+        }
+    }
+    /// Proves the finalization proofs of `proposal_ids`, in the order of the inner
+    /// circuits the aggregation circuit was built with.
+    pub fn prove(
+        &self,
+        proposal_ids: &[Uuid],
+        proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        anyhow::ensure!(
+            proposal_ids.len() == self.proofs.len() && proofs.len() == self.proofs.len(),
+            "expected {} proofs to aggregate",
+            self.proofs.len()
+        );
+        let mut pw = PartialWitness::<F>::new();
+        for i in 0..self.proofs.len() {
+            pw.set_proof_with_pis_target(&self.proofs[i], &proofs[i]);
+            let elements = proposal_id_to_elements(&proposal_ids[i]);
+            for (target, element) in self.proposal_ids[i].iter().zip(elements) {
+                pw.set_target(*target, F::from_canonical_u64(element.to_canonical_u64()));
+            }
+        }
+        self.base_circuit_data.prove(pw)
+    }
+    /// Proves like [`Self::prove`] and checks the proof before packing it into an envelope.
+    pub fn prove_envelope(
+        &self,
+        circuit_id: &str,
+        proposal_ids: &[Uuid],
+        proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) -> anyhow::Result<ProofEnvelope> {
+        let proof = self.prove(proposal_ids, proofs)?;
+        let envelope = ProofEnvelope::new(circuit_id, &self.base_circuit_data, &proof);
+        self.base_circuit_data.verify(proof)?;
+        Ok(envelope)
+    }
+}
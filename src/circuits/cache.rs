@@ -0,0 +1,233 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    plonk::{
+        circuit_data::CircuitData,
+        config::{AlgebraicHasher, GenericConfig},
+    },
+};
+
+use super::{
+    aggregate::{aggregate_finalization_circuit_id, AggregateFinalizationCircuit},
+    deposit::{DepositTransferCircuit, DEPOSIT_TRANSFER_CIRCUIT_ID},
+    payout::{PayoutCircuit, PAYOUT_CIRCUIT_ID},
+    registry::{CircuitRecord, CircuitRegistry},
+    shard_root::{parse_finalization_circuit_id, shard_root_circuit_id, ShardRootCircuit},
+    token_lock::{TokenLockCircuit, TOKEN_LOCK_CIRCUIT_ID},
+    update_balance::{update_balance_circuit_id, UpdateBalanceCircuit, UpdateBalanceShape},
+};
+
+/// The circuit a finalization proof is produced with: an update balance circuit,
+/// or on sharded proposals the shard root circuit over their shard proofs.
+pub enum FinalizationCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    Unsharded(Arc<UpdateBalanceCircuit<F, C, D>>),
+    Sharded(Arc<ShardRootCircuit<F, C, D>>),
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    FinalizationCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        match self {
+            FinalizationCircuit::Unsharded(circuit) => &circuit.base_circuit_data,
+            FinalizationCircuit::Sharded(circuit) => &circuit.base_circuit_data,
+        }
+    }
+}
+
+/// Keeps built update balance circuits around, keyed by their shape.
+/// Building a circuit dominates the cost of small proofs, and padding the updates to
+/// power-of-two sizes keeps the number of distinct circuits small.
+pub struct CircuitCache<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    circuits: HashMap<UpdateBalanceShape, Arc<UpdateBalanceCircuit<F, C, D>>>,
+    /// Aggregation circuits, keyed by the shapes of the update balance circuits they verify.
+    aggregates: HashMap<Vec<UpdateBalanceShape>, Arc<AggregateFinalizationCircuit<F, C, D>>>,
+    /// Shard root circuits, keyed by the shape of the shards and their number.
+    shard_roots: HashMap<(UpdateBalanceShape, usize), Arc<ShardRootCircuit<F, C, D>>>,
+    deposit: Option<Arc<DepositTransferCircuit<F, C, D>>>,
+    payout: Option<Arc<PayoutCircuit<F, C, D>>>,
+    token_lock: Option<Arc<TokenLockCircuit<F, C, D>>>,
+    /// Verifier data of every circuit above, by circuit id.
+    registry: CircuitRegistry,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    CircuitCache<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn new() -> Self {
+        Self {
+            circuits: HashMap::new(),
+            aggregates: HashMap::new(),
+            shard_roots: HashMap::new(),
+            deposit: None,
+            payout: None,
+            token_lock: None,
+            registry: CircuitRegistry::default(),
+        }
+    }
+
+    pub fn get_or_build(
+        &mut self,
+        shape: UpdateBalanceShape,
+    ) -> Arc<UpdateBalanceCircuit<F, C, D>> {
+        if let Some(circuit) = self.circuits.get(&shape) {
+            return circuit.clone();
+        }
+        let circuit = Arc::new(UpdateBalanceCircuit::new(shape));
+        self.registry.register(
+            &update_balance_circuit_id(&shape),
+            &circuit.base_circuit_data,
+        );
+        self.circuits.insert(shape, circuit.clone());
+        circuit
+    }
+
+    /// Returns the aggregation circuit over proofs of the update balance circuits
+    /// of the given shapes, in order.
+    pub fn get_or_build_aggregate(
+        &mut self,
+        shapes: &[UpdateBalanceShape],
+    ) -> Arc<AggregateFinalizationCircuit<F, C, D>> {
+        if let Some(circuit) = self.aggregates.get(shapes) {
+            return circuit.clone();
+        }
+        let inner: Vec<_> = shapes
+            .iter()
+            .map(|shape| self.get_or_build(*shape))
+            .collect();
+        let inner_data: Vec<_> = inner
+            .iter()
+            .map(|circuit| &circuit.base_circuit_data)
+            .collect();
+        let circuit = Arc::new(AggregateFinalizationCircuit::new(&inner_data));
+        let inner_ids: Vec<_> = shapes.iter().map(update_balance_circuit_id).collect();
+        self.registry.register(
+            &aggregate_finalization_circuit_id(&inner_ids),
+            &circuit.base_circuit_data,
+        );
+        self.aggregates.insert(shapes.to_vec(), circuit.clone());
+        circuit
+    }
+
+    /// Returns the circuit combining `shard_count` proofs of the update balance
+    /// circuit of `shard_shape`.
+    pub fn get_or_build_shard_root(
+        &mut self,
+        shard_shape: UpdateBalanceShape,
+        shard_count: usize,
+    ) -> Arc<ShardRootCircuit<F, C, D>> {
+        if let Some(circuit) = self.shard_roots.get(&(shard_shape, shard_count)) {
+            return circuit.clone();
+        }
+        let shard_circuit = self.get_or_build(shard_shape);
+        let circuit = Arc::new(ShardRootCircuit::new(&shard_circuit, shard_count));
+        self.registry.register(
+            &shard_root_circuit_id(&shard_shape, shard_count),
+            &circuit.base_circuit_data,
+        );
+        self.shard_roots
+            .insert((shard_shape, shard_count), circuit.clone());
+        circuit
+    }
+
+    /// Returns the circuit finalization proofs of circuit `circuit_id` are
+    /// produced with, see [`parse_finalization_circuit_id`].
+    pub fn get_or_build_finalization(
+        &mut self,
+        circuit_id: &str,
+    ) -> anyhow::Result<FinalizationCircuit<F, C, D>> {
+        Ok(match parse_finalization_circuit_id(circuit_id)? {
+            (shape, None) => FinalizationCircuit::Unsharded(self.get_or_build(shape)),
+            (shard_shape, Some(shard_count)) => {
+                FinalizationCircuit::Sharded(self.get_or_build_shard_root(shard_shape, shard_count))
+            }
+        })
+    }
+
+    /// Returns the circuit proving transfers of deposits in the treasury tree.
+    pub fn get_or_build_deposit(&mut self) -> Arc<DepositTransferCircuit<F, C, D>> {
+        if let Some(circuit) = &self.deposit {
+            return circuit.clone();
+        }
+        let circuit = Arc::new(DepositTransferCircuit::new());
+        self.registry
+            .register(DEPOSIT_TRANSFER_CIRCUIT_ID, &circuit.base_circuit_data);
+        self.deposit = Some(circuit.clone());
+        circuit
+    }
+
+    /// Returns the circuit proving payouts out of the funds of DAOs.
+    pub fn get_or_build_payout(&mut self) -> Arc<PayoutCircuit<F, C, D>> {
+        if let Some(circuit) = &self.payout {
+            return circuit.clone();
+        }
+        let circuit = Arc::new(PayoutCircuit::new());
+        self.registry
+            .register(PAYOUT_CIRCUIT_ID, &circuit.base_circuit_data);
+        self.payout = Some(circuit.clone());
+        circuit
+    }
+
+    /// Returns the circuit proving locks and releases of tokens in the token tree.
+    pub fn get_or_build_token_lock(&mut self) -> Arc<TokenLockCircuit<F, C, D>> {
+        if let Some(circuit) = &self.token_lock {
+            return circuit.clone();
+        }
+        let circuit = Arc::new(TokenLockCircuit::new());
+        self.registry
+            .register(TOKEN_LOCK_CIRCUIT_ID, &circuit.base_circuit_data);
+        self.token_lock = Some(circuit.clone());
+        circuit
+    }
+
+    /// Whether the circuit `circuit_id` was built so far.
+    pub fn is_built(&self, circuit_id: &str) -> bool {
+        self.registry.get(circuit_id).is_some()
+    }
+
+    /// Shapes of the update balance circuits built so far.
+    pub fn shapes(&self) -> Vec<UpdateBalanceShape> {
+        self.circuits.keys().copied().collect()
+    }
+
+    /// Records of every circuit built so far, in circuit id order.
+    pub fn records(&self) -> Vec<CircuitRecord> {
+        self.registry.records()
+    }
+
+    /// See [`CircuitRegistry::record_proving_time`].
+    pub fn record_proving_time(&mut self, circuit_id: &str, elapsed: Duration) {
+        self.registry.record_proving_time(circuit_id, elapsed)
+    }
+
+    /// See [`CircuitRegistry::estimate_proving_time`].
+    pub fn estimate_proving_time(&self, circuit_id: &str) -> Option<Duration> {
+        self.registry.estimate_proving_time(circuit_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.circuits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.circuits.is_empty()
+    }
+}
@@ -0,0 +1,219 @@
+//! Conviction voting: the weight a vote adds to its tally grows with the time it
+//! stays cast before the voting deadline.
+//!
+//! A vote cast `t` seconds before the deadline of a proposal whose rules count
+//! conviction in steps of `s` seconds is multiplied by `1 + t / s`. The updates
+//! of such a proposal carry a [`ConvictionStamp`] recording when they were made,
+//! and its update balance circuit checks the multiplication against it with a
+//! [`ConvictionGadget`] per update.
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::{target::Target, witness::WitnessWrite},
+    plonk::circuit_builder::CircuitBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    balance::accounts::MAX_BALANCE_BITS, common::u32::multiple_comparison::list_le_circuit,
+};
+
+/// Bits the number of whole steps before the deadline is range checked to.
+pub const CONVICTION_QUOTIENT_BITS: usize = 5;
+/// Largest multiplier a vote can get, for one cast a full voting period early.
+pub const MAX_CONVICTION_MULTIPLIER: u64 = 1 << CONVICTION_QUOTIENT_BITS;
+/// Widest balances of proposals with conviction voting: a balance times the
+/// largest multiplier stays below 2^63, so the product in the circuit cannot
+/// wrap around the field.
+pub const MAX_CONVICTION_BALANCE_BITS: usize = MAX_BALANCE_BITS - CONVICTION_QUOTIENT_BITS;
+/// Bits timestamps and step lengths are range checked to, in seconds.
+pub const TIMESTAMP_BITS: usize = 40;
+
+/// When an update of a proposal with conviction voting was recorded, with the
+/// deadline and step length its multiplier derives from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConvictionStamp {
+    pub voted_at: u64,
+    pub deadline: u64,
+    pub step_secs: u64,
+}
+
+impl ConvictionStamp {
+    fn steps_before_deadline(&self) -> (u64, u64) {
+        let remaining = self.deadline.saturating_sub(self.voted_at);
+        match (
+            remaining.checked_div(self.step_secs),
+            remaining.checked_rem(self.step_secs),
+        ) {
+            (Some(quotient), Some(remainder)) => (quotient, remainder),
+            _ => (0, remaining),
+        }
+    }
+    /// One more than the number of whole steps between the vote and the deadline.
+    pub fn multiplier(&self) -> u64 {
+        1 + self.steps_before_deadline().0
+    }
+}
+
+/// Deadline and step length shared by the updates of a circuit, exposed as
+/// public inputs so verifiers see which rules the tallies were weighted by.
+pub struct ConvictionTargets {
+    pub deadline: Target,
+    pub step_secs: Target,
+}
+
+impl ConvictionTargets {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+    ) -> Self {
+        Self {
+            deadline: builder.add_virtual_target(),
+            step_secs: builder.add_virtual_target(),
+        }
+    }
+    pub fn set_witness<F: RichField>(
+        &self,
+        witness: &mut impl WitnessWrite<F>,
+        stamp: &ConvictionStamp,
+    ) {
+        witness.set_target(self.deadline, F::from_canonical_u64(stamp.deadline));
+        witness.set_target(self.step_secs, F::from_canonical_u64(stamp.step_secs));
+    }
+}
+
+/// Derives the multiplier of an update from the time it was recorded, the
+/// `voted_at` target the update writes to the clock of its tally slot, so a
+/// vote cannot be stamped earlier than an update already counted there.
+///
+/// The time left until the deadline is split into `quotient` whole steps and a
+/// `remainder` shorter than a step. Timestamps and the remainder are range
+/// checked to [`TIMESTAMP_BITS`] bits and the quotient to
+/// [`CONVICTION_QUOTIENT_BITS`], so none of the arithmetic wraps around the field.
+pub struct ConvictionGadget {
+    pub voted_at: Target,
+    pub quotient: Target,
+    pub remainder: Target,
+    /// `quotient + 1`, the weight each unit of a vote adds to its tally.
+    pub multiplier: Target,
+}
+
+impl ConvictionGadget {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+        params: &ConvictionTargets,
+        voted_at: Target,
+    ) -> Self {
+        let quotient = builder.add_virtual_target();
+        let remainder = builder.add_virtual_target();
+        let true_target = builder.one();
+
+        let before_deadline = list_le_circuit(
+            builder,
+            vec![voted_at],
+            vec![params.deadline],
+            TIMESTAMP_BITS,
+        );
+        builder.connect(before_deadline.target, true_target);
+        let remaining = builder.sub(params.deadline, voted_at);
+
+        builder.range_check(quotient, CONVICTION_QUOTIENT_BITS);
+        let one = builder.one();
+        let remainder_plus_one = builder.add(remainder, one);
+        let below_step = list_le_circuit(
+            builder,
+            vec![remainder_plus_one],
+            vec![params.step_secs],
+            TIMESTAMP_BITS,
+        );
+        builder.connect(below_step.target, true_target);
+        let whole_steps = builder.mul_add(quotient, params.step_secs, remainder);
+        builder.connect(whole_steps, remaining);
+
+        let multiplier = builder.add(quotient, one);
+        Self {
+            voted_at,
+            quotient,
+            remainder,
+            multiplier,
+        }
+    }
+    pub fn set_witness<F: RichField>(
+        &self,
+        witness: &mut impl WitnessWrite<F>,
+        stamp: &ConvictionStamp,
+    ) {
+        let (quotient, remainder) = stamp.steps_before_deadline();
+        witness.set_target(self.quotient, F::from_canonical_u64(quotient));
+        witness.set_target(self.remainder, F::from_canonical_u64(remainder));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConvictionStamp;
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            test_fixtures::{linear_shape, prove_fixture, proves},
+            update_balance::{
+                pad_updates, UpdateBalanceCircuit, UpdateBalanceShape, YES_VOTES_PUBLIC_INPUT,
+            },
+        },
+    };
+
+    #[test]
+    fn test_earlier_votes_weigh_more() -> anyhow::Result<()> {
+        let stamp = |voted_at| ConvictionStamp {
+            voted_at,
+            deadline: 100,
+            step_secs: 30,
+        };
+        assert_eq!(stamp(0).multiplier(), 4);
+        assert_eq!(stamp(70).multiplier(), 2);
+        assert_eq!(stamp(71).multiplier(), 1);
+
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(2); 3]);
+        let vote = |position| BalanceTx::Vote {
+            voter: VoterLeaf::from_position(position),
+            slot: TallySlot::YES,
+            amount: WeightDelta::from(2),
+        };
+        let mut updates = vec![];
+        for (position, voted_at) in [(0, 10), (1, 80)] {
+            updates.push(storage.process_stamped_tx(
+                vote(position),
+                Some(stamp(voted_at)),
+                None,
+            )?);
+        }
+        // The tally took a vote at 80, the next one cannot be stamped earlier
+        assert!(storage
+            .process_stamped_tx(vote(2), Some(stamp(50)), None)
+            .is_err());
+        assert_eq!(storage.get_tally(TallySlot::YES)?, Weight::from(2 * 4 + 2));
+        assert_eq!(
+            updates[0].check_weights(storage.balance_bits())?,
+            WeightDelta::from(2)
+        );
+
+        let updates = pad_updates(&updates, 8);
+        let circuit = UpdateBalanceCircuit::new(UpdateBalanceShape {
+            conviction: true,
+            ..linear_shape(updates.len(), storage.balance_bits())
+        });
+        let envelope = prove_fixture(&circuit, &updates, &storage)?;
+        assert_eq!(envelope.public_inputs[YES_VOTES_PUBLIC_INPUT], 10);
+
+        // A vote stamped earlier than the tally slot recorded it does not prove its
+        // multiplier
+        let mut backdated = updates;
+        backdated[1].conviction = Some(stamp(10));
+        assert!(!proves(&circuit, &backdated, &storage));
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! Deadline binding in the update balance circuit: every update is constrained
+//! to have been recorded within the voting window of its proposal.
+//!
+//! The window opens when the proposal is created and closes at its deadline,
+//! both exposed as public inputs, so the proof itself attests that no update
+//! recorded after the deadline was counted. The updates of such a proposal carry
+//! a [`WindowStamp`], like the [`super::conviction::ConvictionStamp`] of
+//! proposals with conviction voting, and the time they were recorded at is
+//! written to the tally slot they vote on or revoke from, see
+//! [`crate::balance::accounts::TALLY_CLOCK_ELEMENT`]. Updates are proven in time
+//! order and a slot's clock never goes back, so the stamps are committed to by
+//! the roots the proof chains rather than left to the prover. Times are unix
+//! seconds.
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::{target::Target, witness::WitnessWrite},
+    plonk::circuit_builder::CircuitBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::common::u32::multiple_comparison::list_le_circuit;
+
+use super::conviction::TIMESTAMP_BITS;
+
+/// When an update of a proposal with a deadline was recorded, with the voting
+/// window of the proposal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowStamp {
+    pub voted_at: u64,
+    pub opens_at: u64,
+    pub deadline: u64,
+}
+
+impl WindowStamp {
+    /// Whether the update was recorded from the opening of the window and
+    /// before its deadline.
+    pub fn is_within(&self) -> bool {
+        self.opens_at <= self.voted_at && self.voted_at < self.deadline
+    }
+}
+
+/// Voting window shared by the updates of a circuit, exposed as public inputs.
+pub struct WindowTargets {
+    pub opens_at: Target,
+    pub deadline: Target,
+}
+
+impl WindowTargets {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+    ) -> Self {
+        Self {
+            opens_at: builder.add_virtual_target(),
+            deadline: builder.add_virtual_target(),
+        }
+    }
+    pub fn set_witness<F: RichField>(
+        &self,
+        witness: &mut impl WitnessWrite<F>,
+        stamp: &WindowStamp,
+    ) {
+        witness.set_target(self.opens_at, F::from_canonical_u64(stamp.opens_at));
+        witness.set_target(self.deadline, F::from_canonical_u64(stamp.deadline));
+    }
+}
+
+/// Checks that an update recorded at `voted_at` was recorded within the window.
+/// Times are range checked to [`TIMESTAMP_BITS`] bits, so the comparisons do not
+/// wrap around the field.
+pub struct WindowGadget {
+    pub voted_at: Target,
+}
+
+impl WindowGadget {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+        params: &WindowTargets,
+        voted_at: Target,
+    ) -> Self {
+        let true_target = builder.one();
+
+        let after_opening = list_le_circuit(
+            builder,
+            vec![params.opens_at],
+            vec![voted_at],
+            TIMESTAMP_BITS,
+        );
+        builder.connect(after_opening.target, true_target);
+        let voted_at_plus_one = builder.add(voted_at, true_target);
+        let before_deadline = list_le_circuit(
+            builder,
+            vec![voted_at_plus_one],
+            vec![params.deadline],
+            TIMESTAMP_BITS,
+        );
+        builder.connect(before_deadline.target, true_target);
+        Self { voted_at }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WindowStamp;
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            test_fixtures::{linear_shape, prove_fixture, proves},
+            update_balance::{
+                pad_updates, parse_update_balance_circuit_id, voting_window_public_inputs,
+                UpdateBalanceCircuit, UpdateBalanceShape,
+            },
+        },
+    };
+
+    #[test]
+    fn test_updates_after_the_deadline_are_not_proven() -> anyhow::Result<()> {
+        let stamp = |voted_at| WindowStamp {
+            voted_at,
+            opens_at: 100,
+            deadline: 200,
+        };
+        assert!(stamp(100).is_within());
+        assert!(!stamp(99).is_within());
+        assert!(!stamp(200).is_within());
+
+        let vote = |position| BalanceTx::Vote {
+            voter: VoterLeaf::from_position(position),
+            slot: TallySlot::YES,
+            amount: WeightDelta::from(2),
+        };
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(2); 2]);
+        let mut update = storage.process_stamped_tx(vote(0), None, Some(stamp(150)))?;
+        // The slot keeps the time of the vote, and takes no vote recorded before it
+        assert!(storage
+            .process_stamped_tx(vote(1), None, Some(stamp(140)))
+            .is_err());
+        let updates = pad_updates(&[update.clone()], 8);
+
+        let shape = UpdateBalanceShape {
+            deadline: true,
+            ..linear_shape(updates.len(), storage.balance_bits())
+        };
+        let circuit = UpdateBalanceCircuit::new(shape);
+        let envelope = prove_fixture(&circuit, &updates, &storage)?;
+        assert_eq!(
+            parse_update_balance_circuit_id(&envelope.circuit_id)?,
+            shape
+        );
+        assert_eq!(
+            envelope.public_inputs[voting_window_public_inputs(&shape).unwrap()],
+            [100, 200]
+        );
+
+        // A vote stamped at the deadline is not counted
+        update.window = Some(stamp(200));
+        assert!(update.check_within_window().is_err());
+        let late = pad_updates(&[update.clone()], 8);
+        assert!(!proves(&circuit, &late, &storage));
+
+        // Nor is one restamped within the window after it was recorded, as the
+        // clock of its slot still holds the time it was recorded at
+        update.window = Some(stamp(120));
+        update.check_within_window()?;
+        assert!(!proves(&circuit, &pad_updates(&[update], 8), &storage));
+        Ok(())
+    }
+}
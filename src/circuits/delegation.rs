@@ -0,0 +1,293 @@
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::{
+        target::{BoolTarget, Target},
+        witness::WitnessWrite,
+    },
+    plonk::circuit_builder::CircuitBuilder,
+};
+
+use crate::{
+    balance::accounts::{DELEGATION_FLAG_ELEMENT, TALLY_CLOCK_ELEMENT, TALLY_SLOT_COUNT},
+    common::{
+        hash::merkle::gadgets::delta_merkle_proof::DeltaMerkleProofGadget,
+        u32::multiple_comparison::list_le_circuit,
+    },
+};
+
+use super::{
+    conviction::TIMESTAMP_BITS,
+    update_balance::{BalanceUpdate, UpdateKind},
+};
+
+// Tally slots keep their clock in the element voter leaves keep the delegation flag in
+const _: () = assert!(TALLY_CLOCK_ELEMENT == DELEGATION_FLAG_ELEMENT);
+
+// index * (index - 1) vanishes exactly on the two tally slots; with indices below
+// 2^32 the product cannot wrap around the Goldilocks modulus.
+const _: () = assert!(TALLY_SLOT_COUNT == 2);
+
+fn tally_slot_product<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    index: Target,
+) -> Target {
+    let one = builder.one();
+    let index_minus_one = builder.sub(index, one);
+    builder.mul(index, index_minus_one)
+}
+
+fn tally_slot_product_inverse<F: RichField>(index: F) -> F {
+    (index * (index - F::ONE)).try_inverse().unwrap_or(F::ZERO)
+}
+
+/// Constrains `x` to be non-zero when `condition` is set, given its inverse as witness.
+fn assert_nonzero_if<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    condition: BoolTarget,
+    x: Target,
+    inverse: Target,
+) {
+    let one = builder.one();
+    let product = builder.mul(x, inverse);
+    let difference = builder.sub(product, one);
+    let gated = builder.mul(condition.target, difference);
+    builder.assert_zero(gated);
+}
+
+/// Tells votes, delegations, revocations and undelegations apart inside an
+/// update balance proof.
+///
+/// A vote moves weight from a voter leaf into a tally slot. A delegation moves it
+/// into another voter leaf and sets the delegation flag of the sender's leaf, which
+/// has to be unset before, so every voter delegates at most once at a time. A
+/// revocation moves weight from a tally slot back into a voter leaf. An
+/// undelegation moves weight from a voter leaf into the leaf of a voter who
+/// delegated, clearing its delegation flag. All other leaf elements are left
+/// unchanged by every kind.
+///
+/// The circuit does not tie a revocation to the vote it revokes, nor an
+/// undelegation to the delegation it reverts: like every update, they only
+/// conserve weight. Moving back no more than was cast or delegated is up to the
+/// server recording the updates.
+///
+/// Given the time `voted_at` the update was recorded at, a vote sets the clock of
+/// the tally slot it goes to and a revocation that of the slot it takes weight
+/// from, see [`TALLY_CLOCK_ELEMENT`]. The clock only moves forward, so the times
+/// of the updates are committed to by the roots and cannot be rewritten without
+/// rewriting the tree.
+pub struct DelegationGadget {
+    pub is_delegation: BoolTarget,
+    pub is_revocation: BoolTarget,
+    pub is_undelegation: BoolTarget,
+    pub sender_index_inverse: Target,
+    pub receiver_index_inverse: Target,
+}
+
+impl DelegationGadget {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+        sender_update: &DeltaMerkleProofGadget,
+        receiver_update: &DeltaMerkleProofGadget,
+        is_noop: BoolTarget,
+        voted_at: Option<Target>,
+    ) -> Self {
+        let is_delegation = builder.add_virtual_bool_target_safe();
+        let is_revocation = builder.add_virtual_bool_target_safe();
+        let is_undelegation = builder.add_virtual_bool_target_safe();
+        let sender_index_inverse = builder.add_virtual_target();
+        let receiver_index_inverse = builder.add_virtual_target();
+
+        // Revocations and undelegations are updates of their own kinds
+        for kind in [is_delegation, is_noop] {
+            let revoked_kind = builder.mul(is_revocation.target, kind.target);
+            builder.assert_zero(revoked_kind);
+        }
+        for kind in [is_delegation, is_noop, is_revocation] {
+            let undelegated_kind = builder.mul(is_undelegation.target, kind.target);
+            builder.assert_zero(undelegated_kind);
+        }
+
+        // Weight is sent from voter leaves, except by revocations, which send it from a tally slot
+        let is_update = builder.not(is_noop);
+        let sends_from_voter =
+            BoolTarget::new_unsafe(builder.sub(is_update.target, is_revocation.target));
+        let sender_product = tally_slot_product(builder, sender_update.index);
+        assert_nonzero_if(
+            builder,
+            sends_from_voter,
+            sender_product,
+            sender_index_inverse,
+        );
+        let revoked_from_voter = builder.mul(is_revocation.target, sender_product);
+        builder.assert_zero(revoked_from_voter);
+
+        // Votes go to a tally slot, every other kind to a voter leaf
+        let receiver_product = tally_slot_product(builder, receiver_update.index);
+        let moves_back = builder.add(is_revocation.target, is_undelegation.target);
+        let sends_to_voter = BoolTarget::new_unsafe(builder.add(is_delegation.target, moves_back));
+        let is_vote = builder.not(sends_to_voter);
+        let vote_receiver = builder.mul(is_vote.target, receiver_product);
+        builder.assert_zero(vote_receiver);
+        assert_nonzero_if(
+            builder,
+            sends_to_voter,
+            receiver_product,
+            receiver_index_inverse,
+        );
+
+        let old_flag = sender_update.old_value.elements[DELEGATION_FLAG_ELEMENT];
+        let new_flag = sender_update.new_value.elements[DELEGATION_FLAG_ELEMENT];
+        let mut flagged = builder.add(old_flag, is_delegation.target);
+        let delegated_twice = builder.mul(is_delegation.target, old_flag);
+        builder.assert_zero(delegated_twice);
+
+        // Only the leaf of a voter who delegated takes an undelegation
+        let old_receiver_flag = receiver_update.old_value.elements[DELEGATION_FLAG_ELEMENT];
+        let new_receiver_flag = receiver_update.new_value.elements[DELEGATION_FLAG_ELEMENT];
+        let mut cleared = builder.sub(old_receiver_flag, is_undelegation.target);
+        let one = builder.one();
+        let not_delegated = builder.sub(one, old_receiver_flag);
+        let undelegated_without_delegation = builder.mul(is_undelegation.target, not_delegated);
+        builder.assert_zero(undelegated_without_delegation);
+
+        // Votes move the clock of the slot they go to and revocations that of the
+        // slot they take from, never back
+        if let Some(voted_at) = voted_at {
+            let is_counted_vote = builder.and(is_vote, is_update);
+            let sender_advance = builder.sub(voted_at, old_flag);
+            flagged = builder.mul_add(is_revocation.target, sender_advance, flagged);
+            let receiver_advance = builder.sub(voted_at, old_receiver_flag);
+            cleared = builder.mul_add(is_counted_vote.target, receiver_advance, cleared);
+
+            let old_clock = builder.select(is_revocation, old_flag, old_receiver_flag);
+            let not_later =
+                list_le_circuit(builder, vec![old_clock], vec![voted_at], TIMESTAMP_BITS);
+            let clocked = builder.or(is_revocation, is_counted_vote);
+            let unclocked = builder.not(clocked);
+            let in_order = builder.or(not_later, unclocked);
+            builder.connect(in_order.target, one);
+        }
+        builder.connect(new_flag, flagged);
+        builder.connect(new_receiver_flag, cleared);
+
+        for i in 1..4 {
+            if i != DELEGATION_FLAG_ELEMENT {
+                builder.connect(
+                    sender_update.new_value.elements[i],
+                    sender_update.old_value.elements[i],
+                );
+            }
+            if i != DELEGATION_FLAG_ELEMENT {
+                builder.connect(
+                    receiver_update.new_value.elements[i],
+                    receiver_update.old_value.elements[i],
+                );
+            }
+        }
+
+        Self {
+            is_delegation,
+            is_revocation,
+            is_undelegation,
+            sender_index_inverse,
+            receiver_index_inverse,
+        }
+    }
+    pub fn set_witness<F: RichField>(
+        &self,
+        witness: &mut impl WitnessWrite<F>,
+        input: &BalanceUpdate<F>,
+    ) {
+        witness.set_bool_target(self.is_delegation, input.kind == UpdateKind::Delegation);
+        witness.set_bool_target(self.is_revocation, input.kind == UpdateKind::Revocation);
+        witness.set_bool_target(self.is_undelegation, input.kind == UpdateKind::Undelegation);
+        witness.set_target(
+            self.sender_index_inverse,
+            tally_slot_product_inverse(input.sender_update.index),
+        );
+        witness.set_target(
+            self.receiver_index_inverse,
+            tally_slot_product_inverse(input.receiver_update.index),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            test_fixtures::{linear_shape, prove_fixture, proves},
+            update_balance::{UpdateBalanceCircuit, UpdateKind},
+        },
+    };
+
+    #[test]
+    fn test_delegation_cannot_pass_as_vote() -> anyhow::Result<()> {
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(1); 4]);
+        let updates = storage.process_txs(vec![
+            BalanceTx::Delegate {
+                voter: VoterLeaf::from_position(0),
+                delegate: VoterLeaf::from_position(1),
+                amount: WeightDelta::from(1),
+            },
+            BalanceTx::Vote {
+                voter: VoterLeaf::from_position(1),
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(2),
+            },
+        ])?;
+        assert!(storage.has_delegated(VoterLeaf::from_position(0))?);
+        let circuit = UpdateBalanceCircuit::new(linear_shape(2, storage.balance_bits()));
+        prove_fixture(&circuit, &updates, &storage)?;
+
+        let mut disguised = updates.clone();
+        disguised[0].kind = UpdateKind::Vote;
+        assert!(!proves(&circuit, &disguised, &storage));
+        Ok(())
+    }
+
+    #[test]
+    fn test_undelegation_clears_the_delegation() -> anyhow::Result<()> {
+        let voter = VoterLeaf::from_position(0);
+        let delegate = VoterLeaf::from_position(1);
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(1); 4]);
+        let updates = storage.process_txs(vec![
+            BalanceTx::Delegate {
+                voter,
+                delegate,
+                amount: WeightDelta::from(1),
+            },
+            BalanceTx::Undelegate {
+                voter,
+                delegate,
+                amount: WeightDelta::from(1),
+            },
+        ])?;
+        assert_eq!(updates[1].kind, UpdateKind::Undelegation);
+        assert!(!storage.has_delegated(voter)?);
+        assert_eq!(storage.get_balance(voter)?, Weight::from(1));
+        // Only a voter who delegated takes an undelegation
+        assert!(storage
+            .process_tx(BalanceTx::Undelegate {
+                voter,
+                delegate,
+                amount: WeightDelta::from(1),
+            })
+            .is_err());
+
+        let circuit = UpdateBalanceCircuit::new(linear_shape(2, storage.balance_bits()));
+        prove_fixture(&circuit, &updates, &storage)?;
+
+        // A delegation does not pass as an undelegation to a voter who never delegated
+        let mut disguised = updates.clone();
+        disguised[0].kind = UpdateKind::Undelegation;
+        assert!(!proves(&circuit, &disguised, &storage));
+        Ok(())
+    }
+}
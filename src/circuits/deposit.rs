@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputs,
+    },
+};
+
+use crate::{
+    balance::treasury::{TREASURY_BALANCE_BITS, TREASURY_TREE_HEIGHT},
+    common::hash::merkle::gadgets::delta_merkle_proof::DeltaMerkleProofGadget,
+    proof::codec::ProofEnvelope,
+};
+
+use super::{
+    prover::InvalidWitness,
+    update_balance::{connect_transfer, BalanceUpdate},
+};
+
+/// Identifies the [`DepositTransferCircuit`] in a [`ProofEnvelope`]. There is
+/// only one, as there is only one treasury tree.
+pub const DEPOSIT_TRANSFER_CIRCUIT_ID: &str = "deposit_transfer";
+
+// Layout of the public inputs of a [`DepositTransferCircuit`] proof.
+pub const DEPOSIT_OLD_ROOT_PUBLIC_INPUTS: Range<usize> = 0..4;
+pub const DEPOSIT_NEW_ROOT_PUBLIC_INPUTS: Range<usize> = 4..8;
+pub const DEPOSIT_SENDER_PUBLIC_INPUT: usize = 8;
+pub const DEPOSIT_RECEIVER_PUBLIC_INPUT: usize = 9;
+pub const DEPOSIT_AMOUNT_PUBLIC_INPUT: usize = 10;
+
+/// Proves one transfer between two leaves of the
+/// [treasury tree](crate::balance::treasury), such as a deposit being locked,
+/// refunded or slashed. The leaves are public inputs, so a verifier can tell
+/// which of those the transfer was from the layout of the tree.
+pub struct DepositTransferCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub sender_update: DeltaMerkleProofGadget,
+    pub receiver_update: DeltaMerkleProofGadget,
+    pub base_circuit_data: CircuitData<F, C, D>,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    DepositTransferCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn new() -> Self {
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let tree_height = TREASURY_TREE_HEIGHT as usize;
+        let sender_update =
+            DeltaMerkleProofGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height);
+        let receiver_update =
+            DeltaMerkleProofGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height);
+        let amount = connect_transfer(
+            &mut builder,
+            &sender_update,
+            &receiver_update,
+            TREASURY_BALANCE_BITS,
+        );
+        builder.register_public_inputs(&sender_update.old_root.elements);
+        builder.register_public_inputs(&receiver_update.new_root.elements);
+        builder.register_public_input(sender_update.index);
+        builder.register_public_input(receiver_update.index);
+        builder.register_public_input(amount);
+        let base_circuit_data = builder.build::<C>();
+        Self {
+            sender_update,
+            receiver_update,
+            base_circuit_data,
+        }
+    }
+    pub fn prove(
+        &self,
+        transfer: &BalanceUpdate<F>,
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        // Fails here rather than with an unsatisfiable witness inside plonky2
+        transfer
+            .check_weights(TREASURY_BALANCE_BITS)
+            .map_err(InvalidWitness)?;
+        let mut pw = PartialWitness::<F>::new();
+        self.sender_update
+            .set_witness_proof(&mut pw, &transfer.sender_update);
+        self.receiver_update
+            .set_witness_proof(&mut pw, &transfer.receiver_update);
+        self.base_circuit_data.prove(pw)
+    }
+    /// Proves `transfer` like [`Self::prove`] and checks the proof before packing
+    /// it into an envelope.
+    pub fn prove_envelope(&self, transfer: &BalanceUpdate<F>) -> anyhow::Result<ProofEnvelope> {
+        let proof = self.prove(transfer)?;
+        let envelope =
+            ProofEnvelope::new(DEPOSIT_TRANSFER_CIRCUIT_ID, &self.base_circuit_data, &proof);
+        self.base_circuit_data.verify(proof)?;
+        Ok(envelope)
+    }
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize> Default
+    for DepositTransferCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+        plonk::config::PoseidonGoldilocksConfig,
+    };
+
+    use super::{
+        DepositTransferCircuit, DEPOSIT_AMOUNT_PUBLIC_INPUT, DEPOSIT_NEW_ROOT_PUBLIC_INPUTS,
+        DEPOSIT_RECEIVER_PUBLIC_INPUT, DEPOSIT_SENDER_PUBLIC_INPUT,
+    };
+    use crate::balance::treasury::{Treasury, SLASHED_DEPOSITS_LEAF};
+
+    #[test]
+    fn test_proves_deposit_transfers() -> anyhow::Result<()> {
+        let mut treasury = Treasury::new();
+        treasury.credit(3, 500)?;
+        let mut deposit = treasury.lock(3, 200)?;
+        treasury.slash(&mut deposit)?;
+        let settlement = deposit.settlement.unwrap();
+
+        let circuit = DepositTransferCircuit::<GoldilocksField, PoseidonGoldilocksConfig, 2>::new();
+        let envelope = circuit.prove_envelope(&settlement)?;
+        let inputs = &envelope.public_inputs;
+        assert_eq!(inputs[DEPOSIT_SENDER_PUBLIC_INPUT], deposit.escrow_index);
+        assert_eq!(inputs[DEPOSIT_RECEIVER_PUBLIC_INPUT], SLASHED_DEPOSITS_LEAF);
+        assert_eq!(inputs[DEPOSIT_AMOUNT_PUBLIC_INPUT], 200);
+        assert_eq!(
+            inputs[DEPOSIT_NEW_ROOT_PUBLIC_INPUTS],
+            treasury
+                .root()?
+                .0
+                .elements
+                .map(|element| element.to_canonical_u64())
+        );
+
+        // Minting out of nothing does not prove
+        let mut forged = settlement;
+        forged.sender_update = deposit.lock.receiver_update;
+        assert!(circuit.prove(&forged).is_err());
+        Ok(())
+    }
+}
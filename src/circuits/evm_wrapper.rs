@@ -0,0 +1,255 @@
+use std::{fs, path::Path};
+
+use clap::ValueEnum;
+use plonky2::{
+    field::{extension::Extendable, goldilocks_field::GoldilocksField},
+    hash::hash_types::RichField,
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{
+            AlgebraicHasher, GenericConfig, KeccakGoldilocksConfig, PoseidonGoldilocksConfig,
+        },
+        proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+};
+
+/// Names of the files [`EvmWrapperCircuit::write_artifacts`] writes, as read by
+/// gnark-based plonky2 verifiers that compile the wrapper into a Groth16 circuit.
+pub const COMMON_CIRCUIT_DATA_FILE: &str = "common_circuit_data.json";
+pub const VERIFIER_ONLY_CIRCUIT_DATA_FILE: &str = "verifier_only_circuit_data.json";
+pub const PROOF_WITH_PUBLIC_INPUTS_FILE: &str = "proof_with_public_inputs.json";
+/// Names the [`WrapperHasher`] the wrapper was built with, for the Groth16 prover
+/// to pick the matching plonky2 verifier.
+pub const HASHER_FILE: &str = "hasher";
+
+/// Identifies the [`EvmWrapperCircuit`] around the circuit with id `inner_circuit_id`.
+pub fn evm_wrapper_circuit_id(inner_circuit_id: &str) -> String {
+    format!("evm_wrapper:{}", inner_circuit_id)
+}
+
+/// Hash function of the config the [`EvmWrapperCircuit`] is built with, which
+/// its Groth16 prover has to verify it with.
+///
+/// Only the wrapper can be built with Keccak: every other circuit is verified
+/// recursively, which takes a hasher plonky2 can compute in a circuit, so the
+/// finalization proofs it wraps are always built with Poseidon.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum WrapperHasher {
+    #[default]
+    Poseidon,
+    /// Cheaper to verify for verifiers running on the EVM, which has a Keccak precompile.
+    Keccak,
+}
+
+impl WrapperHasher {
+    pub fn name(self) -> &'static str {
+        match self {
+            WrapperHasher::Poseidon => "poseidon",
+            WrapperHasher::Keccak => "keccak",
+        }
+    }
+}
+
+/// A config the [`EvmWrapperCircuit`] can be built with, naming its hasher in
+/// the artifacts of the wrapper.
+pub trait WrapperConfig {
+    const HASHER: WrapperHasher;
+}
+
+impl WrapperConfig for PoseidonGoldilocksConfig {
+    const HASHER: WrapperHasher = WrapperHasher::Poseidon;
+}
+
+impl WrapperConfig for KeccakGoldilocksConfig {
+    const HASHER: WrapperHasher = WrapperHasher::Keccak;
+}
+
+/// Recursively verifies a finalization proof and exposes its public inputs as is,
+/// in a circuit made of the standard recursion gates only.
+///
+/// Finalization circuits use custom u32 arithmetic gates that verifiers outside of
+/// plonky2 do not implement. The wrapper proof is what gets handed to a Groth16
+/// prover, whose proof is in turn checked on-chain, see
+/// [`chain::settlement`](crate::chain::settlement).
+///
+/// The wrapped proofs are of config `C`, while the wrapper itself is built with
+/// config `W`, which does not need a hasher plonky2 can verify recursively.
+pub struct EvmWrapperCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    W: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub proof: ProofWithPublicInputsTarget<D>,
+    pub base_circuit_data: CircuitData<F, W, D>,
+    _inner: std::marker::PhantomData<C>,
+}
+
+impl<
+        F: RichField + Extendable<D>,
+        C: GenericConfig<D, F = F> + 'static,
+        W: GenericConfig<D, F = F> + 'static,
+        const D: usize,
+    > EvmWrapperCircuit<F, C, W, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    /// Builds the wrapper of proofs of `inner`, whose verifier data is a constant
+    /// of the circuit.
+    pub fn new(inner: &CircuitData<F, C, D>) -> Self {
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let proof = builder.add_virtual_proof_with_pis(&inner.common);
+        let verifier_data = builder.constant_verifier_data(&inner.verifier_only);
+        builder.verify_proof::<C>(&proof, &verifier_data, &inner.common);
+        builder.register_public_inputs(&proof.public_inputs);
+        let base_circuit_data = builder.build::<W>();
+        Self {
+            proof,
+            base_circuit_data,
+            _inner: std::marker::PhantomData,
+        }
+    }
+    pub fn prove(
+        &self,
+        inner_proof: &ProofWithPublicInputs<F, C, D>,
+    ) -> anyhow::Result<ProofWithPublicInputs<F, W, D>> {
+        let mut pw = PartialWitness::<F>::new();
+        pw.set_proof_with_pis_target(&self.proof, inner_proof);
+        let proof = self.base_circuit_data.prove(pw)?;
+        self.base_circuit_data.verify(proof.clone())?;
+        Ok(proof)
+    }
+    /// Writes the common and verifier-only data of the wrapper and its `proof` as
+    /// JSON into `dir`, which is created if missing, along with the name of the
+    /// hasher of `W`.
+    pub fn write_artifacts(
+        &self,
+        proof: &ProofWithPublicInputs<F, W, D>,
+        dir: &Path,
+    ) -> anyhow::Result<()>
+    where
+        W: WrapperConfig,
+    {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(HASHER_FILE), W::HASHER.name())?;
+        fs::write(
+            dir.join(COMMON_CIRCUIT_DATA_FILE),
+            serde_json::to_vec(&self.base_circuit_data.common)?,
+        )?;
+        fs::write(
+            dir.join(VERIFIER_ONLY_CIRCUIT_DATA_FILE),
+            serde_json::to_vec(&self.base_circuit_data.verifier_only)?,
+        )?;
+        fs::write(
+            dir.join(PROOF_WITH_PUBLIC_INPUTS_FILE),
+            serde_json::to_vec(proof)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Wraps a Poseidon finalization proof of `inner` with `hasher`, checks the
+/// wrapper proof and writes its artifacts into `dir`.
+pub fn wrap_finalization(
+    inner: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+    inner_proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+    hasher: WrapperHasher,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    match hasher {
+        WrapperHasher::Poseidon => {
+            let wrapper = EvmWrapperCircuit::<_, _, PoseidonGoldilocksConfig, 2>::new(inner);
+            let proof = wrapper.prove(inner_proof)?;
+            wrapper.write_artifacts(&proof, dir)
+        }
+        WrapperHasher::Keccak => {
+            let wrapper = EvmWrapperCircuit::<_, _, KeccakGoldilocksConfig, 2>::new(inner);
+            let proof = wrapper.prove(inner_proof)?;
+            wrapper.write_artifacts(&proof, dir)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::goldilocks_field::GoldilocksField,
+        plonk::config::{KeccakGoldilocksConfig, PoseidonGoldilocksConfig},
+    };
+
+    use super::{EvmWrapperCircuit, HASHER_FILE, PROOF_WITH_PUBLIC_INPUTS_FILE};
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::Weight,
+        },
+        circuits::{
+            quadratic::VotingPolicy,
+            update_balance::{UpdateBalanceCircuit, UpdateBalanceShape},
+        },
+        proof::certificate::{compute_action_hash, compute_statement_hash},
+        proposal::action::ProposalAction,
+    };
+
+    type F = GoldilocksField;
+    type C = PoseidonGoldilocksConfig;
+
+    #[test]
+    fn test_wraps_finalization_proofs() -> anyhow::Result<()> {
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(1); 2]);
+        let updates = storage.process_txs(vec![BalanceTx::Vote {
+            voter: VoterLeaf::from_position(0),
+            slot: TallySlot::YES,
+            amount: Weight::from(1).into(),
+        }])?;
+        let tally_proofs = [
+            storage.get_tally_proof(TallySlot::NO)?,
+            storage.get_tally_proof(TallySlot::YES)?,
+        ];
+        let inner = UpdateBalanceCircuit::<F, C, 2>::new(UpdateBalanceShape {
+            number_updates: 1,
+            tree_height: 8,
+            balance_bits: storage.balance_bits(),
+            conviction: false,
+            voting_policy: VotingPolicy::Linear,
+            dependencies: false,
+            vesting: false,
+            deadline: false,
+            min_transfer: false,
+        });
+        let inner_proof = inner.prove(
+            compute_statement_hash("Fund the audit"),
+            compute_action_hash(&ProposalAction::TextOnly),
+            &updates,
+            &tally_proofs,
+        )?;
+
+        let wrapper = EvmWrapperCircuit::<F, C, C, 2>::new(&inner.base_circuit_data);
+        let proof = wrapper.prove(&inner_proof)?;
+        assert_eq!(proof.public_inputs, inner_proof.public_inputs);
+
+        let dir = std::env::temp_dir().join(format!("qed-evm-wrapper-{}", std::process::id()));
+        wrapper.write_artifacts(&proof, &dir)?;
+        assert_eq!(std::fs::read_to_string(dir.join(HASHER_FILE))?, "poseidon");
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join(PROOF_WITH_PUBLIC_INPUTS_FILE))?)?;
+        assert_eq!(written["public_inputs"].as_array().map(Vec::len), Some(18));
+        std::fs::remove_dir_all(&dir)?;
+
+        // The wrapper proof takes the hasher of its own config, the wrapped one stays Poseidon
+        let keccak_wrapper =
+            EvmWrapperCircuit::<F, C, KeccakGoldilocksConfig, 2>::new(&inner.base_circuit_data);
+        let keccak_proof = keccak_wrapper.prove(&inner_proof)?;
+        assert_eq!(keccak_proof.public_inputs, inner_proof.public_inputs);
+        keccak_wrapper.write_artifacts(&keccak_proof, &dir)?;
+        assert_eq!(std::fs::read_to_string(dir.join(HASHER_FILE))?, "keccak");
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
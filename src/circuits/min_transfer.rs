@@ -0,0 +1,121 @@
+//! Dust protection in the update balance circuit: every vote and delegation of
+//! a proposal with a minimum transfer moves at least that much weight, so that
+//! nobody can flood the proposal with votes of a single unit, each of which
+//! takes an update in its list and in its proof.
+//!
+//! The minimum is a public input, for verifiers to see which minimum the votes
+//! were held to. It applies to the weight a vote spends rather than the weight
+//! it adds to the tally. No-ops, revocations and undelegations are left out, as
+//! they give weight back rather than move it.
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::{target::Target, witness::WitnessWrite},
+    plonk::circuit_builder::CircuitBuilder,
+};
+
+use crate::{balance::weight::Weight, common::u32::multiple_comparison::list_le_circuit};
+
+use super::update_balance::BalanceUpdateGadget;
+
+/// Minimum shared by the updates of a circuit.
+pub struct MinTransferTargets {
+    pub minimum: Target,
+}
+
+impl MinTransferTargets {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+    ) -> Self {
+        Self {
+            minimum: builder.add_virtual_target(),
+        }
+    }
+    pub fn set_witness<F: RichField>(&self, witness: &mut impl WitnessWrite<F>, minimum: Weight) {
+        witness.set_target(self.minimum, minimum.to_element());
+    }
+}
+
+/// Constrains `update` to move at least the minimum unless it is a no-op, a
+/// revocation or an undelegation. The minimum is range checked to the width of
+/// the balances, which the weight moved already fits in.
+pub fn connect_min_transfer<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    params: &MinTransferTargets,
+    update: &BalanceUpdateGadget,
+    balance_bits: usize,
+) {
+    let amount = builder.sub(
+        update.sender_update.old_value.elements[0],
+        update.sender_update.new_value.elements[0],
+    );
+    let moves_enough = list_le_circuit(builder, vec![params.minimum], vec![amount], balance_bits);
+    let gives_back = builder.or(
+        update.delegation.is_revocation,
+        update.delegation.is_undelegation,
+    );
+    let exempt = builder.or(update.is_noop, gives_back);
+    let allowed = builder.or(exempt, moves_enough);
+    let true_target = builder.one();
+    builder.connect(allowed.target, true_target);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            test_fixtures::{linear_shape, prove_fixture, proves},
+            update_balance::{
+                min_transfer_public_input, pad_updates, parse_update_balance_circuit_id,
+                UpdateBalanceCircuit, UpdateBalanceShape,
+            },
+        },
+    };
+
+    #[test]
+    fn test_dust_votes_are_not_proven() -> anyhow::Result<()> {
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(5), Weight::from(1)]);
+        storage.set_min_transfer(Some(Weight::from(2)));
+        let dust = storage.process_tx(BalanceTx::Vote {
+            voter: VoterLeaf::from_position(1),
+            slot: TallySlot::NO,
+            amount: WeightDelta::from(1),
+        });
+        assert!(dust.is_err());
+        let update = storage.process_tx(BalanceTx::Vote {
+            voter: VoterLeaf::from_position(0),
+            slot: TallySlot::YES,
+            amount: WeightDelta::from(5),
+        })?;
+        assert_eq!(update.min_transfer, Some(Weight::from(2)));
+        let updates = pad_updates(&[update.clone()], 8);
+
+        let shape = UpdateBalanceShape {
+            min_transfer: true,
+            ..linear_shape(updates.len(), storage.balance_bits())
+        };
+        let circuit = UpdateBalanceCircuit::new(shape);
+        let envelope = prove_fixture(&circuit, &updates, &storage)?;
+        assert_eq!(
+            parse_update_balance_circuit_id(&envelope.circuit_id)?,
+            shape
+        );
+        assert_eq!(
+            envelope.public_inputs[min_transfer_public_input(&shape).unwrap()],
+            2
+        );
+
+        // The same vote held to a higher minimum is not proven
+        let mut held_higher = update;
+        held_higher.min_transfer = Some(Weight::from(6));
+        assert!(held_higher.check_min_transfer().is_err());
+        assert!(!proves(&circuit, &pad_updates(&[held_higher], 8), &storage));
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+pub mod aggregate;
+pub mod cache;
+pub mod conviction;
+pub mod deadline;
+pub mod delegation;
+pub mod deposit;
+pub mod evm_wrapper;
+pub mod min_transfer;
+pub mod payout;
+pub mod prover;
+pub mod quadratic;
+pub mod registry;
+pub mod shard_root;
+#[cfg(test)]
+pub(crate) mod test_fixtures;
+pub mod token_lock;
+pub mod update_balance;
+pub mod vesting;
+pub mod witness;
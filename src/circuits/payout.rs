@@ -0,0 +1,191 @@
+use std::ops::Range;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOutTarget, RichField},
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputs,
+    },
+};
+
+use crate::{
+    balance::funds::{FUNDS_BALANCE_BITS, FUNDS_TREE_HEIGHT},
+    common::{hash::merkle::gadgets::delta_merkle_proof::DeltaMerkleProofGadget, WHashOut},
+    proof::codec::ProofEnvelope,
+};
+
+use super::{
+    prover::InvalidWitness,
+    update_balance::{connect_transfer, BalanceUpdate},
+};
+
+/// Identifies the [`PayoutCircuit`] in a [`ProofEnvelope`]. There is only one,
+/// as there is only one funds tree.
+pub const PAYOUT_CIRCUIT_ID: &str = "treasury_payout";
+
+// Layout of the public inputs of a [`PayoutCircuit`] proof.
+pub const PAYOUT_OLD_ROOT_PUBLIC_INPUTS: Range<usize> = 0..4;
+pub const PAYOUT_NEW_ROOT_PUBLIC_INPUTS: Range<usize> = 4..8;
+pub const PAYOUT_FUND_PUBLIC_INPUT: usize = 8;
+pub const PAYOUT_LEAF_PUBLIC_INPUT: usize = 9;
+pub const PAYOUT_AMOUNT_PUBLIC_INPUT: usize = 10;
+pub const PAYOUT_ACTION_HASH_PUBLIC_INPUTS: Range<usize> = 11..15;
+
+/// Proves one payout out of a fund of the [funds tree](crate::balance::funds)
+/// to a payout leaf. The hash of the action of the proposal that passed is a
+/// public input, so a verifier can match the payout with the finalization
+/// certificate of the proposal, whose action hash is the same.
+pub struct PayoutCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fund_update: DeltaMerkleProofGadget,
+    pub payout_update: DeltaMerkleProofGadget,
+    pub action_hash: HashOutTarget,
+    pub base_circuit_data: CircuitData<F, C, D>,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    PayoutCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn new() -> Self {
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let tree_height = FUNDS_TREE_HEIGHT as usize;
+        let fund_update =
+            DeltaMerkleProofGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height);
+        let payout_update =
+            DeltaMerkleProofGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height);
+        let amount = connect_transfer(
+            &mut builder,
+            &fund_update,
+            &payout_update,
+            FUNDS_BALANCE_BITS,
+        );
+        let action_hash = builder.add_virtual_hash();
+        builder.register_public_inputs(&fund_update.old_root.elements);
+        builder.register_public_inputs(&payout_update.new_root.elements);
+        builder.register_public_input(fund_update.index);
+        builder.register_public_input(payout_update.index);
+        builder.register_public_input(amount);
+        builder.register_public_inputs(&action_hash.elements);
+        let base_circuit_data = builder.build::<C>();
+        Self {
+            fund_update,
+            payout_update,
+            action_hash,
+            base_circuit_data,
+        }
+    }
+    pub fn prove(
+        &self,
+        transfer: &BalanceUpdate<F>,
+        action_hash: WHashOut<F>,
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        // Fails here rather than with an unsatisfiable witness inside plonky2
+        transfer
+            .check_weights(FUNDS_BALANCE_BITS)
+            .map_err(InvalidWitness)?;
+        let mut pw = PartialWitness::<F>::new();
+        self.fund_update
+            .set_witness_proof(&mut pw, &transfer.sender_update);
+        self.payout_update
+            .set_witness_proof(&mut pw, &transfer.receiver_update);
+        pw.set_hash_target(self.action_hash, action_hash.0);
+        self.base_circuit_data.prove(pw)
+    }
+    /// Proves the payout like [`Self::prove`] and checks the proof before packing
+    /// it into an envelope.
+    pub fn prove_envelope(
+        &self,
+        transfer: &BalanceUpdate<F>,
+        action_hash: WHashOut<F>,
+    ) -> anyhow::Result<ProofEnvelope> {
+        let proof = self.prove(transfer, action_hash)?;
+        let envelope = ProofEnvelope::new(PAYOUT_CIRCUIT_ID, &self.base_circuit_data, &proof);
+        self.base_circuit_data.verify(proof)?;
+        Ok(envelope)
+    }
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize> Default
+    for PayoutCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+        plonk::config::PoseidonGoldilocksConfig,
+    };
+    use uuid::Uuid;
+    use web3::types::{Address, U256};
+
+    use super::{
+        PayoutCircuit, PAYOUT_ACTION_HASH_PUBLIC_INPUTS, PAYOUT_AMOUNT_PUBLIC_INPUT,
+        PAYOUT_FUND_PUBLIC_INPUT, PAYOUT_LEAF_PUBLIC_INPUT,
+    };
+    use crate::{
+        balance::funds::{DaoFunds, PAYOUT_OFFSET},
+        proof::certificate::compute_action_hash,
+        proposal::action::ProposalAction,
+    };
+
+    #[test]
+    fn test_proves_payouts() -> anyhow::Result<()> {
+        let recipient = Address::repeat_byte(7);
+        let action = ProposalAction::TreasuryTransfer {
+            token: None,
+            recipient,
+            amount: U256::from(300),
+        };
+        let action_hash = compute_action_hash(&action);
+        let mut funds = DaoFunds::new();
+        funds.credit("other", None, 100)?;
+        funds.credit("dao", None, 500)?;
+        let payout = funds.pay(
+            Uuid::new_v4(),
+            "dao",
+            None,
+            recipient,
+            U256::from(300),
+            action_hash,
+            1,
+        )?;
+
+        let circuit = PayoutCircuit::<GoldilocksField, PoseidonGoldilocksConfig, 2>::new();
+        let envelope = circuit.prove_envelope(&payout.transfer, action_hash)?;
+        let inputs = &envelope.public_inputs;
+        assert_eq!(inputs[PAYOUT_FUND_PUBLIC_INPUT], 1);
+        assert_eq!(inputs[PAYOUT_LEAF_PUBLIC_INPUT], PAYOUT_OFFSET);
+        assert_eq!(inputs[PAYOUT_AMOUNT_PUBLIC_INPUT], 300);
+        assert_eq!(
+            inputs[PAYOUT_ACTION_HASH_PUBLIC_INPUTS],
+            action_hash
+                .0
+                .elements
+                .map(|element| element.to_canonical_u64())
+        );
+
+        // Paying more than the fund held does not prove
+        let mut forged = payout.transfer;
+        forged.sender_update.old_value = forged.sender_update.new_value;
+        assert!(circuit.prove(&forged, action_hash).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    any::Any,
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{field, info, info_span, warn};
+
+/// Attempts a proof gets before its failure is reported.
+pub const DEFAULT_PROVE_ATTEMPTS: usize = 2;
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Error for a witness the circuit cannot satisfy, which fails the same way
+/// however often proving is attempted.
+#[derive(Debug)]
+pub struct InvalidWitness(pub anyhow::Error);
+
+impl fmt::Display for InvalidWitness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid witness: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidWitness {}
+
+/// Why proving gave up, after how many attempts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProvingFailure {
+    pub attempts: usize,
+    /// Whether the last attempt panicked rather than returning an error.
+    pub panicked: bool,
+    pub message: String,
+}
+
+impl fmt::Display for ProvingFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "proving {} after {} attempt(s): {}",
+            if self.panicked { "panicked" } else { "failed" },
+            self.attempts,
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for ProvingFailure {}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic payload".to_string(),
+        },
+    }
+}
+
+/// How proofs are attempted: a panic inside plonky2 fails the proof rather than
+/// the thread running it, and failures that may not happen again are retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProvingRetryPolicy {
+    pub max_attempts: usize,
+    /// Wait before the second attempt, doubled for every further one.
+    pub backoff: Duration,
+}
+
+impl Default for ProvingRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_PROVE_ATTEMPTS,
+            backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl ProvingRetryPolicy {
+    /// Calls `prove` until it succeeds or [`Self::max_attempts`] are used up.
+    /// Panics and [`InvalidWitness`] errors are not retried: plonky2 panics on
+    /// witnesses it cannot satisfy, which a retry does not fix.
+    ///
+    /// Runs in a `prove` span under the span of the caller, recording the
+    /// attempts made and the time they took.
+    pub fn prove<T>(
+        &self,
+        mut prove: impl FnMut() -> anyhow::Result<T>,
+    ) -> Result<T, ProvingFailure> {
+        let span = info_span!("prove", attempts = field::Empty, elapsed_ms = field::Empty);
+        let _entered = span.enter();
+        let started_at = Instant::now();
+        let mut attempts = 0;
+        let mut backoff = self.backoff;
+        loop {
+            attempts += 1;
+            span.record("attempts", attempts);
+            let result = catch_unwind(AssertUnwindSafe(&mut prove));
+            span.record("elapsed_ms", started_at.elapsed().as_millis() as u64);
+            let (message, panicked, permanent) = match result {
+                Ok(Ok(proof)) => {
+                    info!("Proof generated");
+                    return Ok(proof);
+                }
+                Ok(Err(err)) => (
+                    format!("{:#}", err),
+                    false,
+                    err.downcast_ref::<InvalidWitness>().is_some(),
+                ),
+                Err(payload) => (panic_message(&*payload), true, true),
+            };
+            if permanent || attempts >= self.max_attempts {
+                warn!(panicked, "Proving gave up: {}", message);
+                return Err(ProvingFailure {
+                    attempts,
+                    panicked,
+                    message,
+                });
+            }
+            warn!(?backoff, "Proving attempt failed, retrying: {}", message);
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+
+    use super::{InvalidWitness, ProvingRetryPolicy};
+
+    #[test]
+    fn test_retries_transient_failures_only() {
+        let policy = ProvingRetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::ZERO,
+        };
+
+        let mut calls = 0;
+        let proved = policy.prove(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(anyhow!("transient"))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(proved, Ok(3));
+
+        let failure = policy
+            .prove(|| -> anyhow::Result<()> { Err(anyhow!("still failing")) })
+            .unwrap_err();
+        assert_eq!((failure.attempts, failure.panicked), (3, false));
+
+        let failure = policy
+            .prove(|| -> anyhow::Result<()> { Err(InvalidWitness(anyhow!("too wide")).into()) })
+            .unwrap_err();
+        assert_eq!(failure.attempts, 1);
+
+        let failure = policy
+            .prove(|| -> anyhow::Result<()> { panic!("partition set twice") })
+            .unwrap_err();
+        assert_eq!((failure.attempts, failure.panicked), (1, true));
+        assert_eq!(failure.message, "partition set twice");
+    }
+}
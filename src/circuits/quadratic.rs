@@ -0,0 +1,190 @@
+//! Quadratic voting: a vote spending `n` units of weight adds the integer square
+//! root of `n` to its tally, so a voter's influence grows slower than their stake.
+//!
+//! The updates of a proposal voted on quadratically carry its [`VotingPolicy`],
+//! and its update balance circuit derives the weight of each vote from what the
+//! voter spent with a [`QuadraticGadget`].
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::{target::Target, witness::WitnessWrite},
+    plonk::circuit_builder::CircuitBuilder,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{balance::weight::WeightDelta, common::u32::multiple_comparison::list_le_circuit};
+
+/// How the weight a vote adds to its tally derives from the weight the voter spends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VotingPolicy {
+    /// Every unit spent adds a unit to the tally.
+    #[default]
+    Linear,
+    /// Spending `n` adds `floor(sqrt(n))` to the tally.
+    Quadratic,
+}
+
+impl From<VotingPolicy> for qed_verifier::VotingPolicy {
+    fn from(policy: VotingPolicy) -> Self {
+        match policy {
+            VotingPolicy::Linear => Self::Linear,
+            VotingPolicy::Quadratic => Self::Quadratic,
+        }
+    }
+}
+
+impl VotingPolicy {
+    pub fn is_linear(&self) -> bool {
+        *self == VotingPolicy::Linear
+    }
+    /// The weight a vote spending `spent` adds to its tally, before any conviction multiplier.
+    pub fn vote_weight(&self, spent: WeightDelta) -> WeightDelta {
+        match self {
+            VotingPolicy::Linear => spent,
+            // The root of a weight below 2^63 is below 2^32
+            VotingPolicy::Quadratic => WeightDelta::from(integer_sqrt(spent.get()) as u32),
+        }
+    }
+}
+
+/// The largest `w` with `w * w <= n`.
+pub fn integer_sqrt(n: u64) -> u64 {
+    let mut root = (n as f64).sqrt() as u64;
+    while root.checked_mul(root).map_or(true, |square| square > n) {
+        root -= 1;
+    }
+    while (root + 1)
+        .checked_mul(root + 1)
+        .map_or(false, |square| square <= n)
+    {
+        root += 1;
+    }
+    root
+}
+
+/// Derives the weight of a vote from the `amount` its voter spent.
+///
+/// The weight `w` is range checked to half the balance width, rounded up, so its
+/// square cannot wrap around the field. It is then constrained by
+/// `w * w <= amount <= w * w + 2 * w`, i.e. `amount < (w + 1) * (w + 1)`.
+pub struct QuadraticGadget {
+    pub weight: Target,
+}
+
+impl QuadraticGadget {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+        amount: Target,
+        balance_bits: usize,
+    ) -> Self {
+        let weight = builder.add_virtual_target();
+        builder.range_check(weight, (balance_bits + 1) / 2);
+        let true_target = builder.one();
+
+        let square = builder.mul(weight, weight);
+        let square_below = list_le_circuit(builder, vec![square], vec![amount], balance_bits);
+        builder.connect(square_below.target, true_target);
+        // Twice the weight can be one bit wider than it, which only matters for 1 bit balances
+        let excess = builder.sub(amount, square);
+        let twice_weight = builder.add(weight, weight);
+        let next_square_above = list_le_circuit(
+            builder,
+            vec![excess],
+            vec![twice_weight],
+            balance_bits.max(2),
+        );
+        builder.connect(next_square_above.target, true_target);
+        Self { weight }
+    }
+    pub fn set_witness<F: RichField>(&self, witness: &mut impl WitnessWrite<F>, amount: u64) {
+        witness.set_target(self.weight, F::from_canonical_u64(integer_sqrt(amount)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::field::goldilocks_field::GoldilocksField;
+
+    use super::{integer_sqrt, VotingPolicy};
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            test_fixtures::{linear_shape, prove_fixture, proves},
+            update_balance::{
+                pad_updates, parse_update_balance_circuit_id, BalanceUpdate, UpdateBalanceCircuit,
+                UpdateBalanceShape, YES_VOTES_PUBLIC_INPUT,
+            },
+        },
+    };
+
+    fn cast_and_delegate(
+        storage: &mut BalanceStorage,
+    ) -> anyhow::Result<Vec<BalanceUpdate<GoldilocksField>>> {
+        let mut updates = vec![];
+        for (position, amount) in [(0, 9), (1, 24)] {
+            updates.push(storage.process_tx(BalanceTx::Vote {
+                voter: VoterLeaf::from_position(position),
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(amount),
+            })?);
+        }
+        updates.push(storage.process_tx(BalanceTx::Delegate {
+            voter: VoterLeaf::from_position(2),
+            delegate: VoterLeaf::from_position(0),
+            amount: WeightDelta::from(3),
+        })?);
+        Ok(pad_updates(&updates, 8))
+    }
+
+    #[test]
+    fn test_votes_count_the_square_root_of_their_cost() -> anyhow::Result<()> {
+        assert_eq!(integer_sqrt(0), 0);
+        assert_eq!(integer_sqrt(8), 2);
+        assert_eq!(integer_sqrt(9), 3);
+        assert_eq!(integer_sqrt(u64::MAX), u32::MAX as u64);
+
+        let balances = [9u32, 24, 3].map(Weight::from).to_vec();
+        let mut storage = BalanceStorage::new(8, balances.clone());
+        storage.set_voting_policy(VotingPolicy::Quadratic);
+        let updates = cast_and_delegate(&mut storage)?;
+        assert_eq!(storage.get_tally(TallySlot::YES)?, Weight::from(3 + 4));
+        assert_eq!(
+            storage.get_balance(VoterLeaf::from_position(0))?,
+            Weight::from(3)
+        );
+        assert_eq!(
+            updates[1].check_weights(storage.balance_bits())?,
+            WeightDelta::from(24)
+        );
+
+        let circuit = UpdateBalanceCircuit::new(UpdateBalanceShape {
+            voting_policy: VotingPolicy::Quadratic,
+            ..linear_shape(updates.len(), storage.balance_bits())
+        });
+        let envelope = prove_fixture(&circuit, &updates, &storage)?;
+        assert_eq!(envelope.public_inputs[YES_VOTES_PUBLIC_INPUT], 7);
+        assert_eq!(
+            parse_update_balance_circuit_id(&envelope.circuit_id)?,
+            circuit.shape
+        );
+        assert!(
+            parse_update_balance_circuit_id("update_balance:4:8:32:quadratic:conviction").is_err()
+        );
+
+        // Votes counted linearly do not prove under the quadratic circuit
+        let mut linear_storage = BalanceStorage::new(8, balances);
+        let mut linear = cast_and_delegate(&mut linear_storage)?;
+        for update in &mut linear {
+            update.policy = VotingPolicy::Quadratic;
+        }
+        assert!(!proves(&circuit, &linear, &linear_storage));
+        Ok(())
+    }
+}
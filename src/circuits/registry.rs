@@ -0,0 +1,191 @@
+//! Which verifying key goes with which circuit id. Proof envelopes name the
+//! circuit they were produced with and the fingerprint of its verifier data, and
+//! the registry lists the same for every circuit this server built, so verifiers
+//! can tell whether the circuit behind an id changed after an upgrade.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    plonk::{
+        circuit_data::CircuitData,
+        config::{AlgebraicHasher, GenericConfig, GenericHashOut},
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::common::verify::fingerprint::get_circuit_fingerprint_generic;
+
+/// A built circuit and the hashes that identify its verifying key.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CircuitRecord {
+    pub circuit_id: String,
+    /// Hex encoded fingerprint of the verifier data, which proof envelopes of the
+    /// circuit carry as their `common_data_hash`
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub verifier_data_hash: Vec<u8>,
+    /// Hex encoded SHA-256 of the common circuit data as rendered by plonky2, which
+    /// changes with the gates, configuration or size of the circuit
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub common_data_hash: [u8; 32],
+    pub degree_bits: usize,
+    pub num_public_inputs: usize,
+}
+
+impl CircuitRecord {
+    pub fn new<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+        circuit_id: &str,
+        circuit_data: &CircuitData<F, C, D>,
+    ) -> Self
+    where
+        <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+    {
+        Self {
+            circuit_id: circuit_id.to_string(),
+            verifier_data_hash: get_circuit_fingerprint_generic::<D, F, C>(
+                &circuit_data.verifier_only,
+            )
+            .to_bytes(),
+            common_data_hash: Sha256::digest(format!("{:?}", circuit_data.common)).into(),
+            degree_bits: circuit_data.common.degree_bits(),
+            num_public_inputs: circuit_data.common.num_public_inputs,
+        }
+    }
+}
+
+/// The records of the circuits built so far, by circuit id.
+#[derive(Clone, Debug, Default)]
+pub struct CircuitRegistry {
+    records: BTreeMap<String, CircuitRecord>,
+    /// How long the last proof of each circuit took, by circuit id.
+    proving_times: BTreeMap<String, Duration>,
+}
+
+impl CircuitRegistry {
+    pub fn register<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+        &mut self,
+        circuit_id: &str,
+        circuit_data: &CircuitData<F, C, D>,
+    ) where
+        <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+    {
+        self.records.insert(
+            circuit_id.to_string(),
+            CircuitRecord::new(circuit_id, circuit_data),
+        );
+    }
+    pub fn get(&self, circuit_id: &str) -> Option<&CircuitRecord> {
+        self.records.get(circuit_id)
+    }
+    /// The records in circuit id order.
+    pub fn records(&self) -> Vec<CircuitRecord> {
+        self.records.values().cloned().collect()
+    }
+    pub fn record_proving_time(&mut self, circuit_id: &str, elapsed: Duration) {
+        self.proving_times.insert(circuit_id.to_string(), elapsed);
+    }
+    /// How long a proof of the registered circuit `circuit_id` should take: as long
+    /// as its last proof, or else as the last proof of the circuit closest in size,
+    /// scaled by their sizes, which proving time grows about linearly with. Unknown
+    /// until some circuit was proven.
+    pub fn estimate_proving_time(&self, circuit_id: &str) -> Option<Duration> {
+        if let Some(elapsed) = self.proving_times.get(circuit_id) {
+            return Some(*elapsed);
+        }
+        let degree_bits = self.records.get(circuit_id)?.degree_bits as i32;
+        let (proven_bits, elapsed) = self
+            .proving_times
+            .iter()
+            .filter_map(|(proven_id, elapsed)| {
+                let record = self.records.get(proven_id)?;
+                Some((record.degree_bits as i32, *elapsed))
+            })
+            .min_by_key(|(proven_bits, _)| (proven_bits - degree_bits).abs())?;
+        Some(elapsed.mul_f64(2f64.powi(degree_bits - proven_bits)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::goldilocks_field::GoldilocksField,
+        plonk::{
+            circuit_builder::CircuitBuilder,
+            circuit_data::{CircuitConfig, CircuitData},
+            config::PoseidonGoldilocksConfig,
+        },
+    };
+
+    use super::CircuitRegistry;
+
+    type F = GoldilocksField;
+    const D: usize = 2;
+    type C = PoseidonGoldilocksConfig;
+
+    fn build(public_inputs: usize) -> CircuitData<F, C, D> {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        for _ in 0..public_inputs {
+            let target = builder.add_virtual_target();
+            builder.register_public_input(target);
+        }
+        builder.build::<C>()
+    }
+
+    #[test]
+    fn test_registry_tells_circuits_apart() {
+        let mut registry = CircuitRegistry::default();
+        registry.register("small", &build(1));
+        registry.register("large", &build(2));
+        let (small, large) = (
+            registry.get("small").unwrap(),
+            registry.get("large").unwrap(),
+        );
+        assert_eq!(small.num_public_inputs, 1);
+        assert_ne!(small.verifier_data_hash, large.verifier_data_hash);
+        assert_ne!(small.common_data_hash, large.common_data_hash);
+
+        // Rebuilding the same circuit yields the same record
+        let rebuilt = small.clone();
+        registry.register("small", &build(1));
+        assert_eq!(registry.get("small"), Some(&rebuilt));
+        assert_eq!(
+            registry
+                .records()
+                .iter()
+                .map(|record| record.circuit_id.as_str())
+                .collect::<Vec<_>>(),
+            ["large", "small"]
+        );
+    }
+
+    #[test]
+    fn test_estimates_proving_time_from_proven_circuits() {
+        let mut registry = CircuitRegistry::default();
+        registry.register("small", &build(1));
+        registry.register("same", &build(2));
+        assert_eq!(registry.estimate_proving_time("small"), None);
+
+        registry.record_proving_time("small", Duration::from_millis(300));
+        assert_eq!(
+            registry.estimate_proving_time("small"),
+            Some(Duration::from_millis(300))
+        );
+        // Both circuits have the same degree, so the proof should take as long
+        assert_eq!(
+            registry.get("same").unwrap().degree_bits,
+            registry.get("small").unwrap().degree_bits
+        );
+        assert_eq!(
+            registry.estimate_proving_time("same"),
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(registry.estimate_proving_time("unknown"), None);
+    }
+}
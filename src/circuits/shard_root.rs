@@ -0,0 +1,355 @@
+use std::{panic, thread};
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, HashOutTarget, RichField},
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+};
+
+use crate::{
+    common::{
+        hash::merkle::{
+            gadgets::merkle_proof::hash_merkle_leaves, helpers::merkle_proof::MerkleProof,
+        },
+        WHashOut,
+    },
+    proof::codec::ProofEnvelope,
+};
+
+use super::update_balance::{
+    parse_update_balance_circuit_id, public_input_layout, update_balance_circuit_id, BalanceUpdate,
+    PublicInputLayout, UpdateBalanceCircuit, UpdateBalanceShape, ACTION_HASH_PUBLIC_INPUTS,
+    FINAL_ROOT_PUBLIC_INPUTS, INITIAL_ROOT_PUBLIC_INPUTS, NO_VOTES_PUBLIC_INPUT,
+    STATEMENT_HASH_PUBLIC_INPUTS, YES_VOTES_PUBLIC_INPUT,
+};
+
+/// Identifies a [`ShardRootCircuit`] by the number of shards and their shape.
+pub fn shard_root_circuit_id(shard_shape: &UpdateBalanceShape, shard_count: usize) -> String {
+    format!(
+        "shard_root:{}:{}",
+        shard_count,
+        update_balance_circuit_id(shard_shape)
+    )
+}
+
+/// Parses a circuit id produced by [`shard_root_circuit_id`] into the shape of
+/// the shards and their number. Shards are proven without optional rules, see
+/// [`crate::balance::shards`], and there are at least two of them.
+pub fn parse_shard_root_circuit_id(
+    circuit_id: &str,
+) -> anyhow::Result<(UpdateBalanceShape, usize)> {
+    let (shard_count, shard_circuit_id) = circuit_id
+        .strip_prefix("shard_root:")
+        .and_then(|rest| rest.split_once(':'))
+        .ok_or_else(|| anyhow::anyhow!("unknown circuit id {}", circuit_id))?;
+    let shard_count: usize = shard_count.parse()?;
+    let shard_shape = parse_update_balance_circuit_id(shard_circuit_id)?;
+    anyhow::ensure!(
+        shard_count > 1 && shard_root_circuit_id(&shard_shape, shard_count) == circuit_id,
+        "unknown circuit id {}",
+        circuit_id
+    );
+    anyhow::ensure!(
+        public_input_layout(&shard_shape) == PublicInputLayout::default(),
+        "shards of circuit {} are proven under optional rules",
+        circuit_id
+    );
+    Ok((shard_shape, shard_count))
+}
+
+/// Parses the id of a circuit finalization proofs are produced with: the shape
+/// of an update balance circuit, which proves every shard along with their
+/// number on sharded proposals, see [`ShardRootCircuit`].
+pub fn parse_finalization_circuit_id(
+    circuit_id: &str,
+) -> anyhow::Result<(UpdateBalanceShape, Option<usize>)> {
+    if circuit_id.starts_with("shard_root:") {
+        let (shard_shape, shard_count) = parse_shard_root_circuit_id(circuit_id)?;
+        return Ok((shard_shape, Some(shard_count)));
+    }
+    Ok((parse_update_balance_circuit_id(circuit_id)?, None))
+}
+
+/// What proving one shard takes: its updates, padded to the shape shared by all
+/// shards, and the proofs of its tally slots in the final tree.
+pub struct ShardWitness<F: RichField> {
+    pub updates: Vec<BalanceUpdate<F>>,
+    pub tally_proofs: [MerkleProof<F>; 2],
+}
+
+/// Recursively verifies the update balance proof of every shard of a sharded
+/// electorate, see [`crate::balance::shards`]. Its public inputs follow the
+/// layout of an [`UpdateBalanceCircuit`] for the electorate as a whole: the
+/// sharded roots over the initial and final shard roots, see
+/// [`compute_sharded_root`](crate::balance::shards::compute_sharded_root), and
+/// the no and yes tallies added up across shards, and the statement hash all
+/// shard proofs have to agree on.
+///
+/// Each summed tally is range checked to the balance width of the shards, so
+/// the sum cannot wrap around the field. Honest electorates pass the check,
+/// as their total weight was checked to fit in the width when they were seeded.
+pub struct ShardRootCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub shard_shape: UpdateBalanceShape,
+    pub proofs: Vec<ProofWithPublicInputsTarget<D>>,
+    pub base_circuit_data: CircuitData<F, C, D>,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    ShardRootCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    /// Builds the circuit over `shard_count` proofs of `shard_circuit`.
+    pub fn new(shard_circuit: &UpdateBalanceCircuit<F, C, D>, shard_count: usize) -> Self {
+        assert!(shard_count > 0, "nothing to combine");
+        let inner = &shard_circuit.base_circuit_data;
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let verifier_data = builder.constant_verifier_data(&inner.verifier_only);
+        let proofs: Vec<_> = (0..shard_count)
+            .map(|_| {
+                let proof = builder.add_virtual_proof_with_pis(&inner.common);
+                builder.verify_proof::<C>(&proof, &verifier_data, &inner.common);
+                proof
+            })
+            .collect();
+        let zero_leaf = builder.constant_hash(HashOut::ZERO);
+        let mut combine_roots = |range: std::ops::Range<usize>| {
+            let mut roots: Vec<_> = proofs
+                .iter()
+                .map(|proof| HashOutTarget::from_vec(proof.public_inputs[range.clone()].to_vec()))
+                .collect();
+            roots.resize(shard_count.next_power_of_two(), zero_leaf);
+            hash_merkle_leaves::<F, D, C::Hasher>(&mut builder, &roots)
+        };
+        let initial_root = combine_roots(INITIAL_ROOT_PUBLIC_INPUTS);
+        let final_root = combine_roots(FINAL_ROOT_PUBLIC_INPUTS);
+        let [no_votes, yes_votes] = [NO_VOTES_PUBLIC_INPUT, YES_VOTES_PUBLIC_INPUT]
+            .map(|input| builder.add_many(proofs.iter().map(|proof| proof.public_inputs[input])));
+        builder.range_check(no_votes, shard_circuit.shape.balance_bits);
+        builder.range_check(yes_votes, shard_circuit.shape.balance_bits);
+        builder.register_public_inputs(&initial_root.elements);
+        builder.register_public_inputs(&final_root.elements);
+        builder.register_public_input(no_votes);
+        builder.register_public_input(yes_votes);
+        // Every shard proves the same statement and action
+        for range in [STATEMENT_HASH_PUBLIC_INPUTS, ACTION_HASH_PUBLIC_INPUTS] {
+            let hashes: Vec<_> = proofs
+                .iter()
+                .map(|proof| HashOutTarget::from_vec(proof.public_inputs[range.clone()].to_vec()))
+                .collect();
+            for hash in &hashes[1..] {
+                builder.connect_hashes(hashes[0], *hash);
+            }
+            builder.register_public_inputs(&hashes[0].elements);
+        }
+        let base_circuit_data = builder.build::<C>();
+        Self {
+            shard_shape: shard_circuit.shape,
+            proofs,
+            base_circuit_data,
+        }
+    }
+    pub fn shard_count(&self) -> usize {
+        self.proofs.len()
+    }
+    /// Combines the proofs of the shards, in shard order.
+    pub fn prove(
+        &self,
+        shard_proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        anyhow::ensure!(
+            shard_proofs.len() == self.proofs.len(),
+            "expected {} shard proofs",
+            self.proofs.len()
+        );
+        let mut pw = PartialWitness::<F>::new();
+        for (target, proof) in self.proofs.iter().zip(shard_proofs) {
+            pw.set_proof_with_pis_target(target, proof);
+        }
+        self.base_circuit_data.prove(pw)
+    }
+    /// Proves every shard of the proposal whose statement and action hash to
+    /// `statement_hash` and `action_hash` with `shard_circuit`, each on its own
+    /// thread, then combines their proofs and checks the result before packing it
+    /// into an envelope.
+    pub fn prove_envelope(
+        &self,
+        shard_circuit: &UpdateBalanceCircuit<F, C, D>,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        witnesses: &[ShardWitness<F>],
+    ) -> anyhow::Result<ProofEnvelope>
+    where
+        UpdateBalanceCircuit<F, C, D>: Sync,
+        ProofWithPublicInputs<F, C, D>: Send,
+    {
+        anyhow::ensure!(
+            shard_circuit.shape == self.shard_shape,
+            "shards are proven with the {} circuit",
+            update_balance_circuit_id(&self.shard_shape)
+        );
+        anyhow::ensure!(
+            witnesses.len() == self.shard_count(),
+            "expected the witnesses of {} shards",
+            self.shard_count()
+        );
+        let shard_proofs = thread::scope(|scope| {
+            let handles: Vec<_> = witnesses
+                .iter()
+                .map(|witness| {
+                    scope.spawn(move || {
+                        shard_circuit.prove(
+                            statement_hash,
+                            action_hash,
+                            &witness.updates,
+                            &witness.tally_proofs,
+                        )
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                // Hands a panic on to the caller, which may catch it like any other proving panic
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+        let proof = self.prove(&shard_proofs)?;
+        let envelope = ProofEnvelope::new(
+            &shard_root_circuit_id(&self.shard_shape, self.shard_count()),
+            &self.base_circuit_data,
+            &proof,
+        );
+        self.base_circuit_data.verify(proof)?;
+        Ok(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+        plonk::config::PoseidonGoldilocksConfig,
+    };
+
+    use qed_verifier::ProvenRules;
+
+    use super::{parse_finalization_circuit_id, shard_root_circuit_id};
+    use crate::{
+        balance::{accounts::DEFAULT_BALANCE_BITS, shards::ShardedBalanceStorage, weight::Weight},
+        circuits::{
+            cache::CircuitCache,
+            update_balance::{
+                FINAL_ROOT_PUBLIC_INPUTS, INITIAL_ROOT_PUBLIC_INPUTS, STATEMENT_HASH_PUBLIC_INPUTS,
+                YES_VOTES_PUBLIC_INPUT,
+            },
+        },
+        common::WHashOut,
+        proof::{
+            certificate::{compute_action_hash, compute_statement_hash},
+            verify::verify_finalization,
+        },
+        proposal::action::ProposalAction,
+    };
+
+    #[test]
+    fn test_combines_shard_proofs_into_the_global_roots() -> anyhow::Result<()> {
+        let mut storage =
+            ShardedBalanceStorage::new(8, vec![Weight::from(1); 6], 3, DEFAULT_BALANCE_BITS)?;
+        storage.vote(2, true)?;
+        storage.vote(7, true)?;
+        storage.delegate(4, 5)?;
+
+        let (shape, witnesses) = storage.witnesses()?;
+        let mut cache = CircuitCache::<GoldilocksField, PoseidonGoldilocksConfig, 2>::new();
+        let shard_circuit = cache.get_or_build(shape);
+        let circuit = cache.get_or_build_shard_root(shape, storage.shard_count());
+        let statement_hash = compute_statement_hash("Fund the audit");
+        let action_hash = compute_action_hash(&ProposalAction::TextOnly);
+        let envelope =
+            circuit.prove_envelope(&shard_circuit, statement_hash, action_hash, &witnesses)?;
+
+        assert_eq!(envelope.circuit_id, shard_root_circuit_id(&shape, 3));
+        let root_inputs = |root: WHashOut<GoldilocksField>| {
+            root.0.elements.map(|element| element.to_canonical_u64())
+        };
+        assert_eq!(
+            envelope.public_inputs[INITIAL_ROOT_PUBLIC_INPUTS],
+            root_inputs(storage.initial_root())
+        );
+        assert_eq!(
+            envelope.public_inputs[FINAL_ROOT_PUBLIC_INPUTS],
+            root_inputs(storage.root()?)
+        );
+        assert_eq!(envelope.public_inputs[YES_VOTES_PUBLIC_INPUT], 2);
+        assert_eq!(
+            envelope.public_inputs[STATEMENT_HASH_PUBLIC_INPUTS],
+            root_inputs(statement_hash)
+        );
+
+        assert_eq!(
+            parse_finalization_circuit_id(&envelope.circuit_id)?,
+            (shape, Some(3))
+        );
+        let mut rules = ProvenRules {
+            shard_count: Some(3),
+            ..ProvenRules::default()
+        };
+        let verify = |rules: &ProvenRules| {
+            verify_finalization(
+                &envelope,
+                storage.initial_root(),
+                storage.root()?,
+                "Fund the audit",
+                None,
+                &ProposalAction::TextOnly,
+                &[],
+                rules,
+            )
+        };
+        assert_eq!(verify(&rules)?.yes_votes, Weight::from(2));
+        // A proof over other shards than the proposal was split across proves nothing about it
+        rules.shard_count = Some(2);
+        assert!(verify(&rules).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parses_finalization_circuit_ids() {
+        let shape = parse_finalization_circuit_id("update_balance:8:32:32")
+            .unwrap()
+            .0;
+        assert_eq!(
+            parse_finalization_circuit_id("shard_root:4:update_balance:8:32:32").unwrap(),
+            (shape, Some(4))
+        );
+        assert_eq!(
+            parse_finalization_circuit_id("update_balance:8:32:32").unwrap(),
+            (shape, None)
+        );
+        for circuit_id in [
+            "shard_root:1:update_balance:8:32:32",
+            "shard_root:04:update_balance:8:32:32",
+            "shard_root:4:update_balance:8:32:32:vesting",
+            "shard_root:4",
+        ] {
+            assert!(parse_finalization_circuit_id(circuit_id).is_err());
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Deterministic fixtures and proptest strategies for the tests of the update
+//! balance circuits.
+//!
+//! [`RawTransfer`] writes balances straight into a tree, bypassing the checks
+//! of [`BalanceStorage`], so the constraints of a [`BalanceUpdateGadget`] are
+//! the only thing standing between an unsound transfer and a proof.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use once_cell::sync::Lazy;
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::Field},
+    hash::{hash_types::HashOut, poseidon::PoseidonHash},
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::PoseidonGoldilocksConfig,
+    },
+};
+use proptest::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    balance::{
+        accounts::{BalanceTx, TallySlot, VoterLeaf},
+        storage::BalanceStorage,
+        weight::{Weight, WeightDelta},
+    },
+    common::{hash::merkle::helpers::merkle_proof::MerkleProof, WHashOut},
+    proof::{
+        certificate::{compute_action_hash, compute_statement_hash},
+        codec::ProofEnvelope,
+    },
+    proposal::action::ProposalAction,
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+use super::{
+    quadratic::VotingPolicy,
+    update_balance::{
+        BalanceUpdate, BalanceUpdateGadget, UpdateBalanceCircuit, UpdateBalanceShape, UpdateKind,
+    },
+};
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+pub const FIXTURE_TREE_HEIGHT: usize = 8;
+/// Narrow enough for generated balances to overflow it often.
+pub const FIXTURE_BALANCE_BITS: usize = 16;
+pub const FIXTURE_MAX_BALANCE: u64 = 1 << FIXTURE_BALANCE_BITS;
+/// Updates of [`CHAINED_UPDATES_CIRCUIT`], and votes of [`VoteSpec`] sequences.
+pub const FIXTURE_UPDATE_COUNT: usize = 4;
+
+fn leaf(balance: F) -> WHashOut<F> {
+    WHashOut(HashOut {
+        elements: [balance, F::ZERO, F::ZERO, F::ZERO],
+    })
+}
+
+/// A vote from a voter leaf into the yes tally slot, with the sender debited
+/// `debit` and the tally credited `credit`, whether or not that is sound.
+/// Balances wrap around the field rather than failing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawTransfer {
+    pub sender_balance: u64,
+    pub receiver_balance: u64,
+    pub debit: u64,
+    pub credit: u64,
+}
+
+impl RawTransfer {
+    /// Whether the transfer conserves weight and keeps both balances in range.
+    pub fn is_sound(&self) -> bool {
+        self.debit == self.credit
+            && self.debit <= self.sender_balance
+            && self.receiver_balance + self.credit < FIXTURE_MAX_BALANCE
+    }
+    pub fn to_update(&self) -> BalanceUpdate<F> {
+        let mut tree = ZeroMerkleTree::<F, PoseidonHash, SimpleNodeStore>::new(
+            FIXTURE_TREE_HEIGHT as u8,
+            SimpleNodeStore::new(),
+        );
+        let sender = VoterLeaf::from_position(0).index();
+        let receiver = TallySlot::YES.index();
+        let sender_balance = F::from_canonical_u64(self.sender_balance);
+        let receiver_balance = F::from_canonical_u64(self.receiver_balance);
+        tree.set_leaf(sender, leaf(sender_balance)).unwrap();
+        tree.set_leaf(receiver, leaf(receiver_balance)).unwrap();
+        let sender_update = tree
+            .set_leaf(
+                sender,
+                leaf(sender_balance - F::from_canonical_u64(self.debit)),
+            )
+            .unwrap();
+        let receiver_update = tree
+            .set_leaf(
+                receiver,
+                leaf(receiver_balance + F::from_canonical_u64(self.credit)),
+            )
+            .unwrap();
+        BalanceUpdate {
+            sender_update,
+            receiver_update,
+            kind: UpdateKind::Vote,
+            conviction: None,
+            policy: VotingPolicy::Linear,
+            vesting_epoch: None,
+            window: None,
+            min_transfer: None,
+        }
+    }
+}
+
+/// Transfers of which roughly a third are sound, a third move more than the
+/// sender holds or the receiver can take, and a third do not balance.
+pub fn raw_transfers() -> impl Strategy<Value = RawTransfer> {
+    let balance = 0..FIXTURE_MAX_BALANCE;
+    prop_oneof![
+        (balance.clone(), balance.clone())
+            .prop_flat_map(|(sender_balance, receiver_balance)| {
+                let max_amount = sender_balance.min(FIXTURE_MAX_BALANCE - 1 - receiver_balance);
+                (Just(sender_balance), Just(receiver_balance), 0..=max_amount)
+            })
+            .prop_map(|(sender_balance, receiver_balance, amount)| RawTransfer {
+                sender_balance,
+                receiver_balance,
+                debit: amount,
+                credit: amount,
+            }),
+        (balance.clone(), balance.clone(), balance.clone()).prop_map(
+            |(sender_balance, receiver_balance, amount)| RawTransfer {
+                sender_balance,
+                receiver_balance,
+                debit: amount,
+                credit: amount,
+            }
+        ),
+        (balance.clone(), balance.clone(), balance.clone(), balance).prop_map(
+            |(sender_balance, receiver_balance, debit, credit)| RawTransfer {
+                sender_balance,
+                receiver_balance,
+                debit,
+                credit,
+            }
+        ),
+    ]
+}
+
+/// A voter with `balance` voting `amount` of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoteSpec {
+    pub balance: u32,
+    pub amount: u32,
+    pub yes: bool,
+}
+
+/// [`FIXTURE_UPDATE_COUNT`] votes of distinct voters whose tallies cannot overflow.
+pub fn vote_sequences() -> impl Strategy<Value = Vec<VoteSpec>> {
+    let max_balance = (FIXTURE_MAX_BALANCE / FIXTURE_UPDATE_COUNT as u64) as u32;
+    prop::collection::vec(
+        (1..max_balance, any::<bool>()).prop_flat_map(|(balance, yes)| {
+            (1..=balance).prop_map(move |amount| VoteSpec {
+                balance,
+                amount,
+                yes,
+            })
+        }),
+        FIXTURE_UPDATE_COUNT,
+    )
+}
+
+/// The same votes on every call for a given seed.
+pub fn seeded_votes(seed: u64) -> Vec<VoteSpec> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let max_balance = (FIXTURE_MAX_BALANCE / FIXTURE_UPDATE_COUNT as u64) as u32;
+    (0..FIXTURE_UPDATE_COUNT)
+        .map(|_| {
+            let balance = rng.gen_range(1..max_balance);
+            VoteSpec {
+                balance,
+                amount: rng.gen_range(1..=balance),
+                yes: rng.gen(),
+            }
+        })
+        .collect()
+}
+
+/// Casts `votes` in a fresh storage, voter `i` casting the `i`th vote.
+pub fn cast_votes(votes: &[VoteSpec]) -> (BalanceStorage, Vec<BalanceUpdate<F>>) {
+    let mut storage = BalanceStorage::with_store(
+        FIXTURE_TREE_HEIGHT as u8,
+        votes
+            .iter()
+            .map(|vote| Weight::from(vote.balance))
+            .collect(),
+        FIXTURE_BALANCE_BITS,
+        NodeStore::Memory(SimpleNodeStore::new()),
+    )
+    .unwrap();
+    let txs = votes
+        .iter()
+        .enumerate()
+        .map(|(position, vote)| BalanceTx::Vote {
+            voter: VoterLeaf::from_position(position as u64),
+            slot: if vote.yes {
+                TallySlot::YES
+            } else {
+                TallySlot::NO
+            },
+            amount: WeightDelta::from(vote.amount),
+        })
+        .collect();
+    let updates = storage.process_txs(txs).unwrap();
+    (storage, updates)
+}
+
+/// Proves a single [`BalanceUpdateGadget`], with nothing chained to it.
+pub struct SingleUpdateCircuit {
+    pub gadget: BalanceUpdateGadget,
+    pub data: CircuitData<F, C, 2>,
+}
+
+impl SingleUpdateCircuit {
+    pub fn new() -> Self {
+        let mut builder = CircuitBuilder::<F, 2>::new(CircuitConfig::standard_recursion_config());
+        let gadget = BalanceUpdateGadget::add_virtual_to::<PoseidonHash, F, 2>(
+            &mut builder,
+            FIXTURE_TREE_HEIGHT,
+            FIXTURE_BALANCE_BITS,
+        );
+        builder.register_public_inputs(&gadget.old_root.elements);
+        builder.register_public_inputs(&gadget.new_root.elements);
+        Self {
+            gadget,
+            data: builder.build::<C>(),
+        }
+    }
+    /// Whether `update` proves and the proof verifies. Weights are not checked
+    /// beforehand, and plonky2 panicking on an unsatisfiable witness counts as
+    /// a rejection.
+    pub fn accepts(&self, update: &BalanceUpdate<F>) -> bool {
+        let proved = catch_unwind(AssertUnwindSafe(|| {
+            let mut pw = PartialWitness::new();
+            self.gadget.set_witness_proof(&mut pw, update);
+            self.data
+                .prove(pw)
+                .and_then(|proof| self.data.verify(proof))
+        }));
+        matches!(proved, Ok(Ok(())))
+    }
+}
+
+pub static SINGLE_UPDATE_CIRCUIT: Lazy<SingleUpdateCircuit> = Lazy::new(SingleUpdateCircuit::new);
+
+pub static CHAINED_UPDATES_CIRCUIT: Lazy<UpdateBalanceCircuit<F, C, 2>> = Lazy::new(|| {
+    UpdateBalanceCircuit::new(linear_shape(FIXTURE_UPDATE_COUNT, FIXTURE_BALANCE_BITS))
+});
+
+/// A circuit over trees of [`FIXTURE_TREE_HEIGHT`] counting votes linearly,
+/// with every optional rule off for tests to turn on the one they cover.
+pub fn linear_shape(number_updates: usize, balance_bits: usize) -> UpdateBalanceShape {
+    UpdateBalanceShape {
+        number_updates,
+        tree_height: FIXTURE_TREE_HEIGHT,
+        balance_bits,
+        conviction: false,
+        voting_policy: VotingPolicy::Linear,
+        dependencies: false,
+        vesting: false,
+        deadline: false,
+        min_transfer: false,
+    }
+}
+
+/// The proofs of the no and yes tally slots in the tree of `storage`.
+pub fn tally_proofs(storage: &BalanceStorage) -> anyhow::Result<[MerkleProof<F>; 2]> {
+    Ok([
+        storage.get_tally_proof(TallySlot::NO)?,
+        storage.get_tally_proof(TallySlot::YES)?,
+    ])
+}
+
+/// Proves `updates`, which left `storage` in its current tree, for a text only
+/// proposal with a fixed statement.
+pub fn prove_fixture(
+    circuit: &UpdateBalanceCircuit<F, C, 2>,
+    updates: &Vec<BalanceUpdate<F>>,
+    storage: &BalanceStorage,
+) -> anyhow::Result<ProofEnvelope> {
+    circuit.prove_envelope(
+        compute_statement_hash("Fund the audit"),
+        compute_action_hash(&ProposalAction::TextOnly),
+        updates,
+        &tally_proofs(storage)?,
+    )
+}
+
+/// Whether [`prove_fixture`] proves `updates` and the proof verifies, with
+/// plonky2 panicking on an unsatisfiable witness counting as a rejection.
+pub fn proves(
+    circuit: &UpdateBalanceCircuit<F, C, 2>,
+    updates: &Vec<BalanceUpdate<F>>,
+    storage: &BalanceStorage,
+) -> bool {
+    let proved = catch_unwind(AssertUnwindSafe(|| {
+        let tally_proofs = tally_proofs(storage)?;
+        circuit
+            .prove(
+                compute_statement_hash("Fund the audit"),
+                compute_action_hash(&ProposalAction::TextOnly),
+                updates,
+                &tally_proofs,
+            )
+            .and_then(|proof| circuit.base_circuit_data.verify(proof))
+    }));
+    matches!(proved, Ok(Ok(())))
+}
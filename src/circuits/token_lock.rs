@@ -0,0 +1,192 @@
+use std::ops::Range;
+
+use plonky2::{
+    field::{extension::Extendable, types::PrimeField64},
+    hash::hash_types::RichField,
+    iop::{
+        target::Target,
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputs,
+    },
+};
+use uuid::Uuid;
+
+use crate::{
+    balance::locks::{TOKEN_BALANCE_BITS, TOKEN_TREE_HEIGHT},
+    common::hash::merkle::gadgets::delta_merkle_proof::DeltaMerkleProofGadget,
+    nullifier::nullifier_set::proposal_id_to_elements,
+    proof::codec::ProofEnvelope,
+};
+
+use super::{
+    prover::InvalidWitness,
+    update_balance::{connect_transfer, BalanceUpdate},
+};
+
+/// Identifies the [`TokenLockCircuit`] in a [`ProofEnvelope`]. There is only one,
+/// as there is only one token tree.
+pub const TOKEN_LOCK_CIRCUIT_ID: &str = "token_lock";
+
+// Layout of the public inputs of a [`TokenLockCircuit`] proof.
+pub const TOKEN_LOCK_OLD_ROOT_PUBLIC_INPUTS: Range<usize> = 0..4;
+pub const TOKEN_LOCK_NEW_ROOT_PUBLIC_INPUTS: Range<usize> = 4..8;
+pub const TOKEN_LOCK_SENDER_PUBLIC_INPUT: usize = 8;
+pub const TOKEN_LOCK_RECEIVER_PUBLIC_INPUT: usize = 9;
+pub const TOKEN_LOCK_AMOUNT_PUBLIC_INPUT: usize = 10;
+pub const TOKEN_LOCK_PROPOSAL_ID_PUBLIC_INPUTS: Range<usize> = 11..15;
+
+/// Proves one transfer in the [token tree](crate::balance::locks): a lock, from
+/// the account of a voter to a lock leaf, or a release, back from the lock leaf.
+/// Which one it is follows from the leaves, lock leaves being at or above
+/// [`LOCK_OFFSET`](crate::balance::locks::LOCK_OFFSET). The id of the proposal
+/// the tokens were locked on is a public input, so a verifier can match the
+/// transfer with the proposal.
+pub struct TokenLockCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub sender_update: DeltaMerkleProofGadget,
+    pub receiver_update: DeltaMerkleProofGadget,
+    pub proposal_id: [Target; 4],
+    pub base_circuit_data: CircuitData<F, C, D>,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    TokenLockCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn new() -> Self {
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let tree_height = TOKEN_TREE_HEIGHT as usize;
+        let sender_update =
+            DeltaMerkleProofGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height);
+        let receiver_update =
+            DeltaMerkleProofGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height);
+        let amount = connect_transfer(
+            &mut builder,
+            &sender_update,
+            &receiver_update,
+            TOKEN_BALANCE_BITS,
+        );
+        let proposal_id = builder.add_virtual_target_arr::<4>();
+        builder.register_public_inputs(&sender_update.old_root.elements);
+        builder.register_public_inputs(&receiver_update.new_root.elements);
+        builder.register_public_input(sender_update.index);
+        builder.register_public_input(receiver_update.index);
+        builder.register_public_input(amount);
+        builder.register_public_inputs(&proposal_id);
+        let base_circuit_data = builder.build::<C>();
+        Self {
+            sender_update,
+            receiver_update,
+            proposal_id,
+            base_circuit_data,
+        }
+    }
+    pub fn prove(
+        &self,
+        transfer: &BalanceUpdate<F>,
+        proposal_id: &Uuid,
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        // Fails here rather than with an unsatisfiable witness inside plonky2
+        transfer
+            .check_weights(TOKEN_BALANCE_BITS)
+            .map_err(InvalidWitness)?;
+        let mut pw = PartialWitness::<F>::new();
+        self.sender_update
+            .set_witness_proof(&mut pw, &transfer.sender_update);
+        self.receiver_update
+            .set_witness_proof(&mut pw, &transfer.receiver_update);
+        for (target, element) in self
+            .proposal_id
+            .iter()
+            .zip(proposal_id_to_elements(proposal_id))
+        {
+            pw.set_target(*target, F::from_canonical_u64(element.to_canonical_u64()));
+        }
+        self.base_circuit_data.prove(pw)
+    }
+    /// Proves the transfer like [`Self::prove`] and checks the proof before
+    /// packing it into an envelope.
+    pub fn prove_envelope(
+        &self,
+        transfer: &BalanceUpdate<F>,
+        proposal_id: &Uuid,
+    ) -> anyhow::Result<ProofEnvelope> {
+        let proof = self.prove(transfer, proposal_id)?;
+        let envelope = ProofEnvelope::new(TOKEN_LOCK_CIRCUIT_ID, &self.base_circuit_data, &proof);
+        self.base_circuit_data.verify(proof)?;
+        Ok(envelope)
+    }
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize> Default
+    for TokenLockCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+        plonk::config::PoseidonGoldilocksConfig,
+    };
+    use uuid::Uuid;
+
+    use super::{
+        TokenLockCircuit, TOKEN_LOCK_AMOUNT_PUBLIC_INPUT, TOKEN_LOCK_PROPOSAL_ID_PUBLIC_INPUTS,
+        TOKEN_LOCK_RECEIVER_PUBLIC_INPUT, TOKEN_LOCK_SENDER_PUBLIC_INPUT,
+    };
+    use crate::{
+        balance::locks::{TokenLocks, LOCK_OFFSET},
+        nullifier::nullifier_set::proposal_id_to_elements,
+    };
+
+    #[test]
+    fn test_proves_locks_and_releases() -> anyhow::Result<()> {
+        let proposal_id = Uuid::new_v4();
+        let mut locks = TokenLocks::new();
+        locks.credit(7, 500)?;
+        let lock = locks.lock(proposal_id, 7, 300, 1)?;
+        let released = locks.release(&proposal_id)?;
+
+        let circuit = TokenLockCircuit::<GoldilocksField, PoseidonGoldilocksConfig, 2>::new();
+        let envelope = circuit.prove_envelope(&lock.lock, &proposal_id)?;
+        let inputs = &envelope.public_inputs;
+        assert_eq!(inputs[TOKEN_LOCK_SENDER_PUBLIC_INPUT], 7);
+        assert_eq!(inputs[TOKEN_LOCK_RECEIVER_PUBLIC_INPUT], LOCK_OFFSET);
+        assert_eq!(inputs[TOKEN_LOCK_AMOUNT_PUBLIC_INPUT], 300);
+        assert_eq!(
+            inputs[TOKEN_LOCK_PROPOSAL_ID_PUBLIC_INPUTS],
+            proposal_id_to_elements(&proposal_id).map(|element| element.to_canonical_u64())
+        );
+        let release = released[0].release.as_ref().unwrap();
+        let envelope = circuit.prove_envelope(release, &proposal_id)?;
+        assert_eq!(
+            envelope.public_inputs[TOKEN_LOCK_SENDER_PUBLIC_INPUT],
+            LOCK_OFFSET
+        );
+        assert_eq!(envelope.public_inputs[TOKEN_LOCK_RECEIVER_PUBLIC_INPUT], 7);
+
+        // Locking more than the account held does not prove
+        let mut forged = lock.lock;
+        forged.sender_update.old_value = forged.sender_update.new_value;
+        assert!(circuit.prove(&forged, &proposal_id).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1549 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use plonky2::{
+    field::{extension::Extendable, types::PrimeField64},
+    hash::hash_types::{HashOutTarget, RichField},
+    iop::{
+        generator::generate_partial_witness,
+        target::{BoolTarget, Target},
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputs,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    balance::{
+        accounts::{TallySlot, LOCKED_ELEMENT, MAX_BALANCE_BITS, UNLOCK_EPOCH_ELEMENT},
+        storage::MAX_TREE_HEIGHT,
+        weight::{Weight, WeightDelta},
+    },
+    common::{
+        builder::select::CircuitBuilderSelectHelpers,
+        hash::merkle::{
+            gadgets::{
+                delta_merkle_proof::DeltaMerkleProofGadget,
+                merkle_proof::{MerkleProofGadget, OptionalMerkleProofGadget},
+            },
+            helpers::merkle_proof::{DeltaMerkleProof, MerkleProof},
+        },
+        u32::multiple_comparison::list_le_circuit,
+        WHashOut,
+    },
+    proof::codec::ProofEnvelope,
+};
+
+use super::{
+    conviction::{
+        ConvictionGadget, ConvictionStamp, ConvictionTargets, MAX_CONVICTION_BALANCE_BITS,
+        TIMESTAMP_BITS,
+    },
+    deadline::{WindowGadget, WindowStamp, WindowTargets},
+    delegation::DelegationGadget,
+    min_transfer::{connect_min_transfer, MinTransferTargets},
+    prover::{panic_message, InvalidWitness},
+    quadratic::{QuadraticGadget, VotingPolicy},
+    vesting::{VestingGadget, VestingTargets},
+    witness::set_witnesses,
+};
+
+pub struct BalanceUpdateGadget {
+    pub sender_update: DeltaMerkleProofGadget,
+    pub receiver_update: DeltaMerkleProofGadget,
+    /// When set, the update is an identity on `noop_root` and the merkle proofs
+    /// above are ignored for the purpose of chaining roots.
+    pub is_noop: BoolTarget,
+    pub noop_root: HashOutTarget,
+    pub old_root: HashOutTarget,
+    pub new_root: HashOutTarget,
+    pub delegation: DelegationGadget,
+    /// Set on every part of a split vote but the last, see [`UpdateKind::SplitVote`].
+    pub continues_split: BoolTarget,
+    /// In circuits of proposals with conviction voting, see [`super::conviction`].
+    pub conviction: Option<ConvictionGadget>,
+    /// In circuits of proposals with quadratic voting, see [`super::quadratic`].
+    pub quadratic: Option<QuadraticGadget>,
+    /// In circuits of proposals with vesting, see [`super::vesting`].
+    pub vesting: Option<VestingGadget>,
+    /// In circuits of proposals with a deadline, see [`super::deadline`].
+    pub window: Option<WindowGadget>,
+    /// When the update was recorded, in circuits whose updates are stamped with it.
+    pub voted_at: Option<Target>,
+}
+/// Whether an update casts a vote or delegates voting weight, see [`DelegationGadget`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateKind {
+    #[default]
+    Vote,
+    Delegation,
+    /// A vote for part of the sender's balance, continued by a vote from the same
+    /// sender in the next update. The last part of a split vote is a plain
+    /// [`UpdateKind::Vote`] that has to leave the sender without weight, so the
+    /// parts add up to the sender's balance.
+    SplitVote,
+    /// Weight moved back from a tally slot to the voter leaf that cast it, so the
+    /// voter can vote again.
+    Revocation,
+    /// Weight moved back from a delegate to the voter leaf that delegated it,
+    /// clearing the delegation flag of the voter.
+    Undelegation,
+}
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct BalanceUpdate<F: RichField> {
+    pub sender_update: DeltaMerkleProof<F>,
+    pub receiver_update: DeltaMerkleProof<F>,
+    #[serde(default)]
+    pub kind: UpdateKind,
+    /// Set on the updates of proposals with conviction voting, whose votes add
+    /// their weight times [`ConvictionStamp::multiplier`] to the tally.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conviction: Option<ConvictionStamp>,
+    /// How the votes of the proposal the update was recorded for are weighted.
+    #[serde(default, skip_serializing_if = "VotingPolicy::is_linear")]
+    pub policy: VotingPolicy,
+    /// Epoch of the proposal the update was recorded for, on proposals with
+    /// vesting, at which the sender could only spend its unlocked weight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vesting_epoch: Option<u64>,
+    /// When the update was recorded, on proposals with a deadline, see [`super::deadline`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowStamp>,
+    /// Least weight the votes and delegations of the proposal the update was
+    /// recorded for move, see [`super::min_transfer`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_transfer: Option<Weight>,
+}
+impl<F: RichField> BalanceUpdate<F> {
+    /// An identity update that leaves the tree at `root` unchanged, used to pad
+    /// a list of updates up to the size of a cached circuit.
+    pub fn noop(root: WHashOut<F>, tree_height: usize) -> Self {
+        let identity = DeltaMerkleProof {
+            old_root: root,
+            old_value: WHashOut::ZERO,
+            new_root: root,
+            new_value: WHashOut::ZERO,
+            index: F::ZERO,
+            siblings: vec![WHashOut::ZERO; tree_height],
+        };
+        Self {
+            sender_update: identity.clone(),
+            receiver_update: identity,
+            kind: UpdateKind::Vote,
+            conviction: None,
+            policy: VotingPolicy::Linear,
+            vesting_epoch: None,
+            window: None,
+            min_transfer: None,
+        }
+    }
+    pub fn is_noop(&self) -> bool {
+        self.sender_update.old_root == self.sender_update.new_root
+            && self.receiver_update.old_root == self.receiver_update.new_root
+            && self.sender_update.old_root == self.receiver_update.new_root
+    }
+    pub fn old_root(&self) -> WHashOut<F> {
+        self.sender_update.old_root
+    }
+    pub fn new_root(&self) -> WHashOut<F> {
+        self.receiver_update.new_root
+    }
+    /// The weight the receiver gains for each unit the sender loses: the
+    /// conviction multiplier for votes of proposals with conviction voting, one
+    /// otherwise.
+    pub fn multiplier(&self) -> u64 {
+        match (self.kind, &self.conviction) {
+            (UpdateKind::Vote | UpdateKind::SplitVote, Some(stamp)) => stamp.multiplier(),
+            _ => 1,
+        }
+    }
+    /// The weight the receiver gains when the sender loses `sent`: for votes, the
+    /// weight [`Self::policy`] gives what was spent, times [`Self::multiplier`],
+    /// and `sent` as is otherwise.
+    pub fn received_weight(&self, sent: WeightDelta) -> Option<WeightDelta> {
+        match self.kind {
+            UpdateKind::Vote | UpdateKind::SplitVote => {
+                self.policy.vote_weight(sent).checked_mul(self.multiplier())
+            }
+            _ => Some(sent),
+        }
+    }
+    /// Checks that every balance the update touches fits in `balance_bits` bits
+    /// and that the receiver gains the [`Self::received_weight`] of what the
+    /// sender loses, returning the weight the sender loses.
+    pub fn check_weights(&self, balance_bits: usize) -> anyhow::Result<WeightDelta> {
+        let balance = |value: &WHashOut<F>| -> anyhow::Result<Weight> {
+            let weight = Weight::try_from(value.0.elements[0].to_canonical_u64())?;
+            anyhow::ensure!(
+                weight.fits(balance_bits),
+                "balance {} does not fit in {} bits",
+                weight,
+                balance_bits
+            );
+            Ok(weight)
+        };
+        let sent = balance(&self.sender_update.old_value)?
+            .checked_diff(balance(&self.sender_update.new_value)?);
+        let received = balance(&self.receiver_update.new_value)?
+            .checked_diff(balance(&self.receiver_update.old_value)?);
+        match (sent, received) {
+            (Some(sent), Some(received)) if self.received_weight(sent) == Some(received) => {
+                Ok(sent)
+            }
+            _ => Err(anyhow::anyhow!(
+                "the receiver does not gain the weight the sender loses"
+            )),
+        }
+    }
+    /// Checks that the sender keeps the weight its vesting schedule still locks
+    /// at [`Self::vesting_epoch`].
+    pub fn check_unlocked(&self) -> anyhow::Result<()> {
+        let epoch = match self.vesting_epoch {
+            Some(epoch) => epoch,
+            None => return Ok(()),
+        };
+        let old_value = self.sender_update.old_value.0.elements;
+        let locked = old_value[LOCKED_ELEMENT].to_canonical_u64();
+        let unlock_epoch = old_value[UNLOCK_EPOCH_ELEMENT].to_canonical_u64();
+        let balance = self.sender_update.new_value.0.elements[0].to_canonical_u64();
+        anyhow::ensure!(
+            epoch >= unlock_epoch || balance >= locked,
+            "the sender spends {} votes locked until epoch {}",
+            locked - balance,
+            unlock_epoch
+        );
+        Ok(())
+    }
+    /// Checks that a vote or delegation moves at least [`Self::min_transfer`].
+    pub fn check_min_transfer(&self) -> anyhow::Result<()> {
+        let minimum = match self.min_transfer {
+            Some(minimum) if !self.is_noop() => minimum,
+            _ => return Ok(()),
+        };
+        if matches!(self.kind, UpdateKind::Revocation | UpdateKind::Undelegation) {
+            return Ok(());
+        }
+        let sent = self.sender_update.old_value.0.elements[0]
+            .to_canonical_u64()
+            .saturating_sub(self.sender_update.new_value.0.elements[0].to_canonical_u64());
+        anyhow::ensure!(
+            sent >= minimum.get(),
+            "the update moves {} votes, less than the minimum of {}",
+            sent,
+            minimum
+        );
+        Ok(())
+    }
+    /// When the update was recorded, on proposals whose updates are stamped with it.
+    pub fn voted_at(&self) -> Option<u64> {
+        self.window
+            .map(|stamp| stamp.voted_at)
+            .or(self.conviction.map(|stamp| stamp.voted_at))
+    }
+    /// Checks that the update was recorded within the voting window it is stamped with.
+    pub fn check_within_window(&self) -> anyhow::Result<()> {
+        if let Some(stamp) = self.window {
+            anyhow::ensure!(
+                stamp.is_within(),
+                "the update was recorded at {}, outside the voting window from {} to {}",
+                stamp.voted_at,
+                stamp.opens_at,
+                stamp.deadline
+            );
+        }
+        Ok(())
+    }
+}
+/// Constrains `receiver_update` to gain what `sender_update` loses, in the tree
+/// the sender update leaves behind, returning the weight moved. Balances are
+/// range checked to `balance_bits` bits.
+pub fn connect_transfer<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    sender_update: &DeltaMerkleProofGadget,
+    receiver_update: &DeltaMerkleProofGadget,
+    balance_bits: usize,
+) -> Target {
+    connect_weighted_transfer(builder, sender_update, receiver_update, balance_bits, None)
+}
+
+/// Like [`connect_transfer`], with the receiver gaining `received` rather than
+/// what the sender loses if it is given. The caller constrains `received` in
+/// terms of the returned weight moved.
+pub fn connect_weighted_transfer<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    sender_update: &DeltaMerkleProofGadget,
+    receiver_update: &DeltaMerkleProofGadget,
+    balance_bits: usize,
+    received: Option<Target>,
+) -> Target {
+    let amount_recv = builder.sub(
+        receiver_update.new_value.elements[0],
+        receiver_update.old_value.elements[0],
+    );
+    let amount_send = builder.sub(
+        sender_update.old_value.elements[0],
+        sender_update.new_value.elements[0],
+    );
+    builder.connect(amount_recv, received.unwrap_or(amount_send));
+
+    // Range checks all four balances, and that the receiver gains and the sender loses
+    // weight, so neither side can wrap around the field
+    let true_target = builder.one();
+    for (lower, upper) in [
+        (
+            receiver_update.old_value.elements[0],
+            receiver_update.new_value.elements[0],
+        ),
+        (
+            sender_update.new_value.elements[0],
+            sender_update.old_value.elements[0],
+        ),
+    ] {
+        let is_le = list_le_circuit(builder, vec![lower], vec![upper], balance_bits);
+        builder.connect(is_le.target, true_target);
+    }
+
+    builder.connect_hashes(sender_update.new_root, receiver_update.old_root);
+    amount_send
+}
+
+impl BalanceUpdateGadget {
+    /// Adds an update between leaves whose balances are range checked to
+    /// `balance_bits` bits, at most [`MAX_BALANCE_BITS`].
+    pub fn add_virtual_to<H: AlgebraicHasher<F>, F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+        tree_height: usize,
+        balance_bits: usize,
+    ) -> Self {
+        Self::add_virtual_weighted_to::<H, F, D>(
+            builder,
+            tree_height,
+            balance_bits,
+            None,
+            VotingPolicy::Linear,
+            None,
+            None,
+        )
+    }
+    /// Like [`Self::add_virtual_to`], with votes weighted by `voting_policy` and
+    /// by the conviction multiplier the update was recorded with when
+    /// `conviction` is given. Unless votes count as cast, revocations are
+    /// rejected, as they would have to take the weighted vote out of the tally
+    /// but give the voter back what they cast. When `vesting` is given, the
+    /// sender keeps the weight its schedule still locks at the epoch. When
+    /// `conviction` or `window` is given, the update is recorded at the time it
+    /// writes to the clock of the tally slot it touches, which the multiplier
+    /// derives from and which lies within the voting window.
+    pub fn add_virtual_weighted_to<
+        H: AlgebraicHasher<F>,
+        F: RichField + Extendable<D>,
+        const D: usize,
+    >(
+        builder: &mut CircuitBuilder<F, D>,
+        tree_height: usize,
+        balance_bits: usize,
+        conviction: Option<&ConvictionTargets>,
+        voting_policy: VotingPolicy,
+        vesting: Option<&VestingTargets>,
+        window: Option<&WindowTargets>,
+    ) -> Self {
+        assert!(
+            balance_bits <= MAX_BALANCE_BITS,
+            "balances can be at most {} bits wide",
+            MAX_BALANCE_BITS
+        );
+        let sender_update = DeltaMerkleProofGadget::add_virtual_to::<H, F, D>(builder, tree_height);
+        let receiver_update =
+            DeltaMerkleProofGadget::add_virtual_to::<H, F, D>(builder, tree_height);
+        // Set from the weighting gadgets below, once the kind of the update is known
+        let weighted = conviction.is_some() || !voting_policy.is_linear();
+        let received = weighted.then(|| builder.add_virtual_target());
+        let amount = connect_weighted_transfer(
+            builder,
+            &sender_update,
+            &receiver_update,
+            balance_bits,
+            received,
+        );
+
+        let is_noop = builder.add_virtual_bool_target_safe();
+        let noop_root = builder.add_virtual_hash();
+        let old_root = builder.select_hash(is_noop, noop_root, sender_update.old_root);
+        let new_root = builder.select_hash(is_noop, noop_root, receiver_update.new_root);
+        let voted_at =
+            (conviction.is_some() || window.is_some()).then(|| builder.add_virtual_target());
+        let delegation = DelegationGadget::add_virtual_to(
+            builder,
+            &sender_update,
+            &receiver_update,
+            is_noop,
+            voted_at,
+        );
+
+        // Only votes are split
+        let continues_split = builder.add_virtual_bool_target_safe();
+        let split_noop = builder.mul(continues_split.target, is_noop.target);
+        builder.assert_zero(split_noop);
+        let split_delegation = builder.mul(continues_split.target, delegation.is_delegation.target);
+        builder.assert_zero(split_delegation);
+        let split_revocation = builder.mul(continues_split.target, delegation.is_revocation.target);
+        builder.assert_zero(split_revocation);
+        let split_undelegation =
+            builder.mul(continues_split.target, delegation.is_undelegation.target);
+        builder.assert_zero(split_undelegation);
+
+        // Votes are weighted, delegations move weight as is. Undelegations are only
+        // taken by circuits that weigh nothing, asserted below
+        let is_vote = builder.not(delegation.is_delegation);
+        let mut vote_weight = amount;
+        let quadratic = (voting_policy == VotingPolicy::Quadratic).then(|| {
+            let gadget = QuadraticGadget::add_virtual_to(builder, amount, balance_bits);
+            vote_weight = builder.select(is_vote, gadget.weight, amount);
+            gadget
+        });
+        let conviction = conviction.map(|params| {
+            let gadget = ConvictionGadget::add_virtual_to(builder, params, voted_at.unwrap());
+            let one = builder.one();
+            let multiplier = builder.mul_add(is_vote.target, gadget.quotient, one);
+            vote_weight = builder.mul(vote_weight, multiplier);
+            gadget
+        });
+        if let Some(received) = received {
+            builder.connect(received, vote_weight);
+            builder.assert_zero(delegation.is_revocation.target);
+            builder.assert_zero(delegation.is_undelegation.target);
+        }
+        let vesting = vesting.map(|params| {
+            VestingGadget::add_virtual_to(builder, params, &sender_update, balance_bits)
+        });
+        let window =
+            window.map(|params| WindowGadget::add_virtual_to(builder, params, voted_at.unwrap()));
+        Self {
+            sender_update,
+            receiver_update,
+            is_noop,
+            noop_root,
+            old_root,
+            new_root,
+            delegation,
+            continues_split,
+            conviction,
+            quadratic,
+            vesting,
+            window,
+            voted_at,
+        }
+    }
+    pub fn set_witness_proof<F: RichField>(
+        &self,
+        witness: &mut impl WitnessWrite<F>,
+        input: &BalanceUpdate<F>,
+    ) {
+        self.sender_update
+            .set_witness_proof(witness, &input.sender_update);
+        self.receiver_update
+            .set_witness_proof(witness, &input.receiver_update);
+        witness.set_bool_target(self.is_noop, input.is_noop());
+        witness.set_hash_target(self.noop_root, input.old_root().0);
+        self.delegation.set_witness(witness, input);
+        witness.set_bool_target(self.continues_split, input.kind == UpdateKind::SplitVote);
+        if let (Some(gadget), Some(stamp)) = (&self.conviction, &input.conviction) {
+            gadget.set_witness(witness, stamp);
+        }
+        if let (Some(target), Some(voted_at)) = (self.voted_at, input.voted_at()) {
+            witness.set_target(target, F::from_canonical_u64(voted_at));
+        }
+        if let Some(gadget) = &self.quadratic {
+            let spent = input.sender_update.old_value.0.elements[0]
+                .to_canonical_u64()
+                .saturating_sub(input.sender_update.new_value.0.elements[0].to_canonical_u64());
+            gadget.set_witness(witness, spent);
+        }
+    }
+}
+
+/// The parameters an [`UpdateBalanceCircuit`] is built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UpdateBalanceShape {
+    pub number_updates: usize,
+    pub tree_height: usize,
+    pub balance_bits: usize,
+    /// Whether votes are weighted by conviction, see [`super::conviction`].
+    pub conviction: bool,
+    /// How votes are weighted by what they spend, see [`super::quadratic`].
+    pub voting_policy: VotingPolicy,
+    /// Whether the proof exposes the results of the proposals the proposal
+    /// depends on, see [`crate::proposal::dependency`].
+    pub dependencies: bool,
+    /// Whether senders keep the weight their vesting schedules lock, see
+    /// [`super::vesting`].
+    pub vesting: bool,
+    /// Whether updates are bound to the voting window of the proposal, see
+    /// [`super::deadline`].
+    pub deadline: bool,
+    /// Whether votes and delegations move at least a minimum weight, see
+    /// [`super::min_transfer`].
+    pub min_transfer: bool,
+}
+
+/// Most updates an [`UpdateBalanceCircuit`] is built for. Building a circuit for
+/// more would take more memory than a prover has, so such shapes are rejected
+/// rather than built.
+pub const MAX_CIRCUIT_UPDATES: usize = 1 << 16;
+
+impl UpdateBalanceShape {
+    /// Fails unless [`UpdateBalanceCircuit::new`] can build a circuit of this
+    /// shape, which it otherwise panics on, within [`MAX_CIRCUIT_UPDATES`] updates
+    /// and [`MAX_TREE_HEIGHT`] levels.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.number_updates > 0 && self.number_updates <= MAX_CIRCUIT_UPDATES,
+            "circuits prove between 1 and {} updates, not {}",
+            MAX_CIRCUIT_UPDATES,
+            self.number_updates
+        );
+        anyhow::ensure!(
+            self.tree_height > 0 && self.tree_height <= MAX_TREE_HEIGHT as usize,
+            "balance trees are between 1 and {} levels high, not {}",
+            MAX_TREE_HEIGHT,
+            self.tree_height
+        );
+        let max_balance_bits = if self.conviction {
+            MAX_CONVICTION_BALANCE_BITS
+        } else {
+            MAX_BALANCE_BITS
+        };
+        anyhow::ensure!(
+            self.balance_bits > 0 && self.balance_bits <= max_balance_bits,
+            "balances of the circuit are between 1 and {} bits wide, not {}",
+            max_balance_bits,
+            self.balance_bits
+        );
+        Ok(())
+    }
+}
+
+/// Identifies the shape of an [`UpdateBalanceCircuit`] in a
+/// [`ProofEnvelope`](crate::proof::codec::ProofEnvelope).
+pub fn update_balance_circuit_id(shape: &UpdateBalanceShape) -> String {
+    let mut id = format!(
+        "update_balance:{}:{}:{}",
+        shape.number_updates, shape.tree_height, shape.balance_bits
+    );
+    if shape.conviction {
+        id = format!("{}:{}", id, CONVICTION_CIRCUIT_SUFFIX);
+    }
+    if shape.voting_policy == VotingPolicy::Quadratic {
+        id = format!("{}:{}", id, QUADRATIC_CIRCUIT_SUFFIX);
+    }
+    if shape.dependencies {
+        id = format!("{}:{}", id, DEPENDENCIES_CIRCUIT_SUFFIX);
+    }
+    if shape.vesting {
+        id = format!("{}:{}", id, VESTING_CIRCUIT_SUFFIX);
+    }
+    if shape.deadline {
+        id = format!("{}:{}", id, DEADLINE_CIRCUIT_SUFFIX);
+    }
+    if shape.min_transfer {
+        id = format!("{}:{}", id, MIN_TRANSFER_CIRCUIT_SUFFIX);
+    }
+    id
+}
+
+const CONVICTION_CIRCUIT_SUFFIX: &str = "conviction";
+const QUADRATIC_CIRCUIT_SUFFIX: &str = "quadratic";
+const DEPENDENCIES_CIRCUIT_SUFFIX: &str = "dependencies";
+const VESTING_CIRCUIT_SUFFIX: &str = "vesting";
+const DEADLINE_CIRCUIT_SUFFIX: &str = "deadline";
+const MIN_TRANSFER_CIRCUIT_SUFFIX: &str = "min_transfer";
+
+/// Inverse of [`update_balance_circuit_id`]. Ids come from proof envelopes and
+/// snapshots, which anyone can craft, so shapes no circuit can be built for are
+/// rejected, see [`UpdateBalanceShape::validate`].
+pub fn parse_update_balance_circuit_id(circuit_id: &str) -> anyhow::Result<UpdateBalanceShape> {
+    let parts: Vec<&str> = circuit_id.split(':').collect();
+    anyhow::ensure!(
+        parts.len() >= 4 && parts[0] == "update_balance",
+        "unknown circuit id {}",
+        circuit_id
+    );
+    let shape = UpdateBalanceShape {
+        number_updates: parts[1].parse()?,
+        tree_height: parts[2].parse()?,
+        balance_bits: parts[3].parse()?,
+        conviction: parts[4..].contains(&CONVICTION_CIRCUIT_SUFFIX),
+        voting_policy: if parts[4..].contains(&QUADRATIC_CIRCUIT_SUFFIX) {
+            VotingPolicy::Quadratic
+        } else {
+            VotingPolicy::Linear
+        },
+        dependencies: parts[4..].contains(&DEPENDENCIES_CIRCUIT_SUFFIX),
+        vesting: parts[4..].contains(&VESTING_CIRCUIT_SUFFIX),
+        deadline: parts[4..].contains(&DEADLINE_CIRCUIT_SUFFIX),
+        min_transfer: parts[4..].contains(&MIN_TRANSFER_CIRCUIT_SUFFIX),
+    };
+    // Rejects unknown, repeated or reordered suffixes
+    anyhow::ensure!(
+        update_balance_circuit_id(&shape) == circuit_id,
+        "unknown circuit id {}",
+        circuit_id
+    );
+    shape
+        .validate()
+        .map_err(|err| anyhow::anyhow!("invalid circuit id {}: {}", circuit_id, err))?;
+    Ok(shape)
+}
+
+/// Updates are padded with no-ops up to the next power of two, so that a small
+/// number of circuits can be cached and reused across proposals.
+pub fn padded_update_count(number_updates: usize) -> usize {
+    number_updates.max(1).next_power_of_two()
+}
+
+/// Pads `updates` with no-ops on the final root up to [`padded_update_count`].
+pub fn pad_updates<F: RichField>(
+    updates: &[BalanceUpdate<F>],
+    tree_height: usize,
+) -> Vec<BalanceUpdate<F>> {
+    let mut padded = updates.to_vec();
+    if let Some(last) = updates.last() {
+        let mut noop = BalanceUpdate::noop(last.new_root(), tree_height);
+        // Recorded at the time of the last update, so timestamps keep increasing
+        noop.conviction = last.conviction;
+        noop.policy = last.policy;
+        noop.vesting_epoch = last.vesting_epoch;
+        noop.window = last.window;
+        noop.min_transfer = last.min_transfer;
+        padded.resize(padded_update_count(updates.len()), noop);
+    }
+    padded
+}
+
+// Layout of the public inputs of an [`UpdateBalanceCircuit`] proof, shared with the
+// standalone verifier so that the two cannot drift apart.
+pub use qed_verifier::{
+    PublicInputLayout, ACTION_HASH_PUBLIC_INPUTS, CONVICTION_DEADLINE_PUBLIC_INPUT,
+    CONVICTION_STEP_PUBLIC_INPUT, FINAL_ROOT_PUBLIC_INPUTS, INITIAL_ROOT_PUBLIC_INPUTS,
+    NO_VOTES_PUBLIC_INPUT, STATEMENT_HASH_PUBLIC_INPUTS, YES_VOTES_PUBLIC_INPUT,
+};
+
+/// Where the optional public inputs of the circuit of `shape` are exposed.
+pub fn public_input_layout(shape: &UpdateBalanceShape) -> PublicInputLayout {
+    PublicInputLayout {
+        conviction: shape.conviction,
+        quadratic: shape.voting_policy == VotingPolicy::Quadratic,
+        dependencies: shape.dependencies,
+        vesting: shape.vesting,
+        deadline: shape.deadline,
+        min_transfer: shape.min_transfer,
+        shards: None,
+    }
+}
+
+/// Where the hash of the results of the proposals a proposal depends on is
+/// exposed, see [`crate::proposal::dependency::compute_dependencies_hash`]:
+/// after the action hash and the conviction inputs, only in circuits of
+/// proposals with dependencies.
+pub fn dependencies_hash_public_inputs(
+    shape: &UpdateBalanceShape,
+) -> Option<std::ops::Range<usize>> {
+    public_input_layout(shape).dependencies_hash_range()
+}
+
+/// Where the epoch vesting schedules unlock against is exposed, see
+/// [`super::vesting`]: only in circuits of proposals with vesting.
+pub fn vesting_epoch_public_input(shape: &UpdateBalanceShape) -> Option<usize> {
+    public_input_layout(shape).vesting_epoch_index()
+}
+
+/// Where the opening and the deadline of the voting window are exposed, see
+/// [`super::deadline`]: only in circuits of proposals with a deadline.
+pub fn voting_window_public_inputs(shape: &UpdateBalanceShape) -> Option<std::ops::Range<usize>> {
+    public_input_layout(shape).voting_window_range()
+}
+
+/// Where the minimum weight votes and delegations move is exposed, see
+/// [`super::min_transfer`]: last, only in circuits of proposals with a minimum.
+pub fn min_transfer_public_input(shape: &UpdateBalanceShape) -> Option<usize> {
+    public_input_layout(shape).min_transfer_index()
+}
+
+pub struct UpdateBalanceCircuit<
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    const D: usize,
+> where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub shape: UpdateBalanceShape,
+    pub updates: Vec<BalanceUpdateGadget>,
+    /// Read-only proofs of the no and yes tally slots against the final root.
+    pub tallies: [MerkleProofGadget; 2],
+    /// Exposed as is, tying the proof to the statement of the proposal it was made for.
+    pub statement_hash: HashOutTarget,
+    /// Exposed as is, like the statement hash.
+    pub action_hash: HashOutTarget,
+    /// Exposed after the action hash, in circuits of proposals with conviction voting.
+    pub conviction: Option<ConvictionTargets>,
+    /// Exposed as is at [`dependencies_hash_public_inputs`], in circuits of
+    /// proposals with dependencies.
+    pub dependencies_hash: Option<HashOutTarget>,
+    /// Exposed at [`vesting_epoch_public_input`], in circuits of proposals with vesting.
+    pub vesting: Option<VestingTargets>,
+    /// Exposed at [`voting_window_public_inputs`], in circuits of proposals with a deadline.
+    pub window: Option<WindowTargets>,
+    /// Exposed at [`min_transfer_public_input`], in circuits of proposals with a minimum.
+    pub min_transfer: Option<MinTransferTargets>,
+    pub base_circuit_data: CircuitData<F, C, D>,
+}
+
+impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
+    UpdateBalanceCircuit<F, C, D>
+where
+    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+{
+    pub fn new(shape: UpdateBalanceShape) -> Self {
+        let UpdateBalanceShape {
+            number_updates,
+            tree_height,
+            balance_bits,
+            conviction,
+            voting_policy,
+            dependencies,
+            vesting,
+            deadline,
+            min_transfer,
+        } = shape;
+        assert!(
+            number_updates > 0,
+            "the circuit proves at least one update, a no-op if nobody voted"
+        );
+        assert!(
+            !conviction || balance_bits <= MAX_CONVICTION_BALANCE_BITS,
+            "balances of proposals with conviction voting can be at most {} bits wide",
+            MAX_CONVICTION_BALANCE_BITS
+        );
+        let config = CircuitConfig::standard_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+        let conviction = conviction.then(|| ConvictionTargets::add_virtual_to(&mut builder));
+        let vesting = vesting.then(|| VestingTargets::add_virtual_to(&mut builder));
+        let window = deadline.then(|| WindowTargets::add_virtual_to(&mut builder));
+        let updates: Vec<BalanceUpdateGadget> = (0..number_updates)
+            .map(|_| {
+                BalanceUpdateGadget::add_virtual_weighted_to::<C::Hasher, F, D>(
+                    &mut builder,
+                    tree_height,
+                    balance_bits,
+                    conviction.as_ref(),
+                    voting_policy,
+                    vesting.as_ref(),
+                    window.as_ref(),
+                )
+            })
+            .collect();
+        for i in 1..number_updates {
+            builder.connect_hashes(updates[i - 1].new_root, updates[i].old_root);
+        }
+        let min_transfer = min_transfer.then(|| MinTransferTargets::add_virtual_to(&mut builder));
+        if let Some(params) = &min_transfer {
+            for update in &updates {
+                connect_min_transfer(&mut builder, params, update, balance_bits);
+            }
+        }
+        // Updates are recorded in time order
+        if conviction.is_some() || window.is_some() {
+            let true_target = builder.one();
+            for i in 1..number_updates {
+                let in_order = list_le_circuit(
+                    &mut builder,
+                    vec![updates[i - 1].voted_at.unwrap()],
+                    vec![updates[i].voted_at.unwrap()],
+                    TIMESTAMP_BITS,
+                );
+                builder.connect(in_order.target, true_target);
+            }
+        }
+        // A split vote continues with a vote from the same sender, and its last part
+        // leaves the sender without weight
+        for i in 0..number_updates {
+            if i + 1 == number_updates {
+                builder.assert_zero(updates[i].continues_split.target);
+            } else {
+                let next_sender = builder.sub(
+                    updates[i].sender_update.index,
+                    updates[i + 1].sender_update.index,
+                );
+                let other_sender = builder.mul(updates[i].continues_split.target, next_sender);
+                builder.assert_zero(other_sender);
+            }
+            if i > 0 {
+                let is_last_part = builder.not(updates[i].continues_split);
+                let ends_split = builder.and(updates[i - 1].continues_split, is_last_part);
+                let left_over = builder.mul(
+                    ends_split.target,
+                    updates[i].sender_update.new_value.elements[0],
+                );
+                builder.assert_zero(left_over);
+                for kind in [
+                    updates[i].is_noop,
+                    updates[i].delegation.is_delegation,
+                    updates[i].delegation.is_undelegation,
+                ] {
+                    let not_a_vote =
+                        builder.mul(updates[i - 1].continues_split.target, kind.target);
+                    builder.assert_zero(not_a_vote);
+                }
+            }
+        }
+        let final_root = updates[updates.len() - 1].new_root;
+        let tallies = [TallySlot::NO, TallySlot::YES].map(|slot| {
+            let index = builder.constant(F::from_canonical_u64(slot.index()));
+            MerkleProofGadget::add_virtual_to_with_options::<C::Hasher, F, D>(
+                &mut builder,
+                tree_height,
+                OptionalMerkleProofGadget {
+                    root: Some(final_root),
+                    value: None,
+                    index: Some(index),
+                    siblings: None,
+                },
+            )
+        });
+        builder.register_public_inputs(&updates[0].old_root.elements);
+        builder.register_public_inputs(&final_root.elements);
+        builder.register_public_input(tallies[0].value.elements[0]);
+        builder.register_public_input(tallies[1].value.elements[0]);
+        let statement_hash = builder.add_virtual_hash();
+        builder.register_public_inputs(&statement_hash.elements);
+        let action_hash = builder.add_virtual_hash();
+        builder.register_public_inputs(&action_hash.elements);
+        if let Some(params) = &conviction {
+            builder.register_public_input(params.deadline);
+            builder.register_public_input(params.step_secs);
+        }
+        let dependencies_hash = dependencies.then(|| {
+            let hash = builder.add_virtual_hash();
+            builder.register_public_inputs(&hash.elements);
+            hash
+        });
+        if let Some(params) = &vesting {
+            builder.register_public_input(params.epoch);
+        }
+        if let Some(params) = &window {
+            builder.register_public_input(params.opens_at);
+            builder.register_public_input(params.deadline);
+        }
+        if let Some(params) = &min_transfer {
+            builder.register_public_input(params.minimum);
+        }
+        let base_circuit_data = builder.build::<C>();
+        Self {
+            shape,
+            updates,
+            tallies,
+            statement_hash,
+            action_hash,
+            conviction,
+            dependencies_hash,
+            vesting,
+            window,
+            min_transfer,
+            base_circuit_data,
+        }
+    }
+    /// Proves the chained `proofs` of the proposal whose statement and action hash
+    /// to `statement_hash` and `action_hash`, with `tally_proofs` being the proofs
+    /// of the no and yes tally slots in the final tree.
+    pub fn prove(
+        &self,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        proofs: &Vec<BalanceUpdate<F>>,
+        tally_proofs: &[MerkleProof<F>; 2],
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        self.prove_with_dependencies(statement_hash, action_hash, None, proofs, tally_proofs)
+    }
+    /// Like [`Self::prove`], also exposing `dependencies_hash`, which circuits of
+    /// proposals with dependencies require and others do not take.
+    pub fn prove_with_dependencies(
+        &self,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        dependencies_hash: Option<WHashOut<F>>,
+        proofs: &Vec<BalanceUpdate<F>>,
+        tally_proofs: &[MerkleProof<F>; 2],
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        assert_eq!(proofs.len(), self.updates.len());
+        // Fails here rather than with an unsatisfiable witness inside plonky2
+        if let Some(violation) = self
+            .input_violations(dependencies_hash, proofs)
+            .into_iter()
+            .next()
+        {
+            return Err(InvalidWitness(violation).into());
+        }
+        let pw = self.assign_witness(
+            statement_hash,
+            action_hash,
+            dependencies_hash,
+            proofs,
+            tally_proofs,
+        );
+        self.base_circuit_data.prove(pw)
+    }
+    /// Runs the witness generation of a proof of `proofs` without proving, for a
+    /// dry run of a finalization. Returns every reason [`Self::prove_with_dependencies`]
+    /// rejects the updates for up front or, once they pass, the first constraint
+    /// between targets the generated witness breaks, e.g. updates not chaining up
+    /// or tally proofs against another root. Constraints checked only by the gates,
+    /// like the range checks, are left to the proof.
+    pub fn check_witness(
+        &self,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        dependencies_hash: Option<WHashOut<F>>,
+        proofs: &Vec<BalanceUpdate<F>>,
+        tally_proofs: &[MerkleProof<F>; 2],
+    ) -> Vec<String> {
+        if proofs.len() != self.updates.len() {
+            return vec![format!(
+                "the circuit takes {} updates, not {}",
+                self.updates.len(),
+                proofs.len()
+            )];
+        }
+        let violations: Vec<String> = self
+            .input_violations(dependencies_hash, proofs)
+            .iter()
+            .map(|violation| format!("{:#}", violation))
+            .collect();
+        if !violations.is_empty() {
+            return violations;
+        }
+        // plonky2 panics on a target generated with two different values
+        let generated = catch_unwind(AssertUnwindSafe(|| {
+            let pw = self.assign_witness(
+                statement_hash,
+                action_hash,
+                dependencies_hash,
+                proofs,
+                tally_proofs,
+            );
+            generate_partial_witness(
+                pw,
+                &self.base_circuit_data.prover_only,
+                &self.base_circuit_data.common,
+            );
+        }));
+        match generated {
+            Ok(()) => vec![],
+            Err(payload) => vec![panic_message(&*payload)],
+        }
+    }
+    /// Every reason the circuit cannot be satisfied with `proofs` that is known
+    /// before setting the witness.
+    fn input_violations(
+        &self,
+        dependencies_hash: Option<WHashOut<F>>,
+        proofs: &[BalanceUpdate<F>],
+    ) -> Vec<anyhow::Error> {
+        let mut violations = vec![];
+        if dependencies_hash.is_some() != self.shape.dependencies {
+            violations.push(anyhow::anyhow!(
+                "the circuit {} a dependencies hash",
+                if self.shape.dependencies {
+                    "requires"
+                } else {
+                    "does not take"
+                }
+            ));
+        }
+        for update in proofs {
+            if let Err(err) = update.check_weights(self.shape.balance_bits) {
+                violations.push(err);
+            }
+            if let Err(err) = update.check_unlocked() {
+                violations.push(err);
+            }
+            if let Err(err) = update.check_within_window() {
+                violations.push(err);
+            }
+            if let Err(err) = update.check_min_transfer() {
+                violations.push(err);
+            }
+            if update.policy != self.shape.voting_policy {
+                violations.push(anyhow::anyhow!(
+                    "the update is weighted {:?} but the circuit {:?}",
+                    update.policy,
+                    self.shape.voting_policy
+                ));
+            }
+        }
+        if let Err(err) = self.check_conviction(proofs) {
+            violations.push(err);
+        }
+        if let Err(err) = self.check_vesting(proofs) {
+            violations.push(err);
+        }
+        if let Err(err) = self.check_window(proofs) {
+            violations.push(err);
+        }
+        if let Err(err) = self.check_minimum(proofs) {
+            violations.push(err);
+        }
+        violations
+    }
+    /// Sets the witness of a proof of `proofs`, which have passed
+    /// [`Self::input_violations`].
+    fn assign_witness(
+        &self,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        dependencies_hash: Option<WHashOut<F>>,
+        proofs: &Vec<BalanceUpdate<F>>,
+        tally_proofs: &[MerkleProof<F>; 2],
+    ) -> PartialWitness<F> {
+        let mut pw = PartialWitness::<F>::new();
+        if let (Some(params), Ok(Some(stamp))) = (&self.conviction, self.check_conviction(proofs)) {
+            params.set_witness(&mut pw, &stamp);
+        }
+        if let (Some(params), Ok(Some(epoch))) = (&self.vesting, self.check_vesting(proofs)) {
+            params.set_witness(&mut pw, epoch);
+        }
+        if let (Some(params), Ok(Some(stamp))) = (&self.window, self.check_window(proofs)) {
+            params.set_witness(&mut pw, &stamp);
+        }
+        if let (Some(params), Ok(Some(minimum))) = (&self.min_transfer, self.check_minimum(proofs))
+        {
+            params.set_witness(&mut pw, minimum);
+        }
+        set_witnesses(&mut pw, &self.updates, proofs, |update, witness, proof| {
+            update.set_witness_proof(witness, proof)
+        });
+        for (tally, proof) in self.tallies.iter().zip(tally_proofs.iter()) {
+            tally.set_witness_proof(&mut pw, proof);
+        }
+        pw.set_hash_target(self.statement_hash, statement_hash.0);
+        pw.set_hash_target(self.action_hash, action_hash.0);
+        if let (Some(target), Some(hash)) = (self.dependencies_hash, dependencies_hash) {
+            pw.set_hash_target(target, hash.0);
+        }
+        pw
+    }
+    /// Checks that the updates carry conviction stamps if and only if the circuit
+    /// weighs votes by conviction, all for the same deadline and step length,
+    /// returning the stamp of the first update.
+    fn check_conviction(
+        &self,
+        proofs: &[BalanceUpdate<F>],
+    ) -> anyhow::Result<Option<ConvictionStamp>> {
+        let first = proofs.first().and_then(|update| update.conviction);
+        for update in proofs {
+            anyhow::ensure!(
+                update.conviction.is_some() == self.shape.conviction,
+                "the circuit {} conviction stamps",
+                if self.shape.conviction {
+                    "requires"
+                } else {
+                    "does not take"
+                }
+            );
+            if let (Some(stamp), Some(first)) = (update.conviction, first) {
+                anyhow::ensure!(
+                    (stamp.deadline, stamp.step_secs) == (first.deadline, first.step_secs),
+                    "the updates are stamped with different conviction rules"
+                );
+            }
+        }
+        Ok(first)
+    }
+    /// Checks that the updates carry a vesting epoch if and only if the circuit
+    /// takes one, all the same, returning it.
+    fn check_vesting(&self, proofs: &[BalanceUpdate<F>]) -> anyhow::Result<Option<u64>> {
+        let first = proofs.first().and_then(|update| update.vesting_epoch);
+        for update in proofs {
+            anyhow::ensure!(
+                update.vesting_epoch.is_some() == self.shape.vesting,
+                "the circuit {} a vesting epoch",
+                if self.shape.vesting {
+                    "requires"
+                } else {
+                    "does not take"
+                }
+            );
+            anyhow::ensure!(
+                update.vesting_epoch == first,
+                "the updates are recorded at different vesting epochs"
+            );
+        }
+        Ok(first)
+    }
+    /// Checks that the updates carry window stamps if and only if the circuit
+    /// binds them to a voting window, all the same and in time order, returning
+    /// the stamp of the first update.
+    fn check_window(&self, proofs: &[BalanceUpdate<F>]) -> anyhow::Result<Option<WindowStamp>> {
+        let first = proofs.first().and_then(|update| update.window);
+        for update in proofs {
+            anyhow::ensure!(
+                update.window.is_some() == self.shape.deadline,
+                "the circuit {} window stamps",
+                if self.shape.deadline {
+                    "requires"
+                } else {
+                    "does not take"
+                }
+            );
+            if let (Some(stamp), Some(first)) = (update.window, first) {
+                anyhow::ensure!(
+                    (stamp.opens_at, stamp.deadline) == (first.opens_at, first.deadline),
+                    "the updates are stamped with different voting windows"
+                );
+            }
+        }
+        for pair in proofs.windows(2) {
+            anyhow::ensure!(
+                pair[0].voted_at() <= pair[1].voted_at(),
+                "the update stamped at {:?} is proven after one stamped at {:?}",
+                pair[1].voted_at(),
+                pair[0].voted_at()
+            );
+        }
+        Ok(first)
+    }
+    /// Checks that the updates carry a minimum transfer if and only if the
+    /// circuit takes one, all the same, returning it.
+    fn check_minimum(&self, proofs: &[BalanceUpdate<F>]) -> anyhow::Result<Option<Weight>> {
+        let first = proofs.first().and_then(|update| update.min_transfer);
+        for update in proofs {
+            anyhow::ensure!(
+                update.min_transfer.is_some() == self.shape.min_transfer,
+                "the circuit {} a minimum transfer",
+                if self.shape.min_transfer {
+                    "requires"
+                } else {
+                    "does not take"
+                }
+            );
+            anyhow::ensure!(
+                update.min_transfer == first,
+                "the updates are held to different minimum transfers"
+            );
+        }
+        Ok(first)
+    }
+    /// Proves `proofs` like [`Self::prove`] and checks the proof before packing it
+    /// into the envelope handed out to verifiers.
+    pub fn prove_envelope(
+        &self,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        proofs: &Vec<BalanceUpdate<F>>,
+        tally_proofs: &[MerkleProof<F>; 2],
+    ) -> anyhow::Result<ProofEnvelope> {
+        self.prove_envelope_with_dependencies(
+            statement_hash,
+            action_hash,
+            None,
+            proofs,
+            tally_proofs,
+        )
+    }
+    /// Like [`Self::prove_envelope`], see [`Self::prove_with_dependencies`].
+    pub fn prove_envelope_with_dependencies(
+        &self,
+        statement_hash: WHashOut<F>,
+        action_hash: WHashOut<F>,
+        dependencies_hash: Option<WHashOut<F>>,
+        proofs: &Vec<BalanceUpdate<F>>,
+        tally_proofs: &[MerkleProof<F>; 2],
+    ) -> anyhow::Result<ProofEnvelope> {
+        let proof = self.prove_with_dependencies(
+            statement_hash,
+            action_hash,
+            dependencies_hash,
+            proofs,
+            tally_proofs,
+        )?;
+        let envelope = ProofEnvelope::new(
+            &update_balance_circuit_id(&self.shape),
+            &self.base_circuit_data,
+            &proof,
+        );
+        self.base_circuit_data.verify(proof)?;
+        Ok(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+        plonk::config::PoseidonGoldilocksConfig,
+    };
+    use proptest::prelude::*;
+
+    use super::{
+        pad_updates, padded_update_count, parse_update_balance_circuit_id,
+        update_balance_circuit_id, BalanceUpdate, UpdateBalanceCircuit, UpdateBalanceShape,
+        UpdateKind, ACTION_HASH_PUBLIC_INPUTS, INITIAL_ROOT_PUBLIC_INPUTS, NO_VOTES_PUBLIC_INPUT,
+        STATEMENT_HASH_PUBLIC_INPUTS, YES_VOTES_PUBLIC_INPUT,
+    };
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoteSplit, VoterLeaf},
+            storage::BalanceStorage,
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            quadratic::VotingPolicy,
+            test_fixtures::{
+                cast_votes, linear_shape, prove_fixture, proves, raw_transfers, seeded_votes,
+                vote_sequences, CHAINED_UPDATES_CIRCUIT, FIXTURE_BALANCE_BITS,
+                FIXTURE_UPDATE_COUNT, SINGLE_UPDATE_CIRCUIT,
+            },
+        },
+        common::WHashOut,
+        proof::certificate::{compute_action_hash, compute_statement_hash},
+        proposal::{action::ProposalAction, rules::ProposalRules, Proposal},
+        utils::zmt::node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+    };
+
+    type F = GoldilocksField;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(24))]
+
+        #[test]
+        fn test_gadget_accepts_only_sound_transfers(transfer in raw_transfers()) {
+            let update = transfer.to_update();
+            // The server and the circuit agree on which transfers are sound
+            prop_assert_eq!(
+                update.check_weights(FIXTURE_BALANCE_BITS).is_ok(),
+                transfer.is_sound()
+            );
+            prop_assert_eq!(SINGLE_UPDATE_CIRCUIT.accepts(&update), transfer.is_sound());
+        }
+
+        #[test]
+        fn test_proofs_verify_only_for_chained_roots(
+            votes in vote_sequences(),
+            swapped_with in 1..FIXTURE_UPDATE_COUNT,
+        ) {
+            let (storage, updates) = cast_votes(&votes);
+            prop_assert!(proves(&CHAINED_UPDATES_CIRCUIT, &updates, &storage));
+            // Every update changes the tree, so out of order updates start from
+            // roots the previous ones did not leave
+            let mut swapped = updates;
+            swapped.swap(0, swapped_with);
+            prop_assert!(!proves(&CHAINED_UPDATES_CIRCUIT, &swapped, &storage));
+        }
+    }
+
+    #[test]
+    fn test_seeded_votes_prove_their_tallies() -> anyhow::Result<()> {
+        let votes = seeded_votes(7);
+        assert_eq!(votes, seeded_votes(7));
+        let (storage, updates) = cast_votes(&votes);
+        let tally = |yes: bool| -> u64 {
+            votes
+                .iter()
+                .filter(|vote| vote.yes == yes)
+                .map(|vote| vote.amount as u64)
+                .sum()
+        };
+        let envelope = prove_fixture(&CHAINED_UPDATES_CIRCUIT, &updates, &storage)?;
+        assert_eq!(envelope.public_inputs[YES_VOTES_PUBLIC_INPUT], tally(true));
+        assert_eq!(envelope.public_inputs[NO_VOTES_PUBLIC_INPUT], tally(false));
+        assert_eq!(
+            envelope.public_inputs[INITIAL_ROOT_PUBLIC_INPUTS],
+            storage
+                .initial_root()
+                .0
+                .elements
+                .map(|element| element.to_canonical_u64())
+        );
+
+        // An update recorded against another tree does not chain, even in its place
+        let (_, other) = cast_votes(&seeded_votes(8));
+        let mut spliced = updates;
+        spliced[2] = other[2].clone();
+        assert!(!proves(&CHAINED_UPDATES_CIRCUIT, &spliced, &storage));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_witness_reports_violations() -> anyhow::Result<()> {
+        let (storage, updates) = cast_votes(&seeded_votes(3));
+        let statement_hash = compute_statement_hash("Fund the audit");
+        let action_hash = compute_action_hash(&ProposalAction::TextOnly);
+        let tally_proofs = [
+            storage.get_tally_proof(TallySlot::NO)?,
+            storage.get_tally_proof(TallySlot::YES)?,
+        ];
+        let check = |dependencies_hash, updates: &Vec<BalanceUpdate<F>>| {
+            CHAINED_UPDATES_CIRCUIT.check_witness(
+                statement_hash,
+                action_hash,
+                dependencies_hash,
+                updates,
+                &tally_proofs,
+            )
+        };
+        assert!(check(None, &updates).is_empty());
+        assert_eq!(check(Some(statement_hash), &updates).len(), 1);
+        assert_eq!(check(None, &updates[1..].to_vec()).len(), 1);
+
+        // Caught while generating the witness, without proving
+        let (_, other) = cast_votes(&seeded_votes(4));
+        let mut spliced = updates;
+        spliced[1] = other[1].clone();
+        assert_eq!(check(None, &spliced).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pad_updates_to_power_of_two() {
+        assert_eq!(padded_update_count(0), 1);
+        assert_eq!(padded_update_count(1), 1);
+        assert_eq!(padded_update_count(3), 4);
+        assert_eq!(padded_update_count(8), 8);
+
+        let root = WHashOut::<F>::from_values(1, 2, 3, 4);
+        let updates = vec![BalanceUpdate::<F>::noop(root, 8); 3];
+        let padded = pad_updates(&updates, 8);
+        assert_eq!(padded.len(), 4);
+        assert!(padded[3].is_noop());
+        assert_eq!(padded[3].old_root(), root);
+        assert_eq!(padded[3].new_root(), root);
+    }
+
+    #[test]
+    fn test_circuit_ids_of_unbuildable_shapes_are_rejected() {
+        let shape = parse_update_balance_circuit_id("update_balance:8:32:63").unwrap();
+        assert_eq!(update_balance_circuit_id(&shape), "update_balance:8:32:63");
+        for circuit_id in [
+            "update_balance:0:8:32",
+            "update_balance:131072:8:32",
+            "update_balance:1:0:32",
+            "update_balance:1:33:32",
+            "update_balance:1:4000000000:32",
+            "update_balance:1:8:0",
+            "update_balance:1:8:64",
+            "update_balance:1:8:63:conviction",
+            "update_balance:-1:8:32",
+            "update_balance:1:8",
+        ] {
+            assert!(
+                parse_update_balance_circuit_id(circuit_id).is_err(),
+                "{} was accepted",
+                circuit_id
+            );
+        }
+    }
+
+    #[test]
+    fn test_proves_balances_wider_than_32_bits() -> anyhow::Result<()> {
+        let store = || NodeStore::Memory(SimpleNodeStore::new());
+        let whale = Weight::try_from(1u64 << 40)?;
+        assert!(BalanceStorage::with_store(8, vec![whale, whale], 41, store()).is_err());
+
+        let mut storage = BalanceStorage::with_store(8, vec![whale, whale], 42, store())?;
+        let updates = storage.process_txs(vec![BalanceTx::Vote {
+            voter: VoterLeaf::from_position(0),
+            slot: TallySlot::YES,
+            amount: whale.into(),
+        }])?;
+        assert_eq!(storage.get_tally(TallySlot::YES)?, whale);
+        assert_eq!(updates[0].check_weights(42)?, WeightDelta::from(whale));
+        // Only the first voter's balance was debited
+        assert!(storage
+            .process_tx(BalanceTx::Vote {
+                voter: VoterLeaf::from_position(0),
+                slot: TallySlot::NO,
+                amount: WeightDelta::from(1),
+            })
+            .is_err());
+
+        let shape = UpdateBalanceShape {
+            number_updates: 1,
+            tree_height: 8,
+            balance_bits: storage.balance_bits(),
+            conviction: false,
+            voting_policy: VotingPolicy::Linear,
+            dependencies: false,
+            vesting: false,
+            deadline: false,
+            min_transfer: false,
+        };
+        let tally_proofs = [
+            storage.get_tally_proof(TallySlot::NO)?,
+            storage.get_tally_proof(TallySlot::YES)?,
+        ];
+        let circuit = UpdateBalanceCircuit::<F, PoseidonGoldilocksConfig, 2>::new(shape);
+        let statement_hash = compute_statement_hash("Fund the audit");
+        let action_hash = compute_action_hash(&ProposalAction::ParameterChange {
+            parameter: "audit_budget".to_string(),
+            value: "1000".to_string(),
+        });
+        let envelope =
+            circuit.prove_envelope(statement_hash, action_hash, &updates, &tally_proofs)?;
+        assert_eq!(
+            parse_update_balance_circuit_id(&envelope.circuit_id)?,
+            shape
+        );
+        assert_eq!(
+            envelope.public_inputs[STATEMENT_HASH_PUBLIC_INPUTS],
+            statement_hash
+                .0
+                .elements
+                .map(|element| element.to_canonical_u64())
+        );
+        assert_eq!(
+            envelope.public_inputs[ACTION_HASH_PUBLIC_INPUTS],
+            action_hash
+                .0
+                .elements
+                .map(|element| element.to_canonical_u64())
+        );
+
+        // A circuit for narrower balances cannot prove the same updates
+        let narrow =
+            UpdateBalanceCircuit::<F, PoseidonGoldilocksConfig, 2>::new(UpdateBalanceShape {
+                balance_bits: 32,
+                ..shape
+            });
+        assert!(narrow
+            .prove(statement_hash, action_hash, &updates, &tally_proofs)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_votes_add_up_to_the_balance() -> anyhow::Result<()> {
+        let voter = VoterLeaf::from_position(0);
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(5); 2]);
+        let split = |yes_votes: u32, no_votes: u32| VoteSplit {
+            yes_votes: Weight::from(yes_votes),
+            no_votes: Weight::from(no_votes),
+        };
+        assert!(storage
+            .process_split_vote(voter, split(3, 1), None, None)
+            .is_err());
+        assert_eq!(storage.get_balance(voter)?, Weight::from(5));
+        let updates = storage.process_split_vote(voter, split(3, 2), None, None)?;
+        assert_eq!(
+            updates.iter().map(|update| update.kind).collect::<Vec<_>>(),
+            vec![UpdateKind::SplitVote, UpdateKind::Vote]
+        );
+        assert_eq!(storage.get_tally(TallySlot::YES)?, Weight::from(3));
+        assert_eq!(storage.get_tally(TallySlot::NO)?, Weight::from(2));
+
+        let circuit = UpdateBalanceCircuit::new(linear_shape(2, storage.balance_bits()));
+        prove_fixture(&circuit, &updates, &storage)?;
+
+        // Parts leaving weight behind do not pass as a split vote
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(5); 2]);
+        let mut partial = storage.process_txs(vec![
+            BalanceTx::Vote {
+                voter,
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(3),
+            },
+            BalanceTx::Vote {
+                voter,
+                slot: TallySlot::NO,
+                amount: WeightDelta::from(1),
+            },
+        ])?;
+        partial[0].kind = UpdateKind::SplitVote;
+        assert!(!proves(&circuit, &partial, &storage));
+        Ok(())
+    }
+
+    #[test]
+    fn test_revoked_votes_are_cast_again() -> anyhow::Result<()> {
+        let voter = VoterLeaf::from_position(0);
+        let mut storage = BalanceStorage::new(8, vec![Weight::from(5); 2]);
+        let cast = VoteSplit {
+            yes_votes: Weight::from(3),
+            no_votes: Weight::from(2),
+        };
+        let mut updates = storage.process_split_vote(voter, cast, None, None)?;
+        let revocations = storage.process_revocation(voter, cast, None)?;
+        assert!(revocations
+            .iter()
+            .all(|update| update.kind == UpdateKind::Revocation));
+        assert_eq!(storage.get_balance(voter)?, Weight::from(5));
+        assert_eq!(storage.get_tally(TallySlot::YES)?, Weight::ZERO);
+        // More than the tallies hold cannot be revoked
+        assert!(storage.process_revocation(voter, cast, None).is_err());
+        updates.extend(revocations);
+        updates.push(storage.process_tx(BalanceTx::Vote {
+            voter,
+            slot: TallySlot::NO,
+            amount: WeightDelta::from(5),
+        })?);
+
+        let updates = pad_updates(&updates, 8);
+        let circuit =
+            UpdateBalanceCircuit::new(linear_shape(updates.len(), storage.balance_bits()));
+        let envelope = prove_fixture(&circuit, &updates, &storage)?;
+        assert_eq!(envelope.public_inputs[NO_VOTES_PUBLIC_INPUT], 5);
+        assert_eq!(envelope.public_inputs[YES_VOTES_PUBLIC_INPUT], 0);
+
+        // Weight taken out of a tally does not pass as a vote
+        let mut disguised = updates;
+        disguised[2].kind = UpdateKind::Vote;
+        assert!(!proves(&circuit, &disguised, &storage));
+        Ok(())
+    }
+
+    #[test]
+    fn test_proposal_without_votes_is_proven_unchanged() -> anyhow::Result<()> {
+        let rules = ProposalRules {
+            voting_period_secs: Some(3600),
+            ..ProposalRules::default()
+        };
+        let proposal = Proposal::new("Fund the audit".to_string(), 0, 100, rules)?;
+        let updates = proposal.finalization_updates();
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].is_noop());
+
+        let circuit =
+            UpdateBalanceCircuit::<F, PoseidonGoldilocksConfig, 2>::new(UpdateBalanceShape {
+                number_updates: updates.len(),
+                tree_height: proposal.storage.tree_height(),
+                balance_bits: proposal.storage.balance_bits(),
+                conviction: false,
+                voting_policy: VotingPolicy::Linear,
+                dependencies: false,
+                vesting: false,
+                deadline: true,
+                min_transfer: false,
+            });
+        let tally_proofs = [
+            proposal.storage.get_tally_proof(TallySlot::NO)?,
+            proposal.storage.get_tally_proof(TallySlot::YES)?,
+        ];
+        let envelope = circuit.prove_envelope(
+            compute_statement_hash(&proposal.statement),
+            compute_action_hash(&proposal.action),
+            &updates,
+            &tally_proofs,
+        )?;
+        envelope.expect_public_inputs(
+            proposal.storage.initial_root(),
+            proposal.storage.tree.get_root()?,
+        )?;
+        assert_eq!(envelope.public_inputs[NO_VOTES_PUBLIC_INPUT], 0);
+        assert_eq!(envelope.public_inputs[YES_VOTES_PUBLIC_INPUT], 0);
+        Ok(())
+    }
+}
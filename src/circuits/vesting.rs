@@ -0,0 +1,147 @@
+//! Vesting in the update balance circuit: senders keep the weight their
+//! schedule locks until the epoch of the proposal reaches its unlock epoch.
+//!
+//! Leaves pack their locked weight and unlock epoch next to their balance, see
+//! [`crate::balance::vesting`], and no update changes either, so the schedules
+//! seeded in the initial root bind every later update. The epoch of the proposal
+//! is a public input, for verifiers to see which epoch the votes were weighed at.
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::{
+        target::{BoolTarget, Target},
+        witness::WitnessWrite,
+    },
+    plonk::circuit_builder::CircuitBuilder,
+};
+
+use crate::{
+    balance::{
+        accounts::{LOCKED_ELEMENT, UNLOCK_EPOCH_ELEMENT},
+        vesting::EPOCH_BITS,
+    },
+    common::{
+        hash::merkle::gadgets::delta_merkle_proof::DeltaMerkleProofGadget,
+        u32::multiple_comparison::list_le_circuit,
+    },
+};
+
+/// Epoch shared by the updates of a circuit.
+pub struct VestingTargets {
+    pub epoch: Target,
+}
+
+impl VestingTargets {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+    ) -> Self {
+        Self {
+            epoch: builder.add_virtual_target(),
+        }
+    }
+    pub fn set_witness<F: RichField>(&self, witness: &mut impl WitnessWrite<F>, epoch: u64) {
+        witness.set_target(self.epoch, F::from_canonical_u64(epoch));
+    }
+}
+
+/// Checks that an update leaves its sender with at least its locked weight
+/// unless the weight is unlocked at the epoch. Epochs are range checked to
+/// [`EPOCH_BITS`] bits and the locked weight to the width of the balances.
+pub struct VestingGadget {
+    /// Whether the unlock epoch of the sender is at most the epoch.
+    pub unlocked: BoolTarget,
+    /// Whether the sender keeps at least its locked weight.
+    pub keeps_locked: BoolTarget,
+}
+
+impl VestingGadget {
+    pub fn add_virtual_to<F: RichField + Extendable<D>, const D: usize>(
+        builder: &mut CircuitBuilder<F, D>,
+        params: &VestingTargets,
+        sender_update: &DeltaMerkleProofGadget,
+        balance_bits: usize,
+    ) -> Self {
+        let unlocked = list_le_circuit(
+            builder,
+            vec![sender_update.old_value.elements[UNLOCK_EPOCH_ELEMENT]],
+            vec![params.epoch],
+            EPOCH_BITS,
+        );
+        let keeps_locked = list_le_circuit(
+            builder,
+            vec![sender_update.old_value.elements[LOCKED_ELEMENT]],
+            vec![sender_update.new_value.elements[0]],
+            balance_bits,
+        );
+        let allowed = builder.or(unlocked, keeps_locked);
+        let true_target = builder.one();
+        builder.connect(allowed.target, true_target);
+        Self {
+            unlocked,
+            keeps_locked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            storage::BalanceStorage,
+            vesting::{VestingRules, VestingSchedule},
+            weight::{Weight, WeightDelta},
+        },
+        circuits::{
+            test_fixtures::{linear_shape, prove_fixture, proves},
+            update_balance::{
+                pad_updates, parse_update_balance_circuit_id, vesting_epoch_public_input,
+                UpdateBalanceCircuit, UpdateBalanceShape,
+            },
+        },
+    };
+
+    #[test]
+    fn test_locked_weight_is_not_cast_before_its_epoch() -> anyhow::Result<()> {
+        let vesting = |epoch| VestingRules {
+            epoch,
+            schedules: vec![VestingSchedule {
+                voter_id: 2,
+                locked: Weight::from(4),
+                unlock_epoch: 7,
+            }],
+        };
+        let seeded = || BalanceStorage::new(8, vec![Weight::from(10); 2]);
+        let vote = |amount| BalanceTx::Vote {
+            voter: VoterLeaf::from_position(0),
+            slot: TallySlot::YES,
+            amount: WeightDelta::from(amount),
+        };
+        let mut storage = seeded().with_vesting(Some(vesting(6)))?;
+        let updates = pad_updates(&[storage.process_tx(vote(6))?], 8);
+
+        let shape = UpdateBalanceShape {
+            vesting: true,
+            ..linear_shape(updates.len(), storage.balance_bits())
+        };
+        let circuit = UpdateBalanceCircuit::new(shape);
+        let envelope = prove_fixture(&circuit, &updates, &storage)?;
+        assert_eq!(
+            parse_update_balance_circuit_id(&envelope.circuit_id)?,
+            shape
+        );
+        assert_eq!(
+            envelope.public_inputs[vesting_epoch_public_input(&shape).unwrap()],
+            6
+        );
+
+        // Spending the locked weight once unlocked does not pass an epoch earlier
+        let mut storage = seeded().with_vesting(Some(vesting(7)))?;
+        let mut early = vec![storage.process_tx(vote(10))?];
+        early[0].vesting_epoch = Some(6);
+        assert!(early[0].check_unlocked().is_err());
+        assert!(!proves(&circuit, &early, &storage));
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Setting the witnesses of many gadgets at once, in parallel with the
+//! `parallel` feature.
+//!
+//! A [`PartialWitness`] cannot be written from several threads, so each gadget
+//! records its targets in [`WitnessAssignments`] of its own, which are then
+//! applied to the witness in the order of the gadgets.
+
+use plonky2::{
+    field::types::Field,
+    iop::{
+        target::Target,
+        witness::{PartialWitness, WitnessWrite},
+    },
+};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Values set on targets, applied to a [`PartialWitness`] later.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WitnessAssignments<F: Field> {
+    pub values: Vec<(Target, F)>,
+}
+
+impl<F: Field> WitnessAssignments<F> {
+    pub fn new() -> Self {
+        Self { values: vec![] }
+    }
+    /// Sets every recorded target on `witness`, which panics like setting them
+    /// directly if a target was already set to another value.
+    pub fn apply(self, witness: &mut PartialWitness<F>) {
+        for (target, value) in self.values {
+            witness.set_target(target, value);
+        }
+    }
+}
+
+impl<F: Field> WitnessWrite<F> for WitnessAssignments<F> {
+    fn set_target(&mut self, target: Target, value: F) {
+        self.values.push((target, value));
+    }
+}
+
+/// Calls `set` on each gadget with the input of the same position, then sets
+/// what they assigned on `witness`. The gadgets are handled in parallel with
+/// the `parallel` feature.
+pub fn set_witnesses<F: Field, G: Sync, I: Sync>(
+    witness: &mut PartialWitness<F>,
+    gadgets: &[G],
+    inputs: &[I],
+    set: impl Fn(&G, &mut WitnessAssignments<F>, &I) + Sync,
+) {
+    assert_eq!(gadgets.len(), inputs.len());
+    let assign = |(gadget, input): (&G, &I)| {
+        let mut assignments = WitnessAssignments::new();
+        set(gadget, &mut assignments, input);
+        assignments
+    };
+    #[cfg(feature = "parallel")]
+    let assignments: Vec<_> = gadgets.par_iter().zip(inputs).map(assign).collect();
+    #[cfg(not(feature = "parallel"))]
+    let assignments: Vec<_> = gadgets.iter().zip(inputs).map(assign).collect();
+    for assignments in assignments {
+        assignments.apply(witness);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::Field},
+        iop::{
+            target::Target,
+            witness::{PartialWitness, Witness, WitnessWrite},
+        },
+    };
+
+    use super::set_witnesses;
+
+    #[test]
+    fn test_sets_witnesses_in_the_order_of_the_gadgets() {
+        type F = GoldilocksField;
+        let gadgets: Vec<Target> = (0..64)
+            .map(|index| Target::VirtualTarget { index })
+            .collect();
+        let inputs: Vec<u64> = (0..64).map(|i| i * 3).collect();
+        let mut pw = PartialWitness::<F>::new();
+        set_witnesses(&mut pw, &gadgets, &inputs, |target, witness, input| {
+            witness.set_target(*target, F::from_canonical_u64(*input));
+        });
+        for (target, input) in gadgets.iter().zip(&inputs) {
+            assert_eq!(pw.get_target(*target), F::from_canonical_u64(*input));
+        }
+    }
+}
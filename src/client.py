@@ -1,29 +1,58 @@
 import requests
 import asyncio
 import argparse
+import hashlib
+import secrets
 
 
-def list_proposals(base_url: str):
-    response = requests.get(f"{base_url}/")
+def list_proposals(base_url: str, status: str = None, page: int = 1):
+    params = {"page": page}
+    if status is not None:
+        params["status"] = status
+    response = requests.get(f"{base_url}/", params=params)
     print("List of proposals:")
     print(response.text)
 
 
-def propose(base_url: str, proposer_id: int, statement: str):
+def propose(base_url: str, proposer_id: int, statement: str, tie_policy: str = None, dao_id: str = None,
+            commit_period: int = None, beacon_block: int = None):
     proposal_data = {"proposer_id": proposer_id, "statement": statement}
+    if tie_policy is not None:
+        proposal_data["tie_policy"] = tie_policy
+    if beacon_block is not None:
+        proposal_data["beacon_block"] = beacon_block
+    if dao_id is not None:
+        proposal_data["dao_id"] = dao_id
+    if commit_period is not None:
+        proposal_data["commit_period_secs"] = commit_period
     response = requests.post(f"{base_url}/propose", json=proposal_data)
     print("Proposal submission response:")
     print(response.text)
 
 
-def vote(base_url: str, proposal_id: str, voter_id: int, vote: int):
+def vote(base_url: str, proposal_id: str, voter_id: int, vote: int, salt: str = None):
     vote_data = {"proposal_id": proposal_id,
                  "voter_id": voter_id, "is_yes": vote}
+    if salt is not None:
+        vote_data["salt"] = salt
     response = requests.post(f"{base_url}/vote", json=vote_data)
     print("Voting response:")
     print(response.text)
 
 
+def commit(base_url: str, proposal_id: str, voter_id: int, vote: bool):
+    # Keep the salt to open the commitment with once voting opens
+    salt = secrets.token_bytes(32)
+    commitment = hashlib.sha256(
+        voter_id.to_bytes(4, 'little') + bytes([int(vote)]) + salt).hexdigest()
+    commit_data = {"proposal_id": proposal_id,
+                   "voter_id": voter_id, "commitment": commitment}
+    response = requests.post(f"{base_url}/commit", json=commit_data)
+    print("Commit response:")
+    print(response.text)
+    print(f"Salt to vote with: {salt.hex()}")
+
+
 def delegate(base_url: str, proposal_id: str, voter_id: int, delegator_id: int):
     delegate_data = {"proposal_id": proposal_id,
                      "voter_id": voter_id, "delegator_id": delegator_id}
@@ -39,6 +68,49 @@ def finalize(base_url: str, proposal_id: str, finalizer_id: int):
     print(response.text)
 
 
+def cancel(base_url: str, proposal_id: str, proposer_id: int):
+    response = requests.post(
+        f"{base_url}/proposal/{proposal_id}/cancel", json={"proposer_id": proposer_id})
+    print("Cancel response:")
+    print(response.text)
+
+
+def amend(base_url: str, proposal_id: str, proposer_id: int, statement: str):
+    amend_data = {"proposer_id": proposer_id, "statement": statement}
+    response = requests.post(
+        f"{base_url}/proposal/{proposal_id}/amend", json=amend_data)
+    print("Amend response:")
+    print(response.text)
+
+
+def membership(base_url: str, proposal_id: str, voter_id: int):
+    response = requests.get(
+        f"{base_url}/proposal/{proposal_id}/membership/{voter_id}")
+    print("Membership proof:")
+    print(response.text)
+
+
+def transcript(base_url: str, proposal_id: str):
+    response = requests.get(f"{base_url}/proposal/{proposal_id}/transcript")
+    print("Transcript:")
+    print(response.text)
+
+
+def usage(base_url: str, dao_id: str):
+    response = requests.get(f"{base_url}/dao/{dao_id}/usage")
+    print("DAO usage:")
+    print(response.text)
+
+
+def finalize_cycle(base_url: str, proposal_ids: list, dao_id: str = None):
+    cycle_data = {"proposal_ids": proposal_ids}
+    if dao_id is not None:
+        cycle_data["dao_id"] = dao_id
+    response = requests.post(f"{base_url}/cycle/finalize", json=cycle_data)
+    print("Cycle finalization response:")
+    print(response.text)
+
+
 BASE_URL = "http://127.0.0.1:8080"
 
 parser = argparse.ArgumentParser(
@@ -49,6 +121,12 @@ parser_vote = subparsers.add_parser('vote', help='vote help')
 parser_vote.add_argument('proposal_id', type=str)
 parser_vote.add_argument('voter_id', type=int)
 parser_vote.add_argument('vote', type=int)
+parser_vote.add_argument('--salt', type=str, default=None)
+
+parser_commit = subparsers.add_parser('commit', help='commit help')
+parser_commit.add_argument('proposal_id', type=str)
+parser_commit.add_argument('voter_id', type=int)
+parser_commit.add_argument('vote', type=int)
 
 parser_delegate = subparsers.add_parser('delegate', help='delegate help')
 parser_delegate.add_argument('proposal_id', type=str)
@@ -56,24 +134,70 @@ parser_delegate.add_argument('voter_id', type=int)
 parser_delegate.add_argument('delegator_id', type=int)
 
 parser_list_proposals = subparsers.add_parser('list')
+parser_list_proposals.add_argument(
+    '--status', choices=['open', 'finalized', 'cancelled'], default=None)
+parser_list_proposals.add_argument('--page', type=int, default=1)
 
 parser_propose = subparsers.add_parser('propose', help='propose help')
 parser_propose.add_argument('proposer_id', type=int)
 parser_propose.add_argument('statement', type=str)
+parser_propose.add_argument(
+    '--tie-policy', choices=['veto', 'pass', 'revote', 'random_with_beacon'], default=None)
+parser_propose.add_argument('--beacon-block', type=int, default=None)
+parser_propose.add_argument('--dao', type=str, default=None)
+parser_propose.add_argument('--commit-period', type=int, default=None)
 
 parser_finalize = subparsers.add_parser('finalize', help='finalize help')
 parser_finalize.add_argument('proposal_id', type=str)
 parser_finalize.add_argument('finalizer_id', type=int)
 
+parser_cancel = subparsers.add_parser('cancel', help='cancel help')
+parser_cancel.add_argument('proposal_id', type=str)
+parser_cancel.add_argument('proposer_id', type=int)
+
+parser_amend = subparsers.add_parser('amend', help='amend help')
+parser_amend.add_argument('proposal_id', type=str)
+parser_amend.add_argument('proposer_id', type=int)
+parser_amend.add_argument('statement', type=str)
+
+parser_membership = subparsers.add_parser('membership', help='membership help')
+parser_membership.add_argument('proposal_id', type=str)
+parser_membership.add_argument('voter_id', type=int)
+
+parser_transcript = subparsers.add_parser('transcript', help='transcript help')
+parser_transcript.add_argument('proposal_id', type=str)
+
+parser_usage = subparsers.add_parser('usage', help='usage help')
+parser_usage.add_argument('dao_id', type=str)
+
+parser_cycle = subparsers.add_parser('cycle', help='cycle help')
+parser_cycle.add_argument('proposal_ids', type=str, nargs='+')
+parser_cycle.add_argument('--dao', type=str)
+
 
 args = parser.parse_args()
 if args.method == 'vote':
-    vote(BASE_URL, args.proposal_id, args.voter_id, bool(args.vote))
+    vote(BASE_URL, args.proposal_id, args.voter_id, bool(args.vote), args.salt)
+elif args.method == 'commit':
+    commit(BASE_URL, args.proposal_id, args.voter_id, bool(args.vote))
 elif args.method == 'propose':
-    propose(BASE_URL, args.proposer_id, args.statement)
+    propose(BASE_URL, args.proposer_id, args.statement,
+            args.tie_policy, args.dao, args.commit_period, args.beacon_block)
 elif args.method == 'finalize':
     finalize(BASE_URL, args.proposal_id, args.finalizer_id)
 elif args.method == 'list':
-    list_proposals(BASE_URL)
+    list_proposals(BASE_URL, args.status, args.page)
 elif args.method == 'delegate':
     delegate(BASE_URL, args.proposal_id, args.voter_id, args.delegator_id)
+elif args.method == 'cancel':
+    cancel(BASE_URL, args.proposal_id, args.proposer_id)
+elif args.method == 'amend':
+    amend(BASE_URL, args.proposal_id, args.proposer_id, args.statement)
+elif args.method == 'membership':
+    membership(BASE_URL, args.proposal_id, args.voter_id)
+elif args.method == 'transcript':
+    transcript(BASE_URL, args.proposal_id)
+elif args.method == 'usage':
+    usage(BASE_URL, args.dao_id)
+elif args.method == 'cycle':
+    finalize_cycle(BASE_URL, args.proposal_ids, args.dao)
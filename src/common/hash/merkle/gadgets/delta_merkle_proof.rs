@@ -3,7 +3,7 @@ use plonky2::{
     hash::hash_types::{HashOutTarget, RichField},
     iop::{
         target::Target,
-        witness::{PartialWitness, WitnessWrite},
+        witness::WitnessWrite,
     },
     plonk::{circuit_builder::CircuitBuilder, config::AlgebraicHasher},
 };
@@ -149,7 +149,7 @@ impl DeltaMerkleProofGadget {
     }
     pub fn set_witness<F: RichField>(
         &self,
-        witness: &mut PartialWitness<F>,
+        witness: &mut impl WitnessWrite<F>,
         index: F,
         old_value: WHashOut<F>,
         new_value: WHashOut<F>,
@@ -184,7 +184,7 @@ impl DeltaMerkleProofGadget {
     }
     pub fn set_witness_proof<F: RichField>(
         &self,
-        witness: &mut PartialWitness<F>,
+        witness: &mut impl WitnessWrite<F>,
         input: &DeltaMerkleProof<F>,
     ) {
         self.set_witness(
@@ -197,7 +197,7 @@ impl DeltaMerkleProofGadget {
     }
     pub fn set_witness_base_proof<F: RichField>(
         &self,
-        witness: &mut PartialWitness<F>,
+        witness: &mut impl WitnessWrite<F>,
         input: &DeltaMerkleProofBase<F>,
     ) {
         self.set_witness(
@@ -25,7 +25,12 @@ pub fn list_le_circuit<F: RichField + Extendable<D>, const D: usize>(
     );
     let n = a.len();
 
-    let chunk_bits = 2;
+    // Two-bit chunks keep the gate at degree four, wider chunks are only used when
+    // the gate for a wide input would not fit in the wires of the config otherwise
+    let mut chunk_bits = 2;
+    while 5 + 5 * ceil_div_usize(num_bits, chunk_bits) + chunk_bits > builder.config.num_wires {
+        chunk_bits += 1;
+    }
     let num_chunks = ceil_div_usize(num_bits, chunk_bits);
 
     let one = builder.one();
@@ -142,7 +147,7 @@ mod tests {
     #[test]
     fn test_multiple_comparison() -> Result<()> {
         for size in [1, 3, 6] {
-            for num_bits in [20, 32, 40, 44] {
+            for num_bits in [20, 32, 40, 44, 63] {
                 test_list_le(size, num_bits).unwrap();
             }
         }
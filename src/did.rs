@@ -0,0 +1,267 @@
+//! Voters identified by W3C decentralized identifiers instead of bare voter ids.
+//!
+//! A proposal can register its electorate as a list of DIDs, one voter leaf
+//! each, after which every vote, commitment and delegation of a voter has to be
+//! signed by the key the voter's DID resolves to. Two methods are supported:
+//!
+//! - `did:key` with an Ed25519 key, which resolves to the key it encodes;
+//! - `did:ethr`, which resolves to its Ethereum address as the default owner.
+//!   Owner changes and delegates recorded in the ERC-1056 registry are not
+//!   looked up, so the address has to stay the signing key.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, ensure};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use web3::{
+    signing::{hash_message, recover},
+    types::Address,
+};
+
+/// Multicodec prefix of an Ed25519 public key, as an unsigned varint.
+const ED25519_PUB_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// A DID of one of the supported methods.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Did {
+    Key(VerifyingKey),
+    Ethr {
+        /// Network name or hex chain id between the method and the address, if any.
+        network: Option<String>,
+        address: Address,
+    },
+}
+
+impl FromStr for Did {
+    type Err = anyhow::Error;
+
+    fn from_str(did: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = did.split(':').collect();
+        match parts.as_slice() {
+            ["did", "key", multibase] => {
+                let encoded = multibase
+                    .strip_prefix('z')
+                    .ok_or_else(|| anyhow!("did:key must be base58btc encoded"))?;
+                let bytes = bs58::decode(encoded).into_vec()?;
+                let key = bytes
+                    .strip_prefix(&ED25519_PUB_MULTICODEC)
+                    .ok_or_else(|| anyhow!("only Ed25519 did:key identifiers are supported"))?;
+                let key = <[u8; 32]>::try_from(key)
+                    .map_err(|_| anyhow!("Ed25519 keys are 32 bytes long"))?;
+                Ok(Did::Key(VerifyingKey::from_bytes(&key)?))
+            }
+            ["did", "ethr", rest @ ..] if matches!(rest.len(), 1 | 2) => {
+                let address = rest[rest.len() - 1];
+                ensure!(
+                    address.starts_with("0x") && address.len() == 42,
+                    "did:ethr must end in a 0x prefixed address"
+                );
+                Ok(Did::Ethr {
+                    network: (rest.len() == 2).then(|| rest[0].to_string()),
+                    address: address.parse()?,
+                })
+            }
+            ["did", method, ..] => bail!("unsupported DID method {}", method),
+            _ => bail!("{} is not a DID", did),
+        }
+    }
+}
+
+impl fmt::Display for Did {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Did::Key(key) => {
+                let bytes = [&ED25519_PUB_MULTICODEC[..], key.as_bytes()].concat();
+                write!(f, "did:key:z{}", bs58::encode(bytes).into_string())
+            }
+            Did::Ethr {
+                network: Some(network),
+                address,
+            } => write!(f, "did:ethr:{}:{:?}", network, address),
+            Did::Ethr {
+                network: None,
+                address,
+            } => write!(f, "did:ethr:{:?}", address),
+        }
+    }
+}
+
+impl TryFrom<String> for Did {
+    type Error = anyhow::Error;
+
+    fn try_from(did: String) -> anyhow::Result<Self> {
+        did.parse()
+    }
+}
+
+impl From<Did> for String {
+    fn from(did: Did) -> Self {
+        did.to_string()
+    }
+}
+
+/// A verification method of a [`DidDocument`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub controller: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key_multibase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blockchain_account_id: Option<String>,
+}
+
+/// The parts of a W3C DID document needed to check signatures of a voter.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    pub id: String,
+    pub verification_method: Vec<VerificationMethod>,
+    /// Ids of the verification methods requests can be signed with.
+    pub authentication: Vec<String>,
+}
+
+impl Did {
+    /// Resolves the DID to its document, without any network access.
+    pub fn resolve(&self) -> DidDocument {
+        let id = self.to_string();
+        let method = match self {
+            Did::Key(_) => {
+                let multibase = id.trim_start_matches("did:key:").to_string();
+                VerificationMethod {
+                    id: format!("{}#{}", id, multibase),
+                    kind: "Ed25519VerificationKey2020".to_string(),
+                    controller: id.clone(),
+                    public_key_multibase: Some(multibase),
+                    blockchain_account_id: None,
+                }
+            }
+            Did::Ethr { address, .. } => VerificationMethod {
+                id: format!("{}#controller", id),
+                kind: "EcdsaSecp256k1RecoveryMethod2020".to_string(),
+                controller: id.clone(),
+                public_key_multibase: None,
+                blockchain_account_id: Some(format!("eip155:1:{:?}", address)),
+            },
+        };
+        DidDocument {
+            id,
+            authentication: vec![method.id.clone()],
+            verification_method: vec![method],
+        }
+    }
+    /// Checks that `signature` over `message` was made by the key the DID resolves to:
+    /// a 64 byte Ed25519 signature for `did:key`, and a 65 byte `personal_sign`
+    /// signature, r || s || v, for `did:ethr`.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Did::Key(key) => {
+                let signature = Signature::from_slice(signature)?;
+                key.verify_strict(message, &signature)?;
+            }
+            Did::Ethr { address, .. } => {
+                recover_signer(hash_message(message).as_bytes(), signature, address)?
+            }
+        }
+        Ok(())
+    }
+    /// Checks a signature over the 32 byte `digest` of typed data, as
+    /// `eth_signTypedData` makes it: signed as is, without the `personal_sign`
+    /// prefix, for `did:ethr`, and as the message for `did:key`.
+    pub fn verify_digest(&self, digest: &[u8; 32], signature: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Did::Key(_) => self.verify(digest, signature),
+            Did::Ethr { address, .. } => recover_signer(digest, signature, address),
+        }
+    }
+}
+
+/// Fails unless the 65 byte signature, r || s || v, over `hash` recovers to `address`.
+fn recover_signer(hash: &[u8], signature: &[u8], address: &Address) -> anyhow::Result<()> {
+    ensure!(signature.len() == 65, "signature must be 65 bytes");
+    // Wallets report the recovery id as 27 or 28
+    let recovery_id = match signature[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        v => bail!("invalid recovery id {}", v),
+    };
+    let signer = recover(hash, &signature[..64], recovery_id as i32)?;
+    ensure!(
+        signer == *address,
+        "signed by {:?}, not {:?}",
+        signer,
+        address
+    );
+    Ok(())
+}
+
+/// The message a voter signs to authorize `action` on a proposal: the action,
+/// proposal id, voter id and `nonce`, the number of signed requests of the
+/// voter applied on the proposal before, followed by the JSON of the action's
+/// parameters.
+pub fn did_request_message<T: Serialize>(
+    action: &str,
+    proposal_id: &Uuid,
+    voter_id: u32,
+    nonce: u64,
+    params: &T,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(format!(
+        "qed-dapp:{}:{}:{}:{}:{}",
+        action,
+        proposal_id,
+        voter_id,
+        nonce,
+        serde_json::to_string(params)?
+    )
+    .into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use uuid::Uuid;
+    use web3::signing::{hash_message, Key, SecretKeyRef};
+
+    use super::{did_request_message, Did};
+    use crate::proof::identity::InstanceSigner;
+
+    #[test]
+    fn test_did_signatures() -> anyhow::Result<()> {
+        let message = did_request_message("vote", &Uuid::nil(), 2, 0, &(true, None::<String>))?;
+        let replay = did_request_message("vote", &Uuid::nil(), 2, 1, &(true, None::<String>))?;
+
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let did = Did::Key(signing_key.verifying_key());
+        let parsed: Did = did.to_string().parse()?;
+        assert_eq!(parsed, did);
+        assert!(did.to_string().starts_with("did:key:z6Mk"));
+        let signature = signing_key.sign(&message).to_bytes();
+        did.verify(&message, &signature)?;
+        assert!(did.verify(b"another request", &signature).is_err());
+        assert!(did.verify(&replay, &signature).is_err());
+
+        let key = InstanceSigner::key_from_hex(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )?;
+        let address = SecretKeyRef::new(&key).address();
+        let did: Did = format!("did:ethr:sepolia:{:?}", address).parse()?;
+        assert_eq!(did.resolve().authentication.len(), 1);
+        let signed = SecretKeyRef::new(&key).sign_message(hash_message(&message).as_bytes())?;
+        let mut signature = [signed.r.as_bytes(), signed.s.as_bytes()].concat();
+        signature.push(signed.v as u8 + 27);
+        did.verify(&message, &signature)?;
+        signature[0] ^= 1;
+        assert!(did.verify(&message, &signature).is_err());
+
+        assert!("did:web:example.com".parse::<Did>().is_err());
+        Ok(())
+    }
+}
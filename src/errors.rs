@@ -0,0 +1,476 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use utoipa::{
+    openapi::{ObjectBuilder, RefOr, Schema, SchemaType},
+    ToSchema,
+};
+use uuid::Uuid;
+
+use crate::{
+    balance::weight::{Weight, WeightDelta},
+    proposal::ProposalStatus,
+};
+
+/// Declares the API error codes along with their catalog entry, keeping the
+/// enum and [`ApiErrorCode::ALL`] in sync.
+macro_rules! api_error_codes {
+    ($($variant:ident => ($code:literal, $status:literal, $retryable:literal, $description:literal),)*) => {
+        /// Every error the API can respond with. The code is sent in the
+        /// `X-Error-Code` header and in the JSON body, next to a human readable message.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum ApiErrorCode {
+            $($variant,)*
+        }
+
+        impl ApiErrorCode {
+            pub const ALL: &'static [ApiErrorCode] = &[$(ApiErrorCode::$variant,)*];
+
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(ApiErrorCode::$variant => $code,)*
+                }
+            }
+            pub fn http_status(self) -> u16 {
+                match self {
+                    $(ApiErrorCode::$variant => $status,)*
+                }
+            }
+            /// Whether the same request may succeed when retried later.
+            pub fn is_retryable(self) -> bool {
+                match self {
+                    $(ApiErrorCode::$variant => $retryable,)*
+                }
+            }
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(ApiErrorCode::$variant => $description,)*
+                }
+            }
+        }
+    };
+}
+
+api_error_codes! {
+    InvalidQuery => ("invalid_query", 400, false, "The query parameters are out of range or inconsistent."),
+    ProposalNotFound => ("proposal_not_found", 404, false, "No proposal exists with the given id."),
+    ProposalFinalized => ("proposal_finalized", 400, false, "The proposal has been finalized and accepts no more votes or delegations."),
+    VotingClosed => ("voting_closed", 400, false, "The voting period of the proposal has ended."),
+    VotingNotOpen => ("voting_not_open", 400, true, "The proposal takes votes once its commitment period has ended."),
+    NotCommitReveal => ("not_commit_reveal", 400, false, "The proposal has no commitment period and takes votes directly."),
+    CommitmentsClosed => ("commitments_closed", 400, false, "The commitment period of the proposal has ended."),
+    CommitmentMissing => ("commitment_missing", 400, false, "The voter did not commit to a vote during the commitment period."),
+    CommitmentMismatch => ("commitment_mismatch", 400, false, "The vote and salt do not match the commitment of the voter."),
+    InvalidStatement => ("invalid_statement", 400, false, "The statement is empty or longer than the server accepts."),
+    InvalidContent => ("invalid_content", 400, false, "The URI of the document the statement refers to is of an unsupported scheme or too long."),
+    InvalidAction => ("invalid_action", 400, false, "The action of the proposal cannot be executed as given, e.g. a transfer of nothing or to the zero address."),
+    InvalidVoter => ("invalid_voter", 400, false, "The voter id is reserved for a tally, outside the balance tree or not part of the electorate of the proposal."),
+    NoVotingWeight => ("no_voting_weight", 400, false, "The voter holds no voting weight to cast or delegate, e.g. after delegating it."),
+    InvalidDid => ("invalid_did", 400, false, "A voter DID is malformed, of an unsupported method or registered twice."),
+    InvalidDidSignature => ("invalid_did_signature", 401, false, "The request is not signed by the key the DID of the voter resolves to."),
+    InvalidDidNonce => ("invalid_did_nonce", 409, false, "The nonce of the signed request is not the next one of the voter: the request was applied already or signed out of order."),
+    InvalidSplit => ("invalid_split", 400, false, "The weight cast on each option of a split vote does not add up to the voting weight of the voter, or the proposal takes committed votes, which cannot be split."),
+    AlreadyVoted => ("already_voted", 400, false, "The voter has already voted on the proposal."),
+    AlreadyDelegated => ("already_delegated", 400, false, "The voter has already delegated their weight on the proposal."),
+    NotRevocable => ("not_revocable", 400, false, "The voter has no vote to revoke, or the proposal takes committed, conviction weighted or quadratic votes, which cannot be revoked."),
+    InsufficientDeposit => ("insufficient_deposit", 402, false, "The treasury account of the proposer does not hold the deposit the server requires to create a proposal."),
+    NoDeposit => ("no_deposit", 404, false, "The proposal was created without a deposit."),
+    ProposalCancelled => ("proposal_cancelled", 400, false, "The proposal has been cancelled by its proposer."),
+    ProposalNotDraft => ("proposal_not_draft", 400, false, "The proposal has votes and can no longer be amended or cancelled."),
+    NotProposer => ("not_proposer", 400, false, "Only the proposer can amend, cancel or finalize a proposal, or one of its finalizers finalize it."),
+    BeaconUnavailable => ("beacon_unavailable", 503, true, "The block whose hash breaks the tie of the proposal has not been confirmed yet, the head of the chain could not be fetched, or the server runs without an Ethereum RPC endpoint."),
+    OutcomeUnresolved => ("outcome_unresolved", 400, false, "The outcome cannot be resolved under the tie policy of the proposal, e.g. a tie without a beacon value."),
+    NotFinalized => ("not_finalized", 400, true, "The proposal has not been finalized yet."),
+    NotTokenWeighted => ("not_token_weighted", 400, false, "The proposal was not seeded from a token snapshot."),
+    TokenSnapshotsDisabled => ("token_snapshots_disabled", 400, false, "The server runs without an Ethereum RPC endpoint and cannot snapshot token balances."),
+    TokenSnapshotFailed => ("token_snapshot_failed", 502, true, "Fetching token balances from the Ethereum RPC endpoint failed."),
+    RateLimited => ("rate_limited", 429, true, "Too many requests from this client or voter; retry after the number of seconds in the Retry-After header."),
+    NodeStoreUnavailable => ("node_store_unavailable", 500, true, "The merkle tree node store could not be opened."),
+    ProposalFinalizing => ("proposal_finalizing", 409, true, "The proposal is being proven and accepts no changes until finalization completes or fails."),
+    NodeStoreQuotaExceeded => ("node_store_quota_exceeded", 403, false, "The DAO has used up its quota of merkle tree node storage."),
+    ProofQuotaExceeded => ("proof_quota_exceeded", 403, false, "The DAO has used up its quota of stored proof bytes."),
+    UpdateQuotaExceeded => ("update_quota_exceeded", 403, false, "The DAO has used up its quota of recorded votes and delegations."),
+    ProvingFailed => ("proving_failed", 500, true, "Proving the proposal failed; it has been reopened and can be finalized again."),
+    AggregationFailed => ("aggregation_failed", 500, true, "Aggregating the finalization proofs of a governance cycle failed."),
+    AdminUnauthorized => ("admin_unauthorized", 401, false, "The request lacks the admin token of the server, or the server runs without one and has admin endpoints disabled."),
+    SnapshotRejected => ("snapshot_rejected", 400, false, "The snapshot is of an unsupported version, does not reproduce its recorded roots or the server already holds state."),
+    OrganizationNotFound => ("organization_not_found", 404, false, "No organization exists with the given id."),
+    OrganizationExists => ("organization_exists", 409, false, "An organization with the given id already exists."),
+    OrganizationUnauthorized => ("organization_unauthorized", 401, false, "The request lacks the admin token of the organization."),
+    OrganizationScoped => ("organization_scoped", 403, false, "The DAO belongs to an organization, whose proposals are created through its own routes."),
+    RegistrationNotFound => ("registration_not_found", 404, false, "The DID has not registered as a voter of the organization."),
+    RegistrationDecided => ("registration_decided", 409, false, "The registration has already been approved or rejected."),
+    NoRegisteredVoters => ("no_registered_voters", 400, true, "The organization has no approved voters to vote on its proposals yet."),
+    DependencyNotFound => ("dependency_not_found", 404, false, "A proposal the new proposal depends on does not exist."),
+    DependencyCycle => ("dependency_cycle", 400, false, "The dependencies of the new proposal lead back to it."),
+    AttestationsDisabled => ("attestations_disabled", 404, false, "The server runs without an attestation key and does not attest results."),
+    DependencyPending => ("dependency_pending", 409, true, "A proposal this one depends on is neither finalized nor cancelled yet."),
+    ReadOnlyReplica => ("read_only_replica", 503, false, "The server is a read-only replica; send mutations to its primary."),
+    Unauthenticated => ("unauthenticated", 401, false, "The server requires an API key for the route and the request carries none, or one it does not know or whose signature does not verify."),
+    RoleForbidden => ("role_forbidden", 403, false, "The API key of the request does not have the role the route requires."),
+    ApiKeyNotFound => ("api_key_not_found", 404, false, "No API key exists with the given id."),
+    ApiKeyExists => ("api_key_exists", 409, false, "An API key with the given id already exists."),
+    VotingPaused => ("voting_paused", 503, true, "An admin has paused voting on every proposal."),
+    TreeCompacted => ("tree_compacted", 410, false, "The proposal is finalized and its balance tree was compacted to the root and the tallies, so leaves of voters are no longer proven."),
+    EventReplayFailed => ("event_replay_failed", 500, false, "The events of the proposal do not replay from the balance roots recorded with them."),
+    ProposalCreating => ("proposal_creating", 409, true, "Another request is creating a proposal with the same derived id."),
+    DelegationCycle => ("delegation_cycle", 400, false, "The delegate is the voter or has, directly or through others, delegated to the voter."),
+    NotBlinded => ("not_blinded", 400, false, "The proposal places voters at the leaves of their ids, so there is no blinding to reveal."),
+    InvalidFinalizers => ("invalid_finalizers", 400, false, "The finalizers of the proposal are duplicated, too many, have invalid keys or cannot meet the threshold."),
+    NotFinalizer => ("not_finalizer", 400, false, "Only the finalizers of a proposal with a finalizer threshold can approve finalizing it."),
+    InvalidApprovalSignature => ("invalid_approval_signature", 401, false, "The approval is not signed by the key the finalizer was registered with."),
+    AlreadyApproved => ("already_approved", 409, false, "The finalizer has already approved finalizing the proposal."),
+    ApprovalsPending => ("approvals_pending", 409, true, "Fewer finalizers than the threshold of the proposal have approved finalizing it."),
+    RangeNotSatisfiable => ("range_not_satisfiable", 416, false, "The requested byte range starts past the end of the download."),
+    PublicInputsMismatch => ("public_inputs_mismatch", 500, false, "The proof does not start from the initial root or end at the final root of the proposal, which has been reopened."),
+    NotPayable => ("not_payable", 409, false, "Only finalized proposals that passed with a treasury transfer action are paid out."),
+    PayoutFailed => ("payout_failed", 409, false, "The proposal has been paid out already, or the funds of its DAO do not cover the transfer."),
+    TokensLocked => ("tokens_locked", 409, true, "The voter has fewer tokens free than their weight on the proposal, the rest being locked until the other proposals they voted on resolve."),
+    ServerPaused => ("server_paused", 503, true, "An admin has paused the server for an incident; reads and proof downloads are still served."),
+    PauseNotPersisted => ("pause_not_persisted", 500, true, "The pause could not be written to its file, and the server was left as it was."),
+    InvalidCommittee => ("invalid_committee", 400, false, "The ballot committee has too many or duplicated members, invalid or inconsistent public shares, a threshold it cannot meet, or rules that votes cannot be encrypted under."),
+    BallotsEncrypted => ("ballots_encrypted", 400, false, "The proposal takes votes as ballots encrypted to its committee, which cannot be split, revoked or delegated."),
+    NotEncrypted => ("not_encrypted", 400, false, "The proposal takes votes in the clear, without a committee to encrypt ballots to."),
+    InvalidBallot => ("invalid_ballot", 400, false, "The ballot is not proven to encrypt a yes or no vote of the voter to the key of the committee."),
+    InvalidDecryptionShare => ("invalid_decryption_share", 400, false, "The decryption share is not by a member of the committee, repeats one, is not proven against the public share of the member for the aggregate of the ballots, or the shares decrypt the aggregate to no number of yes votes."),
+    DecryptionNotOpen => ("decryption_not_open", 409, true, "Ballots are decrypted once the voting period of the proposal ends."),
+    BallotsSealed => ("ballots_sealed", 409, true, "Fewer committee members than the threshold have shared their decryptions of the ballots, whose votes are not in the tally yet."),
+    DryRunFailed => ("dry_run_failed", 500, true, "Building the circuit or generating the witness of a finalization dry run failed."),
+    InsufficientWeight => ("insufficient_weight", 400, false, "The leaf holds less weight than the vote or delegation moves out of it."),
+    TreeCorruption => ("tree_corruption", 500, false, "The balance tree of the proposal no longer matches its recorded updates, or could not be rolled back after a failed write."),
+    ChainSubmissionFailed => ("chain_submission_failed", 502, true, "Sending a transaction to the Ethereum RPC endpoint failed."),
+    NotRelayable => ("not_relayable", 400, false, "Votes are relayed on proposals whose electorate was registered by DID, whose keys sign them."),
+    InvalidRelayNonce => ("invalid_relay_nonce", 409, false, "The nonce of the relayed vote is not the next one of the voter: the vote was relayed already or signed out of order."),
+    RelayExpired => ("relay_expired", 400, false, "The relayed vote was signed to be valid until a time that has passed."),
+    InvalidWebhook => ("invalid_webhook", 400, false, "The webhooks are too many, repeat a URL, or have a URL that is not HTTP, does not name a public host, or no secret."),
+    ArchiveFailed => ("archive_failed", 500, true, "Writing the archive of the proposal failed."),
+    StatusConflict => ("status_conflict", 409, true, "The status of the proposal changed while the request was handled, e.g. by another finalization; fetch the proposal and retry."),
+    MetadataStoreDisabled => ("metadata_store_disabled", 404, false, "The server runs without a SQL metadata store and does not search proposals."),
+    MetadataQueryFailed => ("metadata_query_failed", 500, true, "Querying the SQL metadata store failed."),
+    NotDelegated => ("not_delegated", 400, false, "The voter has no delegation on the proposal to revert."),
+    DelegationNotFound => ("delegation_not_found", 404, false, "No scoped delegation of the voter exists with the given id."),
+    NotUndelegable => ("not_undelegable", 409, false, "The delegated weight was cast already, or the proposal weighs votes by conviction or quadratically, whose delegations cannot be reverted."),
+    ResultTreeFailed => ("result_tree_failed", 500, false, "Appending the results of finalized proposals to the result tree failed."),
+    UnknownCircuit => ("unknown_circuit", 404, false, "No proof the server holds was produced with a circuit of the given id."),
+    VerifierDataFailed => ("verifier_data_failed", 500, false, "Serializing the verifier data of the circuit failed."),
+    BelowMinTransfer => ("below_min_transfer", 400, false, "The vote or delegation moves less weight than the minimum the proposal sets."),
+    EventLogFailed => ("event_log_failed", 500, true, "The event could not be written to the event log, and the proposal was left as it was."),
+    Sharded => ("sharded", 400, false, "The electorate of the proposal is split across shards, which take only plain votes and delegations between voters of the same shard."),
+}
+
+impl Serialize for ApiErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiErrorCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        ApiErrorCode::ALL
+            .iter()
+            .find(|known| known.as_str() == code)
+            .copied()
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown error code {}", code)))
+    }
+}
+
+impl<'s> ToSchema<'s> for ApiErrorCode {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        (
+            "ApiErrorCode",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .description(Some("See `GET /errors` for what each code means."))
+                .enum_values(Some(ApiErrorCode::ALL.iter().map(|code| code.as_str())))
+                .into(),
+        )
+    }
+}
+
+/// A rejected request, carrying the code and message the client is answered with.
+/// Serializes to the JSON body of the error response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ApiError {
+    pub code: ApiErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ApiErrorCode, message: impl std::fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Step of a finalization a [`QedError::ProofGenerationFailed`] happened at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofStage {
+    /// Assigning the updates and tallies to the circuit, before any proving.
+    Witness,
+    /// Proving the updates of a proposal.
+    Proof,
+    /// Checking the proof against the roots of the proposal.
+    Verification,
+    /// Aggregating the finalizations of a governance cycle.
+    Aggregation,
+    /// Wrapping a proof for verification on Ethereum.
+    Wrapping,
+}
+
+impl ProofStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProofStage::Witness => "witness",
+            ProofStage::Proof => "proof",
+            ProofStage::Verification => "verification",
+            ProofStage::Aggregation => "aggregation",
+            ProofStage::Wrapping => "wrapping",
+        }
+    }
+}
+
+/// Failures of the library an SDK user may want to tell apart. Functions
+/// returning [`anyhow::Error`] wrap one of these when they fail for its cause,
+/// so callers match on `err.downcast_ref::<QedError>()`; every other error is
+/// an invalid input described by its message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QedError {
+    ProposalNotFound(Uuid),
+    AlreadyFinalized(Uuid),
+    /// Leaf `leaf` holds `balance`, less than the `amount` moved out of it.
+    InsufficientWeight {
+        leaf: u64,
+        balance: Weight,
+        amount: WeightDelta,
+    },
+    /// A vote or delegation out of leaf `leaf` moves `amount`, less than the
+    /// `minimum` of the proposal.
+    BelowMinTransfer {
+        leaf: u64,
+        amount: WeightDelta,
+        minimum: Weight,
+    },
+    /// The tree no longer matches what was recorded of it, described by the message.
+    TreeCorruption(String),
+    /// Leaf `leaf` is neither a tally slot nor one of the leaves of the `voters`
+    /// voters of the tree.
+    LeafOutOfRange {
+        leaf: u64,
+        voters: u64,
+    },
+    ProofGenerationFailed {
+        stage: ProofStage,
+        message: String,
+    },
+    ChainSubmissionFailed(String),
+    /// The proposal was expected at `expected` but moved to `actual` meanwhile.
+    StatusConflict {
+        id: Uuid,
+        expected: ProposalStatus,
+        actual: ProposalStatus,
+    },
+    /// Another finalization job holds the proposal, see
+    /// [`crate::proposal::store::FinalizationClaim`].
+    FinalizationClaimed(Uuid),
+}
+
+impl QedError {
+    /// The code the API answers the error with.
+    pub fn code(&self) -> ApiErrorCode {
+        match self {
+            QedError::ProposalNotFound(_) => ApiErrorCode::ProposalNotFound,
+            QedError::AlreadyFinalized(_) => ApiErrorCode::ProposalFinalized,
+            QedError::InsufficientWeight { .. } => ApiErrorCode::InsufficientWeight,
+            QedError::BelowMinTransfer { .. } => ApiErrorCode::BelowMinTransfer,
+            QedError::TreeCorruption(_) => ApiErrorCode::TreeCorruption,
+            QedError::LeafOutOfRange { .. } => ApiErrorCode::InvalidVoter,
+            QedError::ProofGenerationFailed {
+                stage: ProofStage::Aggregation,
+                ..
+            } => ApiErrorCode::AggregationFailed,
+            QedError::ProofGenerationFailed {
+                stage: ProofStage::Verification,
+                ..
+            } => ApiErrorCode::PublicInputsMismatch,
+            QedError::ProofGenerationFailed { .. } => ApiErrorCode::ProvingFailed,
+            QedError::ChainSubmissionFailed(_) => ApiErrorCode::ChainSubmissionFailed,
+            QedError::StatusConflict { .. } => ApiErrorCode::StatusConflict,
+            QedError::FinalizationClaimed(_) => ApiErrorCode::ProposalFinalizing,
+        }
+    }
+    pub fn http_status(&self) -> u16 {
+        self.code().http_status()
+    }
+    /// The [`ApiError`] an unexpected failure of the balance tree is answered
+    /// with: the [`QedError`] it wraps, or else a [`QedError::TreeCorruption`].
+    pub fn from_storage(err: anyhow::Error) -> ApiError {
+        match err.downcast::<QedError>() {
+            Ok(err) => err.into(),
+            Err(err) => QedError::TreeCorruption(format!("{:#}", err)).into(),
+        }
+    }
+}
+
+impl fmt::Display for QedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QedError::ProposalNotFound(id) => write!(f, "proposal {} not found", id),
+            QedError::AlreadyFinalized(id) => write!(f, "proposal {} is finalized", id),
+            QedError::InsufficientWeight {
+                leaf,
+                balance,
+                amount,
+            } => write!(
+                f,
+                "leaf {} holds {}, less than the {} moved out of it",
+                leaf, balance, amount
+            ),
+            QedError::BelowMinTransfer {
+                leaf,
+                amount,
+                minimum,
+            } => write!(
+                f,
+                "leaf {} moves {}, less than the minimum of {}",
+                leaf, amount, minimum
+            ),
+            QedError::TreeCorruption(message) => write!(f, "balance tree corrupted: {}", message),
+            QedError::LeafOutOfRange { leaf, voters } => write!(
+                f,
+                "leaf {} is outside the leaves of the {} voters of the tree",
+                leaf, voters
+            ),
+            QedError::ProofGenerationFailed { stage, message } => {
+                write!(f, "{} stage of proving failed: {}", stage.as_str(), message)
+            }
+            QedError::ChainSubmissionFailed(message) => {
+                write!(f, "submitting to the chain failed: {}", message)
+            }
+            QedError::StatusConflict {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "proposal {} is {:?} rather than {:?}",
+                id, actual, expected
+            ),
+            QedError::FinalizationClaimed(id) => {
+                write!(f, "proposal {} is being finalized by another job", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QedError {}
+
+impl From<QedError> for ApiError {
+    fn from(err: QedError) -> Self {
+        ApiError::new(err.code(), &err)
+    }
+}
+
+/// An entry of the error catalog served by `GET /errors`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorCatalogEntry {
+    pub code: ApiErrorCode,
+    pub http_status: u16,
+    pub retryable: bool,
+    pub description: String,
+}
+
+pub fn error_catalog() -> Vec<ErrorCatalogEntry> {
+    ApiErrorCode::ALL
+        .iter()
+        .map(|code| ErrorCatalogEntry {
+            code: *code,
+            http_status: code.http_status(),
+            retryable: code.is_retryable(),
+            description: code.description().to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use uuid::Uuid;
+
+    use super::{error_catalog, ApiError, ApiErrorCode, ProofStage, QedError};
+    use crate::balance::{
+        accounts::{BalanceTx, TallySlot, VoterLeaf},
+        storage::BalanceStorage,
+        weight::{Weight, WeightDelta},
+    };
+
+    #[test]
+    fn test_error_codes_are_unique() {
+        let catalog = error_catalog();
+        let codes: HashSet<_> = catalog.iter().map(|entry| entry.code.as_str()).collect();
+        assert_eq!(codes.len(), catalog.len());
+        assert!(catalog
+            .iter()
+            .all(|entry| (400..600).contains(&entry.http_status)));
+    }
+
+    #[test]
+    fn test_error_bodies_round_trip() {
+        let error = ApiError::new(ApiErrorCode::VotingClosed, "Voting period has ended");
+        let body = serde_json::to_string(&error).unwrap();
+        assert_eq!(
+            body,
+            r#"{"code":"voting_closed","message":"Voting period has ended"}"#
+        );
+        assert_eq!(serde_json::from_str::<ApiError>(&body).unwrap(), error);
+        assert!(serde_json::from_str::<ApiError>(r#"{"code":"nope","message":""}"#).is_err());
+    }
+
+    #[test]
+    fn test_library_errors_map_to_codes() {
+        let mut storage = BalanceStorage::new(8, [3u32].map(Weight::from).to_vec());
+        let err = storage
+            .process_tx(BalanceTx::Vote {
+                voter: VoterLeaf::from_position(0),
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(4),
+            })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<QedError>(),
+            Some(&QedError::InsufficientWeight {
+                leaf: VoterLeaf::from_position(0).index(),
+                balance: Weight::from(3),
+                amount: WeightDelta::from(4),
+            })
+        );
+        let error = QedError::from_storage(err);
+        assert_eq!(error.code, ApiErrorCode::InsufficientWeight);
+        assert_eq!(error.code.http_status(), 400);
+
+        // Anything else the tree fails with is taken for corruption
+        let error = QedError::from_storage(anyhow::anyhow!("node missing"));
+        assert_eq!(error.code, ApiErrorCode::TreeCorruption);
+        let aggregation = QedError::ProofGenerationFailed {
+            stage: ProofStage::Aggregation,
+            message: "panicked".to_string(),
+        };
+        assert_eq!(aggregation.code(), ApiErrorCode::AggregationFailed);
+        assert_eq!(
+            QedError::AlreadyFinalized(Uuid::nil()).http_status(),
+            ApiErrorCode::ProposalFinalized.http_status()
+        );
+    }
+}
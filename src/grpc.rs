@@ -0,0 +1,474 @@
+//! Messages of the gRPC API, generated from `proto/qed.proto`, and their
+//! conversions to and from the types of the HTTP API in [`crate::api`].
+//!
+//! Requests are converted to the queries of the HTTP API and handled the same
+//! way, so the two APIs cannot drift apart in what they accept.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure};
+use plonky2::{
+    field::{
+        goldilocks_field::GoldilocksField,
+        types::{Field, Field64, PrimeField64},
+    },
+    hash::hash_types::HashOut,
+};
+use tonic::{metadata::MetadataValue, Code, Status};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        ActionResponse, DelegateQuery, FinalizeQuery, FinalizeResponse, ProposeQuery, VoteQuery,
+    },
+    balance::{accounts::VoteSplit, weight::Weight},
+    circuits::quadratic::VotingPolicy,
+    common::{
+        hash::merkle::helpers::merkle_proof::{DeltaMerkleProof, MerkleProof},
+        WHashOut,
+    },
+    did::Did,
+    errors::{ApiError, ApiErrorCode},
+    proof::{codec::ProofEnvelope, membership::MembershipProof},
+    proposal::rules::{ConvictionRules, ProposalOutcome, TiePolicy},
+};
+
+pub mod pb {
+    tonic::include_proto!("qed.v1");
+}
+
+type F = GoldilocksField;
+
+/// Metadata key of a failed call naming its [`ApiErrorCode`].
+pub const ERROR_CODE_METADATA_KEY: &str = "qed-error-code";
+
+/// Size of the proof byte chunks [`pb::ProofChunk`] streams are cut into.
+pub const PROOF_CHUNK_SIZE: usize = 64 * 1024;
+
+fn element_from_u64(value: u64) -> anyhow::Result<F> {
+    ensure!(
+        value < F::ORDER,
+        "{} is not a canonical field element",
+        value
+    );
+    Ok(F::from_canonical_u64(value))
+}
+
+fn parse_proposal_id(proposal_id: &str) -> anyhow::Result<Uuid> {
+    Uuid::parse_str(proposal_id).map_err(|err| anyhow!("invalid proposal id: {}", err))
+}
+
+impl From<WHashOut<F>> for pb::HashOut {
+    fn from(hash: WHashOut<F>) -> Self {
+        Self {
+            elements: hash
+                .0
+                .elements
+                .iter()
+                .map(|element| element.to_canonical_u64())
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::HashOut> for WHashOut<F> {
+    type Error = anyhow::Error;
+
+    fn try_from(hash: pb::HashOut) -> anyhow::Result<Self> {
+        let elements = hash
+            .elements
+            .into_iter()
+            .map(element_from_u64)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let elements: [F; 4] = elements
+            .try_into()
+            .map_err(|elements: Vec<F>| anyhow!("hash has {} elements, not 4", elements.len()))?;
+        Ok(WHashOut(HashOut { elements }))
+    }
+}
+
+fn hash_from_field(hash: Option<pb::HashOut>, field: &str) -> anyhow::Result<WHashOut<F>> {
+    hash.ok_or_else(|| anyhow!("{} is missing", field))?
+        .try_into()
+}
+
+fn siblings_from_pb(siblings: Vec<pb::HashOut>) -> anyhow::Result<Vec<WHashOut<F>>> {
+    siblings.into_iter().map(WHashOut::try_from).collect()
+}
+
+impl From<MerkleProof<F>> for pb::MerkleProof {
+    fn from(proof: MerkleProof<F>) -> Self {
+        Self {
+            root: Some(proof.root.into()),
+            value: Some(proof.value.into()),
+            index: proof.index.to_canonical_u64(),
+            siblings: proof.siblings.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::MerkleProof> for MerkleProof<F> {
+    type Error = anyhow::Error;
+
+    fn try_from(proof: pb::MerkleProof) -> anyhow::Result<Self> {
+        Ok(Self {
+            root: hash_from_field(proof.root, "root")?,
+            value: hash_from_field(proof.value, "value")?,
+            index: element_from_u64(proof.index)?,
+            siblings: siblings_from_pb(proof.siblings)?,
+        })
+    }
+}
+
+impl From<DeltaMerkleProof<F>> for pb::DeltaMerkleProof {
+    fn from(proof: DeltaMerkleProof<F>) -> Self {
+        Self {
+            old_root: Some(proof.old_root.into()),
+            old_value: Some(proof.old_value.into()),
+            new_root: Some(proof.new_root.into()),
+            new_value: Some(proof.new_value.into()),
+            index: proof.index.to_canonical_u64(),
+            siblings: proof.siblings.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::DeltaMerkleProof> for DeltaMerkleProof<F> {
+    type Error = anyhow::Error;
+
+    fn try_from(proof: pb::DeltaMerkleProof) -> anyhow::Result<Self> {
+        Ok(Self {
+            old_root: hash_from_field(proof.old_root, "old_root")?,
+            old_value: hash_from_field(proof.old_value, "old_value")?,
+            new_root: hash_from_field(proof.new_root, "new_root")?,
+            new_value: hash_from_field(proof.new_value, "new_value")?,
+            index: element_from_u64(proof.index)?,
+            siblings: siblings_from_pb(proof.siblings)?,
+        })
+    }
+}
+
+impl From<ProofEnvelope> for pb::ProofEnvelope {
+    fn from(envelope: ProofEnvelope) -> Self {
+        Self {
+            version: envelope.version as u32,
+            circuit_id: envelope.circuit_id,
+            common_data_hash: envelope.common_data_hash,
+            public_inputs: envelope.public_inputs,
+            proof_bytes: envelope.proof_bytes,
+        }
+    }
+}
+
+impl TryFrom<pb::ProofEnvelope> for ProofEnvelope {
+    type Error = anyhow::Error;
+
+    fn try_from(envelope: pb::ProofEnvelope) -> anyhow::Result<Self> {
+        Ok(Self {
+            version: u16::try_from(envelope.version)?,
+            circuit_id: envelope.circuit_id,
+            common_data_hash: envelope.common_data_hash,
+            public_inputs: envelope.public_inputs,
+            proof_bytes: envelope.proof_bytes,
+        })
+    }
+}
+
+/// Splits `envelope` into a header without the proof bytes, followed by the
+/// proof bytes in chunks of at most `chunk_size`.
+pub fn proof_chunks(mut envelope: ProofEnvelope, chunk_size: usize) -> Vec<pb::ProofChunk> {
+    let proof_bytes = std::mem::take(&mut envelope.proof_bytes);
+    std::iter::once(pb::proof_chunk::Chunk::Header(envelope.into()))
+        .chain(
+            proof_bytes
+                .chunks(chunk_size)
+                .map(|chunk| pb::proof_chunk::Chunk::ProofBytes(chunk.to_vec())),
+        )
+        .map(|chunk| pb::ProofChunk { chunk: Some(chunk) })
+        .collect()
+}
+
+/// Puts back together an envelope streamed by [`proof_chunks`].
+pub fn collect_proof_chunks(
+    chunks: impl IntoIterator<Item = pb::ProofChunk>,
+) -> anyhow::Result<ProofEnvelope> {
+    let mut chunks = chunks.into_iter().map(|chunk| chunk.chunk);
+    let mut envelope = match chunks.next() {
+        Some(Some(pb::proof_chunk::Chunk::Header(header))) => ProofEnvelope::try_from(header)?,
+        _ => bail!("proof stream does not start with a header"),
+    };
+    for chunk in chunks {
+        match chunk {
+            Some(pb::proof_chunk::Chunk::ProofBytes(bytes)) => envelope.proof_bytes.extend(bytes),
+            _ => bail!("proof stream has a chunk other than proof bytes after its header"),
+        }
+    }
+    Ok(envelope)
+}
+
+impl TryFrom<pb::ProposeRequest> for ProposeQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(request: pb::ProposeRequest) -> anyhow::Result<Self> {
+        let tie_policy = match pb::TiePolicy::try_from(request.tie_policy)? {
+            pb::TiePolicy::Unspecified => None,
+            pb::TiePolicy::Veto => Some(TiePolicy::Veto),
+            pb::TiePolicy::Pass => Some(TiePolicy::Pass),
+            pb::TiePolicy::Revote => Some(TiePolicy::Revote),
+            pb::TiePolicy::RandomWithBeacon => Some(TiePolicy::RandomWithBeacon),
+        };
+        let voting_policy = match pb::VotingPolicy::try_from(request.voting_policy)? {
+            pb::VotingPolicy::Unspecified => None,
+            pb::VotingPolicy::Linear => Some(VotingPolicy::Linear),
+            pb::VotingPolicy::Quadratic => Some(VotingPolicy::Quadratic),
+        };
+        let voter_dids = if request.voter_dids.is_empty() {
+            None
+        } else {
+            Some(
+                request
+                    .voter_dids
+                    .iter()
+                    .map(|did| Did::from_str(did))
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        };
+        let depends_on = if request.depends_on.is_empty() {
+            None
+        } else {
+            Some(
+                request
+                    .depends_on
+                    .iter()
+                    .map(|id| parse_proposal_id(id))
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        };
+        Ok(Self {
+            proposer_id: request.proposer_id,
+            statement: request.statement,
+            content: None,
+            action: None,
+            voting_period_secs: request.voting_period_secs,
+            quorum: request.quorum.map(Weight::try_from).transpose()?,
+            tie_policy,
+            beacon_block: request.beacon_block,
+            token_snapshot: None,
+            dao_id: request.dao_id,
+            category: None,
+            commit_period_secs: request.commit_period_secs,
+            balance_bits: request.balance_bits.map(|bits| bits as usize),
+            voter_dids,
+            electorate_size: request.electorate_size.map(usize::try_from).transpose()?,
+            conviction: request
+                .conviction_step_secs
+                .map(|step_secs| ConvictionRules { step_secs }),
+            voting_policy,
+            depends_on,
+            nonce: request.nonce,
+            blind_voters: request.blind_voters.then_some(true),
+            finalizers: None,
+            lock_tokens: None,
+            ballot_committee: None,
+            vesting: None,
+            min_transfer: None,
+            shard_count: None,
+        })
+    }
+}
+
+impl TryFrom<pb::VoteRequest> for VoteQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(request: pb::VoteRequest) -> anyhow::Result<Self> {
+        let split = request
+            .split
+            .map(|split| -> anyhow::Result<_> {
+                Ok(VoteSplit {
+                    yes_votes: Weight::try_from(split.yes_votes)?,
+                    no_votes: Weight::try_from(split.no_votes)?,
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            proposal_id: parse_proposal_id(&request.proposal_id)?,
+            voter_id: request.voter_id,
+            is_yes: request.is_yes,
+            split,
+            salt: request.salt,
+            ballot: None,
+            did_signature: request.did_signature,
+            did_nonce: request.did_nonce,
+        })
+    }
+}
+
+impl TryFrom<pb::DelegateRequest> for DelegateQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(request: pb::DelegateRequest) -> anyhow::Result<Self> {
+        Ok(Self {
+            proposal_id: parse_proposal_id(&request.proposal_id)?,
+            voter_id: request.voter_id,
+            delegator_id: request.delegator_id,
+            did_signature: request.did_signature,
+            did_nonce: request.did_nonce,
+        })
+    }
+}
+
+impl TryFrom<pb::FinalizeRequest> for FinalizeQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(request: pb::FinalizeRequest) -> anyhow::Result<Self> {
+        Ok(Self {
+            proposal_id: parse_proposal_id(&request.proposal_id)?,
+            finalizer_id: request.finalizer_id,
+        })
+    }
+}
+
+impl TryFrom<pb::ProofRequest> for Uuid {
+    type Error = anyhow::Error;
+
+    fn try_from(request: pb::ProofRequest) -> anyhow::Result<Self> {
+        parse_proposal_id(&request.proposal_id)
+    }
+}
+
+impl TryFrom<pb::MembershipRequest> for (Uuid, u32) {
+    type Error = anyhow::Error;
+
+    fn try_from(request: pb::MembershipRequest) -> anyhow::Result<Self> {
+        Ok((parse_proposal_id(&request.proposal_id)?, request.voter_id))
+    }
+}
+
+impl From<ActionResponse> for pb::ActionReply {
+    fn from(response: ActionResponse) -> Self {
+        Self {
+            proposal_id: response.proposal_id.to_string(),
+            message: response.message,
+        }
+    }
+}
+
+impl From<FinalizeResponse> for pb::FinalizeReply {
+    fn from(response: FinalizeResponse) -> Self {
+        let outcome = match response.outcome {
+            ProposalOutcome::Passed => pb::ProposalOutcome::Passed,
+            ProposalOutcome::Vetoed => pb::ProposalOutcome::Vetoed,
+            ProposalOutcome::Revote => pb::ProposalOutcome::Revote,
+        };
+        Self {
+            proposal_id: response.proposal_id.to_string(),
+            yes_votes: response.tally.yes_votes.get(),
+            no_votes: response.tally.no_votes.get(),
+            outcome: outcome.into(),
+            revote: response.revote.map(|id| id.to_string()),
+        }
+    }
+}
+
+impl From<MembershipProof> for pb::MembershipProof {
+    fn from(proof: MembershipProof) -> Self {
+        Self {
+            proposal_id: proof.proposal_id.to_string(),
+            voter_id: proof.voter_id,
+            weight: proof.weight.get(),
+            proof: Some(proof.proof.into()),
+            blinded: proof.blinded,
+        }
+    }
+}
+
+impl TryFrom<pb::MembershipProof> for MembershipProof {
+    type Error = anyhow::Error;
+
+    fn try_from(proof: pb::MembershipProof) -> anyhow::Result<Self> {
+        Ok(Self {
+            proposal_id: parse_proposal_id(&proof.proposal_id)?,
+            voter_id: proof.voter_id,
+            weight: Weight::try_from(proof.weight)?,
+            blinded: proof.blinded,
+            proof: proof
+                .proof
+                .ok_or_else(|| anyhow!("proof is missing"))?
+                .try_into()?,
+        })
+    }
+}
+
+/// The status a rejected request fails with, the gRPC counterpart of the HTTP
+/// status of its code, with the code itself in [`ERROR_CODE_METADATA_KEY`].
+pub fn status_from_api_error(error: ApiError) -> Status {
+    let code = match error.code.http_status() {
+        400 => Code::InvalidArgument,
+        401 => Code::Unauthenticated,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        429 => Code::ResourceExhausted,
+        502 | 503 => Code::Unavailable,
+        400..=499 => Code::FailedPrecondition,
+        _ => Code::Internal,
+    };
+    let mut status = Status::new(code, error.message);
+    status.metadata_mut().insert(
+        ERROR_CODE_METADATA_KEY,
+        MetadataValue::from_static(error.code.as_str()),
+    );
+    status
+}
+
+/// The status of a request whose message does not convert to a query.
+pub fn invalid_request(err: anyhow::Error) -> Status {
+    status_from_api_error(ApiError::new(ApiErrorCode::InvalidQuery, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+    use uuid::Uuid;
+
+    use crate::{
+        balance::{accounts::VoterLeaf, storage::BalanceStorage, weight::Weight},
+        errors::{ApiError, ApiErrorCode},
+        proof::{codec::ProofEnvelope, membership::MembershipProof},
+    };
+
+    use super::{
+        collect_proof_chunks, pb, proof_chunks, status_from_api_error, ERROR_CODE_METADATA_KEY,
+    };
+
+    #[test]
+    fn test_messages_round_trip() -> anyhow::Result<()> {
+        let storage = BalanceStorage::new(4, vec![Weight::from(3), Weight::from(5)]);
+        let proof = storage.initial_membership_proof(VoterLeaf::from_position(1))?;
+        let membership = MembershipProof::new(Uuid::new_v4(), 1, proof)?;
+        let message = pb::MembershipProof::from(membership.clone());
+        assert_eq!(MembershipProof::try_from(message)?, membership);
+
+        let envelope = ProofEnvelope {
+            version: 1,
+            circuit_id: "update_balance".to_string(),
+            common_data_hash: vec![7; 32],
+            public_inputs: vec![1, 2, 3],
+            proof_bytes: (0..=255).collect(),
+        };
+        let chunks = proof_chunks(envelope.clone(), 100);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(collect_proof_chunks(chunks.clone())?, envelope);
+        assert!(collect_proof_chunks(chunks.into_iter().skip(1)).is_err());
+
+        let status = status_from_api_error(ApiError::new(
+            ApiErrorCode::ProposalNotFound,
+            "Proposal not found",
+        ));
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(
+            status.metadata().get(ERROR_CODE_METADATA_KEY).unwrap(),
+            "proposal_not_found"
+        );
+        Ok(())
+    }
+}
@@ -2,4 +2,22 @@
 pub mod common;
 pub mod utils;
 pub mod debug;
+pub mod proof;
+pub mod nullifier;
+pub mod chain;
+pub mod balance;
+pub mod circuits;
+pub mod proposal;
+pub mod errors;
+pub mod audit;
+pub mod simulation;
+pub mod loadgen;
+pub mod did;
+pub mod api;
+pub mod qed_client;
+pub mod snapshot;
+pub mod auth;
+pub mod webhook;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 extern crate alloc;
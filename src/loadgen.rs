@@ -0,0 +1,498 @@
+//! Synthetic load against a running server, for capacity planning before a
+//! deployment: creates proposals of synthetic voters, casts a mix of votes on
+//! them with bounded concurrency, finalizes them and reports the latency of each
+//! kind of request along with the depth of the prover queue, see `qed-loadgen`.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+use uuid::Uuid;
+
+use crate::{
+    api::{FinalizeQuery, ProposeQuery, RevokeQuery, VoteQuery},
+    balance::{
+        accounts::{VoteSplit, VoterLeaf},
+        weight::Weight,
+    },
+    errors::ApiError,
+    proposal::{
+        store::{ProposalQuery, ProposalStatusFilter, MAX_PER_PAGE},
+        ProposalStatus,
+    },
+    qed_client::QedClient,
+};
+
+/// A request a synthetic voter sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VoteKind {
+    Yes,
+    No,
+    /// Casts the weight of the voter on yes and no at once.
+    Split,
+    /// Casts a yes vote, then revokes it.
+    Revoke,
+}
+
+impl VoteKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VoteKind::Yes => "yes",
+            VoteKind::No => "no",
+            VoteKind::Split => "split",
+            VoteKind::Revoke => "revoke",
+        }
+    }
+}
+
+/// Relative weights of the kinds of vote, e.g. `yes=6,no=3,split=1` for six yes
+/// votes out of ten. Kinds left out are not sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoteMix {
+    pub yes: u32,
+    pub no: u32,
+    pub split: u32,
+    pub revoke: u32,
+}
+
+impl Default for VoteMix {
+    fn default() -> Self {
+        Self {
+            yes: 1,
+            no: 1,
+            split: 0,
+            revoke: 0,
+        }
+    }
+}
+
+impl VoteMix {
+    fn weights(&self) -> [(VoteKind, u32); 4] {
+        [
+            (VoteKind::Yes, self.yes),
+            (VoteKind::No, self.no),
+            (VoteKind::Split, self.split),
+            (VoteKind::Revoke, self.revoke),
+        ]
+    }
+    pub fn total(&self) -> u32 {
+        self.weights().iter().map(|(_, weight)| weight).sum()
+    }
+    /// The kind a roll below [`Self::total`] lands on.
+    pub fn pick(&self, mut roll: u32) -> VoteKind {
+        for (kind, weight) in self.weights() {
+            if roll < weight {
+                return kind;
+            }
+            roll -= weight;
+        }
+        panic!("roll is not below the total weight {}", self.total());
+    }
+}
+
+impl FromStr for VoteMix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut mix = VoteMix {
+            yes: 0,
+            no: 0,
+            split: 0,
+            revoke: 0,
+        };
+        for entry in s.split(',').filter(|entry| !entry.is_empty()) {
+            let (kind, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected kind=weight, got {}", entry))?;
+            let weight = weight.trim().parse()?;
+            match kind.trim() {
+                "yes" => mix.yes = weight,
+                "no" => mix.no = weight,
+                "split" => mix.split = weight,
+                "revoke" => mix.revoke = weight,
+                kind => return Err(anyhow!("unknown vote kind {}", kind)),
+            }
+        }
+        ensure!(mix.total() > 0, "the vote mix sends nothing");
+        Ok(mix)
+    }
+}
+
+impl fmt::Display for VoteMix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "yes={},no={},split={},revoke={}",
+            self.yes, self.no, self.split, self.revoke
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub proposals: usize,
+    /// Synthetic voters of each proposal, with one vote each.
+    pub voters: usize,
+    /// Requests in flight at once.
+    pub concurrency: usize,
+    pub mix: VoteMix,
+    /// Finalizes the proposals once every vote is in, proving them.
+    pub finalize: bool,
+    pub proposer_id: u32,
+    /// DAO the proposals are created in, which the prover queue is sampled from.
+    pub dao_id: String,
+    /// How often the prover queue is sampled.
+    pub sample_interval: Duration,
+    /// Seeds the kind of vote of each voter, so runs send the same requests.
+    pub seed: u64,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            proposals: 1,
+            voters: 100,
+            concurrency: 16,
+            mix: VoteMix::default(),
+            finalize: false,
+            proposer_id: 0,
+            dao_id: format!("loadgen-{}", Uuid::new_v4()),
+            sample_interval: Duration::from_millis(500),
+            seed: 0,
+        }
+    }
+}
+
+/// Latencies of one kind of request, in milliseconds. Percentiles are taken
+/// over the requests that succeeded.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub requests: usize,
+    pub failed: usize,
+    /// Failed requests by error code, `transport` for those that got no answer.
+    pub errors: BTreeMap<String, usize>,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    pub fn new(samples: &[Result<Duration, String>]) -> Self {
+        let mut latencies: Vec<f64> = samples
+            .iter()
+            .filter_map(|sample| sample.as_ref().ok())
+            .map(|latency| latency.as_secs_f64() * 1000.0)
+            .collect();
+        latencies.sort_by(f64::total_cmp);
+        let mut errors = BTreeMap::new();
+        for code in samples.iter().filter_map(|sample| sample.as_ref().err()) {
+            *errors.entry(code.clone()).or_default() += 1;
+        }
+        Self {
+            requests: samples.len(),
+            failed: samples.len() - latencies.len(),
+            errors,
+            p50_ms: percentile(&latencies, 50),
+            p90_ms: percentile(&latencies, 90),
+            p99_ms: percentile(&latencies, 99),
+            max_ms: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Nearest rank percentile of sorted `values`, 0 if there are none.
+fn percentile(values: &[f64], rank: usize) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let index = ((values.len() * rank + 99) / 100).max(1) - 1;
+    values[index]
+}
+
+/// Proposals being proven at once, out of those of the run.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueueDepth {
+    pub samples: usize,
+    pub max: usize,
+    pub mean: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoadReport {
+    pub proposal_ids: Vec<Uuid>,
+    pub elapsed_ms: u64,
+    /// Successful requests per second, over the whole run.
+    pub throughput: f64,
+    /// Latencies by kind of request: `propose`, each vote kind and `finalize`.
+    pub latencies: BTreeMap<String, LatencySummary>,
+    pub prover_queue: QueueDepth,
+}
+
+type Samples = BTreeMap<&'static str, Vec<Result<Duration, String>>>;
+
+/// Times `request`, keeping the error code it failed with.
+async fn timed<T>(
+    request: impl std::future::Future<Output = anyhow::Result<T>>,
+) -> Result<Duration, String> {
+    let started_at = Instant::now();
+    match request.await {
+        Ok(_) => Ok(started_at.elapsed()),
+        Err(err) => Err(err
+            .downcast_ref::<ApiError>()
+            .map_or("transport", |err| err.code.as_str())
+            .to_string()),
+    }
+}
+
+/// The requests one synthetic voter sends, each with the name it is reported under.
+async fn cast(
+    client: &QedClient,
+    proposal_id: Uuid,
+    voter_id: u32,
+    kind: VoteKind,
+) -> Vec<(&'static str, Result<Duration, String>)> {
+    let vote = VoteQuery {
+        proposal_id,
+        voter_id,
+        is_yes: kind != VoteKind::No,
+        split: None,
+        salt: None,
+        ballot: None,
+        did_signature: None,
+        did_nonce: 0,
+    };
+    match kind {
+        VoteKind::Yes | VoteKind::No => vec![(kind.as_str(), timed(client.vote(&vote)).await)],
+        VoteKind::Split => {
+            // Synthetic voters hold one vote, which goes to an option picked by their id
+            let split = VoteQuery {
+                split: Some(VoteSplit {
+                    yes_votes: Weight::from(voter_id % 2),
+                    no_votes: Weight::from(1 - voter_id % 2),
+                }),
+                ..vote
+            };
+            vec![(kind.as_str(), timed(client.vote(&split)).await)]
+        }
+        VoteKind::Revoke => {
+            let voted = timed(client.vote(&vote)).await;
+            if voted.is_err() {
+                return vec![(VoteKind::Yes.as_str(), voted)];
+            }
+            let revoke = RevokeQuery {
+                proposal_id,
+                voter_id,
+                did_signature: None,
+                did_nonce: 0,
+            };
+            vec![
+                (VoteKind::Yes.as_str(), voted),
+                (kind.as_str(), timed(client.revoke(&revoke)).await),
+            ]
+        }
+    }
+}
+
+/// Proposals of `dao_id` being proven right now.
+async fn prover_queue_depth(client: &QedClient, dao_id: &str) -> anyhow::Result<usize> {
+    let mut depth = 0;
+    for page in 1.. {
+        let proposals = client
+            .list_proposals(&ProposalQuery {
+                status: Some(ProposalStatusFilter::Open),
+                dao_id: Some(dao_id.to_string()),
+                page,
+                per_page: MAX_PER_PAGE,
+                ..ProposalQuery::default()
+            })
+            .await?;
+        depth += proposals
+            .iter()
+            .filter(|proposal| proposal.status == ProposalStatus::Finalizing)
+            .count();
+        if proposals.len() < MAX_PER_PAGE {
+            break;
+        }
+    }
+    Ok(depth)
+}
+
+/// Samples the prover queue every `interval` until `stop` is set.
+async fn sample_queue(
+    client: QedClient,
+    dao_id: String,
+    interval: Duration,
+    mut stop: watch::Receiver<bool>,
+) -> Vec<usize> {
+    let mut ticker = tokio::time::interval(interval);
+    let mut depths = vec![];
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stop.changed() => return depths,
+        }
+        // A sample the server did not answer is skipped rather than counted as empty
+        if let Ok(depth) = prover_queue_depth(&client, &dao_id).await {
+            depths.push(depth);
+        }
+    }
+}
+
+/// Runs every task of `tasks`, at most `concurrency` at a time, gathering the
+/// samples they return.
+async fn run_bounded<F>(
+    concurrency: usize,
+    tasks: impl IntoIterator<Item = F>,
+    samples: &mut Samples,
+) -> anyhow::Result<()>
+where
+    F: std::future::Future<Output = Vec<(&'static str, Result<Duration, String>)>> + Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut running = JoinSet::new();
+    for task in tasks {
+        let permit = permits.clone().acquire_owned().await?;
+        running.spawn(async move {
+            let results = task.await;
+            drop(permit);
+            results
+        });
+    }
+    while let Some(results) = running.join_next().await {
+        for (name, sample) in results? {
+            samples.entry(name).or_default().push(sample);
+        }
+    }
+    Ok(())
+}
+
+/// Creates the proposals, casts the votes and, if asked to, finalizes the
+/// proposals, against the server `client` talks to.
+pub async fn run(client: &QedClient, options: &LoadOptions) -> anyhow::Result<LoadReport> {
+    ensure!(options.voters > 0, "proposals need at least one voter");
+    let started_at = Instant::now();
+    let mut samples = Samples::new();
+    let (stop, stopped) = watch::channel(false);
+    let sampler = tokio::spawn(sample_queue(
+        client.clone(),
+        options.dao_id.clone(),
+        options.sample_interval,
+        stopped,
+    ));
+
+    let mut proposal_ids = vec![];
+    for index in 0..options.proposals {
+        let query = ProposeQuery {
+            proposer_id: options.proposer_id,
+            statement: format!("Load test proposal {} of {}", index + 1, options.proposals),
+            dao_id: Some(options.dao_id.clone()),
+            electorate_size: Some(options.voters),
+            ..ProposeQuery::default()
+        };
+        let started_at = Instant::now();
+        let created = client.propose(&query).await?;
+        samples
+            .entry("propose")
+            .or_default()
+            .push(Ok(started_at.elapsed()));
+        proposal_ids.push(created.proposal_id);
+    }
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut votes = vec![];
+    for proposal_id in &proposal_ids {
+        for position in 0..options.voters as u64 {
+            let kind = options.mix.pick(rng.gen_range(0..options.mix.total()));
+            let voter_id = VoterLeaf::from_position(position).index() as u32;
+            let client = client.clone();
+            let proposal_id = *proposal_id;
+            votes.push(async move { cast(&client, proposal_id, voter_id, kind).await });
+        }
+    }
+    run_bounded(options.concurrency, votes, &mut samples).await?;
+
+    if options.finalize {
+        let finalizations = proposal_ids.iter().map(|proposal_id| {
+            let client = client.clone();
+            let query = FinalizeQuery {
+                proposal_id: *proposal_id,
+                finalizer_id: options.proposer_id,
+            };
+            async move { vec![("finalize", timed(client.finalize(&query)).await)] }
+        });
+        run_bounded(options.concurrency, finalizations, &mut samples).await?;
+    }
+    stop.send(true)?;
+    let depths = sampler.await?;
+
+    let elapsed = started_at.elapsed();
+    let succeeded = samples.values().flatten().filter(|sample| sample.is_ok());
+    Ok(LoadReport {
+        proposal_ids,
+        elapsed_ms: elapsed.as_millis() as u64,
+        throughput: succeeded.count() as f64 / elapsed.as_secs_f64(),
+        latencies: samples
+            .iter()
+            .map(|(name, samples)| (name.to_string(), LatencySummary::new(samples)))
+            .collect(),
+        prover_queue: QueueDepth {
+            samples: depths.len(),
+            max: depths.iter().copied().max().unwrap_or_default(),
+            mean: depths.iter().sum::<usize>() as f64 / depths.len().max(1) as f64,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LatencySummary, VoteKind, VoteMix};
+
+    #[test]
+    fn test_mix_and_percentiles() -> anyhow::Result<()> {
+        let mix: VoteMix = "yes=6, no=3,split=1".parse()?;
+        assert_eq!(mix.total(), 10);
+        assert_eq!(mix.to_string().parse::<VoteMix>()?, mix);
+        assert_eq!(
+            [0, 5, 6, 8, 9].map(|roll| mix.pick(roll)),
+            [
+                VoteKind::Yes,
+                VoteKind::Yes,
+                VoteKind::No,
+                VoteKind::No,
+                VoteKind::Split
+            ]
+        );
+        assert!("abstain=1".parse::<VoteMix>().is_err());
+        assert!("yes=0".parse::<VoteMix>().is_err());
+
+        let mut samples: Vec<_> = (1..=100).map(|ms| Ok(Duration::from_millis(ms))).collect();
+        samples.push(Err("rate_limited".to_string()));
+        let summary = LatencySummary::new(&samples);
+        assert_eq!((summary.requests, summary.failed), (101, 1));
+        assert_eq!(summary.errors["rate_limited"], 1);
+        assert_eq!(
+            (
+                summary.p50_ms,
+                summary.p90_ms,
+                summary.p99_ms,
+                summary.max_ms
+            ),
+            (50.0, 90.0, 99.0, 100.0)
+        );
+        Ok(())
+    }
+}
@@ -1,404 +1,7193 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use actix_cors::Cors;
+use actix_web::{
+    body::{BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{InternalError, JsonPayloadError},
+    http::{
+        header::{self, ContentType},
+        Method, StatusCode,
+    },
+    middleware::{from_fn, Condition, Next},
+    web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, PoisonError,
+};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::{error, field, info, info_span, warn, Instrument};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
+use web3::types::Address;
+
+#[cfg(feature = "sql")]
+use plonky2_tree_hacks::proposal::metadata::{diff_metadata, SqlMetadataStore};
+#[cfg(feature = "chaos")]
+use plonky2_tree_hacks::utils::chaos::{self, ChaosConfig};
 
 use plonky2::{
-    field::{extension::Extendable, goldilocks_field::GoldilocksField},
-    hash::{hash_types::RichField, poseidon::PoseidonHash},
-    iop::witness::PartialWitness,
-    plonk::{
-        circuit_builder::CircuitBuilder,
-        circuit_data::{CircuitConfig, CircuitData},
-        config::{AlgebraicHasher, GenericConfig, PoseidonGoldilocksConfig},
-        proof::ProofWithPublicInputs,
-    },
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    plonk::config::PoseidonGoldilocksConfig,
+    util::serialization::DefaultGateSerializer,
 };
 use plonky2_tree_hacks::{
-    common::{
-        hash::merkle::{
-            gadgets::delta_merkle_proof::DeltaMerkleProofGadget,
-            helpers::merkle_proof::DeltaMerkleProof,
+    api::{
+        ActionResponse, AmendQuery, BatchDelegationItem, CancelQuery, CategoryPolicyQuery,
+        CommitQuery, CreateOrganizationQuery, CycleFinalizeQuery, DaoUsageResponse,
+        DelegateBatchQuery, DelegateQuery, DepositReceipt, FinalizationPreview,
+        FinalizeApprovalQuery, FinalizeApprovalResponse, FinalizeDryRunQuery,
+        FinalizeDryRunResponse, FinalizeQuery, FinalizeResponse, FundsCreditQuery, IssueKeyQuery,
+        IssuedKeyResponse, LeafProofQuery, LeafProofResponse, PauseQuery, PayoutReceipt,
+        ProposalDivergence, ProposalHistoryQuery, ProposalHistoryResponse, ProposeQuery,
+        RegisterQuery, RelayedVoteQuery, RestoreResponse, ResultRootResponse, RevokeQuery,
+        RotateKeyQuery, ScopedDelegateQuery, ScopedRevokeQuery, TallyHistoryQuery,
+        TallyHistoryResponse, TokenAccount, TokenCreditQuery, TokenLockReceipt, TreasuryAccount,
+        TreasuryCreditQuery, TreeDiffResponse, TreeHealthResponse, VoteQuery, VotersQuery,
+        VotingPauseQuery, WebhookDeliveriesQuery, WebhooksQuery,
+    },
+    audit::{hash_request, AuditAction, AuditEntry, AuditLog},
+    auth::{
+        request_message, required_role, ApiKeyView, KeyRing, Principal, Role,
+        MAX_SIGNATURE_SKEW_SECS,
+    },
+    balance::{
+        accounts::{Tally, TallySlot, VoteSplit, VoterLeaf},
+        funds::{DaoFunds, FundBalance, Payout},
+        locks::{LockStatus, TokenLocks},
+        storage::{min_tree_height, BalanceStorage},
+        treasury::{DepositStatus, Treasury},
+        vesting::{VestingRules, VestingSchedule},
+        weight::Weight,
+    },
+    chain::{
+        anchor::{AnchorRecord, ResultRootRecord, RootAnchor},
+        beacon::{check_beacon_lead, BlockBeacon},
+        governance::{GovernanceListener, MirrorOptions},
+        timestamp::{TimestampAuthority, TimestampRecord, TimestampSubject},
+        token_snapshot::{TokenHolder, TokenSnapshot, TokenSnapshotRequest, TokenSnapshotter},
+    },
+    circuits::{
+        aggregate::aggregate_finalization_circuit_id,
+        cache::CircuitCache,
+        prover::{ProvingRetryPolicy, DEFAULT_PROVE_ATTEMPTS},
+        quadratic::VotingPolicy,
+        registry::CircuitRecord,
+        shard_root::{parse_finalization_circuit_id, shard_root_circuit_id, ShardWitness},
+        update_balance::{
+            parse_update_balance_circuit_id, update_balance_circuit_id, BalanceUpdate,
+            UpdateBalanceShape,
+        },
+    },
+    common::{hash::merkle::helpers::merkle_proof::MerkleProof, WHashOut},
+    did::{did_request_message, Did, DidDocument, VerificationMethod},
+    errors::{error_catalog, ApiError, ApiErrorCode, ErrorCatalogEntry, ProofStage, QedError},
+    nullifier::nullifier_set::NullifierSet,
+    proof::{
+        attestation::{ResultAttestation, ResultAttester},
+        certificate::{
+            compute_action_hash, compute_certificate_binding, compute_statement_hash_with_content,
+            compute_transcript_digest, FinalizationCertificate,
+        },
+        codec::ProofEnvelope,
+        cycle::{compute_cycle_root, CycleCertificate, CycleResult},
+        identity::{DeploymentIdentity, InstanceSigner, IssuerSignature},
+        leaf::{diff_leaves, LeafChange, LeafDeltaProof, LeafProof},
+        membership::MembershipProof,
+        results::{ResultInclusionProof, ResultLeaf, ResultTree},
+        verify::expected_public_inputs,
+    },
+    proposal::{
+        action::ProposalAction,
+        approval::{FinalizeApproval, Finalizer, FinalizerPolicy},
+        archive::{build_archive, VerifierFiles},
+        blinding::{BlindedSlot, BlindingReveal, VoterBlinding},
+        content::{ContentCheck, ContentFetcher, ContentHashKind, ContentStatus, StatementContent},
+        delegation::{BatchDelegation, VotingPower},
+        dependency::{
+            check_dependencies, compute_dependencies_hash, gate_outcome, resolve_dependencies,
+            DependencyResult,
+        },
+        encryption::{
+            BallotBox, BallotBoxView, BallotCommittee, CastBallot, Ciphertext, CommitteeMember,
+            DecryptionShare, EncryptedBallot, PartialDecryption,
+        },
+        events::{apply_event, replay, EventLog, ProposalEvent, ProposalGenesis},
+        id::{derive_proposal_id, derive_revote_id},
+        lock::ProposalLock,
+        metadata::{MetadataQuery, ProposalMetadata},
+        org::{
+            CategoryPolicy, Organization, OrganizationRegistry, OrganizationView,
+            RegistrationStatus, VoterRegistration,
+        },
+        quota::{DaoQuotas, DaoUsage, QuotaKind},
+        relay::RelayedVote,
+        rules::{ConvictionRules, ProposalOutcome, ProposalRules, TiePolicy},
+        sanity::{check_tree, TreeDivergence},
+        scoped_delegation::{
+            DeclinedProposal, DelegationScope, DelegationStep, ScopedDelegation,
+            ScopedDelegationRegistry,
         },
-        u32::multiple_comparison::list_le_circuit,
-        WHashOut,
+        search::{SearchHit, SearchIndex, SearchQuery, Snippet, TextRange},
+        store::{ProposalQuery, ProposalSort, ProposalStatusFilter, ProposalStore},
+        tally_history::{downsample, TallyPoint},
+        transcript::{Transcript, TranscriptAction, TranscriptEvent},
+        validation::{validate_category, validate_statement, validate_voter_dids},
+        view::{CallerView, ProposalView},
+        Proposal, ProposalPhase, ProposalStatus, DEFAULT_DAO_ID, DEFAULT_ELECTORATE_SIZE,
+        MAX_ELECTORATE_SIZE,
     },
-    utils::zmt::{
-        node_store::simple_node_store::SimpleNodeStore, zero_merkle_tree::ZeroMerkleTree,
+    qed_client::QedClient,
+    snapshot::{
+        FollowSummary, ProposalSnapshot, SnapshotFollower, StateSnapshot, SNAPSHOT_VERSION,
+    },
+    utils::{
+        pause::{PauseState, PauseSwitch},
+        range::ByteRange,
+        rate_limit::RateLimiter,
+        request_id::{RequestId, RequestTraces, REQUEST_ID_HEADER},
+        supervisor::{ShutdownSignal, TaskSupervisor},
+        time::unix_timestamp,
+        zmt::node_store::backend::NodeStoreBackend,
+    },
+    webhook::{
+        DeliveryStatus, FinalizationPayload, WebhookDelivery, WebhookQueue, WebhookSender,
+        WebhookTarget,
     },
 };
+use qed_verifier::{check_public_inputs, VerifierData};
 
-pub struct BalanceUpdateGadget {
-    pub sender_update: DeltaMerkleProofGadget,
-    pub receiver_update: DeltaMerkleProofGadget,
-}
-pub struct BalanceUpdate<F: RichField> {
-    pub sender_update: DeltaMerkleProof<F>,
-    pub receiver_update: DeltaMerkleProof<F>,
-}
-impl BalanceUpdateGadget {
-    pub fn add_virtual_to<H: AlgebraicHasher<F>, F: RichField + Extendable<D>, const D: usize>(
-        builder: &mut CircuitBuilder<F, D>,
-        tree_height: usize,
-    ) -> Self {
-        let sender_update = DeltaMerkleProofGadget::add_virtual_to::<H, F, D>(builder, tree_height);
-        let receiver_update =
-            DeltaMerkleProofGadget::add_virtual_to::<H, F, D>(builder, tree_height);
-
-        let amount_recv = builder.sub(
-            receiver_update.new_value.elements[0],
-            receiver_update.old_value.elements[0],
-        );
-        let amount_send = builder.sub(
-            sender_update.old_value.elements[0],
-            sender_update.new_value.elements[0],
-        );
-        builder.connect(amount_recv, amount_send);
-
-        let overflow_checks = list_le_circuit(
-            builder,
-            vec![
-                receiver_update.old_value.elements[0],
-                sender_update.new_value.elements[0],
-            ],
-            vec![
-                receiver_update.new_value.elements[0],
-                sender_update.old_value.elements[0],
-            ],
-            32,
-        );
-        let true_target = builder.one();
-        builder.connect(overflow_checks.target, true_target);
-
-        builder.connect_hashes(sender_update.new_root, receiver_update.old_root);
-        Self {
-            sender_update,
-            receiver_update,
-        }
-    }
-    pub fn set_witness_proof<F: RichField>(
-        &self,
-        witness: &mut PartialWitness<F>,
-        input: &BalanceUpdate<F>,
-    ) {
-        self.sender_update
-            .set_witness_proof(witness, &input.sender_update);
-        self.receiver_update
-            .set_witness_proof(witness, &input.receiver_update);
-    }
-}
-
-pub struct UpdateBalanceCircuit<
-    F: RichField + Extendable<D>,
-    C: GenericConfig<D, F = F> + 'static,
-    const D: usize,
-> where
-    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
-{
-    pub updates: Vec<BalanceUpdateGadget>,
-    pub base_circuit_data: CircuitData<F, C, D>,
-}
-
-impl<F: RichField + Extendable<D>, C: GenericConfig<D, F = F> + 'static, const D: usize>
-    UpdateBalanceCircuit<F, C, D>
-where
-    <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
-{
-    pub fn new(number_updates: usize, tree_height: usize) -> Self {
-        let config = CircuitConfig::standard_recursion_config();
-        let mut builder = CircuitBuilder::<F, D>::new(config);
-        let updates: Vec<BalanceUpdateGadget> = (0..number_updates)
-            .map(|_| {
-                BalanceUpdateGadget::add_virtual_to::<C::Hasher, F, D>(&mut builder, tree_height)
-            })
-            .collect();
-        for i in 1..number_updates {
-            builder.connect_hashes(
-                updates[i - 1].receiver_update.new_root,
-                updates[i].sender_update.old_root,
-            );
+// How log lines are written to stdout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    // One JSON object per line, with the fields of the enclosing spans
+    Json,
+}
+
+#[derive(Parser, Debug)]
+struct ServerArgs {
+    /// Address the HTTP API is served on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    bind: String,
+    /// PEM file holding the certificate chain the HTTP API is served over TLS with.
+    /// Served in plain HTTP, e.g. behind a terminating proxy, when this is not set.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM file holding the private key of the TLS certificate.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Origins, e.g. https://dapp.example, whose browser frontends may call the API, or
+    /// `*` for any. Cross-origin requests are not allowed when this is not set.
+    #[arg(long, value_delimiter = ',')]
+    cors_allowed_origins: Vec<String>,
+    /// Methods cross-origin requests may use.
+    #[arg(long, value_delimiter = ',', default_values_t = [Method::GET, Method::POST, Method::DELETE])]
+    cors_allowed_methods: Vec<Method>,
+    /// JSON-RPC endpoint used to anchor balance and nullifier roots on-chain.
+    /// Nullifier tracking and anchoring are disabled when this is not set.
+    #[arg(long)]
+    anchor_rpc_url: Option<String>,
+    /// Address of the root anchor contract.
+    #[arg(long, requires = "anchor_rpc_url")]
+    anchor_contract: Option<Address>,
+    /// Account the anchoring transactions are sent from.
+    #[arg(long, requires = "anchor_rpc_url")]
+    anchor_from: Option<Address>,
+    #[arg(long, default_value_t = 600)]
+    anchor_interval_secs: u64,
+    /// How often the root of the tree of finalized results is anchored, when it changed.
+    /// The anchor contract has to take result roots, which are not anchored when this is
+    /// not set.
+    #[arg(long, requires = "anchor_rpc_url")]
+    result_anchor_interval_secs: Option<u64>,
+    /// JSON-RPC endpoint used to snapshot token balances for token-weighted proposals,
+    /// and to fetch the beacon blocks breaking ties of proposals.
+    #[arg(long)]
+    eth_rpc_url: Option<String>,
+    /// Governance contract whose `ProposalCreated` events are mirrored as local
+    /// proposals, see `chain::governance`. Nothing is mirrored when this is not set.
+    #[arg(long, requires = "eth_rpc_url")]
+    governance_contract: Option<Address>,
+    /// First block scanned for proposals of the governance contract.
+    #[arg(long, default_value_t = 0)]
+    governance_from_block: u64,
+    #[arg(long, default_value_t = 30)]
+    governance_interval_secs: u64,
+    /// Blocks mined on top of a `ProposalCreated` event before it is mirrored, so
+    /// that a reorganization of the chain is unlikely to drop it.
+    #[arg(long, default_value_t = 12)]
+    governance_confirmations: u64,
+    /// Proposer, and finalizer, of the mirrored proposals.
+    #[arg(long, default_value_t = 0)]
+    governance_proposer_id: u32,
+    /// DAO the mirrored proposals are created in.
+    #[arg(long)]
+    governance_dao_id: Option<String>,
+    /// Token whose balances at the snapshot block of a mirrored proposal weigh its
+    /// votes. Mirrored proposals have one vote per voter when this is not set.
+    #[arg(long)]
+    governance_token: Option<Address>,
+    /// RFC 3161 time-stamping authority used to timestamp the transcript and
+    /// certificate of finalized proposals.
+    #[arg(long)]
+    tsa_url: Option<String>,
+    #[arg(long, default_value_t = 60)]
+    tsa_interval_secs: u64,
+    /// HTTP gateway, e.g. https://ipfs.io, through which the server fetches the `ipfs://`
+    /// documents proposals refer to, as well as `https://` ones, to check them against
+    /// their hash. Documents are not fetched when this is not set.
+    #[arg(long)]
+    content_gateway: Option<String>,
+    /// How often documents not yet fetched, or unavailable when last fetched, are fetched.
+    #[arg(long, default_value_t = 60)]
+    content_check_interval_secs: u64,
+    /// How often due callbacks to the webhooks of organizations are sent.
+    #[arg(long, default_value_t = 5)]
+    webhook_interval_secs: u64,
+    /// Attempts made of each callback before it is given up on.
+    #[arg(long, default_value_t = 8)]
+    webhook_max_attempts: u32,
+    /// Delay before the first retry of a failed callback, doubled for each retry after it.
+    #[arg(long, default_value_t = 30)]
+    webhook_retry_secs: u64,
+    /// How long a webhook has to answer a callback.
+    #[arg(long, default_value_t = 10)]
+    webhook_timeout_secs: u64,
+    /// How often scoped delegations are applied to the proposals in their scope, and
+    /// reverted once it ended.
+    #[arg(long, default_value_t = 5)]
+    scoped_delegation_interval_secs: u64,
+    /// How often the trees of open proposals are checked against their recorded updates,
+    /// and those of finalized proposals past their retention compacted.
+    #[arg(long, default_value_t = 300)]
+    tree_check_interval_secs: u64,
+    /// How long finalized proposals keep their full balance tree, proving the leaf of every
+    /// voter, before it is compacted to the root and the tallies their proof stands on.
+    #[arg(long, default_value_t = 86400)]
+    finalized_tree_retention_secs: u64,
+    /// Directory of an on-disk store for the merkle tree nodes of all proposals.
+    /// Nodes are kept in memory when this is not set.
+    #[arg(long)]
+    node_store_path: Option<PathBuf>,
+    /// Number of tree nodes cached in memory per tree when using the on-disk store.
+    #[arg(long, default_value_t = NonZeroUsize::new(4096).unwrap())]
+    node_store_cache_size: NonZeroUsize,
+    /// JSON lines file the audit log of all state mutations is appended to.
+    /// The log is only kept in memory when this is not set.
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// JSON lines file every accepted command is appended to as an event, see
+    /// `proposal::events`. Proposals are rebuilt from it on startup, and the events
+    /// are only kept in memory when this is not set.
+    #[arg(long, conflicts_with = "replica_of")]
+    event_log: Option<PathBuf>,
+    /// Derives the id of proposals created without a nonce from their statement and
+    /// proposer with a nonce of 0, see `proposal::id`, instead of picking a random one.
+    #[arg(long)]
+    deterministic_proposal_ids: bool,
+    /// Votes allowed per minute for each voter and each client IP, in bursts of up to as many.
+    #[arg(long, default_value_t = 60)]
+    vote_rate_limit: u32,
+    /// Proposals allowed per minute for each proposer and each client IP.
+    #[arg(long, default_value_t = 5)]
+    propose_rate_limit: u32,
+    /// How long background tasks get to finish once the server stops.
+    #[arg(long, default_value_t = 10)]
+    shutdown_grace_secs: u64,
+    /// File holding the hex encoded secp256k1 key this instance signs certificates
+    /// and audit entries with. Artifacts are left unsigned when this is not set.
+    #[arg(long)]
+    instance_key_file: Option<PathBuf>,
+    /// Identifies this instance among the deployments of an operator.
+    #[arg(long, requires = "instance_key_file")]
+    instance_id: Option<String>,
+    #[arg(long, requires = "instance_key_file")]
+    region: Option<String>,
+    /// File holding the hex encoded Ed25519 key this instance attests the results of
+    /// finalized proposals with. Results are not attested when this is not set.
+    #[arg(long)]
+    attestation_key_file: Option<PathBuf>,
+    /// Approximate bytes of tree nodes each DAO may store before proposals, votes and
+    /// delegations are rejected.
+    #[arg(long)]
+    dao_max_node_store_bytes: Option<u64>,
+    /// Bytes of finalization proofs each DAO may store before finalizations are rejected.
+    #[arg(long)]
+    dao_max_proof_bytes: Option<u64>,
+    /// Votes and delegations each DAO may record across its proposals.
+    #[arg(long)]
+    dao_max_updates: Option<u64>,
+    /// Attempts a finalization or aggregation proof gets before it is reported as
+    /// failed. Proofs that panic are not attempted again.
+    #[arg(long, default_value_t = DEFAULT_PROVE_ATTEMPTS)]
+    prove_attempts: usize,
+    /// Deposit proposers lock from their treasury account to create a proposal, refunded
+    /// once it is finalized and slashed if an admin cancels it for spam. Proposals are
+    /// created without a deposit when this is not set.
+    #[arg(long)]
+    proposal_deposit: Option<u64>,
+    /// How often settled deposits and payouts are proven.
+    #[arg(long, default_value_t = 60)]
+    deposit_proof_interval_secs: u64,
+    /// File holding the bearer token of the admin endpoints, which snapshot and
+    /// restore the whole state. Admin endpoints are disabled when this is not set.
+    #[arg(long)]
+    admin_token_file: Option<PathBuf>,
+    /// Rejects requests to routes that require a role unless they carry an API key with
+    /// it, see `auth::required_role`. Keys are issued by admins through `/admin/keys`.
+    #[arg(long, requires = "admin_token_file")]
+    enforce_roles: bool,
+    /// URL of a primary server this one replicates as a read-only replica, serving reads
+    /// from the snapshots of the primary and rejecting every mutation.
+    #[arg(long, requires = "primary_admin_token_file")]
+    replica_of: Option<String>,
+    /// File holding the admin token of the primary, which authorizes its snapshots.
+    #[arg(long, requires = "replica_of")]
+    primary_admin_token_file: Option<PathBuf>,
+    /// File the emergency pause of `POST /admin/pause` is kept in, so that a paused
+    /// server restarts paused. The pause is only kept in memory when this is not set.
+    #[arg(long)]
+    pause_file: Option<PathBuf>,
+    /// How often a replica fetches a snapshot of its primary.
+    #[arg(long, default_value_t = 10)]
+    replica_sync_secs: u64,
+    /// Format of the logs, filtered by the RUST_LOG environment variable (info by default).
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Address the gRPC API of proto/qed.proto is served on, next to the HTTP API. Not
+    /// served when this is not set.
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_addr: Option<std::net::SocketAddr>,
+    /// SQLite or Postgres database proposal metadata is mirrored into and searched in by
+    /// `GET /proposals/search`, e.g. sqlite://qed.db?mode=rwc. Not mirrored when this is
+    /// not set.
+    #[cfg(feature = "sql")]
+    #[arg(long)]
+    metadata_db_url: Option<String>,
+    /// How often changed proposal metadata is written to the metadata database.
+    #[cfg(feature = "sql")]
+    #[arg(long, default_value_t = 5)]
+    metadata_sync_secs: u64,
+    /// Probability that a tree node write fails, for crash consistency testing.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.0)]
+    chaos_tree_write_failure_rate: f64,
+    /// Finalizations wait up to this many milliseconds before proving.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0)]
+    chaos_max_proving_delay_ms: u64,
+    /// Probability that a request is answered with a bare 503 without being handled.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.0)]
+    chaos_request_drop_rate: f64,
+    /// Seed of the faults, each worker thread drawing from its own generator.
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0)]
+    chaos_seed: u64,
+}
+
+// Largest snapshot restored, which holds the proofs and trees of every proposal
+const MAX_SNAPSHOT_BYTES: usize = 1 << 30;
+
+struct AppState {
+    shared_map: ProposalLock, // Async lock for safe concurrent access, recovering from panicking handlers
+    nullifier_mode: bool,
+    token_snapshotter: Option<TokenSnapshotter>,
+    // Fetches the blocks breaking ties under the random-with-beacon policy
+    beacon: Option<BlockBeacon>,
+    circuits: Mutex<CircuitCache<GoldilocksField, PoseidonGoldilocksConfig, 2>>,
+    node_stores: NodeStoreBackend,
+    vote_limiter: Arc<RateLimiter>,
+    propose_limiter: Arc<RateLimiter>,
+    audit: Mutex<AuditLog>,
+    events: Mutex<EventLog>,
+    signer: Option<Arc<InstanceSigner>>,
+    attester: Option<ResultAttester>,
+    quotas: DaoQuotas,
+    proving: ProvingRetryPolicy,
+    tree_health: Mutex<TreeHealthResponse>,
+    admin_token: Option<String>,
+    treasury: Mutex<Treasury>,
+    funds: Mutex<DaoFunds>,
+    token_locks: Mutex<TokenLocks>,
+    proposal_deposit: Option<u64>,
+    orgs: Mutex<OrganizationRegistry>,
+    // Callbacks to the webhooks of organizations, sent by `deliver_webhooks`
+    webhooks: Mutex<WebhookQueue>,
+    // Delegations over many proposals, applied and reverted by `apply_scoped_delegations`
+    scoped_delegations: Mutex<ScopedDelegationRegistry>,
+    // Set on replicas, which only serve reads
+    read_only: bool,
+    api_keys: Mutex<KeyRing>,
+    enforce_roles: bool,
+    voting_paused: AtomicBool,
+    // Emergency pause, rejecting every mutation but lifting the pause itself
+    pause: PauseSwitch,
+    deterministic_proposal_ids: bool,
+    // Ids of the proposals being created, so two requests deriving the same id do not
+    // seed its trees at once
+    creating: Mutex<HashSet<Uuid>>,
+    // Full-text index over the statements and actions of proposals, refreshed on search
+    search: Mutex<SearchIndex>,
+    // Commitment to the results of finalized proposals, synced with the store on use
+    results: Mutex<ResultTree>,
+    // Id of the request that last changed each proposal, which the background tasks doing
+    // the work it left log under
+    request_traces: Mutex<RequestTraces>,
+    // Database proposal metadata is mirrored into by `sync_metadata`
+    #[cfg(feature = "sql")]
+    metadata: Option<Arc<SqlMetadataStore>>,
+}
+
+// Holds the id of a proposal being created in `AppState::creating` until dropped
+struct CreationGuard<'a> {
+    data: &'a AppState,
+    proposal_id: Uuid,
+}
+
+impl Drop for CreationGuard<'_> {
+    fn drop(&mut self) {
+        self.data
+            .creating
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.proposal_id);
+    }
+}
+
+// Reserves the id of a proposal to create, unless another request is creating it
+fn reserve_proposal_id(data: &AppState, proposal_id: Uuid) -> Option<CreationGuard<'_>> {
+    let mut creating = data.creating.lock().unwrap_or_else(PoisonError::into_inner);
+    if creating.insert(proposal_id) {
+        Some(CreationGuard { data, proposal_id })
+    } else {
+        None
+    }
+}
+
+// Votes on a specific policiy
+// pub fn vote(proposal_id: u32, voter_id: u32, vote: u32) {}
+
+// Identifies the caller by the optional X-Voter-Id header
+fn caller_voter_id(req: &HttpRequest) -> Option<u32> {
+    req.headers()
+        .get("X-Voter-Id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+// Responds with the status of the error code and a JSON body with the code and message,
+// naming the code in the X-Error-Code header as well
+fn error_response(code: ApiErrorCode, message: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::build(StatusCode::from_u16(code.http_status()).unwrap())
+        .insert_header(("X-Error-Code", code.as_str()))
+        .json(ApiError::new(code, message))
+}
+
+// Answers request bodies that do not deserialize like any other invalid query
+fn payload_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let response = error_response(
+        ApiErrorCode::InvalidQuery,
+        format!("Invalid request body: {}", err),
+    );
+    InternalError::from_response(err, response).into()
+}
+
+// Rejects changes to a proposal that has been cancelled, finalized or is being finalized
+fn closed_response(proposal: &Proposal) -> Option<HttpResponse> {
+    proposal
+        .ensure_accepts_updates()
+        .err()
+        .map(|err| error_response(err.code, err.message))
+}
+
+// Returns the deposit of a proposal that was finalized or cancelled by its proposer
+fn refund_deposit(data: &AppState, proposal_id: Uuid, proposal: &mut Proposal) {
+    if let Some(deposit) = &mut proposal.deposit {
+        let mut treasury = data.treasury.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = treasury.refund(proposal.proposer_id, deposit) {
+            error!(%proposal_id, "Failed to refund the deposit: {}", err);
         }
-        builder.register_public_inputs(&updates[0].sender_update.old_root.elements);
-        builder
-            .register_public_inputs(&updates[updates.len() - 1].receiver_update.new_root.elements);
-        let base_circuit_data = builder.build::<C>();
-        Self {
-            updates,
-            base_circuit_data,
+    }
+}
+
+// The tokens a voter locks as they vote or delegate on a proposal that locks tokens: their
+// own weight, once per proposal. Checked before the vote is accepted and locked after
+fn tokens_to_lock(
+    data: &AppState,
+    proposal_id: Uuid,
+    proposal: &Proposal,
+    voter_id: u32,
+) -> Result<Option<u64>, ApiError> {
+    if !proposal.locks_tokens {
+        return Ok(None);
+    }
+    let amount = proposal
+        .storage
+        .initial_balance(proposal.electorate_voter(voter_id)?)
+        .get();
+    let needed = data
+        .token_locks
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .check_lock(&proposal_id, voter_id, amount)?;
+    Ok(needed.then_some(amount))
+}
+
+// Locks the tokens of a voter whose vote or delegation was accepted, see `tokens_to_lock`
+fn lock_tokens(data: &AppState, proposal_id: Uuid, voter_id: u32, amount: Option<u64>) {
+    if let Some(amount) = amount {
+        let mut token_locks = data
+            .token_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = token_locks.lock(proposal_id, voter_id, amount, unix_timestamp()) {
+            error!(%proposal_id, voter_id, "Failed to lock the tokens of the voter: {}", err);
         }
     }
-    pub fn prove(
-        &self,
-        proofs: &Vec<BalanceUpdate<F>>,
-    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
-        let num_updates = self.updates.len();
-        assert_eq!(proofs.len(), num_updates);
-        let mut pw = PartialWitness::<F>::new();
-        for i in 0..num_updates {
-            self.updates[i].set_witness_proof(&mut pw, &proofs[i])
+}
+
+// Queues callbacks of the result of a finalized proposal to the webhooks of its organization
+fn queue_webhooks(data: &AppState, proposal: &Proposal) {
+    let targets = data
+        .orgs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .webhooks(&proposal.dao_id)
+        .to_vec();
+    if targets.is_empty() {
+        return;
+    }
+    if let (Some(certificate), Some(proof), Some(finalized_at)) = (
+        &proposal.certificate,
+        &proposal.proof,
+        proposal.finalized_at,
+    ) {
+        let payload = FinalizationPayload::new(&proposal.dao_id, certificate, proof, finalized_at);
+        data.webhooks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .enqueue(&targets, &payload, unix_timestamp());
+    }
+}
+
+// Returns the tokens locked on a proposal that was finalized or cancelled to their voters
+fn release_tokens(data: &AppState, proposal_id: Uuid, proposal: &Proposal) {
+    if proposal.locks_tokens {
+        let mut token_locks = data
+            .token_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = token_locks.release(&proposal_id) {
+            error!(%proposal_id, "Failed to release the locked tokens: {}", err);
         }
-        self.base_circuit_data.prove(pw)
     }
 }
-pub struct BalanceStorage {
-    pub tree: ZeroMerkleTree<GoldilocksField, PoseidonHash, SimpleNodeStore>,
+
+// Pays a finalized proposal that passed with a treasury transfer out of the funds of its DAO
+fn pay_out(data: &AppState, proposal_id: Uuid, proposal: &Proposal) -> Result<Payout, ApiError> {
+    let passed = proposal.certificate.as_ref().map_or(false, |certificate| {
+        certificate.outcome == ProposalOutcome::Passed
+    });
+    let (token, recipient, amount) = match &proposal.action {
+        ProposalAction::TreasuryTransfer {
+            token,
+            recipient,
+            amount,
+        } if passed => (*token, *recipient, *amount),
+        _ => {
+            return Err(ApiError::new(
+                ApiErrorCode::NotPayable,
+                "The proposal has not passed with a treasury transfer",
+            ))
+        }
+    };
+    data.funds
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .pay(
+            proposal_id,
+            &proposal.dao_id,
+            token,
+            recipient,
+            amount,
+            compute_action_hash(&proposal.action),
+            unix_timestamp(),
+        )
+        .map_err(|err| {
+            ApiError::new(
+                ApiErrorCode::PayoutFailed,
+                format!("Failed to pay out the proposal: {}", err),
+            )
+        })
+}
+
+// Rejects a request on a DID-registered electorate unless the DID of the voter signed it
+// with their next nonce
+fn did_response<T: Serialize>(
+    proposal: &Proposal,
+    action: &str,
+    proposal_id: &Uuid,
+    voter_id: u32,
+    nonce: u64,
+    params: &T,
+    signature: Option<&str>,
+) -> Option<HttpResponse> {
+    let message = match did_request_message(action, proposal_id, voter_id, nonce, params) {
+        Ok(message) => message,
+        Err(err) => return Some(error_response(ApiErrorCode::InvalidQuery, err)),
+    };
+    proposal
+        .authenticate_voter(voter_id, nonce, &message, signature)
+        .err()
+        .map(|err| error_response(err.code, err.message))
+}
+
+// Rejects a request once the DAO has used up one of the given quotas
+fn quota_response(
+    data: &AppState,
+    proposals: &ProposalStore,
+    dao_id: &str,
+    kinds: &[QuotaKind],
+) -> Option<HttpResponse> {
+    if data.quotas == DaoQuotas::default() {
+        return None;
+    }
+    data.quotas
+        .check(&proposals.dao_usage(dao_id), kinds)
+        .err()
+        .map(|err| error_response(err.code, err.message))
 }
 
-impl BalanceStorage {
-    pub fn new(height: u8, start_balances: Vec<u32>) -> Self {
-        let mut tree = ZeroMerkleTree::<GoldilocksField, PoseidonHash, SimpleNodeStore>::new(
-            height,
-            SimpleNodeStore::new(),
-        );
+// Reads the bearer token of the Authorization header
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
 
-        for (i, balance) in start_balances.iter().enumerate() {
-            tree.set_leaf(i as u64, WHashOut::from_values((*balance) as u64, 0, 0, 0))
-                .unwrap();
+// Finds the caller a bearer token belongs to: admins by the admin token, anyone else by
+// the secret of their API key. Tokens of neither, e.g. of organizations, are left alone.
+fn authenticate_bearer(data: &AppState, token: &str) -> Option<Principal> {
+    // Compares digests so the time taken does not tell how much of the token matched
+    if let Some(admin_token) = &data.admin_token {
+        if Sha256::digest(token) == Sha256::digest(admin_token) {
+            return Some(Principal {
+                key_id: "admin".to_string(),
+                role: Role::Admin,
+            });
         }
-        Self { tree }
     }
-    pub fn get_balance(&self, index: u64) -> anyhow::Result<u32> {
-        let balance_proof = self.tree.get_leaf(index)?;
+    data.api_keys
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .authenticate_secret(token)
+}
 
-        Ok(balance_proof.value.0.elements[0].0 as u32)
+// Fails unless the caller has a role granting `required`
+fn check_role(principal: Option<&Principal>, required: Role) -> Result<(), ApiError> {
+    match principal {
+        Some(principal) if principal.role.grants(required) => Ok(()),
+        Some(principal) => Err(ApiError::new(
+            ApiErrorCode::RoleForbidden,
+            format!(
+                "Key {} is not allowed to act as {:?}",
+                principal.key_id, required
+            ),
+        )),
+        None => Err(ApiError::new(
+            ApiErrorCode::Unauthenticated,
+            "Missing or invalid API key",
+        )),
     }
-    pub fn set_balance(
-        &mut self,
-        index: u64,
-        value: u32,
-    ) -> anyhow::Result<DeltaMerkleProof<GoldilocksField>> {
-        let leaf_value = WHashOut::from_values(value as u64, 0, 0, 0);
+}
 
-        self.tree.set_leaf(index, leaf_value)
+// Rejects admin requests whose caller `authorize` did not authenticate as an admin, and all
+// of them when the server runs without an admin token
+fn admin_response(data: &AppState, req: &HttpRequest) -> Option<HttpResponse> {
+    if data.admin_token.is_none() {
+        return Some(error_response(
+            ApiErrorCode::AdminUnauthorized,
+            "Admin endpoints are disabled",
+        ));
+    }
+    match req.extensions().get::<Principal>() {
+        Some(principal) if principal.role == Role::Admin => None,
+        _ => Some(error_response(
+            ApiErrorCode::AdminUnauthorized,
+            "Missing or invalid admin token",
+        )),
     }
-    pub fn process_tx(
-        &mut self,
-        sender: u64,
-        receiver: u64,
-        amount: u32,
-    ) -> anyhow::Result<BalanceUpdate<GoldilocksField>> {
-        let sender_balance = self.get_balance(sender)?;
-        let receiver_balance = self.get_balance(receiver)?;
-        // println!("Sender balance: {}", sender_balance);
-        assert!(sender_balance >= amount, "Insufficient funds");
+}
 
-        let sender_proof: DeltaMerkleProof<GoldilocksField> =
-            self.set_balance(sender, sender_balance - amount)?;
-        let receiver_proof = self.set_balance(receiver, receiver_balance + amount)?;
-        // println!("New Sender balance: {}", self.get_balance(sender)?);
-        Ok(BalanceUpdate {
-            sender_update: sender_proof,
-            receiver_update: receiver_proof,
-        })
+// Rejects votes, commitments, revocations and delegations while an admin has paused voting
+fn paused_response(data: &AppState) -> Option<HttpResponse> {
+    data.voting_paused.load(Ordering::SeqCst).then(|| {
+        error_response(
+            ApiErrorCode::VotingPaused,
+            "Voting is paused on every proposal",
+        )
+    })
+}
+
+// Handles each request in a span with its own id, so what is logged while handling it can
+// be told apart from concurrent requests, and logs how long it took. The id is taken from
+// the X-Request-Id header of the request when it has a usable one, and returned in the same
+// header of the response.
+async fn request_span(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = RequestId::from_header(
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    );
+    // Ids are restricted to characters valid in a header
+    let request_id_header = (
+        header::HeaderName::from_static(REQUEST_ID_HEADER),
+        header::HeaderValue::from_str(request_id.as_str()).unwrap(),
+    );
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+        status = field::Empty,
+        elapsed_ms = field::Empty,
+    );
+    #[cfg(feature = "chaos")]
+    if chaos::drop_request() {
+        let _entered = span.enter();
+        warn!("Chaos dropped the request");
+        return Ok(req.into_response(
+            HttpResponse::ServiceUnavailable()
+                .insert_header(request_id_header)
+                .finish(),
+        ));
     }
-    pub fn process_txs(
-        &mut self,
-        txs: Vec<(u64, u64, u32)>,
-    ) -> anyhow::Result<Vec<BalanceUpdate<GoldilocksField>>> {
-        let mut proofs = vec![];
-        for (sender, receiver, amount) in txs {
-            proofs.push(self.process_tx(sender, receiver, amount)?);
+    let started_at = Instant::now();
+    let mut response = request_id
+        .scope(next.call(req))
+        .instrument(span.clone())
+        .await;
+    span.record("elapsed_ms", started_at.elapsed().as_millis() as u64);
+    let _entered = span.enter();
+    match &mut response {
+        Ok(response) => {
+            span.record("status", response.status().as_u16());
+            let (name, value) = request_id_header;
+            response.headers_mut().insert(name, value);
+            info!("Request handled");
         }
-        Ok(proofs)
+        Err(err) => warn!("Request failed: {}", err),
     }
+    response
 }
 
-struct AppState {
-    shared_map: Mutex<HashMap<Uuid, Proposal>>, // Mutex for safe concurrent access
-}
-
-pub struct Proposal {
-    pub statement: String,
-    pub storage: BalanceStorage,
-    pub proposer_id: u32,
-    pub updates: Vec<BalanceUpdate<GoldilocksField>>,
-    pub is_finalized: bool,
-}
-impl Proposal {
-    pub fn new(statement: String, proposer_id: u32) -> Self {
-        // Creates a new policiy and balance storage object
-        let mut start_balances = vec![0; 2];
-        let updates = vec![];
-        start_balances.extend(vec![1; 2_usize.pow(10)]);
-        let storage = BalanceStorage::new(32, start_balances);
-        let is_finalized = false;
-        Self {
-            statement,
-            storage,
-            proposer_id,
-            updates,
-            is_finalized,
+// Lets the browser frontends of `origins` call the API with `methods`, reading the error
+// code and retry delay of a response
+fn cors(origins: &[String], methods: &[Method]) -> Cors {
+    let cors = Cors::default()
+        .allowed_methods(methods.iter().cloned())
+        .allow_any_header()
+        .expose_headers([
+            header::HeaderName::from_static("x-error-code"),
+            header::HeaderName::from_static(REQUEST_ID_HEADER),
+            header::RETRY_AFTER,
+            header::ACCEPT_RANGES,
+            header::CONTENT_RANGE,
+            header::ETAG,
+            header::HeaderName::from_static("repr-digest"),
+        ])
+        .max_age(3600);
+    if origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        origins
+            .iter()
+            .fold(cors, |cors, origin| cors.allowed_origin(origin))
+    }
+}
+
+// Adds the headers browsers harden the responses of an API with, unless the handler set
+// them. The Swagger UI runs scripts and keeps its own content security policy.
+async fn security_headers<B: MessageBody>(
+    tls: bool,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let is_swagger_ui = req.path().starts_with("/swagger-ui/");
+    let mut response = next.call(req).await?;
+    let headers = response.headers_mut();
+    let mut defaults = vec![
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        (header::X_FRAME_OPTIONS, "DENY"),
+        (header::REFERRER_POLICY, "no-referrer"),
+    ];
+    if !is_swagger_ui {
+        defaults.push((
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; frame-ancestors 'none'",
+        ));
+    }
+    if tls {
+        defaults.push((header::STRICT_TRANSPORT_SECURITY, "max-age=31536000"));
+    }
+    for (name, value) in defaults {
+        if !headers.contains_key(&name) {
+            headers.insert(name, header::HeaderValue::from_static(value));
         }
     }
+    Ok(response)
 }
 
-// Votes on a specific policiy
-// pub fn vote(proposal_id: u32, voter_id: u32, vote: u32) {}
+// Loads the certificate chain and private key the HTTP API is served over TLS with
+fn load_tls_config(cert_path: &Path, key_path: &Path) -> anyhow::Result<rustls::ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "{} holds no certificate",
+        cert_path.display()
+    );
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| anyhow::anyhow!("{} holds no private key", key_path.display()))?;
+    Ok(rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?)
+}
 
-// List all of the current proposals, stored in HashMap
-async fn list_proposals(data: web::Data<Arc<AppState>>) -> impl Responder {
-    let proposals = data.shared_map.lock().unwrap();
-    let mut out = String::new();
-    for (id, proposal) in proposals.iter() {
-        if proposal.is_finalized {
-            let no_votes = proposal.storage.get_balance(0).unwrap();
-            let yes_votes = proposal.storage.get_balance(1).unwrap();
-            let result = if no_votes >= yes_votes {
-                "vetoed"
-            } else {
-                "passed"
-            };
-            out.push_str(&format!(
-                "Proposal ID: {}, Statement: {}, Proposer ID: {}, Finalized: {}, # of Yes Votes: {}, # of No Votes: {} -> Proposal {}\n",
-                id, proposal.statement, proposal.proposer_id, proposal.is_finalized, yes_votes, no_votes, result
-            ));
-        } else {
-            out.push_str(&format!(
-                "Proposal ID: {}, Statement: {}, Proposer ID: {}, Finalized: {}\n",
-                id, proposal.statement, proposal.proposer_id, proposal.is_finalized
-            ));
+// Rate limits a route per client IP and per the id in the `key_field` of its JSON body
+async fn rate_limit(
+    limiter: Arc<RateLimiter>,
+    key_field: &'static str,
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<EitherBody<BoxBody>>, actix_web::Error> {
+    let mut keys = vec![];
+    if let Some(peer) = req.peer_addr() {
+        keys.push(format!("ip:{}", peer.ip()));
+    }
+    // Reads the body to find the id, then puts it back for the handler
+    let body = req.extract::<web::Bytes>().await?;
+    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
+        if let Some(id) = json.get(key_field).and_then(|id| id.as_u64()) {
+            keys.push(format!("{}:{}", key_field, id));
         }
     }
-    HttpResponse::Ok().body(out)
+    req.set_payload(Payload::from(body));
+    if let Err(wait) = limiter.try_acquire(&keys, Instant::now()) {
+        let retry_after = wait.as_secs_f64().ceil() as u64;
+        let mut response = error_response(
+            ApiErrorCode::RateLimited,
+            format!("Rate limited, retry in {}s", retry_after),
+        );
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            header::HeaderValue::from(retry_after),
+        );
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
 }
 
-#[derive(Deserialize)]
-struct ProposeQuery {
-    proposer_id: u32,
-    statement: String,
+// Authenticates the caller of a request by the bearer token or by the X-Api-Key-Id,
+// X-Timestamp and X-Signature headers of a signing key, and records it for the handler.
+// Requests whose route requires a role the caller lacks are rejected when the server
+// enforces roles.
+async fn authorize(
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<EitherBody<BoxBody>>, actix_web::Error> {
+    let data = match req.app_data::<web::Data<Arc<AppState>>>().cloned() {
+        Some(data) => data,
+        None => {
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        }
+    };
+    let header = |req: &ServiceRequest, name: &str| -> Option<String> {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let mut principal =
+        bearer_token(req.request()).and_then(|token| authenticate_bearer(&data, token));
+    if let Some(key_id) = header(&req, "X-Api-Key-Id") {
+        let timestamp = header(&req, "X-Timestamp").and_then(|value| value.parse::<u64>().ok());
+        let signature = header(&req, "X-Signature");
+        // Reads the body the signature covers, then puts it back for the handler
+        let body = req.extract::<web::Bytes>().await?;
+        req.set_payload(Payload::from(body.clone()));
+        let signed = match (timestamp, signature) {
+            (Some(timestamp), Some(signature))
+                if unix_timestamp().abs_diff(timestamp) <= MAX_SIGNATURE_SKEW_SECS =>
+            {
+                let path = req
+                    .uri()
+                    .path_and_query()
+                    .map_or(req.path(), |path| path.as_str());
+                let message = request_message(req.method().as_str(), path, timestamp, &body);
+                data.api_keys
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .authenticate_signature(&key_id, &message, &signature)
+            }
+            _ => None,
+        };
+        if signed.is_none() {
+            let response = error_response(
+                ApiErrorCode::Unauthenticated,
+                "The signature is invalid, expired or of an unknown key",
+            );
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        principal = signed;
+    }
+    if data.enforce_roles {
+        let required = req
+            .match_pattern()
+            .and_then(|pattern| required_role(req.method().as_str(), &pattern));
+        if let Some(required) = required {
+            if let Err(err) = check_role(principal.as_ref(), required) {
+                let response = error_response(err.code, err.message);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+    }
+    if let Some(principal) = principal {
+        req.extensions_mut().insert(principal);
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
 }
 
-async fn propose(data: web::Data<Arc<AppState>>, item: web::Json<ProposeQuery>) -> impl Responder {
-    let mut proposals = data.shared_map.lock().unwrap();
-    let new_proposal = Proposal::new(item.statement.clone(), item.proposer_id);
-    let proposal_id = Uuid::new_v4();
-    proposals.insert(proposal_id, new_proposal);
-    HttpResponse::Ok().body(format!("New proposal {}: {}", proposal_id, item.statement))
+// Rejects every request but reads on a replica, before it reaches a handler
+async fn reject_mutations(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<EitherBody<BoxBody>>, actix_web::Error> {
+    let (read_only, paused) = req
+        .app_data::<web::Data<Arc<AppState>>>()
+        .map_or((false, false), |data| {
+            (data.read_only, data.pause.is_paused())
+        });
+    // A dry run of a finalization is posted but changes nothing
+    let mutating = !matches!(*req.method(), Method::GET | Method::HEAD)
+        && !req.path().ends_with("/finalize/dry-run");
+    if read_only && mutating {
+        let response = error_response(
+            ApiErrorCode::ReadOnlyReplica,
+            "This server is a read-only replica",
+        );
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    // Lifting the pause is the one mutation a paused server accepts
+    if paused && mutating && req.path() != "/admin/unpause" {
+        let response = error_response(
+            ApiErrorCode::ServerPaused,
+            "The server is paused by an admin, only reads are served",
+        );
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
 }
 
-#[derive(Deserialize)]
-struct VoteQuery {
+// Appends a mutation of `proposal` to the audit log, together with the roots it resulted in
+fn record_audit<T: Serialize>(
+    data: &AppState,
     proposal_id: Uuid,
-    voter_id: u32,
-    is_yes: bool,
+    proposal: &Proposal,
+    action: AuditAction,
+    actor_id: u32,
+    request: &T,
+) {
+    let entry = AuditEntry {
+        seq: 0,
+        proposal_id,
+        action,
+        actor_id,
+        timestamp: unix_timestamp(),
+        request_hash: hash_request(request).unwrap(),
+        balance_root: proposal.storage.tree.get_root().unwrap(),
+        nullifier_root: proposal
+            .nullifiers
+            .as_ref()
+            .map(|nullifiers| nullifiers.root().unwrap()),
+        issuer: None,
+    };
+    let mut audit = data.audit.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(err) = audit.append(entry) {
+        error!(%proposal_id, "Failed to write audit entry: {}", err);
+    }
 }
-async fn vote(data: web::Data<Arc<AppState>>, item: web::Json<VoteQuery>) -> impl Responder {
-    let mut proposals = data.shared_map.lock().unwrap();
-    // Moves vote from user x to 0 or 1
-    // Checks if proposal exists
-    let proposal = proposals.get_mut(&item.proposal_id);
-    if let Some(proposal) = proposal {
-        // Checks if proposal is finalized
-        if proposal.is_finalized {
-            return HttpResponse::BadRequest().body("Proposal is finalized");
-        }
-        let vote = if item.is_yes { 1 } else { 0 };
-        let voter_balance = proposal.storage.get_balance(item.voter_id as u64).unwrap();
-        let update = proposal
-            .storage
-            .process_tx(item.voter_id as u64, vote as u64, voter_balance)
-            .unwrap();
-        proposal.updates.push(update);
-        HttpResponse::Ok().body(format!("Voted on proposal {}", item.proposal_id))
-    } else {
-        HttpResponse::NotFound().body("Proposal not found")
+
+// Appends an event the server accepts at `at` to the event log, with the balance root of
+// `proposal` before it, or none before its creation. The event is on disk before it is
+// applied, and is not applied when it cannot be written
+fn record_event(
+    data: &AppState,
+    proposal_id: Uuid,
+    proposal: Option<&Proposal>,
+    at: u64,
+    event: ProposalEvent,
+) -> Result<u64, ApiError> {
+    let balance_root = proposal.map_or(WHashOut::ZERO, |proposal| {
+        proposal.storage.tree.get_root().unwrap()
+    });
+    let mut events = data.events.lock().unwrap_or_else(PoisonError::into_inner);
+    events
+        .append(proposal_id, at, event, balance_root)
+        .map_err(|err| {
+            error!(%proposal_id, "Failed to write event: {}", err);
+            ApiError::new(
+                ApiErrorCode::EventLogFailed,
+                format!("Failed to write the event to the event log: {}", err),
+            )
+        })
+}
+
+// Takes back the last event recorded for a proposal, which was rejected when applied
+fn retract_event(data: &AppState, proposal_id: Uuid, seq: u64) {
+    let mut events = data.events.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(err) = events.retract(seq) {
+        error!(%proposal_id, "Failed to take back rejected event {}: {}", seq, err);
+    }
+}
+
+// Traces a proposal to the request being handled once an event applied to it, or forgets
+// it once it is deleted
+fn trace_event(data: &AppState, proposal_id: Uuid, proposal: Option<&Proposal>) {
+    let mut traces = data
+        .request_traces
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    match (proposal, RequestId::current()) {
+        (None, _) => traces.remove(&proposal_id),
+        (Some(_), Some(request_id)) => traces.record(proposal_id, request_id),
+        (Some(_), None) => {}
     }
 }
 
-#[derive(Deserialize)]
-struct DelegateQuery {
+// The id of the request that last changed a proposal, for the background work it left to
+// be logged under
+fn traced_request(data: &AppState, proposal_id: &Uuid) -> Option<RequestId> {
+    data.request_traces
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(proposal_id)
+}
+
+// Applies a command to a proposal the way replays of the event log do, recording it ahead
+// and taking it back when it is rejected
+fn accept_event(
+    data: &AppState,
+    proposals: &mut ProposalStore,
     proposal_id: Uuid,
-    voter_id: u32,
-    delegator_id: u32,
+    event: ProposalEvent,
+) -> Result<(), ApiError> {
+    let at = unix_timestamp();
+    let seq = record_event(
+        data,
+        proposal_id,
+        proposals.get(&proposal_id),
+        at,
+        event.clone(),
+    )?;
+    if let Err(err) = apply_event(proposals, proposal_id, &event, at) {
+        retract_event(data, proposal_id, seq);
+        return Err(err);
+    }
+    trace_event(data, proposal_id, proposals.get(&proposal_id));
+    Ok(())
 }
-async fn delegate(
+
+// Lists the proposals matching the filters of the query string, one page at a time
+#[utoipa::path(
+    get,
+    path = "/",
+    params(
+        ProposalQuery,
+        ("X-Voter-Id" = Option<u32>, Header, description = "Voter to describe the caller view for")
+    ),
+    responses(
+        (status = 200, description = "A page of proposals", body = Vec<ProposalView>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn list_proposals(
     data: web::Data<Arc<AppState>>,
-    item: web::Json<DelegateQuery>,
-) -> impl Responder {
-    let mut proposals = data.shared_map.lock().unwrap();
-    // Delegates vote from user x to user y
-    // Checks if proposal exists
-    let proposal = proposals.get_mut(&item.proposal_id);
-    if let Some(proposal) = proposal {
-        // Checks if proposal is finalized
-        if proposal.is_finalized {
-            return HttpResponse::BadRequest().body("Proposal is finalized");
-        }
-        let voter_balance = proposal.storage.get_balance(item.voter_id as u64).unwrap();
-        let update = proposal
-            .storage
-            .process_tx(
-                item.voter_id as u64,
-                item.delegator_id as u64,
-                voter_balance,
-            )
-            .unwrap();
-        proposal.updates.push(update);
-        HttpResponse::Ok().body(format!("Delegated on proposal {}", item.proposal_id))
-    } else {
-        HttpResponse::NotFound().body("Proposal not found")
+    query: web::Query<ProposalQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    let page = match proposals.query(&query) {
+        Ok(page) => page,
+        Err(err) => return error_response(ApiErrorCode::InvalidQuery, err),
+    };
+    let now = unix_timestamp();
+    let caller = caller_voter_id(&req);
+    let views =
+        page.map(|id| ProposalView::new(id, proposals.get(&id).unwrap(), now, caller).unwrap());
+    HttpResponse::Ok().json(views)
+}
+
+// Searches the metadata of proposals mirrored into the SQL database, by statement text,
+// proposer and creation date. Rows lag behind the proposals by up to one sync interval
+#[utoipa::path(
+    get,
+    path = "/proposals/search",
+    params(MetadataQuery),
+    responses(
+        (status = 200, description = "A page of proposal metadata", body = Vec<ProposalMetadata>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+#[cfg_attr(not(feature = "sql"), allow(unused_variables))]
+async fn search_proposals(
+    data: web::Data<Arc<AppState>>,
+    query: web::Query<MetadataQuery>,
+) -> HttpResponse {
+    if let Err(err) = query.validate() {
+        return error_response(ApiErrorCode::InvalidQuery, err);
+    }
+    #[cfg(feature = "sql")]
+    if let Some(metadata) = &data.metadata {
+        return match metadata.search(&query).await {
+            Ok(page) => HttpResponse::Ok().json(page),
+            Err(err) => error_response(ApiErrorCode::MetadataQueryFailed, err),
+        };
     }
+    error_response(
+        ApiErrorCode::MetadataStoreDisabled,
+        "The server runs without a metadata database",
+    )
 }
 
-#[derive(Deserialize)]
-struct FinalizeQuery {
-    proposal_id: Uuid,
-    finalizer_id: u32,
+// Ranks the proposals whose statement or action payload contains words of the query,
+// with excerpts highlighting the matches
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "A page of hits, most relevant first", body = Vec<SearchHit>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn search(data: web::Data<Arc<AppState>>, query: web::Query<SearchQuery>) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    let mut index = data.search.lock().unwrap_or_else(PoisonError::into_inner);
+    index.refresh(&proposals);
+    match index.search(&query) {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(err) => error_response(ApiErrorCode::InvalidQuery, err),
+    }
 }
-async fn finalize(
+
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Proposal id"),
+        ("X-Voter-Id" = Option<u32>, Header, description = "Voter to describe the caller view for")
+    ),
+    responses(
+        (status = 200, body = ProposalView),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_proposal(
     data: web::Data<Arc<AppState>>,
-    item: web::Json<FinalizeQuery>,
+    path: web::Path<Uuid>,
+    req: HttpRequest,
 ) -> impl Responder {
-    type F = GoldilocksField;
-    type C = PoseidonGoldilocksConfig;
-    const D: usize = 2;
-    let mut proposals = data.shared_map.lock().unwrap();
+    let proposals = data.shared_map.read().await;
+    let id = path.into_inner();
+    match proposals.get(&id) {
+        Some(proposal) => HttpResponse::Ok().json(
+            ProposalView::new(id, proposal, unix_timestamp(), caller_voter_id(&req)).unwrap(),
+        ),
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/propose",
+    request_body = ProposeQuery,
+    responses(
+        (status = 200, description = "The proposal was created", body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn propose(data: web::Data<Arc<AppState>>, item: web::Json<ProposeQuery>) -> HttpResponse {
+    let dao_id = item.dao_id.as_deref().unwrap_or(DEFAULT_DAO_ID);
+    if data
+        .orgs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .owns_dao(dao_id)
+    {
+        return error_response(
+            ApiErrorCode::OrganizationScoped,
+            format!(
+                "DAO {} belongs to an organization, propose through /org/{}/propose",
+                dao_id, dao_id
+            ),
+        );
+    }
+    create_proposal(data, item).await
+}
+
+// Creates a proposal in the DAO of the query, once the route has checked it may
+async fn create_proposal(
+    data: web::Data<Arc<AppState>>,
+    item: web::Json<ProposeQuery>,
+) -> HttpResponse {
+    if let Err(err) = validate_statement(&item.statement) {
+        return error_response(err.code, err.message);
+    }
+    if let Some(Err(err)) = item.content.as_ref().map(StatementContent::validate) {
+        return error_response(err.code, err.message);
+    }
+    if let Some(Err(err)) = item.category.as_deref().map(validate_category) {
+        return error_response(err.code, err.message);
+    }
+    let action = item.action.clone().unwrap_or_default();
+    if let Err(err) = action.validate() {
+        return error_response(err.code, err.message);
+    }
+    if item.electorate_size.is_some()
+        && (item.voter_dids.is_some() || item.token_snapshot.is_some())
+    {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "The size of an electorate registered by DID or seeded from a token snapshot follows from it",
+        );
+    }
+    if item
+        .electorate_size
+        .map_or(false, |size| size == 0 || size > MAX_ELECTORATE_SIZE)
+    {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            format!("Electorates have 1 to {} voters", MAX_ELECTORATE_SIZE),
+        );
+    }
+    let locks_tokens = item.lock_tokens.unwrap_or(false);
+    if locks_tokens && (item.voter_dids.is_some() || item.token_snapshot.is_some()) {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "The electorate of a proposal that locks tokens is seeded from the token tree",
+        );
+    }
+    if let Some(voter_dids) = &item.voter_dids {
+        if item.token_snapshot.is_some() {
+            return error_response(
+                ApiErrorCode::InvalidQuery,
+                "An electorate is either registered by DID or seeded from a token snapshot",
+            );
+        }
+        if let Err(err) = validate_voter_dids(voter_dids) {
+            return error_response(err.code, err.message);
+        }
+    }
+    let nonce = item.nonce.or(data.deterministic_proposal_ids.then_some(0));
+    let proposal_id = match nonce {
+        Some(nonce) => derive_proposal_id(&item.statement, item.proposer_id, nonce),
+        None => Uuid::new_v4(),
+    };
+    // Reserved before looking the id up, so a request that finds it free creates it alone
+    let _creation = match reserve_proposal_id(&data, proposal_id) {
+        Some(guard) => guard,
+        None => {
+            return error_response(
+                ApiErrorCode::ProposalCreating,
+                format!(
+                    "Proposal {} is being created by another request",
+                    proposal_id
+                ),
+            )
+        }
+    };
+    // Creating a proposal with a derived id again is a no-op
+    if data.shared_map.read().await.get(&proposal_id).is_some() {
+        return HttpResponse::Ok().json(ActionResponse {
+            proposal_id,
+            message: format!("Proposal {} already exists", proposal_id),
+        });
+    }
+    let dao_id = item.dao_id.as_deref().unwrap_or(DEFAULT_DAO_ID);
+    // Seeding the electorate writes a node per voter
+    if let Some(response) = quota_response(
+        &data,
+        &*data.shared_map.read().await,
+        dao_id,
+        &[QuotaKind::NodeStoreBytes],
+    ) {
+        return response;
+    }
+    // Fetches on-chain voting power before taking the lock, this can take a while
+    let token_snapshot = match &item.token_snapshot {
+        Some(request) => {
+            let snapshotter = match &data.token_snapshotter {
+                Some(snapshotter) => snapshotter,
+                None => {
+                    return error_response(
+                        ApiErrorCode::TokenSnapshotsDisabled,
+                        "Token snapshots require the server to run with --eth-rpc-url",
+                    )
+                }
+            };
+            match snapshotter.snapshot(request).await {
+                Ok(snapshot) => Some(snapshot),
+                Err(err) => {
+                    return error_response(
+                        ApiErrorCode::TokenSnapshotFailed,
+                        format!("Failed to snapshot token balances: {}", err),
+                    )
+                }
+            }
+        }
+        None => None,
+    };
+    let rules = ProposalRules {
+        voting_period_secs: item.voting_period_secs,
+        quorum: item.quorum,
+        tie_policy: item.tie_policy.unwrap_or_default(),
+        commit_period_secs: item.commit_period_secs,
+        balance_bits: item.balance_bits,
+        conviction: item.conviction,
+        voting_policy: item.voting_policy.unwrap_or_default(),
+        vesting: item.vesting.clone(),
+        min_transfer: item.min_transfer,
+        beacon_block: item.beacon_block,
+        shard_count: item.shard_count,
+    };
+    if let Err(err) = rules.validate() {
+        return error_response(ApiErrorCode::InvalidQuery, err);
+    }
+    // A beacon block already mined, or about to be, has a hash the proposer could pick
+    if let Some(block) = rules.beacon_block {
+        let latest = match &data.beacon {
+            Some(fetcher) => fetcher.latest_block().await,
+            None => {
+                return error_response(
+                    ApiErrorCode::BeaconUnavailable,
+                    "The server has no Ethereum RPC endpoint to check the beacon block against",
+                )
+            }
+        };
+        match latest {
+            Ok(latest) => {
+                if let Err(err) = check_beacon_lead(block, latest) {
+                    return error_response(ApiErrorCode::InvalidQuery, err);
+                }
+            }
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::BeaconUnavailable,
+                    format!("Failed to fetch the head of the chain: {}", err),
+                )
+            }
+        }
+    }
+    if let Some(Err(err)) = item.finalizers.as_ref().map(FinalizerPolicy::validate) {
+        return error_response(err.code, err.message);
+    }
+    if let Some(committee) = &item.ballot_committee {
+        if let Err(err) = committee
+            .validate()
+            .and_then(|_| BallotCommittee::check_rules(&rules))
+        {
+            return error_response(err.code, err.message);
+        }
+    }
+    let voter_balances = match (&token_snapshot, &item.voter_dids) {
+        (Some(snapshot), _) => snapshot.voter_balances(),
+        (None, Some(voter_dids)) => vec![Weight::from(1); voter_dids.len()],
+        // Seeded with the tokens voters hold, locked or not, which they lock as they vote
+        (None, None) if locks_tokens => {
+            let token_locks = data
+                .token_locks
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            (0..item.electorate_size.unwrap_or(DEFAULT_ELECTORATE_SIZE) as u64)
+                .map(|position| {
+                    let voter_id = VoterLeaf::from_position(position).index() as u32;
+                    // Credits keep the holdings of a voter within the width of a weight
+                    Weight::try_from(token_locks.holdings(voter_id)).unwrap()
+                })
+                .collect()
+        }
+        (None, None) => {
+            vec![Weight::from(1); item.electorate_size.unwrap_or(DEFAULT_ELECTORATE_SIZE)]
+        }
+    };
+    // Votes weighted by conviction add more than the electorate holds to the tallies
+    if rules.conviction.is_some() {
+        let fits_tallies = voter_balances
+            .iter()
+            .try_fold(Weight::ZERO, |total, balance| {
+                total.checked_add((*balance).into())
+            })
+            .and_then(|total| rules.max_tally(total))
+            .map_or(false, |tally| tally.fits(rules.balance_bits()));
+        if !fits_tallies {
+            return error_response(
+                ApiErrorCode::InvalidQuery,
+                format!(
+                    "The tallies of the electorate weighted by conviction do not fit in {} bits",
+                    rules.balance_bits()
+                ),
+            );
+        }
+    }
+    // Vesting schedules name voters by the leaves they would have without blinding
+    if rules.vesting.is_some() && item.blind_voters.unwrap_or(false) {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "Vesting schedules cannot be set for blinded voters",
+        );
+    }
+    // Shards hold voters at the leaves of their ids, and are proven without dependency results
+    if rules.shard_count.is_some()
+        && (item.blind_voters.unwrap_or(false)
+            || item.depends_on.as_ref().is_some_and(|ids| !ids.is_empty()))
+    {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "Sharded electorates cannot be blinded or depend on other proposals",
+        );
+    }
+    // Voters of blinded proposals are seeded at the leaves the permutation places them at
+    let blinding = item
+        .blind_voters
+        .unwrap_or(false)
+        .then(VoterBlinding::generate);
+    let voter_balances = match &blinding {
+        Some(blinding) => blinding.permute(&voter_balances),
+        None => voter_balances,
+    };
+    let tree_height = min_tree_height(voter_balances.len());
+    let depends_on = item.depends_on.clone().unwrap_or_default();
+    if let Err(err) = check_dependencies(&*data.shared_map.read().await, proposal_id, &depends_on) {
+        return error_response(err.code, err.message);
+    }
+    // A derived id may be that of a deleted proposal, whose trees are left behind
+    let storage = match data
+        .node_stores
+        .open_empty_store(&format!("balances/{}", proposal_id))
+    {
+        Ok(store) => {
+            match BalanceStorage::with_store(
+                tree_height,
+                voter_balances,
+                rules.balance_bits(),
+                store,
+            )
+            .and_then(|storage| storage.with_vesting(rules.vesting.clone()))
+            {
+                Ok(storage) => storage,
+                Err(err) => return error_response(ApiErrorCode::InvalidQuery, err),
+            }
+        }
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::NodeStoreUnavailable,
+                format!("Failed to open node store: {}", err),
+            )
+        }
+    };
+    let mut new_proposal = Proposal::with_storage(
+        item.statement.clone(),
+        item.proposer_id,
+        unix_timestamp(),
+        rules,
+        storage,
+    );
+    new_proposal.content = item.content.clone();
+    new_proposal.action = action;
+    new_proposal.token_snapshot = token_snapshot;
+    new_proposal.voter_dids = item.voter_dids.clone().unwrap_or_default();
+    new_proposal.blinding = blinding;
+    new_proposal.dao_id = dao_id.to_string();
+    new_proposal.category = item.category.clone();
+    new_proposal.depends_on = depends_on;
+    new_proposal.finalizers = item.finalizers.clone();
+    new_proposal.ballots = item.ballot_committee.clone().map(BallotBox::new);
+    new_proposal.locks_tokens = locks_tokens;
+    if let Err(err) = new_proposal.seed_shards() {
+        return error_response(ApiErrorCode::InvalidQuery, err);
+    }
+    if data.nullifier_mode {
+        match data
+            .node_stores
+            .open_empty_store(&format!("nullifiers/{}", proposal_id))
+        {
+            Ok(store) => {
+                new_proposal.nullifiers = Some(NullifierSet::with_store(proposal_id, 32, store))
+            }
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::NodeStoreUnavailable,
+                    format!("Failed to open node store: {}", err),
+                )
+            }
+        }
+    }
+    if let Some(amount) = data.proposal_deposit {
+        let mut treasury = data.treasury.lock().unwrap_or_else(PoisonError::into_inner);
+        match treasury.lock(item.proposer_id, amount) {
+            Ok(deposit) => new_proposal.deposit = Some(deposit),
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::InsufficientDeposit,
+                    format!("Failed to lock a deposit of {}: {}", amount, err),
+                )
+            }
+        }
+    }
+    let mut proposals = data.shared_map.write().await;
+    if let Err(err) = record_event(
+        &data,
+        proposal_id,
+        None,
+        new_proposal.created_at,
+        ProposalEvent::ProposalCreated(Box::new(ProposalGenesis::capture(&new_proposal))),
+    ) {
+        refund_deposit(&data, proposal_id, &mut new_proposal);
+        return error_response(err.code, err.message);
+    }
+    record_audit(
+        &data,
+        proposal_id,
+        &new_proposal,
+        AuditAction::Propose,
+        item.proposer_id,
+        &*item,
+    );
+    proposals.insert(proposal_id, new_proposal);
+    trace_event(&data, proposal_id, proposals.get(&proposal_id));
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id,
+        message: format!("New proposal {}: {}", proposal_id, item.statement),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/vote",
+    request_body = VoteQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn vote(data: web::Data<Arc<AppState>>, item: web::Json<VoteQuery>) -> HttpResponse {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    let mut proposals = data.shared_map.write().await;
+    // Moves vote from user x to 0 or 1
     // Checks if proposal exists
+    if let Some(proposal) = proposals.get(&item.proposal_id) {
+        if let Some(response) = quota_response(
+            &data,
+            &proposals,
+            &proposal.dao_id,
+            &[QuotaKind::Updates, QuotaKind::NodeStoreBytes],
+        ) {
+            return response;
+        }
+    }
+    let salt = match item.salt.as_deref().map(hex::decode).transpose() {
+        Ok(salt) => salt,
+        Err(err) => {
+            return error_response(ApiErrorCode::InvalidQuery, format!("Invalid salt: {}", err))
+        }
+    };
+    if item.split.is_some() && item.ballot.is_some() {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "A vote is either split or encrypted",
+        );
+    }
     let proposal = proposals.get_mut(&item.proposal_id);
     if let Some(proposal) = proposal {
-        // Checks if proposal is finalized
-        if item.finalizer_id != proposal.proposer_id {
-            return HttpResponse::BadRequest().body("Finalizer is not the proposer");
-        }
-        let circuit: UpdateBalanceCircuit<GoldilocksField, PoseidonGoldilocksConfig, 2> =
-            UpdateBalanceCircuit::<F, C, D>::new(proposal.updates.len(), 32);
-        let proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> =
-            circuit.prove(&proposal.updates).unwrap();
-        circuit.base_circuit_data.verify(proof).unwrap();
-        proposal.is_finalized = true;
-        let no_votes = proposal.storage.get_balance(0).unwrap();
-        let yes_votes = proposal.storage.get_balance(1).unwrap();
-        let result = if no_votes >= yes_votes {
-            "vetoed"
-        } else {
-            "passed"
+        // A split vote is signed as the split, and an encrypted one as the ballot, which
+        // replace yes or no and the salt
+        let signed = match (&item.split, &item.ballot) {
+            (_, Some(ballot)) => did_response(
+                proposal,
+                "vote",
+                &item.proposal_id,
+                item.voter_id,
+                item.did_nonce,
+                ballot,
+                item.did_signature.as_deref(),
+            ),
+            (Some(split), None) => did_response(
+                proposal,
+                "vote",
+                &item.proposal_id,
+                item.voter_id,
+                item.did_nonce,
+                split,
+                item.did_signature.as_deref(),
+            ),
+            (None, None) => did_response(
+                proposal,
+                "vote",
+                &item.proposal_id,
+                item.voter_id,
+                item.did_nonce,
+                &(item.is_yes, &item.salt),
+                item.did_signature.as_deref(),
+            ),
+        };
+        if let Some(response) = signed {
+            return response;
+        }
+        let locking = match tokens_to_lock(&data, item.proposal_id, proposal, item.voter_id) {
+            Ok(locking) => locking,
+            Err(err) => return error_response(err.code, err.message),
         };
-        HttpResponse::Ok().body(format!(
-            "Finalized proposal {}; # of Yes votes: {}, # of No votes: {} -> Proposal {}",
-            item.proposal_id, yes_votes, no_votes, result
-        ))
+        let event = match (item.split, &item.ballot) {
+            (_, Some(ballot)) => ProposalEvent::EncryptedVoteCast {
+                voter_id: item.voter_id,
+                ballot: Box::new(ballot.clone()),
+            },
+            (Some(split), None) => ProposalEvent::SplitVoteCast {
+                voter_id: item.voter_id,
+                split,
+            },
+            (None, None) => ProposalEvent::VoteCast {
+                voter_id: item.voter_id,
+                is_yes: item.is_yes,
+                salt,
+            },
+        };
+        // The first vote opens the proposal, after which it can no longer be amended
+        if let Err(err) = accept_event(&data, &mut proposals, item.proposal_id, event) {
+            return error_response(err.code, err.message);
+        }
+        lock_tokens(&data, item.proposal_id, item.voter_id, locking);
+        record_audit(
+            &data,
+            item.proposal_id,
+            proposals.get(&item.proposal_id).unwrap(),
+            AuditAction::Vote,
+            item.voter_id,
+            &*item,
+        );
+        HttpResponse::Ok().json(ActionResponse {
+            proposal_id: item.proposal_id,
+            message: format!("Voted on proposal {}", item.proposal_id),
+        })
     } else {
-        HttpResponse::NotFound().body("Proposal not found")
+        error_response(ApiErrorCode::ProposalNotFound, "Proposal not found")
     }
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let shared_state = AppState {
-        shared_map: Mutex::new(HashMap::new()),
+// Applies a vote its voter signed offline as typed data, submitted by anyone. The signature
+// and the nonce of the voter authenticate it, so the relayer needs no role of its own
+#[utoipa::path(
+    post,
+    path = "/vote/relayed",
+    request_body = RelayedVoteQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn vote_relayed(
+    data: web::Data<Arc<AppState>>,
+    item: web::Json<RelayedVoteQuery>,
+) -> HttpResponse {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    let signature = match hex::decode(item.signature.trim_start_matches("0x")) {
+        Ok(signature) => signature,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::InvalidDidSignature,
+                format!("Invalid signature: {}", err),
+            )
+        }
     };
-    let shared_state = Arc::new(shared_state);
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(shared_state.clone()))
-            .route("/", web::get().to(list_proposals))
-            .route("/vote", web::post().to(vote))
-            .route("/delegate", web::post().to(delegate))
-            .route("/finalize", web::post().to(finalize))
-            .route("/propose", web::post().to(propose))
+    let vote = item.vote;
+    let mut proposals = data.shared_map.write().await;
+    let proposal = match proposals.get(&vote.proposal_id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if let Some(response) = quota_response(
+        &data,
+        &proposals,
+        &proposal.dao_id,
+        &[QuotaKind::Updates, QuotaKind::NodeStoreBytes],
+    ) {
+        return response;
+    }
+    let locking = match tokens_to_lock(&data, vote.proposal_id, proposal, vote.voter_id) {
+        Ok(locking) => locking,
+        Err(err) => return error_response(err.code, err.message),
+    };
+    let event = ProposalEvent::RelayedVoteCast { vote, signature };
+    if let Err(err) = accept_event(&data, &mut proposals, vote.proposal_id, event) {
+        return error_response(err.code, err.message);
+    }
+    lock_tokens(&data, vote.proposal_id, vote.voter_id, locking);
+    record_audit(
+        &data,
+        vote.proposal_id,
+        proposals.get(&vote.proposal_id).unwrap(),
+        AuditAction::Vote,
+        vote.voter_id,
+        &*item,
+    );
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: vote.proposal_id,
+        message: format!("Relayed a vote on proposal {}", vote.proposal_id),
+    })
+}
+
+// Takes back the vote of a voter before the voting period ends, so they can vote again
+#[utoipa::path(
+    post,
+    path = "/vote/revoke",
+    request_body = RevokeQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn revoke(data: web::Data<Arc<AppState>>, item: web::Json<RevokeQuery>) -> impl Responder {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    let mut proposals = data.shared_map.write().await;
+    if let Some(proposal) = proposals.get(&item.proposal_id) {
+        if let Some(response) = quota_response(
+            &data,
+            &proposals,
+            &proposal.dao_id,
+            &[QuotaKind::Updates, QuotaKind::NodeStoreBytes],
+        ) {
+            return response;
+        }
+    }
+    let proposal = match proposals.get_mut(&item.proposal_id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if let Some(response) = did_response(
+        proposal,
+        "revoke",
+        &item.proposal_id,
+        item.voter_id,
+        item.did_nonce,
+        &(),
+        item.did_signature.as_deref(),
+    ) {
+        return response;
+    }
+    let event = ProposalEvent::Revoked {
+        voter_id: item.voter_id,
+    };
+    if let Err(err) = accept_event(&data, &mut proposals, item.proposal_id, event) {
+        return error_response(err.code, err.message);
+    }
+    record_audit(
+        &data,
+        item.proposal_id,
+        proposals.get(&item.proposal_id).unwrap(),
+        AuditAction::Revoke,
+        item.voter_id,
+        &*item,
+    );
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: item.proposal_id,
+        message: format!("Revoked vote on proposal {}", item.proposal_id),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/commit",
+    request_body = CommitQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn commit(data: web::Data<Arc<AppState>>, item: web::Json<CommitQuery>) -> impl Responder {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    let commitment = match hex::decode(&item.commitment)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+    {
+        Some(commitment) => commitment,
+        None => {
+            return error_response(
+                ApiErrorCode::InvalidQuery,
+                "Commitment must be 32 hex encoded bytes",
+            )
+        }
+    };
+    let mut proposals = data.shared_map.write().await;
+    let proposal = match proposals.get_mut(&item.proposal_id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if let Some(response) = did_response(
+        proposal,
+        "commit",
+        &item.proposal_id,
+        item.voter_id,
+        item.did_nonce,
+        &item.commitment,
+        item.did_signature.as_deref(),
+    ) {
+        return response;
+    }
+    let event = ProposalEvent::Committed {
+        voter_id: item.voter_id,
+        commitment,
+    };
+    // Votes are committed to the statement, so it can no longer be amended
+    if let Err(err) = accept_event(&data, &mut proposals, item.proposal_id, event) {
+        return error_response(err.code, err.message);
+    }
+    record_audit(
+        &data,
+        item.proposal_id,
+        proposals.get(&item.proposal_id).unwrap(),
+        AuditAction::Commit,
+        item.voter_id,
+        &*item,
+    );
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: item.proposal_id,
+        message: format!("Committed to a vote on proposal {}", item.proposal_id),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/delegate",
+    request_body = DelegateQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn delegate(data: web::Data<Arc<AppState>>, item: web::Json<DelegateQuery>) -> HttpResponse {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    let mut proposals = data.shared_map.write().await;
+    // Delegates vote from user x to user y
+    // Checks if proposal exists
+    if let Some(proposal) = proposals.get(&item.proposal_id) {
+        if let Some(response) = quota_response(
+            &data,
+            &proposals,
+            &proposal.dao_id,
+            &[QuotaKind::Updates, QuotaKind::NodeStoreBytes],
+        ) {
+            return response;
+        }
+    }
+    let proposal = proposals.get_mut(&item.proposal_id);
+    if let Some(proposal) = proposal {
+        if let Some(response) = did_response(
+            proposal,
+            "delegate",
+            &item.proposal_id,
+            item.voter_id,
+            item.did_nonce,
+            &item.delegator_id,
+            item.did_signature.as_deref(),
+        ) {
+            return response;
+        }
+        let locking = match tokens_to_lock(&data, item.proposal_id, proposal, item.voter_id) {
+            Ok(locking) => locking,
+            Err(err) => return error_response(err.code, err.message),
+        };
+        let event = ProposalEvent::Delegated {
+            voter_id: item.voter_id,
+            delegator_id: item.delegator_id,
+        };
+        if let Err(err) = accept_event(&data, &mut proposals, item.proposal_id, event) {
+            return error_response(err.code, err.message);
+        }
+        lock_tokens(&data, item.proposal_id, item.voter_id, locking);
+        record_audit(
+            &data,
+            item.proposal_id,
+            proposals.get(&item.proposal_id).unwrap(),
+            AuditAction::Delegate,
+            item.voter_id,
+            &*item,
+        );
+        HttpResponse::Ok().json(ActionResponse {
+            proposal_id: item.proposal_id,
+            message: format!("Delegated on proposal {}", item.proposal_id),
+        })
+    } else {
+        error_response(ApiErrorCode::ProposalNotFound, "Proposal not found")
+    }
+}
+
+// Applies many delegations on a proposal all or none, e.g. of the sub-accounts of a holder:
+// a delegation failing rolls the tree back to where it was before the batch
+#[utoipa::path(
+    post,
+    path = "/delegate/batch",
+    request_body = DelegateBatchQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, none of the delegations were applied, see the error code", body = ApiError)
+    )
+)]
+async fn delegate_batch(
+    data: web::Data<Arc<AppState>>,
+    item: web::Json<DelegateBatchQuery>,
+) -> HttpResponse {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    let mut proposals = data.shared_map.write().await;
+    let proposal = match proposals.get(&item.proposal_id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if let Some(response) = quota_response(
+        &data,
+        &proposals,
+        &proposal.dao_id,
+        &[QuotaKind::Updates, QuotaKind::NodeStoreBytes],
+    ) {
+        return response;
+    }
+    let mut lockings = vec![];
+    for delegation in &item.delegations {
+        if let Some(response) = did_response(
+            proposal,
+            "delegate",
+            &item.proposal_id,
+            delegation.voter_id,
+            delegation.did_nonce,
+            &delegation.delegator_id,
+            delegation.did_signature.as_deref(),
+        ) {
+            return response;
+        }
+        match tokens_to_lock(&data, item.proposal_id, proposal, delegation.voter_id) {
+            Ok(locking) => lockings.push((delegation.voter_id, locking)),
+            Err(err) => return error_response(err.code, err.message),
+        }
+    }
+    let event = ProposalEvent::DelegatedBatch {
+        delegations: item
+            .delegations
+            .iter()
+            .map(|delegation| BatchDelegation {
+                voter_id: delegation.voter_id,
+                delegator_id: delegation.delegator_id,
+            })
+            .collect(),
+    };
+    if let Err(err) = accept_event(&data, &mut proposals, item.proposal_id, event) {
+        return error_response(err.code, err.message);
+    }
+    for (voter_id, locking) in lockings {
+        lock_tokens(&data, item.proposal_id, voter_id, locking);
+    }
+    let proposal = proposals.get(&item.proposal_id).unwrap();
+    for delegation in &item.delegations {
+        record_audit(
+            &data,
+            item.proposal_id,
+            proposal,
+            AuditAction::Delegate,
+            delegation.voter_id,
+            delegation,
+        );
+    }
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: item.proposal_id,
+        message: format!(
+            "Applied {} delegations on proposal {}",
+            item.delegations.len(),
+            item.proposal_id
+        ),
+    })
+}
+
+// Delegates the weight of a voter on a single proposal or a category of proposals, until
+// an optional expiry. The delegation is applied by `apply_scoped_delegations`.
+#[utoipa::path(
+    post,
+    path = "/delegations",
+    request_body = ScopedDelegateQuery,
+    responses(
+        (status = 200, body = ScopedDelegation),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn create_scoped_delegation(
+    data: web::Data<Arc<AppState>>,
+    item: web::Json<ScopedDelegateQuery>,
+) -> HttpResponse {
+    if let Some(response) = paused_response(&data) {
+        return response;
+    }
+    if let DelegationScope::Proposal { proposal_id } = &item.scope {
+        match data.shared_map.read().await.get(proposal_id) {
+            Some(proposal) if !proposal.voter_dids.is_empty() || proposal.locks_tokens => {
+                return error_response(
+                    ApiErrorCode::InvalidQuery,
+                    "Delegating on the proposal takes a signature or a lock of the voter, use /delegate",
+                )
+            }
+            Some(_) => {}
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        }
+    }
+    let delegation = data
+        .scoped_delegations
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            item.voter_id,
+            item.delegate_id,
+            item.scope.clone(),
+            item.expires_at,
+            unix_timestamp(),
+        );
+    match delegation {
+        Ok(delegation) => HttpResponse::Ok().json(delegation),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Lists the scoped delegations of a voter, with the proposals each was applied to
+#[utoipa::path(
+    get,
+    path = "/delegations/{voter_id}",
+    params(("voter_id" = u32, Path, description = "Voter id")),
+    responses(
+        (status = 200, body = Vec<ScopedDelegation>)
+    )
+)]
+async fn list_scoped_delegations(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<u32>,
+) -> HttpResponse {
+    let delegations = data
+        .scoped_delegations
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .of_voter(path.into_inner());
+    HttpResponse::Ok().json(delegations)
+}
+
+// Ends a scoped delegation now, its weight moves back to the voter on the next run of
+// `apply_scoped_delegations`
+#[utoipa::path(
+    post,
+    path = "/delegations/{id}/revoke",
+    params(("id" = Uuid, Path, description = "Scoped delegation id")),
+    request_body = ScopedRevokeQuery,
+    responses(
+        (status = 200, body = ScopedDelegation),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn revoke_scoped_delegation(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    item: web::Json<ScopedRevokeQuery>,
+) -> HttpResponse {
+    let delegation = data
+        .scoped_delegations
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .revoke(&path.into_inner(), item.voter_id, unix_timestamp());
+    match delegation {
+        Ok(delegation) => HttpResponse::Ok().json(delegation),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Withdraws a proposal before anyone has voted on it
+#[utoipa::path(
+    post,
+    path = "/proposal/{id}/cancel",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    request_body = CancelQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn cancel(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    item: web::Json<CancelQuery>,
+) -> impl Responder {
+    let mut proposals = data.shared_map.write().await;
+    let id = path.into_inner();
+    let proposal = match proposals.get(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if item.proposer_id != proposal.proposer_id {
+        return error_response(ApiErrorCode::NotProposer, "Caller is not the proposer");
+    }
+    if proposal.status != ProposalStatus::Draft {
+        return error_response(
+            ApiErrorCode::ProposalNotDraft,
+            "Proposal has votes and can no longer be cancelled",
+        );
+    }
+    if let Err(err) = accept_event(&data, &mut proposals, id, ProposalEvent::Cancelled) {
+        return error_response(err.code, err.message);
+    }
+    refund_deposit(&data, id, proposals.get_mut(&id).unwrap());
+    release_tokens(&data, id, proposals.get(&id).unwrap());
+    record_audit(
+        &data,
+        id,
+        proposals.get(&id).unwrap(),
+        AuditAction::Cancel,
+        item.proposer_id,
+        &(id, &*item),
+    );
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: id,
+        message: format!("Cancelled proposal {}", id),
+    })
+}
+
+// Replaces the statement of a proposal before anyone has voted on it
+#[utoipa::path(
+    post,
+    path = "/proposal/{id}/amend",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    request_body = AmendQuery,
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn amend(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    item: web::Json<AmendQuery>,
+) -> impl Responder {
+    if let Err(err) = validate_statement(&item.statement) {
+        return error_response(err.code, err.message);
+    }
+    let mut proposals = data.shared_map.write().await;
+    let id = path.into_inner();
+    let proposal = match proposals.get_mut(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if item.proposer_id != proposal.proposer_id {
+        return error_response(ApiErrorCode::NotProposer, "Caller is not the proposer");
+    }
+    if proposal.status != ProposalStatus::Draft {
+        return error_response(
+            ApiErrorCode::ProposalNotDraft,
+            "Proposal has votes and can no longer be amended",
+        );
+    }
+    let event = ProposalEvent::Amended {
+        statement: item.statement.clone(),
+    };
+    if let Err(err) = accept_event(&data, &mut proposals, id, event) {
+        return error_response(err.code, err.message);
+    }
+    record_audit(
+        &data,
+        id,
+        proposals.get(&id).unwrap(),
+        AuditAction::Amend,
+        item.proposer_id,
+        &(id, &*item),
+    );
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: id,
+        message: format!("Amended proposal {}: {}", id, item.statement),
+    })
+}
+
+// Opens the round reopening voting at `now` on the proposal `tied_id`, which tied under the
+// revote policy, with its trees in fresh stores. The round has the id derived from that of the
+// tied proposal, so a replay of the log opens it under the same one
+fn revote_round(
+    data: &AppState,
+    tied_id: Uuid,
+    tied: &Proposal,
+    now: u64,
+) -> Result<(Uuid, Proposal), ApiError> {
+    let round_id = derive_revote_id(&tied_id);
+    let genesis = ProposalGenesis::revote_round(tied_id, tied, now);
+    let open_store = |namespace: String| {
+        data.node_stores
+            .open_empty_store(&namespace)
+            .map_err(|err| {
+                ApiError::new(
+                    ApiErrorCode::NodeStoreUnavailable,
+                    format!("Failed to open node store: {}", err),
+                )
+            })
+    };
+    let balance_store = open_store(format!("balances/{}", round_id))?;
+    let nullifier_store = match genesis.nullifier_height {
+        Some(_) => Some(open_store(format!("nullifiers/{}", round_id))?),
+        None => None,
+    };
+    let round = genesis
+        .build(round_id, balance_store, nullifier_store)
+        .map_err(|err| {
+            ApiError::new(
+                ApiErrorCode::NodeStoreUnavailable,
+                format!("Failed to seed the revote round: {:#}", err),
+            )
+        })?;
+    Ok((round_id, round))
+}
+
+// What a finalization proves: the updates of the balance tree with the proofs of its tallies,
+// or on sharded proposals the witness of every shard, combined by a shard root proof
+enum FinalizationUpdates {
+    Tree(
+        Vec<BalanceUpdate<GoldilocksField>>,
+        [MerkleProof<GoldilocksField>; 2],
+    ),
+    Shards(Vec<ShardWitness<GoldilocksField>>),
+}
+
+impl FinalizationUpdates {
+    // Id of the circuit proving the updates with the update balance circuit of `shape`
+    fn circuit_id(&self, shape: &UpdateBalanceShape) -> String {
+        match self {
+            FinalizationUpdates::Tree(..) => update_balance_circuit_id(shape),
+            FinalizationUpdates::Shards(witnesses) => shard_root_circuit_id(shape, witnesses.len()),
+        }
+    }
+}
+
+// The shape of the circuit finalizing the proposal proves with, and its witness: the updates,
+// padded with no-ops so the circuit of the next power-of-two size can be reused, or a single
+// no-op if nobody voted, the proofs of the tallies and the statement and action hashes. Every
+// shard of a sharded proposal is proven with the same circuit, padded to the longest shard
+fn finalization_witness(
+    proposal: &Proposal,
+    dependencies_hash: Option<WHashOut<GoldilocksField>>,
+) -> (
+    UpdateBalanceShape,
+    FinalizationUpdates,
+    (WHashOut<GoldilocksField>, WHashOut<GoldilocksField>),
+) {
+    let statement_hash =
+        compute_statement_hash_with_content(&proposal.statement, proposal.content.as_ref());
+    let action_hash = compute_action_hash(&proposal.action);
+    if let Some(shards) = &proposal.shards {
+        let (shape, witnesses) = shards.witnesses().unwrap();
+        return (
+            shape,
+            FinalizationUpdates::Shards(witnesses),
+            (statement_hash, action_hash),
+        );
+    }
+    let updates = proposal.finalization_updates();
+    let shape = UpdateBalanceShape {
+        number_updates: updates.len(),
+        tree_height: proposal.storage.tree_height(),
+        balance_bits: proposal.storage.balance_bits(),
+        conviction: proposal.rules.conviction.is_some(),
+        voting_policy: proposal.rules.voting_policy,
+        dependencies: dependencies_hash.is_some(),
+        vesting: proposal.rules.vesting.is_some(),
+        deadline: proposal.deadline().is_some(),
+        min_transfer: proposal.rules.min_transfer.is_some(),
+    };
+    let tally_proofs = [
+        proposal.storage.get_tally_proof(TallySlot::NO).unwrap(),
+        proposal.storage.get_tally_proof(TallySlot::YES).unwrap(),
+    ];
+    (
+        shape,
+        FinalizationUpdates::Tree(updates, tally_proofs),
+        (statement_hash, action_hash),
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/finalize",
+    request_body = FinalizeQuery,
+    responses(
+        (status = 200, description = "Proven and finalized", body = FinalizeResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn finalize(data: web::Data<Arc<AppState>>, item: web::Json<FinalizeQuery>) -> HttpResponse {
+    let item = item.into_inner();
+    // A tie broken by a beacon needs the hash of the beacon block, fetched before the store
+    // is locked for writing
+    let beacon_block = data
+        .shared_map
+        .read()
+        .await
+        .get(&item.proposal_id)
+        .filter(|proposal| proposal.rules.tie_policy == TiePolicy::RandomWithBeacon)
+        .filter(|proposal| proposal.storage.tally().is_ok_and(|tally| tally.is_tie()))
+        .and_then(|proposal| proposal.rules.beacon_block);
+    let beacon = match (beacon_block, &data.beacon) {
+        (None, _) => None,
+        (Some(_), None) => {
+            return error_response(
+                ApiErrorCode::BeaconUnavailable,
+                "The server has no Ethereum RPC endpoint to fetch the beacon block from",
+            )
+        }
+        (Some(block), Some(fetcher)) => match fetcher.block_hash(block).await {
+            Ok(Some(hash)) => Some(hash),
+            Ok(None) => {
+                return error_response(
+                    ApiErrorCode::BeaconUnavailable,
+                    format!("Beacon block {} has not been confirmed yet", block),
+                )
+            }
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::BeaconUnavailable,
+                    format!("Failed to fetch beacon block {}: {}", block, err),
+                )
+            }
+        },
+    };
+    let (
+        claim,
+        tally,
+        outcome,
+        beacon,
+        shape,
+        (statement_hash, action_hash),
+        (dependencies, dependencies_hash),
+        updates,
+    ) = {
+        let mut proposals = data.shared_map.write().await;
+        // Checks if proposal exists
+        let proposal = match proposals.get(&item.proposal_id) {
+            Some(proposal) => proposal,
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        };
+        // Checks if proposal is already finalized, cancelled or being finalized
+        if let Some(response) = closed_response(proposal) {
+            return response;
+        }
+        if !proposal.may_finalize(item.finalizer_id) {
+            return error_response(
+                ApiErrorCode::NotProposer,
+                "Finalizer is neither the proposer nor a finalizer of the proposal",
+            );
+        }
+        if !proposal.finalization_approved() {
+            return error_response(
+                ApiErrorCode::ApprovalsPending,
+                "Fewer finalizers than the threshold have approved finalizing the proposal",
+            );
+        }
+        if !proposal.ballots_decrypted() {
+            return error_response(
+                ApiErrorCode::BallotsSealed,
+                "The ballots of the proposal are not decrypted yet",
+            );
+        }
+        let dao_id = proposal.dao_id.clone();
+        if let Some(response) = quota_response(&data, &proposals, &dao_id, &[QuotaKind::ProofBytes])
+        {
+            return response;
+        }
+        // A proposal is finalized after the proposals it depends on
+        let dependencies = match resolve_dependencies(&proposals, &proposal.depends_on) {
+            Ok(dependencies) => dependencies,
+            Err(err) => return error_response(err.code, err.message),
+        };
+        let proposal = proposals.get_mut(&item.proposal_id).unwrap();
+        // Resolves the outcome before proving so a missing beacon fails early
+        let tally = proposal.storage.tally().unwrap();
+        let outcome = match proposal
+            .rules
+            .resolve(&item.proposal_id, &tally, beacon.as_deref())
+        {
+            Ok(outcome) => gate_outcome(outcome, &dependencies),
+            Err(err) => return error_response(ApiErrorCode::OutcomeUnresolved, err),
+        };
+        let dependencies_hash = if dependencies.is_empty() {
+            None
+        } else {
+            Some(compute_dependencies_hash(&dependencies))
+        };
+        let previous_status = proposal.status;
+        let (shape, updates, (statement_hash, action_hash)) =
+            finalization_witness(proposal, dependencies_hash);
+        // Fails here rather than with a panic when the circuit is built
+        if let Err(err) = shape.validate() {
+            return error_response(
+                ApiErrorCode::ProvingFailed,
+                format!("The proposal cannot be proven: {:#}", err),
+            );
+        }
+        // Rejects votes and other finalizations while the store is unlocked for proving
+        let claim = match proposals.claim_finalization(&item.proposal_id, previous_status) {
+            Ok(claim) => claim,
+            Err(err) => return error_response(err.code(), err),
+        };
+        (
+            claim,
+            tally,
+            outcome,
+            beacon,
+            shape,
+            (statement_hash, action_hash),
+            (dependencies, dependencies_hash),
+            updates,
+        )
+    };
+
+    // Proving runs on the blocking pool without holding the store. It is spawned so that a
+    // client disconnecting does not leave the proposal stuck in finalizing.
+    let state = data.get_ref().clone();
+    let span = info_span!(
+        "finalize",
+        proposal_id = %item.proposal_id,
+        circuit_id = %updates.circuit_id(&shape),
+    );
+    let proving_span = span.clone();
+    let finalization = async move {
+        #[cfg(feature = "chaos")]
+        actix_web::rt::time::sleep(chaos::proving_delay()).await;
+        let circuit_state = state.clone();
+        let proved = web::block(move || {
+            let _entered = proving_span.enter();
+            circuit_state.proving.prove(|| {
+                let (envelope, elapsed) = match &updates {
+                    FinalizationUpdates::Tree(updates, tally_proofs) => {
+                        let circuit = circuit_state
+                            .circuits
+                            .lock()
+                            // A panic while building leaves no partial entry behind, so the
+                            // cache stays usable
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .get_or_build(shape);
+                        let started_at = Instant::now();
+                        let envelope = circuit.prove_envelope_with_dependencies(
+                            statement_hash,
+                            action_hash,
+                            dependencies_hash,
+                            updates,
+                            tally_proofs,
+                        )?;
+                        (envelope, started_at.elapsed())
+                    }
+                    FinalizationUpdates::Shards(witnesses) => {
+                        let (shard_circuit, circuit) = {
+                            let mut circuits = circuit_state
+                                .circuits
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner);
+                            (
+                                circuits.get_or_build(shape),
+                                circuits.get_or_build_shard_root(shape, witnesses.len()),
+                            )
+                        };
+                        let started_at = Instant::now();
+                        let envelope = circuit.prove_envelope(
+                            &shard_circuit,
+                            statement_hash,
+                            action_hash,
+                            witnesses,
+                        )?;
+                        (envelope, started_at.elapsed())
+                    }
+                };
+                // Estimates the proving time of later dry runs
+                circuit_state
+                    .circuits
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .record_proving_time(&envelope.circuit_id, elapsed);
+                Ok(envelope)
+            })
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|proved| proved.map_err(anyhow::Error::from));
+
+        let mut proposals = state.shared_map.write().await;
+        // A job whose claim was lost, e.g. to a recovery, leaves the proposal to its new status
+        if let Err(err) = proposals.check_claim(&claim) {
+            return error_response(err.code(), err);
+        }
+        let envelope = match proved {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                proposals.abandon_finalization(&claim).unwrap();
+                let error = ApiError::from(QedError::ProofGenerationFailed {
+                    stage: ProofStage::Proof,
+                    message: format!("{:#}", err),
+                });
+                return error_response(error.code, error.message);
+            }
+        };
+        // A valid proof of another tree than the one being finalized, or of the tree under
+        // other rules, proves nothing about it
+        let proposal = proposals.get(&item.proposal_id).unwrap();
+        let (initial_root, final_root) = proposal.proven_roots().unwrap();
+        let expected = expected_public_inputs(
+            &shape,
+            initial_root,
+            final_root,
+            &proposal.statement,
+            proposal.content.as_ref(),
+            &proposal.action,
+            &dependencies,
+            &proposal.proven_rules(),
+        );
+        if let Err(err) =
+            check_public_inputs(&envelope.circuit_id, &envelope.public_inputs, &expected)
+        {
+            proposals.abandon_finalization(&claim).unwrap();
+            return error_response(
+                ApiErrorCode::PublicInputsMismatch,
+                format!("The proof does not match the proposal: {}", err),
+            );
+        }
+        let finalized_at = unix_timestamp();
+        // A tie under the revote policy reopens voting in a new round, whose trees are opened
+        // before the certificate naming it is issued
+        let revote = match outcome {
+            ProposalOutcome::Revote => {
+                match revote_round(&state, item.proposal_id, proposal, finalized_at) {
+                    Ok(round) => Some(round),
+                    Err(err) => {
+                        proposals.abandon_finalization(&claim).unwrap();
+                        return error_response(err.code, err.message);
+                    }
+                }
+            }
+            _ => None,
+        };
+        let revote_id = revote.as_ref().map(|(round_id, _)| *round_id);
+        let proposal = proposals.get_mut(&item.proposal_id).unwrap();
+        let nullifier_root = proposal
+            .nullifiers
+            .as_ref()
+            .map(|nullifiers| nullifiers.root().unwrap());
+        let mut certificate = FinalizationCertificate {
+            proposal_id: item.proposal_id,
+            statement: proposal.statement.clone(),
+            content: proposal.content.clone(),
+            action: proposal.action.clone(),
+            initial_root,
+            final_root,
+            yes_votes: tally.yes_votes,
+            no_votes: tally.no_votes,
+            outcome,
+            tie_policy: proposal.rules.tie_policy,
+            beacon,
+            beacon_block: proposal.rules.beacon_block,
+            revote: revote_id,
+            circuit_id: envelope.circuit_id.clone(),
+            nullifier_root,
+            binding: compute_certificate_binding(final_root, nullifier_root),
+            anchors: proposal.anchors.clone(),
+            transcript_digest: compute_transcript_digest(&proposal.updates),
+            timestamps: vec![],
+            issuer: None,
+            dependencies,
+            approvals: proposal.approvals.clone(),
+            rules: proposal.proven_rules(),
+        };
+        if let Some(signer) = &state.signer {
+            certificate.issuer = Some(signer.sign(&certificate.digest()).unwrap());
+        }
+        // Both the round a revote opens and the finalization are logged before either applies
+        let event = ProposalEvent::Finalized {
+            certificate: Box::new(certificate.clone()),
+            proof: Box::new(envelope.clone()),
+        };
+        let round_seq = match &revote {
+            Some((round_id, round)) => match record_event(
+                &state,
+                *round_id,
+                None,
+                round.created_at,
+                ProposalEvent::ProposalCreated(Box::new(ProposalGenesis::capture(round))),
+            ) {
+                Ok(seq) => Some(seq),
+                Err(err) => {
+                    proposals.abandon_finalization(&claim).unwrap();
+                    return error_response(err.code, err.message);
+                }
+            },
+            None => None,
+        };
+        if let Err(err) = record_event(
+            &state,
+            item.proposal_id,
+            Some(&*proposal),
+            finalized_at,
+            event,
+        ) {
+            if let (Some((round_id, _)), Some(seq)) = (&revote, round_seq) {
+                retract_event(&state, *round_id, seq);
+            }
+            proposals.abandon_finalization(&claim).unwrap();
+            return error_response(err.code, err.message);
+        }
+        let proposal = proposals.get_mut(&item.proposal_id).unwrap();
+        proposal.certificate = Some(certificate);
+        proposal.proof = Some(envelope);
+        proposal.finalized_at = Some(finalized_at);
+        proposals.complete_finalization(&claim).unwrap();
+        trace_event(&state, item.proposal_id, proposals.get(&item.proposal_id));
+        if let Some((round_id, round)) = revote {
+            proposals.insert(round_id, round);
+        }
+        refund_deposit(
+            &state,
+            item.proposal_id,
+            proposals.get_mut(&item.proposal_id).unwrap(),
+        );
+        release_tokens(
+            &state,
+            item.proposal_id,
+            proposals.get(&item.proposal_id).unwrap(),
+        );
+        record_audit(
+            &state,
+            item.proposal_id,
+            proposals.get(&item.proposal_id).unwrap(),
+            AuditAction::Finalize,
+            item.finalizer_id,
+            &item,
+        );
+        queue_webhooks(&state, proposals.get(&item.proposal_id).unwrap());
+        // A transfer the funds of the DAO cannot cover is paid out by an admin once they do
+        let proposal = proposals.get(&item.proposal_id).unwrap();
+        match pay_out(&state, item.proposal_id, proposal) {
+            Ok(payout) => record_audit(
+                &state,
+                item.proposal_id,
+                proposal,
+                AuditAction::Payout,
+                proposal.proposer_id,
+                &PayoutReceipt::from(payout),
+            ),
+            Err(err) if err.code == ApiErrorCode::NotPayable => {}
+            Err(err) => error!(proposal_id = %item.proposal_id, "{}", err.message),
+        }
+        HttpResponse::Ok().json(FinalizeResponse {
+            proposal_id: item.proposal_id,
+            tally,
+            outcome,
+            revote: revote_id,
+        })
+    };
+    // The spawned task records the events of the finalization under the same request
+    let request_id = RequestId::current().unwrap_or_else(RequestId::generate);
+    let finalization = actix_web::rt::spawn(request_id.scope(finalization).instrument(span));
+    match finalization.await {
+        Ok(response) => response,
+        // The panicking task left the store to be recovered on the next lock
+        Err(err) => error_response(
+            ApiErrorCode::ProvingFailed,
+            format!("Finalization failed: {}", err),
+        ),
+    }
+}
+
+// Records the approval of a finalizer of a proposal finalized by a threshold of them, and
+// finalizes the proposal once the approvals meet the threshold
+#[utoipa::path(
+    post,
+    path = "/finalize/approve",
+    request_body = FinalizeApprovalQuery,
+    responses(
+        (status = 200, description = "The threshold was met, proven and finalized", body = FinalizeResponse),
+        (status = 202, description = "Approved, short of the threshold", body = FinalizeApprovalResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn approve_finalization(
+    data: web::Data<Arc<AppState>>,
+    item: web::Json<FinalizeApprovalQuery>,
+) -> HttpResponse {
+    let item = item.into_inner();
+    {
+        let mut proposals = data.shared_map.write().await;
+        let proposal = match proposals.get(&item.proposal_id) {
+            Some(proposal) => proposal,
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        };
+        if let Some(response) = closed_response(proposal) {
+            return response;
+        }
+        let signature = match hex::decode(item.signature.trim_start_matches("0x")) {
+            Ok(signature) => signature,
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::InvalidApprovalSignature,
+                    format!("Invalid signature encoding: {}", err),
+                )
+            }
+        };
+        let event = ProposalEvent::FinalizationApproved {
+            finalizer_id: item.finalizer_id,
+            signature,
+        };
+        if let Err(err) = accept_event(&data, &mut proposals, item.proposal_id, event) {
+            return error_response(err.code, err.message);
+        }
+        let proposal = proposals.get(&item.proposal_id).unwrap();
+        record_audit(
+            &data,
+            item.proposal_id,
+            proposal,
+            AuditAction::ApproveFinalization,
+            item.finalizer_id,
+            &item,
+        );
+        if !proposal.finalization_approved() {
+            return HttpResponse::Accepted().json(FinalizeApprovalResponse {
+                proposal_id: item.proposal_id,
+                approved_by: proposal
+                    .approvals
+                    .iter()
+                    .map(|approval| approval.finalizer_id)
+                    .collect(),
+                threshold: proposal
+                    .finalizers
+                    .as_ref()
+                    .map_or(0, |policy| policy.threshold),
+            });
+        }
+    }
+    // The approval that meets the threshold starts proving, as the proposer would
+    let query = FinalizeQuery {
+        proposal_id: item.proposal_id,
+        finalizer_id: item.finalizer_id,
+    };
+    finalize(data, web::Json(query)).await
+}
+
+// Previews the result finalizing the proposal would produce at this point
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/preview",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = FinalizationPreview),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn preview(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let proposals = data.shared_map.read().await;
+    let id = path.into_inner();
+    match proposals.get(&id) {
+        Some(proposal) => {
+            let tally = proposal.storage.tally().unwrap();
+            let outcome = resolve_dependencies(&proposals, &proposal.depends_on)
+                .ok()
+                .and_then(|dependencies| {
+                    let outcome = proposal.rules.resolve(&id, &tally, None).ok()?;
+                    Some(gate_outcome(outcome, &dependencies))
+                });
+            HttpResponse::Ok().json(FinalizationPreview {
+                tally,
+                tie_policy: proposal.rules.tie_policy,
+                is_tie: tally.is_tie(),
+                outcome,
+                revote: (outcome == Some(ProposalOutcome::Revote)).then(|| derive_revote_id(&id)),
+            })
+        }
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Builds the circuit finalizing the proposal now would prove with, or takes it from the
+// cache, and generates its witness without proving, so a proposer can tell what the proof
+// would cost and whether it would fail before starting it
+#[utoipa::path(
+    post,
+    path = "/proposal/{id}/finalize/dry-run",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    request_body = FinalizeDryRunQuery,
+    responses(
+        (status = 200, body = FinalizeDryRunResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn finalize_dry_run(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    item: web::Json<FinalizeDryRunQuery>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let (shape, updates, (statement_hash, action_hash), dependencies_hash) = {
+        let proposals = data.shared_map.read().await;
+        let proposal = match proposals.get(&id) {
+            Some(proposal) => proposal,
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        };
+        if let Some(response) = closed_response(proposal) {
+            return response;
+        }
+        if !proposal.may_finalize(item.finalizer_id) {
+            return error_response(
+                ApiErrorCode::NotProposer,
+                "Finalizer is neither the proposer nor a finalizer of the proposal",
+            );
+        }
+        if !proposal.ballots_decrypted() {
+            return error_response(
+                ApiErrorCode::BallotsSealed,
+                "The ballots of the proposal are not decrypted yet",
+            );
+        }
+        let dependencies = match resolve_dependencies(&proposals, &proposal.depends_on) {
+            Ok(dependencies) => dependencies,
+            Err(err) => return error_response(err.code, err.message),
+        };
+        let dependencies_hash =
+            (!dependencies.is_empty()).then(|| compute_dependencies_hash(&dependencies));
+        let (shape, updates, hashes) = finalization_witness(proposal, dependencies_hash);
+        if let Err(err) = shape.validate() {
+            return error_response(
+                ApiErrorCode::DryRunFailed,
+                format!("The proposal cannot be proven: {:#}", err),
+            );
+        }
+        (shape, updates, hashes, dependencies_hash)
+    };
+    let state = data.get_ref().clone();
+    let dry_run = web::block(move || {
+        let circuit_id = updates.circuit_id(&shape);
+        let mut circuits = state
+            .circuits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let circuit_cached = circuits.is_built(&circuit_id);
+        let circuit = circuits.get_or_build(shape);
+        let degree_bits = match &updates {
+            FinalizationUpdates::Tree(..) => circuit.base_circuit_data.common.degree_bits(),
+            FinalizationUpdates::Shards(witnesses) => circuits
+                .get_or_build_shard_root(shape, witnesses.len())
+                .base_circuit_data
+                .common
+                .degree_bits(),
+        };
+        let estimated_proving_time = circuits.estimate_proving_time(&circuit_id);
+        drop(circuits);
+        let started_at = Instant::now();
+        let violations = match &updates {
+            FinalizationUpdates::Tree(updates, tally_proofs) => circuit.check_witness(
+                statement_hash,
+                action_hash,
+                dependencies_hash,
+                updates,
+                tally_proofs,
+            ),
+            FinalizationUpdates::Shards(witnesses) => witnesses
+                .iter()
+                .enumerate()
+                .flat_map(|(shard, witness)| {
+                    circuit
+                        .check_witness(
+                            statement_hash,
+                            action_hash,
+                            None,
+                            &witness.updates,
+                            &witness.tally_proofs,
+                        )
+                        .into_iter()
+                        .map(move |violation| format!("shard {}: {}", shard, violation))
+                })
+                .collect(),
+        };
+        FinalizeDryRunResponse {
+            proposal_id: id,
+            circuit_id,
+            circuit_cached,
+            degree_bits,
+            number_updates: shape.number_updates,
+            witness_ms: started_at.elapsed().as_millis() as u64,
+            estimated_proving_ms: estimated_proving_time.map(|elapsed| elapsed.as_millis() as u64),
+            violations,
+        }
+    })
+    .await;
+    match dry_run {
+        Ok(dry_run) => HttpResponse::Ok().json(dry_run),
+        Err(err) => error_response(
+            ApiErrorCode::DryRunFailed,
+            format!("Failed to dry run the finalization: {}", err),
+        ),
+    }
+}
+
+// Resolves a voter DID to the document its requests are verified against
+#[utoipa::path(
+    get,
+    path = "/did/{did}",
+    params(("did" = String, Path, description = "A did:key or did:ethr identifier")),
+    responses(
+        (status = 200, body = DidDocument),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn resolve_did(path: web::Path<String>) -> impl Responder {
+    match path.parse::<Did>() {
+        Ok(did) => HttpResponse::Ok().json(did.resolve()),
+        Err(err) => error_response(ApiErrorCode::InvalidDid, err),
+    }
+}
+
+// Lists the token holders of a token-weighted proposal with their voter ids
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/electorate",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = TokenSnapshot),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_electorate(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let proposals = data.shared_map.read().await;
+    match proposals.get(&path.into_inner()) {
+        Some(proposal) => match &proposal.token_snapshot {
+            Some(snapshot) => HttpResponse::Ok().json(snapshot),
+            None => error_response(
+                ApiErrorCode::NotTokenWeighted,
+                "Proposal is not token-weighted",
+            ),
+        },
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Size of the chunks proof downloads are written in
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+// Body of a download, written a chunk at a time rather than in one piece
+struct ChunkedBody {
+    bytes: web::Bytes,
+}
+
+impl MessageBody for ChunkedBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.bytes.len() as u64)
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<web::Bytes, Self::Error>>> {
+        if self.bytes.is_empty() {
+            return Poll::Ready(None);
+        }
+        let len = self.bytes.len().min(DOWNLOAD_CHUNK_SIZE);
+        Poll::Ready(Some(Ok(self.bytes.split_to(len))))
+    }
+}
+
+// Downloads the proof envelope of a finalized proposal, for offline verification.
+// Envelopes can be several megabytes, so a single byte range can be asked for with
+// the Range header to resume an interrupted download. The ETag is the SHA-256 of
+// the whole envelope, which the Repr-Digest header carries as well for checking the
+// reassembled download, and a range is only served if If-Range names it.
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/proof",
+    params(
+        ("id" = Uuid, Path, description = "Proposal id"),
+        ("Range" = Option<String>, Header, description = "A single byte range, e.g. bytes=1048576-"),
+        ("If-Range" = Option<String>, Header, description = "ETag of the download being resumed")
+    ),
+    responses(
+        (status = 200, body = ProofEnvelope),
+        (status = 206, description = "The requested range of the serialized envelope"),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_proof(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> HttpResponse {
+    let header_str = |name: header::HeaderName| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    proof_download(
+        data,
+        path.into_inner(),
+        header_str(header::RANGE),
+        header_str(header::IF_RANGE),
+    )
+    .await
+}
+
+// Serves the whole proof envelope of a proposal, or the byte range asked for
+async fn proof_download(
+    data: web::Data<Arc<AppState>>,
+    id: Uuid,
+    range: Option<&str>,
+    if_range: Option<&str>,
+) -> HttpResponse {
+    let bytes = {
+        let proposals = data.shared_map.read().await;
+        match proposals.get(&id) {
+            Some(proposal) => match &proposal.proof {
+                Some(envelope) => web::Bytes::from(serde_json::to_vec(envelope).unwrap()),
+                None => {
+                    return error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized")
+                }
+            },
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        }
+    };
+    let digest = Sha256::digest(&bytes);
+    let etag = format!("\"{}\"", hex::encode(digest));
+    let total = bytes.len() as u64;
+    // A range of another envelope than the one the client started with would corrupt its download
+    let range = match range {
+        Some(range) if if_range.map_or(true, |if_range| if_range == etag) => {
+            ByteRange::parse(range, total)
+        }
+        _ => ByteRange::Full,
+    };
+    let mut response = match range {
+        ByteRange::Full => HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(ChunkedBody { bytes }),
+        ByteRange::Partial { start, end } => HttpResponse::PartialContent()
+            .content_type(ContentType::json())
+            .insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, total),
+            ))
+            .body(ChunkedBody {
+                bytes: bytes.slice(start as usize..=end as usize),
+            }),
+        ByteRange::Unsatisfiable => {
+            let mut response = error_response(
+                ApiErrorCode::RangeNotSatisfiable,
+                format!("The proof envelope is {} bytes", total),
+            );
+            response.headers_mut().insert(
+                header::CONTENT_RANGE,
+                header::HeaderValue::from_str(&format!("bytes */{}", total)).unwrap(),
+            );
+            response
+        }
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        header::ACCEPT_RANGES,
+        header::HeaderValue::from_static("bytes"),
+    );
+    headers.insert(header::ETAG, header::HeaderValue::from_str(&etag).unwrap());
+    headers.insert(
+        header::HeaderName::from_static("repr-digest"),
+        header::HeaderValue::from_str(&format!("sha-256=:{}:", base64::encode(digest))).unwrap(),
+    );
+    response
+}
+
+// Downloads a finalized proposal as a tar archive holding its statement, a commitment
+// to its electorate, every balance update, its certificate and proof, the verifier
+// data of its circuit and a manifest with the SHA-256 of each, so that it can be kept
+// and checked without the server
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/archive",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, description = "The archive of the proposal", content_type = "application/x-tar"),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_archive(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> HttpResponse {
+    let id = path.into_inner();
+    let circuit_id = {
+        let proposals = data.shared_map.read().await;
+        match proposals.get(&id) {
+            Some(proposal) => match &proposal.proof {
+                Some(envelope) => envelope.circuit_id.clone(),
+                None => {
+                    return error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized")
+                }
+            },
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        }
+    };
+    // Building the circuit can take a while the first time, so it is done off the store
+    let state = data.get_ref().clone();
+    let verifier = web::block(move || {
+        let circuit = state
+            .circuits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_build_finalization(&circuit_id)?;
+        VerifierFiles::new(&circuit_id, circuit.circuit_data())
+    })
+    .await;
+    // Proofs of circuits this server cannot build are archived without verifier data
+    let verifier = match verifier {
+        Ok(Ok(verifier)) => Some(verifier),
+        Ok(Err(err)) => {
+            warn!(proposal_id = %id, "archiving without verifier data: {:#}", err);
+            None
+        }
+        Err(err) => return error_response(ApiErrorCode::ArchiveFailed, err.to_string()),
+    };
+    let archive = {
+        let proposals = data.shared_map.read().await;
+        match proposals.get(&id) {
+            Some(proposal) => build_archive(id, proposal, verifier.as_ref(), unix_timestamp()),
+            None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+        }
+    };
+    match archive {
+        Ok(archive) => HttpResponse::Ok()
+            .content_type("application/x-tar")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"proposal-{}.tar\"", id),
+            ))
+            .body(archive),
+        Err(err) => error_response(ApiErrorCode::ArchiveFailed, format!("{:#}", err)),
+    }
+}
+
+// Lists the circuits built so far with the fingerprints of their verifier data, which
+// proof envelopes name, so verifiers can pick the key a proof was produced for
+#[utoipa::path(
+    get,
+    path = "/circuits",
+    responses((status = 200, body = [CircuitRecord]))
+)]
+async fn list_circuits(data: web::Data<Arc<AppState>>) -> impl Responder {
+    let records = data
+        .circuits
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .records();
+    HttpResponse::Ok().json(records)
+}
+
+// Serves the verifier data of a circuit in plonky2's binary serialization, for the standalone
+// verifier to check proofs of it without the server. Only circuits that a stored proof was
+// produced with are served, as building any other could take a while
+#[utoipa::path(
+    get,
+    path = "/circuits/{id}/verifier",
+    params(("id" = String, Path, description = "Circuit id, as proof envelopes name it")),
+    responses(
+        (status = 200, description = "The verifier data of the circuit, see qed_verifier::VerifierData", body = Object),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_circuit_verifier(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let circuit_id = path.into_inner();
+    let proven = data
+        .shared_map
+        .read()
+        .await
+        .iter()
+        .filter_map(|(_, proposal)| proposal.proof.as_ref())
+        .any(|envelope| envelope.circuit_id == circuit_id);
+    if !proven || parse_finalization_circuit_id(&circuit_id).is_err() {
+        return error_response(ApiErrorCode::UnknownCircuit, "Unknown circuit");
+    }
+    let state = data.get_ref().clone();
+    let verifier = web::block(move || {
+        let circuit = state
+            .circuits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_build_finalization(&circuit_id)?;
+        let circuit_data = circuit.circuit_data();
+        Ok::<_, anyhow::Error>(VerifierData {
+            fingerprint: qed_verifier::fingerprint(&circuit_data.verifier_only),
+            verifier_only: circuit_data
+                .verifier_only
+                .to_bytes()
+                .map_err(|_| anyhow::anyhow!("failed to serialize the verifier-only data"))?,
+            common: circuit_data
+                .common
+                .to_bytes(&DefaultGateSerializer)
+                .map_err(|_| anyhow::anyhow!("failed to serialize the common circuit data"))?,
+            circuit_id,
+        })
+    })
+    .await;
+    match verifier {
+        Ok(Ok(verifier)) => HttpResponse::Ok().json(verifier),
+        Ok(Err(err)) => error_response(ApiErrorCode::VerifierDataFailed, format!("{:#}", err)),
+        Err(err) => error_response(ApiErrorCode::VerifierDataFailed, err.to_string()),
+    }
+}
+
+// Periodically obtains trusted timestamps for the transcript and certificate of finalized proposals
+async fn timestamp_certificates(
+    data: Arc<AppState>,
+    authority: Arc<TimestampAuthority>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending = {
+            let proposals = data.shared_map.read().await;
+            let mut pending = vec![];
+            for (id, proposal) in proposals.iter() {
+                if let Some(certificate) = &proposal.certificate {
+                    let digests = [
+                        (TimestampSubject::Transcript, certificate.transcript_digest),
+                        (TimestampSubject::Certificate, certificate.digest()),
+                    ];
+                    for (subject, digest) in digests {
+                        let is_timestamped = certificate
+                            .timestamps
+                            .iter()
+                            .any(|record| record.subject == subject && record.digest == digest);
+                        if !is_timestamped {
+                            pending.push((*id, subject, digest));
+                        }
+                    }
+                }
+            }
+            pending
+        };
+        for (id, subject, digest) in pending {
+            let span = info_span!(
+                "timestamp_certificate",
+                proposal_id = %id,
+                request_id = traced_request(&data, &id).map(field::display),
+            );
+            let timestamped = authority
+                .timestamp(subject, digest)
+                .instrument(span.clone())
+                .await;
+            match timestamped {
+                Ok(record) => {
+                    let mut proposals = data.shared_map.write().await;
+                    if let Some(certificate) = proposals
+                        .get_mut(&id)
+                        .and_then(|proposal| proposal.certificate.as_mut())
+                    {
+                        certificate.timestamps.push(record);
+                    }
+                }
+                Err(err) => span.in_scope(|| error!("Failed to timestamp proposal: {}", err)),
+            }
+        }
+    }
+}
+// Issues a voter the proof of their registered weight against the electorate root. The proof
+// tells the leaf of the voter, so on blinded proposals only auditors are issued one when
+// the server enforces roles
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/membership/{voter_id}",
+    params(
+        ("id" = Uuid, Path, description = "Proposal id"),
+        ("voter_id" = u32, Path, description = "Voter id")
+    ),
+    responses(
+        (status = 200, body = MembershipProof),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_membership(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<(Uuid, u32)>,
+) -> HttpResponse {
+    let principal = req.extensions().get::<Principal>().cloned();
+    membership_response(data, path.into_inner(), principal).await
+}
+
+async fn membership_response(
+    data: web::Data<Arc<AppState>>,
+    (id, voter_id): (Uuid, u32),
+    principal: Option<Principal>,
+) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    match proposals.get(&id) {
+        Some(proposal) => {
+            if data.enforce_roles && proposal.blinding.is_some() {
+                if let Err(err) = check_role(principal.as_ref(), Role::Auditor) {
+                    return error_response(err.code, err.message);
+                }
+            }
+            let voter = match proposal.electorate_voter(voter_id) {
+                Ok(voter) => voter,
+                Err(err) => return error_response(err.code, err.message),
+            };
+            let proof = proposal.storage.initial_membership_proof(voter).unwrap();
+            let mut membership = MembershipProof::new(id, voter_id, proof).unwrap();
+            membership.blinded = proposal.blinding.is_some();
+            HttpResponse::Ok().json(membership)
+        }
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Reports the weight a voter casts by the delegations made on a proposal, next to the
+// balance of their leaf
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/voting-power/{voter_id}",
+    params(
+        ("id" = Uuid, Path, description = "Proposal id"),
+        ("voter_id" = u32, Path, description = "Voter id")
+    ),
+    responses(
+        (status = 200, body = VotingPower),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_voting_power(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<(Uuid, u32)>,
+) -> HttpResponse {
+    let (id, voter_id) = path.into_inner();
+    let proposals = data.shared_map.read().await;
+    match proposals
+        .get(&id)
+        .map(|proposal| proposal.voting_power(voter_id))
+    {
+        Some(Ok(power)) => HttpResponse::Ok().json(power),
+        Some(Err(err)) => error_response(err.code, err.message),
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Reveals to auditors the key placing the voters of a blinded proposal at their leaves,
+// which hashes to the commitment published with the proposal, and the leaf of every voter
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/blinding",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = BlindingReveal),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_blinding(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    match proposals.get(&path.into_inner()) {
+        Some(proposal) => match &proposal.blinding {
+            Some(blinding) => HttpResponse::Ok()
+                .json(blinding.reveal(proposal.storage.initial_balances().len() as u64)),
+            None => error_response(
+                ApiErrorCode::NotBlinded,
+                "Proposal does not blind voter indices",
+            ),
+        },
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Lists the encrypted ballots of a proposal, with their weighted sum, for its committee to
+// decrypt and anyone to check the decryption against
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/ballots",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = BallotBoxView),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_ballots(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    match proposals.get(&path.into_inner()) {
+        Some(proposal) => match &proposal.ballots {
+            Some(ballot_box) => HttpResponse::Ok().json(ballot_box.view()),
+            None => error_response(
+                ApiErrorCode::NotEncrypted,
+                "Proposal takes votes in the clear",
+            ),
+        },
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Records the decryption share of the aggregate ballot by a committee member once the
+// voting period ended. The share completing the threshold decrypts the yes votes and casts
+// the ballots into the balance tree, after which the proposal can be finalized
+#[utoipa::path(
+    post,
+    path = "/proposal/{id}/decrypt",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    request_body = DecryptionShare,
+    responses(
+        (status = 200, body = BallotBoxView),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn share_decryption(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    item: web::Json<DecryptionShare>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let item = item.into_inner();
+    let mut proposals = data.shared_map.write().await;
+    let event = ProposalEvent::DecryptionShared {
+        share: item.clone(),
+    };
+    if let Err(err) = accept_event(&data, &mut proposals, id, event) {
+        return error_response(err.code, err.message);
+    }
+    let proposal = proposals.get(&id).unwrap();
+    record_audit(
+        &data,
+        id,
+        proposal,
+        AuditAction::Decrypt,
+        item.member_index,
+        &(id, &item),
+    );
+    HttpResponse::Ok().json(proposal.ballots.as_ref().unwrap().view())
+}
+
+// Proves a leaf of the balance tree of a proposal, for light clients checking a single
+// balance, under the current root or under the root after any number of recorded updates
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/leaf/{index}/proof",
+    params(
+        ("id" = Uuid, Path, description = "Proposal id"),
+        ("index" = u64, Path, description = "Leaf index, of a voter or a tally slot"),
+        LeafProofQuery
+    ),
+    responses(
+        (status = 200, body = LeafProofResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_leaf_proof(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<(Uuid, u64)>,
+    query: web::Query<LeafProofQuery>,
+) -> impl Responder {
+    let (id, index) = path.into_inner();
+    let proposals = data.shared_map.read().await;
+    let proposal = match proposals.get(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    let max_leaves = proposal.storage.tree.max_leaves();
+    if index >= max_leaves {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            format!("Leaf {} is outside a tree of {} leaves", index, max_leaves),
+        );
+    }
+    let at_root = query.at_root.unwrap_or(proposal.updates.len());
+    if at_root > proposal.updates.len() {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            format!(
+                "The proposal has {} recorded updates, not {}",
+                proposal.updates.len(),
+                at_root
+            ),
+        );
+    }
+    let updates = &proposal.updates[..at_root];
+    // Past roots, and any root of a compacted tree asked for explicitly, are proven from a
+    // copy of the tree replayed from the recorded updates
+    let replay = at_root < proposal.updates.len()
+        || (proposal.storage.is_compacted() && query.at_root.is_some());
+    if !replay && proposal.storage.is_compacted() && index > TallySlot::YES.index() {
+        return error_response(
+            ApiErrorCode::TreeCompacted,
+            "The balance tree of the proposal was compacted, pass at_root to prove other leaves",
+        );
+    }
+    let proof = if replay {
+        let replayed = proposal.storage.replayed(updates).unwrap();
+        replayed.tree.get_leaf(index)
+    } else {
+        proposal.storage.tree.get_leaf(index)
+    };
+    // Receiver updates are applied after sender updates, so they are checked first
+    let last_update = updates
+        .iter()
+        .rev()
+        .flat_map(|update| [&update.receiver_update, &update.sender_update])
+        .find(|update| update.index.to_canonical_u64() == index)
+        .map(|update| LeafDeltaProof::from(update.clone()));
+    HttpResponse::Ok().json(LeafProofResponse {
+        proposal_id: id,
+        at_root,
+        proof: LeafProof::from(proof.unwrap()),
+        last_update,
+    })
+}
+
+// The root of the tree committing to the result of every finalized proposal, see
+// `proof::results`, with the last of its roots anchored on-chain
+#[utoipa::path(
+    get,
+    path = "/results/root",
+    responses(
+        (status = 200, body = ResultRootResponse),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn get_result_root(data: web::Data<Arc<AppState>>) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    let mut results = data.results.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(err) = results.sync(&proposals) {
+        return error_response(ApiErrorCode::ResultTreeFailed, err);
+    }
+    HttpResponse::Ok().json(ResultRootResponse {
+        root: results.root(),
+        size: results.len() as u64,
+        last_anchor: results.last_anchor().cloned(),
+    })
+}
+
+// Proves the result of a finalized proposal against the current root of the result tree
+#[utoipa::path(
+    get,
+    path = "/results/{id}/proof",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = ResultInclusionProof),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn get_result_proof(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> HttpResponse {
+    let id = path.into_inner();
+    let proposals = data.shared_map.read().await;
+    if proposals.get(&id).is_none() {
+        return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found");
+    }
+    let mut results = data.results.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(err) = results.sync(&proposals) {
+        return error_response(ApiErrorCode::ResultTreeFailed, err);
+    }
+    match results.prove(&id) {
+        Ok(Some(proof)) => HttpResponse::Ok().json(proof),
+        Ok(None) => error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized"),
+        Err(err) => error_response(ApiErrorCode::ResultTreeFailed, err),
+    }
+}
+
+// Lists the leaves votes changed between the initial and the current root, each proven under both
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/diff",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = TreeDiffResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_tree_diff(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let id = path.into_inner();
+    let proposals = data.shared_map.read().await;
+    let proposal = match proposals.get(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if proposal.storage.is_compacted() {
+        return error_response(
+            ApiErrorCode::TreeCompacted,
+            "The balance tree of the proposal was compacted, its voter leaves are gone",
+        );
+    }
+    HttpResponse::Ok().json(TreeDiffResponse {
+        proposal_id: id,
+        initial_root: proposal.storage.initial_root(),
+        final_root: proposal.storage.tree.get_root().unwrap(),
+        changes: diff_leaves(&proposal.storage, &proposal.updates).unwrap(),
+    })
+}
+
+// Lists the recorded mutations of a proposal, oldest first
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/audit",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = Vec<AuditEntry>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_audit(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let id = path.into_inner();
+    if data.shared_map.read().await.get(&id).is_none() {
+        return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found");
+    }
+    let audit = data.audit.lock().unwrap_or_else(PoisonError::into_inner);
+    HttpResponse::Ok().json(audit.entries(&id))
+}
+
+// Exports the actions of a finalized proposal for replaying it, e.g. with qed-simulate
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/transcript",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = Transcript),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_transcript(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let proposals = data.shared_map.read().await;
+    let id = path.into_inner();
+    match proposals.get(&id) {
+        // Individual votes are only revealed along with the tally
+        Some(proposal) if proposal.is_finalized() => {
+            HttpResponse::Ok().json(Transcript::from_proposal(id, proposal))
+        }
+        Some(_) => error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized"),
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Replays the events of a proposal accepted up to a time into memory, giving the state
+// the proposal was in at that time
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/history",
+    params(("id" = Uuid, Path, description = "Proposal id"), ProposalHistoryQuery),
+    responses(
+        (status = 200, body = ProposalHistoryResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn get_history(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    query: web::Query<ProposalHistoryQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let at = query.at.unwrap_or_else(unix_timestamp);
+    let records = data
+        .events
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .proposal_events(&id);
+    if records.is_empty() {
+        return error_response(ApiErrorCode::ProposalNotFound, "No events of the proposal");
+    }
+    let events_applied = records.iter().take_while(|record| record.at <= at).count();
+    let replayed = match replay(&records[..events_applied], |namespace| {
+        NodeStoreBackend::Memory.open_store(namespace)
+    }) {
+        Ok(replayed) => replayed,
+        Err(err) => return error_response(ApiErrorCode::EventReplayFailed, err),
+    };
+    let proposal = match replayed.get(&id) {
+        Some(proposal) => proposal,
+        None => {
+            return error_response(
+                ApiErrorCode::ProposalNotFound,
+                format!("Proposal did not exist at {}", at),
+            )
+        }
+    };
+    HttpResponse::Ok().json(ProposalHistoryResponse {
+        proposal_id: id,
+        at,
+        events_applied,
+        status: proposal.status,
+        statement: proposal.statement.clone(),
+        tally: proposal.storage.tally().unwrap(),
+        balance_root: proposal.storage.tree.get_root().unwrap(),
+    })
+}
+
+// Charts the tally of a proposal over time, from the point recorded after each action
+// that changed it
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/tally/history",
+    params(("id" = Uuid, Path, description = "Proposal id"), TallyHistoryQuery),
+    responses(
+        (status = 200, body = TallyHistoryResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_tally_history(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<Uuid>,
+    query: web::Query<TallyHistoryQuery>,
+) -> impl Responder {
+    let proposals = data.shared_map.read().await;
+    let id = path.into_inner();
+    match proposals.get(&id) {
+        Some(proposal) => HttpResponse::Ok().json(TallyHistoryResponse {
+            proposal_id: id,
+            created_at: proposal.created_at,
+            points: downsample(
+                &proposal.tally_history,
+                proposal.created_at,
+                query.bucket_secs,
+            ),
+        }),
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Reports the storage used by a DAO along with the quotas it is held to
+#[utoipa::path(
+    get,
+    path = "/dao/{id}/usage",
+    params(("id" = String, Path, description = "DAO id")),
+    responses((status = 200, body = DaoUsageResponse))
+)]
+async fn get_dao_usage(data: web::Data<Arc<AppState>>, path: web::Path<String>) -> impl Responder {
+    let usage = data.shared_map.read().await.dao_usage(&path.into_inner());
+    HttpResponse::Ok().json(DaoUsageResponse {
+        usage,
+        quotas: data.quotas.clone(),
+    })
+}
+
+// Reports the last check of the trees of open proposals against their updates
+#[utoipa::path(
+    get,
+    path = "/health/tree",
+    responses((status = 200, body = TreeHealthResponse))
+)]
+async fn get_tree_health(data: web::Data<Arc<AppState>>) -> impl Responder {
+    let health = data
+        .tree_health
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    HttpResponse::Ok().json(health)
+}
+
+// Describes the deposit locked for a proposal, with the proof of where it went once settled
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/deposit",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = DepositReceipt),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_deposit(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let proposals = data.shared_map.read().await;
+    let id = path.into_inner();
+    match proposals.get(&id) {
+        Some(proposal) => match &proposal.deposit {
+            Some(deposit) => HttpResponse::Ok().json(DepositReceipt {
+                proposal_id: id,
+                amount: deposit.amount,
+                status: deposit.status,
+                escrow_index: deposit.escrow_index,
+                settlement_proof: deposit.settlement_proof.clone(),
+            }),
+            None => error_response(ApiErrorCode::NoDeposit, "Proposal has no deposit"),
+        },
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Reports the treasury account a proposer locks deposits from
+#[utoipa::path(
+    get,
+    path = "/treasury/{proposer_id}",
+    params(("proposer_id" = u32, Path, description = "Proposer id")),
+    responses((status = 200, body = TreasuryAccount))
+)]
+async fn get_treasury_account(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<u32>,
+) -> impl Responder {
+    let proposer_id = path.into_inner();
+    let balance = data
+        .treasury
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .account_balance(proposer_id);
+    HttpResponse::Ok().json(TreasuryAccount {
+        proposer_id,
+        balance,
+    })
+}
+
+// Credits the treasury account of a proposer, e.g. once the operator has been paid for it
+#[utoipa::path(
+    post,
+    path = "/admin/treasury/credit",
+    request_body = TreasuryCreditQuery,
+    responses(
+        (status = 200, body = TreasuryAccount),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn credit_treasury(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<TreasuryCreditQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let credited = data
+        .treasury
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .credit(item.proposer_id, item.amount);
+    match credited {
+        Ok(balance) => HttpResponse::Ok().json(TreasuryAccount {
+            proposer_id: item.proposer_id,
+            balance,
+        }),
+        Err(err) => error_response(ApiErrorCode::InvalidQuery, err),
+    }
+}
+
+// Reports the funds a DAO holds, one per token
+#[utoipa::path(
+    get,
+    path = "/dao/{id}/funds",
+    params(("id" = String, Path, description = "DAO id")),
+    responses((status = 200, body = [FundBalance]))
+)]
+async fn get_dao_funds(data: web::Data<Arc<AppState>>, path: web::Path<String>) -> impl Responder {
+    let funds = data
+        .funds
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .dao_funds(&path.into_inner());
+    HttpResponse::Ok().json(funds)
+}
+
+// Lists the payouts out of the funds of a DAO, oldest first, with their proofs once made
+#[utoipa::path(
+    get,
+    path = "/dao/{id}/payouts",
+    params(("id" = String, Path, description = "DAO id")),
+    responses((status = 200, body = [PayoutReceipt]))
+)]
+async fn get_dao_payouts(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let payouts: Vec<PayoutReceipt> = data
+        .funds
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .dao_payouts(&path.into_inner())
+        .into_iter()
+        .map(PayoutReceipt::from)
+        .collect();
+    HttpResponse::Ok().json(payouts)
+}
+
+// Credits the fund of a DAO in a token, e.g. once the DAO deposited it with the operator
+#[utoipa::path(
+    post,
+    path = "/admin/funds/credit",
+    request_body = FundsCreditQuery,
+    responses(
+        (status = 200, body = FundBalance),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn credit_funds(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<FundsCreditQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let credited = data
+        .funds
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .credit(&item.dao_id, item.token, item.amount);
+    match credited {
+        Ok(fund) => HttpResponse::Ok().json(fund),
+        Err(err) => error_response(ApiErrorCode::InvalidQuery, err),
+    }
+}
+
+// Pays out a passed treasury transfer the funds of its DAO did not cover when it was finalized
+#[utoipa::path(
+    post,
+    path = "/admin/proposal/{id}/payout",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = PayoutReceipt),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn pay_out_proposal(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let proposals = data.shared_map.read().await;
+    let id = path.into_inner();
+    let proposal = match proposals.get(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    let receipt = match pay_out(&data, id, proposal) {
+        Ok(payout) => PayoutReceipt::from(payout),
+        Err(err) => return error_response(err.code, err.message),
+    };
+    record_audit(
+        &data,
+        id,
+        proposal,
+        AuditAction::Payout,
+        proposal.proposer_id,
+        &receipt,
+    );
+    HttpResponse::Ok().json(receipt)
+}
+
+// Reports the tokens a voter holds in the token tree, free and locked, with every lock
+#[utoipa::path(
+    get,
+    path = "/tokens/{voter_id}",
+    params(("voter_id" = u32, Path, description = "Voter id")),
+    responses((status = 200, body = TokenAccount))
+)]
+async fn get_token_account(data: web::Data<Arc<AppState>>, path: web::Path<u32>) -> impl Responder {
+    let voter_id = path.into_inner();
+    let token_locks = data
+        .token_locks
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    HttpResponse::Ok().json(TokenAccount {
+        voter_id,
+        free: token_locks.free_balance(voter_id),
+        locked: token_locks.locked_balance(voter_id),
+        locks: token_locks
+            .voter_locks(voter_id)
+            .into_iter()
+            .map(TokenLockReceipt::from)
+            .collect(),
+    })
+}
+
+// Credits the tokens of a voter, e.g. once they bridged them to the operator. Proposals
+// created afterwards seed the voter with them
+#[utoipa::path(
+    post,
+    path = "/admin/tokens/credit",
+    request_body = TokenCreditQuery,
+    responses(
+        (status = 200, body = TokenAccount),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn credit_tokens(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<TokenCreditQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let mut token_locks = data
+        .token_locks
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    match token_locks.credit(item.voter_id, item.amount) {
+        Ok(free) => HttpResponse::Ok().json(TokenAccount {
+            voter_id: item.voter_id,
+            free,
+            locked: token_locks.locked_balance(item.voter_id),
+            locks: token_locks
+                .voter_locks(item.voter_id)
+                .into_iter()
+                .map(TokenLockReceipt::from)
+                .collect(),
+        }),
+        Err(err) => error_response(ApiErrorCode::InvalidQuery, err),
+    }
+}
+
+// Cancels a proposal for spam, whether or not it has votes, and slashes its deposit
+#[utoipa::path(
+    post,
+    path = "/admin/proposal/{id}/slash",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn slash(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let mut proposals = data.shared_map.write().await;
+    let id = path.into_inner();
+    let proposal = match proposals.get_mut(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    if let Some(response) = closed_response(proposal) {
+        return response;
+    }
+    let slashed = match &mut proposal.deposit {
+        Some(deposit) => {
+            let mut treasury = data.treasury.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(err) = treasury.slash(deposit) {
+                return error_response(
+                    ApiErrorCode::InvalidQuery,
+                    format!("Failed to slash the deposit: {}", err),
+                );
+            }
+            deposit.amount
+        }
+        None => 0,
+    };
+    let proposer_id = proposal.proposer_id;
+    if let Err(err) = accept_event(&data, &mut proposals, id, ProposalEvent::Cancelled) {
+        return error_response(err.code, err.message);
+    }
+    release_tokens(&data, id, proposals.get(&id).unwrap());
+    record_audit(
+        &data,
+        id,
+        proposals.get(&id).unwrap(),
+        AuditAction::Slash,
+        proposer_id,
+        &id,
+    );
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: id,
+        message: format!(
+            "Cancelled proposal {} for spam, slashing a deposit of {}",
+            id, slashed
+        ),
+    })
+}
+
+// Creates an organization, whose admins then manage it with their own token
+#[utoipa::path(
+    post,
+    path = "/admin/org",
+    request_body = CreateOrganizationQuery,
+    responses(
+        (status = 200, body = OrganizationView),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn create_organization(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<CreateOrganizationQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    if item.name.trim().is_empty() || item.admin_token.is_empty() {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "Organizations need a name and an admin token",
+        );
+    }
+    let item = item.into_inner();
+    let org = Organization::new(item.id, item.name, &item.admin_token, unix_timestamp());
+    let view = org.view();
+    let created = data
+        .orgs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .create(org);
+    match created {
+        Ok(()) => HttpResponse::Ok().json(view),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Deletes a spam proposal outright, slashing its deposit if it is still locked. Finalized
+// proposals and proposals others depend on are kept, since their results are relied on.
+#[utoipa::path(
+    delete,
+    path = "/admin/proposal/{id}",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn delete_proposal(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let mut proposals = data.shared_map.write().await;
+    let id = path.into_inner();
+    let dependent = proposals
+        .iter()
+        .find(|(_, proposal)| proposal.depends_on.contains(&id))
+        .map(|(dependent, _)| *dependent);
+    let proposal = match proposals.get_mut(&id) {
+        Some(proposal) => proposal,
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    match proposal.status {
+        ProposalStatus::Finalizing => {
+            return error_response(
+                ApiErrorCode::ProposalFinalizing,
+                "The proposal is being finalized",
+            )
+        }
+        ProposalStatus::Finalized => {
+            return error_response(
+                ApiErrorCode::ProposalFinalized,
+                "Finalized proposals cannot be deleted",
+            )
+        }
+        _ => {}
+    }
+    if let Some(dependent) = dependent {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            format!("Proposal {} depends on the proposal", dependent),
+        );
+    }
+    let mut slashed = 0;
+    if let Some(deposit) = &mut proposal.deposit {
+        if deposit.status == DepositStatus::Locked {
+            let mut treasury = data.treasury.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(err) = treasury.slash(deposit) {
+                return error_response(
+                    ApiErrorCode::InvalidQuery,
+                    format!("Failed to slash the deposit: {}", err),
+                );
+            }
+            slashed = deposit.amount;
+        }
+    }
+    release_tokens(&data, id, proposal);
+    let proposer_id = proposal.proposer_id;
+    // The audit log keeps the history of the proposal, ending with its deletion
+    record_audit(
+        &data,
+        id,
+        proposals.get(&id).unwrap(),
+        AuditAction::Delete,
+        proposer_id,
+        &id,
+    );
+    if let Err(err) = accept_event(&data, &mut proposals, id, ProposalEvent::Deleted) {
+        return error_response(err.code, err.message);
+    }
+    HttpResponse::Ok().json(ActionResponse {
+        proposal_id: id,
+        message: format!(
+            "Deleted proposal {} for spam, slashing a deposit of {}",
+            id, slashed
+        ),
+    })
+}
+
+// Pauses or resumes voting on every proposal, e.g. while an incident is investigated.
+// Voting periods keep running while paused.
+#[utoipa::path(
+    post,
+    path = "/admin/voting",
+    request_body = VotingPauseQuery,
+    responses(
+        (status = 200, body = VotingPauseQuery),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn set_voting_paused(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<VotingPauseQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    data.voting_paused.store(item.paused, Ordering::SeqCst);
+    warn!(paused = item.paused, "Voting pause changed by an admin");
+    HttpResponse::Ok().json(item.into_inner())
+}
+
+// Reports whether the server is paused, and why
+#[utoipa::path(
+    get,
+    path = "/admin/pause",
+    responses(
+        (status = 200, body = PauseState),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_pause_state(data: web::Data<Arc<AppState>>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    HttpResponse::Ok().json(data.pause.state())
+}
+
+// Pauses the server for incident response: every mutating request is rejected until an
+// admin unpauses it, while reads and proof downloads are served. The pause survives
+// restarts when the server keeps it in a file
+#[utoipa::path(
+    post,
+    path = "/admin/pause",
+    request_body = PauseQuery,
+    responses(
+        (status = 200, body = PauseState),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = 500, description = "The pause could not be persisted", body = ApiError)
+    )
+)]
+async fn pause_server(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<PauseQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    set_pause(&data, true, item.into_inner().reason)
+}
+
+// Lifts the pause of the server
+#[utoipa::path(
+    post,
+    path = "/admin/unpause",
+    responses(
+        (status = 200, body = PauseState),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = 500, description = "The pause could not be persisted", body = ApiError)
+    )
+)]
+async fn unpause_server(data: web::Data<Arc<AppState>>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    set_pause(&data, false, None)
+}
+
+// Pauses or unpauses the server once the new state is persisted
+fn set_pause(data: &AppState, paused: bool, reason: Option<String>) -> HttpResponse {
+    match data.pause.set(paused, reason, unix_timestamp()) {
+        Ok(state) => {
+            warn!(paused, reason = ?state.reason, "Server pause changed by an admin");
+            HttpResponse::Ok().json(state)
+        }
+        Err(err) => error_response(
+            ApiErrorCode::PauseNotPersisted,
+            format!("Failed to persist the pause: {}", err),
+        ),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/keys",
+    responses(
+        (status = 200, body = Vec<ApiKeyView>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn list_keys(data: web::Data<Arc<AppState>>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let keys = data
+        .api_keys
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .list();
+    HttpResponse::Ok().json(keys)
+}
+
+// Lists the callbacks to the webhooks of organizations in the order they were queued, with
+// how their attempts went
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    params(WebhookDeliveriesQuery),
+    responses(
+        (status = 200, body = Vec<WebhookDelivery>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn list_webhook_deliveries(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    query: web::Query<WebhookDeliveriesQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let deliveries = data
+        .webhooks
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .list(query.org_id.as_deref(), query.status);
+    HttpResponse::Ok().json(deliveries)
+}
+
+// Issues an API key with a role, answering with its secret this once. Keys live in memory
+// and have to be issued again after a restart.
+#[utoipa::path(
+    post,
+    path = "/admin/keys",
+    request_body = IssueKeyQuery,
+    responses(
+        (status = 200, body = IssuedKeyResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn issue_key(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<IssueKeyQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let item = item.into_inner();
+    let mut keys = data.api_keys.lock().unwrap_or_else(PoisonError::into_inner);
+    match keys.issue(
+        item.id.clone(),
+        item.role,
+        item.public_key.as_deref(),
+        unix_timestamp(),
+    ) {
+        Ok(secret) => HttpResponse::Ok().json(IssuedKeyResponse {
+            key: keys.view(&item.id).unwrap(),
+            secret,
+        }),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Replaces the secret or signing key of an API key, which stops the old one working at once
+#[utoipa::path(
+    post,
+    path = "/admin/keys/{key_id}/rotate",
+    params(("key_id" = String, Path, description = "API key id")),
+    request_body = RotateKeyQuery,
+    responses(
+        (status = 200, body = IssuedKeyResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn rotate_key(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    item: web::Json<RotateKeyQuery>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let key_id = path.into_inner();
+    let mut keys = data.api_keys.lock().unwrap_or_else(PoisonError::into_inner);
+    match keys.rotate(&key_id, item.public_key.as_deref(), unix_timestamp()) {
+        Ok(secret) => HttpResponse::Ok().json(IssuedKeyResponse {
+            key: keys.view(&key_id).unwrap(),
+            secret,
+        }),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/keys/{key_id}",
+    params(("key_id" = String, Path, description = "API key id")),
+    responses(
+        (status = 200, body = ApiKeyView),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn revoke_key(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let key_id = path.into_inner();
+    let mut keys = data.api_keys.lock().unwrap_or_else(PoisonError::into_inner);
+    let view = keys.view(&key_id);
+    match keys.revoke(&key_id) {
+        Ok(()) => HttpResponse::Ok().json(view),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/org/{org_id}",
+    params(("org_id" = String, Path, description = "Organization id")),
+    responses(
+        (status = 200, body = OrganizationView),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_organization(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+    match orgs.get(&path.into_inner()) {
+        Ok(org) => HttpResponse::Ok().json(org.view()),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Requests the registration of a voter in an organization, to be approved by its admins
+#[utoipa::path(
+    post,
+    path = "/org/{org_id}/register",
+    params(("org_id" = String, Path, description = "Organization id")),
+    request_body = RegisterQuery,
+    responses(
+        (status = 200, description = "The registration awaits a decision", body = VoterRegistration),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn register_voter(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    item: web::Json<RegisterQuery>,
+) -> impl Responder {
+    let item = item.into_inner();
+    let mut orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+    match orgs.register(
+        &path.into_inner(),
+        item.did,
+        &item.did_signature,
+        unix_timestamp(),
+    ) {
+        Ok(registration) => HttpResponse::Ok().json(registration),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Lists the registrations of an organization in the order they were requested
+#[utoipa::path(
+    get,
+    path = "/org/{org_id}/voters",
+    params(("org_id" = String, Path, description = "Organization id"), VotersQuery),
+    responses(
+        (status = 200, body = Vec<VoterRegistration>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn list_voters(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<VotersQuery>,
+) -> impl Responder {
+    let orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+    match orgs.registrations(&path.into_inner(), query.status) {
+        Ok(registrations) => HttpResponse::Ok().json(registrations),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+fn decide_registration(
+    data: &AppState,
+    req: &HttpRequest,
+    (org_id, did): (String, String),
+    approve: bool,
+) -> HttpResponse {
+    let did = match did.parse::<Did>() {
+        Ok(did) => did,
+        Err(err) => return error_response(ApiErrorCode::InvalidDid, err.to_string()),
+    };
+    let mut orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+    match orgs.decide(&org_id, bearer_token(req), &did, approve, unix_timestamp()) {
+        Ok(registration) => HttpResponse::Ok().json(registration),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Approves a pending registration, assigning the voter the next free voter leaf
+#[utoipa::path(
+    post,
+    path = "/org/{org_id}/voters/{did}/approve",
+    params(
+        ("org_id" = String, Path, description = "Organization id"),
+        ("did" = String, Path, description = "DID of the voter")
+    ),
+    responses(
+        (status = 200, body = VoterRegistration),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn approve_voter(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    decide_registration(&data, &req, path.into_inner(), true)
+}
+
+// Rejects a pending registration; the DID cannot register again
+#[utoipa::path(
+    post,
+    path = "/org/{org_id}/voters/{did}/reject",
+    params(
+        ("org_id" = String, Path, description = "Organization id"),
+        ("did" = String, Path, description = "DID of the voter")
+    ),
+    responses(
+        (status = 200, body = VoterRegistration),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn reject_voter(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    decide_registration(&data, &req, path.into_inner(), false)
+}
+
+// Replaces the webhooks the results of the proposals of an organization are posted to,
+// answering with their URLs
+#[utoipa::path(
+    post,
+    path = "/org/{org_id}/webhooks",
+    params(("org_id" = String, Path, description = "Organization id")),
+    request_body = WebhooksQuery,
+    responses(
+        (status = 200, description = "URLs of the webhooks", body = Vec<String>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn set_webhooks(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    item: web::Json<WebhooksQuery>,
+) -> impl Responder {
+    let item = item.into_inner();
+    let urls: Vec<String> = item
+        .webhooks
+        .iter()
+        .map(|webhook| webhook.url.clone())
+        .collect();
+    let mut orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+    match orgs.set_webhooks(&path.into_inner(), bearer_token(&req), item.webhooks) {
+        Ok(()) => HttpResponse::Ok().json(urls),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Sets or clears the quorum, voting period and finalizers the proposals an organization
+// files under a category get unless they set their own, answering with all its policies
+#[utoipa::path(
+    post,
+    path = "/org/{org_id}/categories",
+    params(("org_id" = String, Path, description = "Organization id")),
+    request_body = CategoryPolicyQuery,
+    responses(
+        (status = 200, description = "Policies of the organization by category", body = Object),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn set_category_policy(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    item: web::Json<CategoryPolicyQuery>,
+) -> impl Responder {
+    let org_id = path.into_inner();
+    let item = item.into_inner();
+    let mut orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+    let set = orgs.set_category_policy(&org_id, bearer_token(&req), &item.category, item.policy);
+    match set.and_then(|()| orgs.get(&org_id)) {
+        Ok(org) => HttpResponse::Ok().json(&org.category_policies),
+        Err(err) => error_response(err.code, err.message),
+    }
+}
+
+// Creates a proposal in the DAO of an organization, its electorate being the approved
+// voters of the organization at their assigned leaves. The policy of the organization
+// for the category of the proposal fills in the rules it leaves unset
+#[utoipa::path(
+    post,
+    path = "/org/{org_id}/propose",
+    params(("org_id" = String, Path, description = "Organization id")),
+    request_body = ProposeQuery,
+    responses(
+        (status = 200, description = "The proposal was created", body = ActionResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn org_propose(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    item: web::Json<ProposeQuery>,
+) -> HttpResponse {
+    let org_id = path.into_inner();
+    let mut item = item.into_inner();
+    if item
+        .dao_id
+        .as_ref()
+        .map_or(false, |dao_id| *dao_id != org_id)
+    {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "Proposals of an organization are created in its own DAO",
+        );
+    }
+    {
+        let orgs = data.orgs.lock().unwrap_or_else(PoisonError::into_inner);
+        let org = match orgs.get(&org_id) {
+            Ok(org) => org,
+            Err(err) => return error_response(err.code, err.message),
+        };
+        if item.voter_dids.is_some()
+            || item.token_snapshot.is_some()
+            || item.electorate_size.is_some()
+        {
+            return error_response(
+                ApiErrorCode::InvalidQuery,
+                "Proposals of an organization are voted on by its registered voters",
+            );
+        }
+        let roster = org.roster();
+        if roster.is_empty() {
+            return error_response(
+                ApiErrorCode::NoRegisteredVoters,
+                format!("Organization {} has no approved voters", org_id),
+            );
+        }
+        item.voter_dids = Some(roster);
+        let policy = item
+            .category
+            .as_ref()
+            .and_then(|category| org.category_policies.get(category))
+            .cloned();
+        if let Some(policy) = policy {
+            policy.apply(&mut item);
+        }
+    }
+    item.dao_id = Some(org_id);
+    create_proposal(data, web::Json(item)).await
+}
+
+// Lists the proposals of an organization, with the filters of GET /
+#[utoipa::path(
+    get,
+    path = "/org/{org_id}/proposals",
+    params(
+        ("org_id" = String, Path, description = "Organization id"),
+        ProposalQuery,
+        ("X-Voter-Id" = Option<u32>, Header, description = "Voter to describe the caller view for")
+    ),
+    responses(
+        (status = 200, description = "A page of proposals", body = Vec<ProposalView>),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn list_org_proposals(
+    data: web::Data<Arc<AppState>>,
+    path: web::Path<String>,
+    query: web::Query<ProposalQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let org_id = path.into_inner();
+    if let Err(err) = data
+        .orgs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&org_id)
+    {
+        return error_response(err.code, err.message);
+    }
+    let mut query = query.into_inner();
+    query.dao_id = Some(org_id);
+    list_proposals(data, web::Query(query), req).await
+}
+
+// Exports every proposal with its trees, the audit log and the cached circuits, for
+// moving the server or recovering it from a backup
+#[utoipa::path(
+    get,
+    path = "/admin/snapshot",
+    responses(
+        (status = 200, description = "Versioned archive of the server state, restored by POST /admin/restore", body = Object),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_snapshot(data: web::Data<Arc<AppState>>, req: HttpRequest) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let proposals = data.shared_map.read().await;
+    let mut archived = Vec::with_capacity(proposals.len());
+    for (id, proposal) in proposals.iter() {
+        match ProposalSnapshot::capture(*id, proposal) {
+            Ok(snapshot) => archived.push(snapshot),
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::NodeStoreUnavailable,
+                    format!("Failed to read the trees of proposal {}: {}", id, err),
+                )
+            }
+        }
+    }
+    archived.sort_by_key(|snapshot| (snapshot.created_at, snapshot.id));
+    // Read while holding the proposals, so the log ends with the last archived mutation
+    let audit = data
+        .audit
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .all_entries();
+    let treasury = match data
+        .treasury
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .snapshot()
+    {
+        Ok(treasury) => treasury,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::NodeStoreUnavailable,
+                format!("Failed to read the treasury: {}", err),
+            )
+        }
+    };
+    let funds = match data
+        .funds
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .snapshot()
+    {
+        Ok(funds) => funds,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::NodeStoreUnavailable,
+                format!("Failed to read the funds: {}", err),
+            )
+        }
+    };
+    let token_locks = match data
+        .token_locks
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .snapshot()
+    {
+        Ok(token_locks) => token_locks,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::NodeStoreUnavailable,
+                format!("Failed to read the token tree: {}", err),
+            )
+        }
+    };
+    let events = data
+        .events
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .records()
+        .to_vec();
+    drop(proposals);
+    let circuit_ids = data
+        .circuits
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .shapes()
+        .iter()
+        .map(update_balance_circuit_id)
+        .collect();
+    HttpResponse::Ok().json(StateSnapshot {
+        version: SNAPSHOT_VERSION,
+        taken_at: unix_timestamp(),
+        proposals: archived,
+        audit,
+        circuit_ids,
+        treasury: Some(treasury),
+        funds: Some(funds),
+        token_locks: Some(token_locks),
+        organizations: data
+            .orgs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .snapshot(),
+        events,
+    })
+}
+
+// Restores a snapshot into a server without proposals, rebuilding every tree and checking
+// it against its recorded root before anything is stored
+#[utoipa::path(
+    post,
+    path = "/admin/restore",
+    request_body(content = Object, description = "Archive served by GET /admin/snapshot"),
+    responses(
+        (status = 200, body = RestoreResponse),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn restore(
+    data: web::Data<Arc<AppState>>,
+    req: HttpRequest,
+    item: web::Json<StateSnapshot>,
+) -> impl Responder {
+    if let Some(response) = admin_response(&data, &req) {
+        return response;
+    }
+    let snapshot = item.into_inner();
+    if let Err(err) = snapshot.check_version() {
+        return error_response(ApiErrorCode::SnapshotRejected, err);
+    }
+    let mut proposals = data.shared_map.write().await;
+    if !proposals.is_empty() {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "Snapshots can only be restored into a server without proposals",
+        );
+    }
+    // Servers keeping an event log rebuild their proposals from it, so the events the
+    // proposals were built by are restored along with them
+    let events = data.events.lock().unwrap_or_else(PoisonError::into_inner);
+    if !events.is_empty() {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "Snapshots can only be restored into a server whose event log is empty",
+        );
+    }
+    if events.is_persistent() && snapshot.events.is_empty() && !snapshot.proposals.is_empty() {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "The snapshot carries no events, which servers keeping an event log need",
+        );
+    }
+    drop(events);
+    if !snapshot.events.is_empty() {
+        if let Err(err) = snapshot.check_events() {
+            return error_response(ApiErrorCode::SnapshotRejected, err);
+        }
+    }
+    let mut restored = Vec::with_capacity(snapshot.proposals.len());
+    for archived in snapshot.proposals {
+        let id = archived.id;
+        let balance_store = data.node_stores.open_store(&format!("balances/{}", id));
+        let nullifier_store = archived
+            .nullifier_tree
+            .map(|_| data.node_stores.open_store(&format!("nullifiers/{}", id)))
+            .transpose();
+        let (balance_store, nullifier_store) = match (balance_store, nullifier_store) {
+            (Ok(balance_store), Ok(nullifier_store)) => (balance_store, nullifier_store),
+            (Err(err), _) | (_, Err(err)) => {
+                return error_response(
+                    ApiErrorCode::NodeStoreUnavailable,
+                    format!("Failed to open node store: {}", err),
+                )
+            }
+        };
+        match archived.restore(balance_store, nullifier_store) {
+            Ok(proposal) => restored.push((id, proposal)),
+            Err(err) => {
+                return error_response(
+                    ApiErrorCode::SnapshotRejected,
+                    format!("Failed to restore proposal {}: {}", id, err),
+                )
+            }
+        }
+    }
+    let treasury = match snapshot.treasury.map(Treasury::restore).transpose() {
+        Ok(treasury) => treasury,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::SnapshotRejected,
+                format!("Failed to restore the treasury: {}", err),
+            )
+        }
+    };
+    if !data
+        .treasury
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_empty()
+    {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "Snapshots can only be restored into a server whose treasury is untouched",
+        );
+    }
+    let funds = match snapshot.funds.map(DaoFunds::restore).transpose() {
+        Ok(funds) => funds,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::SnapshotRejected,
+                format!("Failed to restore the funds: {}", err),
+            )
+        }
+    };
+    if !data
+        .funds
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_empty()
+    {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "Snapshots can only be restored into a server whose funds are untouched",
+        );
+    }
+    let token_locks = match snapshot.token_locks.map(TokenLocks::restore).transpose() {
+        Ok(token_locks) => token_locks,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::SnapshotRejected,
+                format!("Failed to restore the token tree: {}", err),
+            )
+        }
+    };
+    if !data
+        .token_locks
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_empty()
+    {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "Snapshots can only be restored into a server whose token tree is untouched",
+        );
+    }
+    let orgs = match OrganizationRegistry::restore(snapshot.organizations) {
+        Ok(orgs) => orgs,
+        Err(err) => {
+            return error_response(
+                ApiErrorCode::SnapshotRejected,
+                format!("Failed to restore the organizations: {}", err.message),
+            )
+        }
+    };
+    if !data
+        .orgs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_empty()
+    {
+        return error_response(
+            ApiErrorCode::SnapshotRejected,
+            "Snapshots can only be restored into a server without organizations",
+        );
+    }
+    let events_restored = snapshot.events.len();
+    if let Err(err) = data
+        .events
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .import(snapshot.events)
+    {
+        return error_response(ApiErrorCode::EventLogFailed, err);
+    }
+    let audit_entries_restored = snapshot.audit.len();
+    if let Err(err) = data
+        .audit
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .import(snapshot.audit)
+    {
+        return error_response(ApiErrorCode::SnapshotRejected, err);
+    }
+    if let Some(treasury) = treasury {
+        *data.treasury.lock().unwrap_or_else(PoisonError::into_inner) = treasury;
+    }
+    if let Some(funds) = funds {
+        *data.funds.lock().unwrap_or_else(PoisonError::into_inner) = funds;
+    }
+    if let Some(token_locks) = token_locks {
+        *data
+            .token_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = token_locks;
+    }
+    *data.orgs.lock().unwrap_or_else(PoisonError::into_inner) = orgs;
+    let proposals_restored = restored.len();
+    for (id, proposal) in restored {
+        proposals.insert(id, proposal);
+    }
+    drop(proposals);
+
+    // Builds the circuits the previous server had built, so the first finalizations
+    // after a migration do not wait for them
+    let circuit_ids: Vec<String> = snapshot
+        .circuit_ids
+        .iter()
+        .filter(|circuit_id| parse_finalization_circuit_id(circuit_id).is_ok())
+        .cloned()
+        .collect();
+    let circuits_warming = circuit_ids.len();
+    let state = data.get_ref().clone();
+    actix_web::rt::spawn(async move {
+        let built = web::block(move || {
+            for circuit_id in circuit_ids {
+                // Only ids of finalization circuits were kept, which cannot fail to parse
+                let _ = state
+                    .circuits
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get_or_build_finalization(&circuit_id);
+            }
+        })
+        .await;
+        if let Err(err) = built {
+            error!(
+                "Failed to build the circuits of a restored snapshot: {}",
+                err
+            );
+        }
+    });
+    HttpResponse::Ok().json(RestoreResponse {
+        proposals_restored,
+        audit_entries_restored,
+        events_restored,
+        circuits_warming,
+    })
+}
+
+// Lists every error code the API can respond with
+#[utoipa::path(
+    get,
+    path = "/errors",
+    responses((status = 200, body = Vec<ErrorCatalogEntry>))
+)]
+async fn get_errors() -> impl Responder {
+    HttpResponse::Ok().json(error_catalog())
+}
+
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/certificate",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = FinalizationCertificate),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_certificate(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> impl Responder {
+    let proposals = data.shared_map.read().await;
+    match proposals.get(&path.into_inner()) {
+        Some(proposal) => match &proposal.certificate {
+            Some(certificate) => HttpResponse::Ok().json(certificate),
+            None => error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized"),
+        },
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Lists the public inputs the finalization proof of a proposal exposes, as the standalone
+// verifier checks them. Clients can recompute them from the certificate instead
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/public-inputs",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, description = "The expected public inputs, see qed_verifier::ExpectedInputs", body = Object),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_public_inputs(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> HttpResponse {
+    let proposals = data.shared_map.read().await;
+    let certificate = match proposals.get(&path.into_inner()) {
+        Some(proposal) => match &proposal.certificate {
+            Some(certificate) => certificate,
+            None => return error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized"),
+        },
+        None => return error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    };
+    let shape = match parse_finalization_circuit_id(&certificate.circuit_id) {
+        Ok((shape, _)) => shape,
+        Err(err) => return error_response(ApiErrorCode::UnknownCircuit, err.to_string()),
+    };
+    HttpResponse::Ok().json(expected_public_inputs(
+        &shape,
+        certificate.initial_root,
+        certificate.final_root,
+        &certificate.statement,
+        certificate.content.as_ref(),
+        &certificate.action,
+        &certificate.dependencies,
+        &certificate.rules,
+    ))
+}
+
+// Serves a signed statement of the result of a finalized proposal, for clients that
+// trust this server instead of verifying its proof
+#[utoipa::path(
+    get,
+    path = "/proposal/{id}/attestation",
+    params(("id" = Uuid, Path, description = "Proposal id")),
+    responses(
+        (status = 200, body = ResultAttestation),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError)
+    )
+)]
+async fn get_attestation(data: web::Data<Arc<AppState>>, path: web::Path<Uuid>) -> HttpResponse {
+    let attester = match &data.attester {
+        Some(attester) => attester,
+        None => {
+            return error_response(
+                ApiErrorCode::AttestationsDisabled,
+                "Results are attested when the server runs with --attestation-key-file",
+            )
+        }
+    };
+    let proposals = data.shared_map.read().await;
+    match proposals.get(&path.into_inner()) {
+        Some(proposal) => match (&proposal.certificate, &proposal.proof) {
+            (Some(certificate), Some(proof)) => {
+                HttpResponse::Ok().json(attester.attest(certificate, proof))
+            }
+            _ => error_response(ApiErrorCode::NotFinalized, "Proposal is not finalized"),
+        },
+        None => error_response(ApiErrorCode::ProposalNotFound, "Proposal not found"),
+    }
+}
+
+// Aggregates the finalization proofs of several proposals of a DAO into a single proof
+// committing to all of their results
+#[utoipa::path(
+    post,
+    path = "/cycle/finalize",
+    request_body = CycleFinalizeQuery,
+    responses(
+        (status = 200, description = "The cycle was aggregated", body = CycleCertificate),
+        (status = "4XX", description = "Rejected, see the error code", body = ApiError),
+        (status = "5XX", description = "Failed, see the error code", body = ApiError)
+    )
+)]
+async fn finalize_cycle(
+    data: web::Data<Arc<AppState>>,
+    item: web::Json<CycleFinalizeQuery>,
+) -> impl Responder {
+    let item = item.into_inner();
+    let dao_id = item.dao_id.unwrap_or_else(|| DEFAULT_DAO_ID.to_string());
+    let mut seen = HashSet::new();
+    if item.proposal_ids.is_empty() || !item.proposal_ids.iter().all(|id| seen.insert(*id)) {
+        return error_response(
+            ApiErrorCode::InvalidQuery,
+            "A cycle needs at least one proposal and no duplicates",
+        );
+    }
+    let (results, envelopes) = {
+        let proposals = data.shared_map.read().await;
+        let mut results = vec![];
+        let mut envelopes = vec![];
+        for id in &item.proposal_ids {
+            let proposal = match proposals.get(id) {
+                Some(proposal) => proposal,
+                None => {
+                    return error_response(
+                        ApiErrorCode::ProposalNotFound,
+                        format!("Proposal {} not found", id),
+                    )
+                }
+            };
+            if proposal.dao_id != dao_id {
+                return error_response(
+                    ApiErrorCode::InvalidQuery,
+                    format!("Proposal {} does not belong to DAO {}", id, dao_id),
+                );
+            }
+            let (certificate, envelope) = match (&proposal.certificate, &proposal.proof) {
+                (Some(certificate), Some(envelope)) => (certificate, envelope),
+                _ => {
+                    return error_response(
+                        ApiErrorCode::NotFinalized,
+                        format!("Proposal {} is not finalized", id),
+                    )
+                }
+            };
+            results.push(CycleResult {
+                proposal_id: *id,
+                circuit_id: envelope.circuit_id.clone(),
+                initial_root: certificate.initial_root,
+                final_root: certificate.final_root,
+                no_votes: certificate.no_votes,
+                yes_votes: certificate.yes_votes,
+                statement_hash: compute_statement_hash_with_content(
+                    &certificate.statement,
+                    certificate.content.as_ref(),
+                ),
+                action_hash: compute_action_hash(&certificate.action),
+            });
+            envelopes.push(envelope.clone());
+        }
+        (results, envelopes)
+    };
+
+    // Aggregation only reads the stored proofs, so it runs without holding the store
+    let state = data.get_ref().clone();
+    let proposal_ids = item.proposal_ids.clone();
+    let span = info_span!(
+        "aggregate",
+        dao_id = %dao_id,
+        proposals = proposal_ids.len(),
+    );
+    let proved = web::block(move || {
+        let _entered = span.enter();
+        let shapes = envelopes
+            .iter()
+            .map(|envelope| parse_update_balance_circuit_id(&envelope.circuit_id))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let circuit_ids: Vec<_> = envelopes
+            .iter()
+            .map(|envelope| envelope.circuit_id.clone())
+            .collect();
+        state
+            .proving
+            .prove(|| {
+                let (inner, aggregate) = {
+                    let mut circuits = state
+                        .circuits
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    let inner: Vec<_> = shapes
+                        .iter()
+                        .map(|shape| circuits.get_or_build(*shape))
+                        .collect();
+                    (inner, circuits.get_or_build_aggregate(&shapes))
+                };
+                let proofs = envelopes
+                    .iter()
+                    .zip(&inner)
+                    .map(|(envelope, circuit)| envelope.to_proof(&circuit.base_circuit_data))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                aggregate.prove_envelope(
+                    &aggregate_finalization_circuit_id(&circuit_ids),
+                    &proposal_ids,
+                    &proofs,
+                )
+            })
+            .map_err(anyhow::Error::from)
     })
-    .bind("127.0.0.1:8080")?
-    .run()
     .await
+    .map_err(anyhow::Error::from)
+    .and_then(|proved| proved);
+    let envelope = match proved {
+        Ok(envelope) => envelope,
+        Err(err) => {
+            let error = ApiError::from(QedError::ProofGenerationFailed {
+                stage: ProofStage::Aggregation,
+                message: format!("{:#}", err),
+            });
+            return error_response(error.code, error.message);
+        }
+    };
+    let mut certificate = CycleCertificate {
+        cycle_id: Uuid::new_v4(),
+        dao_id,
+        root: compute_cycle_root(&results),
+        results,
+        proof: envelope,
+        issuer: None,
+    };
+    if !certificate.verify_root() {
+        return error_response(
+            ApiErrorCode::AggregationFailed,
+            "Aggregation proof does not commit to the cycle results",
+        );
+    }
+    if let Some(signer) = &data.signer {
+        certificate.issuer = Some(signer.sign(&certificate.digest()).unwrap());
+    }
+    HttpResponse::Ok().json(certificate)
+}
+
+// Periodically mirrors the proposals created on the governance contract as local
+// proposals. A proposal that fails to be created is retried on the next run, which
+// rescans from its block; the proposals mirrored since are left as they are.
+async fn mirror_governance(
+    data: Arc<AppState>,
+    listener: Arc<GovernanceListener>,
+    options: MirrorOptions,
+    from_block: u64,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    let mut from_block = from_block;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        if data.pause.is_paused() {
+            continue;
+        }
+        let scanned = listener.scan(from_block, options.confirmations).await;
+        let (proposals, mut next_block) = match scanned {
+            Ok(scanned) => scanned,
+            Err(err) => {
+                error!("Failed to read governance proposals: {:#}", err);
+                continue;
+            }
+        };
+        for proposal in proposals {
+            let query = match proposal.propose_query(&options, unix_timestamp()) {
+                Some(query) => query,
+                None => {
+                    warn!(
+                        chain_proposal_id = %proposal.proposal_id,
+                        "Governance proposal is past its deadline, not mirrored"
+                    );
+                    continue;
+                }
+            };
+            let response = create_proposal(web::Data::new(data.clone()), web::Json(query)).await;
+            let status = response.status();
+            if status.is_success() {
+                continue;
+            }
+            let body = actix_web::body::to_bytes(response.into_body())
+                .await
+                .unwrap_or_default();
+            let error = serde_json::from_slice::<ApiError>(&body).ok();
+            // Rejections that cannot succeed on a later scan, e.g. a proposal the
+            // mirror options make invalid, are skipped instead of blocking the scan
+            let retryable = error
+                .as_ref()
+                .map_or(status.is_server_error(), |error| error.code.is_retryable());
+            let message = error.map_or_else(
+                || String::from_utf8_lossy(&body).into_owned(),
+                |error| error.message,
+            );
+            if retryable {
+                warn!(
+                    chain_proposal_id = %proposal.proposal_id,
+                    %status,
+                    "Failed to mirror governance proposal, retrying: {}",
+                    message
+                );
+                next_block = next_block.min(proposal.block);
+            } else {
+                error!(
+                    chain_proposal_id = %proposal.proposal_id,
+                    %status,
+                    "Governance proposal cannot be mirrored, skipped: {}",
+                    message
+                );
+            }
+        }
+        from_block = next_block;
+    }
+}
+
+// Periodically posts the balance and nullifier roots of every proposal whose
+// roots changed since they were last anchored.
+async fn anchor_roots(
+    data: Arc<AppState>,
+    anchor: Arc<RootAnchor>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending = {
+            let proposals = data.shared_map.read().await;
+            let mut pending = vec![];
+            for (id, proposal) in proposals.iter() {
+                if let Some(nullifiers) = &proposal.nullifiers {
+                    // The root the finalization proof ends at, which certificates check anchors against
+                    let (_, balance_root) = proposal.proven_roots().unwrap();
+                    let nullifier_root = nullifiers.root().unwrap();
+                    let is_anchored = proposal.anchors.last().map_or(false, |last| {
+                        last.balance_root == balance_root && last.nullifier_root == nullifier_root
+                    });
+                    if !is_anchored {
+                        pending.push((*id, balance_root, nullifier_root));
+                    }
+                }
+            }
+            pending
+        };
+        for (id, balance_root, nullifier_root) in pending {
+            let span = info_span!(
+                "anchor_roots",
+                proposal_id = %id,
+                request_id = traced_request(&data, &id).map(field::display),
+            );
+            let anchored = anchor
+                .anchor(&id, balance_root, nullifier_root)
+                .instrument(span.clone())
+                .await;
+            match anchored {
+                Ok(record) => {
+                    let mut proposals = data.shared_map.write().await;
+                    if let Some(proposal) = proposals.get_mut(&id) {
+                        if let Some(certificate) = &mut proposal.certificate {
+                            certificate.anchors.push(record.clone());
+                        }
+                        proposal.anchors.push(record);
+                    }
+                }
+                Err(err) => span.in_scope(|| error!("Failed to anchor roots: {}", err)),
+            }
+        }
+    }
+}
+
+// Periodically posts the root of the result tree, once results were appended to it since
+// it was last anchored
+async fn anchor_result_root(
+    data: Arc<AppState>,
+    anchor: Arc<RootAnchor>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending = {
+            let proposals = data.shared_map.read().await;
+            let mut results = data.results.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(err) = results.sync(&proposals) {
+                error!("Failed to sync the result tree: {}", err);
+                continue;
+            }
+            let size = results.len() as u64;
+            let is_anchored = results
+                .last_anchor()
+                .map_or(size == 0, |last| last.size == size);
+            (!is_anchored).then(|| (results.root(), size))
+        };
+        if let Some((root, size)) = pending {
+            match anchor.anchor_result_root(root, size).await {
+                Ok(record) => data
+                    .results
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .record_anchor(record),
+                Err(err) => error!(size, "Failed to anchor the result root: {}", err),
+            }
+        }
+    }
+}
+
+// Periodically proves the transfers that settled deposits, which finalizations and
+// cancellations leave to be proven so they do not wait for it
+async fn prove_deposits(
+    data: Arc<AppState>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending: Vec<_> = {
+            let proposals = data.shared_map.read().await;
+            proposals
+                .iter()
+                .filter_map(|(id, proposal)| {
+                    let deposit = proposal.deposit.as_ref()?;
+                    match (&deposit.settlement, &deposit.settlement_proof) {
+                        (Some(settlement), None) => Some((*id, settlement.clone())),
+                        _ => None,
+                    }
+                })
+                .collect()
+        };
+        for (id, settlement) in pending {
+            let state = data.clone();
+            let span = info_span!(
+                "prove_deposit",
+                proposal_id = %id,
+                request_id = traced_request(&data, &id).map(field::display),
+            );
+            let proving_span = span.clone();
+            let proved = web::block(move || {
+                let _entered = proving_span.enter();
+                state.proving.prove(|| {
+                    state
+                        .circuits
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .get_or_build_deposit()
+                        .prove_envelope(&settlement)
+                })
+            })
+            .await;
+            match proved {
+                Ok(Ok(envelope)) => {
+                    let mut proposals = data.shared_map.write().await;
+                    if let Some(deposit) = proposals
+                        .get_mut(&id)
+                        .and_then(|proposal| proposal.deposit.as_mut())
+                    {
+                        deposit.settlement_proof = Some(envelope);
+                    }
+                }
+                Ok(Err(err)) => span.in_scope(|| error!("Failed to prove the deposit: {}", err)),
+                Err(err) => span.in_scope(|| error!("Failed to prove the deposit: {}", err)),
+            }
+        }
+    }
+}
+
+// Periodically proves the payouts made out of the funds of DAOs
+async fn prove_payouts(
+    data: Arc<AppState>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending = data
+            .funds
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .unproven();
+        for payout in pending {
+            let id = payout.proposal_id;
+            let state = data.clone();
+            let span = info_span!(
+                "prove_payout",
+                proposal_id = %id,
+                request_id = traced_request(&data, &id).map(field::display),
+            );
+            let proving_span = span.clone();
+            let proved = web::block(move || {
+                let _entered = proving_span.enter();
+                state.proving.prove(|| {
+                    state
+                        .circuits
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .get_or_build_payout()
+                        .prove_envelope(&payout.transfer, payout.action_hash)
+                })
+            })
+            .await;
+            match proved {
+                Ok(Ok(envelope)) => data
+                    .funds
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .set_proof(&id, envelope),
+                Ok(Err(err)) => span.in_scope(|| error!("Failed to prove the payout: {}", err)),
+                Err(err) => span.in_scope(|| error!("Failed to prove the payout: {}", err)),
+            }
+        }
+    }
+}
+
+// Periodically proves the locks of tokens in the token tree and their releases
+async fn prove_token_locks(
+    data: Arc<AppState>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending: Vec<_> = data
+            .token_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .unproven()
+            .into_iter()
+            .flat_map(|lock| {
+                let lock_transfer = lock
+                    .lock_proof
+                    .is_none()
+                    .then(|| (lock.proposal_id, lock.lock_index, false, lock.lock.clone()));
+                let release_transfer = match (&lock.release, &lock.release_proof) {
+                    (Some(release), None) => {
+                        Some((lock.proposal_id, lock.lock_index, true, release.clone()))
+                    }
+                    _ => None,
+                };
+                lock_transfer.into_iter().chain(release_transfer)
+            })
+            .collect();
+        for (id, lock_index, release, transfer) in pending {
+            let state = data.clone();
+            let span = info_span!(
+                "prove_token_lock",
+                proposal_id = %id,
+                lock_index,
+                release,
+                request_id = traced_request(&data, &id).map(field::display),
+            );
+            let proving_span = span.clone();
+            let proved = web::block(move || {
+                let _entered = proving_span.enter();
+                state.proving.prove(|| {
+                    state
+                        .circuits
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .get_or_build_token_lock()
+                        .prove_envelope(&transfer, &id)
+                })
+            })
+            .await;
+            match proved {
+                Ok(Ok(envelope)) => data
+                    .token_locks
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .set_proof(lock_index, release, envelope),
+                Ok(Err(err)) => span.in_scope(|| error!("Failed to prove the token lock: {}", err)),
+                Err(err) => span.in_scope(|| error!("Failed to prove the token lock: {}", err)),
+            }
+        }
+    }
+}
+
+// Periodically recomputes the chain of roots through the updates of every open
+// proposal and alerts when it does not end at the root of the tree, which
+// happens only through a bug that wrote to one but not the other.
+async fn check_trees(
+    data: Arc<AppState>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let mut proposals_checked = 0;
+        let mut divergences = vec![];
+        {
+            let proposals = data.shared_map.read().await;
+            for (id, proposal) in proposals.iter() {
+                if !proposal.status.accepts_updates() {
+                    continue;
+                }
+                proposals_checked += 1;
+                match check_tree(&proposal.storage, &proposal.updates) {
+                    Ok(Some(divergence)) => divergences.push(ProposalDivergence {
+                        proposal_id: *id,
+                        divergence,
+                    }),
+                    Ok(None) => {}
+                    Err(err) => error!(proposal_id = %id, "Failed to check the tree: {}", err),
+                }
+                for power in proposal.delegation_divergences() {
+                    error!(
+                        proposal_id = %id,
+                        voter_id = power.voter_id,
+                        effective_weight = %power.effective_weight,
+                        tree_balance = ?power.tree_balance,
+                        "ALERT: balance of a voter diverged from their delegations"
+                    );
+                }
+            }
+        }
+        let mut health = data
+            .tree_health
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for divergence in &divergences {
+            let is_known = health
+                .divergences
+                .iter()
+                .any(|known| known.proposal_id == divergence.proposal_id);
+            if !is_known {
+                error!(
+                    proposal_id = %divergence.proposal_id,
+                    "ALERT: tree diverged from its updates: {}",
+                    divergence.divergence
+                );
+                health.divergences_detected += 1;
+            }
+        }
+        health.checked_at = Some(unix_timestamp());
+        health.proposals_checked = proposals_checked;
+        health.divergences = divergences;
+    }
+}
+
+// Periodically writes the metadata of the proposals that changed since the last sync to
+// the metadata database, in one transaction, and deletes the rows of proposals that are
+// gone. A failed sync is retried with the next tick, as what was synced is only updated
+// once the transaction commits
+#[cfg(feature = "sql")]
+async fn sync_metadata(
+    data: Arc<AppState>,
+    metadata: Arc<SqlMetadataStore>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    let mut synced = std::collections::HashMap::new();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let current: std::collections::HashMap<Uuid, ProposalMetadata> = {
+            let proposals = data.shared_map.read().await;
+            proposals
+                .iter()
+                .map(|(id, proposal)| (*id, ProposalMetadata::new(*id, proposal)))
+                .collect()
+        };
+        let (changed, removed) = diff_metadata(&synced, &current);
+        if changed.is_empty() && removed.is_empty() {
+            continue;
+        }
+        match metadata.sync(&changed, &removed).await {
+            Ok(()) => {
+                info!(
+                    changed = changed.len(),
+                    removed = removed.len(),
+                    "Synced proposal metadata"
+                );
+                synced = current;
+            }
+            Err(err) => warn!("Failed to sync proposal metadata: {}", err),
+        }
+    }
+}
+
+// Periodically compacts the trees of proposals finalized at least `retention` ago, which
+// keep the nodes their finalization proofs and tallies are served from
+async fn compact_trees(
+    data: Arc<AppState>,
+    retention: Duration,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let now = unix_timestamp();
+        let mut proposals = data.shared_map.write().await;
+        let due: Vec<Uuid> = proposals
+            .iter()
+            .filter(|(_, proposal)| {
+                proposal.is_finalized()
+                    && !proposal.storage.is_compacted()
+                    && proposal.finalized_at.map_or(true, |finalized_at| {
+                        finalized_at.saturating_add(retention.as_secs()) <= now
+                    })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in due {
+            match proposals.get_mut(&id).unwrap().compact_trees() {
+                Ok(dropped) => info!(proposal_id = %id, dropped, "Compacted the trees"),
+                Err(err) => error!(proposal_id = %id, "Failed to compact the trees: {}", err),
+            }
+        }
+    }
+}
+
+// Periodically fetches the documents of proposals that were not checked yet, or were
+// unavailable when last checked, and records whether they match their hash. Documents
+// are fetched without holding the store, so a slow gateway does not block voting.
+async fn check_contents(
+    data: Arc<AppState>,
+    fetcher: Arc<ContentFetcher>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let pending: Vec<(Uuid, StatementContent)> = {
+            let proposals = data.shared_map.read().await;
+            proposals
+                .iter()
+                .filter(|(_, proposal)| {
+                    proposal
+                        .content_check
+                        .map_or(true, |check| check.status == ContentStatus::Unavailable)
+                })
+                .filter_map(|(id, proposal)| Some((*id, proposal.content.clone()?)))
+                .collect()
+        };
+        for (id, content) in pending {
+            let status = fetcher.check(&content).await;
+            match status {
+                ContentStatus::Verified => info!(proposal_id = %id, "Verified the document"),
+                ContentStatus::Mismatch => {
+                    warn!(proposal_id = %id, uri = %content.uri, "Document does not match its hash")
+                }
+                ContentStatus::Unavailable => {
+                    warn!(proposal_id = %id, uri = %content.uri, "Failed to fetch the document")
+                }
+            }
+            let mut proposals = data.shared_map.write().await;
+            if let Some(proposal) = proposals.get_mut(&id) {
+                proposal.content_check = Some(ContentCheck {
+                    status,
+                    checked_at: unix_timestamp(),
+                });
+            }
+        }
+    }
+}
+
+// Periodically sends the callbacks to webhooks that are due, recording how each attempt
+// went. Callbacks are sent without holding the queue, so a slow webhook does not block
+// finalizations.
+async fn deliver_webhooks(
+    data: Arc<AppState>,
+    sender: Arc<WebhookSender>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let due = data
+            .webhooks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .due(unix_timestamp());
+        for delivery in due {
+            let outcome = sender.send(&delivery, unix_timestamp()).await;
+            if let Err(err) = &outcome {
+                warn!(
+                    proposal_id = %delivery.payload.proposal_id,
+                    url = %delivery.url,
+                    "Failed to call the webhook: {}",
+                    err
+                );
+            }
+            data.webhooks
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record(&delivery.id, outcome, unix_timestamp());
+        }
+    }
+}
+
+// Periodically applies scoped delegations to the proposals in their scope and reverts them
+// on the proposals still taking updates once their scope ended. Steps rejected by a
+// proposal are recorded on the delegation and not tried again.
+async fn apply_scoped_delegations(
+    data: Arc<AppState>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        if data.voting_paused.load(Ordering::SeqCst) {
+            continue;
+        }
+        let mut proposals = data.shared_map.write().await;
+        let due = data
+            .scoped_delegations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .due(&proposals, unix_timestamp());
+        for step in due {
+            let proposal_id = step.proposal_id();
+            let (event, action, voter_id) = match step {
+                DelegationStep::Apply {
+                    voter_id,
+                    delegate_id,
+                    ..
+                } => (
+                    ProposalEvent::Delegated {
+                        voter_id,
+                        delegator_id: delegate_id,
+                    },
+                    AuditAction::Delegate,
+                    voter_id,
+                ),
+                DelegationStep::Revert { voter_id, .. } => (
+                    ProposalEvent::Undelegated { voter_id },
+                    AuditAction::Undelegate,
+                    voter_id,
+                ),
+            };
+            let quota = match (&step, proposals.get(&proposal_id)) {
+                (DelegationStep::Apply { .. }, Some(proposal))
+                    if data.quotas != DaoQuotas::default() =>
+                {
+                    data.quotas.check(
+                        &proposals.dao_usage(&proposal.dao_id),
+                        &[QuotaKind::Updates, QuotaKind::NodeStoreBytes],
+                    )
+                }
+                _ => Ok(()),
+            };
+            let outcome =
+                quota.and_then(|_| accept_event(&data, &mut proposals, proposal_id, event));
+            match &outcome {
+                Ok(()) => record_audit(
+                    &data,
+                    proposal_id,
+                    proposals.get(&proposal_id).unwrap(),
+                    action,
+                    voter_id,
+                    &step,
+                ),
+                Err(err) => warn!(
+                    %proposal_id,
+                    voter_id,
+                    "Failed to apply a scoped delegation: {}",
+                    err.message
+                ),
+            }
+            data.scoped_delegations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record(&step, outcome);
+        }
+    }
+}
+
+// Periodically fetches a snapshot of the primary of a replica and brings the proposals,
+// treasury, organizations and audit log it serves in line with it. A snapshot that fails
+// to apply is skipped, leaving the replica serving the previous one.
+async fn replicate(
+    data: Arc<AppState>,
+    primary: Arc<QedClient>,
+    admin_token: Arc<String>,
+    interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut ticker = actix_web::rt::time::interval(interval);
+    let mut follower = SnapshotFollower::default();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+        let snapshot = match primary.get_snapshot(&admin_token).await {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                warn!("Failed to fetch a snapshot of the primary: {}", err);
+                continue;
+            }
+        };
+        if let Err(err) = snapshot.check_version() {
+            warn!("Skipped a snapshot of the primary: {}", err);
+            continue;
+        }
+        let treasury = match snapshot.treasury.map(Treasury::restore).transpose() {
+            Ok(treasury) => treasury.unwrap_or_else(Treasury::new),
+            Err(err) => {
+                warn!("Skipped a snapshot of the primary: {}", err);
+                continue;
+            }
+        };
+        let funds = match snapshot.funds.map(DaoFunds::restore).transpose() {
+            Ok(funds) => funds.unwrap_or_else(DaoFunds::new),
+            Err(err) => {
+                warn!("Skipped a snapshot of the primary: {}", err);
+                continue;
+            }
+        };
+        let token_locks = match snapshot.token_locks.map(TokenLocks::restore).transpose() {
+            Ok(token_locks) => token_locks.unwrap_or_else(TokenLocks::new),
+            Err(err) => {
+                warn!("Skipped a snapshot of the primary: {}", err);
+                continue;
+            }
+        };
+        let orgs = match OrganizationRegistry::restore(snapshot.organizations) {
+            Ok(orgs) => orgs,
+            Err(err) => {
+                warn!("Skipped a snapshot of the primary: {}", err.message);
+                continue;
+            }
+        };
+        let mut audit = AuditLog::in_memory();
+        if let Err(err) = audit.import(snapshot.audit) {
+            warn!("Skipped a snapshot of the primary: {}", err);
+            continue;
+        }
+        let mut proposals = data.shared_map.write().await;
+        let summary = match follower.apply(&mut proposals, snapshot.proposals) {
+            Ok(summary) => summary,
+            Err(err) => {
+                warn!("Skipped a snapshot of the primary: {}", err);
+                continue;
+            }
+        };
+        *data.treasury.lock().unwrap_or_else(PoisonError::into_inner) = treasury;
+        *data.funds.lock().unwrap_or_else(PoisonError::into_inner) = funds;
+        *data
+            .token_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = token_locks;
+        *data.orgs.lock().unwrap_or_else(PoisonError::into_inner) = orgs;
+        *data.audit.lock().unwrap_or_else(PoisonError::into_inner) = audit;
+        if summary != FollowSummary::default() {
+            info!(
+                rebuilt = summary.rebuilt,
+                removed = summary.removed,
+                "Replicated the primary"
+            );
+        }
+    }
+}
+
+// Describes the routes for `/openapi.json` and the Swagger UI under `/swagger-ui/`
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "QED DAO voting API",
+        description = "Proposals voted on in a balance merkle tree and finalized with \
+            plonky2 proofs. Rejected requests are answered with an `ApiError` body, see \
+            `GET /errors`."
+    ),
+    paths(
+        list_proposals,
+        search_proposals,
+        search,
+        get_errors,
+        resolve_did,
+        vote,
+        vote_relayed,
+        revoke,
+        commit,
+        delegate,
+        delegate_batch,
+        create_scoped_delegation,
+        list_scoped_delegations,
+        revoke_scoped_delegation,
+        finalize,
+        approve_finalization,
+        finalize_cycle,
+        propose,
+        get_proposal,
+        cancel,
+        amend,
+        preview,
+        finalize_dry_run,
+        get_electorate,
+        get_membership,
+        get_voting_power,
+        get_blinding,
+        get_ballots,
+        share_decryption,
+        get_leaf_proof,
+        get_result_root,
+        get_result_proof,
+        get_tree_diff,
+        get_proof,
+        get_archive,
+        list_circuits,
+        get_circuit_verifier,
+        get_certificate,
+        get_public_inputs,
+        get_attestation,
+        get_audit,
+        get_transcript,
+        get_history,
+        get_tally_history,
+        get_dao_usage,
+        get_tree_health,
+        get_snapshot,
+        restore,
+        get_deposit,
+        get_treasury_account,
+        credit_treasury,
+        get_dao_funds,
+        get_dao_payouts,
+        credit_funds,
+        pay_out_proposal,
+        get_token_account,
+        credit_tokens,
+        slash,
+        delete_proposal,
+        set_voting_paused,
+        get_pause_state,
+        pause_server,
+        unpause_server,
+        list_keys,
+        issue_key,
+        rotate_key,
+        revoke_key,
+        create_organization,
+        get_organization,
+        register_voter,
+        list_voters,
+        approve_voter,
+        reject_voter,
+        org_propose,
+        list_org_proposals,
+        set_webhooks,
+        set_category_policy,
+        list_webhook_deliveries,
+    ),
+    components(schemas(
+        ActionResponse,
+        AmendQuery,
+        AnchorRecord,
+        ApiError,
+        ApiErrorCode,
+        ApiKeyView,
+        AuditAction,
+        AuditEntry,
+        BallotBoxView,
+        BallotCommittee,
+        BatchDelegationItem,
+        BlindedSlot,
+        BlindingReveal,
+        CallerView,
+        CancelQuery,
+        CastBallot,
+        CategoryPolicy,
+        CategoryPolicyQuery,
+        Ciphertext,
+        CircuitRecord,
+        CommitQuery,
+        CommitteeMember,
+        ContentCheck,
+        ContentHashKind,
+        ContentStatus,
+        ConvictionRules,
+        CreateOrganizationQuery,
+        CycleCertificate,
+        CycleFinalizeQuery,
+        CycleResult,
+        DaoQuotas,
+        DaoUsage,
+        DaoUsageResponse,
+        DecryptionShare,
+        DeclinedProposal,
+        DelegateBatchQuery,
+        DelegateQuery,
+        DelegationScope,
+        DeliveryStatus,
+        DependencyResult,
+        DepositReceipt,
+        DepositStatus,
+        DeploymentIdentity,
+        DidDocument,
+        EncryptedBallot,
+        ErrorCatalogEntry,
+        FinalizationCertificate,
+        FinalizationPayload,
+        FinalizationPreview,
+        FinalizeApproval,
+        FinalizeApprovalQuery,
+        FinalizeApprovalResponse,
+        FinalizeDryRunQuery,
+        FinalizeDryRunResponse,
+        FinalizeQuery,
+        FinalizeResponse,
+        Finalizer,
+        FinalizerPolicy,
+        FundBalance,
+        FundsCreditQuery,
+        IssueKeyQuery,
+        IssuedKeyResponse,
+        IssuerSignature,
+        LeafChange,
+        LeafDeltaProof,
+        LeafProof,
+        LeafProofResponse,
+        LockStatus,
+        MembershipProof,
+        OrganizationView,
+        PartialDecryption,
+        PauseQuery,
+        PauseState,
+        PayoutReceipt,
+        ProofEnvelope,
+        ProposalAction,
+        ProposalDivergence,
+        ProposalHistoryResponse,
+        ProposalMetadata,
+        ProposalOutcome,
+        ProposalPhase,
+        ProposalRules,
+        ProposalSort,
+        ProposalStatus,
+        ProposalStatusFilter,
+        ProposalView,
+        ProposeQuery,
+        RegisterQuery,
+        RegistrationStatus,
+        RelayedVote,
+        RelayedVoteQuery,
+        RestoreResponse,
+        ResultAttestation,
+        ResultInclusionProof,
+        ResultLeaf,
+        ResultRootRecord,
+        ResultRootResponse,
+        RevokeQuery,
+        Role,
+        RotateKeyQuery,
+        ScopedDelegateQuery,
+        ScopedDelegation,
+        ScopedRevokeQuery,
+        SearchHit,
+        Snippet,
+        StatementContent,
+        Tally,
+        TallyHistoryResponse,
+        TallyPoint,
+        TextRange,
+        TiePolicy,
+        TimestampRecord,
+        TimestampSubject,
+        TokenAccount,
+        TokenCreditQuery,
+        TokenHolder,
+        TokenLockReceipt,
+        TokenSnapshot,
+        TokenSnapshotRequest,
+        Transcript,
+        TranscriptAction,
+        TranscriptEvent,
+        TreasuryAccount,
+        TreasuryCreditQuery,
+        TreeDivergence,
+        TreeDiffResponse,
+        TreeHealthResponse,
+        VerificationMethod,
+        VestingRules,
+        VestingSchedule,
+        VoteQuery,
+        VoteSplit,
+        VoterBlinding,
+        VoterRegistration,
+        VotingPauseQuery,
+        VotingPolicy,
+        VotingPower,
+        WebhookDelivery,
+        WebhookTarget,
+        WebhooksQuery,
+        Weight,
+    ))
+)]
+struct ApiDoc;
+
+// Serves the gRPC API of proto/qed.proto by handing each call to the HTTP handler of the
+// same operation, so both APIs accept and reject the same requests
+#[cfg(feature = "grpc")]
+mod grpc_service {
+    use std::future::Future;
+
+    use actix_web::rt::ArbiterHandle;
+    use plonky2_tree_hacks::grpc::{
+        invalid_request,
+        pb::{
+            self,
+            qed_server::{Qed, QedServer},
+        },
+        proof_chunks, status_from_api_error, PROOF_CHUNK_SIZE,
+    };
+    use serde::de::DeserializeOwned;
+    use tokio::sync::oneshot;
+    use tonic::{Request, Response, Status};
+
+    use super::*;
+
+    pub(super) struct QedService {
+        state: Arc<AppState>,
+        // Handlers are not Send, so they run on the arbiter of the server rather than on the
+        // tasks of the gRPC connections
+        arbiter: ArbiterHandle,
+    }
+
+    impl QedService {
+        pub(super) fn new(state: Arc<AppState>, arbiter: ArbiterHandle) -> QedServer<Self> {
+            QedServer::new(Self { state, arbiter })
+        }
+
+        // Runs a handler on the arbiter in a span of its own, like HTTP requests, and decodes
+        // its JSON response
+        async fn dispatch<T, Fut>(
+            &self,
+            method: &'static str,
+            handler: impl FnOnce(web::Data<Arc<AppState>>) -> Fut + Send + 'static,
+        ) -> Result<T, Status>
+        where
+            T: DeserializeOwned + Send + 'static,
+            Fut: Future<Output = HttpResponse> + 'static,
+        {
+            let (sender, receiver) = oneshot::channel();
+            let state = self.state.clone();
+            let request_id = RequestId::generate();
+            let span = info_span!("grpc_request", request_id = %request_id, method);
+            let spawned = self.arbiter.spawn_fn(move || {
+                let handled = async move {
+                    let response = handler(web::Data::new(state)).await;
+                    let decoded = decode(response).await;
+                    if let Err(status) = &decoded {
+                        warn!(code = ?status.code(), "Request failed: {}", status.message());
+                    }
+                    let _ = sender.send(decoded);
+                };
+                actix_web::rt::spawn(request_id.scope(handled).instrument(span));
+            });
+            if !spawned {
+                return Err(Status::unavailable("Server is shutting down"));
+            }
+            receiver
+                .await
+                .unwrap_or_else(|_| Err(Status::internal("Request handler panicked")))
+        }
+
+        // Rejects mutating calls on a replica or a paused server, like its HTTP routes
+        fn ensure_writable(&self) -> Result<(), Status> {
+            if self.state.read_only {
+                return Err(status_from_api_error(ApiError::new(
+                    ApiErrorCode::ReadOnlyReplica,
+                    "This server is a read-only replica",
+                )));
+            }
+            if self.state.pause.is_paused() {
+                return Err(status_from_api_error(ApiError::new(
+                    ApiErrorCode::ServerPaused,
+                    "The server is paused by an admin, only reads are served",
+                )));
+            }
+            Ok(())
+        }
+
+        // Checks the bearer token of the call against the role the HTTP route of the
+        // operation requires, when the server enforces roles
+        fn authorize<T>(&self, request: &Request<T>, required: Role) -> Result<(), Status> {
+            if !self.state.enforce_roles {
+                return Ok(());
+            }
+            let principal = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .and_then(|token| authenticate_bearer(&self.state, token));
+            check_role(principal.as_ref(), required).map_err(status_from_api_error)
+        }
+
+        // Counts a call against the same limits as the HTTP route of the operation
+        fn rate_limit<T>(
+            &self,
+            limiter: &RateLimiter,
+            key_field: &str,
+            id: u32,
+            request: &Request<T>,
+        ) -> Result<(), Status> {
+            let mut keys = vec![];
+            if let Some(peer) = request.remote_addr() {
+                keys.push(format!("ip:{}", peer.ip()));
+            }
+            keys.push(format!("{}:{}", key_field, id));
+            limiter.try_acquire(&keys, Instant::now()).map_err(|wait| {
+                status_from_api_error(ApiError::new(
+                    ApiErrorCode::RateLimited,
+                    format!(
+                        "Rate limited, retry in {}s",
+                        wait.as_secs_f64().ceil() as u64
+                    ),
+                ))
+            })
+        }
+    }
+
+    // Serves the gRPC API on `addr` until shutdown
+    pub(super) async fn serve(
+        state: Arc<AppState>,
+        arbiter: ArbiterHandle,
+        addr: std::net::SocketAddr,
+        mut shutdown: ShutdownSignal,
+    ) -> anyhow::Result<()> {
+        tonic::transport::Server::builder()
+            .add_service(QedService::new(state, arbiter))
+            .serve_with_shutdown(addr, shutdown.wait())
+            .await?;
+        Ok(())
+    }
+
+    // Reads the body a handler answered with, as `T` or as the error it was rejected with
+    async fn decode<T: DeserializeOwned>(response: HttpResponse) -> Result<T, Status> {
+        let status = response.status();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| Status::internal(format!("Failed to read response: {}", err)))?;
+        if status.is_success() {
+            serde_json::from_slice(&body)
+                .map_err(|err| Status::internal(format!("Failed to decode response: {}", err)))
+        } else {
+            match serde_json::from_slice::<ApiError>(&body) {
+                Ok(error) => Err(status_from_api_error(error)),
+                Err(_) => Err(Status::unknown(String::from_utf8_lossy(&body))),
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Qed for QedService {
+        async fn propose(
+            &self,
+            request: Request<pb::ProposeRequest>,
+        ) -> Result<Response<pb::ActionReply>, Status> {
+            self.ensure_writable()?;
+            self.authorize(&request, Role::Proposer)?;
+            let proposer_id = request.get_ref().proposer_id;
+            self.rate_limit(
+                &self.state.propose_limiter,
+                "proposer_id",
+                proposer_id,
+                &request,
+            )?;
+            let query = ProposeQuery::try_from(request.into_inner()).map_err(invalid_request)?;
+            let response: ActionResponse = self
+                .dispatch("Propose", move |data| propose(data, web::Json(query)))
+                .await?;
+            Ok(Response::new(response.into()))
+        }
+
+        async fn vote(
+            &self,
+            request: Request<pb::VoteRequest>,
+        ) -> Result<Response<pb::ActionReply>, Status> {
+            self.ensure_writable()?;
+            self.authorize(&request, Role::Voter)?;
+            let voter_id = request.get_ref().voter_id;
+            self.rate_limit(&self.state.vote_limiter, "voter_id", voter_id, &request)?;
+            let query = VoteQuery::try_from(request.into_inner()).map_err(invalid_request)?;
+            let response: ActionResponse = self
+                .dispatch("Vote", move |data| vote(data, web::Json(query)))
+                .await?;
+            Ok(Response::new(response.into()))
+        }
+
+        async fn delegate(
+            &self,
+            request: Request<pb::DelegateRequest>,
+        ) -> Result<Response<pb::ActionReply>, Status> {
+            self.ensure_writable()?;
+            self.authorize(&request, Role::Voter)?;
+            let query = DelegateQuery::try_from(request.into_inner()).map_err(invalid_request)?;
+            let response: ActionResponse = self
+                .dispatch("Delegate", move |data| delegate(data, web::Json(query)))
+                .await?;
+            Ok(Response::new(response.into()))
+        }
+
+        async fn finalize(
+            &self,
+            request: Request<pb::FinalizeRequest>,
+        ) -> Result<Response<pb::FinalizeReply>, Status> {
+            self.ensure_writable()?;
+            self.authorize(&request, Role::Proposer)?;
+            let query = FinalizeQuery::try_from(request.into_inner()).map_err(invalid_request)?;
+            let response: FinalizeResponse = self
+                .dispatch("Finalize", move |data| finalize(data, web::Json(query)))
+                .await?;
+            Ok(Response::new(response.into()))
+        }
+
+        type GetProofStream =
+            tokio_stream::Iter<std::vec::IntoIter<Result<pb::ProofChunk, Status>>>;
+
+        async fn get_proof(
+            &self,
+            request: Request<pb::ProofRequest>,
+        ) -> Result<Response<Self::GetProofStream>, Status> {
+            let id = Uuid::try_from(request.into_inner()).map_err(invalid_request)?;
+            let envelope: ProofEnvelope = self
+                .dispatch("GetProof", move |data| proof_download(data, id, None, None))
+                .await?;
+            let chunks: Vec<_> = proof_chunks(envelope, PROOF_CHUNK_SIZE)
+                .into_iter()
+                .map(Ok)
+                .collect();
+            Ok(Response::new(tokio_stream::iter(chunks)))
+        }
+
+        async fn get_membership_proof(
+            &self,
+            request: Request<pb::MembershipRequest>,
+        ) -> Result<Response<pb::MembershipProof>, Status> {
+            let path = <(Uuid, u32)>::try_from(request.into_inner()).map_err(invalid_request)?;
+            let proof: MembershipProof = self
+                .dispatch("GetMembershipProof", move |data| {
+                    membership_response(data, path, None)
+                })
+                .await?;
+            Ok(Response::new(proof.into()))
+        }
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = ServerArgs::parse();
+    // Spans log when they close, with how long they were busy
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+    match args.log_format {
+        LogFormat::Text => logs.init(),
+        LogFormat::Json => logs
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+    let anchor = match (&args.anchor_rpc_url, args.anchor_contract, args.anchor_from) {
+        (Some(rpc_url), Some(contract), Some(from)) => Some(
+            RootAnchor::new(rpc_url, contract, from)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?,
+        ),
+        (Some(_), _, _) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--anchor-contract and --anchor-from are required when anchoring",
+            ))
+        }
+        _ => None,
+    };
+    let token_snapshotter = args
+        .eth_rpc_url
+        .as_deref()
+        .map(TokenSnapshotter::new)
+        .transpose()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    let beacon = args
+        .eth_rpc_url
+        .as_deref()
+        .map(BlockBeacon::new)
+        .transpose()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    let governance = match (&args.eth_rpc_url, args.governance_contract) {
+        (Some(rpc_url), Some(contract)) => Some(
+            GovernanceListener::new(rpc_url, contract)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?,
+        ),
+        _ => None,
+    };
+    let node_stores = match &args.node_store_path {
+        Some(path) => NodeStoreBackend::open_kv(path, args.node_store_cache_size)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?,
+        None => NodeStoreBackend::Memory,
+    };
+    let signer = match &args.instance_key_file {
+        Some(path) => {
+            let key = InstanceSigner::key_from_hex(&std::fs::read_to_string(path)?)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+            let instance_id = args
+                .instance_id
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            let signer = InstanceSigner::new(instance_id, args.region.clone(), key);
+            info!(
+                instance_id = %signer.identity().instance_id,
+                operator = ?signer.identity().operator,
+                "Signing artifacts"
+            );
+            Some(Arc::new(signer))
+        }
+        None => None,
+    };
+    let attester = match &args.attestation_key_file {
+        Some(path) => {
+            let key = ResultAttester::key_from_hex(&std::fs::read_to_string(path)?)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+            let attester = ResultAttester::new(key);
+            info!(
+                public_key = %hex::encode(attester.public_key()),
+                "Attesting results"
+            );
+            Some(attester)
+        }
+        None => None,
+    };
+    let mut audit = match &args.audit_log {
+        Some(path) => AuditLog::open(path)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?,
+        None => AuditLog::in_memory(),
+    };
+    if let Some(signer) = &signer {
+        audit = audit.with_signer(signer.clone());
+    }
+    let pause = match &args.pause_file {
+        Some(path) => PauseSwitch::open(path)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?,
+        None => PauseSwitch::in_memory(),
+    };
+    if pause.is_paused() {
+        warn!(reason = ?pause.state().reason, "Starting paused, lift it with POST /admin/unpause");
+    }
+    let events = match &args.event_log {
+        Some(path) => EventLog::open(path)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?,
+        None => EventLog::in_memory(),
+    };
+    // Proposals are derived from the events they accepted, checked against the roots
+    // recorded with them, in trees rebuilt from scratch
+    let proposals = replay(events.records(), |namespace| {
+        node_stores.open_empty_store(namespace)
+    })
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    if !events.is_empty() {
+        info!(
+            events = events.records().len(),
+            proposals = proposals.len(),
+            "Replayed the event log"
+        );
+    }
+    let admin_token = match &args.admin_token_file {
+        Some(path) => {
+            let token = std::fs::read_to_string(path)?.trim().to_string();
+            if token.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the admin token file is empty",
+                ));
+            }
+            Some(token)
+        }
+        None => None,
+    };
+    let tls_config = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert_path), Some(key_path)) => Some(
+            load_tls_config(cert_path, key_path)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?,
+        ),
+        _ => None,
+    };
+    let primary_admin_token = match &args.primary_admin_token_file {
+        Some(path) => Some(std::fs::read_to_string(path)?.trim().to_string()),
+        None => None,
+    };
+    #[cfg(feature = "sql")]
+    let metadata = match &args.metadata_db_url {
+        Some(url) => {
+            let store = SqlMetadataStore::connect(url)
+                .await
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+            Some(Arc::new(store))
+        }
+        None => None,
+    };
+    let shared_state = AppState {
+        shared_map: ProposalLock::new(proposals),
+        nullifier_mode: anchor.is_some(),
+        token_snapshotter,
+        beacon,
+        circuits: Mutex::new(CircuitCache::new()),
+        node_stores,
+        vote_limiter: Arc::new(RateLimiter::per_minute(args.vote_rate_limit)),
+        propose_limiter: Arc::new(RateLimiter::per_minute(args.propose_rate_limit)),
+        audit: Mutex::new(audit),
+        events: Mutex::new(events),
+        signer,
+        attester,
+        quotas: DaoQuotas {
+            max_node_store_bytes: args.dao_max_node_store_bytes,
+            max_proof_bytes: args.dao_max_proof_bytes,
+            max_updates: args.dao_max_updates,
+        },
+        proving: ProvingRetryPolicy {
+            max_attempts: args.prove_attempts.max(1),
+            ..Default::default()
+        },
+        tree_health: Mutex::new(TreeHealthResponse::default()),
+        admin_token,
+        treasury: Mutex::new(Treasury::new()),
+        funds: Mutex::new(DaoFunds::new()),
+        token_locks: Mutex::new(TokenLocks::new()),
+        proposal_deposit: args.proposal_deposit,
+        orgs: Mutex::new(OrganizationRegistry::new()),
+        webhooks: Mutex::new(WebhookQueue::new(
+            args.webhook_max_attempts,
+            args.webhook_retry_secs,
+        )),
+        read_only: args.replica_of.is_some(),
+        api_keys: Mutex::new(KeyRing::new()),
+        enforce_roles: args.enforce_roles,
+        voting_paused: AtomicBool::new(false),
+        pause,
+        deterministic_proposal_ids: args.deterministic_proposal_ids,
+        creating: Mutex::new(HashSet::new()),
+        search: Mutex::new(SearchIndex::new()),
+        results: Mutex::new(ResultTree::new()),
+        request_traces: Mutex::new(RequestTraces::new()),
+        scoped_delegations: Mutex::new(ScopedDelegationRegistry::new()),
+        #[cfg(feature = "sql")]
+        metadata,
+    };
+    let shared_state = Arc::new(shared_state);
+    let mut supervisor = TaskSupervisor::new();
+    // Replicas take their state from the primary, which anchors, timestamps and proves it
+    let anchor = anchor.filter(|_| !shared_state.read_only).map(Arc::new);
+    if let Some(anchor) = &anchor {
+        let (state, anchor) = (shared_state.clone(), anchor.clone());
+        let interval = Duration::from_secs(args.anchor_interval_secs);
+        supervisor.spawn("anchor_roots", move |shutdown| {
+            anchor_roots(state.clone(), anchor.clone(), interval, shutdown)
+        });
+    }
+    if let (Some(anchor), Some(interval_secs)) = (&anchor, args.result_anchor_interval_secs) {
+        let (state, anchor) = (shared_state.clone(), anchor.clone());
+        let interval = Duration::from_secs(interval_secs);
+        supervisor.spawn("anchor_result_root", move |shutdown| {
+            anchor_result_root(state.clone(), anchor.clone(), interval, shutdown)
+        });
+    }
+    if let Some(listener) = governance.filter(|_| !shared_state.read_only) {
+        let (state, listener) = (shared_state.clone(), Arc::new(listener));
+        let options = MirrorOptions {
+            proposer_id: args.governance_proposer_id,
+            dao_id: args.governance_dao_id.clone(),
+            token: args.governance_token,
+            token_from_block: args.governance_from_block,
+            confirmations: args.governance_confirmations,
+        };
+        let from_block = args.governance_from_block;
+        let interval = Duration::from_secs(args.governance_interval_secs);
+        supervisor.spawn("mirror_governance", move |shutdown| {
+            mirror_governance(
+                state.clone(),
+                listener.clone(),
+                options.clone(),
+                from_block,
+                interval,
+                shutdown,
+            )
+        });
+    }
+    if let Some(tsa_url) = args.tsa_url.as_ref().filter(|_| !shared_state.read_only) {
+        let (state, authority) = (
+            shared_state.clone(),
+            Arc::new(TimestampAuthority::new(tsa_url)),
+        );
+        let interval = Duration::from_secs(args.tsa_interval_secs);
+        supervisor.spawn("timestamp_certificates", move |shutdown| {
+            timestamp_certificates(state.clone(), authority.clone(), interval, shutdown)
+        });
+    }
+    if let Some(gateway) = args
+        .content_gateway
+        .as_ref()
+        .filter(|_| !shared_state.read_only)
+    {
+        let (state, fetcher) = (shared_state.clone(), Arc::new(ContentFetcher::new(gateway)));
+        let interval = Duration::from_secs(args.content_check_interval_secs);
+        supervisor.spawn("check_contents", move |shutdown| {
+            check_contents(state.clone(), fetcher.clone(), interval, shutdown)
+        });
+    }
+    if !shared_state.read_only {
+        let state = shared_state.clone();
+        let sender = WebhookSender::new(Duration::from_secs(args.webhook_timeout_secs))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+        let sender = Arc::new(sender);
+        let interval = Duration::from_secs(args.webhook_interval_secs);
+        supervisor.spawn("deliver_webhooks", move |shutdown| {
+            deliver_webhooks(state.clone(), sender.clone(), interval, shutdown)
+        });
+    }
+    if !shared_state.read_only {
+        let state = shared_state.clone();
+        let interval = Duration::from_secs(args.scoped_delegation_interval_secs);
+        supervisor.spawn("apply_scoped_delegations", move |shutdown| {
+            apply_scoped_delegations(state.clone(), interval, shutdown)
+        });
+    }
+    {
+        let state = shared_state.clone();
+        let interval = Duration::from_secs(args.tree_check_interval_secs);
+        supervisor.spawn("check_trees", move |shutdown| {
+            check_trees(state.clone(), interval, shutdown)
+        });
+    }
+    {
+        let state = shared_state.clone();
+        let retention = Duration::from_secs(args.finalized_tree_retention_secs);
+        let interval = Duration::from_secs(args.tree_check_interval_secs);
+        supervisor.spawn("compact_trees", move |shutdown| {
+            compact_trees(state.clone(), retention, interval, shutdown)
+        });
+    }
+    if !shared_state.read_only {
+        let state = shared_state.clone();
+        let interval = Duration::from_secs(args.deposit_proof_interval_secs);
+        supervisor.spawn("prove_deposits", move |shutdown| {
+            prove_deposits(state.clone(), interval, shutdown)
+        });
+        let state = shared_state.clone();
+        supervisor.spawn("prove_payouts", move |shutdown| {
+            prove_payouts(state.clone(), interval, shutdown)
+        });
+        let state = shared_state.clone();
+        supervisor.spawn("prove_token_locks", move |shutdown| {
+            prove_token_locks(state.clone(), interval, shutdown)
+        });
+    }
+    if let (Some(primary_url), Some(admin_token)) = (&args.replica_of, primary_admin_token) {
+        let state = shared_state.clone();
+        let primary = Arc::new(QedClient::new(primary_url.as_str()));
+        let admin_token = Arc::new(admin_token);
+        let interval = Duration::from_secs(args.replica_sync_secs);
+        info!(%primary_url, "Serving as a read-only replica");
+        supervisor.spawn("replicate", move |shutdown| {
+            replicate(
+                state.clone(),
+                primary.clone(),
+                admin_token.clone(),
+                interval,
+                shutdown,
+            )
+        });
+    }
+    #[cfg(feature = "sql")]
+    if let Some(metadata) = shared_state.metadata.clone() {
+        let state = shared_state.clone();
+        let interval = Duration::from_secs(args.metadata_sync_secs);
+        supervisor.spawn("sync_metadata", move |shutdown| {
+            sync_metadata(state.clone(), metadata.clone(), interval, shutdown)
+        });
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_addr) = args.grpc_addr {
+        let state = shared_state.clone();
+        let arbiter = actix_web::rt::Arbiter::current();
+        info!(%grpc_addr, "Serving gRPC");
+        supervisor.spawn("grpc", move |shutdown| {
+            grpc_service::serve(state.clone(), arbiter.clone(), grpc_addr, shutdown)
+        });
+    }
+    let openapi = ApiDoc::openapi();
+    #[cfg(feature = "chaos")]
+    let chaos_config = ChaosConfig {
+        tree_write_failure_rate: args.chaos_tree_write_failure_rate,
+        max_proving_delay: Duration::from_millis(args.chaos_max_proving_delay_ms),
+        request_drop_rate: args.chaos_request_drop_rate,
+        seed: args.chaos_seed,
+    };
+    let tls = tls_config.is_some();
+    let (cors_origins, cors_methods) = (
+        args.cors_allowed_origins.clone(),
+        args.cors_allowed_methods.clone(),
+    );
+    let server = HttpServer::new(move || {
+        // Runs once on each worker thread, which handles its requests and their tree writes
+        #[cfg(feature = "chaos")]
+        chaos::install(chaos_config.clone());
+        let vote_limiter = shared_state.vote_limiter.clone();
+        // Commitments and revocations count against the vote limit of a voter
+        let commit_limiter = shared_state.vote_limiter.clone();
+        let revoke_limiter = shared_state.vote_limiter.clone();
+        let propose_limiter = shared_state.propose_limiter.clone();
+        let org_propose_limiter = shared_state.propose_limiter.clone();
+        App::new()
+            .app_data(web::Data::new(shared_state.clone()))
+            .app_data(web::JsonConfig::default().error_handler(payload_error_handler))
+            .wrap(from_fn(authorize))
+            .wrap(from_fn(reject_mutations))
+            .wrap(from_fn(move |req, next| security_headers(tls, req, next)))
+            .wrap(from_fn(request_span))
+            // Outermost, since preflight requests carry no credentials to authorize
+            .wrap(Condition::new(
+                !cors_origins.is_empty(),
+                cors(&cors_origins, &cors_methods),
+            ))
+            .route("/", web::get().to(list_proposals))
+            .route("/proposals/search", web::get().to(search_proposals))
+            .route("/search", web::get().to(search))
+            .route("/errors", web::get().to(get_errors))
+            .route("/did/{did}", web::get().to(resolve_did))
+            .service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/openapi.json", openapi.clone()))
+            .service(
+                web::resource("/vote")
+                    .wrap(from_fn(move |req, next| {
+                        rate_limit(vote_limiter.clone(), "voter_id", req, next)
+                    }))
+                    .route(web::post().to(vote)),
+            )
+            .route("/vote/relayed", web::post().to(vote_relayed))
+            .service(
+                web::resource("/vote/revoke")
+                    .wrap(from_fn(move |req, next| {
+                        rate_limit(revoke_limiter.clone(), "voter_id", req, next)
+                    }))
+                    .route(web::post().to(revoke)),
+            )
+            .service(
+                web::resource("/commit")
+                    .wrap(from_fn(move |req, next| {
+                        rate_limit(commit_limiter.clone(), "voter_id", req, next)
+                    }))
+                    .route(web::post().to(commit)),
+            )
+            .route("/delegate", web::post().to(delegate))
+            .route("/delegate/batch", web::post().to(delegate_batch))
+            .route("/delegations", web::post().to(create_scoped_delegation))
+            .route(
+                "/delegations/{voter_id}",
+                web::get().to(list_scoped_delegations),
+            )
+            .route(
+                "/delegations/{id}/revoke",
+                web::post().to(revoke_scoped_delegation),
+            )
+            .route("/finalize", web::post().to(finalize))
+            .route("/finalize/approve", web::post().to(approve_finalization))
+            .route("/cycle/finalize", web::post().to(finalize_cycle))
+            .service(
+                web::resource("/propose")
+                    .wrap(from_fn(move |req, next| {
+                        rate_limit(propose_limiter.clone(), "proposer_id", req, next)
+                    }))
+                    .route(web::post().to(propose)),
+            )
+            .route("/org/{org_id}", web::get().to(get_organization))
+            .route("/org/{org_id}/register", web::post().to(register_voter))
+            .route("/org/{org_id}/voters", web::get().to(list_voters))
+            .route(
+                "/org/{org_id}/voters/{did}/approve",
+                web::post().to(approve_voter),
+            )
+            .route(
+                "/org/{org_id}/voters/{did}/reject",
+                web::post().to(reject_voter),
+            )
+            .route("/org/{org_id}/proposals", web::get().to(list_org_proposals))
+            .route("/org/{org_id}/webhooks", web::post().to(set_webhooks))
+            .route(
+                "/org/{org_id}/categories",
+                web::post().to(set_category_policy),
+            )
+            .service(
+                web::resource("/org/{org_id}/propose")
+                    .wrap(from_fn(move |req, next| {
+                        rate_limit(org_propose_limiter.clone(), "proposer_id", req, next)
+                    }))
+                    .route(web::post().to(org_propose)),
+            )
+            .route("/proposal/{id}", web::get().to(get_proposal))
+            .route("/proposal/{id}/cancel", web::post().to(cancel))
+            .route("/proposal/{id}/amend", web::post().to(amend))
+            .route("/proposal/{id}/preview", web::get().to(preview))
+            .route(
+                "/proposal/{id}/finalize/dry-run",
+                web::post().to(finalize_dry_run),
+            )
+            .route("/proposal/{id}/electorate", web::get().to(get_electorate))
+            .route(
+                "/proposal/{id}/membership/{voter_id}",
+                web::get().to(get_membership),
+            )
+            .route(
+                "/proposal/{id}/voting-power/{voter_id}",
+                web::get().to(get_voting_power),
+            )
+            .route("/proposal/{id}/blinding", web::get().to(get_blinding))
+            .route("/proposal/{id}/ballots", web::get().to(get_ballots))
+            .route("/proposal/{id}/decrypt", web::post().to(share_decryption))
+            .route(
+                "/proposal/{id}/leaf/{index}/proof",
+                web::get().to(get_leaf_proof),
+            )
+            .route("/results/root", web::get().to(get_result_root))
+            .route("/results/{id}/proof", web::get().to(get_result_proof))
+            .route("/proposal/{id}/diff", web::get().to(get_tree_diff))
+            .route("/proposal/{id}/proof", web::get().to(get_proof))
+            .route("/proposal/{id}/archive", web::get().to(get_archive))
+            .route("/circuits", web::get().to(list_circuits))
+            .route(
+                "/circuits/{id}/verifier",
+                web::get().to(get_circuit_verifier),
+            )
+            .route("/proposal/{id}/certificate", web::get().to(get_certificate))
+            .route(
+                "/proposal/{id}/public-inputs",
+                web::get().to(get_public_inputs),
+            )
+            .route("/proposal/{id}/attestation", web::get().to(get_attestation))
+            .route("/proposal/{id}/audit", web::get().to(get_audit))
+            .route("/proposal/{id}/transcript", web::get().to(get_transcript))
+            .route("/proposal/{id}/history", web::get().to(get_history))
+            .route(
+                "/proposal/{id}/tally/history",
+                web::get().to(get_tally_history),
+            )
+            .route("/proposal/{id}/deposit", web::get().to(get_deposit))
+            .route(
+                "/treasury/{proposer_id}",
+                web::get().to(get_treasury_account),
+            )
+            .route("/dao/{id}/usage", web::get().to(get_dao_usage))
+            .route("/dao/{id}/funds", web::get().to(get_dao_funds))
+            .route("/dao/{id}/payouts", web::get().to(get_dao_payouts))
+            .route("/tokens/{voter_id}", web::get().to(get_token_account))
+            .route("/health/tree", web::get().to(get_tree_health))
+            .route("/admin/snapshot", web::get().to(get_snapshot))
+            .route("/admin/treasury/credit", web::post().to(credit_treasury))
+            .route("/admin/funds/credit", web::post().to(credit_funds))
+            .route(
+                "/admin/proposal/{id}/payout",
+                web::post().to(pay_out_proposal),
+            )
+            .route("/admin/tokens/credit", web::post().to(credit_tokens))
+            .route("/admin/proposal/{id}/slash", web::post().to(slash))
+            .route("/admin/proposal/{id}", web::delete().to(delete_proposal))
+            .route("/admin/voting", web::post().to(set_voting_paused))
+            .service(
+                web::resource("/admin/pause")
+                    .route(web::get().to(get_pause_state))
+                    .route(web::post().to(pause_server)),
+            )
+            .route("/admin/unpause", web::post().to(unpause_server))
+            .service(
+                web::resource("/admin/keys")
+                    .route(web::get().to(list_keys))
+                    .route(web::post().to(issue_key)),
+            )
+            .route("/admin/keys/{key_id}/rotate", web::post().to(rotate_key))
+            .route("/admin/keys/{key_id}", web::delete().to(revoke_key))
+            .route("/admin/org", web::post().to(create_organization))
+            .route("/admin/webhooks", web::get().to(list_webhook_deliveries))
+            .service(
+                web::resource("/admin/restore")
+                    .app_data(
+                        web::JsonConfig::default()
+                            .limit(MAX_SNAPSHOT_BYTES)
+                            .error_handler(payload_error_handler),
+                    )
+                    .route(web::post().to(restore)),
+            )
+    });
+    let server = match tls_config {
+        Some(config) => {
+            info!(bind = %args.bind, "Serving the HTTP API over TLS");
+            server.bind_rustls_0_23(&args.bind, config)?
+        }
+        None => server.bind(&args.bind)?,
+    };
+    server.run().await?;
+    let aborted = supervisor
+        .shutdown(Duration::from_secs(args.shutdown_grace_secs))
+        .await;
+    if !aborted.is_empty() {
+        warn!(?aborted, "Aborted background tasks on shutdown");
+    }
+    Ok(())
 }
@@ -0,0 +1 @@
+pub mod nullifier_set;
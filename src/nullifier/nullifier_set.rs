@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+
+use anyhow::ensure;
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::Field},
+    hash::poseidon::PoseidonHash,
+};
+use uuid::Uuid;
+
+use crate::{
+    common::{
+        hash::{merkle::helpers::merkle_proof::DeltaMerkleProof, traits::hasher::FieldWHasher},
+        WHashOut,
+    },
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+type F = GoldilocksField;
+
+/// Splits a proposal id into field elements so it can be used as a hash domain.
+pub fn proposal_id_to_elements(proposal_id: &Uuid) -> [F; 4] {
+    let id = proposal_id.as_u128();
+    [
+        F::from_canonical_u64((id & 0xffffffff) as u64),
+        F::from_canonical_u64(((id >> 32) & 0xffffffff) as u64),
+        F::from_canonical_u64(((id >> 64) & 0xffffffff) as u64),
+        F::from_canonical_u64((id >> 96) as u64),
+    ]
+}
+
+/// Computes the nullifier a voter leaf spends when voting on a proposal.
+pub fn compute_nullifier(proposal_id: &Uuid, leaf_index: u64) -> WHashOut<F> {
+    let mut elements = proposal_id_to_elements(proposal_id).to_vec();
+    elements.push(F::from_canonical_u64(leaf_index & 0xffffffff));
+    elements.push(F::from_canonical_u64(leaf_index >> 32));
+    PoseidonHash::w_hash_many(&elements)
+}
+
+/// The set of nullifiers spent on a proposal, stored in a merkle tree indexed by
+/// voter leaf so that every voter can occupy at most one slot.
+pub struct NullifierSet {
+    proposal_id: Uuid,
+    tree: ZeroMerkleTree<F, PoseidonHash, NodeStore>,
+    spent: BTreeSet<u64>,
+}
+
+impl NullifierSet {
+    pub fn new(proposal_id: Uuid, height: u8) -> Self {
+        Self::with_store(
+            proposal_id,
+            height,
+            NodeStore::Memory(SimpleNodeStore::new()),
+        )
+    }
+    pub fn with_store(proposal_id: Uuid, height: u8, store: NodeStore) -> Self {
+        Self {
+            proposal_id,
+            tree: ZeroMerkleTree::new(height, store),
+            spent: BTreeSet::new(),
+        }
+    }
+    pub fn contains(&self, leaf_index: u64) -> anyhow::Result<bool> {
+        Ok(self.tree.get_leaf_value(leaf_index)? != WHashOut::ZERO)
+    }
+    pub fn insert(&mut self, leaf_index: u64) -> anyhow::Result<DeltaMerkleProof<F>> {
+        ensure!(
+            !self.contains(leaf_index)?,
+            "nullifier for leaf {} has already been spent",
+            leaf_index
+        );
+        self.spent.insert(leaf_index);
+        self.tree
+            .set_leaf(leaf_index, compute_nullifier(&self.proposal_id, leaf_index))
+    }
+    /// Frees the nullifier of `leaf_index`, whose vote was revoked.
+    pub fn remove(&mut self, leaf_index: u64) -> anyhow::Result<DeltaMerkleProof<F>> {
+        ensure!(
+            self.spent.remove(&leaf_index),
+            "nullifier for leaf {} has not been spent",
+            leaf_index
+        );
+        self.tree.set_leaf(leaf_index, WHashOut::ZERO)
+    }
+    /// Clears the nullifiers of all leaves but `spent`, for rolling back
+    /// nullifiers whose vote was never recorded.
+    pub fn restore(&mut self, spent: &BTreeSet<u64>) -> anyhow::Result<()> {
+        for leaf_index in self.spent.difference(spent).copied().collect::<Vec<_>>() {
+            self.tree.set_leaf(leaf_index, WHashOut::ZERO)?;
+            self.spent.remove(&leaf_index);
+        }
+        Ok(())
+    }
+    /// Drops every node but the root, once no more votes are cast. Nullifiers
+    /// read as unspent afterwards. Returns the number of nodes dropped.
+    pub fn compact(&mut self) -> anyhow::Result<usize> {
+        let keep = self.tree.proof_nodes(&[]);
+        self.tree
+            .store_mut()
+            .retain(|level, index| keep.contains(&(level, index)))
+    }
+    pub fn root(&self) -> anyhow::Result<WHashOut<F>> {
+        self.tree.get_root()
+    }
+    pub fn height(&self) -> u8 {
+        self.tree.get_height()
+    }
+    pub fn node_store(&self) -> &NodeStore {
+        self.tree.store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, num::NonZeroUsize};
+
+    use uuid::Uuid;
+
+    use crate::utils::zmt::node_store::backend::NodeStoreBackend;
+
+    use super::{compute_nullifier, NullifierSet};
+
+    #[test]
+    fn test_nullifier_set_rejects_double_spend() -> anyhow::Result<()> {
+        let proposal_id = Uuid::new_v4();
+        let mut nullifiers = NullifierSet::new(proposal_id, 32);
+        nullifiers.insert(7)?;
+        let root = nullifiers.root()?;
+        assert!(nullifiers.contains(7)?);
+        assert!(nullifiers.insert(7).is_err());
+        assert_eq!(nullifiers.root()?, root);
+
+        // A revoked vote frees the nullifier for a new vote, but only once
+        nullifiers.remove(7)?;
+        assert!(!nullifiers.contains(7)?);
+        assert!(nullifiers.remove(7).is_err());
+        let proof = nullifiers.insert(7)?;
+        assert_eq!(proof.new_value, compute_nullifier(&proposal_id, 7));
+        assert_eq!(nullifiers.root()?, root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nullifier_set_restore() -> anyhow::Result<()> {
+        let proposal_id = Uuid::new_v4();
+        let mut nullifiers = NullifierSet::new(proposal_id, 32);
+        let mut expected = NullifierSet::new(proposal_id, 32);
+        for index in [1, 2, 3] {
+            nullifiers.insert(index)?;
+        }
+        expected.insert(2)?;
+
+        nullifiers.restore(&BTreeSet::from([2]))?;
+        assert!(!nullifiers.contains(1)? && !nullifiers.contains(3)?);
+        assert!(nullifiers.contains(2)?);
+        assert_eq!(nullifiers.root()?, expected.root()?);
+        nullifiers.insert(1)?;
+        assert!(nullifiers.insert(2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_nullifier_set_persists_in_kv_store() -> anyhow::Result<()> {
+        let backend = NodeStoreBackend::Kv {
+            db: sled::Config::new().temporary(true).open()?,
+            cache_capacity: NonZeroUsize::new(4).unwrap(),
+        };
+        let proposal_id = Uuid::new_v4();
+        let root = {
+            let mut nullifiers =
+                NullifierSet::with_store(proposal_id, 32, backend.open_store("nullifiers")?);
+            nullifiers.insert(4)?;
+            nullifiers.insert(1000)?;
+            nullifiers.root()?
+        };
+
+        let mut reopened =
+            NullifierSet::with_store(proposal_id, 32, backend.open_store("nullifiers")?);
+        assert_eq!(reopened.root()?, root);
+        assert!(reopened.contains(4)? && reopened.contains(1000)?);
+        assert!(!reopened.contains(5)?);
+        assert!(reopened.insert(1000).is_err());
+
+        let emptied =
+            NullifierSet::with_store(proposal_id, 32, backend.open_empty_store("nullifiers")?);
+        assert!(emptied.node_store().is_empty());
+        assert_eq!(emptied.root()?, NullifierSet::new(proposal_id, 32).root()?);
+        Ok(())
+    }
+}
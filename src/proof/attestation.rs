@@ -0,0 +1,243 @@
+//! Ed25519 attestations of the results of finalized proposals, for systems
+//! that trust the server rather than verify its proofs.
+//!
+//! The server signs a canonical binary message (see [`attestation_message`])
+//! with a key from its configuration. Ed25519 signatures are deterministic, so
+//! an attestation is the same however often it is requested.
+
+use anyhow::ensure;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use plonky2::field::{goldilocks_field::GoldilocksField, types::PrimeField64};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::weight::Weight,
+    common::WHashOut,
+    proposal::{approval::FinalizeApproval, rules::ProposalOutcome},
+};
+
+use super::{
+    certificate::{compute_statement_hash_with_content, FinalizationCertificate},
+    codec::ProofEnvelope,
+};
+
+type F = GoldilocksField;
+
+/// Prefixes every attestation message, so the key cannot be tricked into
+/// signing one for another protocol.
+pub const ATTESTATION_DOMAIN: &[u8] = b"qed-dapp:result-attestation:v1";
+
+/// A signed statement of what a proposal was finalized with.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResultAttestation {
+    pub proposal_id: Uuid,
+    /// See [`compute_statement_hash_with_content`].
+    #[schema(value_type = String)]
+    pub statement_hash: WHashOut<F>,
+    pub yes_votes: Weight,
+    pub no_votes: Weight,
+    pub outcome: ProposalOutcome,
+    #[schema(value_type = String)]
+    pub final_root: WHashOut<F>,
+    /// SHA-256 digest of the proof bytes of the finalization proof.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub proof_hash: [u8; 32],
+    /// Ed25519 key of the server.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub public_key: [u8; 32],
+    /// Approvals of the finalizers, on proposals finalized by a threshold of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub approvals: Vec<FinalizeApproval>,
+    /// Signature over [`attestation_message`].
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub signature: Vec<u8>,
+}
+
+/// The message an attestation signs: [`ATTESTATION_DOMAIN`], the 16 bytes of
+/// the proposal id, the statement hash, the yes and no votes, the outcome as a
+/// byte (0 passed, 1 vetoed, 2 revote), the final root and the proof hash,
+/// followed by the finalizer id, key and signature of each approval. Hashes are
+/// written as their four elements, weights as one u64 each and finalizer ids as
+/// one u32 each, all little endian.
+pub fn attestation_message(attestation: &ResultAttestation) -> Vec<u8> {
+    let hash_bytes = |hash: &WHashOut<F>| -> Vec<u8> {
+        hash.0
+            .elements
+            .iter()
+            .flat_map(|element| element.to_canonical_u64().to_le_bytes())
+            .collect()
+    };
+    let outcome = match attestation.outcome {
+        ProposalOutcome::Passed => 0u8,
+        ProposalOutcome::Vetoed => 1,
+        ProposalOutcome::Revote => 2,
+    };
+    let mut message = [
+        ATTESTATION_DOMAIN,
+        attestation.proposal_id.as_bytes(),
+        &hash_bytes(&attestation.statement_hash),
+        &attestation.yes_votes.get().to_le_bytes(),
+        &attestation.no_votes.get().to_le_bytes(),
+        &[outcome],
+        &hash_bytes(&attestation.final_root),
+        &attestation.proof_hash,
+    ]
+    .concat();
+    for approval in &attestation.approvals {
+        message.extend_from_slice(&approval.finalizer_id.to_le_bytes());
+        message.extend_from_slice(&approval.public_key);
+        message.extend_from_slice(&approval.signature);
+    }
+    message
+}
+
+impl ResultAttestation {
+    /// Checks the signature against the key the attestation names, and each
+    /// approval against the key of its finalizer. Callers still have to check
+    /// those keys are the ones they trust.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        for approval in &self.approvals {
+            approval.verify(&self.proposal_id)?;
+        }
+        let signature: [u8; 64] = self
+            .signature
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("signature must be 64 bytes"))?;
+        VerifyingKey::from_bytes(&self.public_key)?.verify_strict(
+            &attestation_message(self),
+            &Signature::from_bytes(&signature),
+        )?;
+        Ok(())
+    }
+}
+
+/// Signs result attestations on behalf of this server.
+pub struct ResultAttester {
+    key: SigningKey,
+}
+
+impl ResultAttester {
+    pub fn new(key: SigningKey) -> Self {
+        Self { key }
+    }
+    /// Reads a hex encoded 32 byte Ed25519 secret key.
+    pub fn key_from_hex(hex_key: &str) -> anyhow::Result<SigningKey> {
+        let bytes = hex::decode(hex_key.trim().trim_start_matches("0x"))?;
+        ensure!(bytes.len() == 32, "Ed25519 secret keys are 32 bytes long");
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes);
+        Ok(SigningKey::from_bytes(&key))
+    }
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+    /// Attests the result a proposal was finalized with.
+    pub fn attest(
+        &self,
+        certificate: &FinalizationCertificate,
+        proof: &ProofEnvelope,
+    ) -> ResultAttestation {
+        let mut attestation = ResultAttestation {
+            proposal_id: certificate.proposal_id,
+            statement_hash: compute_statement_hash_with_content(
+                &certificate.statement,
+                certificate.content.as_ref(),
+            ),
+            yes_votes: certificate.yes_votes,
+            no_votes: certificate.no_votes,
+            outcome: certificate.outcome,
+            final_root: certificate.final_root,
+            proof_hash: Sha256::digest(&proof.proof_bytes).into(),
+            public_key: self.public_key(),
+            approvals: certificate.approvals.clone(),
+            signature: vec![],
+        };
+        attestation.signature = self
+            .key
+            .sign(&attestation_message(&attestation))
+            .to_bytes()
+            .to_vec();
+        attestation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{ResultAttestation, ResultAttester};
+    use crate::{
+        balance::weight::Weight,
+        common::WHashOut,
+        proof::{
+            certificate::{compute_certificate_binding, FinalizationCertificate},
+            codec::{ProofEnvelope, PROOF_ENVELOPE_VERSION},
+        },
+        proposal::{
+            action::ProposalAction,
+            rules::{ProposalOutcome, TiePolicy},
+        },
+    };
+
+    #[test]
+    fn test_result_attestation() -> anyhow::Result<()> {
+        let attester = ResultAttester::new(ResultAttester::key_from_hex(&"07".repeat(32))?);
+        let certificate = FinalizationCertificate {
+            proposal_id: Uuid::new_v4(),
+            statement: "Fund the audit".to_string(),
+            content: None,
+            action: ProposalAction::TextOnly,
+            initial_root: WHashOut::ZERO,
+            final_root: WHashOut::ZERO,
+            yes_votes: Weight::from(3),
+            no_votes: Weight::from(1),
+            outcome: ProposalOutcome::Passed,
+            tie_policy: TiePolicy::default(),
+            beacon: None,
+            beacon_block: None,
+            revote: None,
+            circuit_id: "update_balance:8:8:32".to_string(),
+            nullifier_root: None,
+            binding: compute_certificate_binding(WHashOut::ZERO, None),
+            anchors: vec![],
+            transcript_digest: [0u8; 32],
+            timestamps: vec![],
+            issuer: None,
+            dependencies: vec![],
+            approvals: vec![],
+            rules: Default::default(),
+        };
+        let proof = ProofEnvelope {
+            version: PROOF_ENVELOPE_VERSION,
+            circuit_id: certificate.circuit_id.clone(),
+            common_data_hash: vec![],
+            public_inputs: vec![],
+            proof_bytes: vec![1, 2, 3],
+        };
+        let attestation = attester.attest(&certificate, &proof);
+        attestation.verify()?;
+        assert_eq!(attestation, attester.attest(&certificate, &proof));
+        let json = serde_json::to_string(&attestation)?;
+        assert_eq!(
+            serde_json::from_str::<ResultAttestation>(&json)?,
+            attestation
+        );
+
+        let mut flipped = attestation.clone();
+        flipped.outcome = ProposalOutcome::Vetoed;
+        assert!(flipped.verify().is_err());
+        let mut inflated = attestation;
+        inflated.yes_votes = Weight::from(4);
+        assert!(inflated.verify().is_err());
+        Ok(())
+    }
+}
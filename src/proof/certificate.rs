@@ -0,0 +1,317 @@
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::Field},
+    hash::poseidon::PoseidonHash,
+};
+use qed_verifier::ProvenRules;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::weight::Weight,
+    chain::{anchor::AnchorRecord, timestamp::TimestampRecord},
+    circuits::update_balance::BalanceUpdate,
+    common::{hash::traits::hasher::FieldWHasher, WHashOut},
+    nullifier::nullifier_set::proposal_id_to_elements,
+    proof::identity::IssuerSignature,
+    proposal::{
+        action::ProposalAction,
+        approval::FinalizeApproval,
+        content::{ContentHashKind, StatementContent},
+        dependency::DependencyResult,
+        rules::{ProposalOutcome, TiePolicy},
+    },
+};
+
+type F = GoldilocksField;
+
+/// Commits to the final balance root together with the nullifier root, so that
+/// anchoring the binding (or both roots) pins the complete result of a proposal.
+pub fn compute_certificate_binding(
+    final_root: WHashOut<F>,
+    nullifier_root: Option<WHashOut<F>>,
+) -> WHashOut<F> {
+    let nullifier_root = nullifier_root.unwrap_or(WHashOut::ZERO);
+    PoseidonHash::w_hash_many(&[final_root.0.elements, nullifier_root.0.elements].concat())
+}
+
+/// Packs `bytes` little endian into one element per four bytes, after an element
+/// holding their length so that trailing zeros count.
+pub(crate) fn pack_bytes(bytes: &[u8]) -> Vec<F> {
+    let mut elements = vec![F::from_canonical_usize(bytes.len())];
+    elements.extend(bytes.chunks(4).map(|chunk| {
+        let mut packed = [0u8; 4];
+        packed[..chunk.len()].copy_from_slice(chunk);
+        F::from_canonical_u32(u32::from_le_bytes(packed))
+    }));
+    elements
+}
+
+/// Poseidon hash of the statement of a proposal, which its finalization proof
+/// exposes. The UTF-8 bytes are packed as by [`pack_bytes`].
+pub fn compute_statement_hash(statement: &str) -> WHashOut<F> {
+    PoseidonHash::w_hash_many(&pack_bytes(statement.as_bytes()))
+}
+
+/// Hash of the statement of a proposal together with the document it refers to,
+/// which its finalization proof exposes in place of [`compute_statement_hash`].
+/// Without a document this is the statement hash; otherwise the statement hash is
+/// followed by the URI packed as by [`pack_bytes`], the kind of the document hash
+/// as an element and the document hash packed the same way.
+pub fn compute_statement_hash_with_content(
+    statement: &str,
+    content: Option<&StatementContent>,
+) -> WHashOut<F> {
+    let statement_hash = compute_statement_hash(statement);
+    match content {
+        None => statement_hash,
+        Some(content) => {
+            let kind = match content.hash_kind {
+                ContentHashKind::Keccak256 => F::ZERO,
+                ContentHashKind::Poseidon => F::ONE,
+            };
+            PoseidonHash::w_hash_many(
+                &[
+                    statement_hash.0.elements.to_vec(),
+                    pack_bytes(content.uri.as_bytes()),
+                    vec![kind],
+                    pack_bytes(&content.hash),
+                ]
+                .concat(),
+            )
+        }
+    }
+}
+
+/// Poseidon hash of the action of a proposal, which its finalization proof
+/// exposes next to the statement hash. The kind of the action is encoded as an
+/// element, followed by its fields packed as by [`pack_bytes`]: addresses as
+/// their 20 bytes, an absent token as no bytes, and amounts as 32 big endian bytes.
+pub fn compute_action_hash(action: &ProposalAction) -> WHashOut<F> {
+    let elements = match action {
+        ProposalAction::TextOnly => vec![F::ZERO],
+        ProposalAction::TreasuryTransfer {
+            token,
+            recipient,
+            amount,
+        } => {
+            let mut amount_bytes = [0u8; 32];
+            amount.to_big_endian(&mut amount_bytes);
+            let token_bytes = token.map(|token| token.as_bytes().to_vec());
+            [
+                vec![F::ONE],
+                pack_bytes(&token_bytes.unwrap_or_default()),
+                pack_bytes(recipient.as_bytes()),
+                pack_bytes(&amount_bytes),
+            ]
+            .concat()
+        }
+        ProposalAction::ParameterChange { parameter, value } => [
+            vec![F::TWO],
+            pack_bytes(parameter.as_bytes()),
+            pack_bytes(value.as_bytes()),
+        ]
+        .concat(),
+    };
+    PoseidonHash::w_hash_many(&elements)
+}
+
+/// Poseidon hash of what a finalized proposal resolved to: its id, statement
+/// and action hashes, final balance root and outcome, encoded as an element
+/// (0 passed, 1 vetoed, 2 revote). Proposals depending on it commit to this.
+pub fn compute_result_commitment(certificate: &FinalizationCertificate) -> WHashOut<F> {
+    let outcome = match certificate.outcome {
+        ProposalOutcome::Passed => F::ZERO,
+        ProposalOutcome::Vetoed => F::ONE,
+        ProposalOutcome::Revote => F::TWO,
+    };
+    PoseidonHash::w_hash_many(
+        &[
+            proposal_id_to_elements(&certificate.proposal_id).to_vec(),
+            compute_statement_hash_with_content(
+                &certificate.statement,
+                certificate.content.as_ref(),
+            )
+            .0
+            .elements
+            .to_vec(),
+            compute_action_hash(&certificate.action).0.elements.to_vec(),
+            certificate.final_root.0.elements.to_vec(),
+            vec![outcome],
+        ]
+        .concat(),
+    )
+}
+
+/// SHA-256 digest of the vote transcript, i.e. the ordered balance updates a proposal was proven over.
+pub fn compute_transcript_digest(updates: &[BalanceUpdate<F>]) -> [u8; 32] {
+    Sha256::digest(bincode::serialize(updates).unwrap()).into()
+}
+
+/// The result of finalizing a proposal, as handed out to external verifiers.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizationCertificate {
+    pub proposal_id: Uuid,
+    pub statement: String,
+    /// Document the statement refers to, see [`compute_statement_hash_with_content`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<StatementContent>,
+    /// See [`compute_action_hash`].
+    #[serde(default)]
+    pub action: ProposalAction,
+    #[schema(value_type = String)]
+    pub initial_root: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub final_root: WHashOut<F>,
+    pub yes_votes: Weight,
+    pub no_votes: Weight,
+    pub outcome: ProposalOutcome,
+    pub tie_policy: TiePolicy,
+    /// Beacon value used to break a tie under [`TiePolicy::RandomWithBeacon`],
+    /// the hash of [`Self::beacon_block`].
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    #[schema(value_type = Option<String>)]
+    pub beacon: Option<Vec<u8>>,
+    /// Block whose hash breaks a tie under [`TiePolicy::RandomWithBeacon`], fixed
+    /// at creation, see [`crate::chain::beacon`].
+    #[serde(default)]
+    pub beacon_block: Option<u64>,
+    /// Round reopening voting after a tie under [`TiePolicy::Revote`], see
+    /// [`crate::proposal::id::derive_revote_id`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revote: Option<Uuid>,
+    pub circuit_id: String,
+    #[schema(value_type = Option<String>)]
+    pub nullifier_root: Option<WHashOut<F>>,
+    #[schema(value_type = String)]
+    pub binding: WHashOut<F>,
+    pub anchors: Vec<AnchorRecord>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub transcript_digest: [u8; 32],
+    /// Trusted timestamps of the transcript and certificate digests, see [`Self::digest`].
+    #[serde(default)]
+    pub timestamps: Vec<TimestampRecord>,
+    /// Identity of the instance that issued the certificate, signed over [`Self::digest`].
+    #[serde(default)]
+    pub issuer: Option<IssuerSignature>,
+    /// Results of the proposals this one depends on, whose hash its proof exposes.
+    #[serde(default)]
+    pub dependencies: Vec<DependencyResult>,
+    /// Approvals of the finalizers, on proposals finalized by a threshold of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub approvals: Vec<FinalizeApproval>,
+    /// Rules of the proposal its proof exposes, see [`crate::proposal::Proposal::proven_rules`].
+    #[serde(default, skip_serializing_if = "ProvenRules::is_empty")]
+    #[schema(value_type = Object)]
+    pub rules: ProvenRules,
+}
+
+impl FinalizationCertificate {
+    /// SHA-256 digest of the certificate, leaving out the anchors and timestamps
+    /// which are attached after finalization, and the issuer signature over it.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut certificate = self.clone();
+        certificate.anchors.clear();
+        certificate.timestamps.clear();
+        certificate.issuer = None;
+        Sha256::digest(serde_json::to_vec(&certificate).unwrap()).into()
+    }
+    /// Checks the issuer signature, if the certificate carries one.
+    pub fn verify_issuer(&self) -> anyhow::Result<()> {
+        match &self.issuer {
+            Some(issuer) => issuer.verify(&self.digest()),
+            None => Ok(()),
+        }
+    }
+    pub fn verify_binding(&self) -> bool {
+        self.binding == compute_certificate_binding(self.final_root, self.nullifier_root)
+    }
+    /// Returns true if the final roots of the proposal have been posted on-chain.
+    pub fn is_final_state_anchored(&self) -> bool {
+        self.nullifier_root.is_some()
+            && self.anchors.iter().any(|anchor| {
+                anchor.balance_root == self.final_root
+                    && Some(anchor.nullifier_root) == self.nullifier_root
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use web3::types::H256;
+
+    use crate::{
+        balance::weight::Weight,
+        chain::anchor::AnchorRecord,
+        common::WHashOut,
+        proposal::rules::{ProposalOutcome, TiePolicy},
+    };
+
+    use super::{compute_certificate_binding, FinalizationCertificate};
+
+    fn certificate(nullifier_root: WHashOut<super::F>) -> FinalizationCertificate {
+        let final_root = WHashOut::from_values(1, 2, 3, 4);
+        FinalizationCertificate {
+            proposal_id: Uuid::new_v4(),
+            statement: "Fund the audit".to_string(),
+            content: None,
+            action: Default::default(),
+            initial_root: WHashOut::ZERO,
+            final_root,
+            yes_votes: Weight::from(2),
+            no_votes: Weight::from(1),
+            outcome: ProposalOutcome::Passed,
+            tie_policy: TiePolicy::default(),
+            beacon: None,
+            beacon_block: None,
+            revote: None,
+            circuit_id: String::new(),
+            nullifier_root: Some(nullifier_root),
+            binding: compute_certificate_binding(final_root, Some(nullifier_root)),
+            anchors: vec![],
+            transcript_digest: [0; 32],
+            timestamps: vec![],
+            issuer: None,
+            dependencies: vec![],
+            approvals: vec![],
+            rules: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_binding_rejects_other_nullifier_root() {
+        let nullifier_root = WHashOut::from_values(5, 6, 7, 8);
+        let mut certificate = certificate(nullifier_root);
+        assert!(certificate.verify_binding());
+
+        certificate.nullifier_root = Some(WHashOut::from_values(5, 6, 7, 9));
+        assert!(!certificate.verify_binding());
+        certificate.nullifier_root = None;
+        assert!(!certificate.verify_binding());
+    }
+
+    #[test]
+    fn test_anchor_with_other_nullifier_root_does_not_anchor() {
+        let nullifier_root = WHashOut::from_values(5, 6, 7, 8);
+        let mut certificate = certificate(nullifier_root);
+        let anchor = AnchorRecord {
+            balance_root: certificate.final_root,
+            nullifier_root: WHashOut::from_values(5, 6, 7, 9),
+            tx_hash: H256::zero(),
+            anchored_at: 1,
+        };
+        certificate.anchors.push(anchor.clone());
+        assert!(!certificate.is_final_state_anchored());
+
+        certificate.anchors.push(AnchorRecord {
+            nullifier_root,
+            ..anchor
+        });
+        assert!(certificate.is_final_state_anchored());
+    }
+}
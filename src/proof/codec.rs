@@ -0,0 +1,179 @@
+use anyhow::{bail, ensure};
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    plonk::{
+        circuit_data::CircuitData,
+        config::{AlgebraicHasher, GenericConfig, GenericHashOut},
+        proof::ProofWithPublicInputs,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use utoipa::ToSchema;
+
+use crate::common::verify::fingerprint::get_circuit_fingerprint_generic;
+
+/// Current version of the [`ProofEnvelope`] wire format.
+///
+/// Bump this whenever a field is added, removed or reinterpreted, and keep
+/// decoding support for the older versions that may still be stored.
+pub const PROOF_ENVELOPE_VERSION: u16 = 1;
+
+/// A self-describing, versioned container for a serialized plonky2 proof.
+///
+/// `common_data_hash` is the fingerprint of the verifier data the proof was
+/// generated against, so a decoder can refuse to verify a proof with a circuit
+/// that has changed since the proof was stored.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProofEnvelope {
+    pub version: u16,
+    pub circuit_id: String,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub common_data_hash: Vec<u8>,
+    pub public_inputs: Vec<u64>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub proof_bytes: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u16,
+}
+
+fn check_version(version: u16) -> anyhow::Result<()> {
+    ensure!(
+        version == PROOF_ENVELOPE_VERSION,
+        "unsupported proof envelope version {} (expected {})",
+        version,
+        PROOF_ENVELOPE_VERSION
+    );
+    Ok(())
+}
+
+impl ProofEnvelope {
+    pub fn new<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+        circuit_id: &str,
+        circuit_data: &CircuitData<F, C, D>,
+        proof: &ProofWithPublicInputs<F, C, D>,
+    ) -> Self
+    where
+        <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+    {
+        Self {
+            version: PROOF_ENVELOPE_VERSION,
+            circuit_id: circuit_id.to_string(),
+            common_data_hash: get_circuit_fingerprint_generic::<D, F, C>(
+                &circuit_data.verifier_only,
+            )
+            .to_bytes(),
+            public_inputs: proof
+                .public_inputs
+                .iter()
+                .map(|x| x.to_canonical_u64())
+                .collect(),
+            proof_bytes: proof.to_bytes(),
+        }
+    }
+
+    /// Decodes the proof against `circuit_data`, failing if the envelope was
+    /// produced for a different circuit or its public inputs were tampered with.
+    pub fn to_proof<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+        &self,
+        circuit_data: &CircuitData<F, C, D>,
+    ) -> anyhow::Result<ProofWithPublicInputs<F, C, D>>
+    where
+        <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+    {
+        check_version(self.version)?;
+        let expected_hash =
+            get_circuit_fingerprint_generic::<D, F, C>(&circuit_data.verifier_only).to_bytes();
+        ensure!(
+            expected_hash == self.common_data_hash,
+            "proof envelope for circuit {} was produced with different verifier data",
+            self.circuit_id
+        );
+
+        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(
+            self.proof_bytes.clone(),
+            &circuit_data.common,
+        )?;
+        let public_inputs = proof
+            .public_inputs
+            .iter()
+            .map(|x| x.to_canonical_u64())
+            .collect::<Vec<_>>();
+        ensure!(
+            public_inputs == self.public_inputs,
+            "proof envelope public inputs do not match the embedded proof"
+        );
+        Ok(proof)
+    }
+
+    /// Bytes of proof data held by the envelope, used for storage accounting.
+    pub fn artifact_len(&self) -> usize {
+        self.proof_bytes.len() + self.common_data_hash.len() + 8 * self.public_inputs.len()
+    }
+
+    pub fn to_bincode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bincode(bytes: &[u8]) -> anyhow::Result<Self> {
+        // the version is the first field, so it can be read before committing to a layout
+        let version: u16 = match bincode::deserialize(bytes) {
+            Ok(version) => version,
+            Err(_) => bail!("proof envelope is too short to contain a version"),
+        };
+        check_version(version)?;
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let header: VersionHeader = serde_json::from_str(json)?;
+        check_version(header.version)?;
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_envelope() -> ProofEnvelope {
+        ProofEnvelope {
+            version: PROOF_ENVELOPE_VERSION,
+            circuit_id: "update_balance:1:32:32".to_string(),
+            common_data_hash: vec![1; 32],
+            public_inputs: vec![5, 6, 7, 8],
+            proof_bytes: vec![0xde, 0xad, 0xbe, 0xef],
+        }
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() -> anyhow::Result<()> {
+        let envelope = sample_envelope();
+        assert_eq!(
+            ProofEnvelope::from_bincode(&envelope.to_bincode()?)?,
+            envelope
+        );
+        assert_eq!(ProofEnvelope::from_json(&envelope.to_json()?)?, envelope);
+        Ok(())
+    }
+
+    #[test]
+    fn test_envelope_rejects_unknown_version() -> anyhow::Result<()> {
+        let mut envelope = sample_envelope();
+        envelope.version = PROOF_ENVELOPE_VERSION + 1;
+        assert!(ProofEnvelope::from_bincode(&envelope.to_bincode()?).is_err());
+        assert!(ProofEnvelope::from_json(&envelope.to_json()?).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::weight::Weight,
+    common::{hash::traits::hasher::FieldWHasher, WHashOut},
+    nullifier::nullifier_set::proposal_id_to_elements,
+    proof::{codec::ProofEnvelope, identity::IssuerSignature},
+};
+
+type F = GoldilocksField;
+
+/// The part of a proposal's finalization a governance cycle commits to, i.e. the
+/// public inputs of its finalization proof bound to the proposal id.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CycleResult {
+    pub proposal_id: Uuid,
+    pub circuit_id: String,
+    #[schema(value_type = String)]
+    pub initial_root: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub final_root: WHashOut<F>,
+    pub no_votes: Weight,
+    pub yes_votes: Weight,
+    /// See [`compute_statement_hash_with_content`](super::certificate::compute_statement_hash_with_content).
+    #[schema(value_type = String)]
+    pub statement_hash: WHashOut<F>,
+    /// See [`compute_action_hash`](super::certificate::compute_action_hash).
+    #[schema(value_type = String)]
+    pub action_hash: WHashOut<F>,
+}
+
+impl CycleResult {
+    /// Elements hashed into the leaf of the result: the proposal id followed by
+    /// the public inputs of the finalization proof.
+    pub fn leaf_inputs(&self) -> Vec<F> {
+        [
+            proposal_id_to_elements(&self.proposal_id).to_vec(),
+            self.initial_root.0.elements.to_vec(),
+            self.final_root.0.elements.to_vec(),
+            vec![self.no_votes.to_element(), self.yes_votes.to_element()],
+            self.statement_hash.0.elements.to_vec(),
+            self.action_hash.0.elements.to_vec(),
+        ]
+        .concat()
+    }
+    pub fn leaf(&self) -> WHashOut<F> {
+        PoseidonHash::w_hash_many(&self.leaf_inputs())
+    }
+}
+
+/// Merkle root over the leaves of `results`, padded with zero leaves up to a
+/// power of two. Matches the public commitment of the aggregation circuit.
+pub fn compute_cycle_root(results: &[CycleResult]) -> WHashOut<F> {
+    let mut level: Vec<WHashOut<F>> = results.iter().map(CycleResult::leaf).collect();
+    level.resize(results.len().max(1).next_power_of_two(), WHashOut::ZERO);
+    while level.len() > 1 {
+        level = level
+            .chunks_exact(2)
+            .map(|pair| {
+                PoseidonHash::w_hash_many(&[pair[0].0.elements, pair[1].0.elements].concat())
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// The result of finalizing a governance cycle, i.e. several finalized proposals
+/// of a DAO whose proofs were verified by a single aggregation proof.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CycleCertificate {
+    pub cycle_id: Uuid,
+    pub dao_id: String,
+    pub results: Vec<CycleResult>,
+    /// See [`compute_cycle_root`], the single public input of `proof`.
+    #[schema(value_type = String)]
+    pub root: WHashOut<F>,
+    pub proof: ProofEnvelope,
+    #[serde(default)]
+    pub issuer: Option<IssuerSignature>,
+}
+
+impl CycleCertificate {
+    /// SHA-256 digest of the certificate, leaving out the issuer signature over it.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut certificate = self.clone();
+        certificate.issuer = None;
+        Sha256::digest(serde_json::to_vec(&certificate).unwrap()).into()
+    }
+    /// Checks that the root commits to the listed results and is the one the proof exposes.
+    pub fn verify_root(&self) -> bool {
+        let root = compute_cycle_root(&self.results);
+        let public_inputs: Vec<u64> = root
+            .0
+            .elements
+            .iter()
+            .map(|x| x.to_canonical_u64())
+            .collect();
+        self.root == root && self.proof.public_inputs == public_inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
+    use uuid::Uuid;
+
+    use crate::{
+        balance::weight::Weight,
+        common::{hash::traits::hasher::FieldWHasher, WHashOut},
+    };
+
+    use super::{compute_cycle_root, CycleResult};
+
+    type F = GoldilocksField;
+
+    fn result(votes: u32) -> CycleResult {
+        CycleResult {
+            proposal_id: Uuid::new_v4(),
+            circuit_id: "update_balance:1:32:32".to_string(),
+            initial_root: WHashOut::from_values(1, 2, 3, 4),
+            final_root: WHashOut::from_values(5, 6, 7, 8),
+            no_votes: Weight::ZERO,
+            yes_votes: Weight::from(votes),
+            statement_hash: WHashOut::from_values(9, 10, 11, 12),
+            action_hash: WHashOut::from_values(13, 14, 15, 16),
+        }
+    }
+
+    #[test]
+    fn test_cycle_root_pads_with_zero_leaves() {
+        let single = result(1);
+        assert_eq!(compute_cycle_root(&[single.clone()]), single.leaf());
+
+        let results = [result(1), result(2), result(3)];
+        let hash = |l: WHashOut<F>, r: WHashOut<F>| {
+            PoseidonHash::w_hash_many(&[l.0.elements, r.0.elements].concat())
+        };
+        let expected = hash(
+            hash(results[0].leaf(), results[1].leaf()),
+            hash(results[2].leaf(), WHashOut::ZERO),
+        );
+        assert_eq!(compute_cycle_root(&results), expected);
+        assert_ne!(
+            compute_cycle_root(&[results[1].clone(), results[0].clone(), results[2].clone()]),
+            expected
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use web3::{
+    signing::{recover, Key, SecretKey, SecretKeyRef},
+    types::Address,
+};
+
+/// Which server instance, run by which operator, produced an artifact.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DeploymentIdentity {
+    pub instance_id: String,
+    pub region: Option<String>,
+    /// Address of the instance key the operator signs artifacts with.
+    #[schema(value_type = String)]
+    pub operator: Address,
+}
+
+/// A deployment identity attached to an artifact, with the instance key's
+/// signature over the artifact digest and the identity.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct IssuerSignature {
+    pub identity: DeploymentIdentity,
+    /// Recoverable secp256k1 signature, r || s || recovery id.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub signature: Vec<u8>,
+}
+
+/// The message signed for an artifact, binding its digest to the identity of the issuer.
+pub fn issuer_message(
+    digest: &[u8; 32],
+    identity: &DeploymentIdentity,
+) -> anyhow::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(digest);
+    hasher.update(serde_json::to_vec(identity)?);
+    Ok(hasher.finalize().into())
+}
+
+impl IssuerSignature {
+    /// Checks that the operator of the identity signed `digest`.
+    pub fn verify(&self, digest: &[u8; 32]) -> anyhow::Result<()> {
+        ensure!(self.signature.len() == 65, "signature must be 65 bytes");
+        let message = issuer_message(digest, &self.identity)?;
+        let signer = recover(&message, &self.signature[..64], self.signature[64] as i32)?;
+        ensure!(
+            signer == self.identity.operator,
+            "signed by {:?}, not the operator {:?}",
+            signer,
+            self.identity.operator
+        );
+        Ok(())
+    }
+}
+
+/// Signs artifacts on behalf of this instance.
+pub struct InstanceSigner {
+    identity: DeploymentIdentity,
+    key: SecretKey,
+}
+
+impl InstanceSigner {
+    pub fn new(instance_id: String, region: Option<String>, key: SecretKey) -> Self {
+        let operator = SecretKeyRef::new(&key).address();
+        Self {
+            identity: DeploymentIdentity {
+                instance_id,
+                region,
+                operator,
+            },
+            key,
+        }
+    }
+    /// Reads a hex encoded secp256k1 secret key, as written by common Ethereum tooling.
+    pub fn key_from_hex(hex_key: &str) -> anyhow::Result<SecretKey> {
+        let bytes = hex::decode(hex_key.trim().trim_start_matches("0x"))?;
+        Ok(SecretKey::from_slice(&bytes)?)
+    }
+    pub fn identity(&self) -> &DeploymentIdentity {
+        &self.identity
+    }
+    pub fn sign(&self, digest: &[u8; 32]) -> anyhow::Result<IssuerSignature> {
+        let message = issuer_message(digest, &self.identity)?;
+        let signature = SecretKeyRef::new(&self.key).sign_message(&message)?;
+        let mut bytes = Vec::with_capacity(65);
+        bytes.extend_from_slice(signature.r.as_bytes());
+        bytes.extend_from_slice(signature.s.as_bytes());
+        bytes.push(signature.v as u8);
+        Ok(IssuerSignature {
+            identity: self.identity.clone(),
+            signature: bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InstanceSigner;
+
+    #[test]
+    fn test_issuer_signature() -> anyhow::Result<()> {
+        let key = InstanceSigner::key_from_hex(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )?;
+        let signer = InstanceSigner::new("eu-1".to_string(), Some("eu-west".to_string()), key);
+        let digest = [7u8; 32];
+        let signature = signer.sign(&digest)?;
+        signature.verify(&digest)?;
+        assert!(signature.verify(&[8u8; 32]).is_err());
+
+        let mut relabeled = signature.clone();
+        relabeled.identity.instance_id = "us-1".to_string();
+        assert!(relabeled.verify(&digest).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use plonky2::{
+    field::{
+        goldilocks_field::GoldilocksField,
+        types::{Field, PrimeField64},
+    },
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    balance::{storage::BalanceStorage, weight::Weight},
+    circuits::update_balance::BalanceUpdate,
+    common::{
+        hash::merkle::helpers::merkle_proof::{
+            compute_root_merkle_proof, DeltaMerkleProof, MerkleProof,
+        },
+        WHashOut,
+    },
+};
+
+type F = GoldilocksField;
+
+/// Proof that a leaf of a balance tree holds `value` under `root`, in a JSON
+/// shape light clients can check without plonky2's serialization.
+///
+/// Hashes are hex encoded, big endian, like roots everywhere else in the API.
+/// The root is recomputed from the leaf up: at level `i`, the node is hashed
+/// with its sibling `siblings[i]` on its right if bit `i` of `index` is 0,
+/// and on its left otherwise, using Poseidon two-to-one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LeafProof {
+    pub index: u64,
+    /// The first element of a voter or tally leaf is its weight, the others are zero.
+    #[schema(value_type = String)]
+    pub value: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub root: WHashOut<F>,
+    /// From the leaf up to the root.
+    #[schema(value_type = Vec<String>)]
+    pub siblings: Vec<WHashOut<F>>,
+}
+
+/// Proof that a leaf changed from `old_value` to `new_value`, taking the tree
+/// from `old_root` to `new_root`. Both roots are recomputed with the same
+/// siblings, as for a [`LeafProof`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LeafDeltaProof {
+    pub index: u64,
+    #[schema(value_type = String)]
+    pub old_value: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub new_value: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub old_root: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub new_root: WHashOut<F>,
+    #[schema(value_type = Vec<String>)]
+    pub siblings: Vec<WHashOut<F>>,
+}
+
+/// A leaf holding a different value at the final root of a balance tree than at
+/// its initial root, proven under both.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LeafChange {
+    pub index: u64,
+    #[schema(value_type = String)]
+    pub old_value: WHashOut<F>,
+    #[schema(value_type = String)]
+    pub new_value: WHashOut<F>,
+    /// Inclusion of `old_value` under the initial root.
+    pub old_proof: LeafProof,
+    /// Inclusion of `new_value` under the final root.
+    pub new_proof: LeafProof,
+}
+
+impl LeafChange {
+    pub fn verify(&self, initial_root: WHashOut<F>, final_root: WHashOut<F>) -> anyhow::Result<()> {
+        ensure!(
+            self.old_proof.index == self.index && self.new_proof.index == self.index,
+            "proofs are not of leaf {}",
+            self.index
+        );
+        ensure!(
+            self.old_proof.value == self.old_value && self.new_proof.value == self.new_value,
+            "proofs of leaf {} are not of its changed values",
+            self.index
+        );
+        self.old_proof.verify(initial_root)?;
+        self.new_proof.verify(final_root)
+    }
+}
+
+/// Leaves changed by `updates` since `storage` was seeded, by index. A leaf
+/// changed back to its initial value is left out, since the roots do not tell
+/// it apart from one never changed.
+pub fn diff_leaves(
+    storage: &BalanceStorage,
+    updates: &[BalanceUpdate<F>],
+) -> anyhow::Result<Vec<LeafChange>> {
+    ensure!(
+        !storage.is_compacted(),
+        "the voter leaves were dropped when the tree was compacted"
+    );
+    let mut values = BTreeMap::new();
+    for update in updates {
+        for proof in [&update.sender_update, &update.receiver_update] {
+            values
+                .entry(proof.index.to_canonical_u64())
+                .or_insert((proof.old_value, proof.new_value))
+                .1 = proof.new_value;
+        }
+    }
+    values.retain(|_, (old_value, new_value)| old_value != new_value);
+    let indices = values.keys().copied().collect::<Vec<_>>();
+    let old_proofs = storage.initial_leaf_proofs(&indices)?;
+    values
+        .into_iter()
+        .zip(old_proofs)
+        .map(|((index, (old_value, new_value)), old_proof)| {
+            Ok(LeafChange {
+                index,
+                old_value,
+                new_value,
+                old_proof: old_proof.into(),
+                new_proof: storage.tree.get_leaf(index)?.into(),
+            })
+        })
+        .collect()
+}
+
+fn ensure_in_tree(index: u64, siblings: &[WHashOut<F>]) -> anyhow::Result<()> {
+    ensure!(
+        siblings.len() >= 64 || index >> siblings.len() == 0,
+        "leaf {} is outside a tree of height {}",
+        index,
+        siblings.len()
+    );
+    Ok(())
+}
+
+impl LeafProof {
+    /// Checks the proof against `expected_root`, which the client should get
+    /// from a source other than the proof itself, e.g. an anchored root.
+    pub fn verify(&self, expected_root: WHashOut<F>) -> anyhow::Result<()> {
+        ensure!(
+            self.root == expected_root,
+            "proof is against root {}, expected {}",
+            self.root,
+            expected_root
+        );
+        ensure_in_tree(self.index, &self.siblings)?;
+        ensure!(
+            compute_root_merkle_proof::<PoseidonHash, F>(
+                self.value,
+                F::from_canonical_u64(self.index),
+                &self.siblings
+            ) == self.root,
+            "merkle proof does not match its root"
+        );
+        Ok(())
+    }
+    /// The weight held by the leaf, failing if it does not hold one.
+    pub fn weight(&self) -> anyhow::Result<Weight> {
+        ensure!(
+            self.value.0.elements[1..]
+                .iter()
+                .all(|element| *element == F::ZERO),
+            "leaf {} does not hold a weight",
+            self.index
+        );
+        Weight::try_from(self.value.0.elements[0])
+    }
+}
+
+impl LeafDeltaProof {
+    pub fn verify(&self) -> anyhow::Result<()> {
+        ensure_in_tree(self.index, &self.siblings)?;
+        ensure!(
+            compute_root_merkle_proof::<PoseidonHash, F>(
+                self.old_value,
+                F::from_canonical_u64(self.index),
+                &self.siblings
+            ) == self.old_root,
+            "merkle proof does not match its old root"
+        );
+        ensure!(
+            compute_root_merkle_proof::<PoseidonHash, F>(
+                self.new_value,
+                F::from_canonical_u64(self.index),
+                &self.siblings
+            ) == self.new_root,
+            "merkle proof does not match its new root"
+        );
+        Ok(())
+    }
+}
+
+impl From<MerkleProof<F>> for LeafProof {
+    fn from(proof: MerkleProof<F>) -> Self {
+        Self {
+            index: proof.index.to_canonical_u64(),
+            value: proof.value,
+            root: proof.root,
+            siblings: proof.siblings,
+        }
+    }
+}
+
+impl From<DeltaMerkleProof<F>> for LeafDeltaProof {
+    fn from(proof: DeltaMerkleProof<F>) -> Self {
+        Self {
+            index: proof.index.to_canonical_u64(),
+            old_value: proof.old_value,
+            new_value: proof.new_value,
+            old_root: proof.old_root,
+            new_root: proof.new_root,
+            siblings: proof.siblings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::balance::{
+        accounts::{BalanceTx, TallySlot, VoterLeaf},
+        storage::BalanceStorage,
+        weight::{Weight, WeightDelta},
+    };
+
+    use super::{diff_leaves, LeafDeltaProof, LeafProof};
+
+    #[test]
+    fn test_leaf_proofs_survive_json() -> anyhow::Result<()> {
+        let mut storage = BalanceStorage::new(8, [3u32, 5].map(Weight::from).to_vec());
+        let update = storage.process_tx(BalanceTx::Vote {
+            voter: VoterLeaf::from_position(1),
+            slot: TallySlot::YES,
+            amount: WeightDelta::from(5),
+        })?;
+        let root = storage.tree.get_root()?;
+
+        let json = serde_json::to_string(&LeafProof::from(
+            storage.tree.get_leaf(TallySlot::YES.index())?,
+        ))?;
+        let proof: LeafProof = serde_json::from_str(&json)?;
+        proof.verify(root)?;
+        assert_eq!(proof.weight()?, Weight::from(5));
+        assert!(proof.verify(storage.initial_root()).is_err());
+
+        let mut forged = proof.clone();
+        forged.index ^= 1;
+        assert!(forged.verify(root).is_err());
+
+        let delta = LeafDeltaProof::from(update.receiver_update);
+        delta.verify()?;
+        assert_eq!(delta.new_root, root);
+        Ok(())
+    }
+    #[test]
+    fn test_diff_proves_changed_leaves() -> anyhow::Result<()> {
+        let mut storage = BalanceStorage::new(8, [3u32, 5, 2].map(Weight::from).to_vec());
+        let mut updates = Vec::new();
+        for (position, amount) in [(0, 3), (2, 2)] {
+            updates.push(storage.process_tx(BalanceTx::Vote {
+                voter: VoterLeaf::from_position(position),
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(amount),
+            })?);
+        }
+        let diff = diff_leaves(&storage, &updates)?;
+        // Both votes went to the same tally, which is listed once
+        let indices = diff.iter().map(|change| change.index).collect::<Vec<_>>();
+        assert_eq!(
+            indices,
+            [
+                TallySlot::YES.index(),
+                VoterLeaf::from_position(0).index(),
+                VoterLeaf::from_position(2).index()
+            ]
+        );
+        let root = storage.tree.get_root()?;
+        for change in &diff {
+            change.verify(storage.initial_root(), root)?;
+        }
+        assert_eq!(diff[0].old_proof.weight()?, Weight::ZERO);
+        assert_eq!(diff[0].new_proof.weight()?, Weight::from(5));
+        assert!(diff[1].verify(root, storage.initial_root()).is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_replayed_tree_proves_past_roots() -> anyhow::Result<()> {
+        let mut storage = BalanceStorage::new(8, [3u32, 5].map(Weight::from).to_vec());
+        let mut updates = Vec::new();
+        for (position, amount) in [(0, 3), (1, 5)] {
+            updates.push(storage.process_tx(BalanceTx::Vote {
+                voter: VoterLeaf::from_position(position),
+                slot: TallySlot::YES,
+                amount: WeightDelta::from(amount),
+            })?);
+        }
+        let replayed = storage.replayed(&updates[..1])?;
+        let past_root = updates[0].new_root();
+        let proof = LeafProof::from(replayed.tree.get_leaf(TallySlot::YES.index())?);
+        proof.verify(past_root)?;
+        assert_eq!(proof.weight()?, Weight::from(3));
+        assert!(proof.verify(storage.tree.get_root()?).is_err());
+
+        // Replaying nothing gives the initial tree back
+        let initial = storage.replayed(&[])?;
+        assert_eq!(initial.tree.get_root()?, storage.initial_root());
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::ensure;
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::{accounts::VoterLeaf, weight::Weight},
+    common::{hash::merkle::helpers::merkle_proof::MerkleProof, WHashOut},
+};
+
+type F = GoldilocksField;
+
+/// Proof that a voter was registered in the electorate of a proposal with a
+/// given weight, against the balance root the proposal started from.
+///
+/// Verifying it only needs the hashing from plonky2, so it can be done offline
+/// or from a WASM build of the library.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MembershipProof {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    pub weight: Weight,
+    /// Whether the proposal blinds voter indices, so that the leaf is not the one
+    /// of the voter id, see [`crate::proposal::blinding`].
+    #[serde(default)]
+    pub blinded: bool,
+    /// Merkle proof of the leaf of the voter against the initial balance root.
+    #[schema(value_type = Object)]
+    pub proof: MerkleProof<F>,
+}
+
+impl MembershipProof {
+    /// Reads the weight off the proven leaf, failing if it does not hold one.
+    pub fn new(proposal_id: Uuid, voter_id: u32, proof: MerkleProof<F>) -> anyhow::Result<Self> {
+        Ok(Self {
+            proposal_id,
+            voter_id,
+            weight: Weight::try_from(proof.value.0.elements[0])?,
+            blinded: false,
+            proof,
+        })
+    }
+    /// Checks the proof against `electorate_root`, which the voter should get
+    /// from a source other than the proof itself, e.g. the finalization certificate.
+    /// The leaf of a blinded proof is left to auditors, who can recompute it.
+    pub fn verify(&self, electorate_root: WHashOut<F>) -> anyhow::Result<()> {
+        let voter = VoterLeaf::from_voter_id(self.voter_id)?;
+        ensure!(
+            self.blinded || self.proof.index.to_canonical_u64() == voter.index(),
+            "proof is for leaf {}, not voter {}",
+            self.proof.index.to_canonical_u64(),
+            self.voter_id
+        );
+        ensure!(
+            self.proof.value == WHashOut::from_values(self.weight.get(), 0, 0, 0),
+            "proof does not carry a weight of {}",
+            self.weight
+        );
+        ensure!(
+            self.proof.root == electorate_root,
+            "proof is against root {}, expected {}",
+            self.proof.root,
+            electorate_root
+        );
+        ensure!(
+            self.proof.verify::<PoseidonHash>(),
+            "merkle proof does not match its root"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::balance::{accounts::VoterLeaf, storage::BalanceStorage, weight::Weight};
+
+    use super::MembershipProof;
+
+    #[test]
+    fn test_membership_proof() -> anyhow::Result<()> {
+        let storage = BalanceStorage::new(16, [3u32, 5, 7].map(Weight::from).to_vec());
+        let voter = VoterLeaf::from_position(1);
+        let proof = MembershipProof::new(
+            Uuid::new_v4(),
+            voter.index() as u32,
+            storage.initial_membership_proof(voter)?,
+        )?;
+        assert_eq!(proof.weight, Weight::from(5));
+        proof.verify(storage.initial_root())?;
+
+        let mut forged = proof.clone();
+        forged.weight = Weight::from(6);
+        assert!(forged.verify(storage.initial_root()).is_err());
+        Ok(())
+    }
+}
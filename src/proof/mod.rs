@@ -0,0 +1,9 @@
+pub mod attestation;
+pub mod certificate;
+pub mod codec;
+pub mod cycle;
+pub mod identity;
+pub mod leaf;
+pub mod membership;
+pub mod results;
+pub mod verify;
@@ -0,0 +1,274 @@
+//! An append-only merkle tree committing to the result of every finalized
+//! proposal, so that clients can check a result against a single root over
+//! all governance history rather than one certificate per proposal.
+//!
+//! Each leaf is the Poseidon hash of a [`ResultLeaf`], appended in the order
+//! proposals were finalized. Proposals are never removed from the store, so
+//! the tree is rebuilt the same from it on startup and on replicas. Its root
+//! can be anchored on-chain, see
+//! [`RootAnchor::anchor_result_root`](crate::chain::anchor::RootAnchor::anchor_result_root).
+
+use std::collections::HashMap;
+
+use anyhow::ensure;
+use plonky2::{
+    field::{
+        goldilocks_field::GoldilocksField,
+        types::{Field, PrimeField64},
+    },
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::weight::Weight,
+    chain::anchor::ResultRootRecord,
+    common::{
+        hash::{merkle::helpers::merkle_proof::MerkleProof, traits::hasher::FieldWHasher},
+        WHashOut,
+    },
+    nullifier::nullifier_set::proposal_id_to_elements,
+    proposal::store::ProposalStore,
+    utils::zmt::{
+        node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+        zero_merkle_tree::ZeroMerkleTree,
+    },
+};
+
+use super::certificate::{compute_statement_hash_with_content, FinalizationCertificate};
+
+type F = GoldilocksField;
+
+/// Height of the result tree, which holds up to 2^32 results.
+pub const RESULT_TREE_HEIGHT: u8 = 32;
+
+/// What the result tree commits to for a finalized proposal.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResultLeaf {
+    /// Position of the leaf, in the order proposals were finalized.
+    pub index: u64,
+    pub proposal_id: Uuid,
+    /// See [`compute_statement_hash_with_content`].
+    #[schema(value_type = String)]
+    pub statement_hash: WHashOut<F>,
+    pub yes_votes: Weight,
+    pub no_votes: Weight,
+    #[schema(value_type = String)]
+    pub final_root: WHashOut<F>,
+}
+
+impl ResultLeaf {
+    pub fn from_certificate(index: u64, certificate: &FinalizationCertificate) -> Self {
+        Self {
+            index,
+            proposal_id: certificate.proposal_id,
+            statement_hash: compute_statement_hash_with_content(
+                &certificate.statement,
+                certificate.content.as_ref(),
+            ),
+            yes_votes: certificate.yes_votes,
+            no_votes: certificate.no_votes,
+            final_root: certificate.final_root,
+        }
+    }
+    /// The Poseidon hash of the four elements of the proposal id, the statement
+    /// hash, the yes and no votes and the final root.
+    pub fn hash(&self) -> WHashOut<F> {
+        let mut elements = proposal_id_to_elements(&self.proposal_id).to_vec();
+        elements.extend(self.statement_hash.0.elements);
+        elements.push(F::from_canonical_u64(self.yes_votes.get()));
+        elements.push(F::from_canonical_u64(self.no_votes.get()));
+        elements.extend(self.final_root.0.elements);
+        PoseidonHash::w_hash_many(&elements)
+    }
+}
+
+/// Proof that a result is committed to by a root of the result tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResultInclusionProof {
+    pub leaf: ResultLeaf,
+    /// Merkle proof of the hash of the leaf against the root of the tree.
+    #[schema(value_type = Object)]
+    pub proof: MerkleProof<F>,
+}
+
+impl ResultInclusionProof {
+    /// Checks the proof against `root`, which the client should get from a
+    /// source other than the proof itself, e.g. the anchor contract.
+    pub fn verify(&self, root: WHashOut<F>) -> anyhow::Result<()> {
+        ensure!(
+            self.proof.index.to_canonical_u64() == self.leaf.index,
+            "proof is for leaf {}, not {}",
+            self.proof.index.to_canonical_u64(),
+            self.leaf.index
+        );
+        ensure!(
+            self.proof.value == self.leaf.hash(),
+            "proof does not carry the hash of the result"
+        );
+        ensure!(
+            self.proof.root == root,
+            "proof is against root {}, expected {}",
+            self.proof.root,
+            root
+        );
+        ensure!(
+            self.proof.verify::<PoseidonHash>(),
+            "merkle proof does not match its root"
+        );
+        Ok(())
+    }
+}
+
+/// The results of finalized proposals, and the roots of them anchored on-chain.
+pub struct ResultTree {
+    tree: ZeroMerkleTree<F, PoseidonHash, NodeStore>,
+    leaves: Vec<ResultLeaf>,
+    positions: HashMap<Uuid, u64>,
+    anchors: Vec<ResultRootRecord>,
+}
+
+impl Default for ResultTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResultTree {
+    pub fn new() -> Self {
+        Self {
+            tree: ZeroMerkleTree::new(
+                RESULT_TREE_HEIGHT,
+                NodeStore::Memory(SimpleNodeStore::new()),
+            ),
+            leaves: vec![],
+            positions: HashMap::new(),
+            anchors: vec![],
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+    pub fn root(&self) -> WHashOut<F> {
+        self.tree.get_root().unwrap()
+    }
+    /// Appends the result of `certificate`, failing if its proposal has one already.
+    pub fn append(&mut self, certificate: &FinalizationCertificate) -> anyhow::Result<u64> {
+        ensure!(
+            !self.positions.contains_key(&certificate.proposal_id),
+            "the result of proposal {} is committed already",
+            certificate.proposal_id
+        );
+        let index = self.leaves.len() as u64;
+        ensure!(index < self.tree.max_leaves(), "the result tree is full");
+        let leaf = ResultLeaf::from_certificate(index, certificate);
+        self.tree.set_leaf(index, leaf.hash())?;
+        self.positions.insert(leaf.proposal_id, index);
+        self.leaves.push(leaf);
+        Ok(index)
+    }
+    /// Appends the results of the proposals finalized since the last sync, by
+    /// time of finalization then id, returning how many were appended.
+    pub fn sync(&mut self, proposals: &ProposalStore) -> anyhow::Result<usize> {
+        let mut finalized: Vec<_> = proposals
+            .iter()
+            .filter(|(id, _)| !self.positions.contains_key(id))
+            .filter_map(|(id, proposal)| {
+                let certificate = proposal.certificate.as_ref()?;
+                Some((proposal.finalized_at.unwrap_or_default(), *id, certificate))
+            })
+            .collect();
+        finalized.sort_by_key(|(finalized_at, id, _)| (*finalized_at, *id));
+        for (_, _, certificate) in &finalized {
+            self.append(certificate)?;
+        }
+        Ok(finalized.len())
+    }
+    /// Proves the result of `proposal_id` against the current root, if committed.
+    pub fn prove(&self, proposal_id: &Uuid) -> anyhow::Result<Option<ResultInclusionProof>> {
+        let index = match self.positions.get(proposal_id) {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+        Ok(Some(ResultInclusionProof {
+            leaf: self.leaves[index as usize].clone(),
+            proof: self.tree.get_leaf(index)?,
+        }))
+    }
+    /// Records that the root of the first `record.size` results was anchored.
+    pub fn record_anchor(&mut self, record: ResultRootRecord) {
+        self.anchors.push(record);
+    }
+    pub fn last_anchor(&self) -> Option<&ResultRootRecord> {
+        self.anchors.last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        balance::weight::Weight,
+        common::WHashOut,
+        proof::certificate::FinalizationCertificate,
+        proposal::rules::{ProposalOutcome, TiePolicy},
+    };
+
+    use super::ResultTree;
+
+    fn certificate(yes_votes: u32) -> FinalizationCertificate {
+        FinalizationCertificate {
+            proposal_id: Uuid::new_v4(),
+            statement: "Fund the audit".to_string(),
+            content: None,
+            action: Default::default(),
+            initial_root: WHashOut::ZERO,
+            final_root: WHashOut::from_values(yes_votes as u64, 1, 2, 3),
+            yes_votes: Weight::from(yes_votes),
+            no_votes: Weight::from(1),
+            outcome: ProposalOutcome::Passed,
+            tie_policy: TiePolicy::default(),
+            beacon: None,
+            beacon_block: None,
+            revote: None,
+            circuit_id: String::new(),
+            nullifier_root: None,
+            binding: WHashOut::ZERO,
+            anchors: vec![],
+            transcript_digest: [0; 32],
+            timestamps: vec![],
+            issuer: None,
+            dependencies: vec![],
+            approvals: vec![],
+            rules: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_result_inclusion_proof() -> anyhow::Result<()> {
+        let mut results = ResultTree::new();
+        let (first, second) = (certificate(5), certificate(7));
+        results.append(&first)?;
+        let first_root = results.root();
+        results.append(&second)?;
+        assert!(results.append(&first).is_err());
+        assert_ne!(results.root(), first_root);
+
+        let proof = results.prove(&first.proposal_id)?.unwrap();
+        assert_eq!(proof.leaf.index, 0);
+        proof.verify(results.root())?;
+        assert!(proof.verify(first_root).is_err());
+
+        let mut forged = results.prove(&second.proposal_id)?.unwrap();
+        forged.leaf.yes_votes = Weight::from(8);
+        assert!(forged.verify(results.root()).is_err());
+        assert!(results.prove(&Uuid::new_v4())?.is_none());
+        Ok(())
+    }
+}
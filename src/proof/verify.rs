@@ -0,0 +1,212 @@
+use anyhow::ensure;
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    plonk::config::{GenericConfig, PoseidonGoldilocksConfig},
+};
+use qed_verifier::{check_public_inputs, ExpectedInputs, ProvenRules, PublicHash};
+
+use crate::{
+    balance::{accounts::Tally, weight::Weight},
+    circuits::{
+        shard_root::{parse_finalization_circuit_id, ShardRootCircuit},
+        update_balance::{
+            UpdateBalanceCircuit, UpdateBalanceShape, FINAL_ROOT_PUBLIC_INPUTS,
+            INITIAL_ROOT_PUBLIC_INPUTS,
+        },
+    },
+    common::WHashOut,
+    proposal::{
+        action::ProposalAction,
+        content::StatementContent,
+        dependency::{compute_dependencies_hash, DependencyResult},
+    },
+};
+
+use super::{
+    certificate::{compute_action_hash, compute_statement_hash_with_content},
+    codec::ProofEnvelope,
+};
+
+const D: usize = 2;
+type C = PoseidonGoldilocksConfig;
+type F = <C as GenericConfig<D>>::F;
+
+/// The public inputs a hash is exposed as.
+pub fn public_hash(hash: &WHashOut<GoldilocksField>) -> PublicHash {
+    PublicHash(hash.0.elements.map(|x| x.to_canonical_u64()))
+}
+
+impl ProofEnvelope {
+    /// Fails unless the public inputs of a finalization proof start from
+    /// `initial_root` and end at `final_root`. Only compares the inputs the
+    /// envelope lists; the proof itself still has to be verified against them.
+    pub fn expect_public_inputs(
+        &self,
+        initial_root: WHashOut<GoldilocksField>,
+        final_root: WHashOut<GoldilocksField>,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            self.public_inputs.len() >= FINAL_ROOT_PUBLIC_INPUTS.end,
+            "proof has {} public inputs, too few to hold its roots",
+            self.public_inputs.len()
+        );
+        ensure!(
+            public_hash(&initial_root).is_at(&self.public_inputs, INITIAL_ROOT_PUBLIC_INPUTS),
+            "proof does not start from the expected initial root"
+        );
+        ensure!(
+            public_hash(&final_root).is_at(&self.public_inputs, FINAL_ROOT_PUBLIC_INPUTS),
+            "proof does not end at the expected final root"
+        );
+        Ok(())
+    }
+}
+
+/// The public inputs a finalization proof of a proposal with the given roots,
+/// statement, document, action, dependency results and rules has to expose with
+/// the circuit of `shape`, as the standalone verifier checks them.
+#[allow(clippy::too_many_arguments)]
+pub fn expected_public_inputs(
+    shape: &UpdateBalanceShape,
+    initial_root: WHashOut<GoldilocksField>,
+    final_root: WHashOut<GoldilocksField>,
+    statement: &str,
+    content: Option<&StatementContent>,
+    action: &ProposalAction,
+    dependencies: &[DependencyResult],
+    rules: &ProvenRules,
+) -> ExpectedInputs {
+    ExpectedInputs {
+        initial_root: public_hash(&initial_root),
+        final_root: public_hash(&final_root),
+        statement_hash: public_hash(&compute_statement_hash_with_content(statement, content)),
+        action_hash: public_hash(&compute_action_hash(action)),
+        // A circuit without dependencies rejects a proposal with some
+        dependencies_hash: (shape.dependencies || !dependencies.is_empty())
+            .then(|| public_hash(&compute_dependencies_hash(dependencies))),
+        rules: *rules,
+    }
+}
+
+/// Verifies the finalization proof of a proposal without any server state,
+/// checking that it was made for a proposal with the given statement, document
+/// and action, with the given dependency results and under the given rules, as
+/// listed in its certificate.
+///
+/// The circuit is rebuilt from the circuit id recorded in the envelope, so this
+/// is as expensive as building the circuit once; it does not require proving.
+/// Proofs of sharded proposals are checked against the shard root circuit over
+/// the update balance circuit of their shards, see [`ShardRootCircuit`].
+#[allow(clippy::too_many_arguments)]
+pub fn verify_finalization(
+    proof_envelope: &ProofEnvelope,
+    expected_initial_root: WHashOut<GoldilocksField>,
+    expected_final_root: WHashOut<GoldilocksField>,
+    expected_statement: &str,
+    expected_content: Option<&StatementContent>,
+    expected_action: &ProposalAction,
+    expected_dependencies: &[DependencyResult],
+    expected_rules: &ProvenRules,
+) -> anyhow::Result<Tally> {
+    let (shape, shard_count) = parse_finalization_circuit_id(&proof_envelope.circuit_id)?;
+    let circuit = UpdateBalanceCircuit::<F, C, D>::new(shape);
+    let shard_root = shard_count.map(|shard_count| ShardRootCircuit::new(&circuit, shard_count));
+    let circuit_data = match &shard_root {
+        Some(shard_root) => &shard_root.base_circuit_data,
+        None => &circuit.base_circuit_data,
+    };
+    let proof = proof_envelope.to_proof(circuit_data)?;
+
+    let expected = expected_public_inputs(
+        &shape,
+        expected_initial_root,
+        expected_final_root,
+        expected_statement,
+        expected_content,
+        expected_action,
+        expected_dependencies,
+        expected_rules,
+    );
+    let tally = check_public_inputs(
+        &proof_envelope.circuit_id,
+        &proof_envelope.public_inputs,
+        &expected,
+    )?;
+    circuit_data.verify(proof)?;
+
+    Ok(Tally {
+        yes_votes: Weight::try_from(tally.yes_votes)?,
+        no_votes: Weight::try_from(tally.no_votes)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::field::goldilocks_field::GoldilocksField;
+
+    use crate::{
+        circuits::{
+            quadratic::VotingPolicy,
+            update_balance::{
+                dependencies_hash_public_inputs, public_input_layout, update_balance_circuit_id,
+                UpdateBalanceShape,
+            },
+        },
+        common::WHashOut,
+        proof::codec::{ProofEnvelope, PROOF_ENVELOPE_VERSION},
+    };
+
+    use super::public_hash;
+
+    #[test]
+    fn test_expect_public_inputs_checks_both_roots() {
+        let root = |value: u64| WHashOut::<GoldilocksField>::try_from(&[value; 4]).unwrap();
+        let envelope = ProofEnvelope {
+            version: PROOF_ENVELOPE_VERSION,
+            circuit_id: "update_balance:1:32:32".to_string(),
+            common_data_hash: vec![1; 32],
+            public_inputs: vec![1, 1, 1, 1, 2, 2, 2, 2, 0, 0],
+            proof_bytes: vec![],
+        };
+        assert!(envelope.expect_public_inputs(root(1), root(2)).is_ok());
+        assert!(envelope.expect_public_inputs(root(2), root(2)).is_err());
+        assert!(envelope.expect_public_inputs(root(1), root(1)).is_err());
+        let truncated = ProofEnvelope {
+            public_inputs: vec![1, 1, 1, 1],
+            ..envelope
+        };
+        assert!(truncated.expect_public_inputs(root(1), root(2)).is_err());
+    }
+
+    #[test]
+    fn test_standalone_verifier_reads_the_same_inputs() {
+        let hash = WHashOut::<GoldilocksField>::try_from(&[1, 2, 3, u64::MAX - 1]).unwrap();
+        assert_eq!(
+            serde_json::to_string(&public_hash(&hash)).unwrap(),
+            serde_json::to_string(&hash).unwrap()
+        );
+        for (conviction, dependencies) in [(false, false), (false, true), (true, true)] {
+            let shape = UpdateBalanceShape {
+                number_updates: 4,
+                tree_height: 32,
+                balance_bits: 32,
+                conviction,
+                voting_policy: VotingPolicy::Quadratic,
+                dependencies,
+                vesting: true,
+                deadline: false,
+                min_transfer: false,
+            };
+            assert_eq!(
+                qed_verifier::circuit_dependencies_hash_range(&update_balance_circuit_id(&shape))
+                    .unwrap(),
+                dependencies_hash_public_inputs(&shape)
+            );
+            assert_eq!(
+                qed_verifier::PublicInputLayout::of_circuit(&update_balance_circuit_id(&shape))
+                    .unwrap(),
+                public_input_layout(&shape)
+            );
+        }
+    }
+}
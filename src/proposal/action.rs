@@ -0,0 +1,147 @@
+//! What passing a proposal commits the DAO to, beyond the text of its statement.
+//!
+//! The action is fixed when the proposal is created and its hash, see
+//! [`compute_action_hash`](crate::proof::certificate::compute_action_hash), is a
+//! public input of the finalization proof, so a contract executing the action
+//! can check it is the one that was voted on.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use web3::types::{Address, U256};
+
+use crate::errors::{ApiError, ApiErrorCode};
+
+/// Longest name of a parameter a [`ProposalAction::ParameterChange`] sets, in bytes.
+pub const MAX_PARAMETER_NAME_BYTES: usize = 64;
+/// Longest value a [`ProposalAction::ParameterChange`] sets a parameter to, in bytes.
+pub const MAX_PARAMETER_VALUE_BYTES: usize = 256;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProposalAction {
+    /// Nothing beyond the statement, e.g. for signalling votes.
+    #[default]
+    TextOnly,
+    /// Pays `amount` base units of `token` out of the treasury to `recipient`.
+    TreasuryTransfer {
+        /// ERC-20 token contract, ether if not set.
+        #[schema(value_type = Option<String>)]
+        token: Option<Address>,
+        #[schema(value_type = String)]
+        recipient: Address,
+        #[schema(value_type = String)]
+        amount: U256,
+    },
+    /// Sets the governance parameter `parameter` to `value`.
+    ParameterChange { parameter: String, value: String },
+}
+
+fn invalid(message: impl Into<String>) -> ApiError {
+    ApiError::new(ApiErrorCode::InvalidAction, message)
+}
+
+impl ProposalAction {
+    /// Fails unless the action can be executed as it stands: transfers move a
+    /// positive amount between non-zero addresses, and parameter changes name
+    /// the parameter as an identifier and give it a value.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        match self {
+            ProposalAction::TextOnly => Ok(()),
+            ProposalAction::TreasuryTransfer {
+                token,
+                recipient,
+                amount,
+            } => {
+                if recipient.is_zero() || token.map_or(false, |token| token.is_zero()) {
+                    return Err(invalid("Transfers cannot involve the zero address"));
+                }
+                if amount.is_zero() {
+                    return Err(invalid("Transfers must move a positive amount"));
+                }
+                Ok(())
+            }
+            ProposalAction::ParameterChange { parameter, value } => {
+                let is_identifier = !parameter.is_empty()
+                    && parameter.len() <= MAX_PARAMETER_NAME_BYTES
+                    && parameter
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+                if !is_identifier {
+                    return Err(invalid(format!(
+                        "Parameter names are 1 to {} letters, digits, underscores or dots",
+                        MAX_PARAMETER_NAME_BYTES
+                    )));
+                }
+                if value.is_empty() || value.len() > MAX_PARAMETER_VALUE_BYTES {
+                    return Err(invalid(format!(
+                        "Parameter values are 1 to {} bytes long",
+                        MAX_PARAMETER_VALUE_BYTES
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Address, U256};
+
+    use super::ProposalAction;
+    use crate::{errors::ApiErrorCode, proof::certificate::compute_action_hash};
+
+    #[test]
+    fn test_validates_and_hashes_actions() {
+        let transfer = ProposalAction::TreasuryTransfer {
+            token: None,
+            recipient: Address::repeat_byte(0x11),
+            amount: U256::from(1000),
+        };
+        assert!(transfer.validate().is_ok());
+        let invalid = [
+            ProposalAction::TreasuryTransfer {
+                token: Some(Address::zero()),
+                recipient: Address::repeat_byte(0x11),
+                amount: U256::from(1000),
+            },
+            ProposalAction::TreasuryTransfer {
+                token: None,
+                recipient: Address::repeat_byte(0x11),
+                amount: U256::zero(),
+            },
+            ProposalAction::ParameterChange {
+                parameter: "quorum percent".to_string(),
+                value: "20".to_string(),
+            },
+            ProposalAction::ParameterChange {
+                parameter: "quorum_percent".to_string(),
+                value: String::new(),
+            },
+        ];
+        for action in invalid {
+            assert_eq!(
+                action.validate().unwrap_err().code,
+                ApiErrorCode::InvalidAction
+            );
+        }
+
+        let json = r#"{"kind":"parameter_change","parameter":"quorum_percent","value":"20"}"#;
+        let change: ProposalAction = serde_json::from_str(json).unwrap();
+        assert!(change.validate().is_ok());
+        // Every field is committed to, and no action hashes like another
+        let other_value = ProposalAction::ParameterChange {
+            parameter: "quorum_percent".to_string(),
+            value: "2".to_string(),
+        };
+        let hashes = [
+            compute_action_hash(&ProposalAction::TextOnly),
+            compute_action_hash(&transfer),
+            compute_action_hash(&change),
+            compute_action_hash(&other_value),
+        ];
+        for (i, hash) in hashes.iter().enumerate() {
+            assert!(hashes[i + 1..].iter().all(|other| other != hash));
+        }
+    }
+}
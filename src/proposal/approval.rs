@@ -0,0 +1,276 @@
+//! Finalization by a threshold of finalizers. A proposal created with a
+//! [`FinalizerPolicy`] is finalized once `threshold` of its finalizers approved,
+//! rather than when its proposer asks. Each approval is an Ed25519 signature
+//! over [`approval_message`] by the key the finalizer was registered with, and
+//! the approvals end up in the finalization certificate and the attestations of
+//! the result.
+
+use std::collections::BTreeSet;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiErrorCode};
+
+use super::Proposal;
+
+/// Prefixes every approval message.
+pub const APPROVAL_DOMAIN: &str = "qed-dapp:finalize-approval:v1";
+
+/// Most finalizers a proposal can have.
+pub const MAX_FINALIZERS: usize = 32;
+
+/// A finalizer and the key its approvals are signed with.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Finalizer {
+    pub finalizer_id: u32,
+    /// Hex encoded Ed25519 key
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub public_key: [u8; 32],
+}
+
+/// Who finalizes a proposal: any `threshold` of `finalizers`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizerPolicy {
+    pub threshold: usize,
+    pub finalizers: Vec<Finalizer>,
+}
+
+/// An accepted approval, which anyone can check against [`approval_message`].
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizeApproval {
+    pub finalizer_id: u32,
+    /// Hex encoded Ed25519 key of the finalizer
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub public_key: [u8; 32],
+    /// Hex encoded signature over [`approval_message`]
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub signature: Vec<u8>,
+    pub approved_at: u64,
+}
+
+/// The message `finalizer_id` signs to approve finalizing `proposal_id`: the
+/// domain, the proposal id and the finalizer id, separated by colons.
+pub fn approval_message(proposal_id: &Uuid, finalizer_id: u32) -> Vec<u8> {
+    format!("{}:{}:{}", APPROVAL_DOMAIN, proposal_id, finalizer_id).into_bytes()
+}
+
+impl FinalizeApproval {
+    /// Checks the signature against the key the approval names. Callers still
+    /// have to check that key is the one the finalizer was registered with.
+    pub fn verify(&self, proposal_id: &Uuid) -> anyhow::Result<()> {
+        VerifyingKey::from_bytes(&self.public_key)?.verify_strict(
+            &approval_message(proposal_id, self.finalizer_id),
+            &Signature::from_slice(&self.signature)?,
+        )?;
+        Ok(())
+    }
+}
+
+impl FinalizerPolicy {
+    /// Fails unless there are at most [`MAX_FINALIZERS`] finalizers with distinct
+    /// ids and valid keys, and the threshold is between one and their number.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let invalid =
+            |message: String| Err(ApiError::new(ApiErrorCode::InvalidFinalizers, message));
+        if self.finalizers.len() > MAX_FINALIZERS {
+            return invalid(format!(
+                "{} finalizers given, at most {} are allowed",
+                self.finalizers.len(),
+                MAX_FINALIZERS
+            ));
+        }
+        if self.threshold == 0 || self.threshold > self.finalizers.len() {
+            return invalid(format!(
+                "A threshold of {} cannot be met by {} finalizers",
+                self.threshold,
+                self.finalizers.len()
+            ));
+        }
+        let mut seen = BTreeSet::new();
+        for finalizer in &self.finalizers {
+            if !seen.insert(finalizer.finalizer_id) {
+                return invalid(format!(
+                    "Finalizer {} is given more than once",
+                    finalizer.finalizer_id
+                ));
+            }
+            if VerifyingKey::from_bytes(&finalizer.public_key).is_err() {
+                return invalid(format!(
+                    "The key of finalizer {} is not an Ed25519 key",
+                    finalizer.finalizer_id
+                ));
+            }
+        }
+        Ok(())
+    }
+    pub fn finalizer(&self, finalizer_id: u32) -> Option<&Finalizer> {
+        self.finalizers
+            .iter()
+            .find(|finalizer| finalizer.finalizer_id == finalizer_id)
+    }
+}
+
+impl Proposal {
+    /// Records the approval of `finalizer_id`, with `signature` hex encoded,
+    /// failing unless the proposal has a finalizer policy naming it, the
+    /// signature is by its key and it has not approved yet.
+    pub fn approve_finalization(
+        &mut self,
+        proposal_id: &Uuid,
+        finalizer_id: u32,
+        signature: &str,
+        now: u64,
+    ) -> Result<FinalizeApproval, ApiError> {
+        let policy = self.finalizers.as_ref().ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::NotFinalizer,
+                "The proposal is finalized by its proposer, without approvals",
+            )
+        })?;
+        let finalizer = policy.finalizer(finalizer_id).ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::NotFinalizer,
+                format!("{} is not a finalizer of the proposal", finalizer_id),
+            )
+        })?;
+        if self
+            .approvals
+            .iter()
+            .any(|approval| approval.finalizer_id == finalizer_id)
+        {
+            return Err(ApiError::new(
+                ApiErrorCode::AlreadyApproved,
+                format!("Finalizer {} has already approved", finalizer_id),
+            ));
+        }
+        let approval = FinalizeApproval {
+            finalizer_id,
+            public_key: finalizer.public_key,
+            signature: hex::decode(signature.trim_start_matches("0x")).map_err(|err| {
+                ApiError::new(
+                    ApiErrorCode::InvalidApprovalSignature,
+                    format!("Invalid signature encoding: {}", err),
+                )
+            })?,
+            approved_at: now,
+        };
+        approval.verify(proposal_id).map_err(|err| {
+            ApiError::new(
+                ApiErrorCode::InvalidApprovalSignature,
+                format!(
+                    "The approval is not signed by the key of finalizer {}: {}",
+                    finalizer_id, err
+                ),
+            )
+        })?;
+        self.approvals.push(approval.clone());
+        Ok(approval)
+    }
+    /// Whether the proposal can be proven: always for proposals finalized by
+    /// their proposer, and once enough finalizers approved otherwise.
+    pub fn finalization_approved(&self) -> bool {
+        self.finalizers
+            .as_ref()
+            .map_or(true, |policy| self.approvals.len() >= policy.threshold)
+    }
+    /// Whether `finalizer_id` may start proving the proposal: its proposer if it
+    /// has no finalizer policy, and otherwise the proposer or one of its finalizers.
+    pub fn may_finalize(&self, finalizer_id: u32) -> bool {
+        finalizer_id == self.proposer_id
+            || self
+                .finalizers
+                .as_ref()
+                .map_or(false, |policy| policy.finalizer(finalizer_id).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use uuid::Uuid;
+
+    use super::{approval_message, Finalizer, FinalizerPolicy};
+    use crate::{
+        errors::ApiErrorCode,
+        proposal::{rules::ProposalRules, Proposal},
+    };
+
+    #[test]
+    fn test_finalization_waits_for_the_threshold() {
+        let id = Uuid::new_v4();
+        let keys: Vec<SigningKey> = (1..=3u8)
+            .map(|seed| SigningKey::from_bytes(&[seed; 32]))
+            .collect();
+        let policy = FinalizerPolicy {
+            threshold: 2,
+            finalizers: keys
+                .iter()
+                .zip(10..)
+                .map(|(key, finalizer_id)| Finalizer {
+                    finalizer_id,
+                    public_key: key.verifying_key().to_bytes(),
+                })
+                .collect(),
+        };
+        assert!(policy.validate().is_ok());
+        let sign = |key: &SigningKey, finalizer_id: u32| {
+            hex::encode(key.sign(&approval_message(&id, finalizer_id)).to_bytes())
+        };
+        let mut proposal =
+            Proposal::new("Fund the audit".to_string(), 1, 0, ProposalRules::default()).unwrap();
+        proposal.finalizers = Some(policy.clone());
+        assert!(proposal.may_finalize(1) && proposal.may_finalize(11));
+        assert!(!proposal.may_finalize(2));
+
+        // Signed by the key of another finalizer
+        assert_eq!(
+            proposal
+                .approve_finalization(&id, 10, &sign(&keys[1], 10), 5)
+                .unwrap_err()
+                .code,
+            ApiErrorCode::InvalidApprovalSignature
+        );
+        let approval = proposal
+            .approve_finalization(&id, 10, &sign(&keys[0], 10), 5)
+            .unwrap();
+        assert!(approval.verify(&id).is_ok());
+        assert!(approval.verify(&Uuid::new_v4()).is_err());
+        assert!(!proposal.finalization_approved());
+        assert_eq!(
+            proposal
+                .approve_finalization(&id, 10, &sign(&keys[0], 10), 6)
+                .unwrap_err()
+                .code,
+            ApiErrorCode::AlreadyApproved
+        );
+        assert_eq!(
+            proposal
+                .approve_finalization(&id, 13, &sign(&keys[0], 13), 6)
+                .unwrap_err()
+                .code,
+            ApiErrorCode::NotFinalizer
+        );
+        proposal
+            .approve_finalization(&id, 12, &sign(&keys[2], 12), 7)
+            .unwrap();
+        assert!(proposal.finalization_approved());
+
+        let unmet = FinalizerPolicy {
+            threshold: 4,
+            ..policy
+        };
+        assert_eq!(
+            unmet.validate().unwrap_err().code,
+            ApiErrorCode::InvalidFinalizers
+        );
+    }
+}
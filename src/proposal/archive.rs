@@ -0,0 +1,395 @@
+//! Self-contained archives of finalized proposals, for long-term storage and
+//! for third parties to replicate a result without the server.
+//!
+//! An archive is a tar file holding, as JSON, what the proposal was created
+//! with, a commitment to its electorate, every balance update from the initial
+//! to the final root, the finalization certificate and proof and, when the
+//! circuit is known, the verifier data of the circuit. Its first file is a
+//! [`ArchiveManifest`] listing the SHA-256 of every other file, which
+//! [`open_archive`] checks.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, ensure};
+use plonky2::{
+    field::{extension::Extendable, goldilocks_field::GoldilocksField},
+    hash::hash_types::RichField,
+    plonk::{
+        circuit_data::CircuitData,
+        config::{AlgebraicHasher, GenericConfig},
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{circuits::registry::CircuitRecord, common::WHashOut};
+
+use super::{action::ProposalAction, content::StatementContent, rules::ProposalRules, Proposal};
+
+/// Current version of the archive layout.
+pub const ARCHIVE_VERSION: u16 = 1;
+
+/// Path of the manifest, the first file of every archive.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Size of a tar header and of the blocks file contents are padded to.
+const TAR_BLOCK: usize = 512;
+
+/// A file of an archive other than the manifest.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub size: u64,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub sha256: [u8; 32],
+}
+
+/// What an archive holds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub version: u16,
+    pub proposal_id: Uuid,
+    pub archived_at: u64,
+    pub files: Vec<ArchiveEntry>,
+}
+
+/// What a proposal was created with and how it ended, `proposal.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedProposal {
+    pub proposal_id: Uuid,
+    pub dao_id: String,
+    pub statement: String,
+    pub content: Option<StatementContent>,
+    pub action: ProposalAction,
+    pub proposer_id: u32,
+    pub created_at: u64,
+    pub rules: ProposalRules,
+    pub depends_on: Vec<Uuid>,
+    pub finalized_at: Option<u64>,
+}
+
+/// Commitment to the electorate of a proposal, `roster.json`. The initial root
+/// commits to the balance of every voter; the digests let anyone holding the
+/// roster check it without the archive revealing it.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RosterCommitment {
+    pub initial_root: WHashOut<GoldilocksField>,
+    pub tree_height: usize,
+    pub electorate_size: usize,
+    /// SHA-256 of the initial balances of the voters, as little endian u64s in
+    /// voter id order.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub balances_sha256: [u8; 32],
+    /// SHA-256 of the DIDs of the voters, one per line in voter id order, on
+    /// proposals whose electorate was registered by DID.
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    pub dids_sha256: Option<[u8; 32]>,
+}
+
+/// The verifier data of the circuit a finalization proof was produced with.
+pub struct VerifierFiles {
+    pub record: CircuitRecord,
+    /// The verifier-only circuit data, as JSON.
+    pub verifier_only: Vec<u8>,
+    /// The common circuit data, as JSON.
+    pub common: Vec<u8>,
+}
+
+impl VerifierFiles {
+    pub fn new<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>(
+        circuit_id: &str,
+        circuit_data: &CircuitData<F, C, D>,
+    ) -> anyhow::Result<Self>
+    where
+        <C as GenericConfig<D>>::Hasher: AlgebraicHasher<F>,
+    {
+        Ok(Self {
+            record: CircuitRecord::new(circuit_id, circuit_data),
+            verifier_only: serde_json::to_vec(&circuit_data.verifier_only)?,
+            common: serde_json::to_vec(&circuit_data.common)?,
+        })
+    }
+}
+
+/// Archives the finalized proposal `id` at time `archived_at`, with the
+/// verifier data of its circuit if given.
+pub fn build_archive(
+    id: Uuid,
+    proposal: &Proposal,
+    verifier: Option<&VerifierFiles>,
+    archived_at: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let (certificate, proof) = match (&proposal.certificate, &proposal.proof) {
+        (Some(certificate), Some(proof)) => (certificate, proof),
+        _ => bail!("proposal {} is not finalized", id),
+    };
+    let storage = &proposal.storage;
+    let balances: Vec<u8> = storage
+        .initial_balances()
+        .iter()
+        .flat_map(|balance| balance.get().to_le_bytes())
+        .collect();
+    let dids = (!proposal.voter_dids.is_empty()).then(|| {
+        let lines: Vec<String> = proposal
+            .voter_dids
+            .iter()
+            .map(|did| did.to_string())
+            .collect();
+        Sha256::digest(lines.join("\n")).into()
+    });
+    let record = ArchivedProposal {
+        proposal_id: id,
+        dao_id: proposal.dao_id.clone(),
+        statement: proposal.statement.clone(),
+        content: proposal.content.clone(),
+        action: proposal.action.clone(),
+        proposer_id: proposal.proposer_id,
+        created_at: proposal.created_at,
+        rules: proposal.rules.clone(),
+        depends_on: proposal.depends_on.clone(),
+        finalized_at: proposal.finalized_at,
+    };
+    let roster = RosterCommitment {
+        initial_root: storage.initial_root(),
+        tree_height: storage.tree_height(),
+        electorate_size: storage.initial_balances().len(),
+        balances_sha256: Sha256::digest(balances).into(),
+        dids_sha256: dids,
+    };
+    let mut files = vec![
+        ("proposal.json".to_string(), serde_json::to_vec(&record)?),
+        ("roster.json".to_string(), serde_json::to_vec(&roster)?),
+        (
+            "updates.json".to_string(),
+            serde_json::to_vec(&proposal.updates)?,
+        ),
+        (
+            "certificate.json".to_string(),
+            serde_json::to_vec(certificate)?,
+        ),
+        ("proof.json".to_string(), proof.to_json()?.into_bytes()),
+    ];
+    if let Some(verifier) = verifier {
+        files.push((
+            "verifier/circuit.json".to_string(),
+            serde_json::to_vec(&verifier.record)?,
+        ));
+        files.push((
+            "verifier/verifier_only_circuit_data.json".to_string(),
+            verifier.verifier_only.clone(),
+        ));
+        files.push((
+            "verifier/common_circuit_data.json".to_string(),
+            verifier.common.clone(),
+        ));
+    }
+    write_archive(id, &files, archived_at)
+}
+
+/// Writes `files` into a tar archive, preceded by their manifest.
+pub fn write_archive(
+    id: Uuid,
+    files: &[(String, Vec<u8>)],
+    archived_at: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        proposal_id: id,
+        archived_at,
+        files: files
+            .iter()
+            .map(|(path, bytes)| ArchiveEntry {
+                path: path.clone(),
+                size: bytes.len() as u64,
+                sha256: Sha256::digest(bytes).into(),
+            })
+            .collect(),
+    };
+    let mut tar = vec![];
+    append_tar_file(
+        &mut tar,
+        MANIFEST_FILE,
+        &serde_json::to_vec_pretty(&manifest)?,
+        archived_at,
+    )?;
+    for (path, bytes) in files {
+        append_tar_file(&mut tar, path, bytes, archived_at)?;
+    }
+    // Two empty blocks end the archive
+    tar.resize(tar.len() + 2 * TAR_BLOCK, 0);
+    Ok(tar)
+}
+
+/// Reads an archive, failing unless it starts with a manifest of a known
+/// version that lists every other file with its size and hash.
+pub fn open_archive(bytes: &[u8]) -> anyhow::Result<(ArchiveManifest, BTreeMap<String, Vec<u8>>)> {
+    let mut files = read_tar(bytes)?;
+    ensure!(
+        files.first().map(|(path, _)| path.as_str()) == Some(MANIFEST_FILE),
+        "the archive does not start with {}",
+        MANIFEST_FILE
+    );
+    let manifest: ArchiveManifest = serde_json::from_slice(&files.remove(0).1)?;
+    ensure!(
+        manifest.version == ARCHIVE_VERSION,
+        "unsupported archive version {} (expected {})",
+        manifest.version,
+        ARCHIVE_VERSION
+    );
+    ensure!(
+        files.len() == manifest.files.len(),
+        "the archive holds {} files, its manifest lists {}",
+        files.len(),
+        manifest.files.len()
+    );
+    for ((path, bytes), entry) in files.iter().zip(&manifest.files) {
+        ensure!(
+            *path == entry.path,
+            "{} is not the file the manifest lists next, {}",
+            path,
+            entry.path
+        );
+        ensure!(
+            bytes.len() as u64 == entry.size && Sha256::digest(bytes)[..] == entry.sha256,
+            "{} does not match its size or hash in the manifest",
+            path
+        );
+    }
+    Ok((manifest, files.into_iter().collect()))
+}
+
+/// Writes `value` into `field` as a zero padded octal number followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) -> anyhow::Result<()> {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    ensure!(
+        digits.len() < field.len(),
+        "{} does not fit in a tar header field",
+        value
+    );
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+    Ok(())
+}
+
+fn read_octal(field: &[u8]) -> anyhow::Result<u64> {
+    let digits = std::str::from_utf8(field)?.trim_matches(|c: char| c == '\0' || c == ' ');
+    Ok(u64::from_str_radix(digits, 8)?)
+}
+
+/// Sum of the bytes of a header, its checksum field counted as spaces.
+fn header_checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| match i {
+            148..=155 => b' ' as u64,
+            _ => *byte as u64,
+        })
+        .sum()
+}
+
+/// Appends a regular file in the ustar format.
+fn append_tar_file(tar: &mut Vec<u8>, path: &str, bytes: &[u8], mtime: u64) -> anyhow::Result<()> {
+    ensure!(
+        !path.is_empty() && path.len() <= 100,
+        "archive paths are 1 to 100 bytes long"
+    );
+    let mut header = [0u8; TAR_BLOCK];
+    header[..path.len()].copy_from_slice(path.as_bytes());
+    write_octal(&mut header[100..108], 0o644)?;
+    write_octal(&mut header[108..116], 0)?;
+    write_octal(&mut header[116..124], 0)?;
+    write_octal(&mut header[124..136], bytes.len() as u64)?;
+    write_octal(&mut header[136..148], mtime)?;
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let checksum = header_checksum(&header);
+    write_octal(&mut header[148..155], checksum)?;
+    header[155] = b' ';
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(bytes);
+    let padding = (TAR_BLOCK - bytes.len() % TAR_BLOCK) % TAR_BLOCK;
+    tar.resize(tar.len() + padding, 0);
+    Ok(())
+}
+
+/// The regular files of a ustar archive, in order.
+fn read_tar(tar: &[u8]) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut files = vec![];
+    let mut offset = 0;
+    while offset + TAR_BLOCK <= tar.len() {
+        let header = &tar[offset..offset + TAR_BLOCK];
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(files);
+        }
+        ensure!(
+            read_octal(&header[148..156])? == header_checksum(header),
+            "corrupt tar header at byte {}",
+            offset
+        );
+        let name_len = header[..100]
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(100);
+        let path = String::from_utf8(header[..name_len].to_vec())?;
+        let size = read_octal(&header[124..136])? as usize;
+        let start = offset + TAR_BLOCK;
+        let bytes = tar
+            .get(start..start + size)
+            .ok_or_else(|| anyhow!("{} is cut short", path))?;
+        if header[156] == b'0' || header[156] == 0 {
+            files.push((path, bytes.to_vec()));
+        }
+        offset = start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+    }
+    bail!("the archive has no end marker")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{open_archive, write_archive, MANIFEST_FILE, TAR_BLOCK};
+
+    #[test]
+    fn test_archives_round_trip_and_detect_tampering() -> anyhow::Result<()> {
+        let files = vec![
+            (
+                "proposal.json".to_string(),
+                b"{\"statement\":\"Fund\"}".to_vec(),
+            ),
+            ("updates.json".to_string(), vec![b'x'; TAR_BLOCK + 1]),
+            ("verifier/circuit.json".to_string(), vec![]),
+        ];
+        let archive = write_archive(Uuid::nil(), &files, 1_700_000_000)?;
+        // Manifest and files each take a header and whole blocks
+        assert_eq!(archive.len() % TAR_BLOCK, 0);
+        assert_eq!(&archive[..MANIFEST_FILE.len()], MANIFEST_FILE.as_bytes());
+        assert_eq!(&archive[257..262], b"ustar");
+
+        let (manifest, opened) = open_archive(&archive)?;
+        assert_eq!(manifest.files.len(), 3);
+        assert_eq!(manifest.files[1].size, TAR_BLOCK as u64 + 1);
+        assert_eq!(opened.into_iter().collect::<Vec<_>>(), {
+            let mut sorted = files.clone();
+            sorted.sort();
+            sorted
+        });
+
+        // A changed byte of a file no longer matches its hash in the manifest
+        let offset = archive
+            .windows(4)
+            .position(|window| window == b"Fund")
+            .unwrap();
+        let mut tampered = archive.clone();
+        tampered[offset] ^= 1;
+        assert!(open_archive(&tampered).is_err());
+        assert!(open_archive(&archive[..archive.len() - 2 * TAR_BLOCK]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! Blinded voter indices. Leaves of the balance tree are otherwise in voter id
+//! order, so anyone reading a finalization proof or a leaf proof can tell which
+//! voter a leaf belongs to. A blinded proposal places the voter at position `i`
+//! of the electorate at the leaf of a keyed pseudorandom permutation of `i`
+//! instead, with the key kept by the server.
+//!
+//! The SHA-256 of the key is published with the proposal when it is created, and
+//! auditors are handed the key, which they check against it before recomputing
+//! the mapping.
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::balance::accounts::VoterLeaf;
+
+/// Prefixes the key when it is committed to and when the rounds of the
+/// permutation hash it.
+pub const BLINDING_DOMAIN: &str = "qed-dapp:blinding:v1";
+
+/// Feistel rounds of the permutation.
+const ROUNDS: u8 = 8;
+
+/// The key of the permutation of a blinded proposal.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VoterBlinding {
+    /// Hex encoded key of the permutation
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub key: [u8; 32],
+}
+
+/// Where one voter of a blinded proposal was placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BlindedSlot {
+    pub voter_id: u32,
+    pub leaf_index: u64,
+}
+
+/// The mapping of a blinded proposal, as revealed to auditors.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BlindingReveal {
+    /// Hex encoded key of the permutation
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub key: [u8; 32],
+    /// Hex encoded commitment published with the proposal, see [`VoterBlinding::commitment`]
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub commitment: [u8; 32],
+    /// Leaf of every voter, in voter id order.
+    pub slots: Vec<BlindedSlot>,
+}
+
+impl VoterBlinding {
+    /// A blinding with a fresh random key.
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self { key }
+    }
+    /// SHA-256 of [`BLINDING_DOMAIN`] and the key, which fixes the mapping
+    /// without telling it.
+    pub fn commitment(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(BLINDING_DOMAIN);
+        hasher.update(b":commitment:");
+        hasher.update(self.key);
+        hasher.finalize().into()
+    }
+    fn round(&self, round: u8, value: u64) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(BLINDING_DOMAIN);
+        hasher.update(b":round:");
+        hasher.update(self.key);
+        hasher.update([round]);
+        hasher.update(value.to_le_bytes());
+        let digest = hasher.finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+    /// A Feistel network over values of `2 * half_bits` bits.
+    fn feistel(&self, value: u64, half_bits: u32) -> u64 {
+        let mask = (1u64 << half_bits) - 1;
+        let (mut left, mut right) = (value >> half_bits, value & mask);
+        for round in 0..ROUNDS {
+            (left, right) = (right, left ^ (self.round(round, right) & mask));
+        }
+        (left << half_bits) | right
+    }
+    /// The slot, below `electorate_size`, the voter at `position` is placed at.
+    /// Applies the Feistel network over the smallest even number of bits that
+    /// holds the electorate until the value falls back inside it, which it does
+    /// after less than four applications on average.
+    pub fn slot(&self, position: u64, electorate_size: u64) -> u64 {
+        assert!(
+            position < electorate_size,
+            "position outside the electorate"
+        );
+        let bits = (64 - (electorate_size - 1).leading_zeros()).max(2);
+        let half_bits = (bits + 1) / 2;
+        let mut slot = self.feistel(position, half_bits);
+        while slot >= electorate_size {
+            slot = self.feistel(slot, half_bits);
+        }
+        slot
+    }
+    /// `values` in electorate order moved to the slots of their voters.
+    pub fn permute<T: Clone>(&self, values: &[T]) -> Vec<T> {
+        let mut permuted = values.to_vec();
+        for (position, value) in values.iter().enumerate() {
+            permuted[self.slot(position as u64, values.len() as u64) as usize] = value.clone();
+        }
+        permuted
+    }
+    /// The key and the leaf of every voter of an electorate of `electorate_size`.
+    pub fn reveal(&self, electorate_size: u64) -> BlindingReveal {
+        BlindingReveal {
+            key: self.key,
+            commitment: self.commitment(),
+            slots: (0..electorate_size)
+                .map(|position| BlindedSlot {
+                    voter_id: VoterLeaf::from_position(position).index() as u32,
+                    leaf_index: VoterLeaf::from_position(self.slot(position, electorate_size))
+                        .index(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::VoterBlinding;
+    use crate::{
+        balance::{accounts::VoterLeaf, weight::Weight},
+        proposal::{rules::ProposalRules, Proposal, ProposalStatus},
+    };
+
+    #[test]
+    fn test_blinding_permutes_the_electorate() {
+        let blinding = VoterBlinding { key: [7; 32] };
+        for electorate_size in [1, 2, 3, 5, 64, 1000] {
+            let slots: BTreeSet<u64> = (0..electorate_size)
+                .map(|position| blinding.slot(position, electorate_size))
+                .collect();
+            assert_eq!(slots, (0..electorate_size).collect());
+        }
+        let other = VoterBlinding { key: [8; 32] };
+        assert_ne!(blinding.reveal(64).slots, other.reveal(64).slots);
+        assert_ne!(blinding.commitment(), other.commitment());
+
+        let voter_balances: Vec<Weight> = (1..=16u32).map(Weight::from).collect();
+        let mut proposal = Proposal::with_voter_balances(
+            "Fund the audit".to_string(),
+            1,
+            0,
+            ProposalRules::default(),
+            blinding.permute(&voter_balances),
+        )
+        .unwrap();
+        proposal.blinding = Some(blinding.clone());
+        proposal.transition(ProposalStatus::Open).unwrap();
+        // The voter at position 4 keeps its weight, at the leaf the key places it at
+        let voter = proposal.electorate_voter(6).unwrap();
+        assert_eq!(
+            voter,
+            VoterLeaf::from_position(blinding.slot(4, voter_balances.len() as u64))
+        );
+        assert_eq!(proposal.storage.initial_balance(voter), Weight::from(5));
+        proposal.cast_vote(6, true, None, 0).unwrap();
+        assert!(proposal.voted.contains(&voter));
+        assert_eq!(proposal.storage.tally().unwrap().yes_votes, Weight::from(5));
+    }
+}
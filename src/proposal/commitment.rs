@@ -0,0 +1,73 @@
+//! Vote pre-commitments, which keep votes from being cast in reaction to the
+//! running tally in the last seconds of a proposal.
+//!
+//! On a proposal with a commitment period, voters submit a commitment to their
+//! vote before the period ends, and vote afterwards by opening it with the salt.
+//! Matching votes against commitments is enforced by the server; the balance
+//! update circuit proves the votes as cast.
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of the little endian voter id, the vote as a byte and the salt. The
+/// salt should hold at least 16 random bytes, since there are only two votes to guess.
+pub fn compute_vote_commitment(voter_id: u32, is_yes: bool, salt: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(voter_id.to_le_bytes());
+    hasher.update([is_yes as u8]);
+    hasher.update(salt);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::weight::Weight,
+        errors::ApiErrorCode,
+        proposal::{rules::ProposalRules, Proposal, ProposalPhase},
+    };
+
+    use super::compute_vote_commitment;
+
+    #[test]
+    fn test_votes_must_open_a_commitment() {
+        let rules = ProposalRules {
+            voting_period_secs: Some(20),
+            commit_period_secs: Some(10),
+            ..Default::default()
+        };
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            rules,
+            vec![Weight::from(1); 4],
+        )
+        .unwrap();
+        let salt = [7u8; 16];
+        proposal
+            .commit_vote(2, compute_vote_commitment(2, true, &salt), 1)
+            .unwrap();
+        let code = |result: Result<(), crate::errors::ApiError>| result.unwrap_err().code;
+
+        assert_eq!(proposal.phase(5), ProposalPhase::Committing);
+        assert_eq!(
+            code(proposal.cast_vote(2, true, Some(&salt), 5)),
+            ApiErrorCode::VotingNotOpen
+        );
+        assert_eq!(
+            code(proposal.commit_vote(3, [0; 32], 10)),
+            ApiErrorCode::CommitmentsClosed
+        );
+        assert_eq!(
+            code(proposal.cast_vote(3, true, None, 11)),
+            ApiErrorCode::CommitmentMissing
+        );
+        assert_eq!(
+            code(proposal.cast_vote(2, false, Some(&salt), 11)),
+            ApiErrorCode::CommitmentMismatch
+        );
+        proposal.cast_vote(2, true, Some(&salt), 11).unwrap();
+        assert_eq!(proposal.storage.tally().unwrap().yes_votes, Weight::from(1));
+        assert_eq!(proposal.transcript.len(), 2);
+    }
+}
@@ -0,0 +1,208 @@
+//! Statements backed by an external document, e.g. on IPFS, which a proposal
+//! references by URI and hash instead of holding its text.
+//!
+//! The hash is committed to by the finalization proof, see
+//! [`compute_statement_hash_with_content`](crate::proof::certificate::compute_statement_hash_with_content),
+//! so a verifier who fetches the document can check it is the one voted on.
+//! The server can fetch and check documents itself in the background, which
+//! [`ContentCheck`] records.
+
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use utoipa::ToSchema;
+use web3::signing::keccak256;
+
+use crate::{
+    common::hash::traits::hasher::FieldWHasher,
+    errors::{ApiError, ApiErrorCode},
+    proof::certificate::pack_bytes,
+};
+
+/// Longest document URI, in bytes.
+pub const MAX_CONTENT_URI_BYTES: usize = 2048;
+
+/// Largest document the server fetches to check, in bytes.
+pub const MAX_CONTENT_BYTES: usize = 16 << 20;
+
+/// Schemes a document URI may have.
+pub const CONTENT_URI_SCHEMES: [&str; 2] = ["ipfs", "https"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentHashKind {
+    Keccak256,
+    /// Poseidon over the bytes packed four to an element after their length, as
+    /// statements are, serialized as four little endian `u64`s.
+    Poseidon,
+}
+
+/// An external document a proposal refers to.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct StatementContent {
+    /// `ipfs://` or `https://` URI of the document
+    pub uri: String,
+    pub hash_kind: ContentHashKind,
+    /// Hex encoded hash of the document
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub hash: [u8; 32],
+}
+
+/// Hashes a document as `kind` says.
+pub fn hash_content(kind: ContentHashKind, bytes: &[u8]) -> [u8; 32] {
+    match kind {
+        ContentHashKind::Keccak256 => keccak256(bytes),
+        ContentHashKind::Poseidon => {
+            let hash = PoseidonHash::w_hash_many(&pack_bytes(bytes));
+            let mut encoded = [0u8; 32];
+            for (chunk, element) in encoded.chunks_exact_mut(8).zip(hash.0.elements) {
+                chunk.copy_from_slice(&GoldilocksField::to_canonical_u64(&element).to_le_bytes());
+            }
+            encoded
+        }
+    }
+}
+
+impl StatementContent {
+    /// Fails unless the URI has one of [`CONTENT_URI_SCHEMES`] and is at most
+    /// [`MAX_CONTENT_URI_BYTES`] long.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.uri.len() > MAX_CONTENT_URI_BYTES {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidContent,
+                format!(
+                    "Content URI is {} bytes long, more than the {} allowed",
+                    self.uri.len(),
+                    MAX_CONTENT_URI_BYTES
+                ),
+            ));
+        }
+        let scheme = self.uri.split_once("://").map(|(scheme, _)| scheme);
+        match scheme {
+            Some(scheme) if CONTENT_URI_SCHEMES.contains(&scheme) => Ok(()),
+            _ => Err(ApiError::new(
+                ApiErrorCode::InvalidContent,
+                format!(
+                    "Content URIs start with one of {}",
+                    CONTENT_URI_SCHEMES
+                        .map(|scheme| format!("{}://", scheme))
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+    /// Whether `bytes` is the document the hash was taken of.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        hash_content(self.hash_kind, bytes) == self.hash
+    }
+    /// The URL to fetch the document from, with `ipfs://` URIs resolved through
+    /// the HTTP `gateway`.
+    pub fn fetch_url(&self, gateway: &str) -> String {
+        match self.uri.strip_prefix("ipfs://") {
+            Some(path) => format!("{}/ipfs/{}", gateway.trim_end_matches('/'), path),
+            None => self.uri.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentStatus {
+    /// The fetched document matches the hash.
+    Verified,
+    /// The fetched document does not match the hash.
+    Mismatch,
+    /// The document could not be fetched, or was larger than [`MAX_CONTENT_BYTES`].
+    Unavailable,
+}
+
+/// The last check of the document of a proposal by the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ContentCheck {
+    pub status: ContentStatus,
+    pub checked_at: u64,
+}
+
+/// Fetches the documents proposals refer to and checks them against their hash.
+pub struct ContentFetcher {
+    client: reqwest::Client,
+    gateway: String,
+}
+
+impl ContentFetcher {
+    /// `gateway` is the HTTP gateway `ipfs://` URIs are fetched through, e.g.
+    /// `https://ipfs.io`.
+    pub fn new(gateway: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            gateway: gateway.to_string(),
+        }
+    }
+    /// Fetches the document, reading at most [`MAX_CONTENT_BYTES`] of it.
+    pub async fn fetch(&self, content: &StatementContent) -> anyhow::Result<Vec<u8>> {
+        let mut response = self
+            .client
+            .get(content.fetch_url(&self.gateway))
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut bytes = vec![];
+        while let Some(chunk) = response.chunk().await? {
+            anyhow::ensure!(
+                bytes.len() + chunk.len() <= MAX_CONTENT_BYTES,
+                "document is larger than {} bytes",
+                MAX_CONTENT_BYTES
+            );
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+    /// Fetches the document and checks it against its hash.
+    pub async fn check(&self, content: &StatementContent) -> ContentStatus {
+        match self.fetch(content).await {
+            Ok(bytes) if content.matches(&bytes) => ContentStatus::Verified,
+            Ok(_) => ContentStatus::Mismatch,
+            Err(_) => ContentStatus::Unavailable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_content, ContentHashKind, StatementContent};
+    use crate::errors::ApiErrorCode;
+
+    #[test]
+    fn test_content_matches_its_hash() {
+        let document = b"# Fund the audit\n\nThe full proposal.";
+        for hash_kind in [ContentHashKind::Keccak256, ContentHashKind::Poseidon] {
+            let content = StatementContent {
+                uri: "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+                    .to_string(),
+                hash_kind,
+                hash: hash_content(hash_kind, document),
+            };
+            assert!(content.validate().is_ok());
+            assert!(content.matches(document));
+            assert!(!content.matches(b"# Fund the audit"));
+            assert_eq!(
+                content.fetch_url("https://ipfs.io/"),
+                "https://ipfs.io/ipfs/bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+            );
+        }
+        let content = StatementContent {
+            uri: "file:///etc/passwd".to_string(),
+            hash_kind: ContentHashKind::Keccak256,
+            hash: [0; 32],
+        };
+        assert_eq!(
+            content.validate().unwrap_err().code,
+            ApiErrorCode::InvalidContent
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Who delegated to whom on a proposal. The balance tree only holds where the
+//! weight of a voter ended up; the [`DelegationRegistry`] keeps the delegations
+//! voters asked for and resolves them transitively, so the weight of a voter who
+//! delegates to someone who delegated in turn goes to the voter at the end of
+//! the chain.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    balance::weight::Weight,
+    errors::{ApiError, ApiErrorCode, QedError},
+};
+
+use super::{
+    transcript::{TranscriptAction, TranscriptEvent},
+    Proposal,
+};
+
+/// Most delegations a batch can apply, see [`Proposal::delegate_batch`].
+pub const MAX_BATCH_DELEGATIONS: usize = 256;
+
+/// A delegation of a batch, see [`Proposal::delegate_batch`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BatchDelegation {
+    pub voter_id: u32,
+    pub delegator_id: u32,
+}
+
+/// The delegations accepted on a proposal, by delegating voter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DelegationRegistry {
+    delegates: BTreeMap<u32, u32>,
+}
+
+impl DelegationRegistry {
+    /// The delegations recorded in the transcript of a proposal.
+    pub fn from_transcript(transcript: &[TranscriptEvent]) -> Self {
+        let mut registry = Self::default();
+        for event in transcript {
+            match event.action {
+                TranscriptAction::Delegate {
+                    voter_id,
+                    delegator_id,
+                } => registry.insert(voter_id, delegator_id),
+                TranscriptAction::Undelegate { voter_id } => registry.remove(voter_id),
+                _ => {}
+            }
+        }
+        registry
+    }
+    pub fn insert(&mut self, voter_id: u32, delegate_id: u32) {
+        self.delegates.insert(voter_id, delegate_id);
+    }
+    /// Drops the delegation of `voter_id`, once reverted.
+    pub fn remove(&mut self, voter_id: u32) {
+        self.delegates.remove(&voter_id);
+    }
+    /// The voter `voter_id` delegated to, if any.
+    pub fn delegate_of(&self, voter_id: u32) -> Option<u32> {
+        self.delegates.get(&voter_id).copied()
+    }
+    /// The voter who casts the weight of `voter_id`: the first voter along the
+    /// chain of delegations from it who has not delegated, possibly itself.
+    pub fn resolve(&self, voter_id: u32) -> u32 {
+        let mut resolved = voter_id;
+        // Terminates since `check` rejects delegations that close a cycle
+        while let Some(delegate_id) = self.delegate_of(resolved) {
+            resolved = delegate_id;
+        }
+        resolved
+    }
+    /// Resolves the delegate `voter_id` delegating to `delegate_id` moves its
+    /// weight to, failing if the delegation leads back to the voter.
+    pub fn check(&self, voter_id: u32, delegate_id: u32) -> Result<u32, ApiError> {
+        let resolved = self.resolve(delegate_id);
+        if resolved == voter_id {
+            return Err(ApiError::new(
+                ApiErrorCode::DelegationCycle,
+                format!(
+                    "Voter {} cannot delegate to voter {}, whose weight goes back to them",
+                    voter_id, delegate_id
+                ),
+            ));
+        }
+        Ok(resolved)
+    }
+    /// Voters whose weight ends up with `voter_id`, directly or through others.
+    pub fn delegators_of(&self, voter_id: u32) -> Vec<u32> {
+        self.delegates
+            .keys()
+            .copied()
+            .filter(|delegator_id| {
+                *delegator_id != voter_id && self.resolve(*delegator_id) == voter_id
+            })
+            .collect()
+    }
+    /// Voters who delegated or were delegated to.
+    pub fn voters(&self) -> Vec<u32> {
+        let mut voters: Vec<u32> = self
+            .delegates
+            .iter()
+            .flat_map(|(voter_id, delegate_id)| [*voter_id, *delegate_id])
+            .collect();
+        voters.sort_unstable();
+        voters.dedup();
+        voters
+    }
+}
+
+/// The weight a voter casts on a proposal, by the delegations made on it and by
+/// the balance tree, which agree unless the two diverged.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VotingPower {
+    pub voter_id: u32,
+    /// Weight the voter was seeded with.
+    pub own_weight: Weight,
+    /// Voter the voter delegated to, if any.
+    pub delegate_id: Option<u32>,
+    /// Voter who casts the weight of this one, following delegations transitively.
+    pub resolved_delegate_id: u32,
+    /// Voters whose weight ends up with this one, directly or through others.
+    pub delegators: Vec<u32>,
+    /// Weight the voter can cast by the delegations: its own and that of its
+    /// delegators, or none once it delegated.
+    pub effective_weight: Weight,
+    /// Balance of the leaf of the voter, unless the tree was compacted.
+    pub tree_balance: Option<Weight>,
+    pub has_voted: bool,
+    /// Whether the tree balance is the effective weight. Not known once the voter
+    /// voted, which spends the weight, or the tree was compacted.
+    pub consistent: Option<bool>,
+}
+
+impl Proposal {
+    /// Applies `delegations` in order at time `now`, all or none: should one of
+    /// them fail, the balance tree and the delegations are put back as they were
+    /// before the batch and its error is returned. The updates of the batch are
+    /// recorded once all of them applied.
+    pub fn delegate_batch(
+        &mut self,
+        delegations: &[BatchDelegation],
+        now: u64,
+    ) -> Result<(), ApiError> {
+        if delegations.is_empty() || delegations.len() > MAX_BATCH_DELEGATIONS {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                format!(
+                    "A batch applies between 1 and {} delegations",
+                    MAX_BATCH_DELEGATIONS
+                ),
+            ));
+        }
+        let registry = self.delegations.clone();
+        self.storage.begin_batch();
+        let mut updates = Vec::with_capacity(delegations.len());
+        for (position, delegation) in delegations.iter().enumerate() {
+            match self.apply_delegation(delegation.voter_id, delegation.delegator_id, now) {
+                Ok(update) => updates.push(update),
+                Err(err) => {
+                    self.delegations = registry;
+                    self.storage
+                        .rollback_batch()
+                        .map_err(QedError::from_storage)?;
+                    return Err(ApiError::new(
+                        err.code,
+                        format!(
+                            "Delegation {} of the batch failed, none were applied: {}",
+                            position, err.message
+                        ),
+                    ));
+                }
+            }
+        }
+        self.storage.commit_batch();
+        for (delegation, update) in delegations.iter().zip(updates) {
+            self.record(
+                vec![update],
+                now,
+                TranscriptAction::Delegate {
+                    voter_id: delegation.voter_id,
+                    delegator_id: delegation.delegator_id,
+                },
+            );
+        }
+        Ok(())
+    }
+    /// The voting power of `voter_id`, see [`VotingPower`].
+    pub fn voting_power(&self, voter_id: u32) -> Result<VotingPower, ApiError> {
+        let voter = self.electorate_voter(voter_id)?;
+        let own_weight = self.storage.initial_balance(voter);
+        let delegate_id = self.delegations.delegate_of(voter_id);
+        let delegators = self.delegations.delegators_of(voter_id);
+        let effective_weight = if delegate_id.is_some() {
+            Weight::ZERO
+        } else {
+            delegators
+                .iter()
+                .map(|delegator_id| {
+                    self.storage
+                        .initial_balance(self.electorate_voter(*delegator_id).unwrap())
+                })
+                .try_fold(own_weight, |total, weight| total.checked_add(weight.into()))
+                .ok_or_else(|| {
+                    ApiError::new(
+                        ApiErrorCode::InvalidVoter,
+                        "The weight delegated to the voter overflows",
+                    )
+                })?
+        };
+        let tree_balance = if self.storage.is_compacted() {
+            None
+        } else {
+            Some(self.storage.get_balance(voter).unwrap())
+        };
+        let has_voted = self.voted.contains(&voter);
+        let consistent = tree_balance
+            .filter(|_| !has_voted)
+            .map(|balance| balance == effective_weight);
+        Ok(VotingPower {
+            voter_id,
+            own_weight,
+            delegate_id,
+            resolved_delegate_id: self.delegations.resolve(voter_id),
+            delegators,
+            effective_weight,
+            tree_balance,
+            has_voted,
+            consistent,
+        })
+    }
+    /// Voters involved in a delegation whose tree balance is not the weight the
+    /// delegations give them, see [`VotingPower::consistent`].
+    pub fn delegation_divergences(&self) -> Vec<VotingPower> {
+        self.delegations
+            .voters()
+            .into_iter()
+            .filter_map(|voter_id| self.voting_power(voter_id).ok())
+            .filter(|power| power.consistent == Some(false))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::weight::Weight,
+        errors::ApiErrorCode,
+        proposal::{rules::ProposalRules, Proposal, ProposalStatus},
+    };
+
+    use super::BatchDelegation;
+
+    #[test]
+    fn test_delegations_resolve_transitively() {
+        let mut proposal =
+            Proposal::new("Fund the audit".to_string(), 1, 0, ProposalRules::default()).unwrap();
+        proposal.transition(ProposalStatus::Open).unwrap();
+        // 3 delegates to 4, then 4 to 5: the weight of both ends up with 5
+        proposal.delegate(3, 4, 0).unwrap();
+        proposal.delegate(4, 5, 0).unwrap();
+        // 6 delegates to 3, who already delegated, so its weight goes to 5 directly
+        proposal.delegate(6, 3, 0).unwrap();
+        let power = proposal.voting_power(5).unwrap();
+        assert_eq!(power.delegators, vec![3, 4, 6]);
+        assert_eq!(power.effective_weight, Weight::from(4));
+        assert_eq!(power.tree_balance, Some(Weight::from(4)));
+        assert_eq!(power.consistent, Some(true));
+        let power = proposal.voting_power(3).unwrap();
+        assert_eq!(power.delegate_id, Some(4));
+        assert_eq!(power.resolved_delegate_id, 5);
+        assert_eq!(power.effective_weight, Weight::ZERO);
+        assert_eq!(power.consistent, Some(true));
+        assert_eq!(
+            proposal.delegate(5, 6, 0).unwrap_err().code,
+            ApiErrorCode::DelegationCycle
+        );
+        assert_eq!(
+            proposal.delegate(7, 7, 0).unwrap_err().code,
+            ApiErrorCode::DelegationCycle
+        );
+        assert!(proposal.delegation_divergences().is_empty());
+
+        proposal.cast_vote(5, true, None, 0).unwrap();
+        assert_eq!(proposal.storage.tally().unwrap().yes_votes, Weight::from(4));
+        assert_eq!(proposal.voting_power(5).unwrap().consistent, None);
+    }
+
+    #[test]
+    fn test_delegate_batch_is_atomic() {
+        let mut proposal =
+            Proposal::new("Fund the audit".to_string(), 1, 0, ProposalRules::default()).unwrap();
+        proposal.transition(ProposalStatus::Open).unwrap();
+        let batch = |pairs: &[(u32, u32)]| -> Vec<BatchDelegation> {
+            pairs
+                .iter()
+                .map(|(voter_id, delegator_id)| BatchDelegation {
+                    voter_id: *voter_id,
+                    delegator_id: *delegator_id,
+                })
+                .collect()
+        };
+        let root = proposal.storage.tree.get_root().unwrap();
+
+        // The last delegation closes a cycle, so the two before it are undone
+        let err = proposal
+            .delegate_batch(&batch(&[(3, 4), (4, 5), (5, 3)]), 0)
+            .unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::DelegationCycle);
+        assert_eq!(proposal.storage.tree.get_root().unwrap(), root);
+        assert!(proposal.updates.is_empty());
+        assert_eq!(proposal.voting_power(3).unwrap().delegate_id, None);
+
+        proposal
+            .delegate_batch(&batch(&[(3, 4), (6, 4)]), 0)
+            .unwrap();
+        assert_eq!(proposal.updates.len(), 2);
+        let power = proposal.voting_power(4).unwrap();
+        assert_eq!(power.delegators, vec![3, 6]);
+        assert_eq!(power.tree_balance, Some(Weight::from(3)));
+        assert!(proposal.delegate_batch(&[], 0).is_err());
+    }
+}
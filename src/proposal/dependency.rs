@@ -0,0 +1,223 @@
+//! Dependencies between proposals: a proposal can only pass if every proposal
+//! it depends on passed.
+//!
+//! Dependencies are declared at creation and have to exist by then. A dependent
+//! proposal is finalized once all of its dependencies are finalized or
+//! cancelled, and its finalization proof exposes a hash of their results, so a
+//! verifier holding their certificates can check which results it relied on.
+
+use std::collections::BTreeSet;
+
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::Field},
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    common::{hash::traits::hasher::FieldWHasher, WHashOut},
+    errors::{ApiError, ApiErrorCode},
+    nullifier::nullifier_set::proposal_id_to_elements,
+    proof::certificate::compute_result_commitment,
+};
+
+use super::{rules::ProposalOutcome, store::ProposalStore, ProposalStatus};
+
+type F = GoldilocksField;
+
+/// Most proposals a proposal can depend on directly.
+pub const MAX_DEPENDENCIES: usize = 16;
+
+/// What became of a proposal another one depends on, as recorded in the
+/// certificate of the dependent proposal.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DependencyResult {
+    pub proposal_id: Uuid,
+    /// Unset if the dependency was cancelled.
+    pub outcome: Option<ProposalOutcome>,
+    /// See [`compute_result_commitment`], zero if the dependency was cancelled.
+    #[schema(value_type = String)]
+    pub commitment: WHashOut<F>,
+}
+
+impl DependencyResult {
+    pub fn is_passed(&self) -> bool {
+        self.outcome == Some(ProposalOutcome::Passed)
+    }
+}
+
+/// Checks the dependencies `id` declares: at most [`MAX_DEPENDENCIES`] distinct
+/// proposals of the store other than itself, which do not lead back to it.
+pub fn check_dependencies(
+    proposals: &ProposalStore,
+    id: Uuid,
+    depends_on: &[Uuid],
+) -> Result<(), ApiError> {
+    if depends_on.len() > MAX_DEPENDENCIES {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidQuery,
+            format!(
+                "A proposal depends on at most {} proposals",
+                MAX_DEPENDENCIES
+            ),
+        ));
+    }
+    if depends_on.iter().collect::<BTreeSet<_>>().len() != depends_on.len() {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidQuery,
+            "A dependency is declared twice",
+        ));
+    }
+    // Walks everything the proposal depends on, directly or not
+    let mut seen = BTreeSet::new();
+    let mut pending = depends_on.to_vec();
+    while let Some(dependency) = pending.pop() {
+        if dependency == id {
+            return Err(ApiError::new(
+                ApiErrorCode::DependencyCycle,
+                format!("Proposal {} would depend on itself", id),
+            ));
+        }
+        if !seen.insert(dependency) {
+            continue;
+        }
+        match proposals.get(&dependency) {
+            Some(proposal) => pending.extend(proposal.depends_on.iter().copied()),
+            None => {
+                return Err(ApiError::new(
+                    ApiErrorCode::DependencyNotFound,
+                    format!("Dependency {} does not exist", dependency),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The results of the proposals in `depends_on`, in declaration order, failing
+/// while any of them is neither finalized nor cancelled.
+pub fn resolve_dependencies(
+    proposals: &ProposalStore,
+    depends_on: &[Uuid],
+) -> Result<Vec<DependencyResult>, ApiError> {
+    depends_on
+        .iter()
+        .map(|id| {
+            let proposal = proposals.get(id).ok_or_else(|| {
+                ApiError::new(
+                    ApiErrorCode::DependencyNotFound,
+                    format!("Dependency {} does not exist", id),
+                )
+            })?;
+            match (proposal.status, &proposal.certificate) {
+                (ProposalStatus::Finalized, Some(certificate)) => Ok(DependencyResult {
+                    proposal_id: *id,
+                    outcome: Some(certificate.outcome),
+                    commitment: compute_result_commitment(certificate),
+                }),
+                (ProposalStatus::Cancelled, _) => Ok(DependencyResult {
+                    proposal_id: *id,
+                    outcome: None,
+                    commitment: WHashOut::ZERO,
+                }),
+                _ => Err(ApiError::new(
+                    ApiErrorCode::DependencyPending,
+                    format!("Dependency {} is not finalized yet", id),
+                )),
+            }
+        })
+        .collect()
+}
+
+/// The outcome of a proposal whose own votes resolve to `outcome`: a proposal
+/// that would pass is vetoed unless all of its dependencies passed.
+pub fn gate_outcome(
+    outcome: ProposalOutcome,
+    dependencies: &[DependencyResult],
+) -> ProposalOutcome {
+    if outcome == ProposalOutcome::Passed && !dependencies.iter().all(DependencyResult::is_passed) {
+        ProposalOutcome::Vetoed
+    } else {
+        outcome
+    }
+}
+
+/// Poseidon hash of the id and result commitment of each dependency, in order,
+/// after an element holding their number. Exposed by the finalization proof of
+/// the dependent proposal.
+pub fn compute_dependencies_hash(dependencies: &[DependencyResult]) -> WHashOut<F> {
+    let mut elements = vec![F::from_canonical_usize(dependencies.len())];
+    for dependency in dependencies {
+        elements.extend(proposal_id_to_elements(&dependency.proposal_id));
+        elements.extend(dependency.commitment.0.elements);
+    }
+    PoseidonHash::w_hash_many(&elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{check_dependencies, gate_outcome, resolve_dependencies};
+    use crate::{
+        errors::ApiErrorCode,
+        proposal::{
+            rules::{ProposalOutcome, ProposalRules},
+            store::ProposalStore,
+            Proposal, ProposalStatus,
+        },
+    };
+
+    fn proposal(depends_on: Vec<Uuid>) -> Proposal {
+        let mut proposal =
+            Proposal::new("Fund the audit".to_string(), 2, 0, ProposalRules::default()).unwrap();
+        proposal.depends_on = depends_on;
+        proposal
+    }
+
+    #[test]
+    fn test_dependencies_gate_the_outcome() {
+        let mut proposals = ProposalStore::new();
+        let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        proposals.insert(first, proposal(vec![]));
+        assert!(check_dependencies(&proposals, second, &[first]).is_ok());
+        proposals.insert(second, proposal(vec![first]));
+
+        let code = |result: Result<(), _>| result.unwrap_err().code;
+        assert_eq!(
+            code(check_dependencies(&proposals, third, &[Uuid::new_v4()])),
+            ApiErrorCode::DependencyNotFound
+        );
+        assert_eq!(
+            code(check_dependencies(&proposals, third, &[first, first])),
+            ApiErrorCode::InvalidQuery
+        );
+        // Restored or corrupted state cannot make a proposal reach itself
+        proposals.get_mut(&first).unwrap().depends_on = vec![third];
+        assert_eq!(
+            code(check_dependencies(&proposals, third, &[second])),
+            ApiErrorCode::DependencyCycle
+        );
+        proposals.get_mut(&first).unwrap().depends_on = vec![];
+
+        assert_eq!(
+            resolve_dependencies(&proposals, &[first]).unwrap_err().code,
+            ApiErrorCode::DependencyPending
+        );
+        proposals
+            .set_status(&first, ProposalStatus::Cancelled)
+            .unwrap();
+        let results = resolve_dependencies(&proposals, &[first]).unwrap();
+        assert_eq!(results[0].outcome, None);
+        assert_eq!(
+            gate_outcome(ProposalOutcome::Passed, &results),
+            ProposalOutcome::Vetoed
+        );
+        assert_eq!(
+            gate_outcome(ProposalOutcome::Passed, &[]),
+            ProposalOutcome::Passed
+        );
+    }
+}
@@ -0,0 +1,910 @@
+//! Ballots encrypted to a threshold committee, which keep the running tally of
+//! a proposal hidden until its voting period ends.
+//!
+//! A proposal created with a [`BallotCommittee`] takes votes as
+//! [`EncryptedBallot`]s: exponential ElGamal encryptions on secp256k1 of 1 for
+//! yes and 0 for no, to the key the members of the committee share with
+//! Shamir's scheme. Each ballot carries a proof that it encrypts 0 or 1, bound
+//! to the proposal and the voter, so that it can neither count more than once
+//! nor be cast again by someone else. Ballots stay out of the balance tree
+//! while voting runs.
+//!
+//! Once the voting period ends, members post their [`DecryptionShare`]s of the
+//! aggregate of the ballots, their sum weighted by the balances of the voters,
+//! each proven against the public share of the member. No single ballot is ever
+//! decrypted. The share that completes the threshold decrypts the aggregate to
+//! the yes votes, found by a discrete log bounded by the total weight of the
+//! ballots. The balances of the voters are then cast into the tally slots in the
+//! order the ballots were cast, filling yes up to the decrypted sum and no with
+//! the rest, so the balance update circuit proves a tally that matches the sum
+//! without the tree telling how anyone voted.
+//!
+//! Ballots keep the result from being known before the deadline, and the votes
+//! of the voters secret from anyone short of a threshold of the committee.
+//!
+//! Points are hex encoded as their big endian coordinates x and y, 64 bytes,
+//! with the point at infinity as zeros, and scalars as 32 big endian bytes.
+
+use std::collections::{BTreeSet, HashMap};
+
+use num::BigUint;
+use plonky2::field::{
+    secp256k1_scalar::Secp256K1Scalar,
+    types::{Field, PrimeField, Sample},
+};
+use plonky2_ecdsa::curve::{
+    curve_types::{AffinePoint, Curve, CurveScalar, ProjectivePoint},
+    secp256k1::Secp256K1,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::{accounts::VoteSplit, weight::Weight},
+    errors::{ApiError, ApiErrorCode},
+};
+
+use super::{rules::ProposalRules, transcript::TranscriptAction, Proposal, ProposalPhase};
+
+/// Prefixes the hashes the proofs of ballots are challenged with.
+pub const BALLOT_DOMAIN: &str = "qed-dapp:encrypted-ballot:v1";
+/// Prefixes the hashes the proofs of partial decryptions are challenged with.
+pub const DECRYPTION_DOMAIN: &str = "qed-dapp:partial-decryption:v1";
+
+/// Most members a ballot committee can have.
+pub const MAX_COMMITTEE_MEMBERS: usize = 32;
+
+type Point = ProjectivePoint<Secp256K1>;
+type Scalar = Secp256K1Scalar;
+
+/// A member of a ballot committee and its public share of the key.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CommitteeMember {
+    /// Where the polynomial the key is shared with is evaluated for the member, never 0
+    pub member_index: u32,
+    /// Hex encoded secret share of the member times the generator
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub public_share: [u8; 64],
+}
+
+/// Who can decrypt the ballots of a proposal: any `threshold` of `members`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BallotCommittee {
+    pub threshold: usize,
+    pub members: Vec<CommitteeMember>,
+}
+
+/// An ElGamal ciphertext (rG, mG + rK) of `m` under the key `K`.
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Ciphertext {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub c1: [u8; 64],
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub c2: [u8; 64],
+}
+
+/// A vote encrypted to the key of a committee, with a proof that it encrypts 0
+/// or 1: two Chaum-Pedersen proofs, one for each vote, whose challenges add up
+/// to the hash of the proposal, the voter, the ciphertext and the commitments
+/// of both proofs. Only the proof of the actual vote can be made honestly.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct EncryptedBallot {
+    pub ciphertext: Ciphertext,
+    /// Hex encoded challenges of the proofs for no and for yes
+    #[serde_as(as = "[serde_with::hex::Hex; 2]")]
+    #[schema(value_type = Vec<String>)]
+    pub challenges: [[u8; 32]; 2],
+    /// Hex encoded responses of the proofs for no and for yes
+    #[serde_as(as = "[serde_with::hex::Hex; 2]")]
+    #[schema(value_type = Vec<String>)]
+    pub responses: [[u8; 32]; 2],
+}
+
+/// A ballot accepted on a proposal, with the weight it counts with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CastBallot {
+    pub voter_id: u32,
+    pub weight: Weight,
+    pub ballot: EncryptedBallot,
+    /// Unix time the ballot was accepted at, which the vote it decrypts to is
+    /// stamped with, as it is only counted after the deadline.
+    #[serde(default)]
+    pub cast_at: u64,
+}
+
+/// The first half of a ciphertext times the secret share of a member, with a
+/// Chaum-Pedersen proof that it was multiplied by the share behind the public
+/// share of the member.
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PartialDecryption {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub share: [u8; 64],
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub challenge: [u8; 32],
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub response: [u8; 32],
+}
+
+/// The partial decryption of the aggregate of the ballots of a proposal by one
+/// member, see [`BallotBox::aggregate`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DecryptionShare {
+    pub member_index: u32,
+    pub partial: PartialDecryption,
+}
+
+/// The encrypted ballots of a proposal and the decryption shares posted so far.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BallotBox {
+    pub committee: BallotCommittee,
+    pub ballots: Vec<CastBallot>,
+    pub shares: Vec<DecryptionShare>,
+    /// The yes votes the aggregate of `ballots` decrypts to, once `threshold`
+    /// members shared their decryptions.
+    pub yes_votes: Option<Weight>,
+}
+
+/// The ballots of a proposal, for the committee to decrypt and anyone to check
+/// the decryption against.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BallotBoxView {
+    pub committee: BallotCommittee,
+    /// Hex encoded key the ballots are encrypted to
+    pub public_key: String,
+    pub ballots: Vec<CastBallot>,
+    /// The ballots times their weights, added up, which decrypts to the yes votes
+    pub aggregate: Ciphertext,
+    /// Members who shared their decryptions so far
+    pub decrypted_by: Vec<u32>,
+    /// The yes votes `aggregate` decrypts to, once decrypted
+    pub yes_votes: Option<Weight>,
+}
+
+fn generator() -> Point {
+    Secp256K1::GENERATOR_PROJECTIVE
+}
+
+fn mul(scalar: Scalar, point: Point) -> Point {
+    CurveScalar(scalar) * point
+}
+
+fn sub(point: Point, other: Point) -> Point {
+    point + -other
+}
+
+fn same(point: Point, other: Point) -> bool {
+    point.to_affine() == other.to_affine()
+}
+
+fn encode_field<F: PrimeField>(element: F) -> [u8; 32] {
+    let bytes = element.to_canonical_biguint().to_bytes_be();
+    let mut encoded = [0u8; 32];
+    encoded[32 - bytes.len()..].copy_from_slice(&bytes);
+    encoded
+}
+
+fn decode_field<F: Field>(bytes: &[u8]) -> Option<F> {
+    let value = BigUint::from_bytes_be(bytes);
+    (value < F::order()).then(|| F::from_noncanonical_biguint(value))
+}
+
+fn encode_point(point: Point) -> [u8; 64] {
+    let affine = point.to_affine();
+    let mut encoded = [0u8; 64];
+    if !affine.zero {
+        encoded[..32].copy_from_slice(&encode_field(affine.x));
+        encoded[32..].copy_from_slice(&encode_field(affine.y));
+    }
+    encoded
+}
+
+/// Fails on coordinates that are not canonical or not on the curve.
+fn decode_point(bytes: &[u8; 64]) -> Option<Point> {
+    if bytes.iter().all(|byte| *byte == 0) {
+        return Some(Point::ZERO);
+    }
+    let point =
+        AffinePoint::<Secp256K1>::nonzero(decode_field(&bytes[..32])?, decode_field(&bytes[32..])?);
+    point.is_valid().then(|| point.to_projective())
+}
+
+/// Hashes `points` with `domain` and `context` to a scalar, the challenge of a
+/// proof made non-interactive.
+fn challenge(domain: &str, context: &[u8], points: &[Point]) -> Scalar {
+    let mut hasher = Sha256::new();
+    hasher.update(domain.as_bytes());
+    hasher.update(context);
+    for point in points {
+        hasher.update(encode_point(*point));
+    }
+    Scalar::from_noncanonical_biguint(BigUint::from_bytes_be(&hasher.finalize()) % Scalar::order())
+}
+
+/// The proposal and the id of the voter or member a proof is bound to.
+fn proof_context(proposal_id: &Uuid, id: u32) -> Vec<u8> {
+    [proposal_id.as_bytes().as_slice(), &id.to_le_bytes()].concat()
+}
+
+/// Lagrange basis polynomial of `index` among `indices`, evaluated at `at`.
+fn lagrange_coefficient(indices: &[u32], index: u32, at: Scalar) -> Scalar {
+    let x = Scalar::from_canonical_u32(index);
+    indices
+        .iter()
+        .filter(|other| **other != index)
+        .fold(Scalar::ONE, |product, other| {
+            let other = Scalar::from_canonical_u32(*other);
+            product * (at - other) * (x - other).inverse()
+        })
+}
+
+/// Interpolates the shares of a polynomial in the exponent at `at`.
+fn interpolate(shares: &[(u32, Point)], at: Scalar) -> Point {
+    let indices: Vec<u32> = shares.iter().map(|(index, _)| *index).collect();
+    shares.iter().fold(Point::ZERO, |sum, (index, share)| {
+        sum + mul(lagrange_coefficient(&indices, *index, at), *share)
+    })
+}
+
+/// The `m` at most `bound` with `m G` equal to `point`, found with about twice
+/// the square root of `bound` additions by baby steps and giant steps.
+fn discrete_log(point: Point, bound: u64) -> Option<u64> {
+    let steps = (bound as f64).sqrt() as u64 + 1;
+    let mut baby_steps = HashMap::new();
+    let mut multiple = Point::ZERO;
+    for step in 0..steps {
+        baby_steps.entry(encode_point(multiple)).or_insert(step);
+        multiple = multiple + generator();
+    }
+    let giant_step = -multiple;
+    let mut rest = point;
+    for giant in 0..=bound / steps {
+        if let Some(step) = baby_steps.get(&encode_point(rest)) {
+            let found = giant * steps + step;
+            return (found <= bound).then_some(found);
+        }
+        rest = rest + giant_step;
+    }
+    None
+}
+
+/// The commitments a proof that (`c1`, `c2`) encrypts `vote` under `key` opens
+/// to with `challenge` and `response`: zG - e c1 and zK - e (c2 - vote G).
+fn vote_commitments(
+    key: Point,
+    (c1, c2): (Point, Point),
+    vote: usize,
+    challenge: Scalar,
+    response: Scalar,
+) -> [Point; 2] {
+    let message = sub(c2, mul(Scalar::from_canonical_usize(vote), generator()));
+    [
+        sub(mul(response, generator()), mul(challenge, c1)),
+        sub(mul(response, key), mul(challenge, message)),
+    ]
+}
+
+/// The challenge the proofs of a ballot on `proposal_id` by `voter_id` have to add up to.
+fn ballot_challenge(
+    proposal_id: &Uuid,
+    voter_id: u32,
+    (c1, c2): (Point, Point),
+    [[no_g, no_k], [yes_g, yes_k]]: [[Point; 2]; 2],
+) -> Scalar {
+    challenge(
+        BALLOT_DOMAIN,
+        &proof_context(proposal_id, voter_id),
+        &[c1, c2, no_g, no_k, yes_g, yes_k],
+    )
+}
+
+/// Encrypts the vote of `voter_id` on `proposal_id` to the key of its committee,
+/// see [`BallotCommittee::public_key`], as a voter does before casting it.
+pub fn encrypt_ballot(
+    public_key: &[u8; 64],
+    proposal_id: &Uuid,
+    voter_id: u32,
+    is_yes: bool,
+) -> anyhow::Result<EncryptedBallot> {
+    let key = decode_point(public_key).ok_or_else(|| anyhow::anyhow!("invalid public key"))?;
+    let (vote, other) = (is_yes as usize, !is_yes as usize);
+    let randomness = Scalar::rand();
+    let ciphertext = (
+        mul(randomness, generator()),
+        mul(Scalar::from_canonical_usize(vote), generator()) + mul(randomness, key),
+    );
+    // The proof for the other vote is simulated from a chosen challenge
+    let mut challenges = [Scalar::ZERO; 2];
+    let mut responses = [Scalar::ZERO; 2];
+    challenges[other] = Scalar::rand();
+    responses[other] = Scalar::rand();
+    let nonce = Scalar::rand();
+    let mut commitments = [[Point::ZERO; 2]; 2];
+    commitments[other] =
+        vote_commitments(key, ciphertext, other, challenges[other], responses[other]);
+    commitments[vote] = [mul(nonce, generator()), mul(nonce, key)];
+    let total = ballot_challenge(proposal_id, voter_id, ciphertext, commitments);
+    challenges[vote] = total - challenges[other];
+    responses[vote] = nonce + challenges[vote] * randomness;
+    Ok(EncryptedBallot {
+        ciphertext: Ciphertext {
+            c1: encode_point(ciphertext.0),
+            c2: encode_point(ciphertext.1),
+        },
+        challenges: challenges.map(encode_field),
+        responses: responses.map(encode_field),
+    })
+}
+
+impl Ciphertext {
+    fn decode(&self) -> Option<(Point, Point)> {
+        Some((decode_point(&self.c1)?, decode_point(&self.c2)?))
+    }
+}
+
+impl EncryptedBallot {
+    /// Checks that the ballot encrypts 0 or 1 to `key`, and was made by
+    /// `voter_id` for `proposal_id`.
+    fn verify(&self, key: Point, proposal_id: &Uuid, voter_id: u32) -> bool {
+        let decoded = (
+            self.ciphertext.decode(),
+            decode_field::<Scalar>(&self.challenges[0]),
+            decode_field::<Scalar>(&self.challenges[1]),
+            decode_field::<Scalar>(&self.responses[0]),
+            decode_field::<Scalar>(&self.responses[1]),
+        );
+        let (ciphertext, challenges, responses) = match decoded {
+            (Some(ciphertext), Some(no), Some(yes), Some(z_no), Some(z_yes)) => {
+                (ciphertext, [no, yes], [z_no, z_yes])
+            }
+            _ => return false,
+        };
+        let commitments = [0, 1]
+            .map(|vote| vote_commitments(key, ciphertext, vote, challenges[vote], responses[vote]));
+        challenges[0] + challenges[1]
+            == ballot_challenge(proposal_id, voter_id, ciphertext, commitments)
+    }
+}
+
+/// Decrypts `c1` of the `aggregate` of the ballots with the secret share of
+/// `member_index`, as a member of the committee of `proposal_id` does once its
+/// voting period ended, see [`BallotBoxView::aggregate`].
+pub fn decrypt_share(
+    secret_share: &[u8; 32],
+    member_index: u32,
+    proposal_id: &Uuid,
+    aggregate: &Ciphertext,
+) -> anyhow::Result<DecryptionShare> {
+    let secret = decode_field::<Scalar>(secret_share)
+        .ok_or_else(|| anyhow::anyhow!("invalid secret share"))?;
+    let (c1, _) = aggregate
+        .decode()
+        .ok_or_else(|| anyhow::anyhow!("invalid ciphertext"))?;
+    let share = mul(secret, c1);
+    let nonce = Scalar::rand();
+    let challenge = challenge(
+        DECRYPTION_DOMAIN,
+        &proof_context(proposal_id, member_index),
+        &[
+            mul(secret, generator()),
+            c1,
+            share,
+            mul(nonce, generator()),
+            mul(nonce, c1),
+        ],
+    );
+    Ok(DecryptionShare {
+        member_index,
+        partial: PartialDecryption {
+            share: encode_point(share),
+            challenge: encode_field(challenge),
+            response: encode_field(nonce + challenge * secret),
+        },
+    })
+}
+
+impl PartialDecryption {
+    /// Checks that the partial decryption multiplies `c1` by the secret share
+    /// behind `public_share`, of `member_index` on `proposal_id`.
+    fn verify(
+        &self,
+        public_share: Point,
+        c1: Point,
+        proposal_id: &Uuid,
+        member_index: u32,
+    ) -> bool {
+        let decoded = (
+            decode_point(&self.share),
+            decode_field::<Scalar>(&self.challenge),
+            decode_field::<Scalar>(&self.response),
+        );
+        let (share, e, z) = match decoded {
+            (Some(share), Some(e), Some(z)) => (share, e, z),
+            _ => return false,
+        };
+        e == challenge(
+            DECRYPTION_DOMAIN,
+            &proof_context(proposal_id, member_index),
+            &[
+                public_share,
+                c1,
+                share,
+                sub(mul(z, generator()), mul(e, public_share)),
+                sub(mul(z, c1), mul(e, share)),
+            ],
+        )
+    }
+}
+
+/// Shares a fresh key among `members` members, any `threshold` of whom can
+/// decrypt, returning the committee and the secret shares of its members,
+/// whose indices are 1 to `members`. Whoever deals the shares knows
+/// the key, so committees that trust no dealer generate their shares together
+/// and only register the public shares.
+pub fn deal_committee(threshold: usize, members: usize) -> (BallotCommittee, Vec<[u8; 32]>) {
+    let coefficients: Vec<Scalar> = (0..threshold).map(|_| Scalar::rand()).collect();
+    let secret_shares: Vec<Scalar> = (1..=members)
+        .map(|index| {
+            let x = Scalar::from_canonical_usize(index);
+            coefficients
+                .iter()
+                .rev()
+                .fold(Scalar::ZERO, |sum, coefficient| sum * x + *coefficient)
+        })
+        .collect();
+    let committee = BallotCommittee {
+        threshold,
+        members: secret_shares
+            .iter()
+            .zip(1..)
+            .map(|(secret, member_index)| CommitteeMember {
+                member_index,
+                public_share: encode_point(mul(*secret, generator())),
+            })
+            .collect(),
+    };
+    (
+        committee,
+        secret_shares.into_iter().map(encode_field).collect(),
+    )
+}
+
+impl BallotCommittee {
+    /// Fails unless there are at most [`MAX_COMMITTEE_MEMBERS`] members with
+    /// distinct, non-zero indices and valid public shares, which all lie on the
+    /// polynomial the first `threshold` of them interpolate, and the threshold
+    /// is between one and their number.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let invalid = |message: String| Err(ApiError::new(ApiErrorCode::InvalidCommittee, message));
+        if self.members.len() > MAX_COMMITTEE_MEMBERS {
+            return invalid(format!(
+                "{} committee members given, at most {} are allowed",
+                self.members.len(),
+                MAX_COMMITTEE_MEMBERS
+            ));
+        }
+        if self.threshold == 0 || self.threshold > self.members.len() {
+            return invalid(format!(
+                "A threshold of {} cannot be met by {} committee members",
+                self.threshold,
+                self.members.len()
+            ));
+        }
+        let mut seen = BTreeSet::new();
+        for member in &self.members {
+            if member.member_index == 0 || !seen.insert(member.member_index) {
+                return invalid(format!(
+                    "Member index {} is zero or given more than once",
+                    member.member_index
+                ));
+            }
+            match decode_point(&member.public_share) {
+                Some(share) if !same(share, Point::ZERO) => {}
+                _ => {
+                    return invalid(format!(
+                        "The public share of member {} is not a point of secp256k1",
+                        member.member_index
+                    ))
+                }
+            }
+        }
+        let shares = self.shares();
+        let (basis, rest) = shares.split_at(self.threshold);
+        for (index, share) in rest {
+            if !same(
+                interpolate(basis, Scalar::from_canonical_u32(*index)),
+                *share,
+            ) {
+                return invalid(format!(
+                    "The public share of member {} is not a share of the same key as the others",
+                    index
+                ));
+            }
+        }
+        if same(self.key(), Point::ZERO) {
+            return invalid("The public shares add up to no key".to_string());
+        }
+        Ok(())
+    }
+    /// Fails unless votes under `rules` can be cast as encrypted ballots: they
+    /// need a deadline to be decrypted after, and are cast in full, once and in
+    /// parts only when decrypted, so commitments, conviction, quadratic voting
+    /// and minimum transfers do not apply.
+    pub fn check_rules(rules: &ProposalRules) -> Result<(), ApiError> {
+        let invalid = |message: &str| Err(ApiError::new(ApiErrorCode::InvalidCommittee, message));
+        if rules.voting_period_secs.is_none() {
+            return invalid(
+                "Encrypted ballots are decrypted after a voting period, which is unset",
+            );
+        }
+        if rules.commit_period_secs.is_some() {
+            return invalid("Encrypted ballots cannot be committed to");
+        }
+        if rules.conviction.is_some() {
+            return invalid("Encrypted ballots cannot be weighted by conviction");
+        }
+        if !rules.voting_policy.is_linear() {
+            return invalid("Encrypted ballots count their weight linearly");
+        }
+        if rules.min_transfer.is_some() {
+            return invalid("Encrypted ballots are counted in parts of any size");
+        }
+        Ok(())
+    }
+    fn shares(&self) -> Vec<(u32, Point)> {
+        self.members
+            .iter()
+            .map(|member| {
+                (
+                    member.member_index,
+                    decode_point(&member.public_share).unwrap_or(Point::ZERO),
+                )
+            })
+            .collect()
+    }
+    fn key(&self) -> Point {
+        interpolate(&self.shares()[..self.threshold], Scalar::ZERO)
+    }
+    /// The key ballots are encrypted to, see [`encrypt_ballot`].
+    pub fn public_key(&self) -> [u8; 64] {
+        encode_point(self.key())
+    }
+    pub fn member(&self, member_index: u32) -> Option<&CommitteeMember> {
+        self.members
+            .iter()
+            .find(|member| member.member_index == member_index)
+    }
+}
+
+impl BallotBox {
+    pub fn new(committee: BallotCommittee) -> Self {
+        Self {
+            committee,
+            ballots: vec![],
+            shares: vec![],
+            yes_votes: None,
+        }
+    }
+    /// The ballots times their weights, added up.
+    pub fn aggregate(&self) -> Ciphertext {
+        let (c1, c2) = self
+            .ballots
+            .iter()
+            .filter_map(|cast| {
+                let weight = Scalar::from_canonical_u64(cast.weight.get());
+                let (c1, c2) = cast.ballot.ciphertext.decode()?;
+                Some((mul(weight, c1), mul(weight, c2)))
+            })
+            .fold((Point::ZERO, Point::ZERO), |sum, weighted| {
+                (sum.0 + weighted.0, sum.1 + weighted.1)
+            });
+        Ciphertext {
+            c1: encode_point(c1),
+            c2: encode_point(c2),
+        }
+    }
+    pub fn view(&self) -> BallotBoxView {
+        BallotBoxView {
+            committee: self.committee.clone(),
+            public_key: hex::encode(self.committee.public_key()),
+            ballots: self.ballots.clone(),
+            aggregate: self.aggregate(),
+            decrypted_by: self.shares.iter().map(|share| share.member_index).collect(),
+            yes_votes: self.yes_votes,
+        }
+    }
+    /// Decrypts the aggregate with the first `threshold` shares, which were
+    /// checked when posted, to the yes votes, at most the weight of all ballots.
+    fn decrypt(&self) -> Result<Weight, ApiError> {
+        let partials: Option<Vec<(u32, Point)>> = self.shares[..self.committee.threshold]
+            .iter()
+            .map(|share| Some((share.member_index, decode_point(&share.partial.share)?)))
+            .collect();
+        let total = self
+            .ballots
+            .iter()
+            .map(|cast| cast.weight.get())
+            .sum::<u64>();
+        let yes_votes = match (partials, self.aggregate().decode()) {
+            (Some(partials), Some((_, c2))) => {
+                discrete_log(sub(c2, interpolate(&partials, Scalar::ZERO)), total)
+            }
+            _ => None,
+        };
+        yes_votes
+            .and_then(|yes_votes| Weight::try_from(yes_votes).ok())
+            .ok_or_else(|| {
+                ApiError::new(
+                    ApiErrorCode::InvalidDecryptionShare,
+                    format!(
+                        "The ballots decrypt to no number of yes votes up to their weight {}",
+                        total
+                    ),
+                )
+            })
+    }
+}
+
+impl Proposal {
+    /// Fails on proposals that take encrypted ballots, where `what` would cast
+    /// or move weight in the clear.
+    pub(super) fn ensure_clear_votes(&self, what: &str) -> Result<(), ApiError> {
+        if self.ballots.is_some() {
+            return Err(ApiError::new(
+                ApiErrorCode::BallotsEncrypted,
+                format!(
+                    "{} on a proposal whose votes are encrypted to a committee",
+                    what
+                ),
+            ));
+        }
+        Ok(())
+    }
+    /// Accepts the encrypted ballot of `voter_id` at time `now`, if its proof
+    /// checks out. The ballot counts with the full balance of the voter, and
+    /// stays out of the balance tree until the ballots are decrypted.
+    ///
+    /// Like [`Self::cast_vote`], a draft stays a draft.
+    pub fn cast_encrypted_ballot(
+        &mut self,
+        proposal_id: &Uuid,
+        voter_id: u32,
+        ballot: EncryptedBallot,
+        now: u64,
+    ) -> Result<(), ApiError> {
+        let voter = self.ballot_voter(voter_id, now)?;
+        let key = match &self.ballots {
+            Some(ballot_box) => ballot_box.committee.key(),
+            None => {
+                return Err(ApiError::new(
+                    ApiErrorCode::NotEncrypted,
+                    "Proposal takes votes in the clear",
+                ))
+            }
+        };
+        self.ensure_not_voted(voter)?;
+        let weight = self.voting_weight(voter)?;
+        if !ballot.verify(key, proposal_id, voter_id) {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidBallot,
+                "Ballot is not proven to encrypt a vote of the voter to the key of the committee",
+            ));
+        }
+        self.mark_voted(voter);
+        self.ballots.as_mut().unwrap().ballots.push(CastBallot {
+            voter_id,
+            weight,
+            ballot: ballot.clone(),
+            cast_at: now,
+        });
+        self.record(
+            vec![],
+            now,
+            TranscriptAction::EncryptedVote {
+                voter_id,
+                ballot: Box::new(ballot),
+            },
+        );
+        Ok(())
+    }
+    /// Records the decryption share of a member of the committee at time `now`,
+    /// once the voting period ended. The share completing the threshold decrypts
+    /// the aggregate of the ballots to the yes votes, casts the balances of the
+    /// voters into the tally in the order the ballots were cast, the first ones
+    /// on yes until the yes votes are reached and the rest on no, and returns true.
+    pub fn share_decryption(
+        &mut self,
+        proposal_id: &Uuid,
+        share: DecryptionShare,
+        now: u64,
+    ) -> Result<bool, ApiError> {
+        self.ensure_accepts_updates()?;
+        if self.ballots.is_none() {
+            return Err(ApiError::new(
+                ApiErrorCode::NotEncrypted,
+                "Proposal takes votes in the clear",
+            ));
+        }
+        if self.phase(now) != ProposalPhase::AwaitingFinalization {
+            return Err(ApiError::new(
+                ApiErrorCode::DecryptionNotOpen,
+                "Ballots are decrypted once the voting period ends",
+            ));
+        }
+        let ballot_box = self.ballots.as_mut().unwrap();
+        let invalid =
+            |message: String| ApiError::new(ApiErrorCode::InvalidDecryptionShare, message);
+        if ballot_box.yes_votes.is_some() {
+            return Err(invalid("Ballots are decrypted already".to_string()));
+        }
+        let public_share = ballot_box
+            .committee
+            .member(share.member_index)
+            .and_then(|member| decode_point(&member.public_share))
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{} is not a member of the committee",
+                    share.member_index
+                ))
+            })?;
+        if ballot_box
+            .shares
+            .iter()
+            .any(|shared| shared.member_index == share.member_index)
+        {
+            return Err(invalid(format!(
+                "Member {} has shared its decryption already",
+                share.member_index
+            )));
+        }
+        let verified = ballot_box.aggregate().decode().map_or(false, |(c1, _)| {
+            share
+                .partial
+                .verify(public_share, c1, proposal_id, share.member_index)
+        });
+        if !verified {
+            return Err(invalid(format!(
+                "The partial decryption of the aggregate of the ballots is not proven against the public share of member {}",
+                share.member_index
+            )));
+        }
+        ballot_box.shares.push(share.clone());
+        if ballot_box.shares.len() < ballot_box.committee.threshold {
+            self.record(vec![], now, TranscriptAction::Decrypt { share });
+            return Ok(false);
+        }
+        let yes_votes = match ballot_box.decrypt() {
+            Ok(yes_votes) => yes_votes,
+            Err(err) => {
+                ballot_box.shares.pop();
+                return Err(err);
+            }
+        };
+        let ballots = ballot_box.ballots.clone();
+        ballot_box.yes_votes = Some(yes_votes);
+        let mut updates = vec![];
+        // Ballots are counted as cast, in the order they were cast in, which says
+        // nothing about how each voter voted
+        let (mut cast_at, mut remaining) = (0, yes_votes.get());
+        for cast in &ballots {
+            let voter = self.electorate_voter(cast.voter_id)?;
+            cast_at = cast_at.max(cast.cast_at);
+            let yes = remaining.min(cast.weight.get());
+            remaining -= yes;
+            let split = VoteSplit {
+                yes_votes: Weight::try_from(yes).unwrap(),
+                no_votes: Weight::try_from(cast.weight.get() - yes).unwrap(),
+            };
+            let parts = self
+                .storage
+                .process_split_vote(voter, split, None, self.window_stamp(cast_at))
+                .unwrap();
+            updates.extend(parts);
+        }
+        self.record(updates, now, TranscriptAction::Decrypt { share });
+        Ok(true)
+    }
+    /// Whether the tally of the proposal can be proven: unless its encrypted
+    /// ballots still await decryption.
+    pub fn ballots_decrypted(&self) -> bool {
+        self.ballots.as_ref().map_or(true, |ballot_box| {
+            ballot_box.ballots.is_empty() || ballot_box.yes_votes.is_some()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{deal_committee, decrypt_share, encrypt_ballot, BallotBox, BallotCommittee};
+    use crate::{
+        balance::weight::Weight,
+        errors::{ApiError, ApiErrorCode},
+        proposal::{rules::ProposalRules, Proposal},
+    };
+
+    fn code<T: std::fmt::Debug>(result: Result<T, ApiError>) -> ApiErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn test_ballots_are_decrypted_by_a_threshold_after_the_deadline() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let (committee, secrets) = deal_committee(2, 3);
+        committee.validate().unwrap();
+        let rules = ProposalRules {
+            voting_period_secs: Some(10),
+            ..Default::default()
+        };
+        BallotCommittee::check_rules(&rules).unwrap();
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            rules,
+            vec![Weight::from(3); 4],
+        )?;
+        proposal.ballots = Some(BallotBox::new(committee.clone()));
+        let key = committee.public_key();
+
+        assert_eq!(
+            code(proposal.cast_vote(2, true, None, 1)),
+            ApiErrorCode::BallotsEncrypted
+        );
+        // A ballot is bound to its voter
+        let ballot = encrypt_ballot(&key, &id, 2, true)?;
+        assert_eq!(
+            code(proposal.cast_encrypted_ballot(&id, 3, ballot.clone(), 1)),
+            ApiErrorCode::InvalidBallot
+        );
+        proposal.cast_encrypted_ballot(&id, 2, ballot, 1).unwrap();
+        let ballot = encrypt_ballot(&key, &id, 3, false)?;
+        proposal.cast_encrypted_ballot(&id, 3, ballot, 2).unwrap();
+        let ballot = encrypt_ballot(&key, &id, 4, true)?;
+        proposal.cast_encrypted_ballot(&id, 4, ballot, 3).unwrap();
+        assert_eq!(proposal.storage.tally()?.yes_votes, Weight::ZERO);
+        assert!(!proposal.ballots_decrypted());
+
+        let aggregate = proposal.ballots.as_ref().unwrap().aggregate();
+        let share =
+            |member: usize| decrypt_share(&secrets[member - 1], member as u32, &id, &aggregate);
+        assert_eq!(
+            code(proposal.share_decryption(&id, share(1)?, 5)),
+            ApiErrorCode::DecryptionNotOpen
+        );
+        // Partial decryptions are proven against the public share of the member
+        let mut forged = share(1)?;
+        forged.member_index = 2;
+        assert_eq!(
+            code(proposal.share_decryption(&id, forged, 10)),
+            ApiErrorCode::InvalidDecryptionShare
+        );
+        assert!(!proposal.share_decryption(&id, share(3)?, 10).unwrap());
+        assert!(proposal.share_decryption(&id, share(1)?, 11).unwrap());
+
+        // Only the sum is decrypted, and the yes votes fill the first ballots
+        let ballot_box = proposal.ballots.as_ref().unwrap();
+        assert_eq!(ballot_box.yes_votes, Some(Weight::from(6)));
+        let tally = proposal.storage.tally()?;
+        assert_eq!(
+            (tally.yes_votes, tally.no_votes),
+            (Weight::from(6), Weight::from(3))
+        );
+        assert_eq!(proposal.updates.len(), 3);
+        assert!(proposal.ballots_decrypted());
+        Ok(())
+    }
+}
@@ -0,0 +1,746 @@
+//! The event-sourced core of proposals: every accepted command is appended to
+//! an [`EventLog`] as a [`ProposalEvent`], and proposals, their balance trees
+//! included, are derived by replaying it.
+//!
+//! Handlers apply the events they accept through [`apply_event`], the same
+//! function [`replay`] uses, so a replay reproduces the state the server was
+//! in. Each record carries the balance root its event left behind, which a
+//! replay checks, making the log a foundation for audit proofs. Replaying a
+//! prefix of the log gives the state at an earlier time.
+//!
+//! Deposits, anchors and timestamps live outside proposals, in the treasury and
+//! the background tasks, and are not part of the log.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use anyhow::ensure;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use uuid::Uuid;
+
+use crate::{
+    balance::{accounts::VoteSplit, storage::BalanceStorage, weight::Weight},
+    chain::token_snapshot::TokenSnapshot,
+    common::WHashOut,
+    did::Did,
+    errors::{ApiError, ApiErrorCode, QedError},
+    nullifier::nullifier_set::NullifierSet,
+    proof::{certificate::FinalizationCertificate, codec::ProofEnvelope},
+    utils::zmt::node_store::backend::NodeStore,
+};
+
+use super::{
+    action::ProposalAction,
+    approval::FinalizerPolicy,
+    blinding::VoterBlinding,
+    content::StatementContent,
+    delegation::BatchDelegation,
+    encryption::{BallotBox, BallotCommittee, DecryptionShare, EncryptedBallot},
+    relay::RelayedVote,
+    rules::ProposalRules,
+    store::ProposalStore,
+    Proposal, ProposalStatus,
+};
+
+/// What a proposal was created with, which rebuilds it before any event.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProposalGenesis {
+    pub dao_id: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    pub statement: String,
+    #[serde(default)]
+    pub content: Option<StatementContent>,
+    pub action: ProposalAction,
+    pub proposer_id: u32,
+    pub created_at: u64,
+    pub rules: ProposalRules,
+    pub token_snapshot: Option<TokenSnapshot>,
+    pub voter_dids: Vec<Did>,
+    /// Key of the permutation of voters to leaves, on blinded proposals.
+    #[serde(default)]
+    pub blinding: Option<VoterBlinding>,
+    pub tree_height: u8,
+    pub balance_bits: usize,
+    pub voter_balances: Vec<Weight>,
+    /// Height of the nullifier tree, on servers that track nullifiers.
+    pub nullifier_height: Option<u8>,
+    pub depends_on: Vec<Uuid>,
+    #[serde(default)]
+    pub finalizers: Option<FinalizerPolicy>,
+    /// Whether voting locks the tokens of voters in the shared token tree.
+    #[serde(default)]
+    pub locks_tokens: bool,
+    /// Committee the votes are encrypted to, on proposals that take encrypted ballots.
+    #[serde(default)]
+    pub ballot_committee: Option<BallotCommittee>,
+    /// The tied proposal this one reopens voting on, see [`Self::revote_round`].
+    #[serde(default)]
+    pub revote_of: Option<Uuid>,
+}
+
+impl ProposalGenesis {
+    /// The genesis of a proposal that was just created.
+    pub fn capture(proposal: &Proposal) -> Self {
+        Self {
+            dao_id: proposal.dao_id.clone(),
+            category: proposal.category.clone(),
+            statement: proposal.statement.clone(),
+            content: proposal.content.clone(),
+            action: proposal.action.clone(),
+            proposer_id: proposal.proposer_id,
+            created_at: proposal.created_at,
+            rules: proposal.rules.clone(),
+            token_snapshot: proposal.token_snapshot.clone(),
+            voter_dids: proposal.voter_dids.clone(),
+            blinding: proposal.blinding.clone(),
+            tree_height: proposal.storage.tree_height() as u8,
+            balance_bits: proposal.storage.balance_bits(),
+            voter_balances: proposal.storage.initial_balances().to_vec(),
+            nullifier_height: proposal.nullifiers.as_ref().map(NullifierSet::height),
+            depends_on: proposal.depends_on.clone(),
+            finalizers: proposal.finalizers.clone(),
+            locks_tokens: proposal.locks_tokens,
+            ballot_committee: proposal
+                .ballots
+                .as_ref()
+                .map(|ballot_box| ballot_box.committee.clone()),
+            revote_of: proposal.revote_of,
+        }
+    }
+    /// The genesis of the round reopening voting on the proposal `tied_id` after a
+    /// tie under [`TiePolicy::Revote`](super::rules::TiePolicy::Revote), opened at
+    /// `now`: the same statement, action, rules and electorate, every voter holding
+    /// their initial weight again. Its id is [`super::id::derive_revote_id`].
+    pub fn revote_round(tied_id: Uuid, tied: &Proposal, now: u64) -> Self {
+        Self {
+            created_at: now,
+            revote_of: Some(tied_id),
+            ..Self::capture(tied)
+        }
+    }
+    /// Builds the proposal `id` as it was created, with its trees in the given
+    /// stores.
+    pub fn build(
+        &self,
+        id: Uuid,
+        balance_store: NodeStore,
+        nullifier_store: Option<NodeStore>,
+    ) -> anyhow::Result<Proposal> {
+        let storage = BalanceStorage::with_store(
+            self.tree_height,
+            self.voter_balances.clone(),
+            self.balance_bits,
+            balance_store,
+        )?
+        .with_vesting(self.rules.vesting.clone())?;
+        let mut proposal = Proposal::with_storage(
+            self.statement.clone(),
+            self.proposer_id,
+            self.created_at,
+            self.rules.clone(),
+            storage,
+        );
+        proposal.dao_id = self.dao_id.clone();
+        proposal.category = self.category.clone();
+        proposal.content = self.content.clone();
+        proposal.action = self.action.clone();
+        proposal.token_snapshot = self.token_snapshot.clone();
+        proposal.voter_dids = self.voter_dids.clone();
+        proposal.blinding = self.blinding.clone();
+        proposal.depends_on = self.depends_on.clone();
+        proposal.finalizers = self.finalizers.clone();
+        proposal.locks_tokens = self.locks_tokens;
+        proposal.ballots = self.ballot_committee.clone().map(BallotBox::new);
+        proposal.revote_of = self.revote_of;
+        proposal.nullifiers = match (self.nullifier_height, nullifier_store) {
+            (Some(height), Some(store)) => Some(NullifierSet::with_store(id, height, store)),
+            (Some(_), None) => anyhow::bail!("proposal {} needs a nullifier store", id),
+            (None, _) => None,
+        };
+        proposal.seed_shards()?;
+        Ok(proposal)
+    }
+}
+
+/// A command the server accepted.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProposalEvent {
+    ProposalCreated(Box<ProposalGenesis>),
+    Amended {
+        statement: String,
+    },
+    Committed {
+        voter_id: u32,
+        #[serde_as(as = "serde_with::hex::Hex")]
+        commitment: [u8; 32],
+    },
+    VoteCast {
+        voter_id: u32,
+        is_yes: bool,
+        #[serde_as(as = "Option<serde_with::hex::Hex>")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        salt: Option<Vec<u8>>,
+    },
+    SplitVoteCast {
+        voter_id: u32,
+        split: VoteSplit,
+    },
+    EncryptedVoteCast {
+        voter_id: u32,
+        ballot: Box<EncryptedBallot>,
+    },
+    /// A vote signed by its voter and submitted by a relayer.
+    RelayedVoteCast {
+        vote: RelayedVote,
+        #[serde_as(as = "serde_with::hex::Hex")]
+        signature: Vec<u8>,
+    },
+    /// Decryption of the ballots shared by a member of the committee.
+    DecryptionShared {
+        share: DecryptionShare,
+    },
+    Revoked {
+        voter_id: u32,
+    },
+    Delegated {
+        voter_id: u32,
+        delegator_id: u32,
+    },
+    /// Delegations applied all or none, see [`Proposal::delegate_batch`].
+    DelegatedBatch {
+        delegations: Vec<BatchDelegation>,
+    },
+    /// A delegation moved back to the voter who made it, when its scope ended.
+    Undelegated {
+        voter_id: u32,
+    },
+    /// Cancelled by the proposer, or for spam by an admin.
+    Cancelled,
+    /// Approved for finalization by one of its finalizers.
+    FinalizationApproved {
+        finalizer_id: u32,
+        #[serde_as(as = "serde_with::hex::Hex")]
+        signature: Vec<u8>,
+    },
+    Finalized {
+        certificate: Box<FinalizationCertificate>,
+        proof: Box<ProofEnvelope>,
+    },
+    /// Deleted for spam by an admin.
+    Deleted,
+}
+
+impl ProposalEvent {
+    /// Voters who signed the request behind the event, each taking their next
+    /// nonce when it is applied, see [`Proposal::authenticate_voter`]. Relayed
+    /// votes carry nonces of their own.
+    pub fn signers(&self) -> Vec<u32> {
+        match self {
+            ProposalEvent::Committed { voter_id, .. }
+            | ProposalEvent::VoteCast { voter_id, .. }
+            | ProposalEvent::SplitVoteCast { voter_id, .. }
+            | ProposalEvent::EncryptedVoteCast { voter_id, .. }
+            | ProposalEvent::Revoked { voter_id }
+            | ProposalEvent::Delegated { voter_id, .. } => vec![*voter_id],
+            ProposalEvent::DelegatedBatch { delegations } => delegations
+                .iter()
+                .map(|delegation| delegation.voter_id)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// An event in the log, with the balance root of its proposal before it, which
+/// is known when the event is logged ahead of being applied.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Position of the record in the log, across all proposals.
+    pub seq: u64,
+    pub proposal_id: Uuid,
+    /// Unix time the event was accepted at, which replays apply it at.
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: ProposalEvent,
+    /// Zero before the creation of the proposal.
+    pub balance_root: WHashOut<GoldilocksField>,
+}
+
+/// Applies `event` to the proposal `id` of `proposals` at unix time `at`, failing
+/// like the command behind it would. Votes, commitments and delegations open a
+/// draft. Creations are applied by [`replay`], which has the stores to build
+/// their trees in; handlers insert the proposals they create themselves.
+pub fn apply_event(
+    proposals: &mut ProposalStore,
+    id: Uuid,
+    event: &ProposalEvent,
+    at: u64,
+) -> Result<(), ApiError> {
+    let proposal = proposals
+        .get_mut(&id)
+        .ok_or(QedError::ProposalNotFound(id))?;
+    let opens = match event {
+        ProposalEvent::ProposalCreated(_) => {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                format!("Proposal {} already exists", id),
+            ))
+        }
+        ProposalEvent::Amended { statement } => {
+            proposal.statement = statement.clone();
+            false
+        }
+        ProposalEvent::Committed {
+            voter_id,
+            commitment,
+        } => {
+            proposal.commit_vote(*voter_id, *commitment, at)?;
+            true
+        }
+        ProposalEvent::VoteCast {
+            voter_id,
+            is_yes,
+            salt,
+        } => {
+            proposal.cast_vote(*voter_id, *is_yes, salt.as_deref(), at)?;
+            true
+        }
+        ProposalEvent::SplitVoteCast { voter_id, split } => {
+            proposal.cast_split_vote(*voter_id, *split, at)?;
+            true
+        }
+        ProposalEvent::EncryptedVoteCast { voter_id, ballot } => {
+            proposal.cast_encrypted_ballot(&id, *voter_id, *ballot.clone(), at)?;
+            true
+        }
+        ProposalEvent::RelayedVoteCast { vote, signature } => {
+            proposal.cast_relayed_vote(&id, vote, signature, at)?;
+            true
+        }
+        ProposalEvent::DecryptionShared { share } => {
+            proposal.share_decryption(&id, share.clone(), at)?;
+            false
+        }
+        ProposalEvent::Revoked { voter_id } => {
+            proposal.revoke_vote(*voter_id, at)?;
+            false
+        }
+        ProposalEvent::Delegated {
+            voter_id,
+            delegator_id,
+        } => {
+            proposal.delegate(*voter_id, *delegator_id, at)?;
+            true
+        }
+        ProposalEvent::DelegatedBatch { delegations } => {
+            proposal.delegate_batch(delegations, at)?;
+            true
+        }
+        ProposalEvent::Undelegated { voter_id } => {
+            proposal.undelegate(*voter_id, at)?;
+            false
+        }
+        ProposalEvent::FinalizationApproved {
+            finalizer_id,
+            signature,
+        } => {
+            proposal.approve_finalization(&id, *finalizer_id, &hex::encode(signature), at)?;
+            false
+        }
+        ProposalEvent::Cancelled => {
+            proposals
+                .set_status(&id, ProposalStatus::Cancelled)
+                .map_err(|err| ApiError::new(ApiErrorCode::InvalidQuery, err))?;
+            return Ok(());
+        }
+        ProposalEvent::Finalized { certificate, proof } => {
+            proposal.certificate = Some(*certificate.clone());
+            proposal.proof = Some(*proof.clone());
+            proposal.finalized_at = Some(at);
+            // Finalizations are proven in between, which replays skip
+            if proposal.status != ProposalStatus::Finalizing {
+                proposals
+                    .set_status(&id, ProposalStatus::Finalizing)
+                    .map_err(|err| ApiError::new(ApiErrorCode::InvalidQuery, err))?;
+            }
+            proposals
+                .set_status(&id, ProposalStatus::Finalized)
+                .map_err(|err| ApiError::new(ApiErrorCode::InvalidQuery, err))?;
+            return Ok(());
+        }
+        ProposalEvent::Deleted => {
+            proposals.remove(&id);
+            return Ok(());
+        }
+    };
+    for voter_id in event.signers() {
+        proposal.consume_did_nonce(voter_id)?;
+    }
+    if opens && proposal.status == ProposalStatus::Draft {
+        proposals.set_status(&id, ProposalStatus::Open).unwrap();
+    }
+    Ok(())
+}
+
+/// Rebuilds the proposals of `records`, taken in order, opening the node store
+/// of each tree with `open_store`. Fails unless every event follows the balance
+/// root recorded with it and applies.
+pub fn replay<'a>(
+    records: impl IntoIterator<Item = &'a EventRecord>,
+    mut open_store: impl FnMut(&str) -> anyhow::Result<NodeStore>,
+) -> anyhow::Result<ProposalStore> {
+    let mut proposals = ProposalStore::new();
+    for record in records {
+        let id = record.proposal_id;
+        let balance_root = match proposals.get(&id) {
+            Some(proposal) => proposal.storage.tree.get_root()?,
+            None => WHashOut::ZERO,
+        };
+        ensure!(
+            balance_root == record.balance_root,
+            "event {} does not follow the balance root of proposal {}",
+            record.seq,
+            id
+        );
+        match &record.event {
+            ProposalEvent::ProposalCreated(genesis) => {
+                ensure!(
+                    proposals.get(&id).is_none(),
+                    "proposal {} is created twice",
+                    id
+                );
+                let balance_store = open_store(&format!("balances/{}", id))?;
+                let nullifier_store = match genesis.nullifier_height {
+                    Some(_) => Some(open_store(&format!("nullifiers/{}", id))?),
+                    None => None,
+                };
+                proposals.insert(id, genesis.build(id, balance_store, nullifier_store)?);
+            }
+            event => apply_event(&mut proposals, id, event, record.at).map_err(|err| {
+                anyhow::anyhow!("event {} of proposal {}: {}", record.seq, id, err.message)
+            })?,
+        }
+    }
+    Ok(proposals)
+}
+
+/// Append-only log of the events of all proposals, persisted as JSON lines when
+/// backed by a file.
+pub struct EventLog {
+    file: Option<File>,
+    /// Length of the file before the last record, which [`Self::retract`] cuts it back to.
+    last_offset: u64,
+    records: Vec<EventRecord>,
+    by_proposal: HashMap<Uuid, Vec<usize>>,
+}
+
+impl EventLog {
+    pub fn in_memory() -> Self {
+        Self {
+            file: None,
+            last_offset: 0,
+            records: vec![],
+            by_proposal: HashMap::new(),
+        }
+    }
+
+    /// Opens the log at `path`, loading the records already written to it.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut log = Self::in_memory();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                log.push(serde_json::from_str(&line)?)?;
+            }
+        }
+        log.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(log)
+    }
+
+    fn push(&mut self, record: EventRecord) -> anyhow::Result<()> {
+        ensure!(
+            record.seq == self.records.len() as u64,
+            "event {} is out of sequence",
+            record.seq
+        );
+        self.by_proposal
+            .entry(record.proposal_id)
+            .or_default()
+            .push(self.records.len());
+        self.records.push(record);
+        Ok(())
+    }
+
+    /// Appends `event` of the proposal `id`, accepted at `at` on `balance_root`,
+    /// and returns its sequence number. The record is on disk before it becomes
+    /// visible, and before the event is applied.
+    pub fn append(
+        &mut self,
+        proposal_id: Uuid,
+        at: u64,
+        event: ProposalEvent,
+        balance_root: WHashOut<GoldilocksField>,
+    ) -> anyhow::Result<u64> {
+        let record = EventRecord {
+            seq: self.records.len() as u64,
+            proposal_id,
+            at,
+            event,
+            balance_root,
+        };
+        if let Some(file) = &mut self.file {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            let offset = file.metadata()?.len();
+            if let Err(err) = file.write_all(&line).and_then(|_| file.sync_data()) {
+                // A partly written line would keep the log from being opened again
+                file.set_len(offset)?;
+                return Err(err.into());
+            }
+            self.last_offset = offset;
+        }
+        let seq = record.seq;
+        self.push(record)?;
+        Ok(seq)
+    }
+
+    /// Takes back the last record, `seq`, whose event was rejected when applied.
+    pub fn retract(&mut self, seq: u64) -> anyhow::Result<()> {
+        ensure!(
+            seq + 1 == self.records.len() as u64,
+            "event {} is not the last one",
+            seq
+        );
+        if let Some(file) = &mut self.file {
+            file.set_len(self.last_offset)?;
+            file.sync_data()?;
+        }
+        let record = self.records.pop().unwrap();
+        let positions = self.by_proposal.get_mut(&record.proposal_id).unwrap();
+        positions.pop();
+        if positions.is_empty() {
+            self.by_proposal.remove(&record.proposal_id);
+        }
+        Ok(())
+    }
+
+    /// Takes over the records of another log, e.g. when restoring a snapshot. The
+    /// log has to be empty so sequence numbers stay unique.
+    pub fn import(&mut self, records: Vec<EventRecord>) -> anyhow::Result<()> {
+        ensure!(
+            self.records.is_empty(),
+            "the event log already holds {} records",
+            self.records.len()
+        );
+        if let Some(file) = &mut self.file {
+            for record in &records {
+                let mut line = serde_json::to_vec(record)?;
+                line.push(b'\n');
+                file.write_all(&line)?;
+            }
+            file.sync_data()?;
+            self.last_offset = file.metadata()?.len();
+        }
+        for record in records {
+            self.push(record)?;
+        }
+        Ok(())
+    }
+
+    pub fn records(&self) -> &[EventRecord] {
+        &self.records
+    }
+
+    /// The events of one proposal, in order.
+    pub fn proposal_events(&self, proposal_id: &Uuid) -> Vec<EventRecord> {
+        self.by_proposal
+            .get(proposal_id)
+            .map_or(vec![], |positions| {
+                positions
+                    .iter()
+                    .map(|position| self.records[*position].clone())
+                    .collect()
+            })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    /// Whether the log is backed by a file, from which a restarted server rebuilds
+    /// its proposals.
+    pub fn is_persistent(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{apply_event, replay, EventLog, ProposalEvent, ProposalGenesis};
+    use crate::{
+        balance::weight::Weight,
+        common::WHashOut,
+        errors::ApiErrorCode,
+        proposal::{
+            id::derive_revote_id,
+            rules::{ProposalRules, TiePolicy},
+            store::ProposalStore,
+            Proposal, ProposalStatus,
+        },
+        utils::zmt::node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+    };
+
+    fn memory_store(_: &str) -> anyhow::Result<NodeStore> {
+        Ok(NodeStore::Memory(SimpleNodeStore::new()))
+    }
+
+    #[test]
+    fn test_replay_derives_the_proposals_and_their_history() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let proposal = Proposal::with_voter_balances(
+            "Fund the audit".to_string(),
+            0,
+            100,
+            ProposalRules::default(),
+            vec![Weight::from(1); 4],
+        )?;
+        let mut proposals = ProposalStore::new();
+        let mut log = EventLog::in_memory();
+        log.append(
+            id,
+            100,
+            ProposalEvent::ProposalCreated(Box::new(ProposalGenesis::capture(&proposal))),
+            WHashOut::ZERO,
+        )?;
+        proposals.insert(id, proposal);
+        let events = [
+            (
+                110,
+                ProposalEvent::VoteCast {
+                    voter_id: 2,
+                    is_yes: true,
+                    salt: None,
+                },
+            ),
+            (
+                120,
+                ProposalEvent::Delegated {
+                    voter_id: 3,
+                    delegator_id: 4,
+                },
+            ),
+            (
+                130,
+                ProposalEvent::VoteCast {
+                    voter_id: 4,
+                    is_yes: false,
+                    salt: None,
+                },
+            ),
+        ];
+        for (at, event) in events {
+            let root = proposals.get(&id).unwrap().storage.tree.get_root()?;
+            log.append(id, at, event.clone(), root)?;
+            apply_event(&mut proposals, id, &event, at).unwrap();
+        }
+        // Rejected commands are logged ahead, then taken back
+        let rejected = ProposalEvent::VoteCast {
+            voter_id: 2,
+            is_yes: false,
+            salt: None,
+        };
+        let root = proposals.get(&id).unwrap().storage.tree.get_root()?;
+        let seq = log.append(id, 140, rejected.clone(), root)?;
+        assert_eq!(
+            apply_event(&mut proposals, id, &rejected, 140)
+                .unwrap_err()
+                .code,
+            ApiErrorCode::NoVotingWeight
+        );
+        assert!(log.retract(seq - 1).is_err());
+        log.retract(seq)?;
+        assert_eq!(log.proposal_events(&id).len(), 4);
+
+        let replayed = replay(log.records(), memory_store)?;
+        let (live, derived) = (proposals.get(&id).unwrap(), replayed.get(&id).unwrap());
+        assert_eq!(derived.status, ProposalStatus::Open);
+        assert_eq!(derived.storage.tally()?, live.storage.tally()?);
+        assert_eq!(derived.updates, live.updates);
+        assert_eq!(derived.transcript, live.transcript);
+
+        // The state as of the delegation, before the second vote
+        let history = log.proposal_events(&id);
+        let earlier = replay(
+            history.iter().filter(|record| record.at <= 120),
+            memory_store,
+        )?;
+        let tally = earlier.get(&id).unwrap().storage.tally()?;
+        assert_eq!(
+            (tally.yes_votes, tally.no_votes),
+            (Weight::from(1), Weight::ZERO)
+        );
+
+        let mut tampered = log.records().to_vec();
+        tampered[1].balance_root = WHashOut::ZERO;
+        assert!(replay(&tampered, memory_store).is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_revote_rounds_reopen_voting() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let rules = ProposalRules {
+            tie_policy: TiePolicy::Revote,
+            ..ProposalRules::default()
+        };
+        let mut tied = Proposal::with_voter_balances(
+            "Fund the audit".to_string(),
+            0,
+            100,
+            rules,
+            vec![Weight::from(1); 4],
+        )?;
+        tied.cast_vote(2, true, None, 110)?;
+        tied.cast_vote(3, false, None, 120)?;
+        assert!(tied.storage.tally()?.is_tie());
+
+        let round_id = derive_revote_id(&id);
+        let genesis = ProposalGenesis::revote_round(id, &tied, 200);
+        let mut log = EventLog::in_memory();
+        log.append(
+            round_id,
+            200,
+            ProposalEvent::ProposalCreated(Box::new(genesis)),
+            WHashOut::ZERO,
+        )?;
+
+        // The electorate holds its initial weight again, and votes from scratch
+        let mut replayed = replay(log.records(), memory_store)?;
+        let round = replayed.get(&round_id).unwrap();
+        assert_eq!(round.revote_of, Some(id));
+        assert_eq!(round.created_at, 200);
+        assert_eq!(round.status, ProposalStatus::Draft);
+        assert_eq!(round.storage.initial_root(), tied.storage.initial_root());
+        assert!(round.updates.is_empty());
+        assert_eq!(round.storage.tally()?.no_votes, Weight::ZERO);
+        let vote = ProposalEvent::VoteCast {
+            voter_id: 2,
+            is_yes: false,
+            salt: None,
+        };
+        apply_event(&mut replayed, round_id, &vote, 210).unwrap();
+        Ok(())
+    }
+}
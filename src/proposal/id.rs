@@ -0,0 +1,78 @@
+//! Proposal ids derived from what a proposal is created with, so that creating the
+//! same proposal twice, on one server or several, yields the same id.
+
+use plonky2::{
+    field::{
+        goldilocks_field::GoldilocksField,
+        types::{Field, PrimeField64},
+    },
+    hash::poseidon::PoseidonHash,
+};
+use uuid::Uuid;
+
+use crate::{
+    common::{hash::traits::hasher::FieldWHasher, WHashOut},
+    nullifier::nullifier_set::proposal_id_to_elements,
+    proof::certificate::pack_bytes,
+};
+
+type F = GoldilocksField;
+
+/// Derives the id of the proposal `proposer_id` creates with `statement`, from the
+/// Poseidon hash of the statement packed as in
+/// [`compute_statement_hash`](crate::proof::certificate::compute_statement_hash),
+/// the proposer and the `nonce` split into two 32 bit elements. A proposer
+/// creating several proposals with the same statement tells them apart by nonce.
+///
+/// The first two elements of the hash fill a version 8 UUID, whose version and
+/// variant bits overwrite six of them.
+pub fn derive_proposal_id(statement: &str, proposer_id: u32, nonce: u64) -> Uuid {
+    let mut elements = pack_bytes(statement.as_bytes());
+    elements.push(F::from_canonical_u32(proposer_id));
+    elements.push(F::from_canonical_u64(nonce & 0xffffffff));
+    elements.push(F::from_canonical_u64(nonce >> 32));
+    uuid_from_hash(PoseidonHash::w_hash_many(&elements))
+}
+
+/// Derives the id of the round reopening voting on the proposal `tied` after a
+/// tie under [`TiePolicy::Revote`](super::rules::TiePolicy::Revote), from the
+/// Poseidon hash of `revote` packed as a statement and the elements of the id,
+/// so that replaying the log opens the round under the same id.
+pub fn derive_revote_id(tied: &Uuid) -> Uuid {
+    let mut elements = pack_bytes(b"revote");
+    elements.extend(proposal_id_to_elements(tied));
+    uuid_from_hash(PoseidonHash::w_hash_many(&elements))
+}
+
+fn uuid_from_hash(hash: WHashOut<F>) -> Uuid {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&hash.0.elements[0].to_canonical_u64().to_le_bytes());
+    bytes[8..].copy_from_slice(&hash.0.elements[1].to_canonical_u64().to_le_bytes());
+    bytes[6] = (bytes[6] & 0x0f) | 0x80;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Uuid::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{derive_proposal_id, derive_revote_id};
+
+    #[test]
+    fn test_derived_ids_are_stable_and_distinct() {
+        let id = derive_proposal_id("Fund the audit", 7, 0);
+        assert_eq!(id, derive_proposal_id("Fund the audit", 7, 0));
+        assert_eq!(id.get_version_num(), 8);
+        assert_ne!(id, derive_proposal_id("Fund the audit", 7, 1));
+        assert_ne!(id, derive_proposal_id("Fund the audit", 8, 0));
+        assert_ne!(id, derive_proposal_id("Fund the audit ", 7, 0));
+        assert_ne!(
+            derive_proposal_id("Fund the audit", 7, 1 << 32),
+            derive_proposal_id("Fund the audit", 7, 1)
+        );
+        let revote = derive_revote_id(&id);
+        assert_eq!(revote, derive_revote_id(&id));
+        assert_ne!(revote, id);
+        // A revote of the revote is another round again
+        assert_ne!(derive_revote_id(&revote), revote);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::{error, warn};
+
+use super::store::ProposalStore;
+
+/// Holding the store longer than this is reported when another request waits for it.
+pub const STALE_LOCK_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// The async lock around the proposal store. Waiting for it yields to the
+/// runtime instead of blocking a worker thread, and read-only requests share it.
+///
+/// A handler that panics while holding the write lock no longer takes the
+/// server down with it: the proposals it touched are rolled back to their last
+/// recorded update before the store is handed out again, and all other
+/// proposals keep being served.
+pub struct ProposalLock {
+    store: RwLock<ProposalStore>,
+    needs_recovery: AtomicBool,
+    created_at: Instant,
+    /// Milliseconds after `created_at` at which the current writer took the lock, plus one;
+    /// zero while not locked for writing.
+    held_since: AtomicU64,
+}
+
+pub struct ProposalReadGuard<'a> {
+    guard: RwLockReadGuard<'a, ProposalStore>,
+}
+
+pub struct ProposalGuard<'a> {
+    guard: RwLockWriteGuard<'a, ProposalStore>,
+    lock: &'a ProposalLock,
+}
+
+impl ProposalLock {
+    pub fn new(store: ProposalStore) -> Self {
+        Self {
+            store: RwLock::new(store),
+            needs_recovery: AtomicBool::new(false),
+            created_at: Instant::now(),
+            held_since: AtomicU64::new(0),
+        }
+    }
+
+    fn elapsed_millis(&self) -> u64 {
+        self.created_at.elapsed().as_millis() as u64
+    }
+
+    /// Shares the store with other readers.
+    pub async fn read(&self) -> ProposalReadGuard<'_> {
+        if self.needs_recovery.load(Ordering::SeqCst) {
+            // Readers must not see the changes of a panicked writer
+            drop(self.write().await);
+        }
+        ProposalReadGuard {
+            guard: self.store.read().await,
+        }
+    }
+
+    /// Locks the store exclusively, recovering from a writer that panicked first.
+    pub async fn write(&self) -> ProposalGuard<'_> {
+        let guard = match self.store.try_write() {
+            Ok(guard) => guard,
+            Err(_) => {
+                let held_since = self.held_since.load(Ordering::Relaxed);
+                if held_since > 0 {
+                    let held_for = Duration::from_millis(
+                        (self.elapsed_millis() + 1).saturating_sub(held_since),
+                    );
+                    if held_for > STALE_LOCK_THRESHOLD {
+                        warn!(
+                            held_for_secs = held_for.as_secs(),
+                            "Proposal store has been locked for long, waiting"
+                        );
+                    }
+                }
+                self.store.write().await
+            }
+        };
+        self.held_since
+            .store(self.elapsed_millis() + 1, Ordering::Relaxed);
+        let mut guard = ProposalGuard { guard, lock: self };
+        if self.needs_recovery.swap(false, Ordering::SeqCst) {
+            #[cfg(feature = "chaos")]
+            let failed = crate::utils::chaos::calm(|| guard.recover_touched());
+            #[cfg(not(feature = "chaos"))]
+            let failed = guard.recover_touched();
+            if !failed.is_empty() {
+                error!(?failed, "Proposals left in an inconsistent state");
+            }
+        }
+        guard.clear_touched();
+        guard
+    }
+}
+
+impl Deref for ProposalReadGuard<'_> {
+    type Target = ProposalStore;
+
+    fn deref(&self) -> &ProposalStore {
+        &self.guard
+    }
+}
+
+impl Deref for ProposalGuard<'_> {
+    type Target = ProposalStore;
+
+    fn deref(&self) -> &ProposalStore {
+        &self.guard
+    }
+}
+
+impl DerefMut for ProposalGuard<'_> {
+    fn deref_mut(&mut self) -> &mut ProposalStore {
+        &mut self.guard
+    }
+}
+
+impl Drop for ProposalGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.lock.needs_recovery.store(true, Ordering::SeqCst);
+        }
+        self.lock.held_since.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use crate::{
+        balance::{
+            accounts::{BalanceTx, TallySlot, VoterLeaf},
+            weight::{Weight, WeightDelta},
+        },
+        proposal::{rules::ProposalRules, store::ProposalStore, Proposal, ProposalStatus},
+    };
+
+    use super::ProposalLock;
+
+    #[tokio::test]
+    async fn test_recovers_from_panicking_holder() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let mut store = ProposalStore::new();
+        store.insert(
+            id,
+            Proposal::with_voter_balances(
+                "test".to_string(),
+                0,
+                0,
+                ProposalRules::default(),
+                vec![Weight::from(1); 4],
+            )?,
+        );
+        let lock = Arc::new(ProposalLock::new(store));
+        let root = lock
+            .read()
+            .await
+            .get(&id)
+            .unwrap()
+            .storage
+            .tree
+            .get_root()?;
+
+        let task_lock = lock.clone();
+        let result = tokio::spawn(async move {
+            let mut proposals = task_lock.write().await;
+            proposals
+                .set_status(&id, ProposalStatus::Finalizing)
+                .unwrap();
+            let proposal = proposals.get_mut(&id).unwrap();
+            // A vote that is processed but never recorded
+            proposal
+                .storage
+                .process_tx(BalanceTx::Vote {
+                    voter: VoterLeaf::from_position(0),
+                    slot: TallySlot::YES,
+                    amount: WeightDelta::from(1),
+                })
+                .unwrap();
+            panic!("handler failed");
+        })
+        .await;
+        assert!(result.unwrap_err().is_panic());
+
+        let proposals = lock.read().await;
+        let proposal = proposals.get(&id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Draft);
+        assert_eq!(proposal.storage.tree.get_root()?, root);
+        Ok(())
+    }
+}
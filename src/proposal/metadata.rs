@@ -0,0 +1,613 @@
+//! Proposal metadata mirrored into a SQL database, for the queries the store
+//! does not index: searching statements by text, and proposers and creation
+//! dates combined with any other filter.
+//!
+//! Proposals stay in memory, replayed from the event log, and their trees in
+//! the node store. The database only holds one [`ProposalMetadata`] row per
+//! proposal, written whenever the row changes, see [`diff_metadata`]; each
+//! batch of changes is written in one transaction, so a search never sees half
+//! of it. The database is SQLite or Postgres, picked by the scheme of its URL,
+//! and is only compiled in with the `sql` feature.
+
+use std::collections::HashMap;
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::balance::weight::Weight;
+
+use super::{
+    rules::ProposalOutcome,
+    store::{ProposalSort, ProposalStatusFilter, MAX_PER_PAGE},
+    Proposal, ProposalStatus,
+};
+
+/// Longest text a search matches statements against.
+pub const MAX_SEARCH_TEXT_LEN: usize = 256;
+
+/// What the database holds of a proposal.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProposalMetadata {
+    pub proposal_id: Uuid,
+    pub dao_id: String,
+    pub statement: String,
+    pub proposer_id: u32,
+    pub status: ProposalStatus,
+    pub created_at: u64,
+    pub deadline: Option<u64>,
+    pub finalized_at: Option<u64>,
+    /// Result of the proposal, once finalized.
+    pub outcome: Option<ProposalOutcome>,
+    pub yes_votes: Option<Weight>,
+    pub no_votes: Option<Weight>,
+    /// SHA-256 of the bytes of the finalization proof, hex encoded, like the
+    /// proof hash of [`crate::proof::attestation`].
+    pub proof_hash: Option<String>,
+}
+
+impl ProposalMetadata {
+    pub fn new(proposal_id: Uuid, proposal: &Proposal) -> Self {
+        let certificate = proposal.certificate.as_ref();
+        Self {
+            proposal_id,
+            dao_id: proposal.dao_id.clone(),
+            statement: proposal.statement.clone(),
+            proposer_id: proposal.proposer_id,
+            status: proposal.status,
+            created_at: proposal.created_at,
+            deadline: proposal.deadline(),
+            finalized_at: proposal.finalized_at,
+            outcome: certificate.map(|certificate| certificate.outcome),
+            yes_votes: certificate.map(|certificate| certificate.yes_votes),
+            no_votes: certificate.map(|certificate| certificate.no_votes),
+            proof_hash: proposal
+                .proof
+                .as_ref()
+                .map(|proof| hex::encode(Sha256::digest(&proof.proof_bytes))),
+        }
+    }
+}
+
+/// The rows of `current` that differ from what was last written, `synced`, and
+/// the proposals written that are gone since.
+pub fn diff_metadata(
+    synced: &HashMap<Uuid, ProposalMetadata>,
+    current: &HashMap<Uuid, ProposalMetadata>,
+) -> (Vec<ProposalMetadata>, Vec<Uuid>) {
+    let changed = current
+        .iter()
+        .filter(|(id, metadata)| synced.get(id) != Some(metadata))
+        .map(|(_, metadata)| metadata.clone())
+        .collect();
+    let removed = synced
+        .keys()
+        .filter(|id| !current.contains_key(id))
+        .copied()
+        .collect();
+    (changed, removed)
+}
+
+fn default_page() -> usize {
+    1
+}
+fn default_per_page() -> usize {
+    super::store::DEFAULT_PER_PAGE
+}
+
+/// Filters and pagination for searching proposal metadata; pages are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MetadataQuery {
+    /// Only proposals whose statement contains this text, ignoring case
+    pub text: Option<String>,
+    pub proposer_id: Option<u32>,
+    /// Only proposals of this DAO
+    pub dao_id: Option<String>,
+    pub status: Option<ProposalStatusFilter>,
+    /// Only proposals created at or after this unix time
+    pub created_after: Option<u64>,
+    /// Only proposals created before this unix time
+    pub created_before: Option<u64>,
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    #[serde(default)]
+    pub sort: ProposalSort,
+}
+
+impl Default for MetadataQuery {
+    fn default() -> Self {
+        Self {
+            text: None,
+            proposer_id: None,
+            dao_id: None,
+            status: None,
+            created_after: None,
+            created_before: None,
+            page: default_page(),
+            per_page: default_per_page(),
+            sort: ProposalSort::default(),
+        }
+    }
+}
+
+impl MetadataQuery {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(self.page >= 1, "page must be at least 1");
+        ensure!(
+            self.per_page >= 1 && self.per_page <= MAX_PER_PAGE,
+            "per_page must be between 1 and {}",
+            MAX_PER_PAGE
+        );
+        ensure!(
+            self.text
+                .as_ref()
+                .map_or(true, |text| text.len() <= MAX_SEARCH_TEXT_LEN),
+            "text must be at most {} bytes long",
+            MAX_SEARCH_TEXT_LEN
+        );
+        // Unix times are stored as signed 64 bit integers
+        for time in [self.created_after, self.created_before]
+            .into_iter()
+            .flatten()
+        {
+            ensure!(i64::try_from(time).is_ok(), "{} is not a unix time", time);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sql")]
+pub use sql::SqlMetadataStore;
+
+#[cfg(feature = "sql")]
+mod sql {
+    use anyhow::Context;
+    use sqlx::{
+        any::{install_default_drivers, AnyArguments, AnyPoolOptions, AnyRow},
+        query::Query,
+        Any, AnyPool, Row,
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        balance::weight::Weight,
+        proposal::{
+            rules::ProposalOutcome,
+            store::{Page, ProposalSort},
+            ProposalStatus,
+        },
+    };
+
+    use super::{MetadataQuery, ProposalMetadata};
+
+    const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS proposal_metadata (
+        proposal_id TEXT PRIMARY KEY,
+        dao_id TEXT NOT NULL,
+        statement TEXT NOT NULL,
+        proposer_id BIGINT NOT NULL,
+        status TEXT NOT NULL,
+        created_at BIGINT NOT NULL,
+        deadline BIGINT,
+        finalized_at BIGINT,
+        outcome TEXT,
+        yes_votes BIGINT,
+        no_votes BIGINT,
+        proof_hash TEXT
+    )";
+    const CREATE_INDEXES: [&str; 3] = [
+        "CREATE INDEX IF NOT EXISTS proposal_metadata_created_at ON proposal_metadata (created_at)",
+        "CREATE INDEX IF NOT EXISTS proposal_metadata_proposer_id ON proposal_metadata (proposer_id)",
+        "CREATE INDEX IF NOT EXISTS proposal_metadata_dao_id ON proposal_metadata (dao_id)",
+    ];
+    const COLUMNS: &str = "proposal_id, dao_id, statement, proposer_id, status, created_at, \
+        deadline, finalized_at, outcome, yes_votes, no_votes, proof_hash";
+    const UPSERT: &str = "INSERT INTO proposal_metadata (proposal_id, dao_id, statement, \
+        proposer_id, status, created_at, deadline, finalized_at, outcome, yes_votes, no_votes, \
+        proof_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) \
+        ON CONFLICT (proposal_id) DO UPDATE SET dao_id = excluded.dao_id, \
+        statement = excluded.statement, proposer_id = excluded.proposer_id, \
+        status = excluded.status, created_at = excluded.created_at, \
+        deadline = excluded.deadline, finalized_at = excluded.finalized_at, \
+        outcome = excluded.outcome, yes_votes = excluded.yes_votes, \
+        no_votes = excluded.no_votes, proof_hash = excluded.proof_hash";
+    const DELETE: &str = "DELETE FROM proposal_metadata WHERE proposal_id = $1";
+
+    const STATUSES: [ProposalStatus; 5] = [
+        ProposalStatus::Draft,
+        ProposalStatus::Open,
+        ProposalStatus::Cancelled,
+        ProposalStatus::Finalizing,
+        ProposalStatus::Finalized,
+    ];
+    const OUTCOMES: [ProposalOutcome; 3] = [
+        ProposalOutcome::Passed,
+        ProposalOutcome::Vetoed,
+        ProposalOutcome::Revote,
+    ];
+
+    /// A value bound to a placeholder of a search.
+    enum Param {
+        Text(String),
+        Int(i64),
+    }
+
+    /// Proposal metadata in a SQLite or Postgres database.
+    pub struct SqlMetadataStore {
+        pool: AnyPool,
+    }
+
+    impl SqlMetadataStore {
+        /// Connects to the database at `url`, e.g. `sqlite://qed.db?mode=rwc` or
+        /// `postgres://qed@localhost/qed`, creating the table if needed.
+        pub async fn connect(url: &str) -> anyhow::Result<Self> {
+            install_default_drivers();
+            // Every connection to an in-memory SQLite database opens a database of its own
+            let max_connections = if url.starts_with("sqlite:") { 1 } else { 5 };
+            let pool = AnyPoolOptions::new()
+                .max_connections(max_connections)
+                .connect(url)
+                .await
+                .context("failed to connect to the metadata database")?;
+            sqlx::query(CREATE_TABLE).execute(&pool).await?;
+            for index in CREATE_INDEXES {
+                sqlx::query(index).execute(&pool).await?;
+            }
+            Ok(Self { pool })
+        }
+        /// Writes `changed` and deletes the rows of `removed` in one transaction.
+        pub async fn sync(
+            &self,
+            changed: &[ProposalMetadata],
+            removed: &[Uuid],
+        ) -> anyhow::Result<()> {
+            let mut tx = self.pool.begin().await?;
+            for metadata in changed {
+                sqlx::query(UPSERT)
+                    .bind(metadata.proposal_id.to_string())
+                    .bind(metadata.dao_id.clone())
+                    .bind(metadata.statement.clone())
+                    .bind(i64::from(metadata.proposer_id))
+                    .bind(status_str(metadata.status))
+                    .bind(to_i64(metadata.created_at)?)
+                    .bind(metadata.deadline.map(to_i64).transpose()?)
+                    .bind(metadata.finalized_at.map(to_i64).transpose()?)
+                    .bind(metadata.outcome.map(|outcome| outcome.as_str().to_string()))
+                    .bind(
+                        metadata
+                            .yes_votes
+                            .map(|votes| to_i64(votes.get()))
+                            .transpose()?,
+                    )
+                    .bind(
+                        metadata
+                            .no_votes
+                            .map(|votes| to_i64(votes.get()))
+                            .transpose()?,
+                    )
+                    .bind(metadata.proof_hash.clone())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            for id in removed {
+                sqlx::query(DELETE)
+                    .bind(id.to_string())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        }
+        /// The page of the rows matching `query`, oldest or newest first.
+        pub async fn search(
+            &self,
+            query: &MetadataQuery,
+        ) -> anyhow::Result<Page<ProposalMetadata>> {
+            query.validate()?;
+            let mut conditions = vec![];
+            let mut params = vec![];
+            let mut condition = |sql: &str, param: Param| {
+                params.push(param);
+                conditions.push(sql.replace('?', &format!("${}", params.len())));
+            };
+            if let Some(text) = &query.text {
+                let pattern = format!("%{}%", escape_like(&text.to_lowercase()));
+                condition("LOWER(statement) LIKE ? ESCAPE '\\'", Param::Text(pattern));
+            }
+            if let Some(proposer_id) = query.proposer_id {
+                condition("proposer_id = ?", Param::Int(proposer_id.into()));
+            }
+            if let Some(dao_id) = &query.dao_id {
+                condition("dao_id = ?", Param::Text(dao_id.clone()));
+            }
+            if let Some(created_after) = query.created_after {
+                condition("created_at >= ?", Param::Int(to_i64(created_after)?));
+            }
+            if let Some(created_before) = query.created_before {
+                condition("created_at < ?", Param::Int(to_i64(created_before)?));
+            }
+            if let Some(filter) = query.status {
+                for status in STATUSES
+                    .into_iter()
+                    .filter(|status| !filter.matches(*status))
+                {
+                    condition("status <> ?", Param::Text(status_str(status)));
+                }
+            }
+            let filter = if conditions.is_empty() {
+                String::new()
+            } else {
+                format!(" WHERE {}", conditions.join(" AND "))
+            };
+
+            let count_sql = format!("SELECT COUNT(*) FROM proposal_metadata{}", filter);
+            let total_items: i64 = bind(sqlx::query(&count_sql), &params)
+                .fetch_one(&self.pool)
+                .await?
+                .try_get(0)?;
+            let total_items = total_items as usize;
+            let order = match query.sort {
+                ProposalSort::CreatedAt => "ASC",
+                ProposalSort::CreatedAtDesc => "DESC",
+            };
+            let page_sql = format!(
+                "SELECT {} FROM proposal_metadata{} ORDER BY created_at {}, proposal_id {} LIMIT {} OFFSET {}",
+                COLUMNS,
+                filter,
+                order,
+                order,
+                query.per_page,
+                (query.page - 1).saturating_mul(query.per_page)
+            );
+            let rows = bind(sqlx::query(&page_sql), &params)
+                .fetch_all(&self.pool)
+                .await?;
+            Ok(Page {
+                items: rows.iter().map(from_row).collect::<anyhow::Result<_>>()?,
+                page: query.page,
+                per_page: query.per_page,
+                total_items,
+                total_pages: (total_items + query.per_page - 1) / query.per_page,
+            })
+        }
+    }
+
+    fn bind<'q>(
+        mut query: Query<'q, Any, AnyArguments<'q>>,
+        params: &[Param],
+    ) -> Query<'q, Any, AnyArguments<'q>> {
+        for param in params {
+            query = match param {
+                Param::Text(text) => query.bind(text.clone()),
+                Param::Int(int) => query.bind(*int),
+            };
+        }
+        query
+    }
+
+    /// Escapes the wildcards of a `LIKE` pattern, with `\` as the escape character.
+    fn escape_like(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '\\' | '%' | '_') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    fn to_i64(value: u64) -> anyhow::Result<i64> {
+        i64::try_from(value).context("value does not fit in a BIGINT column")
+    }
+
+    fn status_str(status: ProposalStatus) -> String {
+        serde_json::to_value(status)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn from_row(row: &AnyRow) -> anyhow::Result<ProposalMetadata> {
+        let status: String = row.try_get("status")?;
+        let outcome: Option<String> = row.try_get("outcome")?;
+        let weight = |column: &str| -> anyhow::Result<Option<Weight>> {
+            let votes: Option<i64> = row.try_get(column)?;
+            votes
+                .map(|votes| Weight::try_from(votes as u64))
+                .transpose()
+        };
+        let time = |column: &str| -> anyhow::Result<Option<u64>> {
+            let time: Option<i64> = row.try_get(column)?;
+            Ok(time.map(|time| time as u64))
+        };
+        let proposer_id: i64 = row.try_get("proposer_id")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        Ok(ProposalMetadata {
+            proposal_id: row.try_get::<String, _>("proposal_id")?.parse()?,
+            dao_id: row.try_get("dao_id")?,
+            statement: row.try_get("statement")?,
+            proposer_id: u32::try_from(proposer_id)?,
+            status: STATUSES
+                .into_iter()
+                .find(|known| status_str(*known) == status)
+                .with_context(|| format!("unknown status {}", status))?,
+            created_at: created_at as u64,
+            deadline: time("deadline")?,
+            finalized_at: time("finalized_at")?,
+            outcome: match outcome {
+                Some(outcome) => Some(
+                    OUTCOMES
+                        .into_iter()
+                        .find(|known| known.as_str() == outcome)
+                        .with_context(|| format!("unknown outcome {}", outcome))?,
+                ),
+                None => None,
+            },
+            yes_votes: weight("yes_votes")?,
+            no_votes: weight("no_votes")?,
+            proof_hash: row.try_get("proof_hash")?,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+
+        use crate::{
+            balance::weight::Weight,
+            proposal::{
+                metadata::{diff_metadata, MetadataQuery, ProposalMetadata},
+                rules::ProposalRules,
+                store::{ProposalSort, ProposalStatusFilter},
+                Proposal, ProposalStatus,
+            },
+        };
+
+        use super::SqlMetadataStore;
+
+        #[tokio::test]
+        async fn test_searches_proposals_by_text_proposer_and_date() -> anyhow::Result<()> {
+            let store = SqlMetadataStore::connect("sqlite::memory:").await?;
+            let metadata = |statement: &str, proposer_id, created_at| {
+                let proposal = Proposal::with_voter_balances(
+                    statement.to_string(),
+                    proposer_id,
+                    created_at,
+                    ProposalRules::default(),
+                    vec![Weight::from(1); 2],
+                )
+                .unwrap();
+                ProposalMetadata::new(uuid::Uuid::new_v4(), &proposal)
+            };
+            let rows = [
+                metadata("Fund the audit", 1, 100),
+                metadata("Fund 100% of the grant", 2, 200),
+                metadata("Rename the DAO", 1, 300),
+            ];
+            let current: HashMap<_, _> = rows
+                .iter()
+                .map(|metadata| (metadata.proposal_id, metadata.clone()))
+                .collect();
+            let (changed, removed) = diff_metadata(&HashMap::new(), &current);
+            store.sync(&changed, &removed).await?;
+
+            let search = |query: MetadataQuery| {
+                let store = &store;
+                async move {
+                    let page = store.search(&query).await?;
+                    anyhow::Ok(
+                        page.items
+                            .iter()
+                            .map(|metadata| metadata.statement.clone())
+                            .collect::<Vec<_>>(),
+                    )
+                }
+            };
+            assert_eq!(
+                search(MetadataQuery {
+                    text: Some("FUND".to_string()),
+                    sort: ProposalSort::CreatedAtDesc,
+                    ..Default::default()
+                })
+                .await?,
+                ["Fund 100% of the grant", "Fund the audit"]
+            );
+            // Wildcards in the text are matched literally
+            assert_eq!(
+                search(MetadataQuery {
+                    text: Some("0%".to_string()),
+                    ..Default::default()
+                })
+                .await?,
+                ["Fund 100% of the grant"]
+            );
+            assert_eq!(
+                search(MetadataQuery {
+                    proposer_id: Some(1),
+                    created_after: Some(150),
+                    ..Default::default()
+                })
+                .await?,
+                ["Rename the DAO"]
+            );
+
+            // Rows are rewritten as proposals change, and dropped once they are gone
+            let mut finalized = rows[0].clone();
+            finalized.status = ProposalStatus::Finalized;
+            let mut next = current.clone();
+            next.insert(finalized.proposal_id, finalized.clone());
+            next.remove(&rows[2].proposal_id);
+            let (changed, removed) = diff_metadata(&current, &next);
+            assert_eq!(changed, [finalized]);
+            assert_eq!(removed, [rows[2].proposal_id]);
+            store.sync(&changed, &removed).await?;
+            assert_eq!(
+                search(MetadataQuery {
+                    status: Some(ProposalStatusFilter::Open),
+                    ..Default::default()
+                })
+                .await?,
+                ["Fund 100% of the grant"]
+            );
+            let page = store
+                .search(&MetadataQuery {
+                    per_page: 1,
+                    ..Default::default()
+                })
+                .await?;
+            assert_eq!((page.total_items, page.total_pages), (2, 2));
+            assert_eq!(page.items[0].status, ProposalStatus::Finalized);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+
+    use super::{diff_metadata, MetadataQuery, ProposalMetadata};
+    use crate::{
+        balance::weight::Weight,
+        proposal::{rules::ProposalRules, Proposal, ProposalStatus},
+    };
+
+    #[test]
+    fn test_rows_are_rewritten_once_changed() -> anyhow::Result<()> {
+        let proposal = Proposal::with_voter_balances(
+            "Fund the audit".to_string(),
+            1,
+            100,
+            ProposalRules::default(),
+            vec![Weight::from(1); 2],
+        )?;
+        let id = Uuid::new_v4();
+        let metadata = ProposalMetadata::new(id, &proposal);
+        assert_eq!(metadata.proposer_id, 1);
+        assert_eq!((metadata.outcome, metadata.proof_hash), (None, None));
+
+        let synced = HashMap::from([(id, metadata.clone())]);
+        assert_eq!(diff_metadata(&synced, &synced), (vec![], vec![]));
+        let mut finalized = metadata;
+        finalized.status = ProposalStatus::Finalized;
+        let current = HashMap::from([(id, finalized.clone())]);
+        assert_eq!(diff_metadata(&synced, &current), (vec![finalized], vec![]));
+        assert_eq!(diff_metadata(&synced, &HashMap::new()), (vec![], vec![id]));
+
+        assert!(MetadataQuery {
+            created_after: Some(u64::MAX),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        Ok(())
+    }
+}
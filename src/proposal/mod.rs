@@ -0,0 +1,916 @@
+pub mod action;
+pub mod approval;
+pub mod archive;
+pub mod blinding;
+pub mod commitment;
+pub mod content;
+pub mod delegation;
+pub mod dependency;
+pub mod encryption;
+pub mod events;
+pub mod id;
+pub mod lock;
+pub mod metadata;
+pub mod org;
+pub mod quota;
+pub mod relay;
+pub mod rules;
+pub mod sanity;
+pub mod scoped_delegation;
+pub mod search;
+pub mod store;
+pub mod tally_history;
+pub mod transcript;
+pub mod validation;
+pub mod view;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::ensure;
+use plonky2::field::{goldilocks_field::GoldilocksField, types::PrimeField64};
+use qed_verifier::{ConvictionSchedule, ProvenRules, VotingWindow};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::{
+        accounts::{BalanceTx, TallySlot, VoteSplit, VoterLeaf},
+        shards::ShardedBalanceStorage,
+        storage::{min_tree_height, BalanceStorage},
+        treasury::ProposalDeposit,
+        weight::{Weight, WeightDelta},
+    },
+    chain::{anchor::AnchorRecord, token_snapshot::TokenSnapshot},
+    circuits::{
+        conviction::ConvictionStamp,
+        deadline::WindowStamp,
+        update_balance::{pad_updates, BalanceUpdate, UpdateKind},
+    },
+    common::WHashOut,
+    did::Did,
+    errors::{ApiError, ApiErrorCode, QedError},
+    nullifier::nullifier_set::NullifierSet,
+    proof::{certificate::FinalizationCertificate, codec::ProofEnvelope},
+    utils::zmt::node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+};
+
+use self::{
+    action::ProposalAction,
+    approval::{FinalizeApproval, FinalizerPolicy},
+    blinding::VoterBlinding,
+    commitment::compute_vote_commitment,
+    content::{ContentCheck, StatementContent},
+    delegation::DelegationRegistry,
+    encryption::BallotBox,
+    rules::ProposalRules,
+    tally_history::TallyPoint,
+    transcript::{TranscriptAction, TranscriptEvent},
+};
+
+/// Where a proposal is in its lifecycle at a given time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalPhase {
+    /// Voters commit to their votes, which are cast once the commitment period ends.
+    Committing,
+    Voting,
+    AwaitingFinalization,
+    Finalized,
+    Cancelled,
+}
+
+/// The lifecycle state of a proposal. A proposal stays a draft, which the
+/// proposer can amend or cancel, until the first vote or delegation opens it.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Draft,
+    Open,
+    Cancelled,
+    Finalizing,
+    Finalized,
+}
+
+impl ProposalStatus {
+    pub fn can_transition_to(self, next: ProposalStatus) -> bool {
+        use ProposalStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Open)
+                | (Draft, Cancelled)
+                | (Draft, Finalizing)
+                | (Open, Finalizing)
+                // Cancelled for spam by an admin
+                | (Open, Cancelled)
+                | (Finalizing, Finalized)
+                // Proving failed, voting continues
+                | (Finalizing, Open)
+                | (Finalizing, Draft)
+        )
+    }
+    /// Whether votes and delegations can be cast on the proposal.
+    pub fn accepts_updates(self) -> bool {
+        matches!(self, ProposalStatus::Draft | ProposalStatus::Open)
+    }
+    pub fn is_terminal(self) -> bool {
+        matches!(self, ProposalStatus::Cancelled | ProposalStatus::Finalized)
+    }
+}
+
+/// DAO that proposals created without naming one belong to.
+pub const DEFAULT_DAO_ID: &str = "default";
+
+/// Number of voters, each with a weight of one, in proposals not seeded from a token snapshot.
+pub const DEFAULT_ELECTORATE_SIZE: usize = 1024;
+/// Most voters a proposal can be created for by size, each with a weight of one.
+pub const MAX_ELECTORATE_SIZE: usize = 1 << 20;
+
+pub struct Proposal {
+    /// The DAO the proposal belongs to, which its storage is accounted to.
+    pub dao_id: String,
+    /// Category the proposal was filed under, which delegations can be scoped
+    /// to, see [`scoped_delegation`].
+    pub category: Option<String>,
+    pub statement: String,
+    /// External document the statement refers to, which amendments leave unchanged.
+    pub content: Option<StatementContent>,
+    /// The last check of `content` by the server, if it fetches documents.
+    pub content_check: Option<ContentCheck>,
+    /// What passing the proposal commits to, which amendments leave unchanged.
+    pub action: ProposalAction,
+    pub storage: BalanceStorage,
+    /// The electorate split across shards, on proposals whose rules shard it,
+    /// taking every vote and delegation `storage` takes, see [`Self::seed_shards`].
+    pub shards: Option<ShardedBalanceStorage>,
+    pub proposer_id: u32,
+    pub created_at: u64,
+    pub rules: ProposalRules,
+    /// The token holdings the voter balances were seeded from, if any.
+    pub token_snapshot: Option<TokenSnapshot>,
+    /// DIDs of the voters in electorate order, which their requests have to be
+    /// signed by. Empty unless the electorate was registered by DID.
+    pub voter_dids: Vec<Did>,
+    /// Key placing voters at pseudorandom leaves, on proposals whose voter
+    /// indices are blinded, see [`blinding`].
+    pub blinding: Option<VoterBlinding>,
+    pub updates: Vec<BalanceUpdate<GoldilocksField>>,
+    /// The accepted actions behind `updates`, see [`transcript::Transcript`].
+    pub transcript: Vec<TranscriptEvent>,
+    /// The tally after each action that changed it, see [`tally_history`].
+    pub tally_history: Vec<TallyPoint>,
+    pub voted: BTreeSet<VoterLeaf>,
+    /// Who delegated to whom, see [`delegation`].
+    pub delegations: DelegationRegistry,
+    /// Vote commitments made during the commitment period, see [`commitment`].
+    pub commitments: BTreeMap<VoterLeaf, [u8; 32]>,
+    pub status: ProposalStatus,
+    pub proof: Option<ProofEnvelope>,
+    pub nullifiers: Option<NullifierSet>,
+    pub anchors: Vec<AnchorRecord>,
+    pub certificate: Option<FinalizationCertificate>,
+    /// When the proposal was finalized, some time after which its trees are compacted.
+    pub finalized_at: Option<u64>,
+    /// Deposit the proposer locked, on servers that require one.
+    pub deposit: Option<ProposalDeposit>,
+    /// Finalizers a threshold of whom has to approve finalizing the proposal,
+    /// instead of its proposer finalizing it alone, see [`approval`].
+    pub finalizers: Option<FinalizerPolicy>,
+    /// Approvals of the finalizers so far.
+    pub approvals: Vec<FinalizeApproval>,
+    /// Proposals whose outcomes this one depends on, see [`dependency`].
+    pub depends_on: Vec<Uuid>,
+    /// Whether voters were seeded with the tokens they hold in the shared token
+    /// tree, which voting locks until the proposal resolves, see
+    /// [`crate::balance::locks`].
+    pub locks_tokens: bool,
+    /// Ballots encrypted to a committee, on proposals that take votes as such,
+    /// see [`encryption`].
+    pub ballots: Option<BallotBox>,
+    /// Nonce the next relayed vote of each voter id carries, see [`relay`].
+    pub relay_nonces: BTreeMap<u32, u64>,
+    /// Nonce the next signed request of each voter id carries, on proposals whose
+    /// electorate was registered by DID, see [`Self::authenticate_voter`].
+    pub did_nonces: BTreeMap<u32, u64>,
+    /// The proposal whose tie this one reopened voting after, under
+    /// [`rules::TiePolicy::Revote`], see [`events::ProposalGenesis::revote_round`].
+    pub revote_of: Option<Uuid>,
+}
+impl Proposal {
+    pub fn new(
+        statement: String,
+        proposer_id: u32,
+        created_at: u64,
+        rules: ProposalRules,
+    ) -> anyhow::Result<Self> {
+        let voter_balances = vec![Weight::from(1); DEFAULT_ELECTORATE_SIZE];
+        Self::with_voter_balances(statement, proposer_id, created_at, rules, voter_balances)
+    }
+    /// Seeds an in-memory electorate in the smallest tree that holds it, failing
+    /// if its total weight does not fit in the balance width of `rules`.
+    pub fn with_voter_balances(
+        statement: String,
+        proposer_id: u32,
+        created_at: u64,
+        rules: ProposalRules,
+        voter_balances: Vec<Weight>,
+    ) -> anyhow::Result<Self> {
+        let storage = BalanceStorage::with_store(
+            min_tree_height(voter_balances.len()),
+            voter_balances,
+            rules.balance_bits(),
+            NodeStore::Memory(SimpleNodeStore::new()),
+        )?
+        .with_vesting(rules.vesting.clone())?;
+        let mut proposal = Self::with_storage(statement, proposer_id, created_at, rules, storage);
+        proposal.seed_shards()?;
+        Ok(proposal)
+    }
+    pub fn with_storage(
+        statement: String,
+        proposer_id: u32,
+        created_at: u64,
+        rules: ProposalRules,
+        mut storage: BalanceStorage,
+    ) -> Self {
+        // Creates a new policiy around the balance storage object
+        storage.set_voting_policy(rules.voting_policy);
+        storage.set_min_transfer(rules.min_transfer);
+        let updates = vec![];
+        Self {
+            dao_id: DEFAULT_DAO_ID.to_string(),
+            category: None,
+            statement,
+            content: None,
+            content_check: None,
+            action: ProposalAction::TextOnly,
+            storage,
+            shards: None,
+            proposer_id,
+            created_at,
+            rules,
+            token_snapshot: None,
+            voter_dids: vec![],
+            blinding: None,
+            updates,
+            transcript: vec![],
+            tally_history: vec![],
+            voted: BTreeSet::new(),
+            delegations: DelegationRegistry::default(),
+            commitments: BTreeMap::new(),
+            status: ProposalStatus::Draft,
+            proof: None,
+            nullifiers: None,
+            anchors: vec![],
+            certificate: None,
+            finalized_at: None,
+            deposit: None,
+            finalizers: None,
+            approvals: vec![],
+            depends_on: vec![],
+            locks_tokens: false,
+            ballots: None,
+            relay_nonces: BTreeMap::new(),
+            did_nonces: BTreeMap::new(),
+            revote_of: None,
+        }
+    }
+    /// Splits the electorate across the shards the rules ask for, if any, and
+    /// applies the recorded updates to them. Proposals are built unsharded, so
+    /// whoever builds one calls this once its updates are set. Finalized and
+    /// cancelled proposals take no more updates and are left without shards.
+    pub fn seed_shards(&mut self) -> anyhow::Result<()> {
+        let shard_count = match self.rules.shard_count {
+            Some(shard_count) if !self.status.is_terminal() => shard_count,
+            _ => return Ok(()),
+        };
+        let voter_balances = self.storage.initial_balances().to_vec();
+        let shard_size = voter_balances.len().div_ceil(shard_count);
+        let mut shards = ShardedBalanceStorage::new(
+            min_tree_height(shard_size),
+            voter_balances,
+            shard_count,
+            self.storage.balance_bits(),
+        )?;
+        // The proof names the shard count of the rules, see `Self::proven_rules`
+        ensure!(
+            shards.shard_count() == shard_count,
+            "{} voters fill only {} of {} shards",
+            self.storage.initial_balances().len(),
+            shards.shard_count(),
+            shard_count
+        );
+        shards.replay(&self.updates)?;
+        self.shards = Some(shards);
+        Ok(())
+    }
+    /// Roots of the electorate as seeded and as it stands, which its finalization
+    /// proof starts from and ends at: the roots over those of its shards on
+    /// sharded proposals, see [`crate::balance::shards::compute_sharded_root`].
+    /// Finalized proposals keep the roots their certificate lists.
+    pub fn proven_roots(
+        &self,
+    ) -> anyhow::Result<(WHashOut<GoldilocksField>, WHashOut<GoldilocksField>)> {
+        if let Some(certificate) = &self.certificate {
+            return Ok((certificate.initial_root, certificate.final_root));
+        }
+        match &self.shards {
+            Some(shards) => Ok((shards.initial_root(), shards.root()?)),
+            None => Ok((self.storage.initial_root(), self.storage.tree.get_root()?)),
+        }
+    }
+    pub fn deadline(&self) -> Option<u64> {
+        self.rules
+            .voting_period_secs
+            .map(|period| self.created_at.saturating_add(period))
+    }
+    /// End of the commitment period, after which votes are accepted.
+    pub fn commit_deadline(&self) -> Option<u64> {
+        self.rules
+            .commit_period_secs
+            .map(|period| self.created_at.saturating_add(period))
+    }
+    /// The rules its finalization proof exposes, which its certificate lists so
+    /// that verifiers can check the proof against them.
+    pub fn proven_rules(&self) -> ProvenRules {
+        let conviction = self
+            .rules
+            .conviction_stamp(self.created_at, self.created_at);
+        ProvenRules {
+            conviction: conviction.map(|stamp| ConvictionSchedule {
+                deadline: stamp.deadline,
+                step_secs: stamp.step_secs,
+            }),
+            vesting_epoch: self.rules.vesting.as_ref().map(|vesting| vesting.epoch),
+            voting_window: self.deadline().map(|deadline| VotingWindow {
+                opens_at: self.created_at,
+                deadline,
+            }),
+            min_transfer: self.rules.min_transfer.map(Weight::get),
+            voting_policy: self.rules.voting_policy.into(),
+            shard_count: self.rules.shard_count.map(|count| count as u64),
+        }
+    }
+    pub fn is_finalized(&self) -> bool {
+        self.status == ProposalStatus::Finalized
+    }
+    /// Moves the proposal to `next`, failing if the lifecycle does not allow it.
+    /// Use [`store::ProposalStore::set_status`] for proposals held by a store.
+    pub fn transition(&mut self, next: ProposalStatus) -> anyhow::Result<()> {
+        ensure!(
+            self.status.can_transition_to(next),
+            "a {:?} proposal cannot become {:?}",
+            self.status,
+            next
+        );
+        self.status = next;
+        Ok(())
+    }
+    /// Rolls back whatever a panicking handler left half-done, restoring the
+    /// state after the last recorded update. A proposal stuck in
+    /// [`ProposalStatus::Finalizing`] goes back to accepting votes.
+    pub fn recover(&mut self) -> anyhow::Result<()> {
+        // Compacted trees belong to finalized proposals, which have no updates to roll back
+        if self.storage.is_compacted() {
+            return Ok(());
+        }
+        if self.status == ProposalStatus::Finalizing {
+            let status = if self.updates.is_empty() {
+                ProposalStatus::Draft
+            } else {
+                ProposalStatus::Open
+            };
+            self.transition(status)?;
+        }
+        self.storage.restore(&self.updates)?;
+        if let Some(nullifiers) = &mut self.nullifiers {
+            let voted = self.voted.iter().map(|voter| voter.index()).collect();
+            nullifiers.restore(&voted)?;
+        }
+        Ok(())
+    }
+    /// Compacts the balance tree of a finalized proposal to its root and tallies, see
+    /// [`BalanceStorage::compact`], and its nullifier tree to its root. Returns the
+    /// number of nodes dropped.
+    pub fn compact_trees(&mut self) -> anyhow::Result<usize> {
+        ensure!(self.is_finalized(), "only finalized proposals are compacted");
+        let mut dropped = self.storage.compact()?;
+        if let Some(nullifiers) = &mut self.nullifiers {
+            dropped += nullifiers.compact()?;
+        }
+        // The certificate lists the roots of the shards, which take no more updates
+        self.shards = None;
+        Ok(dropped)
+    }
+    /// Fails unless votes and delegations can be cast on the proposal.
+    pub fn ensure_accepts_updates(&self) -> Result<(), ApiError> {
+        match self.status {
+            ProposalStatus::Cancelled => Err(ApiError::new(
+                ApiErrorCode::ProposalCancelled,
+                "Proposal is cancelled",
+            )),
+            ProposalStatus::Finalizing => Err(ApiError::new(
+                ApiErrorCode::ProposalFinalizing,
+                "Proposal is being finalized",
+            )),
+            status if !status.accepts_updates() => Err(ApiError::new(
+                ApiErrorCode::ProposalFinalized,
+                "Proposal is finalized",
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Records the commitment of `voter_id` to a vote at time `now`, replacing an
+    /// earlier one. Like [`Self::cast_vote`], the caller opens a draft.
+    pub fn commit_vote(
+        &mut self,
+        voter_id: u32,
+        commitment: [u8; 32],
+        now: u64,
+    ) -> Result<(), ApiError> {
+        self.ensure_accepts_updates()?;
+        if self.rules.commit_period_secs.is_none() {
+            return Err(ApiError::new(
+                ApiErrorCode::NotCommitReveal,
+                "Proposal takes votes without commitments",
+            ));
+        }
+        if self.phase(now) != ProposalPhase::Committing {
+            return Err(ApiError::new(
+                ApiErrorCode::CommitmentsClosed,
+                "Commitment period has ended",
+            ));
+        }
+        let voter = self.electorate_voter(voter_id)?;
+        self.commitments.insert(voter, commitment);
+        self.transcript.push(TranscriptEvent {
+            at_secs: now.saturating_sub(self.created_at),
+            action: TranscriptAction::Commit {
+                voter_id,
+                commitment,
+            },
+        });
+        Ok(())
+    }
+    /// Casts the full balance of `voter_id` on yes or no at time `now`. On proposals
+    /// with a commitment period, `salt` has to open the commitment of the voter.
+    ///
+    /// A draft stays a draft; the caller opens it, through the store when it holds the proposal.
+    pub fn cast_vote(
+        &mut self,
+        voter_id: u32,
+        is_yes: bool,
+        salt: Option<&[u8]>,
+        now: u64,
+    ) -> Result<(), ApiError> {
+        let voter = self.ballot_voter(voter_id, now)?;
+        self.ensure_clear_votes("Votes cannot be cast in the clear")?;
+        if self.rules.commit_period_secs.is_some() {
+            let commitment = self.commitments.get(&voter).ok_or_else(|| {
+                ApiError::new(
+                    ApiErrorCode::CommitmentMissing,
+                    "Voter did not commit to a vote",
+                )
+            })?;
+            if *commitment != compute_vote_commitment(voter_id, is_yes, salt.unwrap_or_default()) {
+                return Err(ApiError::new(
+                    ApiErrorCode::CommitmentMismatch,
+                    "Vote does not match the commitment of the voter",
+                ));
+            }
+        }
+        self.ensure_not_voted(voter)?;
+        let voter_balance = self.voting_weight(voter)?;
+        let tx = BalanceTx::Vote {
+            voter,
+            slot: TallySlot::for_vote(is_yes),
+            amount: voter_balance.into(),
+        };
+        self.ensure_shardable(tx)?;
+        let update = self
+            .storage
+            .process_stamped_tx(tx, self.conviction_stamp(now), self.window_stamp(now))
+            .map_err(QedError::from_storage)?;
+        self.mirror_tx(tx);
+        self.mark_voted(voter);
+        self.record(
+            vec![update],
+            now,
+            TranscriptAction::Vote {
+                voter_id,
+                is_yes,
+                salt: salt.map(|salt| salt.to_vec()),
+            },
+        );
+        Ok(())
+    }
+    /// Casts the full balance of `voter_id` across yes and no as `split` says, at
+    /// time `now`, as one vote per option that receives weight. The split has to
+    /// add up to the balance of the voter, and cannot be committed to, so it is
+    /// rejected on proposals with a commitment period.
+    ///
+    /// Like [`Self::cast_vote`], a draft stays a draft.
+    pub fn cast_split_vote(
+        &mut self,
+        voter_id: u32,
+        split: VoteSplit,
+        now: u64,
+    ) -> Result<(), ApiError> {
+        let voter = self.ballot_voter(voter_id, now)?;
+        self.ensure_clear_votes("Votes cannot be split")?;
+        self.ensure_unsharded("Votes cannot be split")?;
+        if self.rules.commit_period_secs.is_some() {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidSplit,
+                "Votes committed to cannot be split",
+            ));
+        }
+        self.ensure_not_voted(voter)?;
+        let voter_balance = self.voting_weight(voter)?;
+        if split.total() != Some(voter_balance) {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidSplit,
+                format!(
+                    "Split of {} yes and {} no votes does not add up to the voting weight {}",
+                    split.yes_votes, split.no_votes, voter_balance
+                ),
+            ));
+        }
+        let updates = self
+            .storage
+            .process_split_vote(
+                voter,
+                split,
+                self.conviction_stamp(now),
+                self.window_stamp(now),
+            )
+            .map_err(QedError::from_storage)?;
+        self.mark_voted(voter);
+        self.record(
+            updates,
+            now,
+            TranscriptAction::SplitVote {
+                voter_id,
+                yes_votes: split.yes_votes,
+                no_votes: split.no_votes,
+            },
+        );
+        Ok(())
+    }
+    /// Moves the weight `voter_id` cast back to their leaf at time `now`, so they
+    /// can vote again before the voting period ends. Votes committed to are bound
+    /// to the commitment, votes weighted by conviction stay cast until the
+    /// deadline, and quadratic votes add less to the tally than they spent, so
+    /// none of them can be revoked.
+    pub fn revoke_vote(&mut self, voter_id: u32, now: u64) -> Result<(), ApiError> {
+        let voter = self.ballot_voter(voter_id, now)?;
+        self.ensure_clear_votes("Votes cannot be revoked")?;
+        self.ensure_unsharded("Votes cannot be revoked")?;
+        if self.rules.commit_period_secs.is_some() {
+            return Err(ApiError::new(
+                ApiErrorCode::NotRevocable,
+                "Votes committed to cannot be revoked",
+            ));
+        }
+        if self.rules.conviction.is_some() {
+            return Err(ApiError::new(
+                ApiErrorCode::NotRevocable,
+                "Votes weighted by conviction cannot be revoked",
+            ));
+        }
+        if !self.rules.voting_policy.is_linear() {
+            return Err(ApiError::new(
+                ApiErrorCode::NotRevocable,
+                "Quadratic votes cannot be revoked",
+            ));
+        }
+        let cast = self.cast_weight(voter);
+        if cast.total() == Some(Weight::ZERO) {
+            return Err(ApiError::new(
+                ApiErrorCode::NotRevocable,
+                "Voter has no vote to revoke",
+            ));
+        }
+        let updates = self
+            .storage
+            .process_revocation(voter, cast, self.window_stamp(now))
+            .map_err(|err| ApiError::new(ApiErrorCode::NotRevocable, err))?;
+        if let Some(nullifiers) = &mut self.nullifiers {
+            nullifiers.remove(voter.index()).unwrap();
+        }
+        self.voted.remove(&voter);
+        self.record(updates, now, TranscriptAction::Revoke { voter_id });
+        Ok(())
+    }
+    /// The weight `voter` has cast on each option since they last revoked their vote.
+    fn cast_weight(&self, voter: VoterLeaf) -> VoteSplit {
+        let mut cast = VoteSplit {
+            yes_votes: Weight::ZERO,
+            no_votes: Weight::ZERO,
+        };
+        for update in &self.updates {
+            let sender = update.sender_update.index.to_canonical_u64();
+            let receiver = update.receiver_update.index.to_canonical_u64();
+            if update.kind == UpdateKind::Revocation && receiver == voter.index() {
+                cast.yes_votes = Weight::ZERO;
+                cast.no_votes = Weight::ZERO;
+            } else if matches!(update.kind, UpdateKind::Vote | UpdateKind::SplitVote)
+                && sender == voter.index()
+            {
+                let amount = update.check_weights(self.storage.balance_bits()).unwrap();
+                let tally = if receiver == TallySlot::YES.index() {
+                    &mut cast.yes_votes
+                } else {
+                    &mut cast.no_votes
+                };
+                *tally = tally.checked_add(amount).unwrap();
+            }
+        }
+        cast
+    }
+    /// The leaf of `voter_id`, if the proposal takes votes at time `now`.
+    fn ballot_voter(&self, voter_id: u32, now: u64) -> Result<VoterLeaf, ApiError> {
+        self.ensure_accepts_updates()?;
+        match self.phase(now) {
+            ProposalPhase::Voting => {}
+            ProposalPhase::Committing => {
+                return Err(ApiError::new(
+                    ApiErrorCode::VotingNotOpen,
+                    "Votes are accepted once the commitment period ends",
+                ))
+            }
+            _ => {
+                return Err(ApiError::new(
+                    ApiErrorCode::VotingClosed,
+                    "Voting period has ended",
+                ))
+            }
+        }
+        self.electorate_voter(voter_id)
+    }
+    fn ensure_not_voted(&self, voter: VoterLeaf) -> Result<(), ApiError> {
+        if let Some(nullifiers) = &self.nullifiers {
+            if nullifiers.contains(voter.index()).unwrap() {
+                return Err(ApiError::new(
+                    ApiErrorCode::AlreadyVoted,
+                    "Voter has already voted",
+                ));
+            }
+        }
+        Ok(())
+    }
+    fn mark_voted(&mut self, voter: VoterLeaf) {
+        if let Some(nullifiers) = &mut self.nullifiers {
+            nullifiers.insert(voter.index()).unwrap();
+        }
+        self.voted.insert(voter);
+    }
+    /// Moves the full balance of `voter_id` to `delegator_id` at time `now`, or to
+    /// whoever `delegator_id` delegated to in turn, see [`DelegationRegistry::resolve`].
+    pub fn delegate(&mut self, voter_id: u32, delegator_id: u32, now: u64) -> Result<(), ApiError> {
+        let update = self.apply_delegation(voter_id, delegator_id, now)?;
+        self.record(
+            vec![update],
+            now,
+            TranscriptAction::Delegate {
+                voter_id,
+                delegator_id,
+            },
+        );
+        Ok(())
+    }
+    /// Applies the delegation of `voter_id` to `delegator_id` at time `now` to the
+    /// balance tree and the registry, leaving its update to be recorded.
+    fn apply_delegation(
+        &mut self,
+        voter_id: u32,
+        delegator_id: u32,
+        now: u64,
+    ) -> Result<BalanceUpdate<GoldilocksField>, ApiError> {
+        self.ensure_accepts_updates()?;
+        // Ballots count the weight voters held when casting them
+        self.ensure_clear_votes("Weight cannot be delegated")?;
+        // Updates are proven to be recorded before the deadline, see `Self::window_stamp`
+        if self.deadline().map_or(false, |deadline| now >= deadline) {
+            return Err(ApiError::new(
+                ApiErrorCode::VotingClosed,
+                "Voting period has ended",
+            ));
+        }
+        let voter = self.electorate_voter(voter_id)?;
+        self.electorate_voter(delegator_id)?;
+        let delegate = self.electorate_voter(self.delegations.check(voter_id, delegator_id)?)?;
+        if self.storage.has_delegated(voter).unwrap() {
+            return Err(ApiError::new(
+                ApiErrorCode::AlreadyDelegated,
+                "Voter has already delegated",
+            ));
+        }
+        let voter_balance = self.voting_weight(voter)?;
+        let tx = BalanceTx::Delegate {
+            voter,
+            delegate,
+            amount: voter_balance.into(),
+        };
+        self.ensure_shardable(tx)?;
+        let update = self
+            .storage
+            .process_stamped_tx(tx, self.conviction_stamp(now), self.window_stamp(now))
+            .map_err(QedError::from_storage)?;
+        self.mirror_tx(tx);
+        self.delegations.insert(voter_id, delegator_id);
+        Ok(update)
+    }
+    /// Moves the weight `voter_id` delegated back to them at time `now`, from the
+    /// voter at the end of their chain of delegations, who holds it. Fails once
+    /// that voter voted, if others delegated to `voter_id`, and on proposals
+    /// weighing votes by conviction or quadratically, whose circuits take no
+    /// undelegations.
+    pub fn undelegate(&mut self, voter_id: u32, now: u64) -> Result<(), ApiError> {
+        self.ensure_accepts_updates()?;
+        self.ensure_clear_votes("Weight cannot be undelegated")?;
+        self.ensure_unsharded("Weight cannot be undelegated")?;
+        if self.deadline().map_or(false, |deadline| now >= deadline) {
+            return Err(ApiError::new(
+                ApiErrorCode::VotingClosed,
+                "Voting period has ended",
+            ));
+        }
+        if self.rules.conviction.is_some() || !self.rules.voting_policy.is_linear() {
+            return Err(ApiError::new(
+                ApiErrorCode::NotUndelegable,
+                "Delegations on proposals weighing votes cannot be reverted",
+            ));
+        }
+        let voter = self.electorate_voter(voter_id)?;
+        let delegate_id = self.delegations.delegate_of(voter_id).ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::NotDelegated,
+                format!("Voter {} has not delegated", voter_id),
+            )
+        })?;
+        // The weight of voters who delegated to `voter_id` went to the holder with theirs
+        let delegated_to = self
+            .delegations
+            .voters()
+            .into_iter()
+            .find(|id| self.delegations.delegate_of(*id) == Some(voter_id));
+        if let Some(delegator_id) = delegated_to {
+            return Err(ApiError::new(
+                ApiErrorCode::NotUndelegable,
+                format!("Voter {} delegated to voter {}", delegator_id, voter_id),
+            ));
+        }
+        let holder_id = self.delegations.resolve(delegate_id);
+        let holder = self.electorate_voter(holder_id)?;
+        if self.voted.contains(&holder) {
+            return Err(ApiError::new(
+                ApiErrorCode::NotUndelegable,
+                format!("Voter {} cast the delegated weight already", holder_id),
+            ));
+        }
+        let update = self
+            .storage
+            .process_stamped_tx(
+                BalanceTx::Undelegate {
+                    voter,
+                    delegate: holder,
+                    amount: self.delegated_weight(voter),
+                },
+                None,
+                self.window_stamp(now),
+            )
+            .map_err(QedError::from_storage)?;
+        self.delegations.remove(voter_id);
+        self.record(vec![update], now, TranscriptAction::Undelegate { voter_id });
+        Ok(())
+    }
+    /// Fails on sharded proposals, whose shards take plain votes and delegations only.
+    fn ensure_unsharded(&self, what: &str) -> Result<(), ApiError> {
+        if self.rules.shard_count.is_some() {
+            return Err(ApiError::new(
+                ApiErrorCode::Sharded,
+                format!("{} on a proposal whose electorate is sharded", what),
+            ));
+        }
+        Ok(())
+    }
+    /// Fails unless the shards of a sharded proposal take `tx`, which is checked
+    /// before `storage` takes it, see [`Self::mirror_tx`].
+    fn ensure_shardable(&self, tx: BalanceTx) -> Result<(), ApiError> {
+        if let Some(shards) = &self.shards {
+            shards
+                .shard_tx(tx)
+                .map_err(|err| ApiError::new(ApiErrorCode::Sharded, err))?;
+        }
+        Ok(())
+    }
+    /// Applies `tx`, which `storage` took, to the shards of a sharded proposal.
+    fn mirror_tx(&mut self, tx: BalanceTx) {
+        if let Some(shards) = &mut self.shards {
+            // The shards hold the balances of the tree, so they take what it took
+            shards.process_tx(tx).unwrap();
+        }
+    }
+    /// The weight `voter` moved by their last delegation.
+    fn delegated_weight(&self, voter: VoterLeaf) -> WeightDelta {
+        self.updates
+            .iter()
+            .rev()
+            .find(|update| {
+                update.kind == UpdateKind::Delegation
+                    && update.sender_update.index.to_canonical_u64() == voter.index()
+            })
+            .map_or(WeightDelta::ZERO, |update| {
+                update.check_weights(self.storage.balance_bits()).unwrap()
+            })
+    }
+    /// The conviction stamp of an update made at `now`, if votes are weighted by
+    /// conviction, recorded at the same time as its window stamp.
+    fn conviction_stamp(&self, now: u64) -> Option<ConvictionStamp> {
+        self.rules
+            .conviction_stamp(self.created_at, self.recorded_at(now))
+    }
+    /// The time an update made at `now` is stamped with: never earlier than the
+    /// creation of the proposal nor than the last update, so stamps stay in order
+    /// even if the clock goes back, as the tally slots they are written to require.
+    fn recorded_at(&self, now: u64) -> u64 {
+        let last = self.updates.last().and_then(|update| update.voted_at());
+        now.max(self.created_at).max(last.unwrap_or_default())
+    }
+    /// The window stamp of an update made at `now`, if the proposal has a
+    /// deadline, which the update balance circuit checks the update against.
+    fn window_stamp(&self, now: u64) -> Option<WindowStamp> {
+        self.deadline().map(|deadline| WindowStamp {
+            voted_at: self.recorded_at(now),
+            opens_at: self.created_at,
+            deadline,
+        })
+    }
+    /// The updates a finalization proves, padded as [`pad_updates`] does. A
+    /// proposal nobody voted on has none, so it proves a single no-op on the
+    /// initial root instead, stamped as if recorded at creation: its proof then
+    /// attests that the tree is as seeded and both tallies are zero.
+    pub fn finalization_updates(&self) -> Vec<BalanceUpdate<GoldilocksField>> {
+        let tree_height = self.storage.tree_height();
+        if !self.updates.is_empty() {
+            return pad_updates(&self.updates, tree_height);
+        }
+        let mut noop = BalanceUpdate::noop(self.storage.initial_root(), tree_height);
+        noop.conviction = self.conviction_stamp(self.created_at);
+        noop.policy = self.rules.voting_policy;
+        noop.vesting_epoch = self.storage.vesting().map(|vesting| vesting.epoch);
+        noop.window = self.window_stamp(self.created_at);
+        vec![noop]
+    }
+    /// Records `updates` made at time `now`, stamped as [`Self::window_stamp`] says.
+    fn record(
+        &mut self,
+        updates: Vec<BalanceUpdate<GoldilocksField>>,
+        now: u64,
+        action: TranscriptAction,
+    ) {
+        self.updates.extend(updates);
+        self.transcript.push(TranscriptEvent {
+            at_secs: now.saturating_sub(self.created_at),
+            action,
+        });
+        let tally = match self.storage.tally() {
+            Ok(tally) => tally,
+            Err(_) => return,
+        };
+        let voters = self.voted.len() as u64;
+        let changed = self
+            .tally_history
+            .last()
+            .map_or(true, |last| last.tally != tally || last.voters != voters);
+        if changed {
+            self.tally_history.push(TallyPoint {
+                at: now,
+                tally,
+                voters,
+            });
+        }
+    }
+    pub fn phase(&self, now: u64) -> ProposalPhase {
+        match self.status {
+            ProposalStatus::Finalized => ProposalPhase::Finalized,
+            ProposalStatus::Cancelled => ProposalPhase::Cancelled,
+            ProposalStatus::Finalizing => ProposalPhase::AwaitingFinalization,
+            ProposalStatus::Draft | ProposalStatus::Open => {
+                if self.deadline().map_or(false, |deadline| now >= deadline) {
+                    ProposalPhase::AwaitingFinalization
+                } else if self
+                    .commit_deadline()
+                    .map_or(false, |deadline| now < deadline)
+                {
+                    ProposalPhase::Committing
+                } else {
+                    ProposalPhase::Voting
+                }
+            }
+        }
+    }
+}
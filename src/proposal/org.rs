@@ -0,0 +1,576 @@
+//! Organizations hosted side by side on one server.
+//!
+//! Each organization owns the DAO of the same id: its proposals are created
+//! through its own routes, accounted to its quotas and listed apart from those
+//! of other organizations. Its admins authenticate with a bearer token of
+//! their own, of which only the SHA-256 is kept.
+//!
+//! Voters join an organization by registering their DID, signed with its key,
+//! and wait for an admin to approve them. Approved voters are assigned the next
+//! free voter leaf, which they keep: the electorate of every proposal of the
+//! organization is the registry as it stands at creation, so the voter at leaf
+//! `i` of one proposal is the voter at leaf `i` of any other.
+//!
+//! Admins can also set a [`CategoryPolicy`] per category, e.g. `treasury`,
+//! `protocol` or `social`, whose quorum, voting period and finalizers the
+//! proposals filed under the category get unless they set their own.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{
+    api::ProposeQuery,
+    balance::{allocation::LeafAllocator, storage::MAX_TREE_HEIGHT, weight::Weight},
+    did::Did,
+    errors::{ApiError, ApiErrorCode},
+    webhook::{validate_targets, WebhookTarget},
+};
+
+use super::{
+    approval::FinalizerPolicy,
+    validation::{validate_category, validate_voter_dids},
+    DEFAULT_DAO_ID, MAX_ELECTORATE_SIZE,
+};
+
+/// Longest organization id, in bytes.
+pub const MAX_ORG_ID_BYTES: usize = 64;
+
+/// Most categories an organization sets a policy for.
+pub const MAX_CATEGORY_POLICIES: usize = 64;
+
+/// An organization as archived in snapshots, admin token digest included.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    /// Hex encoded SHA-256 of the admin token.
+    pub admin_token_sha256: String,
+    /// Registrations in the order they were requested, decided ones included.
+    #[serde(default)]
+    pub registrations: Vec<VoterRegistration>,
+    pub created_at: u64,
+    /// Endpoints the results of the proposals of the organization are posted to,
+    /// secrets included, see [`crate::webhook`].
+    #[serde(default)]
+    pub webhooks: Vec<WebhookTarget>,
+    /// Defaults of the proposals filed under each category, by category.
+    #[serde(default)]
+    pub category_policies: BTreeMap<String, CategoryPolicy>,
+}
+
+/// Defaults the admins of an organization set for the proposals it files under
+/// a category. Each fills in what a proposal leaves unset at creation.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CategoryPolicy {
+    pub quorum: Option<Weight>,
+    /// Seconds after creation during which votes are accepted.
+    pub voting_period_secs: Option<u64>,
+    /// Finalizers a threshold of whom has to approve finalizing the proposal.
+    pub finalizers: Option<FinalizerPolicy>,
+}
+
+impl CategoryPolicy {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.voting_period_secs == Some(0) {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                "Voting periods cannot be empty",
+            ));
+        }
+        self.finalizers
+            .as_ref()
+            .map_or(Ok(()), FinalizerPolicy::validate)
+    }
+    /// Fills in the quorum, voting period and finalizers `query` leaves unset.
+    pub fn apply(&self, query: &mut ProposeQuery) {
+        query.quorum = query.quorum.or(self.quorum);
+        query.voting_period_secs = query.voting_period_secs.or(self.voting_period_secs);
+        if query.finalizers.is_none() {
+            query.finalizers = self.finalizers.clone();
+        }
+    }
+}
+
+/// An organization as served to anyone, without its admin token digest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct OrganizationView {
+    pub id: String,
+    pub name: String,
+    /// Approved voters by voter id, the electorate of new proposals of the organization.
+    #[schema(value_type = Vec<String>)]
+    pub roster: Vec<Did>,
+    /// Registrations awaiting a decision of the admins.
+    pub pending: usize,
+    pub created_at: u64,
+    pub category_policies: BTreeMap<String, CategoryPolicy>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// A request of a voter to join an organization, and what the admins decided.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VoterRegistration {
+    #[schema(value_type = String)]
+    pub did: Did,
+    pub status: RegistrationStatus,
+    /// Voter id, i.e. leaf index, of an approved voter on the proposals of the organization.
+    pub voter_id: Option<u32>,
+    pub requested_at: u64,
+    pub decided_at: Option<u64>,
+}
+
+/// The message a voter signs with the key of `did` to register in `org_id`.
+pub fn registration_message(org_id: &str, did: &Did) -> Vec<u8> {
+    format!("qed-dapp:register:{}:{}", org_id, did).into_bytes()
+}
+
+fn token_digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token))
+}
+
+/// Fails unless `id` is a non-empty run of lowercase letters, digits and dashes,
+/// at most [`MAX_ORG_ID_BYTES`] long, other than the default DAO.
+pub fn validate_org_id(id: &str) -> Result<(), ApiError> {
+    let valid = !id.is_empty()
+        && id.len() <= MAX_ORG_ID_BYTES
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidQuery,
+            format!(
+                "Organization ids are 1 to {} lowercase letters, digits or dashes",
+                MAX_ORG_ID_BYTES
+            ),
+        ));
+    }
+    if id == DEFAULT_DAO_ID {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidQuery,
+            format!("{} is the DAO of proposals outside organizations", id),
+        ));
+    }
+    Ok(())
+}
+
+impl Organization {
+    pub fn new(id: String, name: String, admin_token: &str, created_at: u64) -> Self {
+        Self {
+            id,
+            name,
+            admin_token_sha256: token_digest(admin_token),
+            registrations: vec![],
+            created_at,
+            webhooks: vec![],
+            category_policies: BTreeMap::new(),
+        }
+    }
+    /// Whether `token` is the admin token. Compares digests, so the time taken
+    /// does not tell how much of the token matched.
+    pub fn is_admin_token(&self, token: &str) -> bool {
+        token_digest(token) == self.admin_token_sha256
+    }
+    /// The approved voters, the voter at position `i` holding the leaf
+    /// [`VoterLeaf::from_position`](crate::balance::accounts::VoterLeaf::from_position)`(i)`.
+    /// Approvals are never taken back, so approval order is voter id order.
+    pub fn roster(&self) -> Vec<Did> {
+        self.registrations
+            .iter()
+            .filter(|registration| registration.status == RegistrationStatus::Approved)
+            .map(|registration| registration.did.clone())
+            .collect()
+    }
+    pub fn view(&self) -> OrganizationView {
+        OrganizationView {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            roster: self.roster(),
+            pending: self
+                .registrations
+                .iter()
+                .filter(|registration| registration.status == RegistrationStatus::Pending)
+                .count(),
+            created_at: self.created_at,
+            category_policies: self.category_policies.clone(),
+        }
+    }
+}
+
+/// The organizations of a server, by id.
+#[derive(Default)]
+pub struct OrganizationRegistry {
+    orgs: BTreeMap<String, Organization>,
+}
+
+impl OrganizationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Rebuilds the registry a snapshot was taken of.
+    pub fn restore(orgs: Vec<Organization>) -> Result<Self, ApiError> {
+        let mut registry = Self::new();
+        for org in orgs {
+            registry.create(org)?;
+        }
+        Ok(registry)
+    }
+    pub fn create(&mut self, org: Organization) -> Result<(), ApiError> {
+        validate_org_id(&org.id)?;
+        if self.orgs.contains_key(&org.id) {
+            return Err(ApiError::new(
+                ApiErrorCode::OrganizationExists,
+                format!("Organization {} already exists", org.id),
+            ));
+        }
+        self.orgs.insert(org.id.clone(), org);
+        Ok(())
+    }
+    pub fn get(&self, id: &str) -> Result<&Organization, ApiError> {
+        self.orgs.get(id).ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::OrganizationNotFound,
+                format!("Organization {} not found", id),
+            )
+        })
+    }
+    /// Whether the DAO `dao_id` belongs to an organization, so that proposals
+    /// can only be created in it through the routes of the organization.
+    pub fn owns_dao(&self, dao_id: &str) -> bool {
+        self.orgs.contains_key(dao_id)
+    }
+    /// Records a pending registration of `did`, failing unless `signature`, hex
+    /// encoded, signs the [`registration_message`] with the key of the DID, or if
+    /// the DID already registered, whatever the admins decided.
+    pub fn register(
+        &mut self,
+        id: &str,
+        did: Did,
+        signature: &str,
+        now: u64,
+    ) -> Result<&VoterRegistration, ApiError> {
+        let org = self.get(id)?;
+        hex::decode(signature.trim_start_matches("0x"))
+            .map_err(anyhow::Error::from)
+            .and_then(|signature| did.verify(&registration_message(id, &did), &signature))
+            .map_err(|err| {
+                ApiError::new(
+                    ApiErrorCode::InvalidDidSignature,
+                    format!("Invalid signature of {}: {}", did, err),
+                )
+            })?;
+        if org.registrations.len() >= MAX_ELECTORATE_SIZE {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                format!("Organization {} takes no more registrations", id),
+            ));
+        }
+        let mut dids: Vec<Did> = org
+            .registrations
+            .iter()
+            .map(|registration| registration.did.clone())
+            .collect();
+        dids.push(did.clone());
+        validate_voter_dids(&dids)?;
+        let org = self.orgs.get_mut(id).unwrap();
+        org.registrations.push(VoterRegistration {
+            did,
+            status: RegistrationStatus::Pending,
+            voter_id: None,
+            requested_at: now,
+            decided_at: None,
+        });
+        Ok(org.registrations.last().unwrap())
+    }
+    /// Approves or rejects the pending registration of `did`, authenticated by
+    /// the admin token of the organization. An approved voter is assigned the
+    /// leaf after that of the last approved voter.
+    pub fn decide(
+        &mut self,
+        id: &str,
+        admin_token: Option<&str>,
+        did: &Did,
+        approve: bool,
+        now: u64,
+    ) -> Result<&VoterRegistration, ApiError> {
+        self.authenticate(id, admin_token)?;
+        let org = self.orgs.get_mut(id).unwrap();
+        let approved = org
+            .registrations
+            .iter()
+            .filter(|registration| registration.status == RegistrationStatus::Approved)
+            .count();
+        let registration = org
+            .registrations
+            .iter_mut()
+            .find(|registration| registration.did == *did)
+            .ok_or_else(|| {
+                ApiError::new(
+                    ApiErrorCode::RegistrationNotFound,
+                    format!("{} has not registered in organization {}", did, id),
+                )
+            })?;
+        if registration.status != RegistrationStatus::Pending {
+            return Err(ApiError::new(
+                ApiErrorCode::RegistrationDecided,
+                format!("The registration of {} has already been decided", did),
+            ));
+        }
+        if approve {
+            let voter = LeafAllocator::new(MAX_TREE_HEIGHT, approved as u64).allocate()?;
+            registration.status = RegistrationStatus::Approved;
+            // Allocated leaves never exceed the largest voter id
+            registration.voter_id = Some(voter.index() as u32);
+        } else {
+            registration.status = RegistrationStatus::Rejected;
+        }
+        registration.decided_at = Some(now);
+        Ok(registration)
+    }
+    /// Registrations of the organization in request order, of any status unless
+    /// one is given.
+    pub fn registrations(
+        &self,
+        id: &str,
+        status: Option<RegistrationStatus>,
+    ) -> Result<Vec<VoterRegistration>, ApiError> {
+        Ok(self
+            .get(id)?
+            .registrations
+            .iter()
+            .filter(|registration| status.map_or(true, |status| registration.status == status))
+            .cloned()
+            .collect())
+    }
+    /// Fails unless `admin_token` is the admin token of the organization.
+    pub fn authenticate(&self, id: &str, admin_token: Option<&str>) -> Result<(), ApiError> {
+        let org = self.get(id)?;
+        match admin_token {
+            Some(token) if org.is_admin_token(token) => Ok(()),
+            _ => Err(ApiError::new(
+                ApiErrorCode::OrganizationUnauthorized,
+                format!("Missing or invalid admin token of organization {}", id),
+            )),
+        }
+    }
+    /// Replaces the webhooks of the organization, authenticated by its admin token.
+    pub fn set_webhooks(
+        &mut self,
+        id: &str,
+        admin_token: Option<&str>,
+        webhooks: Vec<WebhookTarget>,
+    ) -> Result<(), ApiError> {
+        self.authenticate(id, admin_token)?;
+        validate_targets(&webhooks)?;
+        self.orgs.get_mut(id).unwrap().webhooks = webhooks;
+        Ok(())
+    }
+    /// Sets the policy of the proposals of the organization filed under
+    /// `category`, or clears it, authenticated by its admin token.
+    pub fn set_category_policy(
+        &mut self,
+        id: &str,
+        admin_token: Option<&str>,
+        category: &str,
+        policy: Option<CategoryPolicy>,
+    ) -> Result<(), ApiError> {
+        self.authenticate(id, admin_token)?;
+        validate_category(category)?;
+        let policies = &mut self.orgs.get_mut(id).unwrap().category_policies;
+        let policy = match policy {
+            Some(policy) => policy,
+            None => {
+                policies.remove(category);
+                return Ok(());
+            }
+        };
+        policy.validate()?;
+        if !policies.contains_key(category) && policies.len() >= MAX_CATEGORY_POLICIES {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                format!(
+                    "Organizations set policies for at most {} categories",
+                    MAX_CATEGORY_POLICIES
+                ),
+            ));
+        }
+        policies.insert(category.to_string(), policy);
+        Ok(())
+    }
+    /// The webhooks of the organization owning the DAO `dao_id`, none if no
+    /// organization does.
+    pub fn webhooks(&self, dao_id: &str) -> &[WebhookTarget] {
+        self.orgs
+            .get(dao_id)
+            .map_or(&[], |org| org.webhooks.as_slice())
+    }
+    pub fn is_empty(&self) -> bool {
+        self.orgs.is_empty()
+    }
+    /// The organizations in id order.
+    pub fn snapshot(&self) -> Vec<Organization> {
+        self.orgs.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use crate::{
+        api::ProposeQuery,
+        balance::weight::Weight,
+        did::Did,
+        errors::{ApiError, ApiErrorCode},
+        webhook::WebhookTarget,
+    };
+
+    use super::{
+        registration_message, CategoryPolicy, Organization, OrganizationRegistry,
+        RegistrationStatus,
+    };
+
+    fn register(
+        registry: &mut OrganizationRegistry,
+        org_id: &str,
+        seed: u8,
+    ) -> Result<Did, ApiError> {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let did = Did::Key(key.verifying_key());
+        let signature = key.sign(&registration_message(org_id, &did)).to_bytes();
+        registry.register(org_id, did.clone(), &hex::encode(signature), 5)?;
+        Ok(did)
+    }
+
+    #[test]
+    fn test_organizations_are_kept_apart() -> anyhow::Result<()> {
+        let mut registry = OrganizationRegistry::new();
+        registry.create(Organization::new(
+            "acme".into(),
+            "Acme".into(),
+            "acme-token",
+            1,
+        ))?;
+        registry.create(Organization::new(
+            "globex".into(),
+            "Globex".into(),
+            "globex-token",
+            2,
+        ))?;
+        for id in ["acme", "Acme", "default", ""] {
+            assert!(registry
+                .create(Organization::new(id.into(), "Again".into(), "token", 3))
+                .is_err());
+        }
+        assert!(registry.owns_dao("acme"));
+        assert!(!registry.owns_dao("default"));
+
+        let first = register(&mut registry, "acme", 1)?;
+        let second = register(&mut registry, "acme", 2)?;
+        let third = register(&mut registry, "acme", 3)?;
+        assert!(register(&mut registry, "acme", 1).is_err());
+        // A signature for another organization does not register
+        let key = SigningKey::from_bytes(&[4; 32]);
+        let did = Did::Key(key.verifying_key());
+        let signature = key.sign(&registration_message("globex", &did)).to_bytes();
+        let forged = registry
+            .register("acme", did, &hex::encode(signature), 5)
+            .unwrap_err();
+        assert_eq!(forged.code, ApiErrorCode::InvalidDidSignature);
+
+        let rejected = registry
+            .decide("acme", Some("globex-token"), &first, true, 6)
+            .unwrap_err();
+        assert_eq!(rejected.code, ApiErrorCode::OrganizationUnauthorized);
+        // Leaves follow approval order, skipping rejected voters
+        let approved = registry.decide("acme", Some("acme-token"), &second, true, 6)?;
+        assert_eq!(approved.voter_id, Some(2));
+        registry.decide("acme", Some("acme-token"), &first, false, 7)?;
+        let approved = registry.decide("acme", Some("acme-token"), &third, true, 7)?;
+        assert_eq!(approved.voter_id, Some(3));
+        let decided = registry
+            .decide("acme", Some("acme-token"), &first, true, 8)
+            .unwrap_err();
+        assert_eq!(decided.code, ApiErrorCode::RegistrationDecided);
+
+        let acme = registry.get("acme")?;
+        assert_eq!(acme.roster(), vec![second, third]);
+        assert_eq!(acme.view().pending, 0);
+        assert_eq!(
+            registry
+                .registrations("acme", Some(RegistrationStatus::Rejected))?
+                .len(),
+            1
+        );
+        assert!(registry.get("globex")?.roster().is_empty());
+
+        let webhook = WebhookTarget {
+            url: "https://acme.example/qed".to_string(),
+            secret: "s3cret".to_string(),
+        };
+        let unauthorized = registry
+            .set_webhooks("acme", Some("globex-token"), vec![webhook.clone()])
+            .unwrap_err();
+        assert_eq!(unauthorized.code, ApiErrorCode::OrganizationUnauthorized);
+        registry.set_webhooks("acme", Some("acme-token"), vec![webhook.clone()])?;
+        assert_eq!(registry.webhooks("acme"), &[webhook]);
+        assert!(registry.webhooks("globex").is_empty());
+
+        let restored = OrganizationRegistry::restore(registry.snapshot())?;
+        assert_eq!(restored.snapshot(), registry.snapshot());
+        assert!(restored.get("acme")?.is_admin_token("acme-token"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_policies_fill_in_unset_rules() -> anyhow::Result<()> {
+        let mut registry = OrganizationRegistry::new();
+        registry.create(Organization::new(
+            "acme".into(),
+            "Acme".into(),
+            "acme-token",
+            1,
+        ))?;
+        let treasury = CategoryPolicy {
+            quorum: Some(Weight::from(3)),
+            voting_period_secs: Some(600),
+            finalizers: None,
+        };
+        let unauthorized = registry
+            .set_category_policy("acme", None, "treasury", Some(treasury.clone()))
+            .unwrap_err();
+        assert_eq!(unauthorized.code, ApiErrorCode::OrganizationUnauthorized);
+        assert!(registry
+            .set_category_policy(
+                "acme",
+                Some("acme-token"),
+                "Treasury",
+                Some(treasury.clone())
+            )
+            .is_err());
+        registry.set_category_policy("acme", Some("acme-token"), "treasury", Some(treasury))?;
+
+        let mut query = ProposeQuery {
+            voting_period_secs: Some(60),
+            ..ProposeQuery::default()
+        };
+        let policies = &registry.get("acme")?.view().category_policies;
+        policies["treasury"].apply(&mut query);
+        assert_eq!(query.quorum, Some(Weight::from(3)));
+        // What the proposal sets wins over the policy
+        assert_eq!(query.voting_period_secs, Some(60));
+
+        registry.set_category_policy("acme", Some("acme-token"), "treasury", None)?;
+        assert!(registry.get("acme")?.category_policies.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, ApiErrorCode};
+
+use super::{store::ProposalStore, Proposal};
+
+/// The storage a DAO takes up across all of its proposals.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DaoUsage {
+    pub dao_id: String,
+    pub proposals: u64,
+    /// Approximate size of the balance and nullifier tree nodes.
+    pub node_store_bytes: u64,
+    /// Size of the stored finalization proofs.
+    pub proof_bytes: u64,
+    /// Votes and delegations recorded.
+    pub updates: u64,
+}
+
+impl DaoUsage {
+    fn add(&mut self, proposal: &Proposal) {
+        self.proposals += 1;
+        self.node_store_bytes += proposal.storage.tree.store().approximate_bytes();
+        if let Some(nullifiers) = &proposal.nullifiers {
+            self.node_store_bytes += nullifiers.node_store().approximate_bytes();
+        }
+        self.proof_bytes += proposal
+            .proof
+            .as_ref()
+            .map_or(0, |proof| proof.artifact_len() as u64);
+        self.updates += proposal.updates.len() as u64;
+    }
+}
+
+impl ProposalStore {
+    pub fn dao_usage(&self, dao_id: &str) -> DaoUsage {
+        let mut usage = DaoUsage {
+            dao_id: dao_id.to_string(),
+            ..Default::default()
+        };
+        for (_, proposal) in self.iter() {
+            if proposal.dao_id == dao_id {
+                usage.add(proposal);
+            }
+        }
+        usage
+    }
+}
+
+/// A resource that is accounted per DAO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    NodeStoreBytes,
+    ProofBytes,
+    Updates,
+}
+
+impl QuotaKind {
+    pub fn error_code(self) -> ApiErrorCode {
+        match self {
+            QuotaKind::NodeStoreBytes => ApiErrorCode::NodeStoreQuotaExceeded,
+            QuotaKind::ProofBytes => ApiErrorCode::ProofQuotaExceeded,
+            QuotaKind::Updates => ApiErrorCode::UpdateQuotaExceeded,
+        }
+    }
+}
+
+/// Limits on the usage of every DAO; unset limits are unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DaoQuotas {
+    pub max_node_store_bytes: Option<u64>,
+    pub max_proof_bytes: Option<u64>,
+    pub max_updates: Option<u64>,
+}
+
+impl DaoQuotas {
+    /// Fails once the DAO has used up the quota of any of `kinds`. Quotas are soft:
+    /// the request that reaches a quota is still accepted, only later ones are rejected.
+    pub fn check(&self, usage: &DaoUsage, kinds: &[QuotaKind]) -> Result<(), ApiError> {
+        for kind in kinds {
+            let (used, limit) = match kind {
+                QuotaKind::NodeStoreBytes => (usage.node_store_bytes, self.max_node_store_bytes),
+                QuotaKind::ProofBytes => (usage.proof_bytes, self.max_proof_bytes),
+                QuotaKind::Updates => (usage.updates, self.max_updates),
+            };
+            if let Some(limit) = limit {
+                if used >= limit {
+                    return Err(ApiError::new(
+                        kind.error_code(),
+                        format!(
+                            "DAO {} has used {} of its {:?} quota of {}",
+                            usage.dao_id, used, kind, limit
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::weight::Weight,
+        errors::ApiErrorCode,
+        proposal::{rules::ProposalRules, store::ProposalStore, Proposal},
+    };
+
+    use super::{DaoQuotas, QuotaKind};
+
+    #[test]
+    fn test_usage_is_accounted_per_dao() {
+        let mut store = ProposalStore::new();
+        let mut proposal = Proposal::with_voter_balances(
+            "a".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1); 4],
+        )
+        .unwrap();
+        proposal.dao_id = "a".to_string();
+        proposal.cast_vote(2, true, None, 0).unwrap();
+        store.insert(uuid::Uuid::new_v4(), proposal);
+        store.insert(
+            uuid::Uuid::new_v4(),
+            Proposal::with_voter_balances(
+                "b".to_string(),
+                2,
+                0,
+                ProposalRules::default(),
+                vec![Weight::from(1); 4],
+            )
+            .unwrap(),
+        );
+
+        let usage = store.dao_usage("a");
+        assert_eq!((usage.proposals, usage.updates), (1, 1));
+        assert!(usage.node_store_bytes > 0);
+        assert_eq!(store.dao_usage("default").updates, 0);
+        assert_eq!(store.dao_usage("c").proposals, 0);
+
+        let quotas = DaoQuotas {
+            max_updates: Some(1),
+            ..Default::default()
+        };
+        assert!(quotas.check(&usage, &[QuotaKind::ProofBytes]).is_ok());
+        let err = quotas.check(&usage, &[QuotaKind::Updates]).unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::UpdateQuotaExceeded);
+    }
+}
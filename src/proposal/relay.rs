@@ -0,0 +1,221 @@
+//! Votes relayed on behalf of their voters. A voter registered by DID signs a
+//! [`RelayedVote`] offline as EIP-712 typed data, e.g. with
+//! `eth_signTypedData_v4` in a wallet, and anyone can submit it to
+//! `POST /vote/relayed` without holding a key of their own. Each vote carries
+//! the next nonce of its voter on the proposal and a time it is valid until, so
+//! a relayer can neither apply it twice nor hold it back indefinitely.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use web3::{
+    ethabi::{encode, Token},
+    signing::keccak256,
+};
+
+use crate::{
+    balance::accounts::VoteSplit,
+    errors::{ApiError, ApiErrorCode},
+};
+
+use super::Proposal;
+
+/// Name of the EIP-712 domain relayed votes are signed in.
+pub const RELAY_DOMAIN_NAME: &str = "qed-dapp";
+/// Version of the EIP-712 domain relayed votes are signed in.
+pub const RELAY_DOMAIN_VERSION: &str = "1";
+
+const DOMAIN_TYPE: &str = "EIP712Domain(string name,string version)";
+const RELAYED_VOTE_TYPE: &str = "RelayedVote(bytes16 proposalId,uint32 voterId,bool isYes,uint256 yesVotes,uint256 noVotes,uint64 nonce,uint64 validUntil)";
+
+/// A vote as its voter signs it for a relayer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RelayedVote {
+    pub proposal_id: Uuid,
+    pub voter_id: u32,
+    /// Ignored when the vote is split
+    #[serde(default)]
+    pub is_yes: bool,
+    pub split: Option<VoteSplit>,
+    /// Number of votes of the voter relayed on the proposal before this one.
+    pub nonce: u64,
+    /// Unix time after which the vote is no longer applied.
+    pub valid_until: u64,
+}
+
+impl RelayedVote {
+    /// The EIP-712 digest the voter signs: keccak256 of `0x1901`, the separator
+    /// of the domain and the hash of the vote. A vote that is not split is
+    /// signed with zero yes and no votes.
+    pub fn digest(&self) -> [u8; 32] {
+        let domain = keccak256(&encode(&[
+            Token::FixedBytes(keccak256(DOMAIN_TYPE.as_bytes()).to_vec()),
+            Token::FixedBytes(keccak256(RELAY_DOMAIN_NAME.as_bytes()).to_vec()),
+            Token::FixedBytes(keccak256(RELAY_DOMAIN_VERSION.as_bytes()).to_vec()),
+        ]));
+        let (yes_votes, no_votes) = match self.split {
+            Some(split) => (split.yes_votes.get(), split.no_votes.get()),
+            None => (0, 0),
+        };
+        let vote = keccak256(&encode(&[
+            Token::FixedBytes(keccak256(RELAYED_VOTE_TYPE.as_bytes()).to_vec()),
+            Token::FixedBytes(self.proposal_id.as_bytes().to_vec()),
+            Token::Uint(self.voter_id.into()),
+            Token::Bool(self.is_yes),
+            Token::Uint(yes_votes.into()),
+            Token::Uint(no_votes.into()),
+            Token::Uint(self.nonce.into()),
+            Token::Uint(self.valid_until.into()),
+        ]));
+        keccak256(&[&[0x19, 0x01][..], &domain, &vote].concat())
+    }
+}
+
+impl Proposal {
+    /// The nonce the next relayed vote of `voter_id` has to carry.
+    pub fn relay_nonce(&self, voter_id: u32) -> u64 {
+        self.relay_nonces.get(&voter_id).copied().unwrap_or(0)
+    }
+    /// Casts `vote` on the proposal `id` at time `now`, failing unless it is
+    /// still valid, carries the next nonce of its voter and `signature` over its
+    /// [`RelayedVote::digest`] is by the DID the voter was registered with.
+    pub fn cast_relayed_vote(
+        &mut self,
+        id: &Uuid,
+        vote: &RelayedVote,
+        signature: &[u8],
+        now: u64,
+    ) -> Result<(), ApiError> {
+        if vote.proposal_id != *id {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                format!("The vote was signed for proposal {}", vote.proposal_id),
+            ));
+        }
+        if now > vote.valid_until {
+            return Err(ApiError::new(
+                ApiErrorCode::RelayExpired,
+                format!("The vote was valid until {}", vote.valid_until),
+            ));
+        }
+        let did = match self.voter_did(vote.voter_id)? {
+            Some(did) => did,
+            None => {
+                return Err(ApiError::new(
+                    ApiErrorCode::NotRelayable,
+                    "The electorate of the proposal was not registered by DID",
+                ))
+            }
+        };
+        let nonce = self.relay_nonce(vote.voter_id);
+        if vote.nonce != nonce {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidRelayNonce,
+                format!(
+                    "The next relayed vote of voter {} carries nonce {}, not {}",
+                    vote.voter_id, nonce, vote.nonce
+                ),
+            ));
+        }
+        did.verify_digest(&vote.digest(), signature)
+            .map_err(|err| {
+                ApiError::new(
+                    ApiErrorCode::InvalidDidSignature,
+                    format!("Invalid signature of {}: {}", did, err),
+                )
+            })?;
+        match vote.split {
+            Some(split) => self.cast_split_vote(vote.voter_id, split, now)?,
+            None => self.cast_vote(vote.voter_id, vote.is_yes, None, now)?,
+        }
+        self.relay_nonces.insert(vote.voter_id, nonce + 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use uuid::Uuid;
+    use web3::signing::{Key, SecretKeyRef};
+
+    use crate::{
+        balance::weight::Weight,
+        did::Did,
+        errors::{ApiError, ApiErrorCode},
+        proof::identity::InstanceSigner,
+        proposal::{rules::ProposalRules, Proposal},
+    };
+
+    use super::RelayedVote;
+
+    #[test]
+    fn test_relays_signed_votes_once() -> anyhow::Result<()> {
+        let key = InstanceSigner::key_from_hex(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )?;
+        let ethr: Did = format!("did:ethr:{:?}", SecretKeyRef::new(&key).address()).parse()?;
+        let signing_key = SigningKey::from_bytes(&[5u8; 32]);
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1); 2],
+        )
+        .unwrap();
+        let id = Uuid::nil();
+        let code = |result: Result<(), ApiError>| result.unwrap_err().code;
+
+        let vote = RelayedVote {
+            proposal_id: id,
+            voter_id: 2,
+            is_yes: true,
+            split: None,
+            nonce: 0,
+            valid_until: 100,
+        };
+        let signed = SecretKeyRef::new(&key).sign_message(&vote.digest())?;
+        let mut signature = [signed.r.as_bytes(), signed.s.as_bytes()].concat();
+        signature.push(signed.v as u8 + 27);
+        assert_eq!(
+            code(proposal.cast_relayed_vote(&id, &vote, &signature, 10)),
+            ApiErrorCode::NotRelayable
+        );
+
+        proposal.voter_dids = vec![ethr, Did::Key(signing_key.verifying_key())];
+        assert_eq!(
+            code(proposal.cast_relayed_vote(&id, &vote, &signature, 101)),
+            ApiErrorCode::RelayExpired
+        );
+        let flipped = RelayedVote {
+            is_yes: false,
+            ..vote
+        };
+        assert_eq!(
+            code(proposal.cast_relayed_vote(&id, &flipped, &signature, 10)),
+            ApiErrorCode::InvalidDidSignature
+        );
+        proposal.cast_relayed_vote(&id, &vote, &signature, 10)?;
+        assert_eq!(proposal.relay_nonce(2), 1);
+        assert_eq!(
+            code(proposal.cast_relayed_vote(&id, &vote, &signature, 10)),
+            ApiErrorCode::InvalidRelayNonce
+        );
+
+        // did:key voters sign the digest with their Ed25519 key
+        let vote = RelayedVote {
+            voter_id: 3,
+            is_yes: false,
+            ..vote
+        };
+        let signature = signing_key.sign(&vote.digest()).to_bytes();
+        proposal.cast_relayed_vote(&id, &vote, &signature, 10)?;
+        let tally = proposal.storage.tally().unwrap();
+        assert_eq!(
+            (tally.yes_votes, tally.no_votes),
+            (Weight::from(1), Weight::from(1))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use anyhow::ensure;
+use plonky2::{
+    field::{
+        goldilocks_field::GoldilocksField,
+        types::{Field, PrimeField64},
+    },
+    hash::poseidon::PoseidonHash,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    balance::{
+        accounts::{Tally, DEFAULT_BALANCE_BITS, MAX_BALANCE_BITS},
+        vesting::VestingRules,
+        weight::Weight,
+    },
+    circuits::{
+        conviction::{ConvictionStamp, MAX_CONVICTION_BALANCE_BITS, MAX_CONVICTION_MULTIPLIER},
+        quadratic::VotingPolicy,
+    },
+    common::hash::traits::hasher::FieldWHasher,
+    nullifier::nullifier_set::proposal_id_to_elements,
+};
+
+/// How a proposal with as many yes as no votes is decided.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TiePolicy {
+    #[default]
+    Veto,
+    Pass,
+    /// The proposal closes without a decision and voting reopens in a new
+    /// round, see [`super::events::ProposalGenesis::revote_round`].
+    Revote,
+    /// The decision is derived from the hash of the block
+    /// [`ProposalRules::beacon_block`], which the node fetches at finalization,
+    /// see [`tie_breaker_from_beacon`] and [`crate::chain::beacon`].
+    RandomWithBeacon,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalOutcome {
+    Passed,
+    Vetoed,
+    /// Tied under [`TiePolicy::Revote`], the electorate votes again.
+    Revote,
+}
+
+impl ProposalOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProposalOutcome::Passed => "passed",
+            ProposalOutcome::Vetoed => "vetoed",
+            ProposalOutcome::Revote => "revote",
+        }
+    }
+}
+
+/// The elements a tie is broken by: the proposal id, the length of the beacon
+/// value and the value in little endian chunks of 4 bytes, the last one zero
+/// padded. The length keeps values differing only in trailing zeros apart.
+fn beacon_elements(proposal_id: &Uuid, beacon: &[u8]) -> Vec<GoldilocksField> {
+    let mut elements = proposal_id_to_elements(proposal_id).to_vec();
+    elements.push(GoldilocksField::from_canonical_usize(beacon.len()));
+    elements.extend(beacon.chunks(4).map(|chunk| {
+        let mut bytes = [0u8; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        GoldilocksField::from_canonical_u32(u32::from_le_bytes(bytes))
+    }));
+    elements
+}
+
+/// Returns true if a tie on `proposal_id` is broken in favor of passing, given
+/// the beacon value: the low bit of the Poseidon hash of [`beacon_elements`].
+pub fn tie_breaker_from_beacon(proposal_id: &Uuid, beacon: &[u8]) -> bool {
+    let elements = beacon_elements(proposal_id, beacon);
+    PoseidonHash::w_hash_many(&elements).0.elements[0].to_canonical_u64() & 1 == 1
+}
+
+/// Conviction voting, see [`crate::circuits::conviction`]: a vote cast `t`
+/// seconds before the deadline adds its weight times `1 + t / step_secs` to the tally.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ConvictionRules {
+    pub step_secs: u64,
+}
+
+/// Parameters fixed at proposal creation that govern how it is voted on.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProposalRules {
+    /// Seconds after creation during which votes are accepted; unlimited if unset.
+    pub voting_period_secs: Option<u64>,
+    /// Total weight that has to be cast for the result to count.
+    pub quorum: Option<Weight>,
+    #[serde(default)]
+    pub tie_policy: TiePolicy,
+    /// Seconds after creation during which voters commit to their votes, see
+    /// [`super::commitment`]. Votes are only accepted afterwards, and only if they
+    /// match a commitment. Votes are cast directly if unset.
+    pub commit_period_secs: Option<u64>,
+    /// Width the balances and tallies are range checked to when proving, at most
+    /// [`MAX_BALANCE_BITS`]. Defaults to [`DEFAULT_BALANCE_BITS`] if unset.
+    pub balance_bits: Option<usize>,
+    /// Weighs votes by how long before the deadline they were cast. Requires a
+    /// voting period, and votes cannot be revoked.
+    #[serde(default)]
+    pub conviction: Option<ConvictionRules>,
+    /// Counts the square root of what a vote spends rather than all of it, see
+    /// [`crate::circuits::quadratic`]. Quadratic votes cannot be revoked.
+    #[serde(default)]
+    pub voting_policy: VotingPolicy,
+    /// Locks part of the weight of voters until an epoch, see
+    /// [`crate::balance::vesting`]. Nothing is locked if unset.
+    #[serde(default)]
+    pub vesting: Option<VestingRules>,
+    /// Least weight a vote or delegation moves, which keeps votes of a few units
+    /// from bloating the updates and the proof, see
+    /// [`crate::circuits::min_transfer`]. Any weight moves if unset.
+    #[serde(default)]
+    pub min_transfer: Option<Weight>,
+    /// Block of the chain the node is connected to whose hash breaks ties under
+    /// [`TiePolicy::RandomWithBeacon`], which requires it. It is not mined yet
+    /// when the proposal is created, see [`crate::chain::beacon::check_beacon_lead`].
+    #[serde(default)]
+    pub beacon_block: Option<u64>,
+    /// Splits the electorate across this many balance trees, proven in parallel
+    /// and combined by a shard root proof, see [`crate::balance::shards`]. Takes
+    /// plain votes and delegations within a shard only. Unsharded if unset.
+    #[serde(default)]
+    pub shard_count: Option<usize>,
+}
+
+impl ProposalRules {
+    pub fn balance_bits(&self) -> usize {
+        self.balance_bits.unwrap_or(DEFAULT_BALANCE_BITS)
+    }
+    /// Checks that the rules are consistent, i.e. that the commitment period ends
+    /// before the voting period does and that balances can be range checked.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            (1..=MAX_BALANCE_BITS).contains(&self.balance_bits()),
+            "balances have to be between 1 and {} bits wide",
+            MAX_BALANCE_BITS
+        );
+        if let Some(minimum) = self.min_transfer {
+            ensure!(
+                minimum > Weight::ZERO && minimum.fits(self.balance_bits()),
+                "the minimum transfer has to be positive and fit in {} bits",
+                self.balance_bits()
+            );
+        }
+        if let (Some(commit_period), Some(voting_period)) =
+            (self.commit_period_secs, self.voting_period_secs)
+        {
+            ensure!(
+                commit_period < voting_period,
+                "the commitment period of {}s must end before the voting period of {}s",
+                commit_period,
+                voting_period
+            );
+        }
+        ensure!(
+            self.beacon_block.is_some() == (self.tie_policy == TiePolicy::RandomWithBeacon),
+            "a beacon block is required by, and only taken with, the random-with-beacon tie policy"
+        );
+        if let Some(shard_count) = self.shard_count {
+            ensure!(
+                shard_count > 1,
+                "a sharded electorate takes at least 2 shards"
+            );
+            // Shards are proven with circuits that check none of these
+            ensure!(
+                self.conviction.is_none()
+                    && self.voting_policy.is_linear()
+                    && self.vesting.is_none()
+                    && self.voting_period_secs.is_none()
+                    && self.min_transfer.is_none(),
+                "shards take no conviction, quadratic votes, vesting, voting period or minimum transfer"
+            );
+        }
+        if let Some(conviction) = self.conviction {
+            let voting_period = self
+                .voting_period_secs
+                .ok_or_else(|| anyhow::anyhow!("conviction voting requires a voting period"))?;
+            ensure!(conviction.step_secs > 0, "conviction steps cannot be empty");
+            ensure!(
+                voting_period / conviction.step_secs < MAX_CONVICTION_MULTIPLIER,
+                "a voting period of {}s has more than {} conviction steps of {}s",
+                voting_period,
+                MAX_CONVICTION_MULTIPLIER - 1,
+                conviction.step_secs
+            );
+            ensure!(
+                self.balance_bits() <= MAX_CONVICTION_BALANCE_BITS,
+                "balances of proposals with conviction voting can be at most {} bits wide",
+                MAX_CONVICTION_BALANCE_BITS
+            );
+        }
+        Ok(())
+    }
+    /// The most a tally can hold when the electorate casts `total_weight`, all of
+    /// it with the largest conviction multiplier the voting period allows.
+    pub fn max_tally(&self, total_weight: Weight) -> Option<Weight> {
+        let multiplier = match (self.conviction, self.voting_period_secs) {
+            (Some(conviction), Some(voting_period)) => {
+                1 + voting_period.checked_div(conviction.step_secs)?
+            }
+            _ => 1,
+        };
+        Weight::try_from(total_weight.get().checked_mul(multiplier)?).ok()
+    }
+    /// The conviction stamp of an update recorded at `now` on a proposal created
+    /// at `created_at`, if votes are weighted by conviction.
+    pub fn conviction_stamp(&self, created_at: u64, now: u64) -> Option<ConvictionStamp> {
+        let conviction = self.conviction?;
+        Some(ConvictionStamp {
+            voted_at: now,
+            deadline: created_at.saturating_add(self.voting_period_secs?),
+            step_secs: conviction.step_secs,
+        })
+    }
+    /// Decides the outcome of a proposal with the given tally. `beacon`, the hash
+    /// of [`Self::beacon_block`], is only needed to break ties under
+    /// [`TiePolicy::RandomWithBeacon`].
+    pub fn resolve(
+        &self,
+        proposal_id: &Uuid,
+        tally: &Tally,
+        beacon: Option<&[u8]>,
+    ) -> anyhow::Result<ProposalOutcome> {
+        if !tally.is_tie() {
+            return Ok(if tally.is_passed() {
+                ProposalOutcome::Passed
+            } else {
+                ProposalOutcome::Vetoed
+            });
+        }
+        Ok(match self.tie_policy {
+            TiePolicy::Veto => ProposalOutcome::Vetoed,
+            TiePolicy::Pass => ProposalOutcome::Passed,
+            TiePolicy::Revote => ProposalOutcome::Revote,
+            TiePolicy::RandomWithBeacon => {
+                ensure!(
+                    beacon.is_some(),
+                    "a beacon value is required to break the tie"
+                );
+                if tie_breaker_from_beacon(proposal_id, beacon.unwrap()) {
+                    ProposalOutcome::Passed
+                } else {
+                    ProposalOutcome::Vetoed
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_ties() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let tie = Tally {
+            yes_votes: Weight::from(3),
+            no_votes: Weight::from(3),
+        };
+        let majority = Tally {
+            yes_votes: Weight::from(4),
+            no_votes: Weight::from(3),
+        };
+        let rules = |tie_policy| ProposalRules {
+            tie_policy,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            rules(TiePolicy::Veto).resolve(&id, &tie, None)?,
+            ProposalOutcome::Vetoed
+        );
+        assert_eq!(
+            rules(TiePolicy::Pass).resolve(&id, &tie, None)?,
+            ProposalOutcome::Passed
+        );
+        assert_eq!(
+            rules(TiePolicy::Revote).resolve(&id, &tie, None)?,
+            ProposalOutcome::Revote
+        );
+        assert_eq!(
+            rules(TiePolicy::Revote).resolve(&id, &majority, None)?,
+            ProposalOutcome::Passed
+        );
+        assert!(rules(TiePolicy::RandomWithBeacon)
+            .resolve(&id, &tie, None)
+            .is_err());
+
+        let beacon = [7u8; 32];
+        let expected = if tie_breaker_from_beacon(&id, &beacon) {
+            ProposalOutcome::Passed
+        } else {
+            ProposalOutcome::Vetoed
+        };
+        assert_eq!(
+            rules(TiePolicy::RandomWithBeacon).resolve(&id, &tie, Some(&beacon))?,
+            expected
+        );
+        // Values differing in trailing zeros are hashed apart
+        assert_ne!(
+            beacon_elements(&id, &[0x07]),
+            beacon_elements(&id, &[0x07, 0x00])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_ties_need_a_beacon_block() {
+        let rules = |tie_policy, beacon_block| ProposalRules {
+            tie_policy,
+            beacon_block,
+            ..Default::default()
+        };
+        assert!(rules(TiePolicy::RandomWithBeacon, None).validate().is_err());
+        assert!(rules(TiePolicy::RandomWithBeacon, Some(100))
+            .validate()
+            .is_ok());
+        assert!(rules(TiePolicy::Veto, Some(100)).validate().is_err());
+    }
+    #[test]
+    fn test_sharding_takes_plain_votes_only() {
+        let sharded = ProposalRules {
+            shard_count: Some(4),
+            ..Default::default()
+        };
+        assert!(sharded.validate().is_ok());
+        assert!(ProposalRules {
+            shard_count: Some(1),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(ProposalRules {
+            voting_policy: VotingPolicy::Quadratic,
+            ..sharded.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(ProposalRules {
+            voting_period_secs: Some(600),
+            ..sharded
+        }
+        .validate()
+        .is_err());
+    }
+}
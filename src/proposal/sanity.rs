@@ -0,0 +1,149 @@
+//! Checks that the balance tree of a proposal still agrees with its recorded updates.
+//!
+//! Finalization proves the recorded updates, while votes are counted from the
+//! tree, and nothing keeps the two consistent if a bug writes to one but not the
+//! other. The server checks open proposals periodically so a divergence is
+//! noticed before proving fails on it, or worse, proves a different tally.
+
+use std::fmt;
+
+use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{balance::storage::BalanceStorage, circuits::update_balance::BalanceUpdate};
+
+/// The first point at which the updates of a proposal stop describing its tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TreeDivergence {
+    /// The merkle proofs of the update do not verify.
+    InvalidProof { update: usize },
+    /// The update does not start from the root the previous update, or the
+    /// sender half of the update, left the tree at.
+    BrokenChain { update: usize },
+    /// The update moves weight the circuit would reject.
+    InvalidWeights { update: usize, reason: String },
+    /// Replaying every update does not end at the current root of the tree.
+    RootMismatch { expected: String, actual: String },
+}
+
+impl fmt::Display for TreeDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeDivergence::InvalidProof { update } => {
+                write!(f, "update {} has invalid merkle proofs", update)
+            }
+            TreeDivergence::BrokenChain { update } => {
+                write!(
+                    f,
+                    "update {} does not continue from the previous root",
+                    update
+                )
+            }
+            TreeDivergence::InvalidWeights { update, reason } => {
+                write!(f, "update {} moves invalid weights: {}", update, reason)
+            }
+            TreeDivergence::RootMismatch { expected, actual } => write!(
+                f,
+                "the updates end at root {} but the tree is at {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// Recomputes the chain of roots through `updates`, starting from the initial
+/// root of `storage`, and compares where it ends with the current root of the
+/// tree. Fails only if the tree cannot be read.
+pub fn check_tree(
+    storage: &BalanceStorage,
+    updates: &[BalanceUpdate<GoldilocksField>],
+) -> anyhow::Result<Option<TreeDivergence>> {
+    let mut root = storage.initial_root();
+    for (i, update) in updates.iter().enumerate() {
+        if !update.sender_update.verify::<PoseidonHash>()
+            || !update.receiver_update.verify::<PoseidonHash>()
+        {
+            return Ok(Some(TreeDivergence::InvalidProof { update: i }));
+        }
+        if update.old_root() != root
+            || update.sender_update.new_root != update.receiver_update.old_root
+        {
+            return Ok(Some(TreeDivergence::BrokenChain { update: i }));
+        }
+        if let Err(err) = update.check_weights(storage.balance_bits()) {
+            return Ok(Some(TreeDivergence::InvalidWeights {
+                update: i,
+                reason: err.to_string(),
+            }));
+        }
+        root = update.new_root();
+    }
+    let actual = storage.tree.get_root()?;
+    if actual != root {
+        return Ok(Some(TreeDivergence::RootMismatch {
+            expected: root.to_string(),
+            actual: actual.to_string(),
+        }));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::{accounts::TallySlot, weight::Weight},
+        common::WHashOut,
+        proposal::{rules::ProposalRules, Proposal},
+    };
+
+    use super::{check_tree, TreeDivergence};
+
+    #[test]
+    fn test_detects_diverging_trees() {
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            0,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1); 4],
+        )
+        .unwrap();
+        assert_eq!(
+            check_tree(&proposal.storage, &proposal.updates).unwrap(),
+            None
+        );
+        proposal.cast_vote(2, true, None, 1).unwrap();
+        proposal.cast_vote(3, false, None, 2).unwrap();
+        assert_eq!(
+            check_tree(&proposal.storage, &proposal.updates).unwrap(),
+            None
+        );
+
+        // Out of order updates no longer chain
+        let mut swapped = proposal.updates.clone();
+        swapped.swap(0, 1);
+        assert_eq!(
+            check_tree(&proposal.storage, &swapped).unwrap(),
+            Some(TreeDivergence::BrokenChain { update: 0 })
+        );
+        let mut forged = proposal.updates.clone();
+        forged[1].receiver_update.new_value = WHashOut::from_values(2, 0, 0, 0);
+        assert_eq!(
+            check_tree(&proposal.storage, &forged).unwrap(),
+            Some(TreeDivergence::InvalidProof { update: 1 })
+        );
+
+        // A write to the tree that was never recorded as an update
+        proposal
+            .storage
+            .tree
+            .set_leaf(TallySlot::YES.index(), WHashOut::from_values(5, 0, 0, 0))
+            .unwrap();
+        assert!(matches!(
+            check_tree(&proposal.storage, &proposal.updates).unwrap(),
+            Some(TreeDivergence::RootMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+//! Delegations made once for many proposals: a voter delegates to a delegate on
+//! a single proposal or on every proposal of a category of a DAO, until an
+//! optional expiry. A background task applies each delegation to the proposals
+//! in its scope that accept updates, as the voter delegating on them would, and
+//! moves the weight back to the voter with an undelegation once the scope ends,
+//! at the expiry or when the voter revokes the delegation. Weight the delegate
+//! cast before then stays cast, see [`Proposal::undelegate`].
+//!
+//! Voter ids are positions in the electorate of each proposal, so category
+//! delegations suit DAOs whose proposals share an electorate. Proposals whose
+//! electorate was registered by DID, or whose voters lock tokens, are left out:
+//! delegating on them takes a signature or a lock of the voter. Like the webhook
+//! queue, the registry is kept in memory; the delegations and undelegations it
+//! applied are events of their proposals.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiErrorCode};
+
+use super::{store::ProposalStore, validation::validate_category, Proposal, DEFAULT_DAO_ID};
+
+fn default_dao_id() -> String {
+    DEFAULT_DAO_ID.to_string()
+}
+
+/// The proposals a scoped delegation applies to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DelegationScope {
+    /// A single proposal, until it closes.
+    Proposal { proposal_id: Uuid },
+    /// Every proposal of `dao_id` filed under `category`, including those
+    /// created after the delegation.
+    Category {
+        #[serde(default = "default_dao_id")]
+        dao_id: String,
+        category: String,
+    },
+}
+
+impl DelegationScope {
+    /// Whether the proposal `proposal_id` lies in the scope.
+    pub fn covers(&self, proposal_id: &Uuid, proposal: &Proposal) -> bool {
+        match self {
+            DelegationScope::Proposal { proposal_id: id } => id == proposal_id,
+            DelegationScope::Category { dao_id, category } => {
+                proposal.dao_id == *dao_id
+                    && proposal.category.as_deref() == Some(category.as_str())
+            }
+        }
+    }
+}
+
+/// A proposal in scope a scoped delegation could not be applied to or reverted on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DeclinedProposal {
+    pub proposal_id: Uuid,
+    pub reason: String,
+}
+
+/// A delegation of the weight of a voter over a [`DelegationScope`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ScopedDelegation {
+    pub id: Uuid,
+    pub voter_id: u32,
+    pub delegate_id: u32,
+    pub scope: DelegationScope,
+    pub created_at: u64,
+    /// Unix time the scope ends at, if before the proposals in it close.
+    pub expires_at: Option<u64>,
+    /// Proposals the delegate holds the weight of the voter on.
+    pub applied: Vec<Uuid>,
+    /// Proposals in scope the delegation is not tried on again.
+    pub declined: Vec<DeclinedProposal>,
+}
+
+impl ScopedDelegation {
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |at| now >= at)
+    }
+
+    /// Whether nothing is left for the delegation to apply: it expired, or
+    /// its single proposal no longer takes updates.
+    fn has_ended(&self, proposals: &ProposalStore, now: u64) -> bool {
+        self.has_expired(now)
+            || match &self.scope {
+                DelegationScope::Proposal { proposal_id } => proposals
+                    .get(proposal_id)
+                    .map_or(true, |proposal| !proposal.status.accepts_updates()),
+                DelegationScope::Category { .. } => false,
+            }
+    }
+
+    fn tried(&self, proposal_id: &Uuid) -> bool {
+        self.applied.contains(proposal_id)
+            || self
+                .declined
+                .iter()
+                .any(|declined| declined.proposal_id == *proposal_id)
+    }
+}
+
+/// A change made to a proposal on behalf of a voter with a scoped delegation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum DelegationStep {
+    /// Delegates the weight of the voter on a proposal in scope.
+    Apply {
+        delegation_id: Uuid,
+        proposal_id: Uuid,
+        voter_id: u32,
+        delegate_id: u32,
+    },
+    /// Moves the weight of the voter back to them once the scope ended.
+    Revert {
+        delegation_id: Uuid,
+        proposal_id: Uuid,
+        voter_id: u32,
+    },
+}
+
+impl DelegationStep {
+    pub fn delegation_id(&self) -> Uuid {
+        match self {
+            DelegationStep::Apply { delegation_id, .. }
+            | DelegationStep::Revert { delegation_id, .. } => *delegation_id,
+        }
+    }
+
+    pub fn proposal_id(&self) -> Uuid {
+        match self {
+            DelegationStep::Apply { proposal_id, .. }
+            | DelegationStep::Revert { proposal_id, .. } => *proposal_id,
+        }
+    }
+}
+
+/// The scoped delegations of the server, in the order they were made.
+#[derive(Debug, Default)]
+pub struct ScopedDelegationRegistry {
+    delegations: Vec<ScopedDelegation>,
+}
+
+impl ScopedDelegationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.delegations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.delegations.is_empty()
+    }
+
+    /// Registers a delegation of `voter_id` to `delegate_id` over `scope` at
+    /// time `now`, failing if the voter already delegates over the same scope.
+    pub fn insert(
+        &mut self,
+        voter_id: u32,
+        delegate_id: u32,
+        scope: DelegationScope,
+        expires_at: Option<u64>,
+        now: u64,
+    ) -> Result<ScopedDelegation, ApiError> {
+        if voter_id == delegate_id {
+            return Err(ApiError::new(
+                ApiErrorCode::DelegationCycle,
+                format!("Voter {} cannot delegate to themselves", voter_id),
+            ));
+        }
+        if let DelegationScope::Category { category, .. } = &scope {
+            validate_category(category)?;
+        }
+        if expires_at.map_or(false, |at| at <= now) {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidQuery,
+                "The delegation must expire in the future",
+            ));
+        }
+        let duplicate = self.delegations.iter().any(|delegation| {
+            delegation.voter_id == voter_id
+                && delegation.scope == scope
+                && !delegation.has_expired(now)
+        });
+        if duplicate {
+            return Err(ApiError::new(
+                ApiErrorCode::AlreadyDelegated,
+                format!("Voter {} already delegates over this scope", voter_id),
+            ));
+        }
+        let delegation = ScopedDelegation {
+            id: Uuid::new_v4(),
+            voter_id,
+            delegate_id,
+            scope,
+            created_at: now,
+            expires_at,
+            applied: vec![],
+            declined: vec![],
+        };
+        self.delegations.push(delegation.clone());
+        Ok(delegation)
+    }
+
+    /// The delegations of `voter_id`, including those still being reverted.
+    pub fn of_voter(&self, voter_id: u32) -> Vec<ScopedDelegation> {
+        self.delegations
+            .iter()
+            .filter(|delegation| delegation.voter_id == voter_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Ends the scope of the delegation `id` of `voter_id` at time `now`; its
+    /// weight moves back to the voter on the next run of the registry.
+    pub fn revoke(
+        &mut self,
+        id: &Uuid,
+        voter_id: u32,
+        now: u64,
+    ) -> Result<ScopedDelegation, ApiError> {
+        let delegation = self
+            .delegations
+            .iter_mut()
+            .find(|delegation| delegation.id == *id && delegation.voter_id == voter_id)
+            .ok_or_else(|| {
+                ApiError::new(
+                    ApiErrorCode::DelegationNotFound,
+                    format!("Voter {} has no scoped delegation {}", voter_id, id),
+                )
+            })?;
+        delegation.expires_at = Some(delegation.expires_at.map_or(now, |at| at.min(now)));
+        Ok(delegation.clone())
+    }
+
+    /// The steps due at time `now`: reverting the delegations whose scope
+    /// ended on the proposals still taking updates, and applying the others to
+    /// the proposals in scope they were not tried on yet. Drops the delegations
+    /// left with nothing to do.
+    pub fn due(&mut self, proposals: &ProposalStore, now: u64) -> Vec<DelegationStep> {
+        let mut steps = vec![];
+        for delegation in &mut self.delegations {
+            // The weight stays where it is once a proposal stops taking updates
+            delegation.applied.retain(|proposal_id| {
+                proposals
+                    .get(proposal_id)
+                    .map_or(false, |proposal| proposal.status.accepts_updates())
+            });
+            if delegation.has_expired(now) {
+                steps.extend(
+                    delegation
+                        .applied
+                        .iter()
+                        .map(|proposal_id| DelegationStep::Revert {
+                            delegation_id: delegation.id,
+                            proposal_id: *proposal_id,
+                            voter_id: delegation.voter_id,
+                        }),
+                );
+                continue;
+            }
+            let mut in_scope: Vec<(&Uuid, &Proposal)> = proposals
+                .iter()
+                .filter(|(proposal_id, proposal)| {
+                    proposal.status.accepts_updates()
+                        && delegation.scope.covers(proposal_id, proposal)
+                        && !delegation.tried(proposal_id)
+                })
+                .collect();
+            in_scope.sort_by_key(|(proposal_id, proposal)| (proposal.created_at, **proposal_id));
+            for (proposal_id, proposal) in in_scope {
+                if !proposal.voter_dids.is_empty() || proposal.locks_tokens {
+                    delegation.declined.push(DeclinedProposal {
+                        proposal_id: *proposal_id,
+                        reason:
+                            "Delegating on the proposal takes a signature or a lock of the voter"
+                                .to_string(),
+                    });
+                    continue;
+                }
+                steps.push(DelegationStep::Apply {
+                    delegation_id: delegation.id,
+                    proposal_id: *proposal_id,
+                    voter_id: delegation.voter_id,
+                    delegate_id: delegation.delegate_id,
+                });
+            }
+        }
+        self.delegations.retain(|delegation| {
+            !delegation.applied.is_empty() || !delegation.has_ended(proposals, now)
+        });
+        steps
+    }
+
+    /// Records the outcome of a step returned by [`ScopedDelegationRegistry::due`].
+    pub fn record(&mut self, step: &DelegationStep, outcome: Result<(), ApiError>) {
+        let delegation = match self
+            .delegations
+            .iter_mut()
+            .find(|delegation| delegation.id == step.delegation_id())
+        {
+            Some(delegation) => delegation,
+            None => return,
+        };
+        let proposal_id = step.proposal_id();
+        match (step, outcome) {
+            (DelegationStep::Apply { .. }, Ok(())) => delegation.applied.push(proposal_id),
+            (DelegationStep::Apply { .. }, Err(err)) => {
+                delegation.declined.push(DeclinedProposal {
+                    proposal_id,
+                    reason: err.message,
+                })
+            }
+            (DelegationStep::Revert { .. }, outcome) => {
+                delegation.applied.retain(|id| *id != proposal_id);
+                if let Err(err) = outcome {
+                    delegation.declined.push(DeclinedProposal {
+                        proposal_id,
+                        reason: format!("Not reverted: {}", err.message),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        balance::weight::Weight,
+        errors::{ApiError, ApiErrorCode},
+        proposal::{rules::ProposalRules, store::ProposalStore, Proposal, ProposalStatus},
+    };
+
+    use super::{DelegationScope, DelegationStep, ScopedDelegationRegistry};
+
+    fn apply(store: &mut ProposalStore, step: &DelegationStep, now: u64) -> Result<(), ApiError> {
+        let proposal = store.get_mut(&step.proposal_id()).unwrap();
+        match step {
+            DelegationStep::Apply {
+                voter_id,
+                delegate_id,
+                ..
+            } => proposal.delegate(*voter_id, *delegate_id, now),
+            DelegationStep::Revert { voter_id, .. } => proposal.undelegate(*voter_id, now),
+        }
+    }
+
+    #[test]
+    fn test_category_delegation_applies_and_reverts_at_expiry() {
+        let mut store = ProposalStore::new();
+        let mut ids = vec![];
+        for (category, created_at) in [
+            (Some("treasury"), 0),
+            (Some("grants"), 1),
+            (Some("treasury"), 2),
+        ] {
+            let mut proposal = Proposal::new(
+                "Fund the audit".to_string(),
+                1,
+                created_at,
+                ProposalRules::default(),
+            )
+            .unwrap();
+            proposal.category = category.map(str::to_string);
+            proposal.transition(ProposalStatus::Open).unwrap();
+            let id = Uuid::new_v4();
+            store.insert(id, proposal);
+            ids.push(id);
+        }
+        let mut registry = ScopedDelegationRegistry::new();
+        let scope = DelegationScope::Category {
+            dao_id: "default".to_string(),
+            category: "treasury".to_string(),
+        };
+        let delegation = registry.insert(3, 4, scope.clone(), Some(100), 10).unwrap();
+        assert_eq!(
+            registry.insert(3, 5, scope, None, 10).unwrap_err().code,
+            ApiErrorCode::AlreadyDelegated
+        );
+
+        let steps = registry.due(&store, 10);
+        assert_eq!(
+            steps
+                .iter()
+                .map(DelegationStep::proposal_id)
+                .collect::<Vec<_>>(),
+            vec![ids[0], ids[2]]
+        );
+        for step in &steps {
+            let outcome = apply(&mut store, step, 10);
+            registry.record(step, outcome);
+        }
+        let power = store.get(&ids[0]).unwrap().voting_power(4).unwrap();
+        assert_eq!(power.effective_weight, Weight::from(2));
+        assert!(registry.due(&store, 50).is_empty());
+
+        let steps = registry.due(&store, 100);
+        assert_eq!(steps.len(), 2);
+        assert!(matches!(
+            steps[0],
+            DelegationStep::Revert { voter_id: 3, .. }
+        ));
+        for step in &steps {
+            let outcome = apply(&mut store, step, 100);
+            registry.record(step, outcome);
+        }
+        let power = store.get(&ids[2]).unwrap().voting_power(3).unwrap();
+        assert_eq!(power.effective_weight, Weight::from(1));
+        assert_eq!(power.consistent, Some(true));
+        assert!(registry.of_voter(3)[0].declined.is_empty());
+
+        // Nothing is left to revert, so the delegation is dropped
+        assert!(registry.due(&store, 101).is_empty());
+        assert!(registry.is_empty());
+        assert_eq!(
+            registry.revoke(&delegation.id, 3, 101).unwrap_err().code,
+            ApiErrorCode::DelegationNotFound
+        );
+    }
+}
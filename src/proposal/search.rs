@@ -0,0 +1,401 @@
+//! Full-text search over the statements and action payloads of proposals.
+//!
+//! [`SearchIndex`] maps each term to the proposals containing it and ranks the
+//! proposals matching any term of a query with BM25, terms of the statement
+//! weighing more than terms of the action. The index lives in memory next to
+//! the store and is brought up to date before each search, reindexing only the
+//! proposals whose text changed, see [`SearchIndex::refresh`]. Hits carry the
+//! byte ranges of the matched terms, for clients to highlight.
+//!
+//! Terms are the runs of alphanumeric characters of a text, lowercased. Action
+//! payloads are indexed as their JSON encoding, so a search for a recipient
+//! address or a parameter name finds the proposals acting on it.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::{
+    action::ProposalAction,
+    store::{Page, ProposalStore, DEFAULT_PER_PAGE, MAX_PER_PAGE},
+};
+
+/// Longest query text searched for.
+pub const MAX_QUERY_LEN: usize = 256;
+/// Longest excerpt of a statement or action payload returned with a hit.
+pub const SNIPPET_LEN: usize = 160;
+
+const STATEMENT_WEIGHT: f64 = 2.0;
+const ACTION_WEIGHT: f64 = 1.0;
+// BM25 term frequency saturation and length normalization
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// A term of a text and where it is, in bytes.
+struct Token {
+    term: String,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                tokens.push(Token {
+                    term: text[s..i].to_lowercase(),
+                    start: s,
+                    end: i,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+fn action_text(action: &ProposalAction) -> String {
+    serde_json::to_string(action).expect("actions serialize to JSON")
+}
+
+fn default_page() -> usize {
+    1
+}
+fn default_per_page() -> usize {
+    DEFAULT_PER_PAGE
+}
+
+/// Full-text query over proposals; pages are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Words to search statements and action payloads for, ignoring case
+    pub q: String,
+    /// Only proposals of this DAO
+    pub dao_id: Option<String>,
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+impl SearchQuery {
+    pub fn new(q: impl Into<String>) -> Self {
+        Self {
+            q: q.into(),
+            dao_id: None,
+            page: default_page(),
+            per_page: default_per_page(),
+        }
+    }
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.q.len() <= MAX_QUERY_LEN,
+            "q must be at most {} bytes",
+            MAX_QUERY_LEN
+        );
+        ensure!(!tokenize(&self.q).is_empty(), "q must contain a word");
+        ensure!(self.page >= 1, "page must be at least 1");
+        ensure!(
+            self.per_page >= 1 && self.per_page <= MAX_PER_PAGE,
+            "per_page must be between 1 and {}",
+            MAX_PER_PAGE
+        );
+        Ok(())
+    }
+}
+
+/// Byte range of a matched term within the text of a [`Snippet`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TextRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Excerpt of an indexed text around its first match.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte offset of the excerpt in the whole text.
+    pub offset: usize,
+    pub highlights: Vec<TextRange>,
+}
+
+/// A proposal matching a search, with excerpts of the fields that matched.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SearchHit {
+    pub proposal_id: Uuid,
+    pub dao_id: String,
+    /// BM25 relevance of the proposal, hits are ordered by it.
+    pub score: f64,
+    pub statement: Option<Snippet>,
+    pub action: Option<Snippet>,
+}
+
+fn snippet(text: &str, terms: &HashSet<String>) -> Option<Snippet> {
+    let matches: Vec<Token> = tokenize(text)
+        .into_iter()
+        .filter(|token| terms.contains(&token.term))
+        .collect();
+    let first = matches.first()?;
+    let mut start = first.start.saturating_sub(SNIPPET_LEN / 4);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = text.len().min(start + SNIPPET_LEN).max(first.end);
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    Some(Snippet {
+        text: text[start..end].to_string(),
+        offset: start,
+        highlights: matches
+            .iter()
+            .filter(|token| token.end <= end)
+            .map(|token| TextRange {
+                start: token.start - start,
+                end: token.end - start,
+            })
+            .collect(),
+    })
+}
+
+struct IndexedProposal {
+    dao_id: String,
+    statement: String,
+    action: ProposalAction,
+    action_text: String,
+    /// Weighted number of occurrences of each term.
+    terms: HashMap<String, f64>,
+    /// Weighted number of terms.
+    len: f64,
+}
+
+/// Inverted index over the statements and action payloads of proposals.
+#[derive(Default)]
+pub struct SearchIndex {
+    proposals: HashMap<Uuid, IndexedProposal>,
+    postings: HashMap<String, HashSet<Uuid>>,
+    total_len: f64,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.proposals.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.proposals.is_empty()
+    }
+    /// Indexes a proposal, unless it is indexed with the same text already.
+    pub fn insert(&mut self, id: Uuid, dao_id: &str, statement: &str, action: &ProposalAction) {
+        if let Some(indexed) = self.proposals.get(&id) {
+            if indexed.dao_id == dao_id
+                && indexed.statement == statement
+                && indexed.action == *action
+            {
+                return;
+            }
+        }
+        self.remove(&id);
+
+        let action_text = action_text(action);
+        let mut terms = HashMap::<String, f64>::new();
+        let mut len = 0.0;
+        for (text, weight) in [
+            (statement, STATEMENT_WEIGHT),
+            (action_text.as_str(), ACTION_WEIGHT),
+        ] {
+            for token in tokenize(text) {
+                *terms.entry(token.term).or_default() += weight;
+                len += weight;
+            }
+        }
+        for term in terms.keys() {
+            self.postings.entry(term.clone()).or_default().insert(id);
+        }
+        self.total_len += len;
+        self.proposals.insert(
+            id,
+            IndexedProposal {
+                dao_id: dao_id.to_string(),
+                statement: statement.to_string(),
+                action: action.clone(),
+                action_text,
+                terms,
+                len,
+            },
+        );
+    }
+    pub fn remove(&mut self, id: &Uuid) {
+        let indexed = match self.proposals.remove(id) {
+            Some(indexed) => indexed,
+            None => return,
+        };
+        for term in indexed.terms.keys() {
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        self.total_len -= indexed.len;
+    }
+    /// Brings the index up to date with the proposals of `store`, reindexing
+    /// the ones whose text changed since and dropping the ones gone.
+    pub fn refresh(&mut self, store: &ProposalStore) {
+        let gone: Vec<Uuid> = self
+            .proposals
+            .keys()
+            .filter(|id| store.get(id).is_none())
+            .copied()
+            .collect();
+        for id in gone {
+            self.remove(&id);
+        }
+        for (id, proposal) in store.iter() {
+            self.insert(*id, &proposal.dao_id, &proposal.statement, &proposal.action);
+        }
+    }
+    /// Ranks the proposals containing any term of the query, most relevant
+    /// first.
+    pub fn search(&self, query: &SearchQuery) -> anyhow::Result<Page<SearchHit>> {
+        query.validate()?;
+        let terms: HashSet<String> = tokenize(&query.q)
+            .into_iter()
+            .map(|token| token.term)
+            .collect();
+        let count = self.proposals.len() as f64;
+        let average_len = if self.proposals.is_empty() {
+            1.0
+        } else {
+            (self.total_len / count).max(1.0)
+        };
+
+        let mut scores = HashMap::<Uuid, f64>::new();
+        for term in &terms {
+            let ids = match self.postings.get(term) {
+                Some(ids) => ids,
+                None => continue,
+            };
+            let frequency = ids.len() as f64;
+            let idf = (1.0 + (count - frequency + 0.5) / (frequency + 0.5)).ln();
+            for id in ids {
+                let indexed = &self.proposals[id];
+                if query
+                    .dao_id
+                    .as_ref()
+                    .map_or(false, |dao_id| *dao_id != indexed.dao_id)
+                {
+                    continue;
+                }
+                let tf = indexed.terms[term];
+                let norm = K1 * (1.0 - B + B * indexed.len / average_len);
+                *scores.entry(*id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+        let mut ranked: Vec<(Uuid, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let total_items = ranked.len();
+        Ok(Page {
+            items: ranked
+                .into_iter()
+                .skip((query.page - 1).saturating_mul(query.per_page))
+                .take(query.per_page)
+                .map(|(id, score)| {
+                    let indexed = &self.proposals[&id];
+                    SearchHit {
+                        proposal_id: id,
+                        dao_id: indexed.dao_id.clone(),
+                        score,
+                        statement: snippet(&indexed.statement, &terms),
+                        action: snippet(&indexed.action_text, &terms),
+                    }
+                })
+                .collect(),
+            page: query.page,
+            per_page: query.per_page,
+            total_items,
+            total_pages: (total_items + query.per_page - 1) / query.per_page,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_ranks_and_highlights_matches() -> anyhow::Result<()> {
+        let mut index = SearchIndex::new();
+        let audit = Uuid::from_u128(1);
+        let grants = Uuid::from_u128(2);
+        let quorum = Uuid::from_u128(3);
+        index.insert(
+            audit,
+            "dao",
+            "Fund the security audit of the bridge",
+            &ProposalAction::TextOnly,
+        );
+        index.insert(
+            grants,
+            "dao",
+            "Renew the grants program",
+            &ProposalAction::TextOnly,
+        );
+        index.insert(
+            quorum,
+            "other",
+            "Lower the bar for the audit",
+            &ProposalAction::ParameterChange {
+                parameter: "quorum".to_string(),
+                value: "10".to_string(),
+            },
+        );
+
+        let page = index.search(&SearchQuery::new("Security AUDIT"))?;
+        assert_eq!(page.total_items, 2);
+        assert_eq!(page.items[0].proposal_id, audit);
+        let statement = page.items[0].statement.as_ref().unwrap();
+        let highlighted: Vec<&str> = statement
+            .highlights
+            .iter()
+            .map(|range| &statement.text[range.start..range.end])
+            .collect();
+        assert_eq!(highlighted, ["security", "audit"]);
+        assert!(page.items[0].action.is_none());
+
+        // Action payloads are searched too, and hits filtered by DAO
+        let page = index.search(&SearchQuery::new("quorum"))?;
+        assert_eq!(page.items[0].proposal_id, quorum);
+        assert!(page.items[0].statement.is_none());
+        assert!(page.items[0].action.is_some());
+        let mut query = SearchQuery::new("audit");
+        query.dao_id = Some("dao".to_string());
+        assert_eq!(index.search(&query)?.total_items, 1);
+
+        // Amended statements are reindexed
+        index.insert(
+            grants,
+            "dao",
+            "Renew the grants program after an audit",
+            &ProposalAction::TextOnly,
+        );
+        assert_eq!(index.search(&SearchQuery::new("audit"))?.total_items, 3);
+        index.remove(&audit);
+        assert_eq!(index.search(&SearchQuery::new("security"))?.total_items, 0);
+        assert!(SearchQuery::new(" - ").validate().is_err());
+        Ok(())
+    }
+}
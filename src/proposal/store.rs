@@ -0,0 +1,458 @@
+use std::collections::{hash_map, BTreeMap, BTreeSet, HashMap};
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::errors::QedError;
+
+use super::{Proposal, ProposalStatus};
+
+pub const DEFAULT_PER_PAGE: usize = 20;
+pub const MAX_PER_PAGE: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatusFilter {
+    /// Proposals that are neither finalized nor cancelled.
+    Open,
+    Finalized,
+    Cancelled,
+}
+
+impl ProposalStatusFilter {
+    pub fn matches(self, status: ProposalStatus) -> bool {
+        match self {
+            ProposalStatusFilter::Open => !status.is_terminal(),
+            ProposalStatusFilter::Finalized => status == ProposalStatus::Finalized,
+            ProposalStatusFilter::Cancelled => status == ProposalStatus::Cancelled,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum ProposalSort {
+    #[default]
+    #[serde(rename = "created_at")]
+    CreatedAt,
+    #[serde(rename = "-created_at")]
+    CreatedAtDesc,
+}
+
+fn default_page() -> usize {
+    1
+}
+fn default_per_page() -> usize {
+    DEFAULT_PER_PAGE
+}
+
+/// Filters and pagination for listing proposals; pages are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProposalQuery {
+    pub status: Option<ProposalStatusFilter>,
+    pub proposer_id: Option<u32>,
+    /// Only proposals of this DAO, or of the organization owning it
+    pub dao_id: Option<String>,
+    /// Only proposals filed under this category
+    pub category: Option<String>,
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    #[serde(default)]
+    pub sort: ProposalSort,
+}
+
+impl Default for ProposalQuery {
+    fn default() -> Self {
+        Self {
+            status: None,
+            proposer_id: None,
+            dao_id: None,
+            category: None,
+            page: default_page(),
+            per_page: default_per_page(),
+            sort: ProposalSort::default(),
+        }
+    }
+}
+
+impl ProposalQuery {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(self.page >= 1, "page must be at least 1");
+        ensure!(
+            self.per_page >= 1 && self.per_page <= MAX_PER_PAGE,
+            "per_page must be between 1 and {}",
+            MAX_PER_PAGE
+        );
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total_items: usize,
+    pub total_pages: usize,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            page: self.page,
+            per_page: self.per_page,
+            total_items: self.total_items,
+            total_pages: self.total_pages,
+        }
+    }
+}
+
+/// The hold of a finalization job on the proposal it moved to
+/// [`ProposalStatus::Finalizing`]. Proving runs without holding the store, so
+/// only the job whose claim is still current moves the proposal on afterwards,
+/// and another job cannot claim it meanwhile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FinalizationClaim {
+    pub proposal_id: Uuid,
+    /// Status the proposal goes back to if the job fails.
+    pub previous: ProposalStatus,
+    job: u64,
+}
+
+/// All proposals of the server, indexed by status and creation time.
+pub struct ProposalStore {
+    proposals: HashMap<Uuid, Proposal>,
+    by_status: BTreeMap<ProposalStatus, BTreeSet<(u64, Uuid)>>,
+    /// Proposals borrowed mutably since the last call to [`Self::clear_touched`].
+    touched: BTreeSet<Uuid>,
+    /// Job of the current [`FinalizationClaim`] of each proposal being finalized.
+    claims: HashMap<Uuid, u64>,
+    next_job: u64,
+}
+
+impl ProposalStore {
+    pub fn new() -> Self {
+        Self {
+            proposals: HashMap::new(),
+            by_status: BTreeMap::new(),
+            touched: BTreeSet::new(),
+            claims: HashMap::new(),
+            next_job: 0,
+        }
+    }
+    pub fn insert(&mut self, id: Uuid, proposal: Proposal) {
+        let key = (proposal.created_at, id);
+        let status = proposal.status;
+        self.claims.remove(&id);
+        if let Some(previous) = self.proposals.insert(id, proposal) {
+            self.unindex(previous.status, &(previous.created_at, id));
+        }
+        self.by_status.entry(status).or_default().insert(key);
+    }
+    fn unindex(&mut self, status: ProposalStatus, key: &(u64, Uuid)) {
+        if let Some(keys) = self.by_status.get_mut(&status) {
+            keys.remove(key);
+        }
+    }
+    pub fn remove(&mut self, id: &Uuid) -> Option<Proposal> {
+        let proposal = self.proposals.remove(id)?;
+        self.claims.remove(id);
+        self.unindex(proposal.status, &(proposal.created_at, *id));
+        Some(proposal)
+    }
+    pub fn get(&self, id: &Uuid) -> Option<&Proposal> {
+        self.proposals.get(id)
+    }
+    /// Note: use [`ProposalStore::set_status`] rather than [`Proposal::transition`]
+    /// through the returned reference, so the status index stays in sync.
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut Proposal> {
+        self.touched.insert(*id);
+        self.proposals.get_mut(id)
+    }
+    pub fn iter(&self) -> hash_map::Iter<'_, Uuid, Proposal> {
+        self.proposals.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.proposals.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.proposals.is_empty()
+    }
+    /// Moves a proposal to `status`, failing if it does not exist or the
+    /// lifecycle does not allow the transition.
+    pub fn set_status(&mut self, id: &Uuid, status: ProposalStatus) -> anyhow::Result<()> {
+        let proposal = self.get_mut(id).ok_or(QedError::ProposalNotFound(*id))?;
+        let previous = proposal.status;
+        if previous == ProposalStatus::Finalized {
+            return Err(QedError::AlreadyFinalized(*id).into());
+        }
+        if self.claims.contains_key(id) {
+            return Err(QedError::FinalizationClaimed(*id).into());
+        }
+        proposal.transition(status)?;
+        let key = (proposal.created_at, *id);
+        self.unindex(previous, &key);
+        self.by_status.entry(status).or_default().insert(key);
+        Ok(())
+    }
+    /// Moves a proposal from `expected` to [`ProposalStatus::Finalizing`] for a
+    /// new finalization job, failing if it moved on from `expected` or cannot be
+    /// finalized from there.
+    pub fn claim_finalization(
+        &mut self,
+        id: &Uuid,
+        expected: ProposalStatus,
+    ) -> Result<FinalizationClaim, QedError> {
+        let actual = self.get(id).ok_or(QedError::ProposalNotFound(*id))?.status;
+        if actual != expected || !actual.can_transition_to(ProposalStatus::Finalizing) {
+            return Err(QedError::StatusConflict {
+                id: *id,
+                expected,
+                actual,
+            });
+        }
+        // A proposal that can move to finalizing is neither finalized nor claimed
+        self.set_status(id, ProposalStatus::Finalizing).unwrap();
+        self.next_job += 1;
+        self.claims.insert(*id, self.next_job);
+        Ok(FinalizationClaim {
+            proposal_id: *id,
+            previous: expected,
+            job: self.next_job,
+        })
+    }
+    /// Fails unless `claim` is the current claim of its proposal, which it no
+    /// longer is once the proposal was recovered, replaced or removed.
+    pub fn check_claim(&self, claim: &FinalizationClaim) -> Result<(), QedError> {
+        let id = claim.proposal_id;
+        let actual = self.get(&id).ok_or(QedError::ProposalNotFound(id))?.status;
+        match self.claims.get(&id) {
+            Some(job) if *job == claim.job => Ok(()),
+            Some(_) => Err(QedError::FinalizationClaimed(id)),
+            None => Err(QedError::StatusConflict {
+                id,
+                expected: ProposalStatus::Finalizing,
+                actual,
+            }),
+        }
+    }
+    /// Moves the proposal of `claim` on to [`ProposalStatus::Finalized`].
+    pub fn complete_finalization(&mut self, claim: &FinalizationClaim) -> Result<(), QedError> {
+        self.release(claim, ProposalStatus::Finalized)
+    }
+    /// Moves the proposal of `claim` back to the status it was claimed from, for
+    /// votes to continue.
+    pub fn abandon_finalization(&mut self, claim: &FinalizationClaim) -> Result<(), QedError> {
+        self.release(claim, claim.previous)
+    }
+    fn release(
+        &mut self,
+        claim: &FinalizationClaim,
+        status: ProposalStatus,
+    ) -> Result<(), QedError> {
+        self.check_claim(claim)?;
+        self.claims.remove(&claim.proposal_id);
+        // Finalizing proposals move on to finalized or back to where they were claimed from
+        self.set_status(&claim.proposal_id, status).unwrap();
+        Ok(())
+    }
+    pub fn clear_touched(&mut self) {
+        self.touched.clear();
+    }
+    /// Recovers the proposals touched since [`Self::clear_touched`], after a
+    /// handler panicked while holding the store. Returns the ids of the
+    /// proposals that could not be recovered.
+    pub fn recover_touched(&mut self) -> Vec<Uuid> {
+        let mut failed = vec![];
+        for id in std::mem::take(&mut self.touched) {
+            let proposal = match self.proposals.get_mut(&id) {
+                Some(proposal) => proposal,
+                None => continue,
+            };
+            let previous = proposal.status;
+            let key = (proposal.created_at, id);
+            // The job finalizing the proposal, if any, finds its claim gone and leaves it be
+            self.claims.remove(&id);
+            warn!(proposal_id = %id, "Recovering proposal after a panicking handler");
+            if let Err(err) = proposal.recover() {
+                error!(proposal_id = %id, "Failed to recover proposal: {}", err);
+                failed.push(id);
+            }
+            let status = proposal.status;
+            if status != previous {
+                self.unindex(previous, &key);
+                self.by_status.entry(status).or_default().insert(key);
+            }
+        }
+        failed
+    }
+    /// Returns the ids of the proposals matching `query`.
+    pub fn query(&self, query: &ProposalQuery) -> anyhow::Result<Page<Uuid>> {
+        query.validate()?;
+        let mut keys: Vec<&(u64, Uuid)> = self
+            .by_status
+            .iter()
+            .filter(|(status, _)| query.status.map_or(true, |filter| filter.matches(**status)))
+            .flat_map(|(_, keys)| keys.iter())
+            .collect();
+        keys.sort();
+        if let Some(proposer_id) = query.proposer_id {
+            keys.retain(|(_, id)| self.proposals[id].proposer_id == proposer_id);
+        }
+        if let Some(dao_id) = &query.dao_id {
+            keys.retain(|(_, id)| self.proposals[id].dao_id == *dao_id);
+        }
+        if let Some(category) = &query.category {
+            keys.retain(|(_, id)| self.proposals[id].category.as_ref() == Some(category));
+        }
+        if query.sort == ProposalSort::CreatedAtDesc {
+            keys.reverse();
+        }
+
+        let total_items = keys.len();
+        Ok(Page {
+            items: keys
+                .into_iter()
+                .skip((query.page - 1).saturating_mul(query.per_page))
+                .take(query.per_page)
+                .map(|(_, id)| *id)
+                .collect(),
+            page: query.page,
+            per_page: query.per_page,
+            total_items,
+            total_pages: (total_items + query.per_page - 1) / query.per_page,
+        })
+    }
+}
+
+impl Default for ProposalStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::proposal::{rules::ProposalRules, Proposal};
+
+    use super::*;
+
+    #[test]
+    fn test_query_filters_and_paginates() -> anyhow::Result<()> {
+        let mut store = ProposalStore::new();
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            let mut proposal = Proposal::new(
+                format!("proposal {}", i),
+                (i % 2) as u32,
+                100 + i as u64,
+                ProposalRules::default(),
+            )?;
+            proposal.dao_id = format!("dao-{}", i % 2);
+            proposal.category = (i == 1).then(|| "treasury".to_string());
+            store.insert(*id, proposal);
+        }
+        store.set_status(&ids[1], ProposalStatus::Open)?;
+        store.set_status(&ids[1], ProposalStatus::Finalizing)?;
+        store.set_status(&ids[1], ProposalStatus::Finalized)?;
+        store.set_status(&ids[2], ProposalStatus::Cancelled)?;
+        assert!(store.set_status(&ids[2], ProposalStatus::Open).is_err());
+
+        let all = store.query(&ProposalQuery::default())?;
+        assert_eq!(all.items, ids);
+        assert_eq!(all.total_pages, 1);
+
+        let open = store.query(&ProposalQuery {
+            status: Some(ProposalStatusFilter::Open),
+            ..Default::default()
+        })?;
+        assert_eq!(open.items, vec![ids[0]]);
+
+        let cancelled = store.query(&ProposalQuery {
+            status: Some(ProposalStatusFilter::Cancelled),
+            ..Default::default()
+        })?;
+        assert_eq!(cancelled.items, vec![ids[2]]);
+
+        let by_proposer = store.query(&ProposalQuery {
+            proposer_id: Some(1),
+            ..Default::default()
+        })?;
+        assert_eq!(by_proposer.items, vec![ids[1]]);
+
+        let by_dao = store.query(&ProposalQuery {
+            dao_id: Some("dao-0".to_string()),
+            ..Default::default()
+        })?;
+        assert_eq!(by_dao.items, vec![ids[0], ids[2]]);
+
+        let by_category = store.query(&ProposalQuery {
+            category: Some("treasury".to_string()),
+            ..Default::default()
+        })?;
+        assert_eq!(by_category.items, vec![ids[1]]);
+
+        let second_page = store.query(&ProposalQuery {
+            page: 2,
+            per_page: 2,
+            sort: ProposalSort::CreatedAtDesc,
+            ..Default::default()
+        })?;
+        assert_eq!(second_page.items, vec![ids[0]]);
+        assert_eq!(second_page.total_items, 3);
+        assert_eq!(second_page.total_pages, 2);
+
+        assert!(store
+            .query(&ProposalQuery {
+                per_page: 0,
+                ..Default::default()
+            })
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_one_finalization_job_holds_a_proposal() -> anyhow::Result<()> {
+        let mut store = ProposalStore::new();
+        let id = Uuid::new_v4();
+        store.insert(
+            id,
+            Proposal::new("test".to_string(), 0, 0, ProposalRules::default())?,
+        );
+        let claim = store.claim_finalization(&id, ProposalStatus::Draft)?;
+        assert_eq!(
+            store.claim_finalization(&id, ProposalStatus::Draft),
+            Err(QedError::StatusConflict {
+                id,
+                expected: ProposalStatus::Draft,
+                actual: ProposalStatus::Finalizing,
+            })
+        );
+        assert!(store.set_status(&id, ProposalStatus::Open).is_err());
+        store.abandon_finalization(&claim)?;
+        assert_eq!(store.get(&id).unwrap().status, ProposalStatus::Draft);
+
+        // A recovery while the job proves hands the proposal to the next job
+        let stale = store.claim_finalization(&id, ProposalStatus::Draft)?;
+        store.clear_touched();
+        store.get_mut(&id);
+        assert!(store.recover_touched().is_empty());
+        let claim = store.claim_finalization(&id, ProposalStatus::Draft)?;
+        assert_eq!(
+            store.complete_finalization(&stale),
+            Err(QedError::FinalizationClaimed(id))
+        );
+        store.complete_finalization(&claim)?;
+        assert_eq!(store.get(&id).unwrap().status, ProposalStatus::Finalized);
+        assert!(store.check_claim(&claim).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! The tally of a proposal over time, for charting how votes came in.
+//!
+//! A [`TallyPoint`] is recorded whenever an accepted action changes the tally or
+//! the number of voters, at the time the action was accepted. Replaying the
+//! events of a proposal records the same points, as its actions are accepted
+//! again at the times they were recorded at.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::balance::accounts::Tally;
+
+/// The tally of a proposal right after an accepted action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TallyPoint {
+    /// Unix time the action was accepted at.
+    pub at: u64,
+    #[serde(flatten)]
+    pub tally: Tally,
+    /// Voters who had voted so far, encrypted ballots included.
+    pub voters: u64,
+}
+
+/// Keeps the last point of every `bucket_secs` long window since `created_at`,
+/// stamped with the end of its window, so long running proposals chart with as
+/// many points as windows. Points are returned as they are without a bucket.
+pub fn downsample(
+    points: &[TallyPoint],
+    created_at: u64,
+    bucket_secs: Option<u64>,
+) -> Vec<TallyPoint> {
+    let bucket_secs = match bucket_secs {
+        Some(bucket_secs) if bucket_secs > 0 => bucket_secs,
+        _ => return points.to_vec(),
+    };
+    let bucket = |point: &TallyPoint| point.at.saturating_sub(created_at) / bucket_secs;
+    let mut sampled: Vec<TallyPoint> = vec![];
+    for point in points {
+        let window_end =
+            created_at.saturating_add(bucket(point).saturating_add(1).saturating_mul(bucket_secs));
+        let point = TallyPoint {
+            at: window_end,
+            ..*point
+        };
+        match sampled.last_mut() {
+            Some(last) if last.at == window_end => *last = point,
+            _ => sampled.push(point),
+        }
+    }
+    sampled
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        balance::weight::Weight,
+        proposal::{rules::ProposalRules, Proposal},
+    };
+
+    use super::{downsample, TallyPoint};
+
+    #[test]
+    fn test_records_the_tally_after_each_vote() -> anyhow::Result<()> {
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            0,
+            100,
+            ProposalRules::default(),
+            vec![Weight::from(2), Weight::from(3), Weight::from(5)],
+        )?;
+        proposal.cast_vote(2, true, None, 110)?;
+        proposal.cast_vote(3, false, None, 130)?;
+        proposal.cast_vote(4, true, None, 175)?;
+        let points = &proposal.tally_history;
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[1].at, 130);
+        assert_eq!(points[1].tally.yes_votes, Weight::from(2));
+        assert_eq!(points[1].tally.no_votes, Weight::from(3));
+        assert_eq!(points[2].voters, 3);
+
+        // The last point of each minute, stamped with the end of the minute
+        let sampled = downsample(points, 100, Some(60));
+        assert_eq!(
+            sampled.iter().map(|point| point.at).collect::<Vec<_>>(),
+            vec![160, 220]
+        );
+        assert_eq!(
+            sampled[0],
+            TallyPoint {
+                at: 160,
+                ..points[1]
+            }
+        );
+        assert_eq!(downsample(points, 100, None), *points);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::balance::weight::Weight;
+
+use super::{
+    action::ProposalAction,
+    blinding::VoterBlinding,
+    encryption::{BallotCommittee, DecryptionShare, EncryptedBallot},
+    rules::ProposalRules,
+    Proposal,
+};
+
+/// A vote, delegation or vote commitment accepted on a proposal.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TranscriptAction {
+    Vote {
+        voter_id: u32,
+        is_yes: bool,
+        /// Opens the commitment of the voter on proposals with a commitment period.
+        #[serde_as(as = "Option<serde_with::hex::Hex>")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schema(value_type = Option<String>)]
+        salt: Option<Vec<u8>>,
+    },
+    /// A vote casting the weight of the voter across both options.
+    SplitVote {
+        voter_id: u32,
+        yes_votes: Weight,
+        no_votes: Weight,
+    },
+    Delegate {
+        voter_id: u32,
+        delegator_id: u32,
+    },
+    Revoke {
+        voter_id: u32,
+    },
+    /// A delegation moved back to the voter who made it.
+    Undelegate {
+        voter_id: u32,
+    },
+    Commit {
+        voter_id: u32,
+        #[serde_as(as = "serde_with::hex::Hex")]
+        #[schema(value_type = String)]
+        commitment: [u8; 32],
+    },
+    /// A vote encrypted to the committee of the proposal, see [`super::encryption`].
+    EncryptedVote {
+        voter_id: u32,
+        ballot: Box<EncryptedBallot>,
+    },
+    /// Partial decryption of the aggregate of the ballots by a member of the
+    /// committee, the share completing the threshold casting the decrypted tally.
+    Decrypt {
+        share: DecryptionShare,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TranscriptEvent {
+    /// Seconds after the creation of the proposal at which the action was accepted.
+    pub at_secs: u64,
+    #[serde(flatten)]
+    pub action: TranscriptAction,
+}
+
+/// Everything needed to replay a proposal from its creation: the electorate,
+/// the rules and the accepted actions in order.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Transcript {
+    /// Ties broken with a beacon depend on the proposal id, so a replay reuses it.
+    pub proposal_id: Uuid,
+    pub statement: String,
+    #[serde(default)]
+    pub action: ProposalAction,
+    pub proposer_id: u32,
+    pub rules: ProposalRules,
+    /// In leaf order, which on blinded proposals `blinding` places voters in.
+    pub voter_balances: Vec<Weight>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blinding: Option<VoterBlinding>,
+    /// Committee the votes are encrypted to, on proposals that take encrypted ballots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ballot_committee: Option<BallotCommittee>,
+    pub events: Vec<TranscriptEvent>,
+}
+
+impl Transcript {
+    pub fn from_proposal(proposal_id: Uuid, proposal: &Proposal) -> Self {
+        Self {
+            proposal_id,
+            statement: proposal.statement.clone(),
+            action: proposal.action.clone(),
+            proposer_id: proposal.proposer_id,
+            rules: proposal.rules.clone(),
+            voter_balances: proposal.storage.initial_balances().to_vec(),
+            blinding: proposal.blinding.clone(),
+            ballot_committee: proposal
+                .ballots
+                .as_ref()
+                .map(|ballot_box| ballot_box.committee.clone()),
+            events: proposal.transcript.clone(),
+        }
+    }
+}
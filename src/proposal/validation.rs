@@ -0,0 +1,395 @@
+//! Checks on the ids and statements a request refers to, so that bad input is
+//! rejected with a descriptive error before it reaches the balance tree.
+
+use std::collections::HashSet;
+
+use crate::{
+    balance::{accounts::VoterLeaf, weight::Weight},
+    did::Did,
+    errors::{ApiError, ApiErrorCode},
+};
+
+use super::Proposal;
+
+/// Longest statement a proposal can be created or amended with, in bytes.
+pub const MAX_STATEMENT_BYTES: usize = 4096;
+/// Longest category a proposal can be filed under, in bytes.
+pub const MAX_CATEGORY_BYTES: usize = 64;
+
+/// Fails unless `statement` has some text and is at most [`MAX_STATEMENT_BYTES`] long.
+pub fn validate_statement(statement: &str) -> Result<(), ApiError> {
+    if statement.trim().is_empty() {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidStatement,
+            "Statement is empty",
+        ));
+    }
+    if statement.len() > MAX_STATEMENT_BYTES {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidStatement,
+            format!(
+                "Statement is {} bytes long, at most {} are allowed",
+                statement.len(),
+                MAX_STATEMENT_BYTES
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless `category` is a non-empty run of lowercase letters, digits and
+/// dashes, at most [`MAX_CATEGORY_BYTES`] long.
+pub fn validate_category(category: &str) -> Result<(), ApiError> {
+    let valid = !category.is_empty()
+        && category.len() <= MAX_CATEGORY_BYTES
+        && category
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidQuery,
+            format!(
+                "Categories are 1 to {} lowercase letters, digits or dashes",
+                MAX_CATEGORY_BYTES
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless `dids` register a non-empty electorate with every key at most
+/// once. A `did:ethr` address counts once whatever network it is qualified with.
+pub fn validate_voter_dids(dids: &[Did]) -> Result<(), ApiError> {
+    if dids.is_empty() {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidDid,
+            "No voter DIDs to register",
+        ));
+    }
+    let mut seen = HashSet::new();
+    let duplicate = dids.iter().find(|did| {
+        let key = match did {
+            Did::Key(key) => key.to_bytes().to_vec(),
+            Did::Ethr { address, .. } => address.as_bytes().to_vec(),
+        };
+        !seen.insert(key)
+    });
+    match duplicate {
+        Some(did) => Err(ApiError::new(
+            ApiErrorCode::InvalidDid,
+            format!("{} is registered more than once", did),
+        )),
+        None => Ok(()),
+    }
+}
+
+impl Proposal {
+    /// The leaf of `voter_id`, failing if the id is reserved for a tally slot, lies
+    /// outside the balance tree or does not belong to a voter of the electorate.
+    /// On blinded proposals it is the leaf the voter was placed at, see
+    /// [`blinding`](super::blinding).
+    pub fn electorate_voter(&self, voter_id: u32) -> Result<VoterLeaf, ApiError> {
+        let voter = VoterLeaf::from_voter_id(voter_id)
+            .map_err(|err| ApiError::new(ApiErrorCode::InvalidVoter, err))?;
+        if voter.index() >= self.storage.tree.max_leaves() {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidVoter,
+                format!(
+                    "Voter id {} is outside the balance tree of {} leaves",
+                    voter_id,
+                    self.storage.tree.max_leaves()
+                ),
+            ));
+        }
+        if !self.storage.is_registered(voter) {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidVoter,
+                format!(
+                    "Voter id {} is not part of the electorate of {} voters",
+                    voter_id,
+                    self.storage.initial_balances().len()
+                ),
+            ));
+        }
+        match &self.blinding {
+            Some(blinding) => {
+                let position = voter.position();
+                let electorate_size = self.storage.initial_balances().len() as u64;
+                Ok(VoterLeaf::from_position(
+                    blinding.slot(position, electorate_size),
+                ))
+            }
+            None => Ok(voter),
+        }
+    }
+    /// The DID `voter_id` was registered with, if the electorate was registered by DID.
+    pub fn voter_did(&self, voter_id: u32) -> Result<Option<&Did>, ApiError> {
+        if self.voter_dids.is_empty() {
+            return Ok(None);
+        }
+        self.electorate_voter(voter_id)?;
+        let position = voter_id as u64 - VoterLeaf::from_position(0).index();
+        Ok(self.voter_dids.get(position as usize))
+    }
+    /// The nonce the next signed request of `voter_id` has to carry.
+    pub fn did_nonce(&self, voter_id: u32) -> u64 {
+        self.did_nonces.get(&voter_id).copied().unwrap_or(0)
+    }
+    /// Takes the nonce of a signed request of `voter_id` once it is applied, on
+    /// proposals whose electorate was registered by DID.
+    pub fn consume_did_nonce(&mut self, voter_id: u32) -> Result<(), ApiError> {
+        if self.voter_did(voter_id)?.is_some() {
+            *self.did_nonces.entry(voter_id).or_default() += 1;
+        }
+        Ok(())
+    }
+    /// Fails unless `signature`, hex encoded, signs `message` with the key the DID
+    /// of `voter_id` resolves to and the request carries `nonce`, the next nonce
+    /// of the voter, so a signed request is applied once. Passes without a
+    /// signature on proposals whose electorate was not registered by DID.
+    pub fn authenticate_voter(
+        &self,
+        voter_id: u32,
+        nonce: u64,
+        message: &[u8],
+        signature: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let did = match self.voter_did(voter_id)? {
+            Some(did) => did,
+            None => return Ok(()),
+        };
+        let expected = self.did_nonce(voter_id);
+        if nonce != expected {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidDidNonce,
+                format!(
+                    "The next signed request of voter {} carries nonce {}, not {}",
+                    voter_id, expected, nonce
+                ),
+            ));
+        }
+        let signature = signature.ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::InvalidDidSignature,
+                format!("Requests of {} must carry a did_signature", did),
+            )
+        })?;
+        hex::decode(signature.trim_start_matches("0x"))
+            .map_err(anyhow::Error::from)
+            .and_then(|signature| did.verify(message, &signature))
+            .map_err(|err| {
+                ApiError::new(
+                    ApiErrorCode::InvalidDidSignature,
+                    format!("Invalid signature of {}: {}", did, err),
+                )
+            })
+    }
+    /// The weight `voter` currently holds, failing if there is none to move or
+    /// less than the minimum transfer of the proposal.
+    pub(super) fn voting_weight(&self, voter: VoterLeaf) -> Result<Weight, ApiError> {
+        let weight = self.storage.get_balance(voter).unwrap();
+        if weight == Weight::ZERO {
+            return Err(ApiError::new(
+                ApiErrorCode::NoVotingWeight,
+                "Voter holds no voting weight on this proposal",
+            ));
+        }
+        if let Some(minimum) = self.rules.min_transfer.filter(|minimum| weight < *minimum) {
+            return Err(ApiError::new(
+                ApiErrorCode::BelowMinTransfer,
+                format!(
+                    "Voter holds {} votes, less than the minimum of {} a vote or delegation moves",
+                    weight, minimum
+                ),
+            ));
+        }
+        Ok(weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use uuid::Uuid;
+
+    use crate::{
+        balance::{
+            accounts::{VoteSplit, VoterLeaf},
+            storage::min_tree_height,
+            weight::Weight,
+        },
+        did::Did,
+        errors::ApiErrorCode,
+        nullifier::nullifier_set::NullifierSet,
+        proposal::{rules::ProposalRules, sanity::check_tree, Proposal, DEFAULT_ELECTORATE_SIZE},
+    };
+
+    use super::{validate_statement, validate_voter_dids, MAX_STATEMENT_BYTES};
+
+    #[test]
+    fn test_rejects_invalid_statements_and_voters() {
+        assert!(validate_statement("Fund the audit").is_ok());
+        assert!(validate_statement("  ").is_err());
+        assert!(validate_statement(&"a".repeat(MAX_STATEMENT_BYTES + 1)).is_err());
+
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1), Weight::from(0), Weight::from(1)],
+        )
+        .unwrap();
+        let code = |result: Result<(), crate::errors::ApiError>| result.unwrap_err().code;
+
+        assert_eq!(
+            code(proposal.cast_vote(1, true, None, 0)),
+            ApiErrorCode::InvalidVoter
+        );
+        assert_eq!(
+            code(proposal.cast_vote(5, true, None, 0)),
+            ApiErrorCode::InvalidVoter
+        );
+        assert_eq!(code(proposal.delegate(2, 5, 0)), ApiErrorCode::InvalidVoter);
+        assert_eq!(
+            code(proposal.cast_vote(3, true, None, 0)),
+            ApiErrorCode::NoVotingWeight
+        );
+        let split = VoteSplit {
+            yes_votes: Weight::from(1),
+            no_votes: Weight::from(1),
+        };
+        assert_eq!(
+            code(proposal.cast_split_vote(2, split, 0)),
+            ApiErrorCode::InvalidSplit
+        );
+        // Nothing was written to the tree by the rejected requests
+        assert!(proposal.updates.is_empty());
+
+        proposal.delegate(2, 4, 0).unwrap();
+        assert_eq!(
+            code(proposal.cast_vote(2, true, None, 0)),
+            ApiErrorCode::NoVotingWeight
+        );
+        proposal.cast_vote(4, true, None, 0).unwrap();
+        assert_eq!(proposal.storage.tally().unwrap().yes_votes, Weight::from(2));
+    }
+
+    #[test]
+    fn test_sizes_the_tree_to_the_electorate() {
+        assert_eq!(min_tree_height(0), 1);
+        assert_eq!(min_tree_height(2), 2);
+        assert_eq!(min_tree_height(3), 3);
+        assert_eq!(min_tree_height(DEFAULT_ELECTORATE_SIZE), 11);
+
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1); 6],
+        )
+        .unwrap();
+        assert_eq!(proposal.storage.tree_height(), 3);
+        proposal.cast_vote(7, true, None, 0).unwrap();
+        assert_eq!(
+            proposal.cast_vote(8, true, None, 0).unwrap_err().code,
+            ApiErrorCode::InvalidVoter
+        );
+    }
+
+    #[test]
+    fn test_voters_revoke_and_vote_again() {
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(2); 2],
+        )
+        .unwrap();
+        proposal.nullifiers = Some(NullifierSet::new(Uuid::nil(), 8));
+        let code = |result: Result<(), crate::errors::ApiError>| result.unwrap_err().code;
+        assert_eq!(code(proposal.revoke_vote(2, 0)), ApiErrorCode::NotRevocable);
+
+        let split = VoteSplit {
+            yes_votes: Weight::from(1),
+            no_votes: Weight::from(1),
+        };
+        proposal.cast_split_vote(2, split, 0).unwrap();
+        proposal.revoke_vote(2, 1).unwrap();
+        assert_eq!(code(proposal.revoke_vote(2, 1)), ApiErrorCode::NotRevocable);
+        assert!(!proposal.voted.contains(&VoterLeaf::from_position(0)));
+        proposal.cast_vote(2, true, None, 2).unwrap();
+        let tally = proposal.storage.tally().unwrap();
+        assert_eq!(
+            (tally.yes_votes, tally.no_votes),
+            (Weight::from(2), Weight::ZERO)
+        );
+        assert_eq!(
+            check_tree(&proposal.storage, &proposal.updates).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_authenticates_voters_by_did() {
+        let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+        let did = Did::Key(signing_key.verifying_key());
+        let ethr: Did = "did:ethr:0x52908400098527886e0f7030069857d2e4169ee7"
+            .parse()
+            .unwrap();
+        let ethr_on_mainnet: Did = "did:ethr:mainnet:0x52908400098527886E0F7030069857D2E4169EE7"
+            .parse()
+            .unwrap();
+        assert!(validate_voter_dids(&[]).is_err());
+        assert!(validate_voter_dids(&[did.clone(), ethr.clone()]).is_ok());
+        assert!(validate_voter_dids(&[ethr.clone(), did.clone(), ethr_on_mainnet]).is_err());
+
+        let mut proposal = Proposal::with_voter_balances(
+            "test".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1); 2],
+        )
+        .unwrap();
+        // Without registered DIDs requests need no signature
+        assert!(proposal.authenticate_voter(2, 0, b"vote", None).is_ok());
+
+        proposal.voter_dids = vec![ethr, did.clone()];
+        assert_eq!(proposal.voter_did(3).unwrap(), Some(&did));
+        let signature = hex::encode(signing_key.sign(b"vote").to_bytes());
+        assert!(proposal
+            .authenticate_voter(3, 0, b"vote", Some(&signature))
+            .is_ok());
+        let code = |result: Result<(), crate::errors::ApiError>| result.unwrap_err().code;
+        assert_eq!(
+            code(proposal.authenticate_voter(3, 0, b"vote", None)),
+            ApiErrorCode::InvalidDidSignature
+        );
+        assert_eq!(
+            code(proposal.authenticate_voter(2, 0, b"vote", Some(&signature))),
+            ApiErrorCode::InvalidDidSignature
+        );
+        assert_eq!(
+            code(proposal.authenticate_voter(4, 0, b"vote", Some(&signature))),
+            ApiErrorCode::InvalidVoter
+        );
+
+        // A request applied once cannot be replayed
+        proposal.consume_did_nonce(3).unwrap();
+        assert_eq!(proposal.did_nonce(3), 1);
+        assert_eq!(
+            code(proposal.authenticate_voter(3, 0, b"vote", Some(&signature))),
+            ApiErrorCode::InvalidDidNonce
+        );
+        assert!(proposal
+            .authenticate_voter(3, 1, b"vote", Some(&signature))
+            .is_ok());
+        // Voters without a registered DID keep no nonces
+        proposal.voter_dids.truncate(1);
+        proposal.consume_did_nonce(3).unwrap();
+        assert_eq!(proposal.did_nonce(3), 1);
+    }
+}
@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use plonky2::field::goldilocks_field::GoldilocksField;
+
+use crate::{
+    balance::{accounts::Tally, treasury::DepositStatus, weight::Weight},
+    common::WHashOut,
+};
+
+use super::{
+    action::ProposalAction,
+    approval::FinalizerPolicy,
+    content::{ContentCheck, StatementContent},
+    encryption::BallotCommittee,
+    rules::{ProposalOutcome, TiePolicy},
+    Proposal, ProposalPhase, ProposalStatus,
+};
+
+/// What the caller of a request can do on a proposal.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CallerView {
+    pub voter_id: u32,
+    pub eligible: bool,
+    pub has_voted: bool,
+    pub has_committed: bool,
+}
+
+/// The JSON representation of a proposal, with fields computed at request time so
+/// a frontend can render a proposal from a single response.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ProposalView {
+    pub id: Uuid,
+    pub dao_id: String,
+    /// Category the proposal was filed under, if any.
+    pub category: Option<String>,
+    pub statement: String,
+    /// External document the statement refers to, if any.
+    pub content: Option<StatementContent>,
+    /// The last check of `content`, on servers that fetch documents.
+    pub content_check: Option<ContentCheck>,
+    pub action: ProposalAction,
+    pub proposer_id: u32,
+    pub created_at: u64,
+    pub deadline: Option<u64>,
+    /// End of the commitment period, for proposals that take vote commitments.
+    pub commit_deadline: Option<u64>,
+    pub status: ProposalStatus,
+    pub phase: ProposalPhase,
+    pub seconds_remaining: Option<u64>,
+    pub quorum: Option<Weight>,
+    pub quorum_progress_percent: Option<f64>,
+    pub tie_policy: TiePolicy,
+    /// Block whose hash breaks ties, under the random-with-beacon tie policy.
+    pub beacon_block: Option<u64>,
+    /// Root of the seeded electorate, which membership proofs are checked against.
+    #[schema(value_type = String)]
+    pub electorate_root: WHashOut<GoldilocksField>,
+    /// Hex encoded commitment to the key placing voters at their leaves, on
+    /// proposals whose voter indices are blinded, see [`super::blinding`].
+    pub blinding_commitment: Option<String>,
+    pub is_finalized: bool,
+    /// Only revealed once the proposal is finalized.
+    pub tally: Option<Tally>,
+    pub result: Option<ProposalOutcome>,
+    /// What became of the deposit of the proposer, on servers that require one.
+    pub deposit: Option<DepositStatus>,
+    /// Proposals this one can only pass along with.
+    pub depends_on: Vec<Uuid>,
+    /// The tied proposal this one reopened voting on, under the revote tie policy.
+    pub revote_of: Option<Uuid>,
+    /// The round reopening voting on this proposal, once it tied under the revote
+    /// tie policy.
+    pub revote: Option<Uuid>,
+    /// Whether voting locks the tokens of voters until the proposal resolves.
+    pub locks_tokens: bool,
+    /// Finalizers a threshold of whom finalizes the proposal, if not its proposer.
+    pub finalizers: Option<FinalizerPolicy>,
+    /// Finalizers who approved finalizing the proposal so far.
+    pub approved_by: Vec<u32>,
+    /// Committee votes are encrypted to, on proposals that take encrypted ballots.
+    pub ballot_committee: Option<BallotCommittee>,
+    pub caller: Option<CallerView>,
+}
+
+impl ProposalView {
+    pub fn new(
+        id: Uuid,
+        proposal: &Proposal,
+        now: u64,
+        caller_voter_id: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        let tally = proposal.storage.tally()?;
+        // Weights are at most 63 bits wide, so their sum fits
+        let cast_weight = tally.yes_votes.get() + tally.no_votes.get();
+        let quorum_progress_percent = proposal.rules.quorum.map(|quorum| {
+            if quorum == Weight::ZERO {
+                100.0
+            } else {
+                (cast_weight as f64 * 100.0 / quorum.get() as f64).min(100.0)
+            }
+        });
+        let caller = match caller_voter_id {
+            Some(voter_id) => Some(match proposal.electorate_voter(voter_id) {
+                Ok(voter) => {
+                    let has_voted = proposal.voted.contains(&voter);
+                    CallerView {
+                        voter_id,
+                        // Compacted proposals are finalized, and no one can vote on them
+                        eligible: has_voted
+                            || (!proposal.storage.is_compacted()
+                                && proposal.storage.get_balance(voter)? > Weight::ZERO),
+                        has_voted,
+                        has_committed: proposal.commitments.contains_key(&voter),
+                    }
+                }
+                Err(_) => CallerView {
+                    voter_id,
+                    eligible: false,
+                    has_voted: false,
+                    has_committed: false,
+                },
+            }),
+            None => None,
+        };
+        let finalized_tally = if proposal.is_finalized() {
+            Some(tally)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            id,
+            dao_id: proposal.dao_id.clone(),
+            category: proposal.category.clone(),
+            statement: proposal.statement.clone(),
+            content: proposal.content.clone(),
+            content_check: proposal.content_check,
+            action: proposal.action.clone(),
+            proposer_id: proposal.proposer_id,
+            created_at: proposal.created_at,
+            deadline: proposal.deadline(),
+            commit_deadline: proposal.commit_deadline(),
+            status: proposal.status,
+            phase: proposal.phase(now),
+            seconds_remaining: proposal
+                .deadline()
+                .map(|deadline| deadline.saturating_sub(now)),
+            quorum: proposal.rules.quorum,
+            quorum_progress_percent,
+            tie_policy: proposal.rules.tie_policy,
+            beacon_block: proposal.rules.beacon_block,
+            electorate_root: proposal.storage.initial_root(),
+            blinding_commitment: proposal
+                .blinding
+                .as_ref()
+                .map(|blinding| hex::encode(blinding.commitment())),
+            is_finalized: proposal.is_finalized(),
+            tally: finalized_tally,
+            result: proposal
+                .certificate
+                .as_ref()
+                .map(|certificate| certificate.outcome),
+            deposit: proposal.deposit.as_ref().map(|deposit| deposit.status),
+            depends_on: proposal.depends_on.clone(),
+            revote_of: proposal.revote_of,
+            revote: proposal
+                .certificate
+                .as_ref()
+                .and_then(|certificate| certificate.revote),
+            locks_tokens: proposal.locks_tokens,
+            finalizers: proposal.finalizers.clone(),
+            approved_by: proposal
+                .approvals
+                .iter()
+                .map(|approval| approval.finalizer_id)
+                .collect(),
+            ballot_committee: proposal
+                .ballots
+                .as_ref()
+                .map(|ballot_box| ballot_box.committee.clone()),
+            caller,
+        })
+    }
+}
@@ -0,0 +1,779 @@
+//! Typed client of the HTTP API, following the OpenAPI document the server serves
+//! at `/openapi.json` and sharing its request and response types from [`crate::api`].
+//!
+//! Requests the server rejects fail with an [`ApiError`], which callers can get
+//! back with `err.downcast_ref::<ApiError>()` to match on its code.
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use qed_verifier::{ExpectedInputs, VerifierData};
+use reqwest::{
+    header::{HeaderMap, AUTHORIZATION, CONTENT_RANGE, ETAG, IF_RANGE, RANGE},
+    Client, RequestBuilder, StatusCode,
+};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        ActionResponse, AmendQuery, CancelQuery, CategoryPolicyQuery, CommitQuery,
+        CreateOrganizationQuery, CycleFinalizeQuery, DaoUsageResponse, DelegateBatchQuery,
+        DelegateQuery, DepositReceipt, FinalizationPreview, FinalizeApprovalOutcome,
+        FinalizeApprovalQuery, FinalizeDryRunQuery, FinalizeDryRunResponse, FinalizeQuery,
+        FinalizeResponse, FundsCreditQuery, IssueKeyQuery, IssuedKeyResponse, LeafProofQuery,
+        LeafProofResponse, PauseQuery, PayoutReceipt, ProposalHistoryQuery,
+        ProposalHistoryResponse, ProposeQuery, RegisterQuery, RelayedVoteQuery, RestoreResponse,
+        ResultRootResponse, RevokeQuery, RotateKeyQuery, ScopedDelegateQuery, ScopedRevokeQuery,
+        TallyHistoryQuery, TallyHistoryResponse, TokenAccount, TokenCreditQuery, TreasuryAccount,
+        TreasuryCreditQuery, TreeDiffResponse, TreeHealthResponse, VoteQuery, VotersQuery,
+        VotingPauseQuery, WebhookDeliveriesQuery, WebhooksQuery,
+    },
+    audit::AuditEntry,
+    auth::ApiKeyView,
+    balance::funds::FundBalance,
+    chain::token_snapshot::TokenSnapshot,
+    circuits::registry::CircuitRecord,
+    did::{Did, DidDocument},
+    errors::{ApiError, ErrorCatalogEntry},
+    proof::{
+        attestation::ResultAttestation, certificate::FinalizationCertificate, codec::ProofEnvelope,
+        cycle::CycleCertificate, membership::MembershipProof, results::ResultInclusionProof,
+    },
+    proposal::{
+        archive::open_archive,
+        blinding::BlindingReveal,
+        delegation::VotingPower,
+        encryption::{BallotBoxView, DecryptionShare},
+        metadata::{MetadataQuery, ProposalMetadata},
+        org::{CategoryPolicy, OrganizationView, VoterRegistration},
+        scoped_delegation::ScopedDelegation,
+        search::{SearchHit, SearchQuery},
+        store::{Page, ProposalQuery},
+        transcript::Transcript,
+        view::ProposalView,
+    },
+    snapshot::StateSnapshot,
+    utils::pause::PauseState,
+    webhook::WebhookDelivery,
+};
+
+/// Turns the body of a response into `T`, or into the [`ApiError`] it carries
+/// if the request was rejected.
+fn decode_response<T: DeserializeOwned>(status: StatusCode, body: &[u8]) -> anyhow::Result<T> {
+    if status.is_success() {
+        return serde_json::from_slice(body)
+            .map_err(|err| anyhow!("Unexpected response body: {}", err));
+    }
+    match serde_json::from_slice::<ApiError>(body) {
+        Ok(error) => Err(error.into()),
+        Err(_) => Err(anyhow!(
+            "Request failed with status {}: {}",
+            status,
+            String::from_utf8_lossy(body)
+        )),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QedClient {
+    base_url: String,
+    http: Client,
+    /// Sent as `X-Voter-Id`, for the caller view of proposals.
+    voter_id: Option<u32>,
+    /// Sent as a bearer token, for servers enforcing roles.
+    api_key: Option<String>,
+}
+
+impl QedClient {
+    /// A client of the server at `base_url`, e.g. `http://127.0.0.1:8080`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: Client::new(),
+            voter_id: None,
+            api_key: None,
+        }
+    }
+    /// Describes proposals from the point of view of `voter_id`.
+    pub fn with_voter_id(mut self, voter_id: u32) -> Self {
+        self.voter_id = Some(voter_id);
+        self
+    }
+    /// Authenticates requests with the secret of an API key. Admin methods send the
+    /// token they are given instead.
+    pub fn with_api_key(mut self, secret: impl Into<String>) -> Self {
+        self.api_key = Some(secret.into());
+        self
+    }
+    fn get(&self, path: &str) -> RequestBuilder {
+        let request = self.http.get(format!("{}{}", self.base_url, path));
+        match self.voter_id {
+            Some(voter_id) => request.header("X-Voter-Id", voter_id),
+            None => request,
+        }
+    }
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.http.post(format!("{}{}", self.base_url, path))
+    }
+    fn delete(&self, path: &str) -> RequestBuilder {
+        self.http.delete(format!("{}{}", self.base_url, path))
+    }
+    async fn execute(
+        &self,
+        request: RequestBuilder,
+    ) -> anyhow::Result<(StatusCode, HeaderMap, Vec<u8>)> {
+        let mut request = request.build()?;
+        if let Some(api_key) = &self.api_key {
+            if !request.headers().contains_key(AUTHORIZATION) {
+                request
+                    .headers_mut()
+                    .insert(AUTHORIZATION, format!("Bearer {}", api_key).parse()?);
+            }
+        }
+        let response = self.http.execute(request).await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        Ok((status, headers, body.to_vec()))
+    }
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> anyhow::Result<T> {
+        let (status, _, body) = self.execute(request).await?;
+        decode_response(status, &body)
+    }
+
+    pub async fn list_proposals(&self, query: &ProposalQuery) -> anyhow::Result<Vec<ProposalView>> {
+        self.send(self.get("/").query(query)).await
+    }
+    /// Searches the proposal metadata the server mirrors into its SQL database.
+    pub async fn search_proposals(
+        &self,
+        query: &MetadataQuery,
+    ) -> anyhow::Result<Page<ProposalMetadata>> {
+        self.send(self.get("/proposals/search").query(query)).await
+    }
+    /// Searches the statements and action payloads of proposals, most relevant first.
+    pub async fn search(&self, query: &SearchQuery) -> anyhow::Result<Page<SearchHit>> {
+        self.send(self.get("/search").query(query)).await
+    }
+    pub async fn get_proposal(&self, id: Uuid) -> anyhow::Result<ProposalView> {
+        self.send(self.get(&format!("/proposal/{}", id))).await
+    }
+    pub async fn propose(&self, query: &ProposeQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/propose").json(query)).await
+    }
+    pub async fn vote(&self, query: &VoteQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/vote").json(query)).await
+    }
+    /// Submits a vote its voter signed offline as typed data, on their behalf.
+    pub async fn vote_relayed(&self, query: &RelayedVoteQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/vote/relayed").json(query)).await
+    }
+    /// Moves the weight the voter cast back to them, so they can vote again.
+    pub async fn revoke(&self, query: &RevokeQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/vote/revoke").json(query)).await
+    }
+    pub async fn commit(&self, query: &CommitQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/commit").json(query)).await
+    }
+    pub async fn delegate(&self, query: &DelegateQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/delegate").json(query)).await
+    }
+    /// Applies the delegations of `query` all or none: a rejected batch leaves the
+    /// proposal as it was, with the error of the first delegation that failed.
+    pub async fn delegate_batch(
+        &self,
+        query: &DelegateBatchQuery,
+    ) -> anyhow::Result<ActionResponse> {
+        self.send(self.post("/delegate/batch").json(query)).await
+    }
+    /// Delegates the weight of a voter on every proposal in a scope, see
+    /// [`ScopedDelegation`].
+    pub async fn delegate_scoped(
+        &self,
+        query: &ScopedDelegateQuery,
+    ) -> anyhow::Result<ScopedDelegation> {
+        self.send(self.post("/delegations").json(query)).await
+    }
+    pub async fn scoped_delegations(&self, voter_id: u32) -> anyhow::Result<Vec<ScopedDelegation>> {
+        self.send(self.get(&format!("/delegations/{}", voter_id)))
+            .await
+    }
+    /// Ends a scoped delegation, moving the weight back to the voter shortly after.
+    pub async fn revoke_scoped_delegation(
+        &self,
+        id: Uuid,
+        query: &ScopedRevokeQuery,
+    ) -> anyhow::Result<ScopedDelegation> {
+        self.send(
+            self.post(&format!("/delegations/{}/revoke", id))
+                .json(query),
+        )
+        .await
+    }
+    pub async fn cancel(&self, id: Uuid, query: &CancelQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post(&format!("/proposal/{}/cancel", id)).json(query))
+            .await
+    }
+    pub async fn amend(&self, id: Uuid, query: &AmendQuery) -> anyhow::Result<ActionResponse> {
+        self.send(self.post(&format!("/proposal/{}/amend", id)).json(query))
+            .await
+    }
+    /// Proves and finalizes a proposal, which can take a while for large electorates.
+    pub async fn finalize(&self, query: &FinalizeQuery) -> anyhow::Result<FinalizeResponse> {
+        self.send(self.post("/finalize").json(query)).await
+    }
+    /// Generates the witness of the finalization of proposal `id` without proving,
+    /// see [`FinalizeDryRunResponse`].
+    pub async fn finalize_dry_run(
+        &self,
+        id: Uuid,
+        query: &FinalizeDryRunQuery,
+    ) -> anyhow::Result<FinalizeDryRunResponse> {
+        self.send(
+            self.post(&format!("/proposal/{}/finalize/dry-run", id))
+                .json(query),
+        )
+        .await
+    }
+    /// Approves finalizing a proposal with finalizers, which finalizes it once the
+    /// approval meets the threshold.
+    pub async fn approve_finalization(
+        &self,
+        query: &FinalizeApprovalQuery,
+    ) -> anyhow::Result<FinalizeApprovalOutcome> {
+        self.send(self.post("/finalize/approve").json(query)).await
+    }
+    pub async fn finalize_cycle(
+        &self,
+        query: &CycleFinalizeQuery,
+    ) -> anyhow::Result<CycleCertificate> {
+        self.send(self.post("/cycle/finalize").json(query)).await
+    }
+    pub async fn preview(&self, id: Uuid) -> anyhow::Result<FinalizationPreview> {
+        self.send(self.get(&format!("/proposal/{}/preview", id)))
+            .await
+    }
+    pub async fn get_electorate(&self, id: Uuid) -> anyhow::Result<TokenSnapshot> {
+        self.send(self.get(&format!("/proposal/{}/electorate", id)))
+            .await
+    }
+    pub async fn get_membership(&self, id: Uuid, voter_id: u32) -> anyhow::Result<MembershipProof> {
+        self.send(self.get(&format!("/proposal/{}/membership/{}", id, voter_id)))
+            .await
+    }
+    pub async fn get_voting_power(&self, id: Uuid, voter_id: u32) -> anyhow::Result<VotingPower> {
+        self.send(self.get(&format!("/proposal/{}/voting-power/{}", id, voter_id)))
+            .await
+    }
+    pub async fn get_blinding(&self, id: Uuid) -> anyhow::Result<BlindingReveal> {
+        self.send(self.get(&format!("/proposal/{}/blinding", id)))
+            .await
+    }
+    pub async fn get_ballots(&self, id: Uuid) -> anyhow::Result<BallotBoxView> {
+        self.send(self.get(&format!("/proposal/{}/ballots", id)))
+            .await
+    }
+    /// Shares the decryption of the ballots of a proposal by a member of its committee,
+    /// see `proposal::encryption::decrypt_share`.
+    pub async fn share_decryption(
+        &self,
+        id: Uuid,
+        share: &DecryptionShare,
+    ) -> anyhow::Result<BallotBoxView> {
+        self.send(self.post(&format!("/proposal/{}/decrypt", id)).json(share))
+            .await
+    }
+    pub async fn get_leaf_proof(
+        &self,
+        id: Uuid,
+        index: u64,
+        query: &LeafProofQuery,
+    ) -> anyhow::Result<LeafProofResponse> {
+        self.send(
+            self.get(&format!("/proposal/{}/leaf/{}/proof", id, index))
+                .query(query),
+        )
+        .await
+    }
+    /// The root of the tree committing to the results of finalized proposals.
+    pub async fn get_result_root(&self) -> anyhow::Result<ResultRootResponse> {
+        self.send(self.get("/results/root")).await
+    }
+    /// Proves the result of proposal `id` against the current root of the result
+    /// tree, see [`ResultInclusionProof::verify`].
+    pub async fn get_result_proof(&self, id: Uuid) -> anyhow::Result<ResultInclusionProof> {
+        self.send(self.get(&format!("/results/{}/proof", id))).await
+    }
+    pub async fn get_tree_diff(&self, id: Uuid) -> anyhow::Result<TreeDiffResponse> {
+        self.send(self.get(&format!("/proposal/{}/diff", id))).await
+    }
+    pub async fn get_proof(&self, id: Uuid) -> anyhow::Result<ProofEnvelope> {
+        self.send(self.get(&format!("/proposal/{}/proof", id)))
+            .await
+    }
+    /// Downloads the proof envelope of a finalized proposal in ranges of
+    /// `chunk_size` bytes, so that a failed request only loses its range, which is
+    /// asked for again up to `retries` times in a row. Starts over should the
+    /// envelope change midway, and checks the reassembled envelope against the
+    /// SHA-256 the server names it by.
+    pub async fn download_proof(
+        &self,
+        id: Uuid,
+        chunk_size: u64,
+        retries: u32,
+    ) -> anyhow::Result<ProofEnvelope> {
+        anyhow::ensure!(chunk_size > 0, "The chunk size must be positive");
+        let path = format!("/proposal/{}/proof", id);
+        let mut bytes = Vec::new();
+        let mut total = None;
+        let mut etag: Option<String> = None;
+        let mut failures = 0;
+        while total.map_or(true, |total| (bytes.len() as u64) < total) {
+            let start = bytes.len() as u64;
+            let mut request = self
+                .get(&path)
+                .header(RANGE, format!("bytes={}-{}", start, start + chunk_size - 1));
+            if let Some(etag) = &etag {
+                request = request.header(IF_RANGE, etag);
+            }
+            let (status, headers, body) = match self.execute(request).await {
+                Ok(response) => response,
+                Err(_) if failures < retries => {
+                    failures += 1;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            failures = 0;
+            match status {
+                StatusCode::PARTIAL_CONTENT => {
+                    let range_total = headers
+                        .get(CONTENT_RANGE)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|range| range.rsplit_once('/'))
+                        .and_then(|(_, total)| total.parse::<u64>().ok())
+                        .ok_or_else(|| anyhow!("Partial response without a Content-Range"))?;
+                    anyhow::ensure!(!body.is_empty(), "Empty range of the proof envelope");
+                    bytes.extend_from_slice(&body);
+                    total = Some(range_total);
+                }
+                // The whole envelope, because ranges are not supported or it changed
+                StatusCode::OK => {
+                    bytes = body;
+                    total = Some(bytes.len() as u64);
+                }
+                status => return decode_response(status, &body),
+            }
+            etag = headers
+                .get(ETAG)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+        }
+        if let Some(etag) = &etag {
+            let digest = hex::encode(Sha256::digest(&bytes));
+            anyhow::ensure!(
+                etag.trim_matches('"') == digest,
+                "The downloaded proof envelope does not match its ETag {}",
+                etag
+            );
+        }
+        serde_json::from_slice(&bytes).map_err(|err| anyhow!("Unexpected response body: {}", err))
+    }
+    /// Downloads the tar archive of a finalized proposal, checked against the
+    /// hashes its manifest lists.
+    pub async fn get_archive(&self, id: Uuid) -> anyhow::Result<Vec<u8>> {
+        let (status, _, body) = self
+            .execute(self.get(&format!("/proposal/{}/archive", id)))
+            .await?;
+        if !status.is_success() {
+            return decode_response(status, &body);
+        }
+        open_archive(&body)?;
+        Ok(body)
+    }
+    /// Lists the circuits the server built, to check the `common_data_hash` of a
+    /// proof envelope against the verifier data of its circuit.
+    pub async fn list_circuits(&self) -> anyhow::Result<Vec<CircuitRecord>> {
+        self.send(self.get("/circuits")).await
+    }
+    /// Fetches the verifier data of a circuit a stored proof was produced with, for
+    /// [`qed_verifier::verify`]. Its fingerprint should be pinned by the caller.
+    pub async fn get_circuit_verifier(&self, circuit_id: &str) -> anyhow::Result<VerifierData> {
+        self.send(self.get(&format!("/circuits/{}/verifier", circuit_id)))
+            .await
+    }
+    pub async fn get_certificate(&self, id: Uuid) -> anyhow::Result<FinalizationCertificate> {
+        self.send(self.get(&format!("/proposal/{}/certificate", id)))
+            .await
+    }
+    /// Fetches the public inputs the finalization proof of a proposal exposes, as
+    /// [`qed_verifier::verify`] checks them.
+    pub async fn get_public_inputs(&self, id: Uuid) -> anyhow::Result<ExpectedInputs> {
+        self.send(self.get(&format!("/proposal/{}/public-inputs", id)))
+            .await
+    }
+    /// Fetches the result attestation of a finalized proposal, which callers should
+    /// check with [`ResultAttestation::verify`] and against the key they trust.
+    pub async fn get_attestation(&self, id: Uuid) -> anyhow::Result<ResultAttestation> {
+        self.send(self.get(&format!("/proposal/{}/attestation", id)))
+            .await
+    }
+    pub async fn get_audit(&self, id: Uuid) -> anyhow::Result<Vec<AuditEntry>> {
+        self.send(self.get(&format!("/proposal/{}/audit", id)))
+            .await
+    }
+    pub async fn get_transcript(&self, id: Uuid) -> anyhow::Result<Transcript> {
+        self.send(self.get(&format!("/proposal/{}/transcript", id)))
+            .await
+    }
+    /// The proposal as it was at unix time `at`, replayed from its events.
+    pub async fn get_history(
+        &self,
+        id: Uuid,
+        at: Option<u64>,
+    ) -> anyhow::Result<ProposalHistoryResponse> {
+        let query = ProposalHistoryQuery { at };
+        self.send(self.get(&format!("/proposal/{}/history", id)).query(&query))
+            .await
+    }
+    /// The tally of the proposal over time, sampled every `bucket_secs` if given.
+    pub async fn get_tally_history(
+        &self,
+        id: Uuid,
+        bucket_secs: Option<u64>,
+    ) -> anyhow::Result<TallyHistoryResponse> {
+        let query = TallyHistoryQuery { bucket_secs };
+        self.send(
+            self.get(&format!("/proposal/{}/tally/history", id))
+                .query(&query),
+        )
+        .await
+    }
+    pub async fn get_dao_usage(&self, dao_id: &str) -> anyhow::Result<DaoUsageResponse> {
+        self.send(self.get(&format!("/dao/{}/usage", dao_id))).await
+    }
+    pub async fn get_tree_health(&self) -> anyhow::Result<TreeHealthResponse> {
+        self.send(self.get("/health/tree")).await
+    }
+    /// Exports the whole state of the server, authorized by its admin token.
+    pub async fn get_snapshot(&self, admin_token: &str) -> anyhow::Result<StateSnapshot> {
+        self.send(self.get("/admin/snapshot").bearer_auth(admin_token))
+            .await
+    }
+    /// Restores a snapshot into a server that holds no proposals yet.
+    pub async fn restore(
+        &self,
+        admin_token: &str,
+        snapshot: &StateSnapshot,
+    ) -> anyhow::Result<RestoreResponse> {
+        self.send(
+            self.post("/admin/restore")
+                .bearer_auth(admin_token)
+                .json(snapshot),
+        )
+        .await
+    }
+    pub async fn get_deposit(&self, id: Uuid) -> anyhow::Result<DepositReceipt> {
+        self.send(self.get(&format!("/proposal/{}/deposit", id)))
+            .await
+    }
+    pub async fn get_treasury_account(&self, proposer_id: u32) -> anyhow::Result<TreasuryAccount> {
+        self.send(self.get(&format!("/treasury/{}", proposer_id)))
+            .await
+    }
+    /// Credits the treasury account of a proposer, authorized by the admin token.
+    pub async fn credit_treasury(
+        &self,
+        admin_token: &str,
+        query: &TreasuryCreditQuery,
+    ) -> anyhow::Result<TreasuryAccount> {
+        self.send(
+            self.post("/admin/treasury/credit")
+                .bearer_auth(admin_token)
+                .json(query),
+        )
+        .await
+    }
+    pub async fn get_dao_funds(&self, dao_id: &str) -> anyhow::Result<Vec<FundBalance>> {
+        self.send(self.get(&format!("/dao/{}/funds", dao_id))).await
+    }
+    pub async fn get_dao_payouts(&self, dao_id: &str) -> anyhow::Result<Vec<PayoutReceipt>> {
+        self.send(self.get(&format!("/dao/{}/payouts", dao_id)))
+            .await
+    }
+    /// Credits the fund of a DAO in a token, authorized by the admin token.
+    pub async fn credit_funds(
+        &self,
+        admin_token: &str,
+        query: &FundsCreditQuery,
+    ) -> anyhow::Result<FundBalance> {
+        self.send(
+            self.post("/admin/funds/credit")
+                .bearer_auth(admin_token)
+                .json(query),
+        )
+        .await
+    }
+    /// Pays out a passed treasury transfer its DAO could not cover when it was
+    /// finalized, authorized by the admin token.
+    pub async fn pay_out(&self, admin_token: &str, id: Uuid) -> anyhow::Result<PayoutReceipt> {
+        self.send(
+            self.post(&format!("/admin/proposal/{}/payout", id))
+                .bearer_auth(admin_token),
+        )
+        .await
+    }
+    pub async fn get_token_account(&self, voter_id: u32) -> anyhow::Result<TokenAccount> {
+        self.send(self.get(&format!("/tokens/{}", voter_id))).await
+    }
+    /// Credits the tokens of a voter in the token tree, authorized by the admin token.
+    pub async fn credit_tokens(
+        &self,
+        admin_token: &str,
+        query: &TokenCreditQuery,
+    ) -> anyhow::Result<TokenAccount> {
+        self.send(
+            self.post("/admin/tokens/credit")
+                .bearer_auth(admin_token)
+                .json(query),
+        )
+        .await
+    }
+    /// Cancels a proposal for spam and slashes its deposit, authorized by the admin token.
+    pub async fn slash(&self, admin_token: &str, id: Uuid) -> anyhow::Result<ActionResponse> {
+        self.send(
+            self.post(&format!("/admin/proposal/{}/slash", id))
+                .bearer_auth(admin_token),
+        )
+        .await
+    }
+    /// Deletes a spam proposal and slashes its deposit, authorized by the admin token.
+    pub async fn delete_proposal(
+        &self,
+        admin_token: &str,
+        id: Uuid,
+    ) -> anyhow::Result<ActionResponse> {
+        self.send(
+            self.delete(&format!("/admin/proposal/{}", id))
+                .bearer_auth(admin_token),
+        )
+        .await
+    }
+    pub async fn get_pause_state(&self, admin_token: &str) -> anyhow::Result<PauseState> {
+        self.send(self.get("/admin/pause").bearer_auth(admin_token))
+            .await
+    }
+    /// Pauses the server, rejecting every mutation until it is unpaused, authorized
+    /// by the admin token.
+    pub async fn pause(
+        &self,
+        admin_token: &str,
+        reason: Option<String>,
+    ) -> anyhow::Result<PauseState> {
+        self.send(
+            self.post("/admin/pause")
+                .bearer_auth(admin_token)
+                .json(&PauseQuery { reason }),
+        )
+        .await
+    }
+    pub async fn unpause(&self, admin_token: &str) -> anyhow::Result<PauseState> {
+        self.send(self.post("/admin/unpause").bearer_auth(admin_token))
+            .await
+    }
+    /// Pauses or resumes voting on every proposal.
+    pub async fn set_voting_paused(
+        &self,
+        admin_token: &str,
+        paused: bool,
+    ) -> anyhow::Result<VotingPauseQuery> {
+        self.send(
+            self.post("/admin/voting")
+                .bearer_auth(admin_token)
+                .json(&VotingPauseQuery { paused }),
+        )
+        .await
+    }
+    pub async fn list_keys(&self, admin_token: &str) -> anyhow::Result<Vec<ApiKeyView>> {
+        self.send(self.get("/admin/keys").bearer_auth(admin_token))
+            .await
+    }
+    /// Issues an API key, whose secret the response carries this once.
+    pub async fn issue_key(
+        &self,
+        admin_token: &str,
+        query: &IssueKeyQuery,
+    ) -> anyhow::Result<IssuedKeyResponse> {
+        self.send(
+            self.post("/admin/keys")
+                .bearer_auth(admin_token)
+                .json(query),
+        )
+        .await
+    }
+    pub async fn rotate_key(
+        &self,
+        admin_token: &str,
+        key_id: &str,
+        query: &RotateKeyQuery,
+    ) -> anyhow::Result<IssuedKeyResponse> {
+        self.send(
+            self.post(&format!("/admin/keys/{}/rotate", key_id))
+                .bearer_auth(admin_token)
+                .json(query),
+        )
+        .await
+    }
+    pub async fn revoke_key(&self, admin_token: &str, key_id: &str) -> anyhow::Result<ApiKeyView> {
+        self.send(
+            self.delete(&format!("/admin/keys/{}", key_id))
+                .bearer_auth(admin_token),
+        )
+        .await
+    }
+    /// Creates an organization, authorized by the admin token of the server.
+    pub async fn create_organization(
+        &self,
+        admin_token: &str,
+        query: &CreateOrganizationQuery,
+    ) -> anyhow::Result<OrganizationView> {
+        self.send(self.post("/admin/org").bearer_auth(admin_token).json(query))
+            .await
+    }
+    pub async fn get_organization(&self, org_id: &str) -> anyhow::Result<OrganizationView> {
+        self.send(self.get(&format!("/org/{}", org_id))).await
+    }
+    pub async fn register_voter(
+        &self,
+        org_id: &str,
+        query: &RegisterQuery,
+    ) -> anyhow::Result<VoterRegistration> {
+        self.send(self.post(&format!("/org/{}/register", org_id)).json(query))
+            .await
+    }
+    pub async fn list_voters(
+        &self,
+        org_id: &str,
+        query: &VotersQuery,
+    ) -> anyhow::Result<Vec<VoterRegistration>> {
+        self.send(self.get(&format!("/org/{}/voters", org_id)).query(query))
+            .await
+    }
+    /// Approves or rejects the registration of `did`, authorized by the admin token of the
+    /// organization.
+    pub async fn decide_registration(
+        &self,
+        org_id: &str,
+        org_admin_token: &str,
+        did: &Did,
+        approve: bool,
+    ) -> anyhow::Result<VoterRegistration> {
+        let decision = if approve { "approve" } else { "reject" };
+        self.send(
+            self.post(&format!("/org/{}/voters/{}/{}", org_id, did, decision))
+                .bearer_auth(org_admin_token),
+        )
+        .await
+    }
+    pub async fn org_propose(
+        &self,
+        org_id: &str,
+        query: &ProposeQuery,
+    ) -> anyhow::Result<ActionResponse> {
+        self.send(self.post(&format!("/org/{}/propose", org_id)).json(query))
+            .await
+    }
+    pub async fn list_org_proposals(
+        &self,
+        org_id: &str,
+        query: &ProposalQuery,
+    ) -> anyhow::Result<Vec<ProposalView>> {
+        self.send(self.get(&format!("/org/{}/proposals", org_id)).query(query))
+            .await
+    }
+    /// Replaces the webhooks of an organization, authorized by its admin token.
+    pub async fn set_webhooks(
+        &self,
+        org_id: &str,
+        org_admin_token: &str,
+        query: &WebhooksQuery,
+    ) -> anyhow::Result<Vec<String>> {
+        self.send(
+            self.post(&format!("/org/{}/webhooks", org_id))
+                .bearer_auth(org_admin_token)
+                .json(query),
+        )
+        .await
+    }
+    /// Sets or clears the defaults of the proposals an organization files under
+    /// a category, authorized by its admin token.
+    pub async fn set_category_policy(
+        &self,
+        org_id: &str,
+        org_admin_token: &str,
+        query: &CategoryPolicyQuery,
+    ) -> anyhow::Result<BTreeMap<String, CategoryPolicy>> {
+        self.send(
+            self.post(&format!("/org/{}/categories", org_id))
+                .bearer_auth(org_admin_token)
+                .json(query),
+        )
+        .await
+    }
+    /// Lists the callbacks to webhooks, authorized by the admin token.
+    pub async fn list_webhook_deliveries(
+        &self,
+        admin_token: &str,
+        query: &WebhookDeliveriesQuery,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        self.send(
+            self.get("/admin/webhooks")
+                .bearer_auth(admin_token)
+                .query(query),
+        )
+        .await
+    }
+    pub async fn resolve_did(&self, did: &str) -> anyhow::Result<DidDocument> {
+        self.send(self.get(&format!("/did/{}", did))).await
+    }
+    pub async fn get_errors(&self) -> anyhow::Result<Vec<ErrorCatalogEntry>> {
+        self.send(self.get("/errors")).await
+    }
+    /// The OpenAPI document of the server, e.g. to check it against this client.
+    pub async fn get_openapi(&self) -> anyhow::Result<serde_json::Value> {
+        self.send(self.get("/openapi.json")).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use uuid::Uuid;
+
+    use super::decode_response;
+    use crate::{
+        api::ActionResponse,
+        errors::{ApiError, ApiErrorCode},
+    };
+
+    #[test]
+    fn test_decodes_responses_and_api_errors() {
+        let body = br#"{"proposal_id":"00000000-0000-0000-0000-000000000000","message":"Voted"}"#;
+        let response: ActionResponse = decode_response(StatusCode::OK, body).unwrap();
+        assert_eq!(response.proposal_id, Uuid::nil());
+
+        let body = br#"{"code":"voting_closed","message":"Voting period has ended"}"#;
+        let err = decode_response::<ActionResponse>(StatusCode::BAD_REQUEST, body).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ApiError>().map(|err| err.code),
+            Some(ApiErrorCode::VotingClosed)
+        );
+
+        // A proxy in front of the server may answer with anything
+        let err =
+            decode_response::<ActionResponse>(StatusCode::BAD_GATEWAY, b"Bad Gateway").unwrap_err();
+        assert!(err.downcast_ref::<ApiError>().is_none());
+    }
+}
@@ -0,0 +1,404 @@
+use std::time::Duration;
+
+use anyhow::ensure;
+use plonky2::{field::goldilocks_field::GoldilocksField, plonk::config::PoseidonGoldilocksConfig};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{
+    balance::accounts::{Tally, TallySlot, VoteSplit},
+    circuits::{
+        cache::CircuitCache, prover::ProvingRetryPolicy, update_balance::UpdateBalanceShape,
+    },
+    common::WHashOut,
+    errors::ApiErrorCode,
+    nullifier::nullifier_set::NullifierSet,
+    proof::certificate::{compute_action_hash, compute_statement_hash},
+    proposal::{
+        encryption::BallotBox,
+        rules::ProposalOutcome,
+        transcript::{Transcript, TranscriptAction, TranscriptEvent},
+        Proposal, ProposalPhase, ProposalStatus,
+    },
+};
+
+type F = GoldilocksField;
+
+/// When things happen to a simulated proposal, in virtual seconds after its creation.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseSchedule {
+    /// Overrides the voting period of the transcript's rules.
+    pub voting_period_secs: Option<u64>,
+    /// When the proposer finalizes; at the time of the last event if unset.
+    pub finalize_at_secs: Option<u64>,
+    /// Hash of the beacon block of the proposal, for ties under the random-with-beacon policy.
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    #[serde(default)]
+    pub beacon: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationOptions {
+    /// Virtual seconds that pass per real second; zero replays without waiting.
+    pub speedup: f64,
+    /// Proves the finalization like the server does, instead of only resolving the outcome.
+    pub prove: bool,
+    /// Tracks nullifiers, rejecting double votes like a server with anchoring enabled.
+    pub nullifiers: bool,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self {
+            speedup: 0.0,
+            prove: false,
+            nullifiers: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseChange {
+    pub at_secs: u64,
+    pub phase: ProposalPhase,
+}
+
+/// A transcript event and the error code it was rejected with, if any.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayedEvent {
+    #[serde(flatten)]
+    pub event: TranscriptEvent,
+    pub error: Option<String>,
+}
+
+/// The observable result of a replay, stable across runs so it can be kept as
+/// the expected output of a regression test.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationReport {
+    pub phases: Vec<PhaseChange>,
+    pub events: Vec<ReplayedEvent>,
+    pub tally: Tally,
+    pub final_root: WHashOut<F>,
+    pub outcome: Option<ProposalOutcome>,
+    pub finalize_error: Option<String>,
+    /// Circuit the finalization was proven with, when proving.
+    pub circuit_id: Option<String>,
+}
+
+/// Keeps virtual time, sleeping for the accelerated duration of every step.
+struct VirtualClock {
+    now: u64,
+    speedup: f64,
+}
+
+impl VirtualClock {
+    async fn advance_to(&mut self, at_secs: u64) {
+        if at_secs > self.now && self.speedup > 0.0 {
+            let delay = (at_secs - self.now) as f64 / self.speedup;
+            tokio::time::sleep(Duration::from_secs_f64(delay)).await;
+        }
+        self.now = self.now.max(at_secs);
+    }
+}
+
+struct PhaseRecorder {
+    phases: Vec<PhaseChange>,
+}
+
+impl PhaseRecorder {
+    fn observe(&mut self, proposal: &Proposal, at_secs: u64) {
+        let phase = proposal.phase(proposal.created_at + at_secs);
+        if self.phases.last().map(|change| change.phase) != Some(phase) {
+            self.phases.push(PhaseChange { at_secs, phase });
+        }
+    }
+}
+
+/// Advances the clock to `at_secs`, observing the phase at every boundary passed on the way.
+async fn advance(
+    clock: &mut VirtualClock,
+    recorder: &mut PhaseRecorder,
+    proposal: &Proposal,
+    boundaries: &[u64],
+    at_secs: u64,
+) {
+    for boundary in boundaries {
+        if *boundary > clock.now && *boundary <= at_secs {
+            clock.advance_to(*boundary).await;
+            recorder.observe(proposal, *boundary);
+        }
+    }
+    clock.advance_to(at_secs).await;
+}
+
+/// Replays `transcript` through the proposal pipeline of the server under the
+/// given schedule, at accelerated virtual time.
+pub async fn simulate(
+    transcript: &Transcript,
+    schedule: &PhaseSchedule,
+    options: &SimulationOptions,
+) -> anyhow::Result<SimulationReport> {
+    ensure!(
+        transcript
+            .events
+            .windows(2)
+            .all(|pair| pair[0].at_secs <= pair[1].at_secs),
+        "transcript events are not in chronological order"
+    );
+    let last_event_at = transcript.events.last().map_or(0, |event| event.at_secs);
+    let finalize_at = schedule.finalize_at_secs.unwrap_or(last_event_at);
+    ensure!(
+        finalize_at >= last_event_at,
+        "finalization at {}s precedes the last event at {}s",
+        finalize_at,
+        last_event_at
+    );
+
+    let mut rules = transcript.rules.clone();
+    if schedule.voting_period_secs.is_some() {
+        rules.voting_period_secs = schedule.voting_period_secs;
+    }
+    rules.validate()?;
+    let mut proposal = Proposal::with_voter_balances(
+        transcript.statement.clone(),
+        transcript.proposer_id,
+        0,
+        rules,
+        transcript.voter_balances.clone(),
+    )?;
+    proposal.action = transcript.action.clone();
+    proposal.blinding = transcript.blinding.clone();
+    proposal.ballots = transcript.ballot_committee.clone().map(BallotBox::new);
+    if options.nullifiers {
+        proposal.nullifiers = Some(NullifierSet::new(transcript.proposal_id, 32));
+    }
+    // Phase boundaries, relative to the creation of the proposal at zero
+    let mut boundaries: Vec<u64> = [proposal.commit_deadline(), proposal.deadline()]
+        .into_iter()
+        .flatten()
+        .collect();
+    boundaries.sort();
+    let mut clock = VirtualClock {
+        now: 0,
+        speedup: options.speedup,
+    };
+    let mut recorder = PhaseRecorder { phases: vec![] };
+    recorder.observe(&proposal, 0);
+
+    let mut events = vec![];
+    for event in &transcript.events {
+        advance(
+            &mut clock,
+            &mut recorder,
+            &proposal,
+            &boundaries,
+            event.at_secs,
+        )
+        .await;
+        let now = proposal.created_at + event.at_secs;
+        let result = match &event.action {
+            TranscriptAction::Vote {
+                voter_id,
+                is_yes,
+                salt,
+            } => proposal.cast_vote(*voter_id, *is_yes, salt.as_deref(), now),
+            TranscriptAction::SplitVote {
+                voter_id,
+                yes_votes,
+                no_votes,
+            } => proposal.cast_split_vote(
+                *voter_id,
+                VoteSplit {
+                    yes_votes: *yes_votes,
+                    no_votes: *no_votes,
+                },
+                now,
+            ),
+            TranscriptAction::Delegate {
+                voter_id,
+                delegator_id,
+            } => proposal.delegate(*voter_id, *delegator_id, now),
+            TranscriptAction::Revoke { voter_id } => proposal.revoke_vote(*voter_id, now),
+            TranscriptAction::Undelegate { voter_id } => proposal.undelegate(*voter_id, now),
+            TranscriptAction::Commit {
+                voter_id,
+                commitment,
+            } => proposal.commit_vote(*voter_id, *commitment, now),
+            TranscriptAction::EncryptedVote { voter_id, ballot } => proposal.cast_encrypted_ballot(
+                &transcript.proposal_id,
+                *voter_id,
+                *ballot.clone(),
+                now,
+            ),
+            TranscriptAction::Decrypt { share } => proposal
+                .share_decryption(&transcript.proposal_id, share.clone(), now)
+                .map(|_| ()),
+        };
+        if result.is_ok() && proposal.status == ProposalStatus::Draft {
+            proposal.transition(ProposalStatus::Open)?;
+        }
+        recorder.observe(&proposal, event.at_secs);
+        events.push(ReplayedEvent {
+            event: event.clone(),
+            error: result.err().map(|err| err.code.as_str().to_string()),
+        });
+    }
+
+    advance(
+        &mut clock,
+        &mut recorder,
+        &proposal,
+        &boundaries,
+        finalize_at,
+    )
+    .await;
+    let tally = proposal.storage.tally()?;
+    let mut finalize_error = None;
+    let mut circuit_id = None;
+    let resolved =
+        proposal
+            .rules
+            .resolve(&transcript.proposal_id, &tally, schedule.beacon.as_deref());
+    let outcome = match resolved {
+        Ok(outcome) => Some(outcome),
+        Err(_) => {
+            finalize_error = Some(ApiErrorCode::OutcomeUnresolved.as_str().to_string());
+            None
+        }
+    };
+    if outcome.is_some() {
+        proposal.transition(ProposalStatus::Finalizing)?;
+        let mut proved = true;
+        if options.prove {
+            let tree_height = proposal.storage.tree_height();
+            let updates = proposal.finalization_updates();
+            let tally_proofs = [
+                proposal.storage.get_tally_proof(TallySlot::NO)?,
+                proposal.storage.get_tally_proof(TallySlot::YES)?,
+            ];
+            let shape = UpdateBalanceShape {
+                number_updates: updates.len(),
+                tree_height,
+                balance_bits: proposal.storage.balance_bits(),
+                conviction: proposal.rules.conviction.is_some(),
+                voting_policy: proposal.rules.voting_policy,
+                dependencies: false,
+                vesting: false,
+                deadline: proposal.deadline().is_some(),
+                min_transfer: proposal.rules.min_transfer.is_some(),
+            };
+            let statement_hash = compute_statement_hash(&proposal.statement);
+            let action_hash = compute_action_hash(&proposal.action);
+            let circuit = CircuitCache::<F, PoseidonGoldilocksConfig, 2>::new().get_or_build(shape);
+            let envelope = tokio::task::spawn_blocking(move || {
+                ProvingRetryPolicy::default().prove(|| {
+                    circuit.prove_envelope(statement_hash, action_hash, &updates, &tally_proofs)
+                })
+            })
+            .await?;
+            match envelope {
+                Ok(envelope) => circuit_id = Some(envelope.circuit_id),
+                Err(_) => {
+                    finalize_error = Some(ApiErrorCode::ProvingFailed.as_str().to_string());
+                    proved = false;
+                }
+            }
+        }
+        if proved {
+            proposal.transition(ProposalStatus::Finalized)?;
+        } else {
+            proposal.recover()?;
+        }
+    }
+    recorder.observe(&proposal, finalize_at);
+
+    Ok(SimulationReport {
+        phases: recorder.phases,
+        events,
+        tally,
+        final_root: proposal.storage.tree.get_root()?,
+        outcome,
+        finalize_error,
+        circuit_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        balance::weight::Weight,
+        proposal::{
+            action::ProposalAction,
+            rules::{ProposalOutcome, ProposalRules},
+            transcript::{Transcript, TranscriptAction, TranscriptEvent},
+            ProposalPhase,
+        },
+    };
+
+    use super::{simulate, PhaseChange, PhaseSchedule, SimulationOptions};
+
+    fn vote(at_secs: u64, voter_id: u32, is_yes: bool) -> TranscriptEvent {
+        TranscriptEvent {
+            at_secs,
+            action: TranscriptAction::Vote {
+                voter_id,
+                is_yes,
+                salt: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_votes_after_the_deadline() -> anyhow::Result<()> {
+        let transcript = Transcript {
+            proposal_id: Uuid::nil(),
+            statement: "test".to_string(),
+            action: ProposalAction::TextOnly,
+            proposer_id: 2,
+            rules: ProposalRules::default(),
+            voter_balances: vec![Weight::from(1); 4],
+            blinding: None,
+            ballot_committee: None,
+            events: vec![vote(1, 2, true), vote(5, 3, true), vote(20, 4, false)],
+        };
+        let schedule = PhaseSchedule {
+            voting_period_secs: Some(10),
+            finalize_at_secs: Some(30),
+            beacon: None,
+        };
+        let report = simulate(&transcript, &schedule, &SimulationOptions::default()).await?;
+
+        let errors: Vec<_> = report
+            .events
+            .iter()
+            .map(|event| event.error.as_deref())
+            .collect();
+        assert_eq!(errors, vec![None, None, Some("voting_closed")]);
+        assert_eq!(
+            (report.tally.yes_votes, report.tally.no_votes),
+            (Weight::from(2), Weight::from(0))
+        );
+        assert_eq!(report.outcome, Some(ProposalOutcome::Passed));
+        assert_eq!(
+            report.phases,
+            vec![
+                PhaseChange {
+                    at_secs: 0,
+                    phase: ProposalPhase::Voting
+                },
+                PhaseChange {
+                    at_secs: 10,
+                    phase: ProposalPhase::AwaitingFinalization
+                },
+                PhaseChange {
+                    at_secs: 30,
+                    phase: ProposalPhase::Finalized
+                },
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,564 @@
+//! Versioned archives of the whole state of a server, for moving a deployment
+//! to another server or recovering it from a backup.
+//!
+//! Trees are archived by what they were built from, the electorate and the
+//! recorded updates, along with the roots they had. A restore rebuilds them in
+//! the node stores of the target server and fails unless every root matches.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::ensure;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use uuid::Uuid;
+
+use crate::{
+    audit::AuditEntry,
+    balance::{
+        accounts::VoterLeaf,
+        funds::FundsSnapshot,
+        locks::TokenLocksSnapshot,
+        storage::BalanceStorage,
+        treasury::{ProposalDeposit, TreasurySnapshot},
+        weight::Weight,
+    },
+    chain::{anchor::AnchorRecord, token_snapshot::TokenSnapshot},
+    circuits::update_balance::BalanceUpdate,
+    common::WHashOut,
+    did::Did,
+    nullifier::nullifier_set::NullifierSet,
+    proof::{certificate::FinalizationCertificate, codec::ProofEnvelope},
+    proposal::{
+        action::ProposalAction,
+        approval::{FinalizeApproval, FinalizerPolicy},
+        blinding::VoterBlinding,
+        content::StatementContent,
+        delegation::DelegationRegistry,
+        encryption::BallotBox,
+        events::{replay, EventRecord},
+        org::Organization,
+        rules::ProposalRules,
+        store::ProposalStore,
+        tally_history::TallyPoint,
+        transcript::TranscriptEvent,
+        Proposal, ProposalStatus,
+    },
+    utils::zmt::node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+};
+
+/// Version of the archive layout written by [`StateSnapshot`]. Archives of any
+/// other version are rejected rather than guessed at.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything a server holds: its proposals, the audit log and which circuits
+/// it had built, so the target server can build them ahead of finalizations.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub version: u32,
+    pub taken_at: u64,
+    pub proposals: Vec<ProposalSnapshot>,
+    /// Entries of the audit log in sequence order, signatures included.
+    pub audit: Vec<AuditEntry>,
+    /// Ids of the cached update balance circuits, see
+    /// [`update_balance_circuit_id`](crate::circuits::update_balance::update_balance_circuit_id).
+    pub circuit_ids: Vec<String>,
+    /// Accounts and escrowed deposits of proposers, see [`crate::balance::treasury`].
+    #[serde(default)]
+    pub treasury: Option<TreasurySnapshot>,
+    /// Funds and payouts of DAOs, see [`crate::balance::funds`].
+    #[serde(default)]
+    pub funds: Option<FundsSnapshot>,
+    /// Accounts and locks of voters, see [`crate::balance::locks`].
+    #[serde(default)]
+    pub token_locks: Option<TokenLocksSnapshot>,
+    /// Organizations hosted on the server, see [`crate::proposal::org`].
+    #[serde(default)]
+    pub organizations: Vec<Organization>,
+    /// Records of the event log in sequence order, which servers keeping one
+    /// rebuild their proposals from, see [`crate::proposal::events`].
+    #[serde(default)]
+    pub events: Vec<EventRecord>,
+}
+
+impl StateSnapshot {
+    pub fn check_version(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.version == SNAPSHOT_VERSION,
+            "snapshot version {} is not supported, expected {}",
+            self.version,
+            SNAPSHOT_VERSION
+        );
+        Ok(())
+    }
+    /// Fails unless the events replay to the proposals of the snapshot and no
+    /// other, each at the balance root it was archived with.
+    pub fn check_events(&self) -> anyhow::Result<()> {
+        let replayed = replay(&self.events, |_| {
+            Ok(NodeStore::Memory(SimpleNodeStore::new()))
+        })?;
+        ensure!(
+            replayed.len() == self.proposals.len(),
+            "the events replay to {} proposals, the snapshot holds {}",
+            replayed.len(),
+            self.proposals.len()
+        );
+        for archived in &self.proposals {
+            let proposal = replayed
+                .get(&archived.id)
+                .ok_or_else(|| anyhow::anyhow!("no event creates proposal {}", archived.id))?;
+            ensure!(
+                proposal.storage.tree.get_root()? == archived.balance_root,
+                "the events of proposal {} do not replay to its balance root",
+                archived.id
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A proposal with its trees reduced to what rebuilds them.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProposalSnapshot {
+    pub id: Uuid,
+    pub dao_id: String,
+    pub statement: String,
+    #[serde(default)]
+    pub content: Option<StatementContent>,
+    pub action: ProposalAction,
+    pub proposer_id: u32,
+    pub created_at: u64,
+    pub rules: ProposalRules,
+    pub token_snapshot: Option<TokenSnapshot>,
+    pub voter_dids: Vec<Did>,
+    #[serde(default)]
+    pub blinding: Option<VoterBlinding>,
+    pub tree_height: u8,
+    pub balance_bits: usize,
+    pub voter_balances: Vec<Weight>,
+    pub updates: Vec<BalanceUpdate<GoldilocksField>>,
+    pub balance_root: WHashOut<GoldilocksField>,
+    pub transcript: Vec<TranscriptEvent>,
+    /// Leaf indices of the voters who voted.
+    pub voted: BTreeSet<u64>,
+    #[serde_as(as = "BTreeMap<_, serde_with::hex::Hex>")]
+    pub commitments: BTreeMap<u64, [u8; 32]>,
+    pub status: ProposalStatus,
+    pub proof: Option<ProofEnvelope>,
+    /// Height and root of the nullifier tree, on servers that track nullifiers.
+    pub nullifier_tree: Option<(u8, WHashOut<GoldilocksField>)>,
+    pub anchors: Vec<AnchorRecord>,
+    pub certificate: Option<FinalizationCertificate>,
+    #[serde(default)]
+    pub finalized_at: Option<u64>,
+    #[serde(default)]
+    pub deposit: Option<ProposalDeposit>,
+    #[serde(default)]
+    pub depends_on: Vec<Uuid>,
+    #[serde(default)]
+    pub finalizers: Option<FinalizerPolicy>,
+    #[serde(default)]
+    pub approvals: Vec<FinalizeApproval>,
+    #[serde(default)]
+    pub locks_tokens: bool,
+    #[serde(default)]
+    pub ballots: Option<BallotBox>,
+    #[serde(default)]
+    pub relay_nonces: BTreeMap<u32, u64>,
+    #[serde(default)]
+    pub did_nonces: BTreeMap<u32, u64>,
+    #[serde(default)]
+    pub revote_of: Option<Uuid>,
+    #[serde(default)]
+    pub tally_history: Vec<TallyPoint>,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl ProposalSnapshot {
+    pub fn capture(id: Uuid, proposal: &Proposal) -> anyhow::Result<Self> {
+        let nullifier_tree = match &proposal.nullifiers {
+            Some(nullifiers) => Some((nullifiers.height(), nullifiers.root()?)),
+            None => None,
+        };
+        Ok(Self {
+            id,
+            dao_id: proposal.dao_id.clone(),
+            statement: proposal.statement.clone(),
+            content: proposal.content.clone(),
+            action: proposal.action.clone(),
+            proposer_id: proposal.proposer_id,
+            created_at: proposal.created_at,
+            rules: proposal.rules.clone(),
+            token_snapshot: proposal.token_snapshot.clone(),
+            voter_dids: proposal.voter_dids.clone(),
+            blinding: proposal.blinding.clone(),
+            tree_height: proposal.storage.tree_height() as u8,
+            balance_bits: proposal.storage.balance_bits(),
+            voter_balances: proposal.storage.initial_balances().to_vec(),
+            updates: proposal.updates.clone(),
+            balance_root: proposal.storage.tree.get_root()?,
+            transcript: proposal.transcript.clone(),
+            tally_history: proposal.tally_history.clone(),
+            category: proposal.category.clone(),
+            voted: proposal.voted.iter().map(|voter| voter.index()).collect(),
+            commitments: proposal
+                .commitments
+                .iter()
+                .map(|(voter, commitment)| (voter.index(), *commitment))
+                .collect(),
+            status: proposal.status,
+            proof: proposal.proof.clone(),
+            nullifier_tree,
+            anchors: proposal.anchors.clone(),
+            certificate: proposal.certificate.clone(),
+            finalized_at: proposal.finalized_at,
+            deposit: proposal.deposit.clone(),
+            depends_on: proposal.depends_on.clone(),
+            finalizers: proposal.finalizers.clone(),
+            approvals: proposal.approvals.clone(),
+            locks_tokens: proposal.locks_tokens,
+            ballots: proposal.ballots.clone(),
+            relay_nonces: proposal.relay_nonces.clone(),
+            did_nonces: proposal.did_nonces.clone(),
+            revote_of: proposal.revote_of,
+        })
+    }
+    /// Rebuilds the proposal with its balance tree in `balance_store` and, if it
+    /// tracked nullifiers, its nullifier tree in `nullifier_store`. Both stores
+    /// have to be empty. A proposal archived while being proven accepts votes
+    /// again, as it would after a restart.
+    pub fn restore(
+        self,
+        balance_store: NodeStore,
+        nullifier_store: Option<NodeStore>,
+    ) -> anyhow::Result<Proposal> {
+        ensure!(
+            balance_store.is_empty(),
+            "the balance tree of proposal {} already has nodes",
+            self.id
+        );
+        let mut storage = BalanceStorage::with_store(
+            self.tree_height,
+            self.voter_balances,
+            self.balance_bits,
+            balance_store,
+        )?
+        .with_vesting(self.rules.vesting.clone())?;
+        storage.restore(&self.updates)?;
+        ensure!(
+            storage.tree.get_root()? == self.balance_root,
+            "the updates of proposal {} do not reproduce its balance root",
+            self.id
+        );
+        let voted: BTreeSet<VoterLeaf> = self
+            .voted
+            .iter()
+            .map(|index| VoterLeaf::from_voter_id(*index as u32))
+            .collect::<anyhow::Result<_>>()?;
+        let nullifiers = match (self.nullifier_tree, nullifier_store) {
+            (Some((height, root)), Some(store)) => {
+                ensure!(
+                    store.is_empty(),
+                    "the nullifier tree of proposal {} already has nodes",
+                    self.id
+                );
+                let mut nullifiers = NullifierSet::with_store(self.id, height, store);
+                for voter in &voted {
+                    nullifiers.insert(voter.index())?;
+                }
+                ensure!(
+                    nullifiers.root()? == root,
+                    "the voters of proposal {} do not reproduce its nullifier root",
+                    self.id
+                );
+                Some(nullifiers)
+            }
+            (None, _) => None,
+            (Some(_), None) => anyhow::bail!("proposal {} needs a nullifier store", self.id),
+        };
+
+        let mut proposal = Proposal::with_storage(
+            self.statement,
+            self.proposer_id,
+            self.created_at,
+            self.rules,
+            storage,
+        );
+        proposal.dao_id = self.dao_id;
+        proposal.content = self.content;
+        proposal.action = self.action;
+        proposal.token_snapshot = self.token_snapshot;
+        proposal.voter_dids = self.voter_dids;
+        proposal.blinding = self.blinding;
+        proposal.updates = self.updates;
+        proposal.transcript = self.transcript;
+        proposal.tally_history = self.tally_history;
+        proposal.category = self.category;
+        proposal.delegations = DelegationRegistry::from_transcript(&proposal.transcript);
+        proposal.voted = voted;
+        proposal.commitments = self
+            .commitments
+            .into_iter()
+            .map(|(index, commitment)| Ok((VoterLeaf::from_voter_id(index as u32)?, commitment)))
+            .collect::<anyhow::Result<_>>()?;
+        proposal.status = self.status;
+        proposal.proof = self.proof;
+        proposal.nullifiers = nullifiers;
+        proposal.anchors = self.anchors;
+        proposal.certificate = self.certificate;
+        proposal.finalized_at = self.finalized_at;
+        proposal.deposit = self.deposit;
+        proposal.depends_on = self.depends_on;
+        proposal.finalizers = self.finalizers;
+        proposal.approvals = self.approvals;
+        proposal.locks_tokens = self.locks_tokens;
+        proposal.ballots = self.ballots;
+        proposal.relay_nonces = self.relay_nonces;
+        proposal.did_nonces = self.did_nonces;
+        proposal.revote_of = self.revote_of;
+        proposal.recover()?;
+        proposal.seed_shards()?;
+        Ok(proposal)
+    }
+}
+
+/// Keeps a read-only copy of the proposals of another server up to date from its
+/// snapshots, as a replica does. Only proposals that changed since the previous
+/// snapshot are rebuilt, with their trees in memory, and they keep the status
+/// they have on the other server, even one a restore would roll back.
+#[derive(Default)]
+pub struct SnapshotFollower {
+    applied: HashMap<Uuid, ProposalSnapshot>,
+}
+
+/// What applying a snapshot changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FollowSummary {
+    pub rebuilt: usize,
+    pub removed: usize,
+}
+
+impl SnapshotFollower {
+    /// Brings `store` in line with `proposals`. Fails without touching `store` if
+    /// a changed proposal does not rebuild.
+    pub fn apply(
+        &mut self,
+        store: &mut ProposalStore,
+        proposals: Vec<ProposalSnapshot>,
+    ) -> anyhow::Result<FollowSummary> {
+        let mut rebuilt = vec![];
+        for archived in &proposals {
+            if self.applied.get(&archived.id) == Some(archived) {
+                continue;
+            }
+            let nullifier_store = archived
+                .nullifier_tree
+                .map(|_| NodeStore::Memory(SimpleNodeStore::new()));
+            let mut proposal = archived
+                .clone()
+                .restore(NodeStore::Memory(SimpleNodeStore::new()), nullifier_store)?;
+            proposal.status = archived.status;
+            rebuilt.push((archived.id, proposal));
+        }
+        let current: BTreeSet<Uuid> = proposals.iter().map(|archived| archived.id).collect();
+        let removed: Vec<Uuid> = self
+            .applied
+            .keys()
+            .filter(|id| !current.contains(id))
+            .copied()
+            .collect();
+        let summary = FollowSummary {
+            rebuilt: rebuilt.len(),
+            removed: removed.len(),
+        };
+        for id in removed {
+            store.remove(&id);
+        }
+        for (id, proposal) in rebuilt {
+            store.insert(id, proposal);
+        }
+        self.applied = proposals
+            .into_iter()
+            .map(|archived| (archived.id, archived))
+            .collect();
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{
+        FollowSummary, ProposalSnapshot, SnapshotFollower, StateSnapshot, SNAPSHOT_VERSION,
+    };
+    use crate::{
+        balance::{accounts::VoterLeaf, weight::Weight},
+        common::WHashOut,
+        nullifier::nullifier_set::NullifierSet,
+        proposal::{
+            events::{replay, EventLog, ProposalEvent, ProposalGenesis},
+            rules::ProposalRules,
+            store::ProposalStore,
+            Proposal, ProposalStatus,
+        },
+        utils::zmt::node_store::{backend::NodeStore, simple_node_store::SimpleNodeStore},
+    };
+
+    fn store() -> NodeStore {
+        NodeStore::Memory(SimpleNodeStore::new())
+    }
+
+    #[test]
+    fn test_restores_proposals_from_snapshots() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let mut proposal = Proposal::with_voter_balances(
+            "Fund the audit".to_string(),
+            2,
+            0,
+            ProposalRules::default(),
+            vec![Weight::from(1), Weight::from(2), Weight::from(3)],
+        )?;
+        proposal.nullifiers = Some(NullifierSet::new(id, 8));
+        proposal.cast_vote(3, true, None, 1).unwrap();
+        proposal.delegate(2, 4, 2).unwrap();
+        proposal.status = ProposalStatus::Finalizing;
+
+        let snapshot = StateSnapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at: 3,
+            proposals: vec![ProposalSnapshot::capture(id, &proposal)?],
+            audit: vec![],
+            circuit_ids: vec![],
+            treasury: None,
+            funds: None,
+            token_locks: None,
+            organizations: vec![],
+            events: vec![],
+        };
+        let json = serde_json::to_string(&snapshot)?;
+        let snapshot: StateSnapshot = serde_json::from_str(&json)?;
+        snapshot.check_version()?;
+        let archived = snapshot.proposals[0].clone();
+        let mut restored = archived.clone().restore(store(), Some(store()))?;
+        assert_eq!(
+            restored.storage.tree.get_root()?,
+            proposal.storage.tree.get_root()?
+        );
+        assert_eq!(restored.storage.tally()?, proposal.storage.tally()?);
+        assert_eq!(restored.voted, proposal.voted);
+        // Voting resumes where it stopped, without a second vote of the same voter
+        assert_eq!(restored.status, ProposalStatus::Open);
+        assert!(restored.cast_vote(3, false, None, 4).is_err());
+        restored.cast_vote(4, false, None, 4).unwrap();
+        assert_eq!(
+            restored
+                .storage
+                .initial_membership_proof(VoterLeaf::from_position(0))?
+                .root,
+            proposal.storage.initial_root()
+        );
+
+        let mut tampered = archived;
+        tampered.updates.pop();
+        assert!(tampered.restore(store(), Some(store())).is_err());
+        let mut future = snapshot.clone();
+        future.version += 1;
+        assert!(future.check_version().is_err());
+
+        // Events restored along with the proposals have to replay to them
+        let mut log = EventLog::in_memory();
+        let genesis = ProposalGenesis::capture(&proposal);
+        log.append(
+            id,
+            0,
+            ProposalEvent::ProposalCreated(Box::new(genesis)),
+            WHashOut::ZERO,
+        )?;
+        let behind = StateSnapshot {
+            events: log.records().to_vec(),
+            ..snapshot
+        };
+        assert!(behind.check_events().is_err());
+        let events = [
+            (
+                1,
+                ProposalEvent::VoteCast {
+                    voter_id: 3,
+                    is_yes: true,
+                    salt: None,
+                },
+            ),
+            (
+                2,
+                ProposalEvent::Delegated {
+                    voter_id: 2,
+                    delegator_id: 4,
+                },
+            ),
+        ];
+        for (at, event) in events {
+            let replayed = replay(log.records(), |_| Ok(store()))?;
+            let root = replayed.get(&id).unwrap().storage.tree.get_root()?;
+            log.append(id, at, event, root)?;
+        }
+        let caught_up = StateSnapshot {
+            events: log.records().to_vec(),
+            ..behind
+        };
+        caught_up.check_events()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_followers_rebuild_changed_proposals() -> anyhow::Result<()> {
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let new_proposal = || {
+            Proposal::with_voter_balances(
+                "Fund the audit".to_string(),
+                2,
+                0,
+                ProposalRules::default(),
+                vec![Weight::from(1); 4],
+            )
+        };
+        let (mut first_proposal, second_proposal) = (new_proposal()?, new_proposal()?);
+        let capture = |first_proposal: &Proposal| -> anyhow::Result<_> {
+            Ok(vec![
+                ProposalSnapshot::capture(first, first_proposal)?,
+                ProposalSnapshot::capture(second, &second_proposal)?,
+            ])
+        };
+
+        let mut follower = SnapshotFollower::default();
+        let mut replica = ProposalStore::new();
+        let summary = follower.apply(&mut replica, capture(&first_proposal)?)?;
+        assert_eq!(summary.rebuilt, 2);
+        assert_eq!(
+            follower.apply(&mut replica, capture(&first_proposal)?)?,
+            FollowSummary::default()
+        );
+
+        first_proposal.cast_vote(2, true, None, 1).unwrap();
+        first_proposal.status = ProposalStatus::Finalizing;
+        assert_eq!(
+            follower
+                .apply(&mut replica, capture(&first_proposal)?)?
+                .rebuilt,
+            1
+        );
+        let followed = replica.get(&first).unwrap();
+        assert_eq!(followed.status, ProposalStatus::Finalizing);
+        assert_eq!(followed.storage.tally()?, first_proposal.storage.tally()?);
+
+        let summary = follower.apply(
+            &mut replica,
+            vec![ProposalSnapshot::capture(first, &first_proposal)?],
+        )?;
+        assert_eq!(summary.removed, 1);
+        assert!(replica.get(&second).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Failure injection for testing crash consistency, compiled in with the `chaos` feature.
+//!
+//! Once [`install`]ed on a thread, tree writes on it fail at random, finalizations
+//! wait a random time before proving and requests are dropped before they reach
+//! their handler. The faults are drawn from a seeded generator per thread, so a
+//! single threaded run fails the same way every time.
+//!
+//! Recovering from a fault must not fault itself, so the code restoring state
+//! runs [`calm`].
+
+use std::{cell::RefCell, time::Duration};
+
+use anyhow::bail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// How often each fault is injected.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Probability that a tree node write fails.
+    pub tree_write_failure_rate: f64,
+    /// Finalizations wait up to this long before proving.
+    pub max_proving_delay: Duration,
+    /// Probability that a request is answered with a bare 503.
+    pub request_drop_rate: f64,
+    pub seed: u64,
+}
+
+struct Chaos {
+    config: ChaosConfig,
+    rng: StdRng,
+    /// Number of nested [`calm`] sections being run.
+    calm: usize,
+}
+
+thread_local! {
+    static CHAOS: RefCell<Option<Chaos>> = RefCell::new(None);
+}
+
+/// Starts injecting faults on the current thread.
+pub fn install(config: ChaosConfig) {
+    let rng = StdRng::seed_from_u64(config.seed);
+    CHAOS.with(|chaos| {
+        *chaos.borrow_mut() = Some(Chaos {
+            config,
+            rng,
+            calm: 0,
+        })
+    });
+}
+
+/// Stops injecting faults on the current thread.
+pub fn uninstall() {
+    CHAOS.with(|chaos| *chaos.borrow_mut() = None);
+}
+
+/// Draws whether a fault with probability `rate` happens, never while calm.
+fn strikes(rate: impl Fn(&ChaosConfig) -> f64) -> bool {
+    CHAOS.with(|chaos| match &mut *chaos.borrow_mut() {
+        Some(chaos) if chaos.calm == 0 => {
+            let rate = rate(&chaos.config);
+            rate > 0.0 && chaos.rng.gen_bool(rate.min(1.0))
+        }
+        _ => false,
+    })
+}
+
+/// Called before each tree node write, failing it at the configured rate.
+pub fn tree_write() -> anyhow::Result<()> {
+    if strikes(|config| config.tree_write_failure_rate) {
+        bail!("chaos: tree write failed");
+    }
+    Ok(())
+}
+
+/// How long the next finalization waits before proving.
+pub fn proving_delay() -> Duration {
+    CHAOS.with(|chaos| match &mut *chaos.borrow_mut() {
+        Some(chaos) if chaos.calm == 0 && !chaos.config.max_proving_delay.is_zero() => chaos
+            .rng
+            .gen_range(Duration::ZERO..=chaos.config.max_proving_delay),
+        _ => Duration::ZERO,
+    })
+}
+
+/// Whether the next request is dropped.
+pub fn drop_request() -> bool {
+    strikes(|config| config.request_drop_rate)
+}
+
+fn adjust_calm(adjust: impl FnOnce(usize) -> usize) {
+    CHAOS.with(|chaos| {
+        if let Some(chaos) = &mut *chaos.borrow_mut() {
+            chaos.calm = adjust(chaos.calm);
+        }
+    })
+}
+
+/// Leaves a [`calm`] section, even if it panics.
+struct CalmGuard;
+
+impl Drop for CalmGuard {
+    fn drop(&mut self) {
+        adjust_calm(|calm| calm.saturating_sub(1));
+    }
+}
+
+/// Runs `f` without injecting faults.
+pub fn calm<T>(f: impl FnOnce() -> T) -> T {
+    adjust_calm(|calm| calm + 1);
+    let _guard = CalmGuard;
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use super::{install, uninstall, ChaosConfig};
+    use crate::{
+        balance::weight::Weight,
+        proposal::{
+            lock::ProposalLock, rules::ProposalRules, sanity::check_tree, store::ProposalStore,
+            Proposal, ProposalStatus,
+        },
+        utils::time::unix_timestamp,
+    };
+
+    #[tokio::test]
+    async fn test_failing_tree_writes_never_break_the_update_chain() -> anyhow::Result<()> {
+        let id = Uuid::new_v4();
+        let mut store = ProposalStore::new();
+        store.insert(
+            id,
+            Proposal::with_voter_balances(
+                "Fund the audit".to_string(),
+                0,
+                0,
+                ProposalRules::default(),
+                vec![Weight::from(1); 64],
+            )?,
+        );
+        let lock = Arc::new(ProposalLock::new(store));
+        // The runtime of the test is single threaded, so its tasks run under chaos
+        install(ChaosConfig {
+            tree_write_failure_rate: 0.02,
+            seed: 7,
+            ..Default::default()
+        });
+        let mut accepted = 0;
+        for voter_id in 0..64 {
+            let task_lock = lock.clone();
+            // Votes the way the vote handler does, which panics if the tree cannot be written
+            let vote = tokio::spawn(async move {
+                let mut proposals = task_lock.write().await;
+                let proposal = proposals.get_mut(&id).unwrap();
+                proposal
+                    .cast_vote(voter_id, voter_id % 3 != 0, None, unix_timestamp())
+                    .unwrap();
+                proposals.set_status(&id, ProposalStatus::Open).ok();
+            });
+            if vote.await.is_ok() {
+                accepted += 1;
+            }
+        }
+        uninstall();
+        assert!(
+            accepted < 64,
+            "no tree write failed, raise the failure rate"
+        );
+
+        let proposals = lock.read().await;
+        let proposal = proposals.get(&id).unwrap();
+        assert_eq!(check_tree(&proposal.storage, &proposal.updates)?, None);
+        assert_eq!(proposal.updates.len(), accepted);
+        assert_eq!(proposal.voted.len(), accepted);
+        let tally = proposal.storage.tally()?;
+        assert_eq!(
+            tally.yes_votes.get() + tally.no_votes.get(),
+            accepted as u64
+        );
+        Ok(())
+    }
+}
@@ -1 +1,9 @@
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod pause;
+pub mod range;
+pub mod rate_limit;
+pub mod request_id;
+pub mod supervisor;
+pub mod time;
 pub mod zmt;
@@ -0,0 +1,121 @@
+//! The emergency pause of a server. While paused, the server rejects every
+//! mutating request and keeps serving reads and proof downloads, so an incident
+//! can be investigated without the state moving under it. The switch is kept in
+//! a file when one is given, so that a paused server restarts paused.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Whether the server is paused, why and since when.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PauseState {
+    pub paused: bool,
+    /// Reason given by the admin who last paused the server.
+    pub reason: Option<String>,
+    /// When the server was last paused or unpaused, 0 if it never was.
+    pub changed_at: u64,
+}
+
+pub struct PauseSwitch {
+    /// Read by every request, so they do not contend on `state`.
+    paused: AtomicBool,
+    state: Mutex<PauseState>,
+    path: Option<PathBuf>,
+}
+
+impl PauseSwitch {
+    /// A switch that starts unpaused and is forgotten on restart.
+    pub fn in_memory() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            state: Mutex::new(PauseState::default()),
+            path: None,
+        }
+    }
+    /// Opens the switch kept at `path`, unpaused if the file does not exist yet.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let state: PauseState = if path.exists() {
+            serde_json::from_slice(&fs::read(path)?)?
+        } else {
+            PauseState::default()
+        };
+        Ok(Self {
+            paused: AtomicBool::new(state.paused),
+            state: Mutex::new(state),
+            path: Some(path.to_path_buf()),
+        })
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+    pub fn state(&self) -> PauseState {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+    /// Pauses or unpauses the server. The new state is written to the file of the
+    /// switch, through a temporary file renamed over it, before it takes effect,
+    /// so a failed write leaves the switch as it was.
+    pub fn set(
+        &self,
+        paused: bool,
+        reason: Option<String>,
+        changed_at: u64,
+    ) -> anyhow::Result<PauseState> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let next = PauseState {
+            paused,
+            reason: reason.filter(|_| paused),
+            changed_at,
+        };
+        if let Some(path) = &self.path {
+            let temporary = path.with_extension("tmp");
+            fs::write(&temporary, serde_json::to_vec(&next)?)?;
+            fs::rename(&temporary, path)?;
+        }
+        self.paused.store(paused, Ordering::SeqCst);
+        *state = next.clone();
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::PauseSwitch;
+
+    #[test]
+    fn test_pause_survives_reopening() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("qed-pause-{}.json", Uuid::new_v4()));
+        let switch = PauseSwitch::open(&path)?;
+        assert!(!switch.is_paused());
+        switch.set(true, Some("Investigating a leak".to_string()), 5)?;
+        assert!(switch.is_paused());
+
+        let reopened = PauseSwitch::open(&path)?;
+        assert!(reopened.is_paused());
+        assert_eq!(
+            reopened.state().reason.as_deref(),
+            Some("Investigating a leak")
+        );
+        let state = reopened.set(false, Some("ignored".to_string()), 9)?;
+        assert_eq!(
+            (state.paused, state.reason, state.changed_at),
+            (false, None, 9)
+        );
+        assert!(!PauseSwitch::open(&path)?.is_paused());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Single byte ranges of the `Range` header (RFC 9110, section 14), which let
+//! clients resume a download where it stopped.
+
+/// What a `Range` header asks of a representation of `total` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The whole representation, for headers that are missing, malformed, not
+    /// in bytes or ask for several ranges, which servers may ignore.
+    Full,
+    /// The bytes from `start` to `end`, both included.
+    Partial { start: u64, end: u64 },
+    /// The range starts past the end of the representation.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses `header`, e.g. `bytes=0-1023`, `bytes=1024-` or `bytes=-512`, against
+    /// a representation of `total` bytes, clamping the end of the range to it.
+    pub fn parse(header: &str, total: u64) -> Self {
+        Self::parse_single(header, total).unwrap_or(Self::Full)
+    }
+    fn parse_single(header: &str, total: u64) -> Option<Self> {
+        let (unit, spec) = header.trim().split_once('=')?;
+        if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+            return None;
+        }
+        let (first, last) = spec.trim().split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+        let range = match (first.is_empty(), last.is_empty()) {
+            // The last `suffix` bytes
+            (true, false) => match last.parse::<u64>().ok()? {
+                0 => Self::Unsatisfiable,
+                _ if total == 0 => Self::Unsatisfiable,
+                suffix => Self::Partial {
+                    start: total.saturating_sub(suffix),
+                    end: total - 1,
+                },
+            },
+            (false, true) => match first.parse::<u64>().ok()? {
+                start if start >= total => Self::Unsatisfiable,
+                start => Self::Partial {
+                    start,
+                    end: total - 1,
+                },
+            },
+            (false, false) => {
+                let (start, end) = (first.parse::<u64>().ok()?, last.parse::<u64>().ok()?);
+                if start > end {
+                    return None;
+                }
+                if start >= total {
+                    Self::Unsatisfiable
+                } else {
+                    Self::Partial {
+                        start,
+                        end: end.min(total - 1),
+                    }
+                }
+            }
+            (true, true) => return None,
+        };
+        Some(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn test_parse_byte_ranges() {
+        let partial = |start, end| ByteRange::Partial { start, end };
+        assert_eq!(ByteRange::parse("bytes=0-99", 1000), partial(0, 99));
+        assert_eq!(ByteRange::parse("bytes=900-", 1000), partial(900, 999));
+        assert_eq!(ByteRange::parse("bytes=900-5000", 1000), partial(900, 999));
+        assert_eq!(ByteRange::parse("bytes=-100", 1000), partial(900, 999));
+        assert_eq!(ByteRange::parse("bytes=-5000", 1000), partial(0, 999));
+        assert_eq!(
+            ByteRange::parse("bytes=1000-", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(ByteRange::parse("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=-10", 0), ByteRange::Unsatisfiable);
+        for ignored in [
+            "bytes=5-1",
+            "bytes=0-1,5-9",
+            "items=0-1",
+            "bytes=a-",
+            "bytes=-",
+        ] {
+            assert_eq!(ByteRange::parse(ignored, 1000), ByteRange::Full);
+        }
+    }
+}
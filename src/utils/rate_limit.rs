@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Buckets are pruned once there are more than this many, dropping the full ones.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket rate limiter with one bucket per key, e.g. per voter or per client IP.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allows bursts of `per_minute` requests per key, refilled evenly over a minute.
+    pub fn per_minute(per_minute: u32) -> Self {
+        Self::new(per_minute as f64, per_minute as f64 / 60.0)
+    }
+
+    pub fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_sec,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of every key, or from none of them.
+    /// On failure returns how long to wait until all buckets have a token again.
+    pub fn try_acquire(&self, keys: &[String], now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() > PRUNE_THRESHOLD {
+            let (capacity, refill_per_sec) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated_at);
+                bucket.tokens + elapsed.as_secs_f64() * refill_per_sec < capacity
+            });
+        }
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: self.capacity,
+                updated_at: now,
+            });
+            let elapsed = now.saturating_duration_since(bucket.updated_at);
+            bucket.tokens =
+                (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+            bucket.updated_at = now;
+            if bucket.tokens < 1.0 {
+                let missing = 1.0 - bucket.tokens;
+                let key_wait = if self.refill_per_sec > 0.0 {
+                    Duration::from_secs_f64(missing / self.refill_per_sec)
+                } else {
+                    Duration::MAX
+                };
+                wait = wait.max(key_wait);
+            }
+        }
+        if wait > Duration::ZERO {
+            return Err(wait);
+        }
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::per_minute(2);
+        let now = Instant::now();
+        let ip = vec!["ip:127.0.0.1".to_string()];
+        let voter = vec!["ip:127.0.0.1".to_string(), "voter:7".to_string()];
+
+        assert!(limiter.try_acquire(&voter, now).is_ok());
+        assert!(limiter.try_acquire(&ip, now).is_ok());
+        // The shared ip bucket is empty, so the voter bucket must not be charged
+        let wait = limiter.try_acquire(&voter, now).unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+        assert!(limiter.try_acquire(&["voter:7".to_string()], now).is_ok());
+        assert!(limiter
+            .try_acquire(&voter, now + Duration::from_secs(15))
+            .is_err());
+        assert!(limiter
+            .try_acquire(&voter, now + Duration::from_secs(60))
+            .is_ok());
+    }
+}
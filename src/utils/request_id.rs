@@ -0,0 +1,108 @@
+//! Ids tying together what the server does for one request. The id comes from
+//! the `X-Request-Id` header of the request, or is generated when it has none
+//! or an unusable one, and is returned in the same header of the response.
+//!
+//! The id of the request being handled is kept in a task local, so that the
+//! code recording events does not need it passed down. Work the request leaves
+//! to background tasks, such as proving deposits and anchoring roots, is done
+//! after the request returned, so the id of the last request changing each
+//! proposal is kept in [`RequestTraces`] for those tasks to log under.
+
+use std::{collections::HashMap, fmt, future::Future};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Header the id of a request is read from and returned in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest id accepted from a client.
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+    /// Accepts ids of up to [`MAX_REQUEST_ID_LEN`] letters, digits and `.`, `_`,
+    /// `:` or `-`, which are safe to log and to send back in a header.
+    pub fn parse(id: &str) -> Option<Self> {
+        let is_valid = !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | '-'));
+        is_valid.then(|| Self(id.to_string()))
+    }
+    /// The id a client sent in `header`, or a new one.
+    pub fn from_header(header: Option<&str>) -> Self {
+        header.and_then(Self::parse).unwrap_or_else(Self::generate)
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    /// Runs `future` as the handling of this request.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+    /// The id of the request being handled by the current task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The id of the last request changing each proposal.
+#[derive(Debug, Default)]
+pub struct RequestTraces {
+    by_proposal: HashMap<Uuid, RequestId>,
+}
+
+impl RequestTraces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn record(&mut self, proposal_id: Uuid, request_id: RequestId) {
+        self.by_proposal.insert(proposal_id, request_id);
+    }
+    pub fn get(&self, proposal_id: &Uuid) -> Option<RequestId> {
+        self.by_proposal.get(proposal_id).cloned()
+    }
+    pub fn remove(&mut self, proposal_id: &Uuid) {
+        self.by_proposal.remove(proposal_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestId, MAX_REQUEST_ID_LEN};
+
+    #[tokio::test]
+    async fn test_request_id_is_parsed_and_scoped() {
+        assert_eq!(
+            RequestId::parse("req-1.a_b:c").unwrap().as_str(),
+            "req-1.a_b:c"
+        );
+        assert!(RequestId::parse("").is_none());
+        assert!(RequestId::parse("line\nbreak").is_none());
+        assert!(RequestId::parse(&"a".repeat(MAX_REQUEST_ID_LEN + 1)).is_none());
+        assert_ne!(RequestId::from_header(Some("bad id")).as_str(), "bad id");
+
+        assert!(RequestId::current().is_none());
+        let id = RequestId::parse("req-1").unwrap();
+        let current = id.clone().scope(async { RequestId::current() }).await;
+        assert_eq!(current, Some(id));
+    }
+}
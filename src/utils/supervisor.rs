@@ -0,0 +1,174 @@
+use std::{future::Future, time::Duration};
+
+use tokio::{
+    sync::watch,
+    task::{spawn_local, JoinHandle},
+    time::{sleep, timeout, Instant},
+};
+use tracing::error;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Handed to every supervised task so it can stop at a convenient point once
+/// the supervisor shuts down.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_shutdown(&self) -> bool {
+        *self.0.borrow()
+    }
+    /// Resolves once shutdown has been requested, or the supervisor was dropped.
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Aborts the wrapped task when dropped, so that aborting a supervisor loop
+/// also stops the run it is waiting on.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Owns the background tasks of the server. Tasks that fail or panic are
+/// restarted with exponential backoff until the supervisor shuts down.
+///
+/// Tasks are spawned on the current `LocalSet`, so the supervisor has to be
+/// used from within the actix runtime.
+pub struct TaskSupervisor {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<(String, JoinHandle<()>)>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl TaskSupervisor {
+    pub fn new() -> Self {
+        Self::with_backoff(INITIAL_BACKOFF, MAX_BACKOFF)
+    }
+
+    pub fn with_backoff(initial_backoff: Duration, max_backoff: Duration) -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            shutdown,
+            tasks: vec![],
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.shutdown.subscribe())
+    }
+
+    /// Registers a task. `task` is called again with a fresh signal every time
+    /// the previous run failed; a run that returns `Ok` is not restarted.
+    pub fn spawn<T, Fut>(&mut self, name: &str, task: T)
+    where
+        T: Fn(ShutdownSignal) -> Fut + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + 'static,
+    {
+        let signal = self.signal();
+        let task_name = name.to_string();
+        let (initial_backoff, max_backoff) = (self.initial_backoff, self.max_backoff);
+        let handle = spawn_local(async move {
+            let mut backoff = initial_backoff;
+            loop {
+                let started_at = Instant::now();
+                let mut run = AbortOnDrop(spawn_local(task(signal.clone())));
+                let error = match (&mut run.0).await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(err)) => err.to_string(),
+                    Err(err) if err.is_panic() => "task panicked".to_string(),
+                    Err(_) => return,
+                };
+                if signal.is_shutdown() {
+                    return;
+                }
+                // A task that ran for a while before failing starts over with a short backoff
+                if started_at.elapsed() > max_backoff {
+                    backoff = initial_backoff;
+                }
+                error!(
+                    task = %task_name,
+                    ?backoff,
+                    "Background task failed, restarting: {}",
+                    error
+                );
+                let mut shutdown = signal.clone();
+                tokio::select! {
+                    _ = sleep(backoff) => {}
+                    _ = shutdown.wait() => return,
+                }
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        });
+        self.tasks.push((name.to_string(), handle));
+    }
+
+    /// Signals all tasks to stop and waits up to `grace_period` for them to
+    /// finish. Tasks still running afterwards are aborted, and their names returned.
+    pub async fn shutdown(self, grace_period: Duration) -> Vec<String> {
+        let _ = self.shutdown.send(true);
+        let deadline = Instant::now() + grace_period;
+        let mut aborted = vec![];
+        for (name, mut handle) in self.tasks {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if timeout(remaining, &mut handle).await.is_err() {
+                handle.abort();
+                aborted.push(name);
+            }
+        }
+        aborted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    #[test]
+    fn test_supervisor_restarts_and_shuts_down() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&runtime, async {
+            let runs = Rc::new(Cell::new(0));
+            let mut supervisor =
+                TaskSupervisor::with_backoff(Duration::from_millis(1), Duration::from_millis(10));
+            let task_runs = runs.clone();
+            supervisor.spawn("flaky", move |mut shutdown| {
+                let runs = task_runs.clone();
+                async move {
+                    runs.set(runs.get() + 1);
+                    if runs.get() < 3 {
+                        anyhow::bail!("run {} failed", runs.get());
+                    }
+                    shutdown.wait().await;
+                    Ok(())
+                }
+            });
+            supervisor.spawn("stuck", |_| async {
+                sleep(Duration::from_secs(3600)).await;
+                Ok(())
+            });
+            sleep(Duration::from_millis(100)).await;
+            assert_eq!(runs.get(), 3);
+            let aborted = supervisor.shutdown(Duration::from_millis(50)).await;
+            assert_eq!(aborted, vec!["stuck".to_string()]);
+        });
+    }
+}
@@ -0,0 +1,110 @@
+use std::{num::NonZeroUsize, path::Path};
+
+use plonky2::hash::hash_types::RichField;
+
+use crate::common::WHashOut;
+
+use super::{core::ZMTNodeStore, kv_node_store::KvNodeStore, simple_node_store::SimpleNodeStore};
+
+/// Bytes a stored node takes up on disk, ignoring the overhead of the store itself:
+/// a 9 byte (level, index) key and four 8 byte elements.
+pub const NODE_ENTRY_BYTES: u64 = 9 + 32;
+
+/// A node store of either backend, so trees can be typed independently of the deployment.
+pub enum NodeStore {
+    Memory(SimpleNodeStore),
+    Kv(KvNodeStore),
+}
+
+impl NodeStore {
+    pub fn len(&self) -> usize {
+        match self {
+            NodeStore::Memory(store) => store.len(),
+            NodeStore::Kv(store) => store.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Drops the nodes `keep` rejects, returning how many were dropped.
+    pub fn retain(&mut self, keep: impl Fn(u8, u64) -> bool) -> anyhow::Result<usize> {
+        match self {
+            NodeStore::Memory(store) => Ok(store.retain(keep)),
+            NodeStore::Kv(store) => store.retain(keep),
+        }
+    }
+    /// Size of the stored nodes, used for storage accounting.
+    pub fn approximate_bytes(&self) -> u64 {
+        self.len() as u64 * NODE_ENTRY_BYTES
+    }
+}
+
+impl<F: RichField> ZMTNodeStore<F> for NodeStore {
+    fn set_node(
+        &mut self,
+        level: u8,
+        index: u64,
+        node: &WHashOut<F>,
+    ) -> anyhow::Result<Option<WHashOut<F>>> {
+        #[cfg(feature = "chaos")]
+        crate::utils::chaos::tree_write()?;
+        match self {
+            NodeStore::Memory(store) => store.set_node(level, index, node),
+            NodeStore::Kv(store) => store.set_node(level, index, node),
+        }
+    }
+    fn get_node(&self, level: u8, index: u64) -> anyhow::Result<Option<WHashOut<F>>> {
+        match self {
+            NodeStore::Memory(store) => store.get_node(level, index),
+            NodeStore::Kv(store) => store.get_node(level, index),
+        }
+    }
+    fn remove_node(&mut self, level: u8, index: u64) -> anyhow::Result<()> {
+        #[cfg(feature = "chaos")]
+        crate::utils::chaos::tree_write()?;
+        match self {
+            NodeStore::Memory(store) => {
+                <SimpleNodeStore as ZMTNodeStore<F>>::remove_node(store, level, index)
+            }
+            NodeStore::Kv(store) => {
+                <KvNodeStore as ZMTNodeStore<F>>::remove_node(store, level, index)
+            }
+        }
+    }
+}
+
+/// Where the trees of a deployment keep their nodes.
+pub enum NodeStoreBackend {
+    Memory,
+    Kv {
+        db: sled::Db,
+        cache_capacity: NonZeroUsize,
+    },
+}
+
+impl NodeStoreBackend {
+    pub fn open_kv(path: &Path, cache_capacity: NonZeroUsize) -> anyhow::Result<Self> {
+        Ok(Self::Kv {
+            db: sled::open(path)?,
+            cache_capacity,
+        })
+    }
+    /// Opens the store of a single tree; `namespace` must be unique per tree.
+    pub fn open_store(&self, namespace: &str) -> anyhow::Result<NodeStore> {
+        match self {
+            NodeStoreBackend::Memory => Ok(NodeStore::Memory(SimpleNodeStore::new())),
+            NodeStoreBackend::Kv { db, cache_capacity } => Ok(NodeStore::Kv(KvNodeStore::new(
+                db.open_tree(namespace)?,
+                *cache_capacity,
+            ))),
+        }
+    }
+    /// Opens the store of a single tree like [`Self::open_store`], dropping the nodes
+    /// it held, for a tree that is rebuilt from scratch.
+    pub fn open_empty_store(&self, namespace: &str) -> anyhow::Result<NodeStore> {
+        if let NodeStoreBackend::Kv { db, .. } = self {
+            db.drop_tree(namespace)?;
+        }
+        self.open_store(namespace)
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use lru::LruCache;
+use plonky2::hash::hash_types::RichField;
+
+use crate::common::WHashOut;
+
+use super::{
+    core::ZMTNodeStore,
+    simple_node_store::{u64_array_to_whashout, whashout_to_u64_array},
+};
+
+fn encode_key(level: u8, index: u64) -> [u8; 9] {
+    let mut key = [0u8; 9];
+    key[0] = level;
+    key[1..].copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn encode_node(node: &[u64; 4]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (chunk, value) in bytes.chunks_exact_mut(8).zip(node.iter()) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+fn decode_node(bytes: &[u8]) -> anyhow::Result<[u64; 4]> {
+    anyhow::ensure!(bytes.len() == 32, "corrupt node of {} bytes", bytes.len());
+    let mut node = [0u64; 4];
+    for (value, chunk) in node.iter_mut().zip(bytes.chunks_exact(8)) {
+        *value = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    Ok(node)
+}
+
+/// Stores tree nodes in an on-disk key value store, keeping recently used nodes
+/// (including absent ones, which are the common case in a sparse tree) in an LRU cache.
+pub struct KvNodeStore {
+    tree: sled::Tree,
+    cache: Mutex<LruCache<(u8, u64), Option<[u64; 4]>>>,
+    /// Number of nodes in `tree`, counted once on open and kept up to date on writes.
+    len: usize,
+}
+
+impl KvNodeStore {
+    pub fn new(tree: sled::Tree, cache_capacity: NonZeroUsize) -> Self {
+        Self {
+            len: tree.len(),
+            tree,
+            cache: Mutex::new(LruCache::new(cache_capacity)),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Drops the nodes `keep` rejects, returning how many were dropped.
+    pub fn retain(&mut self, keep: impl Fn(u8, u64) -> bool) -> anyhow::Result<usize> {
+        let mut dropped = vec![];
+        for key in self.tree.iter().keys() {
+            let key = key?;
+            anyhow::ensure!(key.len() == 9, "corrupt key of {} bytes", key.len());
+            let (level, index) = (key[0], u64::from_be_bytes(key[1..].try_into().unwrap()));
+            if !keep(level, index) {
+                dropped.push((level, index));
+            }
+        }
+        for (level, index) in &dropped {
+            self.tree.remove(encode_key(*level, *index))?;
+            self.cache().put((*level, *index), None);
+            self.len -= 1;
+        }
+        Ok(dropped.len())
+    }
+    fn cache(&self) -> MutexGuard<'_, LruCache<(u8, u64), Option<[u64; 4]>>> {
+        // The cache only mirrors the tree, so a poisoned one is still consistent
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn load(&self, level: u8, index: u64) -> anyhow::Result<Option<[u64; 4]>> {
+        if let Some(node) = self.cache().get(&(level, index)) {
+            return Ok(*node);
+        }
+        let node = self
+            .tree
+            .get(encode_key(level, index))?
+            .map(|bytes| decode_node(&bytes))
+            .transpose()?;
+        self.cache().put((level, index), node);
+        Ok(node)
+    }
+}
+
+impl<F: RichField> ZMTNodeStore<F> for KvNodeStore {
+    fn set_node(
+        &mut self,
+        level: u8,
+        index: u64,
+        node: &WHashOut<F>,
+    ) -> anyhow::Result<Option<WHashOut<F>>> {
+        let old_node = self.load(level, index)?;
+        let node = whashout_to_u64_array(node);
+        self.tree
+            .insert(encode_key(level, index), &encode_node(&node)[..])?;
+        self.cache().put((level, index), Some(node));
+        if old_node.is_none() {
+            self.len += 1;
+        }
+        Ok(old_node.map(|node| u64_array_to_whashout(&node)))
+    }
+    fn get_node(&self, level: u8, index: u64) -> anyhow::Result<Option<WHashOut<F>>> {
+        Ok(self
+            .load(level, index)?
+            .map(|node| u64_array_to_whashout(&node)))
+    }
+    fn remove_node(&mut self, level: u8, index: u64) -> anyhow::Result<()> {
+        if self.tree.remove(encode_key(level, index))?.is_some() {
+            self.len -= 1;
+        }
+        self.cache().put((level, index), None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
+
+    use crate::{
+        common::WHashOut,
+        utils::zmt::{
+            node_store::simple_node_store::SimpleNodeStore, zero_merkle_tree::ZeroMerkleTree,
+        },
+    };
+
+    use super::KvNodeStore;
+
+    type F = GoldilocksField;
+    type H = PoseidonHash;
+
+    #[test]
+    fn test_kv_node_store_matches_memory_store() -> anyhow::Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        // A tiny cache forces most reads to go to disk
+        let store = KvNodeStore::new(db.open_tree("test")?, NonZeroUsize::new(4).unwrap());
+        let mut kv_tree = ZeroMerkleTree::<F, H, KvNodeStore>::new(32, store);
+        let mut memory_tree =
+            ZeroMerkleTree::<F, H, SimpleNodeStore>::new(32, SimpleNodeStore::new());
+        for (index, value) in [(1, 5), (1000, 7), (1, 9)] {
+            let a = kv_tree.set_leaf(index, WHashOut::from_values(value, 0, 0, 0))?;
+            let b = memory_tree.set_leaf(index, WHashOut::from_values(value, 0, 0, 0))?;
+            assert_eq!(a, b);
+        }
+        assert_eq!(kv_tree.get_leaf(1000)?, memory_tree.get_leaf(1000)?);
+        Ok(())
+    }
+}
@@ -1,2 +1,4 @@
+pub mod backend;
 pub mod core;
+pub mod kv_node_store;
 pub mod simple_node_store;
\ No newline at end of file
@@ -44,7 +44,7 @@ pub struct SimpleNodeStore {
     nodes: BTreeMap<NodeStoreKey, [u64; 4]>,
 }
 
-fn u64_array_to_whashout<F: RichField>(arr: &[u64; 4])->WHashOut<F>{
+pub(crate) fn u64_array_to_whashout<F: RichField>(arr: &[u64; 4])->WHashOut<F>{
     WHashOut(HashOut::<F>{
         elements: [
             F::from_canonical_u64(arr[0]),
@@ -54,7 +54,7 @@ fn u64_array_to_whashout<F: RichField>(arr: &[u64; 4])->WHashOut<F>{
         ]
     })
 }
-fn whashout_to_u64_array<F: RichField>(hash: &WHashOut<F>)->[u64; 4] {
+pub(crate) fn whashout_to_u64_array<F: RichField>(hash: &WHashOut<F>)->[u64; 4] {
     [
         hash.0.elements[0].to_canonical_u64(),
         hash.0.elements[1].to_canonical_u64(),
@@ -68,6 +68,18 @@ impl SimpleNodeStore {
             nodes: BTreeMap::new(),
         }
     }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    /// Drops the nodes `keep` rejects, returning how many were dropped.
+    pub fn retain(&mut self, keep: impl Fn(u8, u64) -> bool) -> usize {
+        let before = self.nodes.len();
+        self.nodes.retain(|key, _| keep(key.level, key.index));
+        before - self.nodes.len()
+    }
 }
 impl<F: RichField> ZMTNodeStore<F> for SimpleNodeStore {
     fn set_node(&mut self, level: u8, index: u64, node: &WHashOut<F>)-> anyhow::Result<Option<WHashOut<F>>>  {
@@ -87,4 +99,8 @@ impl<F: RichField> ZMTNodeStore<F> for SimpleNodeStore {
             Ok(None)
         }
     }
+    fn remove_node(&mut self, level: u8, index: u64)->anyhow::Result<()> {
+        self.nodes.remove(&NodeStoreKey{level, index});
+        Ok(())
+    }
 }
\ No newline at end of file
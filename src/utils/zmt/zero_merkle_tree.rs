@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use plonky2::hash::hash_types::RichField;
 
 use crate::common::{
@@ -17,6 +19,8 @@ pub struct ZeroMerkleTree<F: RichField, H: WHasher<F>, S: ZMTNodeStore<F>> {
     height: u8,
     zero_hashes: Vec<WHashOut<F>>,
     store: S,
+    /// Nodes written since [`Self::begin`], with what each held before, see [`Self::rollback`].
+    undo_log: Option<Vec<(u8, u64, Option<WHashOut<F>>)>>,
     _field: std::marker::PhantomData<F>,
     _hasher: std::marker::PhantomData<H>,
 }
@@ -28,10 +32,32 @@ impl<F: RichField, H: WHasher<F>, S: ZMTNodeStore<F>> ZeroMerkleTree<F, H, S> {
             height,
             store,
             zero_hashes: compute_zero_hashes::<F, H>(height),
+            undo_log: None,
             _field: std::marker::PhantomData,
             _hasher: std::marker::PhantomData,
         }
     }
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+    /// Note: writing nodes through the store directly leaves the tree inconsistent,
+    /// this is meant for dropping nodes that are no longer read, see [`Self::proof_nodes`].
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+    /// The nodes `get_leaf` reads to prove each of `leaves`, the root included.
+    pub fn proof_nodes(&self, leaves: &[u64]) -> BTreeSet<(u8, u64)> {
+        let mut nodes = BTreeSet::from([(0, 0)]);
+        for leaf in leaves {
+            nodes.insert((self.height, *leaf));
+            let mut current_index = *leaf;
+            for level in (1..=self.height).rev() {
+                nodes.insert((level, current_index ^ 1));
+                current_index = current_index >> 1;
+            }
+        }
+        nodes
+    }
     fn get_node_or_zero(&self, level: u8, index: u64) -> anyhow::Result<WHashOut<F>> {
         self.store
             .get_node(level, index)
@@ -43,9 +69,39 @@ impl<F: RichField, H: WHasher<F>, S: ZMTNodeStore<F>> ZeroMerkleTree<F, H, S> {
         index: u64,
         value: &WHashOut<F>,
     ) -> anyhow::Result<WHashOut<F>> {
-        self.store
-            .set_node(level, index, value)
-            .map(|node| node.unwrap_or_else(|| self.zero_hashes[(self.height - level) as usize]))
+        let old_node = self.store.set_node(level, index, value)?;
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push((level, index, old_node));
+        }
+        Ok(old_node.unwrap_or_else(|| self.zero_hashes[(self.height - level) as usize]))
+    }
+    /// Starts logging the nodes written, so that they can all be undone by
+    /// [`Self::rollback`], e.g. when a write fails halfway through a leaf.
+    pub fn begin(&mut self) {
+        self.undo_log = Some(vec![]);
+    }
+    /// Whether writes are being logged since [`Self::begin`].
+    pub fn in_transaction(&self) -> bool {
+        self.undo_log.is_some()
+    }
+    /// Keeps the writes since [`Self::begin`] and stops logging them.
+    pub fn commit(&mut self) {
+        self.undo_log = None;
+    }
+    /// Puts back every node written since [`Self::begin`] as it was, in reverse
+    /// order, which leaves the tree at the root it had then. A node that was not
+    /// stored is removed again rather than set to its zero hash.
+    pub fn rollback(&mut self) -> anyhow::Result<()> {
+        let undo_log = self.undo_log.take().unwrap_or_default();
+        for (level, index, old_node) in undo_log.into_iter().rev() {
+            match old_node {
+                Some(node) => {
+                    self.store.set_node(level, index, &node)?;
+                }
+                None => self.store.remove_node(level, index)?,
+            }
+        }
+        Ok(())
     }
     pub fn set_leaf(
         &mut self,
@@ -111,6 +167,9 @@ impl<F: RichField, H: WHasher<F>, S: ZMTNodeStore<F>> ZeroMerkleTree<F, H, S> {
             siblings: siblings,
         })
     }
+    pub fn get_root(&self) -> anyhow::Result<WHashOut<F>> {
+        self.get_node_or_zero(0, 0)
+    }
     pub fn get_height(&self) -> u8 {
         self.height
     }
@@ -124,12 +183,32 @@ impl<F: RichField, H: WHasher<F>, S: ZMTNodeStore<F>> ZeroMerkleTree<F, H, S> {
 mod tests {
     use plonky2::{field::goldilocks_field::GoldilocksField, hash::poseidon::PoseidonHash};
 
-    use crate::{common::{WHashOut, hash::merkle::helpers::merkle_proof::MerkleProof}, utils::zmt::node_store::simple_node_store::SimpleNodeStore};
+    use crate::{common::{WHashOut, hash::merkle::helpers::merkle_proof::MerkleProof}, utils::zmt::node_store::{core::ZMTNodeStore, simple_node_store::SimpleNodeStore}};
 
     use super::ZeroMerkleTree;
 
     type F = GoldilocksField;
     type H = PoseidonHash;
+
+    /// Fails every write once `writes_left` runs out.
+    struct FailingStore {
+        nodes: SimpleNodeStore,
+        writes_left: usize,
+    }
+
+    impl ZMTNodeStore<F> for FailingStore {
+        fn set_node(&mut self, level: u8, index: u64, node: &WHashOut<F>) -> anyhow::Result<Option<WHashOut<F>>> {
+            anyhow::ensure!(self.writes_left > 0, "store is full");
+            self.writes_left -= 1;
+            self.nodes.set_node(level, index, node)
+        }
+        fn get_node(&self, level: u8, index: u64) -> anyhow::Result<Option<WHashOut<F>>> {
+            self.nodes.get_node(level, index)
+        }
+        fn remove_node(&mut self, level: u8, index: u64) -> anyhow::Result<()> {
+            ZMTNodeStore::<F>::remove_node(&mut self.nodes, level, index)
+        }
+    }
     #[test]
     fn test_zmt_basic() -> anyhow::Result<()> {
         let mut zmt = ZeroMerkleTree::<F, H, SimpleNodeStore>::new(32, SimpleNodeStore::new());
@@ -161,4 +240,48 @@ mod tests {
         );
         Ok(())
     }
+    #[test]
+    fn test_zmt_proves_retained_leaves() -> anyhow::Result<()> {
+        let mut zmt = ZeroMerkleTree::<F, H, SimpleNodeStore>::new(8, SimpleNodeStore::new());
+        for index in [0, 1, 5, 200] {
+            zmt.set_leaf(index, WHashOut::from_values(index + 1, 0, 0, 0))?;
+        }
+        let root = zmt.get_root()?;
+        let proof = zmt.get_leaf(1)?;
+        let keep = zmt.proof_nodes(&[0, 1]);
+        let dropped = zmt.store_mut().retain(|level, index| keep.contains(&(level, index)));
+        assert!(dropped > 0);
+        assert!(zmt.store().len() <= keep.len());
+        assert_eq!(zmt.get_root()?, root);
+        assert_eq!(zmt.get_leaf(1)?, proof);
+        assert_eq!(zmt.get_leaf_value(200)?, WHashOut::ZERO);
+        Ok(())
+    }
+    #[test]
+    fn test_zmt_rolls_back_half_written_leaves() -> anyhow::Result<()> {
+        let store = FailingStore { nodes: SimpleNodeStore::new(), writes_left: 100 };
+        let mut zmt = ZeroMerkleTree::<F, H, FailingStore>::new(8, store);
+        zmt.set_leaf(3, WHashOut::from_values(1, 0, 0, 0))?;
+        let root = zmt.get_root()?;
+        let stored = zmt.store().nodes.len();
+
+        zmt.begin();
+        zmt.set_leaf(3, WHashOut::from_values(2, 0, 0, 0))?;
+        // The store fills up halfway through the path of the second leaf
+        zmt.store_mut().writes_left = 4;
+        assert!(zmt.set_leaf(200, WHashOut::from_values(5, 0, 0, 0)).is_err());
+        zmt.store_mut().writes_left = 100;
+        zmt.rollback()?;
+        assert!(!zmt.in_transaction());
+        assert_eq!(zmt.get_root()?, root);
+        assert_eq!(zmt.get_leaf_value(3)?, WHashOut::from_values(1, 0, 0, 0));
+        assert_eq!(zmt.store().nodes.len(), stored);
+
+        zmt.begin();
+        zmt.set_leaf(200, WHashOut::from_values(5, 0, 0, 0))?;
+        zmt.commit();
+        assert!(zmt.rollback().is_ok());
+        assert_eq!(zmt.get_leaf_value(200)?, WHashOut::from_values(5, 0, 0, 0));
+        Ok(())
+    }
 }
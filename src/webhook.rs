@@ -0,0 +1,515 @@
+//! Callbacks to the systems of an organization when its proposals are finalized.
+//!
+//! The admins of an organization register [`WebhookTarget`]s, each with a secret
+//! shared with the target. Finalizing a proposal of the organization queues a
+//! [`WebhookDelivery`] of its result to every target, which a background task
+//! posts as JSON signed with [`sign_payload`]. Deliveries the target does not
+//! answer with a 2xx status are retried with exponential backoff, and are
+//! kept, delivered or not, for admins to inspect. Queued deliveries are kept in
+//! memory and do not survive a restart.
+//!
+//! Callbacks are only posted to public hosts: targets naming a loopback,
+//! private, link-local or otherwise internal address, such as the metadata
+//! endpoint of a cloud provider, are rejected, and the names of targets are
+//! only resolved to public addresses when a callback is sent.
+
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use web3::types::H256;
+
+use crate::{
+    balance::accounts::Tally,
+    errors::{ApiError, ApiErrorCode},
+    proof::{certificate::FinalizationCertificate, codec::ProofEnvelope},
+    proposal::rules::ProposalOutcome,
+};
+
+/// Most targets an organization can register.
+pub const MAX_WEBHOOKS: usize = 8;
+
+/// Most deliveries kept, the oldest finished ones being dropped first.
+pub const MAX_DELIVERIES: usize = 10_000;
+
+/// Event of the callbacks sent on finalization.
+pub const FINALIZED_EVENT: &str = "proposal.finalized";
+
+/// Longest a retry waits for, however many attempts failed before it.
+const MAX_RETRY_SECS: u64 = 3600;
+
+/// An endpoint the results of the proposals of an organization are posted to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct WebhookTarget {
+    /// `http://` or `https://` URL of a public host the callbacks are posted to.
+    pub url: String,
+    /// Key of the HMAC signing the callbacks, shared with the target.
+    pub secret: String,
+}
+
+/// Whether `ip` is reachable from the internet, rather than an address of the
+/// host itself, of a private network, or reserved.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8, shared address space 100.64.0.0/10 and reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64)
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local fc00::/7, link-local fe80::/10 and documentation 2001:db8::/32
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+/// Fails unless `url` is an HTTP URL whose host is a name or a public address.
+/// Names are checked again when they are resolved, see [`WebhookSender`].
+fn check_url(url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|err| format!("Invalid URL {}: {}", url, err))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{} is not an HTTP URL", url));
+    }
+    // IPv6 hosts are written in brackets
+    let host = url.host_str().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let is_public = match host.parse::<IpAddr>() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => {
+            let name = host.trim_end_matches('.').to_ascii_lowercase();
+            !name.is_empty() && name != "localhost" && !name.ends_with(".localhost")
+        }
+    };
+    if !is_public {
+        return Err(format!("{} is not a public host", url));
+    }
+    Ok(())
+}
+
+/// Fails unless there are at most [`MAX_WEBHOOKS`] targets, each with an HTTP
+/// URL of its own on a public host and a secret.
+pub fn validate_targets(targets: &[WebhookTarget]) -> Result<(), ApiError> {
+    if targets.len() > MAX_WEBHOOKS {
+        return Err(ApiError::new(
+            ApiErrorCode::InvalidWebhook,
+            format!("At most {} webhooks can be registered", MAX_WEBHOOKS),
+        ));
+    }
+    for (i, target) in targets.iter().enumerate() {
+        check_url(&target.url)
+            .map_err(|message| ApiError::new(ApiErrorCode::InvalidWebhook, message))?;
+        if target.secret.is_empty() {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidWebhook,
+                format!("The webhook {} has no secret", target.url),
+            ));
+        }
+        if targets[..i].iter().any(|other| other.url == target.url) {
+            return Err(ApiError::new(
+                ApiErrorCode::InvalidWebhook,
+                format!("{} is registered more than once", target.url),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The body of a callback on the finalization of a proposal.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FinalizationPayload {
+    /// [`FINALIZED_EVENT`]
+    pub event: String,
+    pub org_id: String,
+    pub proposal_id: Uuid,
+    pub outcome: ProposalOutcome,
+    pub tally: Tally,
+    /// SHA-256 digest of the proof bytes of the finalization proof.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    #[schema(value_type = String)]
+    pub proof_hash: [u8; 32],
+    /// Transaction of the last anchor of the roots of the proposal, if it was anchored.
+    #[schema(value_type = Option<String>)]
+    pub tx_hash: Option<H256>,
+    pub finalized_at: u64,
+}
+
+impl FinalizationPayload {
+    pub fn new(
+        org_id: &str,
+        certificate: &FinalizationCertificate,
+        proof: &ProofEnvelope,
+        finalized_at: u64,
+    ) -> Self {
+        Self {
+            event: FINALIZED_EVENT.to_string(),
+            org_id: org_id.to_string(),
+            proposal_id: certificate.proposal_id,
+            outcome: certificate.outcome,
+            tally: Tally {
+                yes_votes: certificate.yes_votes,
+                no_votes: certificate.no_votes,
+            },
+            proof_hash: Sha256::digest(&proof.proof_bytes).into(),
+            tx_hash: certificate.anchors.last().map(|anchor| anchor.tx_hash),
+            finalized_at,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not attempted yet, or to be retried.
+    Pending,
+    Delivered,
+    /// Given up on after the last attempt failed.
+    Failed,
+}
+
+/// A callback to one target, and how its attempts went.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub url: String,
+    pub payload: FinalizationPayload,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub created_at: u64,
+    /// When the next attempt is due, while the delivery is pending.
+    pub next_attempt_at: Option<u64>,
+    /// HTTP status the target last answered with.
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    #[serde(skip)]
+    secret: String,
+}
+
+/// The signature of a callback, sent in the `X-Qed-Signature` header: the hex
+/// encoded HMAC-SHA256, keyed with the secret of the target, of the timestamp
+/// sent in the `X-Qed-Timestamp` header, a dot and the body. Targets recompute
+/// it to check that a callback comes from the server and, by its timestamp,
+/// that it is not an old one replayed.
+pub fn sign_payload(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let message = [format!("{}.", timestamp).as_bytes(), body].concat();
+    hex::encode(hmac_sha256(secret.as_bytes(), &message))
+}
+
+/// HMAC-SHA256 as specified in RFC 2104.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// The deliveries of a server, in the order they were queued.
+pub struct WebhookQueue {
+    deliveries: VecDeque<WebhookDelivery>,
+    max_attempts: u32,
+    retry_secs: u64,
+}
+
+impl WebhookQueue {
+    /// A queue making at most `max_attempts` attempts of each delivery, the
+    /// first retry `retry_secs` after the first attempt.
+    pub fn new(max_attempts: u32, retry_secs: u64) -> Self {
+        Self {
+            deliveries: VecDeque::new(),
+            max_attempts: max_attempts.max(1),
+            retry_secs,
+        }
+    }
+    /// Queues a delivery of `payload` to each of `targets`, due right away.
+    pub fn enqueue(&mut self, targets: &[WebhookTarget], payload: &FinalizationPayload, now: u64) {
+        for target in targets {
+            self.deliveries.push_back(WebhookDelivery {
+                id: Uuid::new_v4(),
+                url: target.url.clone(),
+                payload: payload.clone(),
+                status: DeliveryStatus::Pending,
+                attempts: 0,
+                created_at: now,
+                next_attempt_at: Some(now),
+                response_status: None,
+                last_error: None,
+                secret: target.secret.clone(),
+            });
+        }
+        while self.deliveries.len() > MAX_DELIVERIES {
+            let finished = self
+                .deliveries
+                .iter()
+                .position(|delivery| delivery.status != DeliveryStatus::Pending);
+            match finished {
+                Some(position) => self.deliveries.remove(position),
+                None => break,
+            };
+        }
+    }
+    /// The pending deliveries whose next attempt is due at time `now`.
+    pub fn due(&self, now: u64) -> Vec<WebhookDelivery> {
+        self.deliveries
+            .iter()
+            .filter(|delivery| {
+                delivery.status == DeliveryStatus::Pending
+                    && delivery.next_attempt_at.map_or(false, |at| at <= now)
+            })
+            .cloned()
+            .collect()
+    }
+    /// Records an attempt of the delivery `id` at time `now`: the HTTP status the
+    /// target answered with, or why it could not be reached. A failed attempt
+    /// is retried after the retry delay, doubled for each attempt before it,
+    /// unless it was the last one.
+    pub fn record(&mut self, id: &Uuid, outcome: Result<u16, String>, now: u64) {
+        let delivery = match self
+            .deliveries
+            .iter_mut()
+            .find(|delivery| delivery.id == *id)
+        {
+            Some(delivery) => delivery,
+            None => return,
+        };
+        delivery.attempts += 1;
+        let error = match outcome {
+            Ok(status) => {
+                delivery.response_status = Some(status);
+                (!(200..300).contains(&status)).then(|| format!("Answered with HTTP {}", status))
+            }
+            Err(err) => Some(err),
+        };
+        match error {
+            None => {
+                delivery.status = DeliveryStatus::Delivered;
+                delivery.next_attempt_at = None;
+                delivery.last_error = None;
+            }
+            Some(err) if delivery.attempts >= self.max_attempts => {
+                delivery.status = DeliveryStatus::Failed;
+                delivery.next_attempt_at = None;
+                delivery.last_error = Some(err);
+            }
+            Some(err) => {
+                let delay = self
+                    .retry_secs
+                    .saturating_mul(1 << (delivery.attempts - 1).min(20))
+                    .min(MAX_RETRY_SECS);
+                delivery.next_attempt_at = Some(now.saturating_add(delay));
+                delivery.last_error = Some(err);
+            }
+        }
+    }
+    /// The deliveries in the order they were queued, of any organization and
+    /// status unless they are given.
+    pub fn list(
+        &self,
+        org_id: Option<&str>,
+        status: Option<DeliveryStatus>,
+    ) -> Vec<WebhookDelivery> {
+        self.deliveries
+            .iter()
+            .filter(|delivery| org_id.map_or(true, |org_id| delivery.payload.org_id == org_id))
+            .filter(|delivery| status.map_or(true, |status| delivery.status == status))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Resolves the names of targets to their public addresses only, so that a
+/// name pointing at an internal address is not called.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Posts deliveries to their targets.
+pub struct WebhookSender {
+    client: reqwest::Client,
+}
+
+impl WebhookSender {
+    /// A sender giving up on targets that take longer than `timeout` to answer.
+    /// Redirects are not followed, as they could lead to an internal host.
+    pub fn new(timeout: Duration) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .redirect(reqwest::redirect::Policy::none())
+                .dns_resolver(Arc::new(PublicResolver))
+                .build()?,
+        })
+    }
+    /// Posts the payload of `delivery`, signed at time `now`, answering with the
+    /// HTTP status of the response.
+    pub async fn send(&self, delivery: &WebhookDelivery, now: u64) -> Result<u16, String> {
+        // Targets registered before their host was checked are refused here
+        check_url(&delivery.url)?;
+        let body = serde_json::to_vec(&delivery.payload).map_err(|err| err.to_string())?;
+        let response = self
+            .client
+            .post(&delivery.url)
+            .header("Content-Type", "application/json")
+            .header("X-Qed-Delivery", delivery.id.to_string())
+            .header("X-Qed-Timestamp", now.to_string())
+            .header(
+                "X-Qed-Signature",
+                sign_payload(&delivery.secret, now, &body),
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(response.status().as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        balance::{accounts::Tally, weight::Weight},
+        errors::ApiErrorCode,
+        proposal::rules::ProposalOutcome,
+    };
+
+    use super::{
+        hmac_sha256, validate_targets, DeliveryStatus, FinalizationPayload, WebhookQueue,
+        WebhookTarget, FINALIZED_EVENT,
+    };
+
+    #[test]
+    fn test_retries_deliveries_with_backoff() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let target = |url: &str| WebhookTarget {
+            url: url.to_string(),
+            secret: "s3cret".to_string(),
+        };
+        let targets = vec![target("https://a.example/hook"), target("http://b.example")];
+        assert!(validate_targets(&targets).is_ok());
+        assert!(validate_targets(&[target("https://93.184.216.34/hook")]).is_ok());
+        for invalid in [
+            vec![target("ftp://a.example")],
+            vec![target("not a url")],
+            vec![target("http://169.254.169.254/latest/meta-data")],
+            vec![target("http://127.0.0.1:8080")],
+            vec![target("http://10.0.0.5/hook")],
+            vec![target("http://100.64.0.1/hook")],
+            vec![target("http://[::1]/hook")],
+            vec![target("http://[::ffff:192.168.0.1]/hook")],
+            vec![target("http://[fd00::1]/hook")],
+            vec![target("http://localhost:3000")],
+            vec![target("http://api.localhost")],
+            vec![target("https://a.example"), target("https://a.example")],
+            vec![WebhookTarget {
+                secret: String::new(),
+                ..target("https://a.example")
+            }],
+        ] {
+            assert_eq!(
+                validate_targets(&invalid).unwrap_err().code,
+                ApiErrorCode::InvalidWebhook
+            );
+        }
+
+        let payload = FinalizationPayload {
+            event: FINALIZED_EVENT.to_string(),
+            org_id: "acme".to_string(),
+            proposal_id: Uuid::nil(),
+            outcome: ProposalOutcome::Passed,
+            tally: Tally {
+                yes_votes: Weight::from(2),
+                no_votes: Weight::from(1),
+            },
+            proof_hash: [7; 32],
+            tx_hash: None,
+            finalized_at: 100,
+        };
+        let mut queue = WebhookQueue::new(3, 10);
+        queue.enqueue(&targets, &payload, 100);
+        let due = queue.due(100);
+        assert_eq!(due.len(), 2);
+        queue.record(&due[0].id, Ok(204), 100);
+        queue.record(&due[1].id, Err("connection refused".to_string()), 100);
+        // Retried after 10s, then 20s, then given up on
+        assert!(queue.due(109).is_empty());
+        assert_eq!(queue.due(110).len(), 1);
+        queue.record(&due[1].id, Ok(500), 110);
+        assert!(queue.due(129).is_empty());
+        queue.record(&due[1].id, Ok(503), 130);
+        assert!(queue.due(u64::MAX).is_empty());
+
+        let failed = queue.list(Some("acme"), Some(DeliveryStatus::Failed));
+        assert_eq!(failed.len(), 1);
+        assert_eq!(
+            (failed[0].attempts, failed[0].response_status),
+            (3, Some(503))
+        );
+        assert_eq!(
+            queue.list(None, Some(DeliveryStatus::Delivered))[0].url,
+            "https://a.example/hook"
+        );
+        assert!(queue.list(Some("globex"), None).is_empty());
+    }
+}
@@ -0,0 +1,760 @@
+//! Verifies the finalization proofs of the QED server without the server, in
+//! `no_std` so that it compiles to WebAssembly and browser dapps can check a
+//! result client-side, see the `wasm` feature.
+//!
+//! A proof is checked against the verifier data of its circuit, which the
+//! server serves at `/circuits/{id}/verifier`, and against the public inputs it
+//! has to expose, which `/proposal/{id}/public-inputs` lists for a finalized
+//! proposal. Neither has to be trusted blindly: the fingerprint of the verifier
+//! data can be pinned per circuit, and the expected inputs recomputed from the
+//! certificate of the proposal.
+
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, ops::Range};
+
+use anyhow::{anyhow, ensure};
+use plonky2::{
+    field::{goldilocks_field::GoldilocksField, types::PrimeField64},
+    plonk::{
+        circuit_data::{CommonCircuitData, VerifierCircuitData, VerifierOnlyCircuitData},
+        config::{GenericConfig, GenericHashOut, Hasher, PoseidonGoldilocksConfig},
+        proof::ProofWithPublicInputs,
+    },
+    util::serialization::DefaultGateSerializer,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+const D: usize = 2;
+type C = PoseidonGoldilocksConfig;
+type F = GoldilocksField;
+
+/// Version of the proof envelope the server writes, see [`ProofEnvelope`].
+pub const PROOF_ENVELOPE_VERSION: u16 = 1;
+
+pub const INITIAL_ROOT_PUBLIC_INPUTS: Range<usize> = 0..4;
+pub const FINAL_ROOT_PUBLIC_INPUTS: Range<usize> = 4..8;
+pub const NO_VOTES_PUBLIC_INPUT: usize = 8;
+pub const YES_VOTES_PUBLIC_INPUT: usize = 9;
+/// Hash of the statement voted on, with the document it refers to if any.
+pub const STATEMENT_HASH_PUBLIC_INPUTS: Range<usize> = 10..14;
+/// Hash of the action passing commits to.
+pub const ACTION_HASH_PUBLIC_INPUTS: Range<usize> = 14..18;
+/// Voting deadline of a proposal with conviction voting, only in circuits of such proposals.
+pub const CONVICTION_DEADLINE_PUBLIC_INPUT: usize = 18;
+/// Length of a conviction step in seconds, only in circuits of proposals with conviction voting.
+pub const CONVICTION_STEP_PUBLIC_INPUT: usize = 19;
+
+/// Where the public inputs only some circuits expose are, as the suffixes of
+/// the id of an update balance circuit name them, e.g.
+/// `update_balance:8:32:32:conviction:dependencies`. They follow the action hash
+/// in the order of the fields, each taking no room in circuits without it.
+///
+/// Shard root circuits, e.g. `shard_root:4:update_balance:8:32:32`, combine the
+/// proofs of the shards of a sharded electorate and expose only the inputs all
+/// circuits share, over the roots of the whole electorate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PublicInputLayout {
+    /// Deadline and step length of conviction voting.
+    pub conviction: bool,
+    /// Votes weighed by the square root of their cost. Only the suffix names
+    /// it, as it exposes no public input.
+    pub quadratic: bool,
+    /// Hash of the results of the proposals a proposal depends on.
+    pub dependencies: bool,
+    /// Epoch vesting schedules unlock against.
+    pub vesting: bool,
+    /// Opening and deadline of the voting window.
+    pub deadline: bool,
+    /// Least weight votes and delegations move.
+    pub min_transfer: bool,
+    /// Number of shards a shard root circuit combines, unset for the circuits
+    /// of unsharded proposals.
+    pub shards: Option<u64>,
+}
+
+impl PublicInputLayout {
+    /// The layout of the update balance or shard root circuit `circuit_id`.
+    pub fn of_circuit(circuit_id: &str) -> anyhow::Result<Self> {
+        if let Some(shard_circuit_id) = circuit_id.strip_prefix("shard_root:") {
+            let (count, shard_circuit_id) = shard_circuit_id
+                .split_once(':')
+                .ok_or_else(|| anyhow!("unknown circuit id {}", circuit_id))?;
+            let shards: u64 = count
+                .parse()
+                .map_err(|_| anyhow!("unknown circuit id {}", circuit_id))?;
+            ensure!(
+                shards > 1 && Self::of_circuit(shard_circuit_id)? == Self::default(),
+                "unknown circuit id {}",
+                circuit_id
+            );
+            return Ok(Self {
+                shards: Some(shards),
+                ..Self::default()
+            });
+        }
+        let mut parts = circuit_id.split(':');
+        ensure!(
+            parts.next() == Some("update_balance") && parts.clone().count() >= 3,
+            "unknown circuit id {}",
+            circuit_id
+        );
+        let suffixes: Vec<&str> = parts.skip(3).collect();
+        Ok(Self {
+            conviction: suffixes.contains(&"conviction"),
+            quadratic: suffixes.contains(&"quadratic"),
+            dependencies: suffixes.contains(&"dependencies"),
+            vesting: suffixes.contains(&"vesting"),
+            deadline: suffixes.contains(&"deadline"),
+            min_transfer: suffixes.contains(&"min_transfer"),
+            shards: None,
+        })
+    }
+    /// The ranges of the optional inputs, in order, unset for those the circuit
+    /// does not expose.
+    fn ranges(&self) -> [Option<Range<usize>>; 5] {
+        let mut next = ACTION_HASH_PUBLIC_INPUTS.end;
+        [
+            (self.conviction, 2),
+            (self.dependencies, 4),
+            (self.vesting, 1),
+            (self.deadline, 2),
+            (self.min_transfer, 1),
+        ]
+        .map(|(exposed, len)| {
+            exposed.then(|| {
+                next += len;
+                next - len..next
+            })
+        })
+    }
+    /// Where the deadline and step length of conviction voting are, see
+    /// [`CONVICTION_DEADLINE_PUBLIC_INPUT`].
+    pub fn conviction_range(&self) -> Option<Range<usize>> {
+        self.ranges()[0].clone()
+    }
+    pub fn dependencies_hash_range(&self) -> Option<Range<usize>> {
+        self.ranges()[1].clone()
+    }
+    pub fn vesting_epoch_index(&self) -> Option<usize> {
+        self.ranges()[2].clone().map(|range| range.start)
+    }
+    /// Where the opening and the deadline of the voting window are.
+    pub fn voting_window_range(&self) -> Option<Range<usize>> {
+        self.ranges()[3].clone()
+    }
+    pub fn min_transfer_index(&self) -> Option<usize> {
+        self.ranges()[4].clone().map(|range| range.start)
+    }
+    /// Number of public inputs the circuit exposes.
+    pub fn input_count(&self) -> usize {
+        self.ranges()
+            .into_iter()
+            .flatten()
+            .last()
+            .map_or(ACTION_HASH_PUBLIC_INPUTS.end, |range| range.end)
+    }
+}
+
+/// Where the hash of the results of the proposals a proposal depends on is
+/// exposed, only in circuits of proposals with dependencies: after the
+/// conviction parameters in circuits with conviction voting, after the action
+/// hash otherwise.
+pub fn dependencies_hash_range(conviction: bool, dependencies: bool) -> Option<Range<usize>> {
+    PublicInputLayout {
+        conviction,
+        dependencies,
+        ..PublicInputLayout::default()
+    }
+    .dependencies_hash_range()
+}
+
+/// [`dependencies_hash_range`] of the update balance circuit `circuit_id`
+/// names, e.g. `update_balance:8:32:32:conviction:dependencies`.
+pub fn circuit_dependencies_hash_range(circuit_id: &str) -> anyhow::Result<Option<Range<usize>>> {
+    Ok(PublicInputLayout::of_circuit(circuit_id)?.dependencies_hash_range())
+}
+
+/// A hash exposed as four public inputs, serialized as the server serializes
+/// hashes: the hex of its four elements as little endian `u64`s, reversed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PublicHash(pub [u64; 4]);
+
+impl PublicHash {
+    /// Whether the public inputs at `range` are this hash.
+    pub fn is_at(&self, public_inputs: &[u64], range: Range<usize>) -> bool {
+        public_inputs[range] == self.0[..]
+    }
+}
+
+impl fmt::Display for PublicHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for element in self.0.iter().rev() {
+            write!(f, "{:016x}", element)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for PublicHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let mut bytes = hex::decode(hex).map_err(de::Error::custom)?;
+        if bytes.len() > 32 {
+            return Err(de::Error::custom("too long hexadecimal sequence"));
+        }
+        bytes.reverse(); // little endian
+        bytes.resize(32, 0);
+        let mut elements = [0; 4];
+        for (element, chunk) in elements.iter_mut().zip(bytes.chunks(8)) {
+            *element = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Ok(Self(elements))
+    }
+}
+
+/// The JSON form of the proof envelope the server stores a proof in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofEnvelope {
+    pub version: u16,
+    pub circuit_id: String,
+    /// Fingerprint of the verifier data the proof was produced with.
+    #[serde(with = "hex::serde")]
+    pub common_data_hash: Vec<u8>,
+    pub public_inputs: Vec<u64>,
+    #[serde(with = "hex::serde")]
+    pub proof_bytes: Vec<u8>,
+}
+
+/// The verifier data of a circuit, in plonky2's binary serialization.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifierData {
+    pub circuit_id: String,
+    /// See [`fingerprint`].
+    #[serde(with = "hex::serde")]
+    pub fingerprint: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub verifier_only: Vec<u8>,
+    /// Serialized with plonky2's default gate serializer.
+    #[serde(with = "hex::serde")]
+    pub common: Vec<u8>,
+}
+
+/// Opening and deadline of the voting window of a proposal, in unix seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VotingWindow {
+    pub opens_at: u64,
+    pub deadline: u64,
+}
+
+/// Deadline and step length of a proposal weighing votes by conviction, in
+/// seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConvictionSchedule {
+    pub deadline: u64,
+    pub step_secs: u64,
+}
+
+/// How the weight a vote adds to its tally derives from the weight spent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VotingPolicy {
+    #[default]
+    Linear,
+    Quadratic,
+}
+
+impl VotingPolicy {
+    pub fn is_linear(&self) -> bool {
+        *self == VotingPolicy::Linear
+    }
+}
+
+/// Rules of a proposal its finalization proof exposes, each set only on
+/// proposals that have it, as listed in its certificate. A proof made under
+/// other rules than the proposal was created with does not check out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenRules {
+    /// Every vote counted was multiplied by the steps it was cast before the
+    /// deadline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conviction: Option<ConvictionSchedule>,
+    /// Every spend kept the weight vesting schedules lock at this epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vesting_epoch: Option<u64>,
+    /// Every vote and delegation counted was recorded within the window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voting_window: Option<VotingWindow>,
+    /// No vote or delegation counted moved less weight than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_transfer: Option<u64>,
+    /// Every vote counted added the square root of its cost if quadratic.
+    #[serde(default, skip_serializing_if = "VotingPolicy::is_linear")]
+    pub voting_policy: VotingPolicy,
+    /// The electorate was split across this many shards, each proven on its
+    /// own, and the roots are those of the shard roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard_count: Option<u64>,
+}
+
+impl ProvenRules {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What a finalization proof has to expose to prove the result of a proposal.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedInputs {
+    pub initial_root: PublicHash,
+    pub final_root: PublicHash,
+    pub statement_hash: PublicHash,
+    pub action_hash: PublicHash,
+    /// Set if the proposal has dependencies or was proven with a circuit that
+    /// exposes them, unset otherwise.
+    pub dependencies_hash: Option<PublicHash>,
+    #[serde(default)]
+    pub rules: ProvenRules,
+}
+
+/// The votes a finalization proof tallies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    pub yes_votes: u64,
+    pub no_votes: u64,
+}
+
+/// Poseidon hash of the constants and sigmas cap and the circuit digest of the
+/// verifier data, as little endian bytes. Proof envelopes name the verifier
+/// data they were produced with by it.
+pub fn fingerprint(verifier_only: &VerifierOnlyCircuitData<C, D>) -> Vec<u8> {
+    let elements: Vec<F> = verifier_only
+        .constants_sigmas_cap
+        .0
+        .iter()
+        .flat_map(|hash| hash.elements)
+        .chain(verifier_only.circuit_digest.elements)
+        .collect();
+    <C as GenericConfig<D>>::Hasher::hash_no_pad(&elements).to_bytes()
+}
+
+/// Checks the public inputs of a proof of the circuit `circuit_id` against
+/// `expected`, returning the tally they expose. The proof itself still has to
+/// be verified against them, see [`verify`].
+pub fn check_public_inputs(
+    circuit_id: &str,
+    public_inputs: &[u64],
+    expected: &ExpectedInputs,
+) -> anyhow::Result<Tally> {
+    let layout = PublicInputLayout::of_circuit(circuit_id)?;
+    let dependencies = layout.dependencies_hash_range();
+    ensure!(
+        public_inputs.len() >= layout.input_count(),
+        "proof has {} public inputs, too few for circuit {}",
+        public_inputs.len(),
+        circuit_id
+    );
+    ensure!(
+        expected
+            .initial_root
+            .is_at(public_inputs, INITIAL_ROOT_PUBLIC_INPUTS),
+        "proof does not start from the expected initial root"
+    );
+    ensure!(
+        expected
+            .final_root
+            .is_at(public_inputs, FINAL_ROOT_PUBLIC_INPUTS),
+        "proof does not end at the expected final root"
+    );
+    ensure!(
+        expected
+            .statement_hash
+            .is_at(public_inputs, STATEMENT_HASH_PUBLIC_INPUTS),
+        "proof was not made for the expected statement and document"
+    );
+    ensure!(
+        expected
+            .action_hash
+            .is_at(public_inputs, ACTION_HASH_PUBLIC_INPUTS),
+        "proof was not made for the expected action"
+    );
+    match (dependencies, &expected.dependencies_hash) {
+        (Some(range), Some(hash)) => ensure!(
+            hash.is_at(public_inputs, range),
+            "proof was not made for the expected dependency results"
+        ),
+        (Some(_), None) => anyhow::bail!("proof was made for a proposal with dependencies"),
+        (None, Some(_)) => anyhow::bail!("proof was made for a proposal without dependencies"),
+        (None, None) => {}
+    }
+    match (layout.quadratic, expected.rules.voting_policy) {
+        (true, VotingPolicy::Linear) => {
+            anyhow::bail!("proof was made for a proposal with quadratic voting")
+        }
+        (false, VotingPolicy::Quadratic) => {
+            anyhow::bail!("proof was made for a proposal without quadratic voting")
+        }
+        _ => {}
+    }
+    match (layout.shards, expected.rules.shard_count) {
+        (Some(proven), Some(expected)) => ensure!(
+            proven == expected,
+            "proof combines {} shards, not the expected {}",
+            proven,
+            expected
+        ),
+        (Some(_), None) => anyhow::bail!("proof was made for a sharded proposal"),
+        (None, Some(_)) => anyhow::bail!("proof was made for an unsharded proposal"),
+        (None, None) => {}
+    }
+    let conviction = expected
+        .rules
+        .conviction
+        .map(|schedule| [schedule.deadline, schedule.step_secs]);
+    check_rule(
+        "conviction schedule",
+        public_inputs,
+        layout.conviction_range(),
+        conviction.as_ref().map(|schedule| &schedule[..]),
+    )?;
+    check_rule(
+        "vesting epoch",
+        public_inputs,
+        layout.vesting_epoch_index().map(|index| index..index + 1),
+        expected
+            .rules
+            .vesting_epoch
+            .as_ref()
+            .map(core::slice::from_ref),
+    )?;
+    let voting_window = expected
+        .rules
+        .voting_window
+        .map(|window| [window.opens_at, window.deadline]);
+    check_rule(
+        "voting window",
+        public_inputs,
+        layout.voting_window_range(),
+        voting_window.as_ref().map(|window| &window[..]),
+    )?;
+    check_rule(
+        "minimum transfer",
+        public_inputs,
+        layout.min_transfer_index().map(|index| index..index + 1),
+        expected
+            .rules
+            .min_transfer
+            .as_ref()
+            .map(core::slice::from_ref),
+    )?;
+    Ok(Tally {
+        yes_votes: public_inputs[YES_VOTES_PUBLIC_INPUT],
+        no_votes: public_inputs[NO_VOTES_PUBLIC_INPUT],
+    })
+}
+
+/// Checks that a circuit exposes a rule at `range` if and only if the proposal
+/// has it, as `expected`.
+fn check_rule(
+    name: &str,
+    public_inputs: &[u64],
+    range: Option<Range<usize>>,
+    expected: Option<&[u64]>,
+) -> anyhow::Result<()> {
+    match (range, expected) {
+        (Some(range), Some(expected)) => ensure!(
+            public_inputs[range] == *expected,
+            "proof was not made under the expected {}",
+            name
+        ),
+        (Some(_), None) => anyhow::bail!("proof was made for a proposal with a {}", name),
+        (None, Some(_)) => anyhow::bail!("proof was made for a proposal without a {}", name),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Verifies `envelope` with the verifier data of its circuit and checks its
+/// public inputs against `expected`, returning the tally it proves.
+pub fn verify(
+    envelope: &ProofEnvelope,
+    verifier: &VerifierData,
+    expected: &ExpectedInputs,
+) -> anyhow::Result<Tally> {
+    ensure!(
+        envelope.version == PROOF_ENVELOPE_VERSION,
+        "unsupported proof envelope version {} (expected {})",
+        envelope.version,
+        PROOF_ENVELOPE_VERSION
+    );
+    ensure!(
+        envelope.circuit_id == verifier.circuit_id,
+        "proof is of circuit {}, the verifier data of {}",
+        envelope.circuit_id,
+        verifier.circuit_id
+    );
+    // Cheap to check, and spares decoding the verifier data of a proof of another tree
+    let tally = check_public_inputs(&envelope.circuit_id, &envelope.public_inputs, expected)?;
+
+    let verifier_only = VerifierOnlyCircuitData::<C, D>::from_bytes(verifier.verifier_only.clone())
+        .map_err(|_| anyhow!("malformed verifier-only data"))?;
+    let common =
+        CommonCircuitData::<F, D>::from_bytes(verifier.common.clone(), &DefaultGateSerializer)
+            .map_err(|_| anyhow!("malformed common circuit data"))?;
+    ensure!(
+        fingerprint(&verifier_only) == envelope.common_data_hash
+            && verifier.fingerprint == envelope.common_data_hash,
+        "proof envelope for circuit {} was produced with different verifier data",
+        envelope.circuit_id
+    );
+
+    let proof =
+        ProofWithPublicInputs::<F, C, D>::from_bytes(envelope.proof_bytes.clone(), &common)?;
+    let public_inputs: Vec<u64> = proof
+        .public_inputs
+        .iter()
+        .map(|x| x.to_canonical_u64())
+        .collect();
+    ensure!(
+        public_inputs == envelope.public_inputs,
+        "proof envelope public inputs do not match the embedded proof"
+    );
+    VerifierCircuitData {
+        verifier_only,
+        common,
+    }
+    .verify(proof)?;
+    Ok(tally)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::{
+        check_public_inputs, circuit_dependencies_hash_range, ConvictionSchedule, ExpectedInputs,
+        ProvenRules, PublicHash, PublicInputLayout, Tally, VotingPolicy, VotingWindow,
+    };
+
+    #[test]
+    fn test_check_public_inputs() {
+        let hash = |value: u64| PublicHash([value; 4]);
+        let mut expected = ExpectedInputs {
+            initial_root: hash(1),
+            final_root: hash(2),
+            statement_hash: hash(3),
+            action_hash: hash(4),
+            dependencies_hash: None,
+            rules: ProvenRules::default(),
+        };
+        let mut public_inputs = vec![1, 1, 1, 1, 2, 2, 2, 2, 5, 7, 3, 3, 3, 3, 4, 4, 4, 4];
+        let tally = check_public_inputs("update_balance:1:32:32", &public_inputs, &expected);
+        assert_eq!(
+            tally.unwrap(),
+            Tally {
+                yes_votes: 7,
+                no_votes: 5
+            }
+        );
+        assert!(
+            check_public_inputs("update_balance:1:32:32", &public_inputs[..10], &expected).is_err()
+        );
+        expected.final_root = hash(1);
+        assert!(check_public_inputs("update_balance:1:32:32", &public_inputs, &expected).is_err());
+        expected.final_root = hash(2);
+
+        // Dependencies follow the conviction parameters
+        let circuit_id = "update_balance:1:32:32:conviction:dependencies";
+        assert_eq!(
+            circuit_dependencies_hash_range(circuit_id).unwrap(),
+            Some(20..24)
+        );
+        public_inputs.extend([0, 0, 6, 6, 6, 6]);
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+        expected.dependencies_hash = Some(hash(6));
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_ok());
+        assert!(check_public_inputs("update_balance:1:32:32", &public_inputs, &expected).is_err());
+    }
+
+    #[test]
+    fn test_check_voting_window() {
+        let hash = |value: u64| PublicHash([value; 4]);
+        let mut expected = ExpectedInputs {
+            initial_root: hash(1),
+            final_root: hash(2),
+            statement_hash: hash(3),
+            action_hash: hash(4),
+            dependencies_hash: None,
+            rules: ProvenRules::default(),
+        };
+        // The window follows the vesting epoch
+        let circuit_id = "update_balance:1:32:32:vesting:deadline";
+        let layout = PublicInputLayout::of_circuit(circuit_id).unwrap();
+        assert_eq!(layout.vesting_epoch_index(), Some(18));
+        assert_eq!(layout.voting_window_range(), Some(19..21));
+        assert_eq!(layout.input_count(), 21);
+        let mut public_inputs = vec![1, 1, 1, 1, 2, 2, 2, 2, 5, 7, 3, 3, 3, 3, 4, 4, 4, 4];
+        public_inputs.extend([9, 100, 200]);
+
+        expected.rules.vesting_epoch = Some(9);
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+        expected.rules.voting_window = Some(VotingWindow {
+            opens_at: 100,
+            deadline: 200,
+        });
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_ok());
+        // Nor does a proof made with schedules unlocked at a later epoch
+        public_inputs[18] = 10;
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+        public_inputs[18] = 9;
+        assert!(check_public_inputs(circuit_id, &public_inputs[..20], &expected).is_err());
+        // A proof of a later deadline does not pass for the proposal
+        public_inputs[20] = 300;
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+        assert!(
+            check_public_inputs("update_balance:1:32:32:vesting", &public_inputs, &expected)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_check_conviction_schedule() {
+        let hash = |value: u64| PublicHash([value; 4]);
+        let mut expected = ExpectedInputs {
+            initial_root: hash(1),
+            final_root: hash(2),
+            statement_hash: hash(3),
+            action_hash: hash(4),
+            dependencies_hash: None,
+            rules: ProvenRules::default(),
+        };
+        let circuit_id = "update_balance:1:32:32:conviction";
+        assert_eq!(
+            PublicInputLayout::of_circuit(circuit_id)
+                .unwrap()
+                .conviction_range(),
+            Some(18..20)
+        );
+        let mut public_inputs = vec![1, 1, 1, 1, 2, 2, 2, 2, 5, 7, 3, 3, 3, 3, 4, 4, 4, 4];
+        public_inputs.extend([500, 60]);
+
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+        expected.rules.conviction = Some(ConvictionSchedule {
+            deadline: 500,
+            step_secs: 60,
+        });
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_ok());
+        // Shorter steps would have multiplied the votes more
+        public_inputs[19] = 30;
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+    }
+
+    #[test]
+    fn test_check_min_transfer() {
+        let hash = |value: u64| PublicHash([value; 4]);
+        let mut expected = ExpectedInputs {
+            initial_root: hash(1),
+            final_root: hash(2),
+            statement_hash: hash(3),
+            action_hash: hash(4),
+            dependencies_hash: None,
+            rules: ProvenRules::default(),
+        };
+        let circuit_id = "update_balance:1:32:32:min_transfer";
+        assert_eq!(
+            PublicInputLayout::of_circuit(circuit_id)
+                .unwrap()
+                .min_transfer_index(),
+            Some(18)
+        );
+        let mut public_inputs = vec![1, 1, 1, 1, 2, 2, 2, 2, 5, 7, 3, 3, 3, 3, 4, 4, 4, 4];
+        public_inputs.push(10);
+
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+        expected.rules.min_transfer = Some(10);
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_ok());
+        // A proof letting smaller transfers through does not pass for the proposal
+        public_inputs[18] = 1;
+        assert!(check_public_inputs(circuit_id, &public_inputs, &expected).is_err());
+    }
+
+    #[test]
+    fn test_check_voting_policy() {
+        let hash = |value: u64| PublicHash([value; 4]);
+        let mut expected = ExpectedInputs {
+            initial_root: hash(1),
+            final_root: hash(2),
+            statement_hash: hash(3),
+            action_hash: hash(4),
+            dependencies_hash: None,
+            rules: ProvenRules::default(),
+        };
+        let public_inputs = vec![1, 1, 1, 1, 2, 2, 2, 2, 5, 7, 3, 3, 3, 3, 4, 4, 4, 4];
+        let quadratic = "update_balance:1:32:32:quadratic";
+        assert!(PublicInputLayout::of_circuit(quadratic).unwrap().quadratic);
+
+        assert!(check_public_inputs(quadratic, &public_inputs, &expected).is_err());
+        expected.rules.voting_policy = VotingPolicy::Quadratic;
+        assert!(check_public_inputs(quadratic, &public_inputs, &expected).is_ok());
+        // A proof counting votes linearly does not pass for a quadratic proposal
+        assert!(check_public_inputs("update_balance:1:32:32", &public_inputs, &expected).is_err());
+    }
+
+    #[test]
+    fn test_check_shard_count() {
+        let hash = |value: u64| PublicHash([value; 4]);
+        let mut expected = ExpectedInputs {
+            initial_root: hash(1),
+            final_root: hash(2),
+            statement_hash: hash(3),
+            action_hash: hash(4),
+            dependencies_hash: None,
+            rules: ProvenRules::default(),
+        };
+        let public_inputs = vec![1, 1, 1, 1, 2, 2, 2, 2, 5, 7, 3, 3, 3, 3, 4, 4, 4, 4];
+        let sharded = "shard_root:4:update_balance:8:32:32";
+        assert_eq!(
+            PublicInputLayout::of_circuit(sharded).unwrap().shards,
+            Some(4)
+        );
+        // Shards are proven without any optional rule
+        assert!(
+            PublicInputLayout::of_circuit("shard_root:4:update_balance:8:32:32:vesting").is_err()
+        );
+
+        assert!(check_public_inputs(sharded, &public_inputs, &expected).is_err());
+        expected.rules.shard_count = Some(4);
+        assert!(check_public_inputs(sharded, &public_inputs, &expected).is_ok());
+        // Neither a proof over other shards nor one of the whole tree passes
+        assert!(check_public_inputs(
+            "shard_root:2:update_balance:8:32:32",
+            &public_inputs,
+            &expected
+        )
+        .is_err());
+        assert!(check_public_inputs("update_balance:8:32:32", &public_inputs, &expected).is_err());
+    }
+
+    #[test]
+    fn test_public_hash_serializes_as_the_server() {
+        let hash = PublicHash([1, 2, 3, u64::MAX]);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(
+            json,
+            "\"ffffffffffffffff000000000000000300000000000000020000000000000001\""
+        );
+        assert_eq!(serde_json::from_str::<PublicHash>(&json).unwrap(), hash);
+    }
+}
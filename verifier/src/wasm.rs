@@ -0,0 +1,33 @@
+//! JavaScript bindings of the verifier, built with
+//! `wasm-pack build verifier --target web -- --features wasm`.
+//!
+//! ```js
+//! import init, { verifyFinalization } from "qed-verifier";
+//!
+//! await init();
+//! const tally = JSON.parse(verifyFinalization(proof, verifier, expected));
+//! ```
+
+use alloc::string::{String, ToString};
+use core::fmt::Display;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{ExpectedInputs, ProofEnvelope, VerifierData};
+
+// serde_json and anyhow errors only implement `std::error::Error` with std
+fn error(err: impl Display) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// Verifies the proof envelope `proof` with the verifier data `verifier` of its
+/// circuit against the public inputs `expected`, all as JSON, returning the
+/// tally it proves as JSON. Throws why the proof was rejected otherwise.
+#[wasm_bindgen(js_name = verifyFinalization)]
+pub fn verify_finalization(proof: &str, verifier: &str, expected: &str) -> Result<String, JsError> {
+    let envelope: ProofEnvelope = serde_json::from_str(proof).map_err(error)?;
+    let verifier: VerifierData = serde_json::from_str(verifier).map_err(error)?;
+    let expected: ExpectedInputs = serde_json::from_str(expected).map_err(error)?;
+    let tally = crate::verify(&envelope, &verifier, &expected).map_err(error)?;
+    serde_json::to_string(&tally).map_err(error)
+}